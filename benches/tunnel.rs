@@ -0,0 +1,85 @@
+//! Compares throughput of forwarding data between two TCP connections with
+//! `splice(2)` against copying it through a user-space buffer.
+
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::sync::mpsc;
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use netservices::tunnel::Forwarder;
+
+/// Amount of data forwarded with each benchmark iteration.
+const TRANSFER: usize = 16 * 1024 * 1024;
+/// Size of the writes made by the data source.
+const CHUNK: usize = 64 * 1024;
+
+/// Connected pair of TCP sockets.
+fn socket_pair() -> (TcpStream, TcpStream) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    (client, server)
+}
+
+/// Forwarder between the sockets of a source, which sends [`TRANSFER`] bytes
+/// on each request, and a sink discarding all the data.
+struct Setup {
+    forwarder: Forwarder,
+    requests: mpsc::Sender<()>,
+    _sockets: (TcpStream, TcpStream),
+}
+
+impl Setup {
+    fn new(splice: bool) -> Self {
+        let (mut src, inbound) = socket_pair();
+        let (outbound, mut dst) = socket_pair();
+        inbound.set_nonblocking(true).unwrap();
+        outbound.set_nonblocking(true).unwrap();
+
+        let (requests, recv) = mpsc::channel::<()>();
+        thread::spawn(move || {
+            let chunk = [0xA5u8; CHUNK];
+            for _ in recv {
+                for _ in 0..TRANSFER / CHUNK {
+                    src.write_all(&chunk).unwrap();
+                }
+            }
+        });
+        thread::spawn(move || io::copy(&mut dst, &mut io::sink()));
+
+        let forwarder = match splice {
+            true => Forwarder::with_splice(inbound.as_raw_fd(), outbound.as_raw_fd()).unwrap(),
+            false => Forwarder::with_copy(inbound.as_raw_fd(), outbound.as_raw_fd()),
+        };
+        Setup {
+            forwarder,
+            requests,
+            _sockets: (inbound, outbound),
+        }
+    }
+
+    fn transfer(&mut self) {
+        let target = self.forwarder.bytes_forwarded() + TRANSFER as u64;
+        self.requests.send(()).unwrap();
+        while self.forwarder.bytes_forwarded() < target {
+            if self.forwarder.forward().unwrap() == 0 {
+                thread::yield_now();
+            }
+        }
+    }
+}
+
+fn forwarding(c: &mut Criterion) {
+    let mut group = c.benchmark_group("tunnel forwarding");
+    group.throughput(Throughput::Bytes(TRANSFER as u64));
+    let mut copy = Setup::new(false);
+    group.bench_function("copy", |b| b.iter(|| copy.transfer()));
+    let mut splice = Setup::new(true);
+    group.bench_function("splice", |b| b.iter(|| splice.transfer()));
+    group.finish();
+}
+
+criterion_group!(benches, forwarding);
+criterion_main!(benches);
@@ -0,0 +1,164 @@
+//! Compares throughput of a single reactor against a multi-reactor with
+//! [`SHARDS`] shards by running echo over a large number of simultaneous TCP
+//! connections.
+//!
+//! Requires the limit on the number of open files to be above
+//! `2 * CONNECTIONS` (see `ulimit -n`).
+
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::thread;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use reactor::poller::{popol, IoType};
+use reactor::{Action, Error, Handler, Io, MultiReactor, Resource, WriteAtomic};
+
+/// Number of simultaneous echo connections.
+const CONNECTIONS: usize = 20_000;
+
+/// Number of the multi-reactor shards.
+const SHARDS: usize = 4;
+
+/// Server side of the echo connection, writing the received data back.
+struct Echo(TcpStream);
+
+impl AsRawFd for Echo {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+}
+
+impl Write for Echo {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl WriteAtomic for Echo {
+    fn is_ready_to_write(&self) -> bool {
+        true
+    }
+
+    fn write_or_buffer(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.0.write_all(buf)
+    }
+}
+
+impl Resource for Echo {
+    type Id = RawFd;
+    type Event = ();
+
+    fn id(&self) -> Self::Id {
+        self.as_raw_fd()
+    }
+
+    fn interests(&self) -> IoType {
+        IoType::read_only()
+    }
+
+    fn handle_io(&mut self, _: Io) -> Option<Self::Event> {
+        let mut buf = [0u8; 16];
+        match self.0.read(&mut buf) {
+            Ok(len) => self.0.write_all(&buf[..len]).unwrap(),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            Err(err) => panic!("{err}"),
+        }
+        None
+    }
+
+    fn disconnect(self) -> io::Result<()> {
+        self.0.shutdown(Shutdown::Both)
+    }
+}
+
+/// Service of the echo reactors; the echo is done by the resources themselves.
+#[derive(Clone)]
+struct Service;
+
+impl Iterator for Service {
+    type Item = Action<Echo, Echo>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        None
+    }
+}
+
+impl Handler for Service {
+    type Listener = Echo;
+    type Transport = Echo;
+    type Command = ();
+
+    fn tick(&mut self, _: Duration) {}
+    fn handle_wakeup(&mut self) {}
+    fn handle_listener_event(&mut self, _: RawFd, _: (), _: Duration) {}
+    fn handle_transport_event(&mut self, _: RawFd, _: (), _: Duration) {}
+    fn handle_command(&mut self, _: ()) {}
+    fn handle_error(&mut self, _: Error<Echo, Echo>) {}
+    fn handover_listener(&mut self, _: Echo) {}
+    fn handover_transport(&mut self, _: Echo) {}
+}
+
+/// Client sides of the echo connections, whose server sides are run by the
+/// reactor.
+struct Clients(Vec<TcpStream>);
+
+impl Clients {
+    fn connect(reactor: &MultiReactor<Service>) -> Self {
+        let controller = reactor.controller();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut clients = Vec::with_capacity(CONNECTIONS);
+        for _ in 0..CONNECTIONS {
+            let client = TcpStream::connect(addr).unwrap();
+            client.set_nodelay(true).unwrap();
+            let (server, _) = listener.accept().unwrap();
+            server.set_nonblocking(true).unwrap();
+            server.set_nodelay(true).unwrap();
+            controller.register_transport(Echo(server)).unwrap();
+            clients.push(client);
+        }
+        Clients(clients)
+    }
+
+    /// Sends a byte over each of the connections and waits for all of them to
+    /// be echoed back. The connections are split between [`SHARDS`] threads,
+    /// so the clients do not limit the throughput of the multi-reactor.
+    fn echo(&mut self) {
+        let chunk = self.0.len().div_ceil(SHARDS);
+        thread::scope(|scope| {
+            for clients in self.0.chunks_mut(chunk) {
+                scope.spawn(move || {
+                    for client in clients.iter_mut() {
+                        client.write_all(&[1]).unwrap();
+                    }
+                    let mut buf = [0u8; 1];
+                    for client in clients {
+                        client.read_exact(&mut buf).unwrap();
+                    }
+                });
+            }
+        });
+    }
+}
+
+fn bench_reactor(c: &mut Criterion, name: &str, shards: usize) {
+    let reactor = MultiReactor::new(Service, shards, popol::Poller::new).unwrap();
+    let mut clients = Clients::connect(&reactor);
+    c.bench_function(name, |b| b.iter(|| clients.echo()));
+    reactor.shutdown_all().unwrap();
+}
+
+fn echo(c: &mut Criterion) {
+    // Multi-reactor with a single shard runs a single reactor
+    bench_reactor(c, "single reactor echo", 1);
+    bench_reactor(c, "multi-reactor echo", SHARDS);
+}
+
+criterion_group!(benches, echo);
+criterion_main!(benches);
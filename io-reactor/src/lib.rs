@@ -14,11 +14,13 @@
 #[macro_use]
 extern crate amplify;
 
+mod multi;
 pub mod poller;
 mod reactor;
 mod resource;
 mod timeouts;
 
+pub use multi::{MultiController, MultiReactor};
 pub use reactor::{Action, Controller, Error, Handler, Reactor, Runtime};
 pub use resource::{Io, Resource, ResourceId, WriteAtomic, WriteError};
-pub use timeouts::TimeoutManager;
+pub use timeouts::{TimeoutManager, MAX_INTERVAL_REPEATS};
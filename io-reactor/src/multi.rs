@@ -0,0 +1,425 @@
+//! Multi-threaded reactor ([`MultiReactor`]) spreading resources over several
+//! [`Reactor`] shards, each running in its own thread. Since a single reactor
+//! thread polls all of its resources, it becomes a bottleneck once the number
+//! of the resources grows large; sharding allows to scale horizontally.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::time::Duration;
+use std::{io, thread};
+
+use crate::poller::Poll;
+use crate::{Controller, Handler, Reactor, Resource};
+
+/// Reactor running a number of [`Reactor`] shards, each in a dedicated thread
+/// with its own poller and its own clone of the service.
+///
+/// Shards are controlled via [`MultiController`] returned by
+/// [`MultiReactor::controller`].
+pub struct MultiReactor<S: Handler> {
+    shards: Vec<Reactor<S>>,
+    controller: MultiController<S>,
+}
+
+impl<S: Handler> MultiReactor<S> {
+    /// Spawns `num_threads` reactor shards, each provided with a clone of the
+    /// `service` and a poller constructed by `poller`.
+    ///
+    /// # Errors
+    ///
+    /// If `num_threads` is zero or if a shard thread can't be spawned. In the
+    /// latter case the shards which were already spawned are shut down.
+    pub fn new<P>(service: S, num_threads: usize, poller: impl Fn() -> P) -> Result<Self, io::Error>
+    where
+        S: Clone + 'static,
+        P: Poll + 'static,
+    {
+        if num_threads == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "multi-reactor requires at least one thread",
+            ));
+        }
+
+        let mut shards = Vec::<Reactor<S>>::with_capacity(num_threads);
+        for no in 0..num_threads {
+            match Reactor::named(service.clone(), poller(), format!("reactor-{no}")) {
+                Ok(shard) => shards.push(shard),
+                Err(err) => {
+                    for shard in shards {
+                        // Nothing to do if the shard has already terminated
+                        let _ = shard.controller().shutdown();
+                    }
+                    return Err(err);
+                }
+            }
+        }
+
+        let controller = MultiController {
+            shards: shards.iter().map(Reactor::controller).collect(),
+            hasher: RandomState::new(),
+        };
+        Ok(MultiReactor { shards, controller })
+    }
+
+    /// Returns controller dispatching the requests to the shards.
+    pub fn controller(&self) -> MultiController<S> {
+        self.controller.clone()
+    }
+
+    /// Shuts down the shards in order, waiting for each of them to complete
+    /// before shutting down the next one.
+    ///
+    /// # Errors
+    ///
+    /// If any of the shard threads has panicked. The remaining shards are
+    /// still shut down, and the first of the errors is returned.
+    pub fn shutdown_all(self) -> thread::Result<()> {
+        let mut res = Ok(());
+        for shard in self.shards {
+            // The shard which has already terminated is still joined, which
+            // reports the way it has terminated
+            let _ = shard.controller().shutdown();
+            let joined = shard.join();
+            if res.is_ok() {
+                res = joined;
+            }
+        }
+        res
+    }
+
+    /// Joins all shard threads.
+    pub fn join(self) -> thread::Result<()> {
+        for shard in self.shards {
+            shard.join()?;
+        }
+        Ok(())
+    }
+}
+
+/// Controller of a [`MultiReactor`] dispatching the requests to its shards.
+///
+/// Transports are assigned to the shards by the hash of their ids, such that
+/// the requests addressing a transport by its id are dispatched to the shard
+/// running it. Listeners and commands to the service, which usually lead to
+/// new connections, are dispatched to the shard running the fewest
+/// resources.
+///
+/// Transports registered by the service of a shard itself (with
+/// [`crate::Action::RegisterTransport`]) are run by that shard regardless of
+/// their ids, so they must be controlled via the controller of that shard
+/// (see [`MultiController::shards`]).
+pub struct MultiController<S: Handler> {
+    shards: Vec<Controller<S>>,
+    hasher: RandomState,
+}
+
+impl<S: Handler> Clone for MultiController<S> {
+    fn clone(&self) -> Self {
+        MultiController {
+            shards: self.shards.clone(),
+            hasher: self.hasher.clone(),
+        }
+    }
+}
+
+impl<S: Handler> MultiController<S> {
+    /// Returns controllers of the shards.
+    pub fn shards(&self) -> &[Controller<S>] {
+        &self.shards
+    }
+
+    /// Returns controller of the shard which runs the transport with the
+    /// given id.
+    pub fn shard_for(&self, id: <S::Transport as Resource>::Id) -> &Controller<S> {
+        let hash = self.hasher.hash_one(id);
+        &self.shards[(hash % self.shards.len() as u64) as usize]
+    }
+
+    /// Returns controller of the shard running the fewest resources.
+    pub fn least_loaded(&self) -> &Controller<S> {
+        self.shards
+            .iter()
+            .min_by_key(|shard| shard.resource_count())
+            .expect("multi-reactor has at least one shard")
+    }
+
+    /// Returns number of listeners and transports run by all shards (see
+    /// [`Controller::resource_count`]).
+    pub fn resource_count(&self) -> usize {
+        self.shards.iter().map(Controller::resource_count).sum()
+    }
+
+    /// Returns number of transports run by all shards (see
+    /// [`Controller::transport_count`]).
+    pub fn transport_count(&self) -> usize {
+        self.shards.iter().map(Controller::transport_count).sum()
+    }
+
+    /// Drains all shards, each of which shuts down once it has no transports
+    /// left or once the deadline is reached (see [`Controller::drain`]).
+    pub fn drain(&self, deadline: Duration) -> Result<(), io::Error> {
+        for shard in &self.shards {
+            shard.drain(deadline)?;
+        }
+        Ok(())
+    }
+
+    /// Registers listener with the shard running the fewest resources.
+    pub fn register_listener(&self, listener: S::Listener) -> Result<(), io::Error> {
+        self.least_loaded().register_listener(listener)
+    }
+
+    /// Registers transport with the shard selected by the hash of its id.
+    pub fn register_transport(&self, transport: S::Transport) -> Result<(), io::Error> {
+        self.shard_for(transport.id()).register_transport(transport)
+    }
+
+    /// Stops reading from the transport (see [`Controller::pause_read`]).
+    pub fn pause_read(&self, id: <S::Transport as Resource>::Id) -> Result<(), io::Error> {
+        self.shard_for(id).pause_read(id)
+    }
+
+    /// Resumes reading from the transport (see [`Controller::resume_read`]).
+    pub fn resume_read(&self, id: <S::Transport as Resource>::Id) -> Result<(), io::Error> {
+        self.shard_for(id).resume_read(id)
+    }
+
+    /// Unregisters the transport and disconnects it (see
+    /// [`Controller::disconnect`]).
+    pub fn disconnect(&self, id: <S::Transport as Resource>::Id) -> Result<(), io::Error> {
+        self.shard_for(id).disconnect(id)
+    }
+
+    /// Sends command to the service of the shard running the fewest
+    /// resources.
+    pub fn send(&self, command: S::Command) -> Result<(), io::Error> {
+        self.least_loaded().send(command)
+    }
+}
+
+#[cfg(all(test, feature = "popol"))]
+mod tests {
+    use std::io::Write;
+    use std::os::unix::io::{AsRawFd, RawFd};
+    use std::os::unix::net::UnixStream;
+    use std::ptr;
+    use std::sync::mpsc;
+
+    use super::*;
+    use crate::poller::{popol, IoType};
+    use crate::{Action, Error, Io, WriteAtomic};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Reports sent to the test by the resources and the service from the
+    /// shard threads.
+    #[derive(Clone, Eq, PartialEq, Debug)]
+    enum Report {
+        Resumed(RawFd, String),
+        Disconnected(RawFd, String),
+        Error(String),
+    }
+
+    fn thread_name() -> String {
+        thread::current().name().unwrap_or_default().to_owned()
+    }
+
+    /// Resource over one end of a socket pair, reporting the name of the
+    /// shard thread it is resumed and disconnected by.
+    struct Peer {
+        socket: UnixStream,
+        reports: mpsc::Sender<Report>,
+    }
+
+    impl Peer {
+        /// Constructs resource and returns it together with the other end of
+        /// the socket pair, which must be kept open by the test.
+        fn pair(reports: &mpsc::Sender<Report>) -> (Self, UnixStream) {
+            let (socket, remote) = UnixStream::pair().unwrap();
+            socket.set_nonblocking(true).unwrap();
+            let peer = Peer {
+                socket,
+                reports: reports.clone(),
+            };
+            (peer, remote)
+        }
+    }
+
+    impl AsRawFd for Peer {
+        fn as_raw_fd(&self) -> RawFd {
+            self.socket.as_raw_fd()
+        }
+    }
+
+    impl Write for Peer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.socket.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.socket.flush()
+        }
+    }
+
+    impl WriteAtomic for Peer {
+        fn is_ready_to_write(&self) -> bool {
+            true
+        }
+
+        fn write_or_buffer(&mut self, buf: &[u8]) -> io::Result<()> {
+            self.socket.write_all(buf)
+        }
+    }
+
+    impl Resource for Peer {
+        type Id = RawFd;
+        type Event = Report;
+
+        fn id(&self) -> Self::Id {
+            self.as_raw_fd()
+        }
+
+        fn interests(&self) -> IoType {
+            IoType::read_only()
+        }
+
+        fn handle_io(&mut self, _: Io) -> Option<Self::Event> {
+            None
+        }
+
+        fn handle_resume(&mut self) -> Option<Self::Event> {
+            Some(Report::Resumed(self.id(), thread_name()))
+        }
+
+        fn disconnect(self) -> io::Result<()> {
+            // Test may have already stopped listening to the reports
+            let _ = self
+                .reports
+                .send(Report::Disconnected(self.id(), thread_name()));
+            Ok(())
+        }
+    }
+
+    /// Service forwarding the transport events and the errors to the test.
+    #[derive(Clone)]
+    struct Service(mpsc::Sender<Report>);
+
+    impl Iterator for Service {
+        type Item = Action<Peer, Peer>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            None
+        }
+    }
+
+    impl Handler for Service {
+        type Listener = Peer;
+        type Transport = Peer;
+        type Command = ();
+
+        fn tick(&mut self, _: Duration) {}
+        fn handle_wakeup(&mut self) {}
+        fn handle_listener_event(&mut self, _: RawFd, _: Report, _: Duration) {}
+
+        fn handle_transport_event(&mut self, _: RawFd, report: Report, _: Duration) {
+            let _ = self.0.send(report);
+        }
+
+        fn handle_command(&mut self, _: ()) {}
+
+        fn handle_error(&mut self, err: Error<Peer, Peer>) {
+            let _ = self.0.send(Report::Error(err.to_string()));
+        }
+
+        fn handover_listener(&mut self, _: Peer) {}
+        fn handover_transport(&mut self, _: Peer) {}
+    }
+
+    /// Returns index of the shard which transports with the given id are
+    /// dispatched to.
+    fn shard_no(controller: &MultiController<Service>, id: RawFd) -> usize {
+        let shard = controller.shard_for(id);
+        controller
+            .shards()
+            .iter()
+            .position(|controller| ptr::eq(controller, shard))
+            .expect("shard belongs to the controller")
+    }
+
+    #[test]
+    fn transports_are_controlled_by_their_shards() {
+        const SHARDS: usize = 4;
+        const TRANSPORTS: usize = 16;
+
+        let (sender, reports) = mpsc::channel();
+        let reactor =
+            MultiReactor::new(Service(sender.clone()), SHARDS, popol::Poller::new).unwrap();
+        let controller = reactor.controller();
+
+        let mut ids = vec![];
+        let mut remotes = vec![];
+        let mut load = [0; SHARDS];
+        for _ in 0..TRANSPORTS {
+            let (peer, remote) = Peer::pair(&sender);
+            let id = peer.id();
+            // All clones of the controller select the same shard for an id
+            let no = shard_no(&controller, id);
+            assert_eq!(shard_no(&controller.clone(), id), no);
+            assert_eq!(shard_no(&controller, id), no);
+            load[no] += 1;
+
+            controller.register_transport(peer).unwrap();
+            ids.push(id);
+            remotes.push(remote);
+        }
+        for (shard, load) in controller.shards().iter().zip(load) {
+            assert_eq!(shard.resource_count(), load);
+        }
+
+        // Requests addressing a transport by id are processed by the shard
+        // running it, which otherwise reports the transport as unknown
+        for id in &ids {
+            controller.pause_read(*id).unwrap();
+            controller.resume_read(*id).unwrap();
+            let shard = format!("reactor-{}", shard_no(&controller, *id));
+            assert_eq!(
+                reports.recv_timeout(TIMEOUT),
+                Ok(Report::Resumed(*id, shard.clone()))
+            );
+            controller.disconnect(*id).unwrap();
+            assert_eq!(
+                reports.recv_timeout(TIMEOUT),
+                Ok(Report::Disconnected(*id, shard))
+            );
+        }
+
+        reactor.shutdown_all().unwrap();
+        assert_eq!(reports.try_recv(), Err(mpsc::TryRecvError::Empty));
+        drop(remotes);
+    }
+
+    #[test]
+    fn listener_is_registered_with_least_loaded_shard() {
+        let (sender, reports) = mpsc::channel();
+        let reactor = MultiReactor::new(Service(sender.clone()), 2, popol::Poller::new).unwrap();
+        let controller = reactor.controller();
+
+        let (transport, transport_remote) = Peer::pair(&sender);
+        let busy = shard_no(&controller, transport.id());
+        controller.register_transport(transport).unwrap();
+        assert_eq!(controller.least_loaded().resource_count(), 0);
+
+        let (listener, listener_remote) = Peer::pair(&sender);
+        controller.register_listener(listener).unwrap();
+        assert_eq!(controller.shards()[busy].resource_count(), 1);
+        assert_eq!(controller.shards()[1 - busy].resource_count(), 1);
+        assert_eq!(controller.resource_count(), 2);
+
+        reactor.shutdown_all().unwrap();
+        assert!(reports
+            .try_iter()
+            .all(|report| !matches!(report, Report::Error(_))));
+        drop((transport_remote, listener_remote));
+    }
+}
@@ -1,8 +1,9 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Display, Formatter};
 use std::io::Write;
 use std::os::unix::io::{AsRawFd, RawFd};
 use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
 use std::time::{Duration, SystemTime};
@@ -66,6 +67,17 @@ pub enum Action<L: Resource, T: Resource> {
     UnregisterTransport(T::Id),
     #[display("send_to({0})")]
     Send(T::Id, Vec<u8>),
+    /// Stops polling the transport for read events until
+    /// [`Action::ResumeRead`] is received. Used to apply backpressure to the
+    /// resources feeding data to a transport which can't keep up with them.
+    #[display("pause_read({0})")]
+    PauseRead(T::Id),
+    #[display("resume_read({0})")]
+    ResumeRead(T::Id),
+    /// Unregisters the transport and disconnects it, without handing it over
+    /// to the service.
+    #[display("disconnect_transport({0})")]
+    DisconnectTransport(T::Id),
     #[display("set_timer({0:?})")]
     SetTimer(Duration),
 }
@@ -145,6 +157,7 @@ impl<S: Handler> Reactor<S> {
             cmd_send,
             ctl_send,
             waker: Arc::new(Mutex::new(waker_writer)),
+            load: default!(),
         };
 
         #[cfg(feature = "log")]
@@ -166,12 +179,16 @@ impl<S: Handler> Reactor<S> {
                 transports: empty!(),
                 listener_map: empty!(),
                 transport_map: empty!(),
+                paused: empty!(),
                 waker: waker_reader,
                 timeouts: TimeoutManager::new(Duration::from_secs(1)),
+                draining: None,
             };
 
             #[cfg(feature = "log")]
             log::info!(target: "reactor", "Entering reactor event loop");
+            #[cfg(feature = "tracing")]
+            tracing::info!(target: "reactor", "Entering reactor event loop");
 
             runtime.run();
         })?;
@@ -193,14 +210,31 @@ impl<S: Handler> Reactor<S> {
 enum Ctl<S: Handler> {
     RegisterListener(S::Listener),
     RegisterTransport(S::Transport),
+    PauseRead(<S::Transport as Resource>::Id),
+    ResumeRead(<S::Transport as Resource>::Id),
+    Disconnect(<S::Transport as Resource>::Id),
+    Drain(Duration),
     Shutdown,
 }
 
+/// Number of resources run by a reactor, shared by its controllers.
+#[derive(Debug, Default)]
+struct Load {
+    /// Resources registered with the runtime.
+    registered: AtomicUsize,
+    /// Resources sent for the registration with [`Controller`], which are not
+    /// yet processed by the runtime.
+    queued: AtomicUsize,
+    /// Transports registered with the runtime.
+    transports: AtomicUsize,
+}
+
 pub struct Controller<S: Handler> {
     // TODO: Unify command anc control channels
     cmd_send: chan::Sender<S::Command>,
     ctl_send: chan::Sender<Ctl<S>>,
     waker: Arc<Mutex<UnixStream>>,
+    load: Arc<Load>,
 }
 
 impl<S: Handler> Clone for Controller<S> {
@@ -209,28 +243,106 @@ impl<S: Handler> Clone for Controller<S> {
             cmd_send: self.cmd_send.clone(),
             ctl_send: self.ctl_send.clone(),
             waker: self.waker.clone(),
+            load: self.load.clone(),
         }
     }
 }
 
 impl<S: Handler> Controller<S> {
+    /// Returns number of listeners and transports run by the reactor,
+    /// including the ones sent for the registration which are not yet
+    /// registered.
+    pub fn resource_count(&self) -> usize {
+        self.load.registered.load(Ordering::Relaxed) + self.load.queued.load(Ordering::Relaxed)
+    }
+
+    /// Returns number of transports registered with the reactor, which allows
+    /// to observe the progress of [`Controller::drain`].
+    pub fn transport_count(&self) -> usize {
+        self.load.transports.load(Ordering::Relaxed)
+    }
+
     pub fn register_listener(&self, listener: S::Listener) -> Result<(), io::Error> {
         #[cfg(feature = "log")]
         log::debug!(target: "reactor-controller", "Registering listener {}", listener.id());
 
+        self.queue_registration(Ctl::RegisterListener(listener))
+    }
+
+    pub fn register_transport(&self, transport: S::Transport) -> Result<(), io::Error> {
+        #[cfg(feature = "log")]
+        log::debug!(target: "reactor-controller", "Registering transport {}", transport.id());
+
+        self.queue_registration(Ctl::RegisterTransport(transport))
+    }
+
+    fn queue_registration(&self, ctl: Ctl<S>) -> Result<(), io::Error> {
+        self.load.queued.fetch_add(1, Ordering::Relaxed);
+        if self.ctl_send.send(ctl).is_err() {
+            self.load.queued.fetch_sub(1, Ordering::Relaxed);
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        self.wake()?;
+        Ok(())
+    }
+
+    /// Stops reading from the transport until [`Controller::resume_read`] is
+    /// called for it. The transport is still polled for write events.
+    pub fn pause_read(&self, id: <S::Transport as Resource>::Id) -> Result<(), io::Error> {
+        #[cfg(feature = "log")]
+        log::debug!(target: "reactor-controller", "Pausing read from transport {id}");
+
         self.ctl_send
-            .send(Ctl::RegisterListener(listener))
+            .send(Ctl::PauseRead(id))
             .map_err(|_| io::ErrorKind::BrokenPipe)?;
         self.wake()?;
         Ok(())
     }
 
-    pub fn register_transport(&self, transport: S::Transport) -> Result<(), io::Error> {
+    /// Resumes reading from the transport paused with
+    /// [`Controller::pause_read`]. The transport is notified with
+    /// [`Resource::handle_resume`] even if it was not paused.
+    pub fn resume_read(&self, id: <S::Transport as Resource>::Id) -> Result<(), io::Error> {
         #[cfg(feature = "log")]
-        log::debug!(target: "reactor-controller", "Registering transport {}", transport.id());
+        log::debug!(target: "reactor-controller", "Resuming read from transport {id}");
+
+        self.ctl_send
+            .send(Ctl::ResumeRead(id))
+            .map_err(|_| io::ErrorKind::BrokenPipe)?;
+        self.wake()?;
+        Ok(())
+    }
+
+    /// Unregisters the transport from the reactor and disconnects it. Any
+    /// activity the transport has scheduled for itself, like re-dialing a
+    /// dropped connection, is cancelled.
+    pub fn disconnect(&self, id: <S::Transport as Resource>::Id) -> Result<(), io::Error> {
+        #[cfg(feature = "log")]
+        log::debug!(target: "reactor-controller", "Disconnecting transport {id}");
 
         self.ctl_send
-            .send(Ctl::RegisterTransport(transport))
+            .send(Ctl::Disconnect(id))
+            .map_err(|_| io::ErrorKind::BrokenPipe)?;
+        self.wake()?;
+        Ok(())
+    }
+
+    /// Gracefully shuts down the reactor. The reactor stops accepting
+    /// connections by handing over all its listeners to the service and
+    /// notifies each of its transports with [`Resource::handle_drain`], so
+    /// the application may finish the sessions and unregister them. Listeners
+    /// registered afterwards are handed over right away.
+    ///
+    /// The reactor shuts down once no transports are left or once the
+    /// `deadline`, a time as a duration since the UNIX epoch, is reached;
+    /// the remaining number of transports is reported by
+    /// [`Controller::transport_count`].
+    pub fn drain(&self, deadline: Duration) -> Result<(), io::Error> {
+        #[cfg(feature = "log")]
+        log::info!(target: "reactor-controller", "Initiating reactor drain...");
+
+        self.ctl_send
+            .send(Ctl::Drain(deadline))
             .map_err(|_| io::ErrorKind::BrokenPipe)?;
         self.wake()?;
         Ok(())
@@ -271,16 +383,18 @@ impl<S: Handler> Controller<S> {
         match waker.write_all(&[0x1]) {
             Ok(_) => Ok(()),
             Err(e) if e.kind() == WouldBlock => {
+                // The reactor has not yet consumed the previous wake-ups, so
+                // it is going to be woken up anyway
                 #[cfg(feature = "log")]
-                log::error!(target: "reactor-controller", "Waker write queue got overfilled, resetting and repeating...");
+                log::trace!(target: "reactor-controller", "Waker write queue is full, the reactor is already woken up");
 
-                reset_fd(&waker.as_raw_fd())?;
-                self.wake()
+                Ok(())
             }
             Err(e) if e.kind() == Interrupted => {
                 #[cfg(feature = "log")]
                 log::error!(target: "reactor-controller", "Waker failure, repeating...");
 
+                drop(waker);
                 self.wake()
             }
             Err(e) => {
@@ -331,8 +445,13 @@ pub struct Runtime<H: Handler, P: Poll> {
     transport_map: HashMap<RawFd, <H::Transport as Resource>::Id>,
     listeners: HashMap<<H::Listener as Resource>::Id, H::Listener>,
     transports: HashMap<<H::Transport as Resource>::Id, H::Transport>,
+    /// Transports which are not polled for read events.
+    paused: HashSet<<H::Transport as Resource>::Id>,
     waker: UnixStream,
     timeouts: TimeoutManager,
+    /// Deadline of the drain started with [`Controller::drain`], as a
+    /// duration since the UNIX epoch.
+    draining: Option<Duration>,
 }
 
 impl<H: Handler, P: Poll> Runtime<H, P> {
@@ -348,6 +467,7 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
             cmd_send,
             ctl_send,
             waker: Arc::new(Mutex::new(waker_writer)),
+            load: default!(),
         };
 
         Ok(Runtime {
@@ -360,8 +480,10 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
             transports: empty!(),
             listener_map: empty!(),
             transport_map: empty!(),
+            paused: empty!(),
             waker: waker_reader,
             timeouts: TimeoutManager::new(Duration::from_secs(1)),
+            draining: None,
         })
     }
 
@@ -370,21 +492,52 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
     }
 
     fn run(mut self) {
+        #[cfg(feature = "tracing")]
+        let mut iteration = 0u64;
         loop {
+            #[cfg(feature = "tracing")]
+            let _span = {
+                iteration += 1;
+                tracing::trace_span!(target: "reactor", "iteration", no = iteration).entered()
+            };
+
             let before_poll = SystemTime::now()
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .expect("system time");
+            if let Some(deadline) = self.draining {
+                if self.transports.is_empty() || deadline <= before_poll {
+                    return self.handle_shutdown();
+                }
+            }
+            let deadline = self
+                .listeners
+                .values()
+                .filter_map(|listener| listener.deadline())
+                .chain(
+                    self.transports
+                        .values()
+                        .filter_map(|transport| transport.deadline()),
+                )
+                .chain(self.draining)
+                .min()
+                .map(|deadline| deadline.saturating_sub(before_poll));
             let timeout = self
                 .timeouts
                 .next(before_poll)
-                .unwrap_or(WAIT_TIMEOUT)
-                .into();
+                .into_iter()
+                .chain(deadline)
+                .min()
+                .unwrap_or(WAIT_TIMEOUT);
 
             for res in self.listeners.values() {
                 self.poller.set_interest(res, res.interests());
             }
-            for res in self.transports.values() {
-                self.poller.set_interest(res, res.interests());
+            for (id, res) in &self.transports {
+                let mut interests = res.interests();
+                if self.paused.contains(id) {
+                    interests.read = false;
+                }
+                self.poller.set_interest(res, interests);
             }
 
             // Blocking
@@ -394,12 +547,20 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
                 Ok(0) => {
                     #[cfg(feature = "log")]
                     log::trace!(target: "reactor", "Timeout");
+                    let now = SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .expect("system time");
+                    if self.handle_deadlines(now) {
+                        self.handle_actions(now);
+                    }
                     continue;
                 }
                 Ok(count) => count,
                 Err(err) => {
                     #[cfg(feature = "log")]
                     log::error!(target: "reactor", "Error during polling: {err}");
+                    #[cfg(feature = "tracing")]
+                    tracing::error!(target: "reactor", %err, "Error during polling");
                     self.service.handle_error(Error::Poll(err));
                     continue;
                 }
@@ -411,6 +572,7 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
             self.service.tick(now);
 
             let awoken = self.handle_events(now);
+            self.handle_deadlines(now);
 
             // Process the commands only if we awaken by the waker
             if awoken {
@@ -430,12 +592,34 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
                             panic!("shutdown channel is broken")
                         }
                         Ok(Ctl::Shutdown) => return self.handle_shutdown(),
-                        Ok(Ctl::RegisterListener(listener)) => self
-                            .handle_action(Action::RegisterListener(listener), now)
-                            .expect("register actions do not error"),
-                        Ok(Ctl::RegisterTransport(transport)) => self
-                            .handle_action(Action::RegisterTransport(transport), now)
-                            .expect("register actions do not error"),
+                        Ok(Ctl::RegisterListener(listener)) => {
+                            self.handle_action(Action::RegisterListener(listener), now)
+                                .expect("register actions do not error");
+                            self.dequeue_registration();
+                        }
+                        Ok(Ctl::RegisterTransport(transport)) => {
+                            self.handle_action(Action::RegisterTransport(transport), now)
+                                .expect("register actions do not error");
+                            self.dequeue_registration();
+                        }
+                        Ok(Ctl::PauseRead(id)) => {
+                            if let Err(err) = self.handle_action(Action::PauseRead(id), now) {
+                                self.service.handle_error(err);
+                            }
+                        }
+                        Ok(Ctl::ResumeRead(id)) => {
+                            if let Err(err) = self.handle_action(Action::ResumeRead(id), now) {
+                                self.service.handle_error(err);
+                            }
+                        }
+                        Ok(Ctl::Disconnect(id)) => {
+                            if let Err(err) =
+                                self.handle_action(Action::DisconnectTransport(id), now)
+                            {
+                                self.service.handle_error(err);
+                            }
+                        }
+                        Ok(Ctl::Drain(deadline)) => self.handle_drain(deadline, now),
                     }
                 }
             }
@@ -454,8 +638,11 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
         for (fd, res) in &mut self.poller {
             if fd == self.waker.as_raw_fd() {
                 if let Err(err) = res {
+                    #[cfg(feature = "log")]
                     log::error!(target: "reactor", "Polling waker has failed: {err}");
-                    panic!("waker failure");
+                    #[cfg(feature = "tracing")]
+                    tracing::error!(target: "reactor", %err, "Polling waker has failed");
+                    panic!("waker failure: {err}");
                 };
 
                 #[cfg(feature = "log")]
@@ -464,6 +651,9 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
                 reset_fd(&self.waker).expect("waker failure");
                 awoken = true;
             } else if let Some(id) = self.listener_map.get(&fd) {
+                #[cfg(feature = "tracing")]
+                let _span = tracing::debug_span!(target: "reactor", "listener", id = ?id).entered();
+
                 match res {
                     Ok(io) => {
                         #[cfg(feature = "log")]
@@ -479,6 +669,8 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
                     Err(IoFail::Connectivity(flags)) => {
                         #[cfg(feature = "log")]
                         log::trace!(target: "reactor", "Listener {id} hung up (OS flags {flags:#b})");
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!(target: "reactor", flags, "Listener hung up");
 
                         let listener = self.listeners.remove(id).expect("resource disappeared");
                         unregister_queue.push(listener.as_raw_fd());
@@ -488,12 +680,18 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
                     Err(IoFail::Os(flags)) => {
                         #[cfg(feature = "log")]
                         log::trace!(target: "reactor", "Listener {id} errored (OS flags {flags:#b})");
+                        #[cfg(feature = "tracing")]
+                        tracing::error!(target: "reactor", flags, "Listener errored");
 
                         self.service
                             .handle_error(Error::ListenerPollError(*id, flags));
                     }
                 }
             } else if let Some(id) = self.transport_map.get(&fd) {
+                #[cfg(feature = "tracing")]
+                let _span =
+                    tracing::debug_span!(target: "reactor", "transport", id = ?id).entered();
+
                 match res {
                     Ok(io) => {
                         #[cfg(feature = "log")]
@@ -509,8 +707,21 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
                     Err(IoFail::Connectivity(flags)) => {
                         #[cfg(feature = "log")]
                         log::trace!(target: "reactor", "Transport {id} hanged up (OS flags {flags:#b})");
+                        #[cfg(feature = "tracing")]
+                        tracing::debug!(target: "reactor", flags, "Transport hung up");
 
+                        let transport = self.transports.get_mut(id).expect("resource disappeared");
+                        if let Some(event) = transport.handle_hangup() {
+                            #[cfg(feature = "log")]
+                            log::debug!(target: "reactor", "Transport {id} has recovered from the hang up");
+                            #[cfg(feature = "tracing")]
+                            tracing::debug!(target: "reactor", "Transport has recovered from the hang up");
+
+                            self.service.handle_transport_event(*id, event, time);
+                            continue;
+                        }
                         let transport = self.transports.remove(id).expect("resource disappeared");
+                        self.paused.remove(id);
                         unregister_queue.push(transport.as_raw_fd());
                         self.service
                             .handle_error(Error::TransportDisconnect(*id, transport, flags));
@@ -518,6 +729,8 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
                     Err(IoFail::Os(flags)) => {
                         #[cfg(feature = "log")]
                         log::trace!(target: "reactor", "Transport {id} errored (OS flags {flags:#b})");
+                        #[cfg(feature = "tracing")]
+                        tracing::error!(target: "reactor", flags, "Transport errored");
 
                         self.service
                             .handle_error(Error::TransportPollError(*id, flags));
@@ -538,6 +751,52 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
         awoken
     }
 
+    /// Calls [`Resource::handle_timeout`] on the listeners and transports which
+    /// deadline has been reached.
+    ///
+    /// # Returns
+    ///
+    /// Whether any of the deadlines were reached
+    fn handle_deadlines(&mut self, time: Duration) -> bool {
+        let mut reached = false;
+        for (id, listener) in &mut self.listeners {
+            if !matches!(listener.deadline(), Some(deadline) if deadline <= time) {
+                continue;
+            }
+            reached = true;
+
+            #[cfg(feature = "log")]
+            log::trace!(target: "reactor", "Deadline of listener {id} is reached");
+            #[cfg(feature = "tracing")]
+            tracing::trace!(target: "reactor", id = ?id, "Listener deadline is reached");
+
+            if let Some(event) = listener.handle_timeout(time) {
+                self.service.handle_listener_event(*id, event, time);
+            }
+        }
+        for (id, transport) in &mut self.transports {
+            if !matches!(transport.deadline(), Some(deadline) if deadline <= time) {
+                continue;
+            }
+            reached = true;
+
+            #[cfg(feature = "log")]
+            log::trace!(target: "reactor", "Deadline of transport {id} is reached");
+            #[cfg(feature = "tracing")]
+            tracing::trace!(target: "reactor", id = ?id, "Transport deadline is reached");
+
+            let event = transport.handle_timeout(time);
+            if transport.take_fd_replaced() {
+                self.poller.unregister(&*transport);
+                self.poller.register(&*transport, transport.interests());
+            }
+            if let Some(event) = event {
+                self.service.handle_transport_event(*id, event, time);
+            }
+        }
+        reached
+    }
+
     fn handle_actions(&mut self, time: Duration) {
         while let Some(action) = self.service.next() {
             #[cfg(feature = "log")]
@@ -548,9 +807,55 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
             if let Err(err) = self.handle_action(action, time) {
                 #[cfg(feature = "log")]
                 log::error!(target: "reactor", "Error: {err}");
+                #[cfg(feature = "tracing")]
+                tracing::error!(target: "reactor", %err, "Error handling action");
                 self.service.handle_error(err);
             }
         }
+        self.update_load();
+    }
+
+    /// Accounts registration of a resource sent with [`Controller`] in the
+    /// reactor load. The resource is counted as registered first, so the
+    /// load is never underestimated.
+    fn dequeue_registration(&self) {
+        self.update_load();
+        self.controller.load.queued.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn update_load(&self) {
+        self.controller.load.registered.store(
+            self.listeners.len() + self.transports.len(),
+            Ordering::Relaxed,
+        );
+        self.controller
+            .load
+            .transports
+            .store(self.transports.len(), Ordering::Relaxed);
+    }
+
+    /// Starts draining (see [`Controller::drain`]): hands over all listeners
+    /// to the service and notifies the transports.
+    fn handle_drain(&mut self, deadline: Duration, time: Duration) {
+        #[cfg(feature = "log")]
+        log::info!(target: "reactor", "Draining {} transports until {deadline:?}", self.transports.len());
+        #[cfg(feature = "tracing")]
+        tracing::info!(target: "reactor", transports = self.transports.len(), ?deadline, "Draining");
+
+        // Repeated drain may only bring the deadline closer
+        self.draining = Some(self.draining.map_or(deadline, |prev| prev.min(deadline)));
+
+        let listeners = self.listeners.keys().copied().collect::<Vec<_>>();
+        for id in listeners {
+            self.handle_action(Action::UnregisterListener(id), time)
+                .expect("listener is registered");
+        }
+        for (id, transport) in &mut self.transports {
+            if let Some(event) = transport.handle_drain() {
+                self.service.handle_transport_event(*id, event, time);
+            }
+        }
+        self.update_load();
     }
 
     fn handle_action(
@@ -559,12 +864,22 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
         time: Duration,
     ) -> Result<(), Error<H::Listener, H::Transport>> {
         match action {
+            Action::RegisterListener(listener) if self.draining.is_some() => {
+                #[cfg(feature = "log")]
+                log::debug!(target: "reactor", "Handing over listener on {} registered while draining", listener.id());
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "reactor", id = ?listener.id(), "Handing over listener registered while draining");
+
+                self.service.handover_listener(listener);
+            }
             Action::RegisterListener(listener) => {
                 let id = listener.id();
                 let fd = listener.as_raw_fd();
 
                 #[cfg(feature = "log")]
                 log::debug!(target: "reactor", "Registering listener on {id} (fd={fd})");
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "reactor", id = ?id, fd, "Registering listener");
 
                 self.poller.register(&listener, IoType::read_only());
                 self.listeners.insert(id, listener);
@@ -576,6 +891,8 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
 
                 #[cfg(feature = "log")]
                 log::debug!(target: "reactor", "Registering transport on {id} (fd={fd})");
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "reactor", id = ?id, fd, "Registering transport");
 
                 self.poller.register(&transport, IoType::read_only());
                 self.transports.insert(id, transport);
@@ -590,6 +907,8 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
 
                 #[cfg(feature = "log")]
                 log::debug!(target: "reactor", "Handling over listener {id} (fd={fd})");
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "reactor", id = ?id, fd, "Handing over listener");
 
                 self.listener_map
                     .remove(&fd)
@@ -606,10 +925,13 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
 
                 #[cfg(feature = "log")]
                 log::debug!(target: "reactor", "Handling over transport {id} (fd={fd})");
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "reactor", id = ?id, fd, "Handing over transport");
 
                 self.transport_map
                     .remove(&fd)
                     .expect("transport index content doesn't match registered transports");
+                self.paused.remove(&id);
                 self.poller.unregister(&transport);
                 self.service.handover_transport(transport);
             }
@@ -620,6 +942,8 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
                 let transport = self.transports.get_mut(&id).ok_or_else(|| {
                     #[cfg(feature = "log")]
                     log::error!(target: "reactor", "Transport {id} is not in the reactor");
+                    #[cfg(feature = "tracing")]
+                    tracing::error!(target: "reactor", id = ?id, "Transport is not in the reactor");
 
                     Error::TransportUnknown(id)
                 })?;
@@ -628,14 +952,73 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
                         #[cfg(feature = "log")]
                         log::error!(target: "reactor", internal = true; 
                                 "An attempt to write to transport {id} before it got ready");
+                        #[cfg(feature = "tracing")]
+                        tracing::error!(target: "reactor", id = ?id, internal = true,
+                                "An attempt to write to transport before it got ready");
                         Error::WriteLogicError(id, data)
                     }
                     WriteError::Io(e) => {
                         #[cfg(feature = "log")]
                         log::error!(target: "reactor", "Error writing to transport {id}: {e:?}");
+                        #[cfg(feature = "tracing")]
+                        tracing::error!(target: "reactor", id = ?id, err = %e, "Error writing to transport");
                         Error::WriteFailure(id, e)
                     }
                 })?;
+                if let Some(event) = transport.handle_queued() {
+                    self.service.handle_transport_event(id, event, time);
+                }
+            }
+            Action::PauseRead(id) => {
+                #[cfg(feature = "log")]
+                log::debug!(target: "reactor", "Pausing read from transport {id}");
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "reactor", id = ?id, "Pausing read from transport");
+
+                if !self.transports.contains_key(&id) {
+                    return Err(Error::TransportUnknown(id));
+                }
+                self.paused.insert(id);
+            }
+            Action::ResumeRead(id) => {
+                #[cfg(feature = "log")]
+                log::debug!(target: "reactor", "Resuming read from transport {id}");
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "reactor", id = ?id, "Resuming read from transport");
+
+                let transport = self
+                    .transports
+                    .get_mut(&id)
+                    .ok_or(Error::TransportUnknown(id))?;
+                self.paused.remove(&id);
+                if let Some(event) = transport.handle_resume() {
+                    self.service.handle_transport_event(id, event, time);
+                }
+            }
+            Action::DisconnectTransport(id) => {
+                let transport = self
+                    .transports
+                    .remove(&id)
+                    .ok_or(Error::TransportUnknown(id))?;
+                let fd = transport.as_raw_fd();
+
+                #[cfg(feature = "log")]
+                log::debug!(target: "reactor", "Disconnecting transport {id} (fd={fd})");
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "reactor", id = ?id, fd, "Disconnecting transport");
+
+                self.transport_map
+                    .remove(&fd)
+                    .expect("transport index content doesn't match registered transports");
+                self.paused.remove(&id);
+                self.poller.unregister(&transport);
+                #[allow(unused_variables)]
+                if let Err(err) = transport.disconnect() {
+                    #[cfg(feature = "log")]
+                    log::warn!(target: "reactor", "Error disconnecting transport {id}: {err}");
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(target: "reactor", id = ?id, %err, "Error disconnecting transport");
+                }
             }
             Action::SetTimer(duration) => {
                 #[cfg(feature = "log")]
@@ -649,7 +1032,9 @@ impl<H: Handler, P: Poll> Runtime<H, P> {
 
     fn handle_shutdown(self) {
         #[cfg(feature = "log")]
-        log::info!(target: "reactor", "Shutdown");
+        log::info!(target: "reactor", "Shutdown with {} transports left", self.transports.len());
+        #[cfg(feature = "tracing")]
+        tracing::info!(target: "reactor", transports = self.transports.len(), "Shutdown");
 
         // We just drop here?
     }
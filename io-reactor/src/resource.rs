@@ -2,6 +2,7 @@ use std::fmt::{Debug, Display};
 use std::hash::Hash;
 use std::os::unix::io::AsRawFd;
 use std::os::unix::prelude::RawFd;
+use std::time::Duration;
 use std::{io, net};
 
 use crate::poller::IoType;
@@ -23,6 +24,66 @@ pub trait Resource: AsRawFd + WriteAtomic + Send {
 
     fn handle_io(&mut self, io: Io) -> Option<Self::Event>;
 
+    /// Called by the reactor each time the data were queued to the resource
+    /// with [`WriteAtomic::write_atomic`], allowing the resource to report
+    /// changes in its state caused by the write (for instance, its write
+    /// queue reaching a high watermark).
+    fn handle_queued(&mut self) -> Option<Self::Event> {
+        None
+    }
+
+    /// Called by the reactor each time reading from the resource is resumed
+    /// with [`crate::Controller::resume_read`], allowing the resource to
+    /// leave a state in which it was parked by the application.
+    fn handle_resume(&mut self) -> Option<Self::Event> {
+        None
+    }
+
+    /// Returns time, as a duration since the UNIX epoch, at which the reactor
+    /// must call [`Resource::handle_timeout`] even if there is no I/O on the
+    /// resource; for instance, the deadline of a handshake. The deadline is
+    /// re-read by the reactor each time before polling.
+    fn deadline(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Called by the reactor once the [`Resource::deadline`] is reached,
+    /// with the current time as a duration since the UNIX epoch. The
+    /// resource must clear or postpone its deadline, otherwise the method is
+    /// called again on each reactor iteration.
+    fn handle_timeout(&mut self, _time: Duration) -> Option<Self::Event> {
+        None
+    }
+
+    /// Returns whether the file descriptor of the resource was re-pointed to
+    /// another file (for instance, to a re-dialed connection) since the last
+    /// call. The reactor checks it after each [`Resource::handle_timeout`]
+    /// and re-registers such resources with the poller, since pollers based
+    /// on `epoll` watch the registered files rather than the descriptor
+    /// numbers.
+    fn take_fd_replaced(&mut self) -> bool {
+        false
+    }
+
+    /// Called by the reactor when the poller reports that the transport has
+    /// hung up. Resources able to recover from the hang up (for instance, by
+    /// re-dialing the connection) return an event reporting the recovery and
+    /// stay registered; by default the resource is unregistered and passed to
+    /// [`crate::Handler::handle_error`] inside
+    /// [`crate::Error::TransportDisconnect`].
+    fn handle_hangup(&mut self) -> Option<Self::Event> {
+        None
+    }
+
+    /// Called by the reactor on each of its transports once the reactor
+    /// starts draining with [`crate::Controller::drain`], allowing the
+    /// resource to report to the application that it must finish the work
+    /// with the resource (for instance, send a "going away" message to the
+    /// peer) and unregister it.
+    fn handle_drain(&mut self) -> Option<Self::Event> {
+        None
+    }
+
     fn disconnect(self) -> io::Result<()>;
 }
 
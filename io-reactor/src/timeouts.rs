@@ -1,10 +1,26 @@
+use std::cmp;
 use std::time::Duration;
 
+/// Maximum number of times a key of an interval timer is yielded by a single
+/// [`TimeoutManager::check`] call if multiple periods of the timer were
+/// missed; the rest of the missed periods are skipped.
+pub const MAX_INTERVAL_REPEATS: u32 = 8;
+
+/// Timer registered with [`TimeoutManager`].
+#[derive(Debug)]
+struct Timer<K> {
+    key: K,
+    /// Time of the next expiration as a duration since the UNIX epoch.
+    time: Duration,
+    /// Period of the interval timers; `None` for one-shot timeouts.
+    period: Option<Duration>,
+}
+
 /// Manages timers and triggers timeouts.
 #[derive(Debug)]
 pub struct TimeoutManager<K = ()> {
-    /// Timeouts are durations since the UNIX epoch.
-    timeouts: Vec<(K, Duration)>,
+    /// Timers sorted by the expiration time in descending order.
+    timeouts: Vec<Timer<K>>,
     /// Threshold below which a timeout can't be added if another timeout is set
     /// within the range of the threshold.
     threshold: Duration,
@@ -57,23 +73,71 @@ impl<K> TimeoutManager<K> {
     /// ```
     pub fn register(&mut self, key: K, time: Duration) -> bool {
         // If this timeout is too close to a pre-existing timeout,
-        // don't register it.
-        if self.timeouts.iter().any(|(_, t)| {
-            if *t < time {
-                time - *t < self.threshold
-            } else {
-                *t - time < self.threshold
-            }
-        }) {
+        // don't register it. Interval timers are not taken into account.
+        if self
+            .timeouts
+            .iter()
+            .filter(|timer| timer.period.is_none())
+            .any(|Timer { time: t, .. }| {
+                if *t < time {
+                    time - *t < self.threshold
+                } else {
+                    *t - time < self.threshold
+                }
+            })
+        {
             return false;
         }
 
-        self.timeouts.push((key, time));
-        self.timeouts.sort_unstable_by(|(_, a), (_, b)| b.cmp(a));
-
+        self.insert(Timer {
+            key,
+            time,
+            period: None,
+        });
         true
     }
 
+    /// Register a timer which expires each `period` starting from `now`
+    /// (a UNIX time epoch) until it is cancelled with
+    /// [`TimeoutManager::cancel`]. The threshold does not apply to interval
+    /// timers.
+    ///
+    /// The timer keeps its phase: it is re-scheduled relatively to the time
+    /// it was due at and not to the time it was checked at.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use reactor::TimeoutManager;
+    ///
+    /// let mut tm = TimeoutManager::new(Duration::from_secs(1));
+    /// let now = Duration::from_secs(100);
+    /// tm.register_interval(0xA, Duration::from_secs(30), now);
+    ///
+    /// let mut timeouts = Vec::new();
+    /// assert_eq!(tm.check(now + Duration::from_secs(31), &mut timeouts), 1);
+    /// assert_eq!(tm.check(now + Duration::from_secs(61), &mut timeouts), 1);
+    /// assert_eq!(timeouts, vec![0xA, 0xA]);
+    /// assert_eq!(tm.next(now + Duration::from_secs(61)), Some(Duration::from_secs(29)));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// If the period is zero.
+    pub fn register_interval(&mut self, key: K, period: Duration, now: impl Into<Duration>) {
+        assert!(!period.is_zero(), "zero period of the interval timer");
+        self.insert(Timer {
+            key,
+            time: now.into() + period,
+            period: Some(period),
+        });
+    }
+
+    fn insert(&mut self, timer: Timer<K>) {
+        self.timeouts.push(timer);
+        self.timeouts
+            .sort_unstable_by_key(|timer| cmp::Reverse(timer.time));
+    }
+
     /// Get the minimum time duration we should wait for at least one timeout
     /// to be reached.  Returns `None` if there are no timeouts.
     ///
@@ -99,7 +163,7 @@ impl<K> TimeoutManager<K> {
     /// ```
     pub fn next(&self, now: impl Into<Duration>) -> Option<Duration> {
         let now = now.into();
-        self.timeouts.last().map(|(_, t)| {
+        self.timeouts.last().map(|Timer { time: t, .. }| {
             if *t >= now {
                 *t - now
             } else {
@@ -107,19 +171,58 @@ impl<K> TimeoutManager<K> {
             }
         })
     }
+}
 
+impl<K: PartialEq> TimeoutManager<K> {
+    /// Cancel all timers, both one-shot and interval ones, registered with
+    /// the key. Returns whether any timers were cancelled.
+    ///
+    /// Keys which were already added to the vector by
+    /// [`TimeoutManager::check`] are not affected, but a cancelled timer is
+    /// not yielded again even if it has expired before the cancellation.
+    pub fn cancel(&mut self, key: &K) -> bool {
+        let len = self.timeouts.len();
+        self.timeouts.retain(|timer| &timer.key != key);
+        self.timeouts.len() < len
+    }
+}
+
+impl<K: Clone> TimeoutManager<K> {
     /// Given a specific time, add to the input vector keys that
     /// have timed out by that time. Returns the number of keys that timed out.
+    ///
+    /// Interval timers are re-scheduled; if they have missed several periods
+    /// by that time their key is added once per missed period, but no more
+    /// than [`MAX_INTERVAL_REPEATS`] times.
     pub fn check(&mut self, time: Duration, fired: &mut Vec<K>) -> usize {
         let before = fired.len();
 
-        while let Some((k, t)) = self.timeouts.pop() {
-            if time >= t {
-                fired.push(k);
-            } else {
-                self.timeouts.push((k, t));
+        let mut rescheduled = vec![];
+        while let Some(mut timer) = self.timeouts.pop() {
+            if time < timer.time {
+                self.timeouts.push(timer);
                 break;
             }
+            let period = match timer.period {
+                None => {
+                    fired.push(timer.key);
+                    continue;
+                }
+                Some(period) => period,
+            };
+            let elapsed = (time - timer.time).as_nanos();
+            let missed = elapsed / period.as_nanos();
+            let repeats = (missed + 1).min(MAX_INTERVAL_REPEATS as u128) as usize;
+            for _ in 0..repeats {
+                fired.push(timer.key.clone());
+            }
+            // Remainder is less than the period, so it fits into u64 nanoseconds
+            let phase = Duration::from_nanos((elapsed % period.as_nanos()) as u64);
+            timer.time = time - phase + period;
+            rescheduled.push(timer);
+        }
+        for timer in rescheduled {
+            self.insert(timer);
         }
         fired.len() - before
     }
@@ -184,4 +287,82 @@ mod tests {
         assert_eq!(timeouts, vec![0xD]);
         assert!(tm.is_empty(), "all timeouts have expired");
     }
+
+    #[test]
+    fn test_interval() {
+        let mut tm = TimeoutManager::new(Duration::from_secs(1));
+        let now = Duration::from_secs(1000);
+
+        tm.register_interval(0xA, Duration::from_secs(30), now);
+        // Threshold applies only to one-shot timeouts
+        assert!(tm.register(0xB, now + Duration::from_secs(30)));
+        assert_eq!(tm.next(now), Some(Duration::from_secs(30)));
+
+        let mut timeouts = Vec::new();
+        assert_eq!(tm.check(now + Duration::from_secs(29), &mut timeouts), 0);
+        assert_eq!(tm.check(now + Duration::from_secs(35), &mut timeouts), 2);
+        // Order of the timers expiring at the same time is not defined
+        timeouts.sort();
+        assert_eq!(timeouts, vec![0xA, 0xB]);
+        assert_eq!(tm.len(), 1, "interval is re-scheduled");
+
+        // Delayed check does not shift the phase
+        assert_eq!(
+            tm.next(now + Duration::from_secs(35)),
+            Some(Duration::from_secs(25))
+        );
+        timeouts.clear();
+        assert_eq!(tm.check(now + Duration::from_secs(60), &mut timeouts), 1);
+        assert_eq!(timeouts, vec![0xA]);
+    }
+
+    #[test]
+    fn test_missed_intervals() {
+        let mut tm = TimeoutManager::new(Duration::from_secs(0));
+        let now = Duration::from_secs(1000);
+        let period = Duration::from_millis(10);
+        tm.register_interval(0xA, period, now);
+
+        // Three periods were missed
+        let mut timeouts = Vec::new();
+        assert_eq!(tm.check(now + Duration::from_millis(35), &mut timeouts), 3);
+        assert_eq!(timeouts, vec![0xA; 3]);
+        assert_eq!(
+            tm.next(now + Duration::from_millis(35)),
+            Some(Duration::from_millis(5))
+        );
+
+        // Reactor stalled for a long time: repeats are capped
+        timeouts.clear();
+        let time = now + Duration::from_secs(60) + Duration::from_millis(7);
+        assert_eq!(tm.check(time, &mut timeouts), MAX_INTERVAL_REPEATS as usize);
+        assert_eq!(tm.next(time), Some(Duration::from_millis(3)));
+    }
+
+    #[test]
+    fn test_cancel() {
+        let mut tm = TimeoutManager::new(Duration::from_secs(0));
+        let now = Duration::from_secs(1000);
+        let period = Duration::from_secs(30);
+        tm.register_interval(0xA, period, now);
+        tm.register_interval(0xB, period, now);
+        tm.register(0xC, now + period);
+
+        // Timers have expired, but are cancelled before being checked
+        let time = now + period;
+        assert!(tm.cancel(&0xA));
+        assert!(tm.cancel(&0xC));
+        assert!(!tm.cancel(&0xC));
+        let mut timeouts = Vec::new();
+        assert_eq!(tm.check(time, &mut timeouts), 1);
+        assert_eq!(timeouts, vec![0xB]);
+
+        // Interval cancelled right after it has fired is not re-scheduled
+        assert!(tm.cancel(&0xB));
+        assert!(tm.is_empty());
+        assert_eq!(tm.next(time), None);
+        timeouts.clear();
+        assert_eq!(tm.check(time + period * 10, &mut timeouts), 0);
+        assert!(timeouts.is_empty());
+    }
 }
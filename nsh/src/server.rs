@@ -67,9 +67,11 @@ impl<D: Delegate> reactor::Handler for Server<D> {
     ) {
         log::trace!(target: "server", "Listener event on {id} at {time:?}");
         match event {
-            ListenerEvent::Accepted(session) => {
+            ListenerEvent::Accepted(session, guard) => {
                 log::info!(target: "server", "Incoming connection from {} on {}", session.transient_addr(), session.local_addr());
-                match Transport::new(session) {
+                match Transport::new(session)
+                    .map(|transport| transport.with_connection_guard(guard))
+                {
                     Ok(transport) => {
                         log::info!(target: "server", "Connection accepted, registering {} with reactor", transport.transient_addr());
                         self.action_queue
@@ -80,6 +82,18 @@ impl<D: Delegate> reactor::Handler for Server<D> {
                     }
                 }
             }
+            ListenerEvent::RateLimited(ip) => {
+                log::warn!(target: "server", "Connection from {ip} on {id} is dropped due to exceeded rate limit")
+            }
+            ListenerEvent::CapReached { current, max } => {
+                log::warn!(target: "server", "Connection on {id} is dropped since {current} out of {max} allowed connections are open")
+            }
+            ListenerEvent::Paused { err, backoff } => {
+                log::error!(target: "server", "Listener {id} stops accepting connections for {backoff:?}: {err}")
+            }
+            ListenerEvent::Resumed => {
+                log::info!(target: "server", "Listener {id} resumes accepting connections")
+            }
             ListenerEvent::Failure(err) => {
                 log::error!(target: "server", "Error on listener {id}: {err}")
             }
@@ -94,7 +108,7 @@ impl<D: Delegate> reactor::Handler for Server<D> {
     ) {
         log::trace!(target: "server", "I/O on {id} at {time:?}");
         match event {
-            SessionEvent::Established(key) => {
+            SessionEvent::Established(key, _) => {
                 let queue = self.outbox.remove(&id).unwrap_or_default();
                 log::debug!(target: "server", "Connection with remote peer {key}@{id} successfully established; processing {} items from outbox", queue.len());
                 self.action_queue.extend(self.delegate.new_client(id, key));
@@ -106,6 +120,25 @@ impl<D: Delegate> reactor::Handler for Server<D> {
                 self.action_queue
                     .extend(self.delegate.input(id, data, &self.ecdh));
             }
+            SessionEvent::Paused => {
+                log::debug!(target: "server", "Write queue of {id} is full");
+            }
+            SessionEvent::Resumed => {
+                log::debug!(target: "server", "Write queue of {id} has drained");
+            }
+            SessionEvent::Rejected(key, reason) => {
+                log::warn!(target: "server", "Remote peer {key}@{id} is rejected: {reason}");
+                self.action_queue.push_back(Action::UnregisterTransport(id));
+            }
+            SessionEvent::Deferred(key) => {
+                // Server has no authorization policy which may defer the verdict
+                log::warn!(target: "server", "Verdict on remote peer {key}@{id} is deferred");
+                self.action_queue.push_back(Action::UnregisterTransport(id));
+            }
+            SessionEvent::HandshakeTimeout(elapsed) => {
+                log::warn!(target: "server", "Handshake with {id} has not completed within {elapsed:?}");
+                self.action_queue.push_back(Action::UnregisterTransport(id));
+            }
             SessionEvent::Terminated(err) => {
                 log::error!(target: "server", "Connection with {id} is terminated due to an error: {err}");
                 self.action_queue.push_back(Action::UnregisterTransport(id));
@@ -0,0 +1,177 @@
+//! Compares throughput of the `io_uring`-based scheduler against the
+//! epoll-based one (provided by [`polling`] library on Linux) by running
+//! echo over a large number of simultaneous TCP connections.
+//!
+//! Requires the limit on the number of open files to be above
+//! `2 * CONNECTIONS` (see `ulimit -n`).
+
+#[macro_use]
+extern crate amplify;
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use re_actor::actors::IoEv;
+use re_actor::schedulers::{PollingScheduler, UringScheduler};
+use re_actor::{Actor, Controller, Layout, Pool, Scheduler};
+
+/// Number of simultaneous echo connections.
+const CONNECTIONS: usize = 1000;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display(Debug)]
+struct Fd(RawFd);
+
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl FromRawFd for Fd {
+    unsafe fn from_raw_fd(fd: RawFd) -> Self {
+        Fd(fd)
+    }
+}
+
+impl polling::Source for Fd {
+    fn raw(&self) -> RawFd {
+        self.0
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display(Debug)]
+struct Bench;
+
+impl From<u32> for Bench {
+    fn from(_: u32) -> Self {
+        Bench
+    }
+}
+
+impl From<Bench> for u32 {
+    fn from(_: Bench) -> Self {
+        0
+    }
+}
+
+impl Layout for Bench {
+    type RootActor = Echo;
+
+    fn default_pools() -> Vec<Pool<Echo, Self>> {
+        vec![]
+    }
+
+    fn convert(_: Box<dyn Any>) -> <Echo as Actor>::Context {}
+}
+
+/// Server side of the echo connection. Schedulers are driven directly by the
+/// benchmark, so the actor is used only for the registration.
+struct Echo(Fd);
+
+impl Actor for Echo {
+    type Layout = Bench;
+    type Id = Fd;
+    type Context = ();
+    type Cmd = ();
+    type Error = io::Error;
+
+    fn with(_: Self::Context, _: Controller<Self::Layout>) -> Result<Self, Self::Error> {
+        unreachable!("echo actors are constructed by the benchmark")
+    }
+
+    fn id(&self) -> Self::Id {
+        self.0
+    }
+
+    fn io_ready(&mut self, _: IoEv) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn handle_cmd(&mut self, _: Self::Cmd) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+        Err(err)
+    }
+
+    fn disconnect(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Set of connected client and server sockets.
+struct Connections {
+    clients: Vec<TcpStream>,
+    servers: HashMap<Fd, TcpStream>,
+}
+
+impl Connections {
+    fn new(scheduler: &mut impl Scheduler<Echo>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut clients = Vec::with_capacity(CONNECTIONS);
+        let mut servers = HashMap::with_capacity(CONNECTIONS);
+        for _ in 0..CONNECTIONS {
+            let client = TcpStream::connect(addr).unwrap();
+            client.set_nodelay(true).unwrap();
+            let (server, _) = listener.accept().unwrap();
+            server.set_nonblocking(true).unwrap();
+            server.set_nodelay(true).unwrap();
+            let fd = Fd(server.as_raw_fd());
+            scheduler.register_actor(&Echo(fd)).unwrap();
+            clients.push(client);
+            servers.insert(fd, server);
+        }
+        Connections { clients, servers }
+    }
+
+    /// Sends a byte over each of the connections and waits for all of them to
+    /// be echoed back.
+    fn echo(&mut self, scheduler: &mut impl Scheduler<Echo>) {
+        for client in &mut self.clients {
+            client.write_all(&[1]).unwrap();
+        }
+        let mut buf = [0u8; 16];
+        let mut echoed = 0;
+        while echoed < CONNECTIONS {
+            scheduler.wait_io(None).unwrap();
+            for ev in &mut *scheduler {
+                if !ev.io.is_readable {
+                    continue;
+                }
+                let server = self.servers.get_mut(&ev.source).unwrap();
+                match server.read(&mut buf) {
+                    Ok(len) => {
+                        server.write_all(&buf[..len]).unwrap();
+                        echoed += len;
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                    Err(err) => panic!("{err}"),
+                }
+            }
+        }
+        for client in &mut self.clients {
+            client.read_exact(&mut buf[..1]).unwrap();
+        }
+    }
+}
+
+fn bench_scheduler(c: &mut Criterion, name: &str, mut scheduler: impl Scheduler<Echo>) {
+    let mut connections = Connections::new(&mut scheduler);
+    c.bench_function(name, |b| b.iter(|| connections.echo(&mut scheduler)));
+}
+
+fn echo(c: &mut Criterion) {
+    bench_scheduler(c, "polling (epoll) echo", PollingScheduler::new().unwrap());
+    bench_scheduler(c, "io_uring echo", UringScheduler::new().unwrap());
+}
+
+criterion_group!(benches, echo);
+criterion_main!(benches);
@@ -0,0 +1,136 @@
+//! Compares sending messages consisting of a header and a body over a
+//! [`TcpConnection`] with a separate write for each of the parts against a
+//! single vectored write of both of them.
+//!
+//! Before running the benchmark, the number of the write system calls issued
+//! by each of the approaches is counted (Linux only).
+
+#[macro_use]
+extern crate amplify;
+
+use std::any::Any;
+use std::io::{self, IoSlice};
+use std::net::TcpListener;
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use re_actor::actors::stdtcp::{TcpAction, TcpConnection};
+use re_actor::actors::IoEv;
+use re_actor::{Actor, Controller, Layout, Pool, Reactor};
+
+/// Number of messages sent with each benchmark iteration.
+const MESSAGES: usize = 10_000;
+const HEADER_LEN: usize = 64;
+const BODY_LEN: usize = 1024;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display(Debug)]
+struct Bench;
+
+impl From<u32> for Bench {
+    fn from(_: u32) -> Self {
+        Bench
+    }
+}
+
+impl From<Bench> for u32 {
+    fn from(_: Bench) -> Self {
+        0
+    }
+}
+
+impl Layout for Bench {
+    type RootActor = TcpConnection<Bench>;
+
+    fn default_pools() -> Vec<Pool<Self::RootActor, Self>> {
+        vec![]
+    }
+
+    fn convert(_: Box<dyn Any>) -> TcpAction {
+        unreachable!("connections are constructed by the benchmark")
+    }
+}
+
+/// Connects to a remote peer which discards all received data.
+fn connect(controller: Controller<Bench>) -> TcpConnection<Bench> {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let conn = TcpConnection::connect(listener.local_addr().unwrap(), controller)
+        .unwrap()
+        .with_high_watermark(usize::MAX);
+    let (mut server, _) = listener.accept().unwrap();
+    thread::spawn(move || io::copy(&mut server, &mut io::sink()));
+    conn
+}
+
+/// Sends [`MESSAGES`] and waits for the write queue to be flushed.
+fn send(conn: &mut TcpConnection<Bench>, header: &[u8], body: &[u8], vectored: bool) {
+    for _ in 0..MESSAGES {
+        if vectored {
+            conn.queue_send_vectored(&[IoSlice::new(header), IoSlice::new(body)])
+                .unwrap();
+        } else {
+            conn.queue_send(header).unwrap();
+            conn.queue_send(body).unwrap();
+        }
+    }
+    let writable = IoEv {
+        is_readable: false,
+        is_writable: true,
+        is_hangup: false,
+        is_error: false,
+    };
+    while conn.has_pending_output() {
+        conn.io_ready(writable).unwrap();
+    }
+}
+
+/// Returns number of the write system calls made by the current thread.
+#[cfg(target_os = "linux")]
+fn write_syscalls() -> u64 {
+    let stats = std::fs::read_to_string("/proc/thread-self/io").unwrap();
+    stats
+        .lines()
+        .find_map(|line| line.strip_prefix("syscw: "))
+        .and_then(|count| count.parse().ok())
+        .expect("kernel does not report I/O statistics")
+}
+
+#[cfg(target_os = "linux")]
+fn count_syscalls(conn: &mut TcpConnection<Bench>, header: &[u8], body: &[u8]) {
+    let start = write_syscalls();
+    send(conn, header, body, false);
+    let scalar = write_syscalls() - start;
+
+    let start = write_syscalls();
+    send(conn, header, body, true);
+    let vectored = write_syscalls() - start;
+
+    let reduction = 100 - vectored * 100 / scalar;
+    println!("write syscalls per {MESSAGES} messages: {scalar} scalar, {vectored} vectored");
+    println!("vectored writes issue {reduction}% fewer syscalls");
+    assert!(
+        reduction >= 10,
+        "vectored writes do not reduce number of syscalls"
+    );
+}
+
+fn header_body(c: &mut Criterion) {
+    let mut reactor = Reactor::<Bench>::new().unwrap();
+    let header = [0xFFu8; HEADER_LEN];
+    let body = [0xAAu8; BODY_LEN];
+    let mut scalar = connect(reactor.controller());
+    let mut vectored = connect(reactor.controller());
+
+    #[cfg(target_os = "linux")]
+    count_syscalls(&mut scalar, &header, &body);
+
+    c.bench_function("scalar header and body writes", |b| {
+        b.iter(|| send(&mut scalar, &header, &body, false))
+    });
+    c.bench_function("vectored header and body writes", |b| {
+        b.iter(|| send(&mut vectored, &header, &body, true))
+    });
+}
+
+criterion_group!(benches, header_body);
+criterion_main!(benches);
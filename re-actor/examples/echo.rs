@@ -0,0 +1,158 @@
+//! Echo server and client exchanging frames over in-memory sockets within a
+//! single re-actor pool. Prints metrics of the pool runtime on shutdown.
+
+use std::any::Any;
+use std::fmt::{self, Display, Formatter};
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crossbeam_channel as chan;
+use re_actor::actors::mem::{AsMemSocket, MemQueue, MemSocket};
+use re_actor::actors::IoEv;
+use re_actor::schedulers::MemScheduler;
+use re_actor::{Actor, Controller, Handler, InternalError, Layout, Pool, Reactor, ReactorApi};
+
+/// Number of frames sent by the client.
+const FRAMES: u32 = 1000;
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+enum EchoPool {
+    Main,
+}
+
+impl Display for EchoPool {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str("main")
+    }
+}
+
+impl From<u32> for EchoPool {
+    fn from(_: u32) -> Self {
+        EchoPool::Main
+    }
+}
+
+impl From<EchoPool> for u32 {
+    fn from(_: EchoPool) -> Self {
+        0
+    }
+}
+
+impl Layout for EchoPool {
+    type RootActor = Echo;
+
+    fn default_pools() -> Vec<Pool<Self::RootActor, Self>> {
+        vec![Pool::new(EchoPool::Main, MemScheduler::new(), ErrorHandler)]
+    }
+
+    fn convert(_: Box<dyn Any>) -> MemSocket<EchoPool> {
+        unreachable!()
+    }
+}
+
+struct ErrorHandler;
+
+impl Handler<EchoPool> for ErrorHandler {
+    fn handle_err(&mut self, err: InternalError<EchoPool>) {
+        eprintln!("re-actor error: {err}")
+    }
+}
+
+/// Socket which either echoes the received frames back (server) or forwards
+/// them to the main thread (client).
+struct Echo {
+    socket: MemSocket<EchoPool>,
+    received: Option<chan::Sender<Vec<u8>>>,
+}
+
+impl AsMemSocket for Echo {
+    fn inbox(&self) -> &Arc<MemQueue> {
+        self.socket.inbox()
+    }
+}
+
+impl Actor for Echo {
+    type Layout = EchoPool;
+    type Id = u64;
+    type Context = MemSocket<EchoPool>;
+    type Cmd = Vec<u8>;
+    type Error = io::Error;
+
+    fn with(socket: Self::Context, _: Controller<EchoPool>) -> Result<Self, Self::Error> {
+        Ok(Echo {
+            socket,
+            received: None,
+        })
+    }
+
+    fn id(&self) -> Self::Id {
+        self.socket.id()
+    }
+
+    fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+        self.socket.io_ready(io)?;
+        while let Some(frame) = self.socket.recv_frame() {
+            match &self.received {
+                None => self.socket.send_frame(frame),
+                Some(sender) => sender.send(frame).map_err(|_| io::ErrorKind::BrokenPipe)?,
+            }
+        }
+        Ok(())
+    }
+
+    fn handle_cmd(&mut self, frame: Self::Cmd) -> Result<(), Self::Error> {
+        self.socket.handle_cmd(frame)
+    }
+
+    fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+        Err(err)
+    }
+
+    fn interests(&self) -> IoEv {
+        self.socket.interests()
+    }
+
+    fn disconnect(&mut self) -> Result<(), Self::Error> {
+        self.socket.disconnect()
+    }
+}
+
+fn main() -> Result<(), InternalError<EchoPool>> {
+    let mut reactor = Reactor::<EchoPool>::new()?;
+    let mut controller = reactor.controller();
+
+    let (client_socket, server_socket) = MemSocket::pair();
+    let client = client_socket.id();
+    let (sender, receiver) = chan::unbounded();
+    controller.start_actor(EchoPool::Main, server_socket)?;
+    controller.insert_actor(
+        EchoPool::Main,
+        Echo {
+            socket: client_socket,
+            received: Some(sender),
+        },
+    )?;
+    // Actors are added asynchronously by the pool thread
+    while !controller.contains_actor(&client)? {
+        thread::sleep(Duration::from_millis(1));
+    }
+
+    for no in 0..FRAMES {
+        controller.send(client, no.to_be_bytes().to_vec())?;
+    }
+    let mut echoed = 0;
+    while echoed < FRAMES {
+        match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(frame) => assert_eq!(frame, echoed.to_be_bytes()),
+            Err(_) => break,
+        }
+        echoed += 1;
+    }
+    println!("{echoed} of {FRAMES} frames echoed");
+
+    let metrics = controller.metrics(EchoPool::Main)?;
+    println!("{metrics:#?}");
+    reactor.shutdown()
+}
@@ -0,0 +1,488 @@
+//! Circuit breaker wrapper stopping actors which fail on each I/O event, for
+//! instance because the downstream service is overloaded, from flooding the
+//! logs and burning CPU.
+
+use std::io;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::actors::mem::{AsMemSocket, MemQueue};
+use crate::actors::{DisconnectReason, IoEv};
+use crate::{Actor, Controller};
+
+/// Error returned by [`CircuitBreaker`] from [`Actor::io_ready`] while its
+/// circuit is open.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display, Error)]
+#[display("circuit breaker is open after repeated failures")]
+pub struct CircuitOpenError;
+
+impl From<CircuitOpenError> for io::Error {
+    fn from(err: CircuitOpenError) -> Self {
+        io::Error::new(io::ErrorKind::WouldBlock, err)
+    }
+}
+
+/// Thresholds of a [`CircuitBreaker`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct CircuitConfig {
+    /// Number of consecutive failures opening the circuit.
+    pub threshold: u32,
+    /// Time within which the consecutive failures must happen to open the
+    /// circuit; the failures are counted anew once it elapses since the
+    /// first of them.
+    pub window: Duration,
+    /// Time after which an open circuit is half-opened, letting a single
+    /// probe through.
+    pub reset_timeout: Duration,
+}
+
+impl CircuitConfig {
+    pub fn new(threshold: u32, window: Duration, reset_timeout: Duration) -> Self {
+        CircuitConfig {
+            threshold,
+            window,
+            reset_timeout,
+        }
+    }
+}
+
+/// State of a [`CircuitBreaker`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum CircuitState {
+    /// I/O events are passed to the wrapped actor.
+    Closed,
+    /// I/O events are rejected since the given time, after the wrapped actor
+    /// has failed too many times in a row.
+    Open(Instant),
+    /// Next I/O event is passed to the wrapped actor as a probe, closing the
+    /// circuit on success and reopening it on failure.
+    HalfOpen,
+}
+
+/// Actor counting consecutive failures of the wrapped actor
+/// [`Actor::io_ready`] and opening the circuit once there are
+/// [`CircuitConfig::threshold`] of them within [`CircuitConfig::window`].
+///
+/// While the circuit is open the breaker drops its interest in I/O events and
+/// rejects the events which are still reported with [`CircuitOpenError`],
+/// without calling the wrapped actor; the rejections are not passed to
+/// [`Actor::handle_err`] of the wrapped actor. After
+/// [`CircuitConfig::reset_timeout`] the circuit is half-opened from
+/// [`Actor::on_deadline`]. The re-actor runtime reports the circuit state
+/// changes to [`Handler::on_circuit_open`] and [`Handler::on_circuit_close`].
+///
+/// Commands and other events are delegated to the wrapped actor as they are.
+///
+/// [`Handler::on_circuit_open`]: crate::Handler::on_circuit_open
+/// [`Handler::on_circuit_close`]: crate::Handler::on_circuit_close
+pub struct CircuitBreaker<A: Actor> {
+    inner: A,
+    config: CircuitConfig,
+    state: CircuitState,
+    failures: u32,
+    /// Time of the first of the consecutive failures.
+    first_failure: Option<Instant>,
+    /// Whether the last I/O event was rejected by the open circuit.
+    rejected: bool,
+}
+
+impl<A: Actor> CircuitBreaker<A> {
+    /// Wraps actor with a closed circuit.
+    pub fn new(inner: A, config: CircuitConfig) -> Self {
+        CircuitBreaker {
+            inner,
+            config,
+            state: CircuitState::Closed,
+            failures: 0,
+            first_failure: None,
+            rejected: false,
+        }
+    }
+
+    /// Returns the wrapped actor.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns the wrapped actor.
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    /// Unwraps the actor.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    pub fn config(&self) -> CircuitConfig {
+        self.config
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.state
+    }
+
+    /// Returns number of the consecutive failures counted within the current
+    /// window.
+    pub fn failures(&self) -> u32 {
+        self.failures
+    }
+
+    fn reset_deadline(&self) -> Option<Instant> {
+        match self.state {
+            CircuitState::Open(since) => Some(since + self.config.reset_timeout),
+            CircuitState::Closed | CircuitState::HalfOpen => None,
+        }
+    }
+
+    fn open(&mut self, now: Instant) {
+        self.state = CircuitState::Open(now);
+        self.failures = 0;
+        self.first_failure = None;
+    }
+
+    fn record_failure(&mut self, now: Instant) {
+        match self.first_failure {
+            Some(first) if now.duration_since(first) <= self.config.window => self.failures += 1,
+            _ => {
+                self.first_failure = Some(now);
+                self.failures = 1;
+            }
+        }
+        if self.failures >= self.config.threshold {
+            self.open(now);
+        }
+    }
+}
+
+impl<A: Actor> Actor for CircuitBreaker<A>
+where
+    A::Error: From<CircuitOpenError>,
+{
+    type Layout = A::Layout;
+    type Id = A::Id;
+    type Context = (A::Context, CircuitConfig);
+    type Cmd = A::Cmd;
+    type Error = A::Error;
+
+    const PRIORITY: u8 = A::PRIORITY;
+
+    fn with(
+        (context, config): Self::Context,
+        controller: Controller<Self::Layout>,
+    ) -> Result<Self, Self::Error> {
+        A::with(context, controller).map(|inner| CircuitBreaker::new(inner, config))
+    }
+
+    fn id(&self) -> Self::Id {
+        self.inner.id()
+    }
+
+    fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+        if let CircuitState::Open(_) = self.state {
+            self.rejected = true;
+            return Err(CircuitOpenError.into());
+        }
+        let res = self.inner.io_ready(io);
+        match (&res, self.state) {
+            (Ok(_), CircuitState::HalfOpen) => self.state = CircuitState::Closed,
+            (Ok(_), _) => {
+                self.failures = 0;
+                self.first_failure = None;
+            }
+            (Err(_), CircuitState::HalfOpen) => self.open(Instant::now()),
+            (Err(_), _) => self.record_failure(Instant::now()),
+        }
+        res
+    }
+
+    fn handle_cmd(&mut self, cmd: Self::Cmd) -> Result<(), Self::Error> {
+        self.inner.handle_cmd(cmd)
+    }
+
+    fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+        if self.rejected {
+            self.rejected = false;
+            return Ok(());
+        }
+        self.inner.handle_err(err)
+    }
+
+    fn has_pending_output(&self) -> bool {
+        self.inner.has_pending_output()
+    }
+
+    fn on_idle(&mut self) {
+        self.inner.on_idle()
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        [self.reset_deadline(), self.inner.deadline()]
+            .into_iter()
+            .flatten()
+            .min()
+    }
+
+    fn on_deadline(&mut self) -> Result<(), Self::Error> {
+        let now = Instant::now();
+        if matches!(self.reset_deadline(), Some(deadline) if deadline <= now) {
+            self.state = CircuitState::HalfOpen;
+        }
+        if matches!(self.inner.deadline(), Some(deadline) if deadline <= now) {
+            self.inner.on_deadline()?;
+        }
+        Ok(())
+    }
+
+    fn deadline_reason(&self) -> DisconnectReason {
+        self.inner.deadline_reason()
+    }
+
+    fn is_circuit_open(&self) -> bool {
+        self.state != CircuitState::Closed
+    }
+
+    fn priority(&self) -> u8 {
+        self.inner.priority()
+    }
+
+    fn interests(&self) -> IoEv {
+        match self.state {
+            CircuitState::Open(_) => IoEv {
+                is_readable: false,
+                is_writable: false,
+                is_hangup: false,
+                is_error: false,
+            },
+            CircuitState::Closed | CircuitState::HalfOpen => self.inner.interests(),
+        }
+    }
+
+    fn shutdown_write(&mut self) -> Result<(), Self::Error> {
+        self.inner.shutdown_write()
+    }
+
+    fn disconnect(&mut self) -> Result<(), Self::Error> {
+        self.inner.disconnect()
+    }
+}
+
+impl<A: Actor + AsMemSocket> AsMemSocket for CircuitBreaker<A> {
+    fn inbox(&self) -> &Arc<MemQueue> {
+        self.inner.inbox()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::cell::RefCell;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use crossbeam_channel as chan;
+
+    use super::*;
+    use crate::actors::mem::MemSocket;
+    use crate::schedulers::MemScheduler;
+    use crate::{Handler, InternalError, Layout, Pool, Reactor, ReactorApi};
+
+    const THRESHOLD: u32 = 10;
+    const RESET_TIMEOUT: Duration = Duration::from_millis(100);
+
+    thread_local! {
+        /// Channel for reporting circuit state changes, set up by [`start`]
+        /// for the test thread.
+        static CIRCUITS: RefCell<Option<chan::Sender<(u64, bool)>>> = RefCell::new(None);
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+    #[display(Debug)]
+    enum CircuitPool {
+        Main,
+    }
+
+    impl From<u32> for CircuitPool {
+        fn from(_: u32) -> Self {
+            CircuitPool::Main
+        }
+    }
+
+    impl From<CircuitPool> for u32 {
+        fn from(_: CircuitPool) -> Self {
+            0
+        }
+    }
+
+    impl Layout for CircuitPool {
+        type RootActor = CircuitBreaker<Downstream>;
+
+        fn default_pools() -> Vec<Pool<Self::RootActor, Self>> {
+            let circuits = CIRCUITS.with(|circuits| circuits.borrow().clone());
+            let handler = CircuitHandler(circuits.expect("channel is not set up"));
+            vec![Pool::new(CircuitPool::Main, MemScheduler::new(), handler)]
+        }
+
+        fn convert(_: Box<dyn Any>) -> (Downstream, CircuitConfig) {
+            unreachable!()
+        }
+    }
+
+    struct CircuitHandler(chan::Sender<(u64, bool)>);
+
+    impl Handler<CircuitPool> for CircuitHandler {
+        fn handle_err(&mut self, _: InternalError<CircuitPool>) {}
+
+        fn on_circuit_open(&mut self, id: &u64) {
+            let _ = self.0.send((*id, true));
+        }
+
+        fn on_circuit_close(&mut self, id: &u64) {
+            let _ = self.0.send((*id, false));
+        }
+    }
+
+    /// Actor failing to process the received frames while it is overloaded,
+    /// leaving them unread.
+    struct Downstream {
+        socket: MemSocket<CircuitPool>,
+        overloaded: Arc<AtomicBool>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl AsMemSocket for Downstream {
+        fn inbox(&self) -> &Arc<MemQueue> {
+            self.socket.inbox()
+        }
+    }
+
+    impl Actor for Downstream {
+        type Layout = CircuitPool;
+        type Id = u64;
+        type Context = Self;
+        type Cmd = Vec<u8>;
+        type Error = io::Error;
+
+        fn with(actor: Self, _: Controller<CircuitPool>) -> Result<Self, Self::Error> {
+            Ok(actor)
+        }
+
+        fn id(&self) -> Self::Id {
+            self.socket.id()
+        }
+
+        fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if self.overloaded.load(Ordering::SeqCst) {
+                return Err(io::Error::new(io::ErrorKind::Other, "overloaded"));
+            }
+            self.socket.io_ready(io)?;
+            while self.socket.recv_frame().is_some() {}
+            Ok(())
+        }
+
+        fn handle_cmd(&mut self, frame: Self::Cmd) -> Result<(), Self::Error> {
+            self.socket.handle_cmd(frame)
+        }
+
+        fn handle_err(&mut self, _: Self::Error) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        fn interests(&self) -> IoEv {
+            self.socket.interests()
+        }
+
+        fn disconnect(&mut self) -> Result<(), Self::Error> {
+            self.socket.disconnect()
+        }
+    }
+
+    #[test]
+    fn opens_and_closes_after_probe() {
+        let (send, circuits) = chan::unbounded();
+        CIRCUITS.with(|circuits| *circuits.borrow_mut() = Some(send));
+        let overloaded = Arc::new(AtomicBool::new(true));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (socket, mut remote) = MemSocket::pair();
+        let id = socket.id();
+        let downstream = Downstream {
+            socket,
+            overloaded: overloaded.clone(),
+            calls: calls.clone(),
+        };
+        let config = CircuitConfig::new(THRESHOLD, Duration::from_secs(10), RESET_TIMEOUT);
+        let mut reactor = Reactor::<CircuitPool>::new().unwrap();
+        reactor
+            .insert_actor(CircuitPool::Main, CircuitBreaker::new(downstream, config))
+            .unwrap();
+
+        // Unread frame makes the actor readable until it is processed, so
+        // the actor fails on each event loop iteration
+        let start = Instant::now();
+        remote.send_frame(b"request".to_vec());
+        assert_eq!(
+            circuits.recv_timeout(Duration::from_secs(1)).unwrap(),
+            (id, true)
+        );
+        let opened = start.elapsed();
+        assert_eq!(calls.load(Ordering::SeqCst), THRESHOLD as usize);
+        // Open circuit does not call the actor
+        assert_eq!(
+            circuits.recv_timeout(RESET_TIMEOUT / 2),
+            Err(chan::RecvTimeoutError::Timeout)
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), THRESHOLD as usize);
+
+        overloaded.store(false, Ordering::SeqCst);
+        assert_eq!(
+            circuits.recv_timeout(Duration::from_secs(1)).unwrap(),
+            (id, false)
+        );
+        assert!(start.elapsed() >= opened + RESET_TIMEOUT);
+        // The frame is processed by the probe
+        assert_eq!(calls.load(Ordering::SeqCst), THRESHOLD as usize + 1);
+
+        reactor.shutdown().unwrap();
+    }
+
+    #[test]
+    fn failed_probe_reopens() {
+        let (send, circuits) = chan::unbounded();
+        CIRCUITS.with(|circuits| *circuits.borrow_mut() = Some(send));
+        let overloaded = Arc::new(AtomicBool::new(true));
+        let calls = Arc::new(AtomicUsize::new(0));
+        let (socket, mut remote) = MemSocket::pair();
+        let id = socket.id();
+        let downstream = Downstream {
+            socket,
+            overloaded: overloaded.clone(),
+            calls: calls.clone(),
+        };
+        let config = CircuitConfig::new(THRESHOLD, Duration::from_secs(10), RESET_TIMEOUT);
+        let mut reactor = Reactor::<CircuitPool>::new().unwrap();
+        reactor
+            .insert_actor(CircuitPool::Main, CircuitBreaker::new(downstream, config))
+            .unwrap();
+
+        remote.send_frame(b"request".to_vec());
+        assert_eq!(
+            circuits.recv_timeout(Duration::from_secs(1)).unwrap(),
+            (id, true)
+        );
+        // Each probe fails and reopens the circuit without closing it
+        assert_eq!(
+            circuits.recv_timeout(RESET_TIMEOUT * 3 + RESET_TIMEOUT / 2),
+            Err(chan::RecvTimeoutError::Timeout)
+        );
+        let probes = calls.load(Ordering::SeqCst) - THRESHOLD as usize;
+        assert!((1..=3).contains(&probes), "{probes} probes");
+
+        overloaded.store(false, Ordering::SeqCst);
+        assert_eq!(
+            circuits.recv_timeout(Duration::from_secs(1)).unwrap(),
+            (id, false)
+        );
+
+        reactor.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,407 @@
+//! Keepalive wrapper detecting connections which went silent without being
+//! closed, for instance because the remote host has crashed or a NAT has
+//! dropped the connection mapping.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::actors::mem::{AsMemSocket, MemQueue};
+use crate::actors::{DisconnectReason, IoEv};
+use crate::{Actor, Controller};
+
+/// Actor speaking a protocol with ping and pong messages, which can be
+/// wrapped into [`HeartbeatActor`].
+pub trait Heartbeat: Actor {
+    /// Sends ping message to the remote peer. The message may be buffered:
+    /// the re-actor runtime updates the actor interests right after the call.
+    fn send_ping(&mut self) -> Result<(), Self::Error>;
+
+    /// Reports whether a pong message was received since the previous call.
+    /// Checked after each I/O event processed by the actor.
+    fn take_pong(&mut self) -> bool;
+}
+
+/// Timing of the heartbeat messages sent by a [`HeartbeatActor`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct HeartbeatConfig {
+    /// Interval between the pings sent to the remote peer.
+    pub interval: Duration,
+    /// Time given to the remote peer to answer a ping before the connection
+    /// is considered dead.
+    pub pong_timeout: Duration,
+}
+
+impl HeartbeatConfig {
+    pub fn new(interval: Duration, pong_timeout: Duration) -> Self {
+        HeartbeatConfig {
+            interval,
+            pong_timeout,
+        }
+    }
+}
+
+/// Actor pinging the remote peer every [`HeartbeatConfig::interval`] and
+/// disconnecting with [`DisconnectReason::HeartbeatTimeout`] once a ping is not
+/// answered within [`HeartbeatConfig::pong_timeout`].
+///
+/// Pings are sent from [`Actor::on_deadline`], so the wrapped actor deadline
+/// is merged with the heartbeat one. All other events are delegated to the
+/// wrapped actor.
+pub struct HeartbeatActor<A: Heartbeat> {
+    inner: A,
+    config: HeartbeatConfig,
+    next_ping: Instant,
+    /// Time by which the earliest unanswered ping must be answered.
+    pong_deadline: Option<Instant>,
+}
+
+impl<A: Heartbeat> HeartbeatActor<A> {
+    /// Wraps actor, scheduling its first ping in [`HeartbeatConfig::interval`].
+    pub fn new(inner: A, config: HeartbeatConfig) -> Self {
+        HeartbeatActor {
+            inner,
+            config,
+            next_ping: Instant::now() + config.interval,
+            pong_deadline: None,
+        }
+    }
+
+    /// Returns the wrapped actor.
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Returns the wrapped actor.
+    pub fn inner_mut(&mut self) -> &mut A {
+        &mut self.inner
+    }
+
+    /// Unwraps the actor, stopping the heartbeat.
+    pub fn into_inner(self) -> A {
+        self.inner
+    }
+
+    /// Detects whether a ping was sent and is not answered yet.
+    pub fn is_awaiting_pong(&self) -> bool {
+        self.pong_deadline.is_some()
+    }
+
+    fn is_timed_out(&self, now: Instant) -> bool {
+        matches!(self.pong_deadline, Some(deadline) if deadline <= now)
+    }
+}
+
+impl<A: Heartbeat> Actor for HeartbeatActor<A>
+where
+    A::Error: From<DisconnectReason>,
+{
+    type Layout = A::Layout;
+    type Id = A::Id;
+    type Context = (A::Context, HeartbeatConfig);
+    type Cmd = A::Cmd;
+    type Error = A::Error;
+
+    const PRIORITY: u8 = A::PRIORITY;
+
+    fn with(
+        (context, config): Self::Context,
+        controller: Controller<Self::Layout>,
+    ) -> Result<Self, Self::Error> {
+        A::with(context, controller).map(|inner| HeartbeatActor::new(inner, config))
+    }
+
+    fn id(&self) -> Self::Id {
+        self.inner.id()
+    }
+
+    fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+        let res = self.inner.io_ready(io);
+        if self.inner.take_pong() {
+            self.pong_deadline = None;
+        }
+        res
+    }
+
+    fn handle_cmd(&mut self, cmd: Self::Cmd) -> Result<(), Self::Error> {
+        self.inner.handle_cmd(cmd)
+    }
+
+    fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+        self.inner.handle_err(err)
+    }
+
+    fn has_pending_output(&self) -> bool {
+        self.inner.has_pending_output()
+    }
+
+    fn on_idle(&mut self) {
+        self.inner.on_idle()
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        [
+            Some(self.next_ping),
+            self.pong_deadline,
+            self.inner.deadline(),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    fn on_deadline(&mut self) -> Result<(), Self::Error> {
+        let now = Instant::now();
+        if self.is_timed_out(now) {
+            return Err(DisconnectReason::HeartbeatTimeout.into());
+        }
+        if self.next_ping <= now {
+            self.inner.send_ping()?;
+            // Later pings do not extend the time given for the earlier ones
+            self.pong_deadline
+                .get_or_insert(now + self.config.pong_timeout);
+            self.next_ping = now + self.config.interval;
+        }
+        if matches!(self.inner.deadline(), Some(deadline) if deadline <= now) {
+            self.inner.on_deadline()?;
+        }
+        Ok(())
+    }
+
+    fn deadline_reason(&self) -> DisconnectReason {
+        if self.is_timed_out(Instant::now()) {
+            DisconnectReason::HeartbeatTimeout
+        } else {
+            self.inner.deadline_reason()
+        }
+    }
+
+    fn priority(&self) -> u8 {
+        self.inner.priority()
+    }
+
+    fn interests(&self) -> IoEv {
+        self.inner.interests()
+    }
+
+    fn shutdown_write(&mut self) -> Result<(), Self::Error> {
+        self.inner.shutdown_write()
+    }
+
+    fn disconnect(&mut self) -> Result<(), Self::Error> {
+        self.inner.disconnect()
+    }
+}
+
+impl<A: Heartbeat + AsMemSocket> AsMemSocket for HeartbeatActor<A> {
+    fn inbox(&self) -> &Arc<MemQueue> {
+        self.inner.inbox()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::cell::RefCell;
+    use std::io;
+
+    use crossbeam_channel as chan;
+
+    use super::*;
+    use crate::actors::mem::MemSocket;
+    use crate::schedulers::MemScheduler;
+    use crate::{Handler, InternalError, Layout, Pool, Reactor, ReactorApi};
+
+    const PING: &[u8] = b"ping";
+    const PONG: &[u8] = b"pong";
+
+    thread_local! {
+        /// Channel for reporting disconnections, set up by [`connect`] for the
+        /// test thread.
+        static DISCONNECTS: RefCell<Option<chan::Sender<(u64, String)>>> = RefCell::new(None);
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+    #[display(Debug)]
+    enum HeartbeatPool {
+        Main,
+    }
+
+    impl From<u32> for HeartbeatPool {
+        fn from(_: u32) -> Self {
+            HeartbeatPool::Main
+        }
+    }
+
+    impl From<HeartbeatPool> for u32 {
+        fn from(_: HeartbeatPool) -> Self {
+            0
+        }
+    }
+
+    impl Layout for HeartbeatPool {
+        type RootActor = HeartbeatActor<Peer>;
+
+        fn default_pools() -> Vec<Pool<Self::RootActor, Self>> {
+            let disconnects = DISCONNECTS.with(|disconnects| disconnects.borrow().clone());
+            let handler = DisconnectHandler(disconnects.expect("channel is not set up"));
+            vec![Pool::new(HeartbeatPool::Main, MemScheduler::new(), handler)]
+        }
+
+        fn convert(_: Box<dyn Any>) -> (Peer, HeartbeatConfig) {
+            unreachable!()
+        }
+    }
+
+    struct DisconnectHandler(chan::Sender<(u64, String)>);
+
+    impl Handler<HeartbeatPool> for DisconnectHandler {
+        fn handle_err(&mut self, _: InternalError<HeartbeatPool>) {}
+
+        fn on_disconnect(&mut self, id: &u64, reason: &DisconnectReason) {
+            let _ = self.0.send((*id, reason.to_string()));
+        }
+    }
+
+    /// Peer answering pings unless it is muted.
+    struct Peer {
+        socket: MemSocket<HeartbeatPool>,
+        muted: bool,
+        pongs: usize,
+    }
+
+    impl Peer {
+        fn new(socket: MemSocket<HeartbeatPool>, muted: bool) -> Self {
+            Peer {
+                socket,
+                muted,
+                pongs: 0,
+            }
+        }
+    }
+
+    impl AsMemSocket for Peer {
+        fn inbox(&self) -> &Arc<MemQueue> {
+            self.socket.inbox()
+        }
+    }
+
+    impl Heartbeat for Peer {
+        fn send_ping(&mut self) -> Result<(), Self::Error> {
+            self.socket.send_frame(PING.to_vec());
+            Ok(())
+        }
+
+        fn take_pong(&mut self) -> bool {
+            let received = self.pongs > 0;
+            self.pongs = 0;
+            received
+        }
+    }
+
+    impl Actor for Peer {
+        type Layout = HeartbeatPool;
+        type Id = u64;
+        type Context = Self;
+        type Cmd = Vec<u8>;
+        type Error = io::Error;
+
+        fn with(peer: Self, _: Controller<HeartbeatPool>) -> Result<Self, Self::Error> {
+            Ok(peer)
+        }
+
+        fn id(&self) -> Self::Id {
+            self.socket.id()
+        }
+
+        fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+            self.socket.io_ready(io)?;
+            while let Some(frame) = self.socket.recv_frame() {
+                match frame.as_slice() {
+                    PING if !self.muted => self.socket.send_frame(PONG.to_vec()),
+                    PONG => self.pongs += 1,
+                    _ => {}
+                }
+            }
+            Ok(())
+        }
+
+        fn handle_cmd(&mut self, frame: Self::Cmd) -> Result<(), Self::Error> {
+            self.socket.handle_cmd(frame)
+        }
+
+        fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+            Err(err)
+        }
+
+        fn interests(&self) -> IoEv {
+            self.socket.interests()
+        }
+
+        fn disconnect(&mut self) -> Result<(), Self::Error> {
+            self.socket.disconnect()
+        }
+    }
+
+    /// Starts pinging client and the server answering its pings unless it is
+    /// muted, returning the re-actors, the client id and the receiver of the
+    /// disconnections.
+    fn connect(
+        muted: bool,
+    ) -> (
+        Reactor<HeartbeatPool>,
+        Reactor<HeartbeatPool>,
+        u64,
+        chan::Receiver<(u64, String)>,
+    ) {
+        let (send, recv) = chan::unbounded();
+        DISCONNECTS.with(|disconnects| *disconnects.borrow_mut() = Some(send));
+        let mut client_reactor = Reactor::<HeartbeatPool>::new().unwrap();
+        let mut server_reactor = Reactor::<HeartbeatPool>::new().unwrap();
+        let (client_socket, server_socket) = MemSocket::pair();
+        let id = client_socket.id();
+        // Server does not ping on its own within the test
+        let server = HeartbeatConfig::new(Duration::from_secs(60), Duration::from_secs(60));
+        server_reactor
+            .insert_actor(
+                HeartbeatPool::Main,
+                HeartbeatActor::new(Peer::new(server_socket, muted), server),
+            )
+            .unwrap();
+        let client = HeartbeatConfig::new(Duration::from_millis(100), Duration::from_millis(50));
+        client_reactor
+            .insert_actor(
+                HeartbeatPool::Main,
+                HeartbeatActor::new(Peer::new(client_socket, false), client),
+            )
+            .unwrap();
+        (client_reactor, server_reactor, id, recv)
+    }
+
+    #[test]
+    fn answered_pings_keep_connection() {
+        let (mut client_reactor, server_reactor, id, disconnects) = connect(false);
+        assert_eq!(
+            disconnects.recv_timeout(Duration::from_millis(450)),
+            Err(chan::RecvTimeoutError::Timeout)
+        );
+        assert!(client_reactor.controller().contains_actor(&id).unwrap());
+
+        client_reactor.shutdown().unwrap();
+        server_reactor.shutdown().unwrap();
+    }
+
+    #[test]
+    fn missed_pong_disconnects() {
+        let start = Instant::now();
+        let (mut client_reactor, server_reactor, id, disconnects) = connect(true);
+        let (disconnected, reason) = disconnects.recv_timeout(Duration::from_secs(1)).unwrap();
+        // First ping is sent in 100ms and is given 50ms to be answered
+        assert!(start.elapsed() < Duration::from_millis(200));
+        assert_eq!(disconnected, id);
+        assert_eq!(reason, DisconnectReason::HeartbeatTimeout.to_string());
+        assert!(!client_reactor.controller().contains_actor(&id).unwrap());
+
+        client_reactor.shutdown().unwrap();
+        server_reactor.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,305 @@
+//! In-memory sockets, which allow to test re-actor business logic without
+//! network access. The sockets must be run by a
+//! [`MemScheduler`](crate::schedulers::MemScheduler).
+
+#[cfg(feature = "snapshot")]
+use std::collections::BTreeMap;
+use std::collections::VecDeque;
+use std::io;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+#[cfg(feature = "snapshot")]
+use std::sync::Weak;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+#[cfg(feature = "snapshot")]
+use serde::{Deserialize, Serialize};
+
+use crate::actors::IoEv;
+#[cfg(feature = "snapshot")]
+use crate::actors::{ActorSnapshot, Migrate};
+use crate::schedulers::Waker;
+use crate::{Actor, Controller, Layout};
+
+/// Sequence used to generate unique ids of the sockets.
+static SOCKET_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Queues of the sockets by their ids, allowing to restore sockets from their
+/// snapshots. The queues are kept alive by the sockets exchanging frames
+/// through them, so a snapshot can't be restored once both sockets of the
+/// pair are dropped.
+#[cfg(feature = "snapshot")]
+static SOCKET_QUEUES: Mutex<BTreeMap<u64, (Weak<MemQueue>, Weak<MemQueue>)>> =
+    Mutex::new(BTreeMap::new());
+
+/// Notification used to wake up a [`MemScheduler`] when new data are pushed
+/// into one of its sockets.
+///
+/// [`MemScheduler`]: crate::schedulers::MemScheduler
+#[derive(Default)]
+pub struct MemSignal {
+    notified: Mutex<bool>,
+    cond: Condvar,
+}
+
+impl MemSignal {
+    /// Blocks until notified or until the timeout expires, returning whether
+    /// the notification was received.
+    pub(crate) fn wait(&self, timeout: Option<Duration>) -> bool {
+        let notified = self.notified.lock().expect("signal lock is poisoned");
+        let mut notified = match timeout {
+            Some(timeout) => {
+                self.cond
+                    .wait_timeout_while(notified, timeout, |notified| !*notified)
+                    .expect("signal lock is poisoned")
+                    .0
+            }
+            None => self
+                .cond
+                .wait_while(notified, |notified| !*notified)
+                .expect("signal lock is poisoned"),
+        };
+        let received = *notified;
+        *notified = false;
+        received
+    }
+
+    fn notify(&self) {
+        *self.notified.lock().expect("signal lock is poisoned") = true;
+        self.cond.notify_all();
+    }
+}
+
+impl Waker for MemSignal {
+    fn wake(&self) -> io::Result<()> {
+        self.notify();
+        Ok(())
+    }
+}
+
+/// Queue of frames sent in one direction between a pair of [`MemSocket`]s.
+#[derive(Default)]
+pub struct MemQueue {
+    frames: Mutex<VecDeque<Vec<u8>>>,
+    signal: Mutex<Option<Arc<MemSignal>>>,
+}
+
+impl MemQueue {
+    /// Detects whether there are frames which are not yet received.
+    pub fn has_frames(&self) -> bool {
+        !self
+            .frames
+            .lock()
+            .expect("queue lock is poisoned")
+            .is_empty()
+    }
+
+    /// Sets signal which gets notified each time a frame is pushed to the
+    /// queue, replacing the previous one.
+    pub(crate) fn set_signal(&self, signal: Option<Arc<MemSignal>>) {
+        let has_frames = self.has_frames();
+        let mut current = self.signal.lock().expect("queue lock is poisoned");
+        *current = signal;
+        // Frames pushed before the registration must not be missed
+        if let Some(signal) = current.as_ref().filter(|_| has_frames) {
+            signal.notify();
+        }
+    }
+
+    fn push(&self, frame: Vec<u8>) {
+        self.frames
+            .lock()
+            .expect("queue lock is poisoned")
+            .push_back(frame);
+        if let Some(signal) = &*self.signal.lock().expect("queue lock is poisoned") {
+            signal.notify();
+        }
+    }
+
+    fn drain_into(&self, frames: &mut VecDeque<Vec<u8>>) {
+        frames.extend(
+            self.frames
+                .lock()
+                .expect("queue lock is poisoned")
+                .drain(..),
+        );
+    }
+}
+
+/// Actors which are run by a [`MemScheduler`], providing access to their
+/// in-memory socket.
+///
+/// [`MemScheduler`]: crate::schedulers::MemScheduler
+pub trait AsMemSocket {
+    /// Returns queue of the frames received by the actor socket.
+    fn inbox(&self) -> &Arc<MemQueue>;
+}
+
+/// In-memory socket exchanging frames with its peer; constructed in pairs by
+/// [`MemSocket::pair`].
+///
+/// Frames sent with [`Actor::handle_cmd`] are delivered to the peer socket
+/// at once, such that the socket is interested only in read events.
+pub struct MemSocket<L: Layout> {
+    id: u64,
+    inbox: Arc<MemQueue>,
+    outbox: Arc<MemQueue>,
+    received: VecDeque<Vec<u8>>,
+    _phantom: PhantomData<L>,
+}
+
+impl<L: Layout> MemSocket<L> {
+    /// Constructs pair of sockets connected to each other.
+    pub fn pair() -> (Self, Self) {
+        let a = Arc::new(MemQueue::default());
+        let b = Arc::new(MemQueue::default());
+        (
+            Self::with_queues(a.clone(), b.clone()),
+            Self::with_queues(b, a),
+        )
+    }
+
+    fn with_queues(inbox: Arc<MemQueue>, outbox: Arc<MemQueue>) -> Self {
+        let id = SOCKET_SEQ.fetch_add(1, Ordering::Relaxed);
+        #[cfg(feature = "snapshot")]
+        {
+            let mut queues = SOCKET_QUEUES
+                .lock()
+                .expect("socket queues lock is poisoned");
+            queues.retain(|_, (inbox, _)| inbox.strong_count() > 0);
+            queues.insert(id, (Arc::downgrade(&inbox), Arc::downgrade(&outbox)));
+        }
+        MemSocket {
+            id,
+            inbox,
+            outbox,
+            received: empty!(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns new socket with the same id, exchanging frames with the same
+    /// peer, like [`std::net::TcpStream::try_clone`] does. Frames already
+    /// received by this socket are not shared.
+    pub fn duplicate(&self) -> Self {
+        MemSocket {
+            id: self.id,
+            inbox: self.inbox.clone(),
+            outbox: self.outbox.clone(),
+            received: empty!(),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the oldest of the received frames, if any.
+    pub fn recv_frame(&mut self) -> Option<Vec<u8>> {
+        self.received.pop_front()
+    }
+
+    /// Sends frame to the peer socket.
+    pub fn send_frame(&mut self, frame: Vec<u8>) {
+        self.outbox.push(frame);
+    }
+}
+
+/// State of [`MemSocket`] encoded into its snapshot.
+#[cfg(feature = "snapshot")]
+#[derive(Serialize, Deserialize)]
+struct MemSocketState {
+    id: u64,
+    received: VecDeque<Vec<u8>>,
+}
+
+/// Snapshots refer to the socket queues by the socket id, so they can be
+/// restored only within the same process, while the peer socket exists.
+/// Frames received by the socket but not yet read with
+/// [`MemSocket::recv_frame`] are kept in the snapshot.
+#[cfg(feature = "snapshot")]
+impl<L: Layout> Migrate for MemSocket<L> {
+    fn snapshot(&self) -> Result<ActorSnapshot, Self::Error> {
+        ActorSnapshot::encode(&MemSocketState {
+            id: self.id,
+            received: self.received.clone(),
+        })
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    fn restore(snapshot: ActorSnapshot, _controller: Controller<L>) -> Result<Self, Self::Error> {
+        let state: MemSocketState = snapshot
+            .decode()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let queues = SOCKET_QUEUES
+            .lock()
+            .expect("socket queues lock is poisoned");
+        let Some((inbox, outbox)) = queues
+            .get(&state.id)
+            .and_then(|(inbox, outbox)| Some((inbox.upgrade()?, outbox.upgrade()?)))
+        else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("socket {} does not exist anymore", state.id),
+            ));
+        };
+        Ok(MemSocket {
+            id: state.id,
+            inbox,
+            outbox,
+            received: state.received,
+            _phantom: PhantomData,
+        })
+    }
+}
+
+impl<L: Layout> AsMemSocket for MemSocket<L> {
+    fn inbox(&self) -> &Arc<MemQueue> {
+        &self.inbox
+    }
+}
+
+impl<L: Layout> Actor for MemSocket<L> {
+    type Layout = L;
+    type Id = u64;
+    type Context = Self;
+    type Cmd = Vec<u8>;
+    type Error = io::Error;
+
+    /// Sockets are constructed with [`MemSocket::pair`], so the context is
+    /// the socket itself.
+    fn with(socket: Self, _controller: Controller<L>) -> Result<Self, Self::Error> {
+        Ok(socket)
+    }
+
+    fn id(&self) -> Self::Id {
+        self.id
+    }
+
+    fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+        if io.is_readable {
+            self.inbox.drain_into(&mut self.received);
+        }
+        Ok(())
+    }
+
+    fn handle_cmd(&mut self, frame: Self::Cmd) -> Result<(), Self::Error> {
+        self.send_frame(frame);
+        Ok(())
+    }
+
+    fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+        Err(err)
+    }
+
+    fn interests(&self) -> IoEv {
+        IoEv {
+            is_readable: true,
+            is_writable: false,
+            is_hangup: false,
+            is_error: false,
+        }
+    }
+
+    fn disconnect(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
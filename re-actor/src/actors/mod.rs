@@ -14,21 +14,34 @@
 //! composed into an actor which performs encoding on that stream - and then
 //! into actor providing some framing protocol etc.
 
+pub mod circuit;
+pub mod heartbeat;
+pub mod mem;
 #[cfg(feature = "mio")]
 pub mod mio;
 #[cfg(feature = "socket2")]
 pub mod socket2;
 pub mod stdfd;
 pub mod stdtcp;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod udp;
+#[cfg(unix)]
+pub mod uds;
 #[cfg(feature = "zmq")]
 pub mod zeromq;
 
 use std::error::Error as StdError;
 use std::fmt::{Debug, Display};
 use std::hash::Hash;
+use std::io;
+use std::time::Instant;
 
 use crate::{Controller, Layout};
 
+/// Priority of the actors which do not define [`Actor::PRIORITY`].
+pub const DEFAULT_PRIORITY: u8 = 128;
+
 /// Actor is an piece of business logic which depends on I/O and managed in
 /// concurrent way by a [`Reactor`] runtime.
 ///
@@ -68,13 +81,21 @@ pub trait Actor {
     ///
     /// In case of a single command this can be just a data type providing
     /// parameters to that command (for instance a byte string for an
-    /// actor operating as a writer).
-    type Cmd: Send;
+    /// actor operating as a writer). Commands must be cloneable, so they can
+    /// be broadcasted to all actors with [`ReactorApi::broadcast`].
+    type Cmd: Clone + Send;
 
     /// Actor-specific error type, returned from I/O events handling or
     /// command-processing business logic.
     type Error: StdError;
 
+    /// Priority of the actor I/O events. Among the events collected by a
+    /// single scheduler wait, events of the actors with higher priority are
+    /// dispatched first. Actors of the same priority keep the scheduler order.
+    ///
+    /// Defaults to [`DEFAULT_PRIORITY`].
+    const PRIORITY: u8 = DEFAULT_PRIORITY;
+
     /// Constructs actor giving some `context`. Each actor is provided with the
     /// controller, which it should store internally in cases when it requires
     /// operating with other actors.
@@ -105,6 +126,167 @@ pub trait Actor {
 
     /// The errors returned by this method are forwarded to [`Broker::handle_err`].
     fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error>;
+
+    /// Reports whether the actor has outbound data which is not yet written to
+    /// the underlying I/O. During the re-actor shutdown such actors are given
+    /// a grace period to complete writing before they get disconnected.
+    fn has_pending_output(&self) -> bool {
+        false
+    }
+
+    /// Called by the re-actor runtime when the pool has been idle, i.e. no I/O
+    /// events happened and no timers fired during the pool idle timeout.
+    /// Called only for the pools constructed with [`Pool::with_actor_idle`],
+    /// since it takes time proportional to the number of actors in the pool.
+    fn on_idle(&mut self) {}
+
+    /// Deadline by which the actor must become operational, for instance
+    /// complete establishing its connection. Checked by the re-actor runtime
+    /// once the actor is registered and each time the deadline passes:
+    /// [`Actor::on_deadline`] is called unless the actor has no deadline (or
+    /// has a later one) by that time.
+    fn deadline(&self) -> Option<Instant> {
+        None
+    }
+
+    /// Called by the re-actor runtime once the deadline returned by
+    /// [`Actor::deadline`] has passed.
+    ///
+    /// The errors returned by this method are forwarded to [`Self::handle_err`];
+    /// if the error is not handled the actor gets disconnected.
+    fn on_deadline(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Reason reported to [`Handler::on_disconnect`] once the actor gets
+    /// disconnected for failing to handle its deadline in
+    /// [`Actor::on_deadline`]. Defaults to a timed out connection.
+    fn deadline_reason(&self) -> DisconnectReason {
+        DisconnectReason::ConnectionError(io::ErrorKind::TimedOut.into())
+    }
+
+    /// Reports whether the actor has stopped processing I/O events after
+    /// repeated failures, like [`circuit::CircuitBreaker`] does. Checked by
+    /// the re-actor runtime together with [`Actor::interests`]; changes are
+    /// reported to [`Handler::on_circuit_open`] and
+    /// [`Handler::on_circuit_close`], and the [`Actor::deadline`] of an actor
+    /// with an open circuit is re-read on each check.
+    fn is_circuit_open(&self) -> bool {
+        false
+    }
+
+    /// Priority of the actor I/O events, which is passed to the scheduler by
+    /// the re-actor runtime once the actor is registered. Default
+    /// implementation returns [`Actor::PRIORITY`]; it should be overridden by
+    /// actors wrapping actors of different types, like enums of actors.
+    fn priority(&self) -> u8 {
+        Self::PRIORITY
+    }
+
+    /// I/O events the actor is interested in. Checked by the re-actor runtime
+    /// once the actor is registered and after each I/O event or command
+    /// processed by the actor.
+    ///
+    /// Actors should drop the write interest when they have nothing to write,
+    /// otherwise an idle connection, which is always writable, makes the
+    /// runtime to call [`Actor::io_ready`] continuously. Default
+    /// implementation is interested in both read and write events.
+    fn interests(&self) -> IoEv {
+        IoEv {
+            is_readable: true,
+            is_writable: true,
+            is_hangup: false,
+            is_error: false,
+        }
+    }
+
+    /// Called by the re-actor runtime on [`ReactorApi::half_close`] request.
+    /// Implementations should shut down the writing half of the connection
+    /// once the pending output is written, keeping it open for reading.
+    /// Default implementation does nothing.
+    ///
+    /// The errors returned by this method are forwarded to [`Self::handle_err`].
+    fn shutdown_write(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// Called by the re-actor [`Runtime`] when the actor is removed from the
+    /// re-actor, either by [`ReactorApi::stop_actor`] or during the re-actor
+    /// shutdown. The actor is already unregistered from the scheduler at this
+    /// point. Implementations should flush pending data and close the
+    /// underlying I/O resources. Defaults to doing nothing, leaving it to the
+    /// actor `Drop` implementation.
+    ///
+    /// The errors returned by this method are forwarded to [`Self::handle_err`].
+    fn disconnect(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// Actor which can be moved between re-actor instances, for instance for load
+/// balancing or to survive a process restart, by encoding its state into an
+/// [`ActorSnapshot`] and constructing an equivalent actor out of it.
+///
+/// Actors are migrated with [`ReactorApi::migrate_actor`] or restored from a
+/// previously taken snapshot with [`ReactorApi::restore_actor`].
+///
+/// [`ReactorApi::migrate_actor`]: crate::ReactorApi::migrate_actor
+/// [`ReactorApi::restore_actor`]: crate::ReactorApi::restore_actor
+pub trait Migrate: Actor {
+    /// Encodes actor state, like connection parameters, encryption keys and
+    /// sequence numbers, into a snapshot. The actor itself is kept intact and
+    /// gets disconnected by the re-actor only once the snapshot is passed to
+    /// the target re-actor.
+    fn snapshot(&self) -> Result<ActorSnapshot, Self::Error>;
+
+    /// Constructs actor out of the `snapshot` produced by
+    /// [`Migrate::snapshot`], providing it with the controller of the re-actor
+    /// it is restored in. The restored actor must have the same id as the
+    /// actor the snapshot was taken from.
+    fn restore(
+        snapshot: ActorSnapshot,
+        controller: Controller<Self::Layout>,
+    ) -> Result<Self, Self::Error>
+    where
+        Self: Sized;
+}
+
+/// Opaque state of an actor produced by [`Migrate::snapshot`].
+///
+/// With `snapshot` feature the state can be encoded using `serde` with
+/// `bincode` (see [`ActorSnapshot::encode`]).
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct ActorSnapshot(Vec<u8>);
+
+impl ActorSnapshot {
+    /// Returns encoded actor state.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Returns encoded actor state, consuming the snapshot.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.0
+    }
+}
+
+#[cfg(feature = "snapshot")]
+impl ActorSnapshot {
+    /// Encodes actor state with `bincode`.
+    pub fn encode(state: &impl serde::Serialize) -> Result<Self, bincode::Error> {
+        bincode::serialize(state).map(ActorSnapshot)
+    }
+
+    /// Decodes actor state encoded with [`ActorSnapshot::encode`].
+    pub fn decode<T: serde::de::DeserializeOwned>(&self) -> Result<T, bincode::Error> {
+        bincode::deserialize(&self.0)
+    }
+}
+
+impl From<Vec<u8>> for ActorSnapshot {
+    fn from(bytes: Vec<u8>) -> Self {
+        ActorSnapshot(bytes)
+    }
 }
 
 /// Information about generated I/O events from the event loop.
@@ -116,11 +298,100 @@ pub struct IoSrc<S> {
     pub io: IoEv,
 }
 
-/// Information about I/O events which has happened for an actor
+/// Information about I/O events which has happened for an actor.
+///
+/// The same structure is used to express actor interests (see
+/// [`Actor::interests`]); in this case hang-up and error flags have no effect,
+/// since such conditions are always reported by the schedulers.
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
 pub struct IoEv {
     /// Specifies whether I/O source has data to read.
     pub is_readable: bool,
     /// Specifies whether I/O source is ready for write operations.
     pub is_writable: bool,
+    /// Specifies whether the remote peer has closed the connection. Not all
+    /// schedulers are able to detect this condition; in this case it is
+    /// reported as a readable event followed by a zero-length read.
+    pub is_hangup: bool,
+    /// Specifies whether an error condition has happened on the I/O source.
+    pub is_error: bool,
+}
+
+/// Reason of the connection being closed, reported by the connection actors
+/// as an error from [`Actor::io_ready`] when the scheduler detects a hang-up or
+/// an error condition on the socket. The re-actor runtime reports it to
+/// [`Handler::on_disconnect`] for each of the removed actors.
+#[derive(Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DisconnectReason {
+    /// connection closed by the remote peer
+    Hangup,
+
+    /// unable to connect to the remote peer: {0}
+    DialError(io::Error),
+
+    /// connection failed: {0}
+    ConnectionError(io::Error),
+
+    /// disconnected on request
+    OnDemand,
+
+    /// actor has panicked
+    Panicked,
+
+    /// remote peer has not answered the heartbeat in time
+    HeartbeatTimeout,
+
+    /// actor was migrated to another re-actor
+    Migrated,
+
+    /// all reconnection attempts have failed
+    ReconnectExhausted,
+}
+
+impl From<DisconnectReason> for io::Error {
+    fn from(reason: DisconnectReason) -> Self {
+        let kind = match &reason {
+            DisconnectReason::Hangup
+            | DisconnectReason::OnDemand
+            | DisconnectReason::Panicked
+            | DisconnectReason::Migrated => io::ErrorKind::ConnectionAborted,
+            DisconnectReason::HeartbeatTimeout => io::ErrorKind::TimedOut,
+            DisconnectReason::ReconnectExhausted => io::ErrorKind::NotConnected,
+            DisconnectReason::DialError(err) | DisconnectReason::ConnectionError(err) => err.kind(),
+        };
+        io::Error::new(kind, reason)
+    }
+}
+
+/// Raw OS handle of an I/O source, which can be used as an actor id: a file
+/// descriptor on unix and a socket on Windows.
+#[cfg(unix)]
+pub type RawSource = std::os::unix::io::RawFd;
+/// Raw OS handle of an I/O source, which can be used as an actor id: a file
+/// descriptor on unix and a socket on Windows.
+#[cfg(windows)]
+pub type RawSource = std::os::windows::io::RawSocket;
+
+/// Platform-independent way of extracting [`RawSource`] from an I/O source.
+///
+/// The trait is implemented for all types implementing `AsRawFd` on unix and
+/// `AsRawSocket` on Windows.
+pub trait AsRawSource {
+    /// Returns raw OS handle of the I/O source.
+    fn as_raw_source(&self) -> RawSource;
+}
+
+#[cfg(unix)]
+impl<T: std::os::unix::io::AsRawFd + ?Sized> AsRawSource for T {
+    fn as_raw_source(&self) -> RawSource {
+        self.as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl<T: std::os::windows::io::AsRawSocket + ?Sized> AsRawSource for T {
+    fn as_raw_source(&self) -> RawSource {
+        self.as_raw_socket()
+    }
 }
@@ -1,21 +1,286 @@
 use std::collections::VecDeque;
 use std::io::{self, Read, Write};
-use std::net::{SocketAddr, TcpStream};
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::mem;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+#[cfg(unix)]
 use std::os::unix::io::{AsRawFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, RawSocket};
+#[cfg(target_vendor = "apple")]
+use std::ptr;
 use std::time;
 
-use socket2::{Domain, Socket, Type};
+#[cfg(target_vendor = "apple")]
+use socket2::SockAddr;
+use socket2::{Domain, Socket, TcpKeepalive, Type};
 
 use crate::actors::stdtcp::TcpAction;
-use crate::actors::IoEv;
-use crate::{Actor, Controller, Layout};
+use crate::actors::{AsRawSource, DisconnectReason, IoEv, RawSource};
+use crate::{Actor, Controller, Layout, ReactorApi};
+
+/// Error returned by `connect` when a connection attempt is already in
+/// progress for the socket.
+#[cfg(unix)]
+const EALREADY: i32 = libc::EALREADY;
+/// Error returned by `connect` when a connection attempt is already in
+/// progress for the socket (`WSAEALREADY`).
+#[cfg(windows)]
+const EALREADY: i32 = 10037;
 
-// TODO: Move to context
 /// Maximum time to wait when reading from a socket.
 const READ_TIMEOUT: time::Duration = time::Duration::from_secs(6);
-// TODO: Move to context
 /// Maximum time to wait when writing to a socket.
 const WRITE_TIMEOUT: time::Duration = time::Duration::from_secs(3);
+/// Maximum time to wait for an outbound connection to get established.
+const DIAL_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// Options applied to the sockets of [`SocketConnection`]s.
+///
+/// Default configuration uses 6 seconds read timeout, 3 seconds write timeout,
+/// 10 seconds dial timeout, leaves TCP keepalive, `TCP_NODELAY` and TCP Fast
+/// Open disabled and keeps the OS defaults for linger and buffer sizes.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct TcpConfig {
+    /// Maximum time to wait when reading from a socket.
+    pub read_timeout: Option<time::Duration>,
+    /// Maximum time to wait when writing to a socket.
+    pub write_timeout: Option<time::Duration>,
+    /// Maximum time to wait for an outbound connection to get established.
+    /// Enforced by the re-actor runtime via [`Actor::deadline`].
+    pub dial_timeout: Option<time::Duration>,
+    /// Time the connection has to be idle before keepalive probes are sent
+    /// (`TCP_KEEPIDLE`).
+    pub keepalive_idle: Option<time::Duration>,
+    /// Time between keepalive probes (`TCP_KEEPINTVL`).
+    pub keepalive_interval: Option<time::Duration>,
+    /// Number of unanswered keepalive probes after which the connection is
+    /// dropped (`TCP_KEEPCNT`).
+    pub keepalive_retries: Option<u32>,
+    /// Use TCP Fast Open for outbound connections, sending the data written
+    /// first to the socket together with the SYN packet. If the platform or
+    /// the remote peer does not support it, the connection silently falls
+    /// back to the regular TCP handshake. Supported on Linux and Apple
+    /// platforms only; ignored elsewhere.
+    pub fast_open: bool,
+    /// Disable Nagle's algorithm (`TCP_NODELAY`), sending small writes at once
+    /// instead of coalescing them.
+    pub nodelay: bool,
+    /// Time to block closing the socket while unsent data are transmitted
+    /// (`SO_LINGER`). `None` leaves the OS default.
+    pub linger: Option<time::Duration>,
+    /// Size of the socket send buffer (`SO_SNDBUF`). `None` leaves the OS
+    /// default.
+    pub send_buffer_size: Option<usize>,
+    /// Size of the socket receive buffer (`SO_RCVBUF`). `None` leaves the OS
+    /// default.
+    pub recv_buffer_size: Option<usize>,
+}
+
+impl Default for TcpConfig {
+    fn default() -> Self {
+        TcpConfig {
+            read_timeout: Some(READ_TIMEOUT),
+            write_timeout: Some(WRITE_TIMEOUT),
+            dial_timeout: Some(DIAL_TIMEOUT),
+            keepalive_idle: None,
+            keepalive_interval: None,
+            keepalive_retries: None,
+            fast_open: false,
+            nodelay: false,
+            linger: None,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        }
+    }
+}
+
+impl TcpConfig {
+    pub fn with_read_timeout(mut self, timeout: Option<time::Duration>) -> Self {
+        self.read_timeout = timeout;
+        self
+    }
+
+    pub fn with_write_timeout(mut self, timeout: Option<time::Duration>) -> Self {
+        self.write_timeout = timeout;
+        self
+    }
+
+    pub fn with_dial_timeout(mut self, timeout: Option<time::Duration>) -> Self {
+        self.dial_timeout = timeout;
+        self
+    }
+
+    pub fn with_keepalive_idle(mut self, idle: time::Duration) -> Self {
+        self.keepalive_idle = Some(idle);
+        self
+    }
+
+    /// Sets time between keepalive probes. Ignored on platforms which do not
+    /// support `TCP_KEEPINTVL` socket option.
+    pub fn with_keepalive_interval(mut self, interval: time::Duration) -> Self {
+        self.keepalive_interval = Some(interval);
+        self
+    }
+
+    /// Sets number of keepalive probes. Ignored on platforms which do not
+    /// support `TCP_KEEPCNT` socket option.
+    pub fn with_keepalive_retries(mut self, retries: u32) -> Self {
+        self.keepalive_retries = Some(retries);
+        self
+    }
+
+    pub fn with_fast_open(mut self, fast_open: bool) -> Self {
+        self.fast_open = fast_open;
+        self
+    }
+
+    pub fn with_nodelay(mut self, nodelay: bool) -> Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    pub fn with_linger(mut self, linger: time::Duration) -> Self {
+        self.linger = Some(linger);
+        self
+    }
+
+    /// Sets size of the socket send buffer. The OS may adjust the value, for
+    /// instance Linux doubles it; the effective size is returned by
+    /// [`SocketConnection::send_buffer_size`].
+    pub fn with_send_buffer_size(mut self, size: usize) -> Self {
+        self.send_buffer_size = Some(size);
+        self
+    }
+
+    /// Sets size of the socket receive buffer. The OS may adjust the value,
+    /// for instance Linux doubles it; the effective size is returned by
+    /// [`SocketConnection::recv_buffer_size`].
+    pub fn with_recv_buffer_size(mut self, size: usize) -> Self {
+        self.recv_buffer_size = Some(size);
+        self
+    }
+
+    /// Detects whether TCP keepalive is enabled by any of the options.
+    pub fn is_keepalive(&self) -> bool {
+        self.keepalive_idle.is_some()
+            || self.keepalive_interval.is_some()
+            || self.keepalive_retries.is_some()
+    }
+
+    fn apply(&self, socket: &Socket) -> io::Result<()> {
+        socket.set_read_timeout(self.read_timeout)?;
+        socket.set_write_timeout(self.write_timeout)?;
+        socket.set_nodelay(self.nodelay)?;
+        if let Some(linger) = self.linger {
+            socket.set_linger(Some(linger))?;
+        }
+        if let Some(size) = self.send_buffer_size {
+            socket.set_send_buffer_size(size)?;
+        }
+        if let Some(size) = self.recv_buffer_size {
+            socket.set_recv_buffer_size(size)?;
+        }
+        if !self.is_keepalive() {
+            return Ok(());
+        }
+
+        let mut keepalive = TcpKeepalive::new();
+        if let Some(idle) = self.keepalive_idle {
+            keepalive = keepalive.with_time(idle);
+        }
+        #[cfg(any(
+            target_os = "android",
+            target_os = "freebsd",
+            target_os = "linux",
+            target_os = "netbsd",
+            target_vendor = "apple",
+        ))]
+        {
+            if let Some(interval) = self.keepalive_interval {
+                keepalive = keepalive.with_interval(interval);
+            }
+            if let Some(retries) = self.keepalive_retries {
+                keepalive = keepalive.with_retries(retries);
+            }
+        }
+        socket.set_tcp_keepalive(&keepalive)
+    }
+}
+
+/// Connects the socket with TCP Fast Open enabled by `TCP_FASTOPEN_CONNECT`
+/// option. The kernel defers sending SYN packet until the first write, which
+/// works then as `sendto` with `MSG_FASTOPEN` flag.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn connect_fast_open(socket: &Socket, addr: SocketAddr) -> io::Result<()> {
+    let enable: libc::c_int = 1;
+    // Failure means that the kernel does not support the option; in this case
+    // the regular TCP handshake is used
+    let _ = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_TCP,
+            libc::TCP_FASTOPEN_CONNECT,
+            &enable as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    socket.connect(&addr.into())
+}
+
+/// Connects the socket with `connectx`, which defers the connection until the
+/// first write, sending the written data together with SYN packet.
+#[cfg(target_vendor = "apple")]
+fn connect_fast_open(socket: &Socket, addr: SocketAddr) -> io::Result<()> {
+    let addr = SockAddr::from(addr);
+    let endpoints = libc::sa_endpoints_t {
+        sae_srcif: 0,
+        sae_srcaddr: ptr::null(),
+        sae_srcaddrlen: 0,
+        sae_dstaddr: addr.as_ptr(),
+        sae_dstaddrlen: addr.len(),
+    };
+    let res = unsafe {
+        libc::connectx(
+            socket.as_raw_fd(),
+            &endpoints,
+            libc::SAE_ASSOCID_ANY,
+            libc::CONNECT_RESUME_ON_READ_WRITE | libc::CONNECT_DATA_IDEMPOTENT,
+            ptr::null(),
+            0,
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_vendor = "apple")))]
+fn connect_fast_open(socket: &Socket, addr: SocketAddr) -> io::Result<()> {
+    socket.connect(&addr.into())
+}
+
+/// Context for constructing [`SocketConnection`], which carries the socket
+/// configuration. Can be converted from [`TcpAction`], in which case the
+/// default configuration is used.
+pub enum SocketAction {
+    Accept(TcpStream, SocketAddr, TcpConfig),
+    Connect(SocketAddr, TcpConfig),
+}
+
+impl From<TcpAction> for SocketAction {
+    fn from(action: TcpAction) -> Self {
+        match action {
+            TcpAction::Accept(stream, addr) => {
+                SocketAction::Accept(stream, addr, TcpConfig::default())
+            }
+            TcpAction::Connect(addr) => SocketAction::Connect(addr, TcpConfig::default()),
+        }
+    }
+}
 
 pub struct SocketConnection<L: Layout> {
     socket: Socket,
@@ -23,11 +288,23 @@ pub struct SocketConnection<L: Layout> {
     read_buf: Vec<u8>,
     pub(super) controller: Controller<L>,
     pub(super) is_inbound: bool,
+    /// Whether the outbound connection is not yet established.
+    is_connecting: bool,
+    dial_deadline: Option<time::Instant>,
 }
 
 impl<L: Layout> SocketConnection<L> {
     pub fn connect(addr: SocketAddr, controller: Controller<L>) -> io::Result<Self> {
-        let read_buf = Vec::with_capacity(u16::MAX as usize);
+        Self::connect_with_config(addr, TcpConfig::default(), controller)
+    }
+
+    /// Connects to the remote address using a custom socket configuration.
+    pub fn connect_with_config(
+        addr: SocketAddr,
+        config: TcpConfig,
+        controller: Controller<L>,
+    ) -> io::Result<Self> {
+        let read_buf = vec![0u8; u16::MAX as usize];
 
         let domain = if addr.is_ipv4() {
             Domain::IPV4
@@ -36,19 +313,25 @@ impl<L: Layout> SocketConnection<L> {
         };
         let socket = Socket::new(domain, Type::STREAM, None)?;
 
-        socket.set_read_timeout(Some(READ_TIMEOUT))?;
-        socket.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        config.apply(&socket)?;
         socket.set_nonblocking(true)?;
 
-        match socket.connect(&addr.into()) {
-            Ok(()) => {}
-            Err(e) if e.raw_os_error() == Some(libc::EINPROGRESS) => {}
-            Err(e) if e.raw_os_error() == Some(libc::EALREADY) => {
+        let res = if config.fast_open {
+            connect_fast_open(&socket, addr)
+        } else {
+            socket.connect(&addr.into())
+        };
+        let is_connecting = match res {
+            Ok(()) => false,
+            #[cfg(unix)]
+            Err(e) if e.raw_os_error() == Some(libc::EINPROGRESS) => true,
+            Err(e) if e.raw_os_error() == Some(EALREADY) => {
                 return Err(io::Error::from(io::ErrorKind::AlreadyExists))
             }
-            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            // Non-blocking connect on Windows fails with `WSAEWOULDBLOCK`
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => true,
             Err(e) => return Err(e),
-        }
+        };
 
         Ok(Self {
             socket,
@@ -56,16 +339,29 @@ impl<L: Layout> SocketConnection<L> {
             read_buf,
             controller,
             is_inbound: false,
+            is_connecting,
+            dial_deadline: config
+                .dial_timeout
+                .map(|timeout| time::Instant::now() + timeout),
         })
     }
 
     pub fn accept(stream: TcpStream, controller: Controller<L>) -> io::Result<Self> {
-        let read_buf = Vec::with_capacity(u16::MAX as usize);
+        Self::accept_with_config(stream, TcpConfig::default(), controller)
+    }
+
+    /// Takes inbound connection using a custom socket configuration. Dial
+    /// timeout and TCP Fast Open options do not apply to inbound connections.
+    pub fn accept_with_config(
+        stream: TcpStream,
+        config: TcpConfig,
+        controller: Controller<L>,
+    ) -> io::Result<Self> {
+        let read_buf = vec![0u8; u16::MAX as usize];
 
         let socket = Socket::from(stream);
 
-        socket.set_read_timeout(Some(READ_TIMEOUT))?;
-        socket.set_write_timeout(Some(WRITE_TIMEOUT))?;
+        config.apply(&socket)?;
         socket.set_nonblocking(true)?;
 
         Ok(Self {
@@ -74,14 +370,52 @@ impl<L: Layout> SocketConnection<L> {
             read_buf,
             controller,
             is_inbound: true,
+            is_connecting: false,
+            dial_deadline: None,
         })
     }
+
+    /// Detects whether the connection is established. Outbound connections
+    /// get established once the socket becomes writable for the first time.
+    pub fn is_connected(&self) -> bool {
+        !self.is_connecting
+    }
+
+    /// Returns effective value of `TCP_NODELAY` option of the socket.
+    pub fn nodelay(&self) -> io::Result<bool> {
+        self.socket.nodelay()
+    }
+
+    /// Returns effective value of `SO_LINGER` option of the socket.
+    pub fn linger(&self) -> io::Result<Option<time::Duration>> {
+        self.socket.linger()
+    }
+
+    /// Returns effective size of the socket send buffer.
+    pub fn send_buffer_size(&self) -> io::Result<usize> {
+        self.socket.send_buffer_size()
+    }
+
+    /// Returns effective size of the socket receive buffer.
+    pub fn recv_buffer_size(&self) -> io::Result<usize> {
+        self.socket.recv_buffer_size()
+    }
+
+    /// Returns time the connection has to be idle before keepalive probes are
+    /// sent, or `None` if TCP keepalive is disabled.
+    #[cfg(not(windows))]
+    pub fn keepalive_idle(&self) -> io::Result<Option<time::Duration>> {
+        if !self.socket.keepalive()? {
+            return Ok(None);
+        }
+        self.socket.keepalive_time().map(Some)
+    }
 }
 
 impl<L: Layout> Actor for SocketConnection<L> {
     type Layout = L;
-    type Id = RawFd;
-    type Context = TcpAction;
+    type Id = RawSource;
+    type Context = SocketAction;
     type Cmd = Vec<u8>;
     type Error = io::Error;
 
@@ -93,22 +427,51 @@ impl<L: Layout> Actor for SocketConnection<L> {
         Self: Sized,
     {
         match context {
-            TcpAction::Accept(stream, _addr) => Self::accept(stream, controller),
-            TcpAction::Connect(addr) => Self::connect(addr, controller),
+            SocketAction::Accept(stream, _addr, config) => {
+                Self::accept_with_config(stream, config, controller)
+            }
+            SocketAction::Connect(addr, config) => {
+                Self::connect_with_config(addr, config, controller)
+            }
         }
     }
 
     fn id(&self) -> Self::Id {
-        self.socket.as_raw_fd()
+        self.socket.as_raw_source()
     }
 
     fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+        if self.is_connecting && (io.is_writable || io.is_error) {
+            // Outcome of the non-blocking connect is reported via `SO_ERROR`
+            if let Some(err) = self.socket.take_error()? {
+                return Err(DisconnectReason::DialError(err).into());
+            }
+            self.is_connecting = false;
+        }
+        if io.is_error {
+            let err = self
+                .socket
+                .take_error()?
+                .unwrap_or_else(|| io::ErrorKind::Other.into());
+            return Err(DisconnectReason::ConnectionError(err).into());
+        }
         if io.is_readable {
-            self.flush()?;
+            // Socket is read until it is drained, so no data is left unread
+            // with the edge-triggered schedulers
+            loop {
+                match self.socket.read(&mut self.read_buf) {
+                    Ok(0) => return Err(DisconnectReason::Hangup.into()),
+                    Ok(len) => self.queue.extend(&self.read_buf[..len]),
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => return Err(err),
+                }
+            }
         }
         if io.is_writable {
-            let len = self.socket.read(&mut self.read_buf)?;
-            self.queue.extend(&self.read_buf[..len]);
+            self.flush()?;
+        }
+        if io.is_hangup {
+            return Err(DisconnectReason::Hangup.into());
         }
         Ok(())
     }
@@ -120,6 +483,32 @@ impl<L: Layout> Actor for SocketConnection<L> {
     fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
         Err(err)
     }
+
+    fn deadline(&self) -> Option<time::Instant> {
+        if self.is_connecting {
+            self.dial_deadline
+        } else {
+            None
+        }
+    }
+
+    fn on_deadline(&mut self) -> Result<(), Self::Error> {
+        if !self.is_connecting {
+            return Ok(());
+        }
+        let err = io::Error::from(io::ErrorKind::TimedOut);
+        Err(DisconnectReason::DialError(err).into())
+    }
+
+    fn disconnect(&mut self) -> Result<(), Self::Error> {
+        if self.is_connecting {
+            // There is nothing to flush or shut down; the socket is closed
+            // once the actor is dropped
+            return Ok(());
+        }
+        self.flush()?;
+        self.socket.shutdown(Shutdown::Both)
+    }
 }
 
 impl<L: Layout> Read for SocketConnection<L> {
@@ -138,8 +527,403 @@ impl<L: Layout> Write for SocketConnection<L> {
     }
 }
 
+#[cfg(unix)]
 impl<L: Layout> AsRawFd for SocketConnection<L> {
     fn as_raw_fd(&self) -> RawFd {
         self.socket.as_raw_fd()
     }
 }
+
+#[cfg(windows)]
+impl<L: Layout> AsRawSocket for SocketConnection<L> {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.socket.as_raw_socket()
+    }
+}
+
+/// Listener spawning [`SocketConnection`]s in the `SESSION_POOL_ID` pool.
+/// Accepted connections inherit the socket configuration of the listener.
+pub struct SocketSpawner<L: Layout, const SESSION_POOL_ID: u32> {
+    socket: TcpListener,
+    config: TcpConfig,
+    controller: Controller<L>,
+}
+
+impl<L: Layout, const SESSION_POOL_ID: u32> SocketSpawner<L, SESSION_POOL_ID> {
+    /// Binds listener to the address; the accepted connections are configured
+    /// with `config`.
+    pub fn listen(
+        addr: SocketAddr,
+        config: TcpConfig,
+        controller: Controller<L>,
+    ) -> io::Result<Self> {
+        let socket = TcpListener::bind(addr)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            config,
+            controller,
+        })
+    }
+
+    /// Returns address the listening socket is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Returns configuration applied to the accepted connections.
+    pub fn config(&self) -> TcpConfig {
+        self.config
+    }
+}
+
+impl<L: Layout, const SESSION_POOL_ID: u32> Actor for SocketSpawner<L, SESSION_POOL_ID> {
+    type Layout = L;
+    type Id = RawSource;
+    type Context = (SocketAddr, TcpConfig);
+    type Cmd = ();
+    type Error = io::Error;
+
+    fn with(context: Self::Context, controller: Controller<L>) -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        let (addr, config) = context;
+        Self::listen(addr, config, controller)
+    }
+
+    fn id(&self) -> Self::Id {
+        self.socket.as_raw_source()
+    }
+
+    fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+        if !io.is_readable {
+            return Ok(());
+        }
+        // All pending connections are accepted, since a single event may be
+        // reported for several of them
+        loop {
+            let (stream, peer_socket_addr) = match self.socket.accept() {
+                Ok(accepted) => accepted,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            let action = SocketAction::Accept(stream, peer_socket_addr, self.config);
+            let ctx = L::convert(Box::new(action));
+            self.controller
+                .start_actor(SESSION_POOL_ID.into(), ctx)
+                .map_err(|_| io::ErrorKind::NotConnected)?;
+        }
+    }
+
+    fn handle_cmd(&mut self, _cmd: Self::Cmd) -> Result<(), Self::Error> {
+        // Listener does not support any commands
+        Ok(())
+    }
+
+    fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+        // Listener does not know how to handle errors, so it just propagates them
+        Err(err)
+    }
+
+    fn interests(&self) -> IoEv {
+        // Pending connections are signalled by the listening socket being
+        // readable
+        IoEv {
+            is_readable: true,
+            is_writable: false,
+            is_hangup: false,
+            is_error: false,
+        }
+    }
+
+    fn disconnect(&mut self) -> Result<(), Self::Error> {
+        // Listening socket is closed once the actor is dropped
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+impl<L: Layout, const SESSION_POOL_ID: u32> AsRawFd for SocketSpawner<L, SESSION_POOL_ID> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl<L: Layout, const SESSION_POOL_ID: u32> AsRawSocket for SocketSpawner<L, SESSION_POOL_ID> {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.socket.as_raw_socket()
+    }
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use std::any::Any;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::reactor::tests::{reactor, IdleScheduler};
+    use crate::{Handler, InternalError, Pool, Reactor};
+
+    /// Connections started by the [`SocketPool`] re-actors.
+    static ACCEPTED: Mutex<Vec<RawSource>> = Mutex::new(Vec::new());
+
+    #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+    #[display(Debug)]
+    enum SocketPool {
+        Sessions,
+    }
+
+    impl From<u32> for SocketPool {
+        fn from(_: u32) -> Self {
+            SocketPool::Sessions
+        }
+    }
+
+    impl From<SocketPool> for u32 {
+        fn from(_: SocketPool) -> Self {
+            0
+        }
+    }
+
+    impl Layout for SocketPool {
+        type RootActor = SocketConnection<SocketPool>;
+
+        fn default_pools() -> Vec<Pool<Self::RootActor, Self>> {
+            vec![Pool::new(
+                SocketPool::Sessions,
+                IdleScheduler::default(),
+                AcceptRecorder,
+            )]
+        }
+
+        fn convert(ctx: Box<dyn Any>) -> SocketAction {
+            *ctx.downcast().expect("spawner provides socket actions")
+        }
+    }
+
+    /// Handler recording started connections in [`ACCEPTED`].
+    struct AcceptRecorder;
+
+    impl Handler<SocketPool> for AcceptRecorder {
+        fn handle_err(&mut self, err: InternalError<SocketPool>) {
+            panic!("unexpected re-actor error: {err}")
+        }
+
+        fn on_connect(&mut self, id: &RawSource) {
+            ACCEPTED.lock().unwrap().push(*id);
+        }
+    }
+
+    #[test]
+    fn keepalive_options() {
+        let (mut reactor, _) = reactor();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = TcpConfig::default()
+            .with_read_timeout(None)
+            .with_keepalive_idle(Duration::from_secs(60))
+            .with_keepalive_interval(Duration::from_secs(10))
+            .with_keepalive_retries(5);
+        let conn = SocketConnection::connect_with_config(
+            listener.local_addr().unwrap(),
+            config,
+            reactor.controller(),
+        )
+        .unwrap();
+
+        assert!(conn.socket.keepalive().unwrap());
+        assert_eq!(
+            conn.socket.keepalive_time().unwrap(),
+            Duration::from_secs(60)
+        );
+        assert_eq!(
+            conn.socket.keepalive_interval().unwrap(),
+            Duration::from_secs(10)
+        );
+        assert_eq!(conn.socket.keepalive_retries().unwrap(), 5);
+        assert_eq!(conn.socket.read_timeout().unwrap(), None);
+        assert_eq!(conn.socket.write_timeout().unwrap(), Some(WRITE_TIMEOUT));
+
+        let conn = SocketConnection::connect(listener.local_addr().unwrap(), reactor.controller())
+            .unwrap();
+        assert!(!conn.socket.keepalive().unwrap());
+        reactor.shutdown().unwrap();
+    }
+
+    #[test]
+    fn fast_open() {
+        let (mut reactor, _) = reactor();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = TcpConfig::default().with_fast_open(true);
+        let mut conn = SocketConnection::connect_with_config(
+            listener.local_addr().unwrap(),
+            config,
+            reactor.controller(),
+        )
+        .unwrap();
+
+        let mut enabled: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let res = unsafe {
+            libc::getsockopt(
+                conn.as_raw_fd(),
+                libc::IPPROTO_TCP,
+                libc::TCP_FASTOPEN_CONNECT,
+                &mut enabled as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(res, 0);
+        assert_eq!(enabled, 1);
+
+        // The server does not support Fast Open, so the connection falls back
+        // to the regular handshake
+        let (mut stream, _) = listener.accept().unwrap();
+        conn.handle_cmd(b"hello".to_vec()).unwrap();
+        let mut data = [0u8; 5];
+        stream.read_exact(&mut data).unwrap();
+        assert_eq!(&data, b"hello");
+        reactor.shutdown().unwrap();
+    }
+
+    #[test]
+    fn socket_options() {
+        let (mut reactor, _) = reactor();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = TcpConfig::default()
+            .with_nodelay(true)
+            .with_linger(Duration::from_secs(1))
+            .with_send_buffer_size(64 * 1024)
+            .with_recv_buffer_size(64 * 1024);
+        let conn = SocketConnection::connect_with_config(
+            listener.local_addr().unwrap(),
+            config,
+            reactor.controller(),
+        )
+        .unwrap();
+
+        assert!(conn.nodelay().unwrap());
+        assert_eq!(conn.linger().unwrap(), Some(Duration::from_secs(1)));
+        // Linux doubles the requested buffer sizes
+        assert!(conn.send_buffer_size().unwrap() >= 64 * 1024);
+        assert!(conn.recv_buffer_size().unwrap() >= 64 * 1024);
+        assert_eq!(conn.keepalive_idle().unwrap(), None);
+
+        let conn = SocketConnection::connect(listener.local_addr().unwrap(), reactor.controller())
+            .unwrap();
+        assert!(!conn.nodelay().unwrap());
+        assert_eq!(conn.linger().unwrap(), None);
+        reactor.shutdown().unwrap();
+    }
+
+    #[test]
+    fn accepted_connections_inherit_config() {
+        let mut reactor = Reactor::<SocketPool>::new().unwrap();
+        let config = TcpConfig::default()
+            .with_nodelay(true)
+            .with_keepalive_idle(Duration::from_secs(60));
+        let mut spawner = SocketSpawner::<SocketPool, 0>::listen(
+            "127.0.0.1:0".parse().unwrap(),
+            config,
+            reactor.controller(),
+        )
+        .unwrap();
+        let _client = TcpStream::connect(spawner.local_addr().unwrap()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        spawner.io_ready(readable()).unwrap();
+
+        let start = Instant::now();
+        let id = loop {
+            if let Some(id) = ACCEPTED.lock().unwrap().pop() {
+                break id;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "connection was not accepted"
+            );
+            thread::sleep(Duration::from_millis(10));
+        };
+        let conn = reactor
+            .take_actor(id)
+            .unwrap()
+            .recv_timeout(Duration::from_secs(1))
+            .unwrap();
+        assert!(conn.is_inbound);
+        assert!(conn.nodelay().unwrap());
+        assert_eq!(
+            conn.keepalive_idle().unwrap(),
+            Some(Duration::from_secs(60))
+        );
+        reactor.shutdown().unwrap();
+    }
+
+    fn readable() -> IoEv {
+        IoEv {
+            is_readable: true,
+            is_writable: false,
+            is_hangup: false,
+            is_error: false,
+        }
+    }
+
+    fn writable() -> IoEv {
+        IoEv {
+            is_readable: false,
+            is_writable: true,
+            is_hangup: false,
+            is_error: false,
+        }
+    }
+
+    #[test]
+    fn dial_error() {
+        let (mut reactor, _) = reactor();
+        // Nobody listens on the port once the listener is dropped
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let mut conn = SocketConnection::connect(addr, reactor.controller()).unwrap();
+        assert!(!conn.is_connected());
+
+        thread::sleep(Duration::from_millis(100));
+        let err = conn.io_ready(writable()).unwrap_err();
+        match err.into_inner().unwrap().downcast::<DisconnectReason>() {
+            Ok(reason) => match *reason {
+                DisconnectReason::DialError(err) => {
+                    assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused)
+                }
+                reason => panic!("unexpected disconnect reason {reason}"),
+            },
+            Err(err) => panic!("unexpected error {err}"),
+        }
+        assert!(!conn.is_connected());
+        reactor.shutdown().unwrap();
+    }
+
+    #[test]
+    fn dial_timeout() {
+        let (mut reactor, _) = reactor();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = TcpConfig::default().with_dial_timeout(Some(Duration::ZERO));
+        let mut conn = SocketConnection::connect_with_config(
+            listener.local_addr().unwrap(),
+            config,
+            reactor.controller(),
+        )
+        .unwrap();
+        assert!(conn.deadline().unwrap() <= time::Instant::now());
+        let err = conn.on_deadline().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        // Once the connection is established the deadline does not apply
+        conn.io_ready(writable()).unwrap();
+        assert!(conn.is_connected());
+        assert_eq!(conn.deadline(), None);
+        conn.on_deadline().unwrap();
+        reactor.shutdown().unwrap();
+    }
+}
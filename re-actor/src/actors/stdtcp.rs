@@ -1,53 +1,250 @@
 use std::collections::VecDeque;
-use std::io::{self, Read, Write};
-use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::io::{self, IoSlice, IoSliceMut, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
 use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, AsSocket, BorrowedSocket, RawSocket};
 
-use crate::actors::IoEv;
+use crate::actors::{AsRawSource, DisconnectReason, IoEv, RawSource};
 use crate::{Actor, Controller, Layout, ReactorApi};
 
+/// Default size of the [`TcpConnection`] write queue above which
+/// [`WriteEvent::HighWatermark`] is reported.
+pub const DEFAULT_HIGH_WATERMARK: usize = 1024 * 1024;
+
+/// Maximal number of the queued buffers written out with a single system
+/// call; matches `IOV_MAX` on Linux.
+const MAX_IOVECS: usize = 1024;
+
 pub enum TcpAction {
     Accept(TcpStream, SocketAddr),
     Connect(SocketAddr),
 }
 
+/// Notifications about the state of the [`TcpConnection`] write queue, which
+/// allow the protocol layer to implement backpressure. Retrieved with
+/// [`TcpConnection::next_write_event`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display(doc_comments)]
+pub enum WriteEvent {
+    /// write queue has grown above the high watermark
+    HighWatermark,
+
+    /// all queued data were written to the socket
+    Flushed,
+}
+
+/// TCP connection actor.
+///
+/// The connection supports half-closing: its writing half can be shut down
+/// with [`TcpConnection::shutdown_write`] (or [`ReactorApi::half_close`])
+/// while the data from the remote peer are still received. Once the remote
+/// peer shuts down its writing half, the connection stops reading and reports
+/// [`TcpConnection::is_remote_closed`], leaving it to the application to
+/// complete writing and to disconnect. Schedulers which detect hang-ups
+/// report the remote half-close as a hang-up, after which the connection is
+/// removed from the re-actor.
+///
+/// Data queued for sending are kept as separate buffers, which are written
+/// out with a single vectored write (`writev` on Unix) once the socket
+/// becomes writable. Protocols sending header and body in separate buffers
+/// may queue them with [`TcpConnection::queue_send_vectored`] without
+/// concatenating them first.
 pub struct TcpConnection<L: Layout> {
     stream: TcpStream,
     queue: VecDeque<u8>,
+    outbox: VecDeque<Vec<u8>>,
+    /// Number of bytes of the first [`TcpConnection::outbox`] buffer which are
+    /// already written.
+    outbox_pos: usize,
+    /// Number of bytes in [`TcpConnection::outbox`] which are not written yet.
+    outbox_len: usize,
     read_buf: Vec<u8>,
+    high_watermark: usize,
+    is_congested: bool,
+    write_events: VecDeque<WriteEvent>,
+    is_remote_closed: bool,
+    /// Whether the writing half is requested to be shut down once the write
+    /// queue is flushed.
+    is_write_closing: bool,
+    is_write_closed: bool,
     pub(super) controller: Controller<L>,
     pub(super) is_inbound: bool,
 }
 
 impl<L: Layout> TcpConnection<L> {
     pub fn connect(addr: impl ToSocketAddrs, controller: Controller<L>) -> io::Result<Self> {
-        let read_buf = Vec::with_capacity(u16::MAX as usize);
+        let read_buf = vec![0u8; u16::MAX as usize];
+
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nonblocking(true)?;
 
         Ok(Self {
-            stream: TcpStream::connect(addr)?,
+            stream,
             queue: empty!(),
+            outbox: empty!(),
+            outbox_pos: 0,
+            outbox_len: 0,
             read_buf,
+            high_watermark: DEFAULT_HIGH_WATERMARK,
+            is_congested: false,
+            write_events: empty!(),
+            is_remote_closed: false,
+            is_write_closing: false,
+            is_write_closed: false,
             controller,
             is_inbound: false,
         })
     }
 
     pub fn accept(stream: TcpStream, controller: Controller<L>) -> io::Result<Self> {
-        let read_buf = Vec::with_capacity(u16::MAX as usize);
+        let read_buf = vec![0u8; u16::MAX as usize];
+
+        stream.set_nonblocking(true)?;
 
         Ok(Self {
             stream,
             queue: empty!(),
+            outbox: empty!(),
+            outbox_pos: 0,
+            outbox_len: 0,
             read_buf,
+            high_watermark: DEFAULT_HIGH_WATERMARK,
+            is_congested: false,
+            write_events: empty!(),
+            is_remote_closed: false,
+            is_write_closing: false,
+            is_write_closed: false,
             controller,
             is_inbound: true,
         })
     }
+
+    /// Sets size of the write queue above which
+    /// [`WriteEvent::HighWatermark`] is reported.
+    pub fn with_high_watermark(mut self, high_watermark: usize) -> Self {
+        self.high_watermark = high_watermark;
+        self
+    }
+
+    /// Queues data for sending, writing out as much of the queue as the
+    /// socket accepts without blocking. The rest is written once the socket
+    /// becomes writable.
+    pub fn queue_send(&mut self, data: &[u8]) -> io::Result<()> {
+        self.queue_send_vectored(&[IoSlice::new(data)])
+    }
+
+    /// Queues data from several buffers for sending, writing out as much of
+    /// them as the socket accepts with a single vectored write. The rest is
+    /// written once the socket becomes writable.
+    pub fn queue_send_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<()> {
+        if self.is_write_closing || self.is_write_closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        let mut written = 0;
+        if self.outbox.is_empty() {
+            written = match self.stream.write_vectored(bufs) {
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => 0,
+                Err(err) => return Err(err),
+            };
+        }
+        // Preserving ordering: new data go after the pending ones, which are
+        // written once the socket becomes writable
+        for buf in bufs {
+            if written >= buf.len() {
+                written -= buf.len();
+                continue;
+            }
+            self.outbox.push_back(buf[written..].to_vec());
+            self.outbox_len += buf.len() - written;
+            written = 0;
+        }
+        if !self.is_congested && self.outbox_len > self.high_watermark {
+            self.is_congested = true;
+            self.write_events.push_back(WriteEvent::HighWatermark);
+        }
+        Ok(())
+    }
+
+    /// Returns number of bytes queued for sending.
+    pub fn queued_len(&self) -> usize {
+        self.outbox_len
+    }
+
+    /// Shuts down the writing half of the connection once all queued data
+    /// are written, so the remote peer receives end of stream while the
+    /// connection still can receive data.
+    pub fn shutdown_write(&mut self) -> io::Result<()> {
+        self.is_write_closing = true;
+        self.write_outbox()
+    }
+
+    /// Detects whether the remote peer has shut down its writing half of the
+    /// connection, such that no more data will be received.
+    pub fn is_remote_closed(&self) -> bool {
+        self.is_remote_closed
+    }
+
+    /// Returns the oldest of the write queue notifications, if any.
+    pub fn next_write_event(&mut self) -> Option<WriteEvent> {
+        self.write_events.pop_front()
+    }
+
+    /// Writes out as much of the queued data as the socket accepts without
+    /// blocking, reporting [`WriteEvent::Flushed`] once the data which were
+    /// left pending by the previous writes are written out.
+    ///
+    /// Up to [`MAX_IOVECS`] queued buffers are written with each system call.
+    fn write_outbox(&mut self) -> io::Result<()> {
+        let was_pending = !self.outbox.is_empty();
+        while !self.outbox.is_empty() {
+            let mut iovecs = [IoSlice::new(&[]); MAX_IOVECS];
+            let mut count = 0;
+            for (iovec, buf) in iovecs.iter_mut().zip(&self.outbox) {
+                let offset = if count == 0 { self.outbox_pos } else { 0 };
+                *iovec = IoSlice::new(&buf[offset..]);
+                count += 1;
+            }
+            match self.stream.write_vectored(&iovecs[..count]) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(len) => self.consume_outbox(len),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        if was_pending && self.outbox.is_empty() {
+            self.is_congested = false;
+            self.write_events.push_back(WriteEvent::Flushed);
+        }
+        if self.is_write_closing && self.outbox.is_empty() {
+            self.is_write_closing = false;
+            self.is_write_closed = true;
+            self.stream.shutdown(Shutdown::Write)?;
+        }
+        Ok(())
+    }
+
+    /// Removes `len` written bytes from the front of the write queue.
+    fn consume_outbox(&mut self, mut len: usize) {
+        self.outbox_len -= len;
+        while let Some(buf) = self.outbox.front() {
+            let left = buf.len() - self.outbox_pos;
+            if len < left {
+                self.outbox_pos += len;
+                break;
+            }
+            len -= left;
+            self.outbox_pos = 0;
+            self.outbox.pop_front();
+        }
+    }
 }
 
 impl<L: Layout> Actor for TcpConnection<L> {
     type Layout = L;
-    type Id = RawFd;
+    type Id = RawSource;
     type Context = TcpAction;
     type Cmd = Vec<u8>;
     type Error = io::Error;
@@ -66,65 +263,158 @@ impl<L: Layout> Actor for TcpConnection<L> {
     }
 
     fn id(&self) -> Self::Id {
-        self.stream.as_raw_fd()
+        self.stream.as_raw_source()
     }
 
     fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+        if io.is_error {
+            let err = self
+                .stream
+                .take_error()?
+                .unwrap_or_else(|| io::ErrorKind::Other.into());
+            return Err(DisconnectReason::ConnectionError(err).into());
+        }
         if io.is_readable {
-            self.flush()?;
+            // Socket is read until it is drained, so no data is left unread
+            // with the edge-triggered schedulers
+            loop {
+                match self.stream.read(&mut self.read_buf) {
+                    Ok(0) => {
+                        // Remote peer may still receive the data we send
+                        self.is_remote_closed = true;
+                        break;
+                    }
+                    Ok(len) => self.queue.extend(&self.read_buf[..len]),
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => return Err(err),
+                }
+            }
         }
         if io.is_writable {
-            let len = self.stream.read(&mut self.read_buf)?;
-            self.queue.extend(&self.read_buf[..len]);
+            self.write_outbox()?;
+        }
+        if io.is_hangup {
+            return Err(DisconnectReason::Hangup.into());
         }
         Ok(())
     }
 
     fn handle_cmd(&mut self, data: Self::Cmd) -> Result<(), Self::Error> {
-        self.write_all(&data)
+        self.queue_send(&data)
     }
 
     fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
         Err(err)
     }
+
+    fn has_pending_output(&self) -> bool {
+        !self.outbox.is_empty()
+    }
+
+    fn shutdown_write(&mut self) -> Result<(), Self::Error> {
+        TcpConnection::shutdown_write(self)
+    }
+
+    fn interests(&self) -> IoEv {
+        IoEv {
+            // End of stream is reported by the socket as readable forever
+            is_readable: !self.is_remote_closed,
+            is_writable: self.has_pending_output(),
+            is_hangup: false,
+            is_error: false,
+        }
+    }
+
+    fn disconnect(&mut self) -> Result<(), Self::Error> {
+        self.write_outbox()?;
+        match self.stream.shutdown(Shutdown::Both) {
+            // Both halves of the connection are already closed
+            Err(err) if err.kind() == io::ErrorKind::NotConnected => Ok(()),
+            res => res,
+        }
+    }
 }
 
 impl<L: Layout> Read for TcpConnection<L> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         self.queue.read(buf)
     }
+
+    /// Fills the buffers one after another with the received data, so a
+    /// message header and body can be read into separate buffers at once.
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> io::Result<usize> {
+        let mut total = 0;
+        for buf in bufs {
+            let len = self.queue.read(buf)?;
+            total += len;
+            if len < buf.len() {
+                break;
+            }
+        }
+        Ok(total)
+    }
 }
 
 impl<L: Layout> Write for TcpConnection<L> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.stream.write(buf)
+        // Data are queued, so the writes never block the re-actor
+        self.queue_send(buf)?;
+        Ok(buf.len())
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> io::Result<usize> {
+        self.queue_send_vectored(bufs)?;
+        Ok(bufs.iter().map(|buf| buf.len()).sum())
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.stream.flush()
+        self.write_outbox()
     }
 }
 
+#[cfg(unix)]
 impl<L: Layout> AsRawFd for TcpConnection<L> {
     fn as_raw_fd(&self) -> RawFd {
         self.stream.as_raw_fd()
     }
 }
 
+#[cfg(unix)]
 impl<L: Layout> AsFd for TcpConnection<L> {
     fn as_fd(&self) -> BorrowedFd<'_> {
         self.stream.as_fd()
     }
 }
 
+#[cfg(windows)]
+impl<L: Layout> AsRawSocket for TcpConnection<L> {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.stream.as_raw_socket()
+    }
+}
+
+#[cfg(windows)]
+impl<L: Layout> AsSocket for TcpConnection<L> {
+    fn as_socket(&self) -> BorrowedSocket<'_> {
+        self.stream.as_socket()
+    }
+}
+
 pub struct TcpSpawner<L: Layout, const SESSION_POOL_ID: u32> {
     socket: TcpListener,
     controller: Controller<L>,
 }
 
+impl<L: Layout, const SESSION_POOL_ID: u32> TcpSpawner<L, SESSION_POOL_ID> {
+    /// Returns address the listening socket is bound to.
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+}
+
 impl<L: Layout, const SESSION_POOL_ID: u32> Actor for TcpSpawner<L, SESSION_POOL_ID> {
     type Layout = L;
-    type Id = RawFd;
+    type Id = RawSource;
     type Context = SocketAddr;
     type Cmd = ();
     type Error = io::Error;
@@ -139,17 +429,27 @@ impl<L: Layout, const SESSION_POOL_ID: u32> Actor for TcpSpawner<L, SESSION_POOL
     }
 
     fn id(&self) -> Self::Id {
-        self.socket.as_raw_fd()
+        self.socket.as_raw_source()
     }
 
-    fn io_ready(&mut self, _: IoEv) -> Result<(), Self::Error> {
-        let (stream, peer_socket_addr) = self.socket.accept()?;
-        let action = TcpAction::Accept(stream, peer_socket_addr);
-        let ctx = L::convert(Box::new(action));
-        self.controller
-            .start_actor(SESSION_POOL_ID.into(), ctx)
-            .map_err(|_| io::ErrorKind::NotConnected)?;
-        Ok(())
+    fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+        if !io.is_readable {
+            return Ok(());
+        }
+        // All pending connections are accepted, since a single event may be
+        // reported for several of them
+        loop {
+            let (stream, peer_socket_addr) = match self.socket.accept() {
+                Ok(accepted) => accepted,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            let action = TcpAction::Accept(stream, peer_socket_addr);
+            let ctx = L::convert(Box::new(action));
+            self.controller
+                .start_actor(SESSION_POOL_ID.into(), ctx)
+                .map_err(|_| io::ErrorKind::NotConnected)?;
+        }
     }
 
     fn handle_cmd(&mut self, _cmd: Self::Cmd) -> Result<(), Self::Error> {
@@ -161,16 +461,434 @@ impl<L: Layout, const SESSION_POOL_ID: u32> Actor for TcpSpawner<L, SESSION_POOL
         // Listener does not know how to handle errors, so it just propagates them
         Err(err)
     }
+
+    fn interests(&self) -> IoEv {
+        // Pending connections are signalled by the listening socket being
+        // readable
+        IoEv {
+            is_readable: true,
+            is_writable: false,
+            is_hangup: false,
+            is_error: false,
+        }
+    }
+
+    fn disconnect(&mut self) -> Result<(), Self::Error> {
+        // Listening socket is closed once the actor is dropped
+        Ok(())
+    }
 }
 
+#[cfg(unix)]
 impl<L: Layout, const SESSION_POOL_ID: u32> AsRawFd for TcpSpawner<L, SESSION_POOL_ID> {
     fn as_raw_fd(&self) -> RawFd {
         self.socket.as_raw_fd()
     }
 }
 
+#[cfg(unix)]
 impl<L: Layout, const SESSION_POOL_ID: u32> AsFd for TcpSpawner<L, SESSION_POOL_ID> {
     fn as_fd(&self) -> BorrowedFd<'_> {
         self.socket.as_fd()
     }
 }
+
+#[cfg(windows)]
+impl<L: Layout, const SESSION_POOL_ID: u32> AsRawSocket for TcpSpawner<L, SESSION_POOL_ID> {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.socket.as_raw_socket()
+    }
+}
+
+#[cfg(windows)]
+impl<L: Layout, const SESSION_POOL_ID: u32> AsSocket for TcpSpawner<L, SESSION_POOL_ID> {
+    fn as_socket(&self) -> BorrowedSocket<'_> {
+        self.socket.as_socket()
+    }
+}
+
+#[cfg(all(test, unix, feature = "polling"))]
+mod tests {
+    use std::any::Any;
+    use std::os::unix::io::AsRawFd;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Mutex, MutexGuard, PoisonError};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::reactor::tests::reactor;
+    use crate::schedulers::PollingScheduler;
+    use crate::{Handler, InternalError, Pool, Reactor};
+
+    /// Number of the connections started by the [`SessionPool`] re-actors.
+    static CONNECTED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Number of the connections disconnected by the [`SessionPool`] re-actors.
+    static DISCONNECTED: AtomicUsize = AtomicUsize::new(0);
+
+    /// Lock serializing the tests which run [`SessionPool`] re-actors.
+    static SESSIONS: Mutex<()> = Mutex::new(());
+
+    /// Serializes the tests running [`SessionPool`] re-actors, since they share
+    /// the connection counters, and resets the counters.
+    fn sessions() -> MutexGuard<'static, ()> {
+        let guard = SESSIONS.lock().unwrap_or_else(PoisonError::into_inner);
+        CONNECTED.store(0, Ordering::SeqCst);
+        DISCONNECTED.store(0, Ordering::SeqCst);
+        guard
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+    #[display(Debug)]
+    enum SessionPool {
+        Sessions,
+    }
+
+    impl From<u32> for SessionPool {
+        fn from(_: u32) -> Self {
+            SessionPool::Sessions
+        }
+    }
+
+    impl From<SessionPool> for u32 {
+        fn from(_: SessionPool) -> Self {
+            0
+        }
+    }
+
+    impl Layout for SessionPool {
+        type RootActor = TcpConnection<SessionPool>;
+
+        fn default_pools() -> Vec<Pool<Self::RootActor, Self>> {
+            vec![Pool::new(
+                SessionPool::Sessions,
+                PollingScheduler::new().expect("unable to construct scheduler"),
+                ConnectCounter,
+            )]
+        }
+
+        fn convert(ctx: Box<dyn Any>) -> TcpAction {
+            *ctx.downcast().expect("spawner provides TCP actions")
+        }
+    }
+
+    /// Handler counting started connections in [`CONNECTED`] and disconnected
+    /// ones in [`DISCONNECTED`].
+    struct ConnectCounter;
+
+    impl Handler<SessionPool> for ConnectCounter {
+        fn handle_err(&mut self, err: InternalError<SessionPool>) {
+            panic!("unexpected re-actor error: {err}")
+        }
+
+        fn on_connect(&mut self, _id: &RawSource) {
+            CONNECTED.fetch_add(1, Ordering::SeqCst);
+        }
+
+        fn on_disconnect(&mut self, _id: &RawSource, _reason: &DisconnectReason) {
+            DISCONNECTED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    /// Size of the socket send buffer, which is shrunk to make partial writes
+    /// happen with the small amounts of data.
+    const SEND_BUF: libc::c_int = 4096;
+
+    fn shrink_send_buf(fd: RawFd) {
+        let res = unsafe {
+            libc::setsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_SNDBUF,
+                &SEND_BUF as *const libc::c_int as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            )
+        };
+        assert_eq!(res, 0, "unable to set socket buffer size");
+    }
+
+    #[test]
+    fn partial_writes_are_queued() {
+        let (mut reactor, _) = reactor();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        shrink_send_buf(client.as_raw_fd());
+
+        let mut conn = TcpConnection::accept(client, reactor.controller())
+            .unwrap()
+            .with_high_watermark(64 * 1024);
+        let data = (0..1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+        conn.queue_send(&data).unwrap();
+        assert!(conn.has_pending_output());
+        assert!(conn.interests().is_writable);
+        assert_eq!(conn.next_write_event(), Some(WriteEvent::HighWatermark));
+        assert_eq!(conn.next_write_event(), None);
+
+        let len = data.len();
+        let reader = thread::spawn(move || {
+            let mut server = server;
+            let mut received = vec![0u8; len];
+            server.read_exact(&mut received).unwrap();
+            received
+        });
+
+        let writable = IoEv {
+            is_readable: false,
+            is_writable: true,
+            is_hangup: false,
+            is_error: false,
+        };
+        let start = Instant::now();
+        while conn.has_pending_output() {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "queue is not drained"
+            );
+            conn.io_ready(writable).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(conn.queued_len(), 0);
+        assert!(!conn.interests().is_writable);
+        assert_eq!(conn.next_write_event(), Some(WriteEvent::Flushed));
+        assert_eq!(conn.next_write_event(), None);
+        assert_eq!(reader.join().unwrap(), data);
+
+        reactor.shutdown().unwrap();
+    }
+
+    #[test]
+    fn vectored_io() {
+        const MESSAGES: usize = 1000;
+
+        let (mut reactor, _) = reactor();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        shrink_send_buf(client.as_raw_fd());
+
+        let mut conn = TcpConnection::accept(client, reactor.controller()).unwrap();
+        let header = [0xFFu8; 64];
+        let body = (0..1024).map(|i| i as u8).collect::<Vec<_>>();
+        for _ in 0..MESSAGES {
+            conn.queue_send_vectored(&[IoSlice::new(&header), IoSlice::new(&body)])
+                .unwrap();
+        }
+        // Sending buffer can't take all the data, so the rest is queued
+        assert!(conn.has_pending_output());
+
+        let echo = thread::spawn(move || {
+            let mut server = server;
+            let mut received = vec![0u8; MESSAGES * (64 + 1024)];
+            server.read_exact(&mut received).unwrap();
+            server.write_all(&received[..64 + 1024]).unwrap();
+            received
+        });
+
+        let io = IoEv {
+            is_readable: true,
+            is_writable: true,
+            is_hangup: false,
+            is_error: false,
+        };
+        let start = Instant::now();
+        while conn.has_pending_output() || conn.queue.len() < 64 + 1024 {
+            assert!(start.elapsed() < Duration::from_secs(10), "no echo");
+            conn.io_ready(io).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(conn.queued_len(), 0);
+        let received = echo.join().unwrap();
+        for message in received.chunks(64 + 1024) {
+            assert_eq!(&message[..64], header);
+            assert_eq!(&message[64..], body);
+        }
+
+        // Echoed message is split between the header and body buffers
+        let mut echoed_header = [0u8; 64];
+        let mut echoed_body = [0u8; 1024];
+        let len = conn
+            .read_vectored(&mut [
+                IoSliceMut::new(&mut echoed_header),
+                IoSliceMut::new(&mut echoed_body),
+            ])
+            .unwrap();
+        assert_eq!(len, 64 + 1024);
+        assert_eq!(echoed_header, header);
+        assert_eq!(&echoed_body[..], body);
+
+        reactor.shutdown().unwrap();
+    }
+
+    #[test]
+    fn all_pending_connections_are_accepted() {
+        const CLIENTS: usize = 10;
+
+        let _sessions = sessions();
+        let mut reactor = Reactor::<SessionPool>::new().unwrap();
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let mut spawner = TcpSpawner::<SessionPool, 0>::with(addr, reactor.controller()).unwrap();
+        assert!(!spawner.interests().is_writable);
+
+        let addr = spawner.local_addr().unwrap();
+        let clients = (0..CLIENTS)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect::<Vec<_>>();
+
+        // Listener is not interested in the write events
+        let writable = IoEv {
+            is_readable: false,
+            is_writable: true,
+            is_hangup: false,
+            is_error: false,
+        };
+        spawner.io_ready(writable).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(CONNECTED.load(Ordering::SeqCst), 0);
+
+        // Single readable event accepts all the connections which are pending
+        let readable = IoEv {
+            is_readable: true,
+            is_writable: false,
+            is_hangup: false,
+            is_error: false,
+        };
+        spawner.io_ready(readable).unwrap();
+        let start = Instant::now();
+        while CONNECTED.load(Ordering::SeqCst) < CLIENTS {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "not all connections are accepted"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(CONNECTED.load(Ordering::SeqCst), CLIENTS);
+
+        reactor.shutdown().unwrap();
+        drop(clients);
+    }
+
+    #[test]
+    fn gracefully_stopped_connection_delivers_pending_data() {
+        let _sessions = sessions();
+        let mut reactor = Reactor::<SessionPool>::new().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        shrink_send_buf(server.as_raw_fd());
+        let conn = TcpConnection::accept(server, reactor.controller()).unwrap();
+        let id = conn.id();
+        reactor.insert_actor(SessionPool::Sessions, conn).unwrap();
+        while reactor.controller().pool_for(id).is_err() {
+            thread::sleep(Duration::from_millis(1));
+        }
+
+        // The data can't be written out until the client starts reading
+        let data = (0..1024 * 1024).map(|i| i as u8).collect::<Vec<_>>();
+        reactor.send(id, data.clone()).unwrap();
+        reactor
+            .stop_actor_gracefully(id, Duration::from_secs(10))
+            .unwrap();
+        assert!(matches!(
+            reactor.send(id, b"late".to_vec()),
+            Err(InternalError::ActorDraining(_))
+        ));
+
+        let mut received = vec![];
+        client.read_to_end(&mut received).unwrap();
+        assert_eq!(received.len(), data.len());
+        assert_eq!(received, data);
+        reactor.shutdown().unwrap();
+    }
+
+    #[test]
+    fn shutdown_disconnects_accepted_connections() {
+        const CLIENTS: usize = 3;
+
+        let _sessions = sessions();
+        let mut reactor = Reactor::<SessionPool>::new().unwrap();
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let mut spawner = TcpSpawner::<SessionPool, 0>::with(addr, reactor.controller()).unwrap();
+        let addr = spawner.local_addr().unwrap();
+        let mut clients = (0..CLIENTS)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect::<Vec<_>>();
+
+        let readable = IoEv {
+            is_readable: true,
+            is_writable: false,
+            is_hangup: false,
+            is_error: false,
+        };
+        spawner.io_ready(readable).unwrap();
+        let start = Instant::now();
+        while CONNECTED.load(Ordering::SeqCst) < CLIENTS {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "not all connections are accepted"
+            );
+            thread::sleep(Duration::from_millis(1));
+        }
+        reactor.broadcast(b"bye".to_vec()).unwrap();
+
+        reactor.shutdown().unwrap();
+        assert_eq!(DISCONNECTED.load(Ordering::SeqCst), CLIENTS);
+        // Queued data are delivered before the connections get closed
+        for client in &mut clients {
+            client
+                .set_read_timeout(Some(Duration::from_secs(1)))
+                .unwrap();
+            let mut received = vec![];
+            client.read_to_end(&mut received).unwrap();
+            assert_eq!(received, b"bye");
+        }
+        spawner.disconnect().unwrap();
+    }
+
+    #[test]
+    fn half_closed_exchange() {
+        let (mut reactor, _) = reactor();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut conn =
+            TcpConnection::connect(listener.local_addr().unwrap(), reactor.controller()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let server = thread::spawn(move || {
+            let mut server = server;
+            // Client signals the end of the request by half-closing the
+            // connection
+            let mut request = vec![];
+            server.read_to_end(&mut request).unwrap();
+            assert_eq!(request, b"GET / HTTP/1.0\r\n\r\n");
+            server.write_all(b"HTTP/1.0 200 OK\r\n\r\nhello").unwrap();
+        });
+
+        conn.queue_send(b"GET / HTTP/1.0\r\n\r\n").unwrap();
+        conn.shutdown_write().unwrap();
+        assert!(conn.queue_send(b"more").is_err());
+
+        let readable = IoEv {
+            is_readable: true,
+            is_writable: false,
+            is_hangup: false,
+            is_error: false,
+        };
+        let start = Instant::now();
+        while !conn.is_remote_closed() {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "response is not received"
+            );
+            conn.io_ready(readable).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!conn.interests().is_readable);
+        let mut response = vec![];
+        conn.read_to_end(&mut response).unwrap();
+        assert_eq!(response, b"HTTP/1.0 200 OK\r\n\r\nhello");
+
+        server.join().unwrap();
+        conn.disconnect().unwrap();
+        reactor.shutdown().unwrap();
+    }
+}
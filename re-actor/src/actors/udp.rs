@@ -0,0 +1,477 @@
+use std::collections::VecDeque;
+use std::io;
+use std::net::{self, SocketAddr};
+#[cfg(unix)]
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsRawSocket, AsSocket, BorrowedSocket, RawSocket};
+
+use crate::actors::{AsRawSource, DisconnectReason, IoEv, RawSource};
+use crate::{Actor, Controller, Layout};
+
+/// Default maximal number of datagrams in the outbound queue of a
+/// [`UdpSocket`].
+pub const DEFAULT_OUTBOX_CAPACITY: usize = 1024;
+
+/// Maximal size of a UDP datagram payload.
+pub const MAX_DATAGRAM_LEN: usize = u16::MAX as usize;
+
+/// Local address of a [`UdpSocket`]. Since UDP is connectionless, there is no
+/// distinction between listening and connected sockets: unless connected with
+/// [`UdpAction::Connect`], the same socket is used for sending datagrams to and
+/// receiving them from any peer.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display("{0}")]
+pub struct UdpLocator(pub SocketAddr);
+
+/// Context for constructing [`UdpSocket`].
+pub enum UdpAction {
+    /// Bind socket exchanging datagrams with any peer.
+    Bind(UdpLocator),
+
+    /// Bind socket and connect it to a single peer, such that the datagrams
+    /// from other peers are ignored by the OS and the peer being unreachable
+    /// is reported as an error.
+    Connect(UdpLocator, SocketAddr),
+}
+
+/// Datagram received by a [`UdpSocket`].
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct Datagram {
+    /// Address of the datagram sender.
+    pub from: SocketAddr,
+    /// Datagram payload.
+    pub data: Vec<u8>,
+    /// Whether the datagram was larger than the maximal datagram size of the
+    /// socket (see [`UdpSocket::with_max_datagram_len`]), such that its
+    /// payload is truncated.
+    pub is_truncated: bool,
+}
+
+pub struct UdpSocket<L: Layout> {
+    socket: net::UdpSocket,
+    peer: Option<SocketAddr>,
+    inbox: VecDeque<Datagram>,
+    outbox: VecDeque<(SocketAddr, Vec<u8>)>,
+    outbox_capacity: usize,
+    /// Read buffer is one byte larger than the maximal datagram size, which
+    /// allows to detect truncated datagrams.
+    read_buf: Vec<u8>,
+    pub(super) controller: Controller<L>,
+}
+
+impl<L: Layout> UdpSocket<L> {
+    pub fn bind(locator: UdpLocator, controller: Controller<L>) -> io::Result<Self> {
+        let socket = net::UdpSocket::bind(locator.0)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            peer: None,
+            inbox: empty!(),
+            outbox: empty!(),
+            outbox_capacity: DEFAULT_OUTBOX_CAPACITY,
+            read_buf: vec![0u8; MAX_DATAGRAM_LEN + 1],
+            controller,
+        })
+    }
+
+    /// Binds socket and connects it to the `peer`.
+    pub fn connect(
+        locator: UdpLocator,
+        peer: SocketAddr,
+        controller: Controller<L>,
+    ) -> io::Result<Self> {
+        let mut socket = Self::bind(locator, controller)?;
+        socket.socket.connect(peer)?;
+        socket.peer = Some(peer);
+        Ok(socket)
+    }
+
+    /// Sets maximal number of datagrams in the outbound queue, above which
+    /// new datagrams are refused.
+    pub fn with_outbox_capacity(mut self, capacity: usize) -> Self {
+        self.outbox_capacity = capacity;
+        self
+    }
+
+    /// Sets size of the datagrams above which they are truncated when
+    /// received (and reported with [`Datagram::is_truncated`] flag).
+    pub fn with_max_datagram_len(mut self, len: usize) -> Self {
+        self.read_buf = vec![0u8; len.min(MAX_DATAGRAM_LEN) + 1];
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Returns address of the peer the socket is connected to, if any.
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer
+    }
+
+    /// Queues datagram for sending, sending out as many of the queued
+    /// datagrams as the socket accepts without blocking.
+    ///
+    /// # Errors
+    ///
+    /// If the outbound queue is full (with [`io::ErrorKind::WouldBlock`]) or
+    /// if sending fails.
+    pub fn send_datagram(&mut self, to: SocketAddr, data: Vec<u8>) -> io::Result<()> {
+        if self.outbox.len() >= self.outbox_capacity {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "outbound datagram queue is full",
+            ));
+        }
+        self.outbox.push_back((to, data));
+        self.write_outbox()
+    }
+
+    /// Returns the oldest of the received datagrams, if any.
+    pub fn recv_datagram(&mut self) -> Option<Datagram> {
+        self.inbox.pop_front()
+    }
+
+    /// Sends out as many of the queued datagrams as the socket accepts without
+    /// blocking.
+    fn write_outbox(&mut self) -> io::Result<()> {
+        while let Some((addr, data)) = self.outbox.front() {
+            let res = match self.peer {
+                Some(peer) if peer == *addr => self.socket.send(data),
+                _ => self.socket.send_to(data, addr),
+            };
+            match res {
+                Ok(_) => {
+                    self.outbox.pop_front();
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(self.map_err(err)),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reports the connected peer being unreachable as a connection error.
+    fn map_err(&self, err: io::Error) -> io::Error {
+        if self.peer.is_some() && err.kind() == io::ErrorKind::ConnectionRefused {
+            return DisconnectReason::ConnectionError(err).into();
+        }
+        err
+    }
+}
+
+impl<L: Layout> Actor for UdpSocket<L> {
+    type Layout = L;
+    type Id = RawSource;
+    type Context = UdpAction;
+    type Cmd = (SocketAddr, Vec<u8>);
+    type Error = io::Error;
+
+    fn with(
+        context: Self::Context,
+        controller: Controller<Self::Layout>,
+    ) -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        match context {
+            UdpAction::Bind(locator) => Self::bind(locator, controller),
+            UdpAction::Connect(locator, peer) => Self::connect(locator, peer, controller),
+        }
+    }
+
+    fn id(&self) -> Self::Id {
+        self.socket.as_raw_source()
+    }
+
+    fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+        if io.is_readable {
+            loop {
+                match self.socket.recv_from(&mut self.read_buf) {
+                    Ok((len, from)) => {
+                        let is_truncated = len == self.read_buf.len();
+                        let len = len.min(self.read_buf.len() - 1);
+                        self.inbox.push_back(Datagram {
+                            from,
+                            data: self.read_buf[..len].to_vec(),
+                            is_truncated,
+                        })
+                    }
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => return Err(self.map_err(err)),
+                }
+            }
+        }
+        if io.is_writable {
+            self.write_outbox()?;
+        }
+        Ok(())
+    }
+
+    fn handle_cmd(&mut self, (to, data): Self::Cmd) -> Result<(), Self::Error> {
+        self.send_datagram(to, data)
+    }
+
+    fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+        Err(err)
+    }
+
+    fn has_pending_output(&self) -> bool {
+        !self.outbox.is_empty()
+    }
+
+    fn interests(&self) -> IoEv {
+        IoEv {
+            is_readable: true,
+            is_writable: self.has_pending_output(),
+            is_hangup: false,
+            is_error: false,
+        }
+    }
+
+    fn disconnect(&mut self) -> Result<(), Self::Error> {
+        // There is no connection to shut down; the socket is closed once the
+        // actor is dropped
+        self.write_outbox()
+    }
+}
+
+#[cfg(unix)]
+impl<L: Layout> AsRawFd for UdpSocket<L> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+#[cfg(unix)]
+impl<L: Layout> AsFd for UdpSocket<L> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.socket.as_fd()
+    }
+}
+
+#[cfg(windows)]
+impl<L: Layout> AsRawSocket for UdpSocket<L> {
+    fn as_raw_socket(&self) -> RawSocket {
+        self.socket.as_raw_socket()
+    }
+}
+
+#[cfg(windows)]
+impl<L: Layout> AsSocket for UdpSocket<L> {
+    fn as_socket(&self) -> BorrowedSocket<'_> {
+        self.socket.as_socket()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reactor::tests::{reactor, TestPool};
+
+    fn socket(controller: Controller<TestPool>) -> UdpSocket<TestPool> {
+        UdpSocket::bind(UdpLocator("127.0.0.1:0".parse().unwrap()), controller).unwrap()
+    }
+
+    fn readable() -> IoEv {
+        IoEv {
+            is_readable: true,
+            is_writable: false,
+            is_hangup: false,
+            is_error: false,
+        }
+    }
+
+    /// Reads all datagrams which are already in the socket receive buffer.
+    fn recv_all(socket: &mut UdpSocket<TestPool>, count: usize) -> Vec<Datagram> {
+        let mut datagrams = vec![];
+        for _ in 0..100 {
+            socket.io_ready(readable()).unwrap();
+            while let Some(datagram) = socket.recv_datagram() {
+                datagrams.push(datagram);
+            }
+            if datagrams.len() >= count {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        datagrams
+    }
+
+    #[test]
+    fn full_outbox_refuses_datagrams() {
+        let (mut reactor, _) = reactor();
+        let mut sender = socket(reactor.controller()).with_outbox_capacity(0);
+        let addr = sender.local_addr().unwrap();
+        let err = sender.handle_cmd((addr, vec![1, 2, 3])).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(!sender.has_pending_output());
+    }
+
+    #[test]
+    fn oversized_datagrams_are_truncated() {
+        let (mut reactor, _) = reactor();
+        let mut sender = socket(reactor.controller());
+        let mut receiver = socket(reactor.controller()).with_max_datagram_len(4);
+        let addr = receiver.local_addr().unwrap();
+        sender.handle_cmd((addr, vec![1, 2, 3, 4])).unwrap();
+        sender.handle_cmd((addr, vec![1, 2, 3, 4, 5, 6])).unwrap();
+
+        let datagrams = recv_all(&mut receiver, 2);
+        assert_eq!(datagrams.len(), 2);
+        assert_eq!(datagrams[0].data, vec![1, 2, 3, 4]);
+        assert!(!datagrams[0].is_truncated);
+        assert_eq!(datagrams[1].data, vec![1, 2, 3, 4]);
+        assert!(datagrams[1].is_truncated);
+    }
+
+    #[test]
+    fn connected_socket_exchange() {
+        let (mut reactor, _) = reactor();
+        let mut receiver = socket(reactor.controller());
+        let addr = receiver.local_addr().unwrap();
+        let locator = UdpLocator("127.0.0.1:0".parse().unwrap());
+        let mut sender = UdpSocket::connect(locator, addr, reactor.controller()).unwrap();
+        assert_eq!(sender.peer_addr(), Some(addr));
+        sender.handle_cmd((addr, b"ping".to_vec())).unwrap();
+
+        let datagrams = recv_all(&mut receiver, 1);
+        assert_eq!(datagrams.len(), 1);
+        assert_eq!(datagrams[0].from, sender.local_addr().unwrap());
+        assert_eq!(datagrams[0].data, b"ping");
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn unreachable_peer_is_reported() {
+        let (mut reactor, _) = reactor();
+        // Bind and close a socket to get a local port nobody listens on
+        let addr = socket(reactor.controller()).local_addr().unwrap();
+        let locator = UdpLocator("127.0.0.1:0".parse().unwrap());
+        let mut sender = UdpSocket::connect(locator, addr, reactor.controller()).unwrap();
+        sender.handle_cmd((addr, b"ping".to_vec())).unwrap();
+
+        let mut res = Ok(());
+        for _ in 0..100 {
+            res = sender.io_ready(readable());
+            if res.is_err() {
+                break;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        let err = res.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        let reason = err
+            .into_inner()
+            .unwrap()
+            .downcast::<DisconnectReason>()
+            .unwrap();
+        assert!(matches!(*reason, DisconnectReason::ConnectionError(_)));
+    }
+
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    mod uring {
+        use std::any::Any;
+        use std::collections::HashSet;
+        use std::thread;
+        use std::time::{Duration, Instant};
+
+        use super::super::*;
+        use crate::schedulers::UringScheduler;
+        use crate::{Handler, InternalError, Pool, Reactor, ReactorApi};
+
+        /// Number of datagrams sent by the test.
+        const DATAGRAMS: u32 = 100;
+
+        #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+        #[display(Debug)]
+        enum UdpPool {
+            Main,
+        }
+
+        impl From<u32> for UdpPool {
+            fn from(_: u32) -> Self {
+                UdpPool::Main
+            }
+        }
+
+        impl From<UdpPool> for u32 {
+            fn from(_: UdpPool) -> Self {
+                0
+            }
+        }
+
+        impl Layout for UdpPool {
+            type RootActor = UdpSocket<UdpPool>;
+
+            fn default_pools() -> Vec<Pool<Self::RootActor, Self>> {
+                vec![Pool::new(
+                    UdpPool::Main,
+                    UringScheduler::new().unwrap(),
+                    PanicHandler,
+                )]
+            }
+
+            fn convert(_: Box<dyn Any>) -> UdpAction {
+                unreachable!()
+            }
+        }
+
+        struct PanicHandler;
+
+        impl Handler<UdpPool> for PanicHandler {
+            fn handle_err(&mut self, err: InternalError<UdpPool>) {
+                panic!("unexpected re-actor error: {err}")
+            }
+        }
+
+        #[test]
+        fn datagrams_delivery() {
+            let mut reactor = Reactor::<UdpPool>::new().unwrap();
+            let mut controller = reactor.controller();
+            let locator = UdpLocator("127.0.0.1:0".parse().unwrap());
+
+            let sender = UdpSocket::bind(locator, controller.clone()).unwrap();
+            let receiver = UdpSocket::bind(locator, controller.clone()).unwrap();
+            let (sender_id, sender_addr) = (sender.id(), sender.local_addr().unwrap());
+            let (receiver_id, receiver_addr) = (receiver.id(), receiver.local_addr().unwrap());
+            controller.insert_actor(UdpPool::Main, sender).unwrap();
+            controller.insert_actor(UdpPool::Main, receiver).unwrap();
+            // Queries are processed after the insertion, so the actors are known
+            // to the re-actor once the queries return
+            assert!(controller.contains_actor(&sender_id).unwrap());
+            assert!(controller.contains_actor(&receiver_id).unwrap());
+
+            for no in 0..DATAGRAMS {
+                controller
+                    .send(sender_id, (receiver_addr, no.to_be_bytes().to_vec()))
+                    .unwrap();
+            }
+
+            let start = Instant::now();
+            let mut received = HashSet::new();
+            while received.len() < DATAGRAMS as usize {
+                assert!(
+                    start.elapsed() < Duration::from_secs(1),
+                    "not all datagrams were delivered"
+                );
+                thread::sleep(Duration::from_millis(10));
+                let mut receiver = controller
+                    .take_actor(receiver_id)
+                    .unwrap()
+                    .recv_timeout(Duration::from_secs(1))
+                    .unwrap();
+                while let Some(datagram) = receiver.recv_datagram() {
+                    assert_eq!(datagram.from, sender_addr);
+                    let no = u32::from_be_bytes(datagram.data.try_into().unwrap());
+                    assert!(received.insert(no), "datagram {no} is duplicated");
+                }
+                controller.insert_actor(UdpPool::Main, receiver).unwrap();
+                assert!(controller.contains_actor(&receiver_id).unwrap());
+            }
+            assert_eq!(received, (0..DATAGRAMS).collect());
+            reactor.shutdown().unwrap();
+        }
+    }
+}
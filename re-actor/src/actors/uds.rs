@@ -0,0 +1,399 @@
+use std::collections::VecDeque;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::Shutdown;
+use std::os::unix::io::{AsFd, AsRawFd, BorrowedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+
+use crate::actors::IoEv;
+use crate::{Actor, Controller, Layout, ReactorApi};
+
+/// Address of a Unix domain socket.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum UnixLocator {
+    /// Socket bound to a file system path.
+    Path(PathBuf),
+
+    /// Socket bound to a name in the abstract namespace, not related to the
+    /// file system.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Abstract(Vec<u8>),
+}
+
+impl UnixLocator {
+    fn connect(&self) -> io::Result<UnixStream> {
+        match self {
+            UnixLocator::Path(path) => UnixStream::connect(path),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            UnixLocator::Abstract(name) => UnixStream::connect_addr(&abstract_addr(name)?),
+        }
+    }
+
+    fn bind(&self) -> io::Result<UnixListener> {
+        match self {
+            UnixLocator::Path(path) => UnixListener::bind(path),
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            UnixLocator::Abstract(name) => UnixListener::bind_addr(&abstract_addr(name)?),
+        }
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn abstract_addr(name: &[u8]) -> io::Result<std::os::unix::net::SocketAddr> {
+    #[cfg(target_os = "android")]
+    use std::os::android::net::SocketAddrExt;
+    #[cfg(target_os = "linux")]
+    use std::os::linux::net::SocketAddrExt;
+
+    std::os::unix::net::SocketAddr::from_abstract_name(name)
+}
+
+pub enum UnixAction {
+    Accept(UnixStream),
+    Connect(UnixLocator),
+}
+
+pub struct UnixConnection<L: Layout> {
+    stream: UnixStream,
+    queue: VecDeque<u8>,
+    outbox: VecDeque<u8>,
+    read_buf: Vec<u8>,
+    pub(super) controller: Controller<L>,
+    pub(super) is_inbound: bool,
+}
+
+impl<L: Layout> UnixConnection<L> {
+    pub fn connect(locator: &UnixLocator, controller: Controller<L>) -> io::Result<Self> {
+        Self::with_stream(locator.connect()?, controller, false)
+    }
+
+    pub fn accept(stream: UnixStream, controller: Controller<L>) -> io::Result<Self> {
+        Self::with_stream(stream, controller, true)
+    }
+
+    fn with_stream(
+        stream: UnixStream,
+        controller: Controller<L>,
+        is_inbound: bool,
+    ) -> io::Result<Self> {
+        stream.set_nonblocking(true)?;
+        Ok(Self {
+            stream,
+            queue: empty!(),
+            outbox: empty!(),
+            read_buf: vec![0u8; u16::MAX as usize],
+            controller,
+            is_inbound,
+        })
+    }
+
+    /// Writes out as much of the buffered data as the socket accepts without
+    /// blocking.
+    fn write_outbox(&mut self) -> io::Result<()> {
+        while !self.outbox.is_empty() {
+            let (data, _) = self.outbox.as_slices();
+            match self.stream.write(data) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(len) => {
+                    self.outbox.drain(..len);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<L: Layout> Actor for UnixConnection<L> {
+    type Layout = L;
+    type Id = RawFd;
+    type Context = UnixAction;
+    type Cmd = Vec<u8>;
+    type Error = io::Error;
+
+    fn with(
+        context: Self::Context,
+        controller: Controller<Self::Layout>,
+    ) -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        match context {
+            UnixAction::Accept(stream) => Self::accept(stream, controller),
+            UnixAction::Connect(locator) => Self::connect(&locator, controller),
+        }
+    }
+
+    fn id(&self) -> Self::Id {
+        self.stream.as_raw_fd()
+    }
+
+    fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+        if io.is_readable {
+            loop {
+                match self.stream.read(&mut self.read_buf) {
+                    Ok(0) => break,
+                    Ok(len) => self.queue.extend(&self.read_buf[..len]),
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => return Err(err),
+                }
+            }
+        }
+        if io.is_writable {
+            self.write_outbox()?;
+        }
+        Ok(())
+    }
+
+    fn handle_cmd(&mut self, data: Self::Cmd) -> Result<(), Self::Error> {
+        self.write_all(&data)
+    }
+
+    fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+        Err(err)
+    }
+
+    fn has_pending_output(&self) -> bool {
+        !self.outbox.is_empty()
+    }
+
+    fn interests(&self) -> IoEv {
+        IoEv {
+            is_readable: true,
+            is_writable: self.has_pending_output(),
+            is_hangup: false,
+            is_error: false,
+        }
+    }
+
+    fn disconnect(&mut self) -> Result<(), Self::Error> {
+        self.write_outbox()?;
+        self.stream.shutdown(Shutdown::Both)
+    }
+}
+
+impl<L: Layout> Read for UnixConnection<L> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.queue.read(buf)
+    }
+}
+
+impl<L: Layout> Write for UnixConnection<L> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Data are buffered, so the writes never block the re-actor
+        self.outbox.extend(buf);
+        self.write_outbox()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_outbox()
+    }
+}
+
+impl<L: Layout> AsRawFd for UnixConnection<L> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
+
+impl<L: Layout> AsFd for UnixConnection<L> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.stream.as_fd()
+    }
+}
+
+/// Listener accepting Unix domain socket connections. Socket file of the
+/// listener bound to a file system path is removed once the listener is
+/// disconnected.
+pub struct UnixSpawner<L: Layout, const SESSION_POOL_ID: u32> {
+    socket: UnixListener,
+    locator: UnixLocator,
+    controller: Controller<L>,
+}
+
+impl<L: Layout, const SESSION_POOL_ID: u32> UnixSpawner<L, SESSION_POOL_ID> {
+    pub fn listen(locator: &UnixLocator, controller: Controller<L>) -> io::Result<Self> {
+        let socket = locator.bind()?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            locator: locator.clone(),
+            controller,
+        })
+    }
+
+    /// Returns address the listener is bound to.
+    pub fn locator(&self) -> &UnixLocator {
+        &self.locator
+    }
+}
+
+impl<L: Layout, const SESSION_POOL_ID: u32> Actor for UnixSpawner<L, SESSION_POOL_ID> {
+    type Layout = L;
+    type Id = RawFd;
+    type Context = UnixLocator;
+    type Cmd = ();
+    type Error = io::Error;
+
+    fn with(context: Self::Context, controller: Controller<L>) -> Result<Self, Self::Error>
+    where
+        Self: Sized,
+    {
+        Self::listen(&context, controller)
+    }
+
+    fn id(&self) -> Self::Id {
+        self.socket.as_raw_fd()
+    }
+
+    fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+        if !io.is_readable {
+            return Ok(());
+        }
+        // All pending connections are accepted, since a single event may be
+        // reported for several of them
+        loop {
+            let (stream, _) = match self.socket.accept() {
+                Ok(accepted) => accepted,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            let action = UnixAction::Accept(stream);
+            let ctx = L::convert(Box::new(action));
+            self.controller
+                .start_actor(SESSION_POOL_ID.into(), ctx)
+                .map_err(|_| io::ErrorKind::NotConnected)?;
+        }
+    }
+
+    fn handle_cmd(&mut self, _cmd: Self::Cmd) -> Result<(), Self::Error> {
+        // Listener does not support any commands
+        Ok(())
+    }
+
+    fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+        // Listener does not know how to handle errors, so it just propagates them
+        Err(err)
+    }
+
+    fn interests(&self) -> IoEv {
+        // Pending connections are signalled by the listening socket being
+        // readable
+        IoEv {
+            is_readable: true,
+            is_writable: false,
+            is_hangup: false,
+            is_error: false,
+        }
+    }
+
+    fn disconnect(&mut self) -> Result<(), Self::Error> {
+        // Listening socket is closed once the actor is dropped, but the
+        // socket file stays in the file system, preventing binding to the
+        // same path again
+        match &self.locator {
+            UnixLocator::Path(path) => match fs::remove_file(path) {
+                Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+                res => res,
+            },
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            UnixLocator::Abstract(_) => Ok(()),
+        }
+    }
+}
+
+impl<L: Layout, const SESSION_POOL_ID: u32> AsRawFd for UnixSpawner<L, SESSION_POOL_ID> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+}
+
+impl<L: Layout, const SESSION_POOL_ID: u32> AsFd for UnixSpawner<L, SESSION_POOL_ID> {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.socket.as_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::reactor::tests::{reactor, TestPool};
+
+    /// Reads from the connection until `len` bytes are received.
+    fn receive(conn: &mut UnixConnection<TestPool>, len: usize) -> Vec<u8> {
+        let start = Instant::now();
+        let mut data = vec![];
+        while data.len() < len {
+            assert!(start.elapsed() < Duration::from_secs(1), "no data received");
+            conn.io_ready(IoEv {
+                is_readable: true,
+                is_writable: true,
+                is_hangup: false,
+                is_error: false,
+            })
+            .unwrap();
+            conn.read_to_end(&mut data).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+        data
+    }
+
+    #[test]
+    fn bidirectional_data_flow() {
+        let (mut reactor, _) = reactor();
+        let path = std::env::temp_dir().join(format!("re-actor-uds-{}.sock", std::process::id()));
+        let locator = UnixLocator::Path(path.clone());
+        let listener = locator.bind().unwrap();
+
+        let controller = reactor.controller();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut conn = UnixConnection::accept(stream, controller).unwrap();
+            assert!(conn.is_inbound);
+            let request = receive(&mut conn, 4);
+            assert_eq!(request, b"ping");
+            conn.handle_cmd(b"pong".to_vec()).unwrap();
+            conn.disconnect().unwrap();
+        });
+
+        let controller = reactor.controller();
+        let client = thread::spawn(move || {
+            let mut conn = UnixConnection::connect(&locator, controller).unwrap();
+            assert!(!conn.is_inbound);
+            conn.handle_cmd(b"ping".to_vec()).unwrap();
+            receive(&mut conn, 4)
+        });
+
+        server.join().unwrap();
+        assert_eq!(client.join().unwrap(), b"pong");
+        std::fs::remove_file(path).unwrap();
+        reactor.shutdown().unwrap();
+    }
+
+    #[test]
+    fn socket_file_is_removed_on_disconnect() {
+        let (mut reactor, _) = reactor();
+        let path =
+            std::env::temp_dir().join(format!("re-actor-uds-{}-spawner.sock", std::process::id()));
+        let locator = UnixLocator::Path(path.clone());
+        let mut spawner =
+            UnixSpawner::<TestPool, 0>::listen(&locator, reactor.controller()).unwrap();
+        assert_eq!(spawner.locator(), &locator);
+        assert!(path.exists());
+
+        spawner.disconnect().unwrap();
+        assert!(!path.exists());
+        drop(spawner);
+        // The path can be reused by a new listener
+        let mut spawner =
+            UnixSpawner::<TestPool, 0>::listen(&locator, reactor.controller()).unwrap();
+        spawner.disconnect().unwrap();
+        reactor.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,429 @@
+//! Configuration of the re-actor runtime loaded from TOML or YAML files,
+//! allowing to tune timeouts and buffer sizes without recompilation.
+//!
+//! Durations are given either as a number of seconds or as a string with a
+//! unit suffix, like `"250ms"`, `"6s"`, `"2m"` or `"1h"`. Optional durations
+//! may be disabled with the `"none"` string (or `null` in YAML).
+
+use std::fmt::{self, Formatter};
+use std::path::Path;
+use std::time::Duration;
+use std::{fs, io};
+
+use serde::de::{self, Deserializer, Visitor};
+use serde::Deserialize;
+
+#[cfg(feature = "socket2")]
+use crate::actors::socket2::TcpConfig;
+use crate::{
+    Actor, InternalError, Layout, Pool, ReactorApi, DEFAULT_MAX_IO_EVENTS,
+    DEFAULT_MAX_IO_EVENTS_PER_ACTOR, DEFAULT_SHUTDOWN_GRACE,
+};
+
+/// Errors loading [`ReactorConfig`].
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum ConfigError {
+    /// unable to read configuration file: {0}
+    #[from]
+    Io(io::Error),
+
+    /// unsupported configuration file extension `{0}`; use `toml`, `yaml` or
+    /// `yml`
+    UnsupportedFormat(String),
+
+    /// invalid TOML configuration: {0}
+    #[from]
+    Toml(toml::de::Error),
+
+    /// invalid YAML configuration: {0}
+    #[from]
+    Yaml(serde_yaml::Error),
+}
+
+/// Tunable parameters of the re-actor runtime, applied with
+/// [`crate::Reactor::with_config`].
+///
+/// All fields are optional in the configuration files and default to the
+/// values used by [`crate::Reactor::new`].
+#[derive(Clone, PartialEq, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReactorConfig {
+    /// Time given to the actors to write out pending data during the re-actor
+    /// shutdown.
+    #[serde(deserialize_with = "duration")]
+    pub shutdown_grace: Duration,
+    /// Maximal number of the queued control events of each pool; unbounded
+    /// if not set (see [`crate::Reactor::with_capacity`]).
+    pub control_capacity: Option<usize>,
+    /// Settings applied to all pools of the re-actor layout.
+    pub pool: PoolConfig,
+    /// Backoff of the reconnection attempts.
+    pub reconnect: ReconnectConfig,
+    /// Options of the TCP sockets, including the keepalive ones.
+    #[cfg(feature = "socket2")]
+    #[serde(deserialize_with = "tcp")]
+    pub tcp: TcpConfig,
+}
+
+impl Default for ReactorConfig {
+    fn default() -> Self {
+        ReactorConfig {
+            shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
+            control_capacity: None,
+            pool: default!(),
+            reconnect: default!(),
+            #[cfg(feature = "socket2")]
+            tcp: default!(),
+        }
+    }
+}
+
+impl ReactorConfig {
+    /// Loads configuration from a TOML or YAML file, detecting the format by
+    /// the file extension.
+    pub fn from_file(path: &Path) -> Result<Self, ConfigError> {
+        let ext = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        let parse = match ext.as_str() {
+            "toml" => Self::from_toml,
+            "yaml" | "yml" => Self::from_yaml,
+            _ => return Err(ConfigError::UnsupportedFormat(ext)),
+        };
+        parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn from_toml(data: &str) -> Result<Self, ConfigError> {
+        toml::from_str(data).map_err(ConfigError::from)
+    }
+
+    pub fn from_yaml(data: &str) -> Result<Self, ConfigError> {
+        serde_yaml::from_str(data).map_err(ConfigError::from)
+    }
+}
+
+/// Settings of the re-actor pools, overriding the ones provided by
+/// [`Layout::default_pools`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PoolConfig {
+    /// Time after which idle pools call [`crate::Handler::on_idle`] (see
+    /// [`Pool::with_idle_timeout`]). If not set, the timeout of the pool is
+    /// kept.
+    #[serde(deserialize_with = "optional_duration")]
+    pub idle_timeout: Option<Duration>,
+    /// Maximal number of I/O events dispatched in a single event loop
+    /// iteration (see [`Pool::with_io_budget`]).
+    pub max_io_events: usize,
+    /// Maximal number of I/O events dispatched to a single actor in a single
+    /// event loop iteration (see [`Pool::with_io_budget`]).
+    pub max_io_events_per_actor: usize,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        PoolConfig {
+            idle_timeout: None,
+            max_io_events: DEFAULT_MAX_IO_EVENTS,
+            max_io_events_per_actor: DEFAULT_MAX_IO_EVENTS_PER_ACTOR,
+        }
+    }
+}
+
+impl PoolConfig {
+    pub(crate) fn apply<R: Actor, L: Layout>(&self, mut pool: Pool<R, L>) -> Pool<R, L> {
+        if let Some(timeout) = self.idle_timeout {
+            pool = pool.with_idle_timeout(timeout);
+        }
+        pool.with_io_budget(self.max_io_events, self.max_io_events_per_actor)
+    }
+}
+
+/// Backoff of the reconnection attempts made with [`ReactorApi::reconnect`].
+#[derive(Copy, Clone, PartialEq, Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReconnectConfig {
+    /// Interval before the first attempt.
+    #[serde(deserialize_with = "duration")]
+    pub backoff: Duration,
+    /// Multiplier of the interval before each subsequent attempt.
+    pub multiplier: f64,
+    /// Maximal number of the attempts.
+    pub max_attempts: u32,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            backoff: Duration::from_secs(1),
+            multiplier: 2.0,
+            max_attempts: 5,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// Connects new actor with [`ReactorApi::reconnect`] using this backoff.
+    pub fn reconnect<A: ReactorApi>(
+        &self,
+        api: &mut A,
+        pool: A::Pool,
+        id: <A::Actor as Actor>::Id,
+        ctx: <A::Actor as Actor>::Context,
+    ) -> Result<(), InternalError<A::Pool>>
+    where
+        <A::Actor as Actor>::Context: Clone + 'static,
+    {
+        api.reconnect(
+            pool,
+            id,
+            ctx,
+            self.backoff,
+            self.multiplier,
+            self.max_attempts,
+        )
+    }
+}
+
+/// Options of the TCP sockets in the configuration files. Options which are
+/// not given keep the values of [`TcpConfig::default`].
+#[cfg(feature = "socket2")]
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TcpSection {
+    #[serde(default, deserialize_with = "some_duration")]
+    read_timeout: Option<Option<Duration>>,
+    #[serde(default, deserialize_with = "some_duration")]
+    write_timeout: Option<Option<Duration>>,
+    #[serde(default, deserialize_with = "some_duration")]
+    dial_timeout: Option<Option<Duration>>,
+    #[serde(default, deserialize_with = "some_duration")]
+    keepalive_idle: Option<Option<Duration>>,
+    #[serde(default, deserialize_with = "some_duration")]
+    keepalive_interval: Option<Option<Duration>>,
+    keepalive_retries: Option<u32>,
+    fast_open: Option<bool>,
+    nodelay: Option<bool>,
+    #[serde(default, deserialize_with = "some_duration")]
+    linger: Option<Option<Duration>>,
+    send_buffer_size: Option<usize>,
+    recv_buffer_size: Option<usize>,
+}
+
+#[cfg(feature = "socket2")]
+fn tcp<'de, D: Deserializer<'de>>(deserializer: D) -> Result<TcpConfig, D::Error> {
+    let section = TcpSection::deserialize(deserializer)?;
+    let default = TcpConfig::default();
+    Ok(TcpConfig {
+        read_timeout: section.read_timeout.unwrap_or(default.read_timeout),
+        write_timeout: section.write_timeout.unwrap_or(default.write_timeout),
+        dial_timeout: section.dial_timeout.unwrap_or(default.dial_timeout),
+        keepalive_idle: section.keepalive_idle.unwrap_or(default.keepalive_idle),
+        keepalive_interval: section
+            .keepalive_interval
+            .unwrap_or(default.keepalive_interval),
+        keepalive_retries: section.keepalive_retries.or(default.keepalive_retries),
+        fast_open: section.fast_open.unwrap_or(default.fast_open),
+        nodelay: section.nodelay.unwrap_or(default.nodelay),
+        linger: section.linger.unwrap_or(default.linger),
+        send_buffer_size: section.send_buffer_size.or(default.send_buffer_size),
+        recv_buffer_size: section.recv_buffer_size.or(default.recv_buffer_size),
+    })
+}
+
+/// Deserializes duration which must be set.
+fn duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+    optional_duration(deserializer)?.ok_or_else(|| de::Error::custom("duration must be set"))
+}
+
+/// Deserializes duration which may be disabled.
+fn optional_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Duration>, D::Error> {
+    deserializer.deserialize_any(DurationVisitor)
+}
+
+/// Deserializes duration which may be disabled, distinguishing it from the
+/// duration which is not given (and so is deserialized with the field default).
+#[cfg(feature = "socket2")]
+fn some_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Option<Duration>>, D::Error> {
+    optional_duration(deserializer).map(Some)
+}
+
+struct DurationVisitor;
+
+impl<'de> Visitor<'de> for DurationVisitor {
+    type Value = Option<Duration>;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("number of seconds or a duration string like \"250ms\", \"6s\" or \"none\"")
+    }
+
+    fn visit_u64<E: de::Error>(self, secs: u64) -> Result<Self::Value, E> {
+        Ok(Some(Duration::from_secs(secs)))
+    }
+
+    fn visit_i64<E: de::Error>(self, secs: i64) -> Result<Self::Value, E> {
+        let secs = u64::try_from(secs).map_err(|_| E::custom("negative duration"))?;
+        self.visit_u64(secs)
+    }
+
+    fn visit_f64<E: de::Error>(self, secs: f64) -> Result<Self::Value, E> {
+        Duration::try_from_secs_f64(secs)
+            .map(Some)
+            .map_err(E::custom)
+    }
+
+    fn visit_str<E: de::Error>(self, s: &str) -> Result<Self::Value, E> {
+        if s == "none" {
+            return Ok(None);
+        }
+        let split = s
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(s.len());
+        let (value, unit) = s.split_at(split);
+        let value = value
+            .parse::<f64>()
+            .map_err(|_| E::custom(format!("invalid duration `{s}`")))?;
+        let secs = match unit.trim() {
+            "ms" => value / 1000.0,
+            "" | "s" => value,
+            "m" => value * 60.0,
+            "h" => value * 3600.0,
+            unit => return Err(E::custom(format!("unknown duration unit `{unit}`"))),
+        };
+        self.visit_f64(secs)
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<Self::Value, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "socket2")]
+    const TOML: &str = r#"
+shutdown_grace = "500ms"
+control_capacity = 256
+
+[pool]
+idle_timeout = 2
+max_io_events = 128
+
+[reconnect]
+backoff = "100ms"
+multiplier = 1.5
+max_attempts = 3
+
+[tcp]
+read_timeout = "10s"
+write_timeout = "none"
+keepalive_idle = "1m"
+keepalive_retries = 4
+nodelay = true
+recv_buffer_size = 65536
+"#;
+
+    #[cfg(feature = "socket2")]
+    fn expected() -> ReactorConfig {
+        ReactorConfig {
+            shutdown_grace: Duration::from_millis(500),
+            control_capacity: Some(256),
+            pool: PoolConfig {
+                idle_timeout: Some(Duration::from_secs(2)),
+                max_io_events: 128,
+                max_io_events_per_actor: DEFAULT_MAX_IO_EVENTS_PER_ACTOR,
+            },
+            reconnect: ReconnectConfig {
+                backoff: Duration::from_millis(100),
+                multiplier: 1.5,
+                max_attempts: 3,
+            },
+            tcp: TcpConfig::default()
+                .with_read_timeout(Some(Duration::from_secs(10)))
+                .with_write_timeout(None)
+                .with_keepalive_idle(Duration::from_secs(60))
+                .with_keepalive_retries(4)
+                .with_nodelay(true)
+                .with_recv_buffer_size(65536),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "socket2")]
+    fn toml_file() {
+        let path = std::env::temp_dir().join(format!("re-actor-{}.toml", std::process::id()));
+        fs::write(&path, TOML).unwrap();
+        let config = ReactorConfig::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(config.unwrap(), expected());
+    }
+
+    #[test]
+    #[cfg(feature = "socket2")]
+    fn yaml_file() {
+        let yaml = r#"
+shutdown_grace: 0.5
+control_capacity: 256
+pool:
+  idle_timeout: 2s
+  max_io_events: 128
+reconnect:
+  backoff: 100ms
+  multiplier: 1.5
+  max_attempts: 3
+tcp:
+  read_timeout: 10
+  write_timeout: null
+  keepalive_idle: 1m
+  keepalive_retries: 4
+  nodelay: true
+  recv_buffer_size: 65536
+"#;
+        let path = std::env::temp_dir().join(format!("re-actor-{}.yml", std::process::id()));
+        fs::write(&path, yaml).unwrap();
+        let config = ReactorConfig::from_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(config.unwrap(), expected());
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(
+            ReactorConfig::from_toml("").unwrap(),
+            ReactorConfig::default()
+        );
+        assert!(matches!(
+            ReactorConfig::from_toml("shutdown_grace = \"3 days\""),
+            Err(ConfigError::Toml(_))
+        ));
+        assert!(matches!(
+            ReactorConfig::from_toml("unknown = 1"),
+            Err(ConfigError::Toml(_))
+        ));
+        assert!(matches!(
+            ReactorConfig::from_file(Path::new("reactor.json")),
+            Err(ConfigError::UnsupportedFormat(ext)) if ext == "json"
+        ));
+        assert!(matches!(
+            ReactorConfig::from_file(Path::new("missing.toml")),
+            Err(ConfigError::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,315 @@
+//! Routing of the actor commands to the handlers by the command type, allowing
+//! applications with heterogeneous commands to avoid a monolithic `match` in
+//! [`Actor::handle_cmd`].
+//!
+//! Actors accepting arbitrary commands use [`DynCmd`] as their
+//! [`Actor::Cmd`] type and route them with [`DynDispatcher`], which finds the
+//! handler by the [`TypeId`] of the command. When the set of commands is known
+//! at compile time, commands can be an enum implementing [`Command`], routed
+//! with zero allocations by [`StaticDispatcher`] from a static `phf` map.
+//!
+//! ```ignore
+//! impl Actor for Service {
+//!     type Cmd = DynCmd;
+//!     type Error = io::Error;
+//!     // ...
+//!
+//!     fn handle_cmd(&mut self, cmd: DynCmd) -> Result<(), Self::Error> {
+//!         self.dispatcher.dispatch(cmd).map_err(io::Error::from)
+//!     }
+//! }
+//!
+//! controller.send(id, Arc::new(Subscribe(topic)) as DynCmd)?;
+//! ```
+//!
+//! [`Actor::handle_cmd`]: crate::Actor::handle_cmd
+//! [`Actor::Cmd`]: crate::Actor::Cmd
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::io;
+use std::sync::Arc;
+
+/// Type-erased command, used as [`Actor::Cmd`] by the actors routing their
+/// commands with [`DynDispatcher`]. Commands are reference-counted and not
+/// boxed since actor commands must be cloneable to support broadcasting.
+///
+/// [`Actor::Cmd`]: crate::Actor::Cmd
+pub type DynCmd = Arc<dyn Any + Send + Sync>;
+
+/// Type-erased command handler registered with [`DynDispatcher`].
+type CmdHandler = Box<dyn FnMut(DynCmd) -> Result<(), DispatchError> + Send>;
+
+/// Errors routing commands with a [`Dispatcher`].
+#[derive(Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DispatchError {
+    /// no handler is registered for the command
+    UnknownCommand,
+
+    /// command is rejected by its handler: {0}
+    Rejected(Box<dyn StdError + Send + Sync>),
+}
+
+impl From<DispatchError> for io::Error {
+    fn from(err: DispatchError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidInput, err)
+    }
+}
+
+/// Router of the commands of `Cmd` type to their handlers.
+pub trait Dispatcher<Cmd> {
+    /// Passes command to the handler registered for it.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`DispatchError::UnknownCommand`] if there is no handler for
+    /// the command, or the error returned by the handler.
+    fn dispatch(&mut self, cmd: Cmd) -> Result<(), DispatchError>;
+}
+
+/// Dispatcher routing type-erased [`DynCmd`] commands by their type.
+#[derive(Default)]
+pub struct DynDispatcher {
+    handlers: HashMap<TypeId, CmdHandler>,
+}
+
+impl DynDispatcher {
+    pub fn new() -> Self {
+        default!()
+    }
+
+    /// Registers handler for the commands of type `C`, replacing previously
+    /// registered handler for the same type.
+    ///
+    /// The command is passed to the handler by value; it is cloned only if
+    /// it is shared with other actors, i.e. was broadcasted.
+    pub fn register<C: Clone + Send + Sync + 'static>(
+        &mut self,
+        mut handler: impl FnMut(C) -> Result<(), DispatchError> + Send + 'static,
+    ) {
+        let handler = move |cmd: DynCmd| {
+            let cmd = cmd
+                .downcast::<C>()
+                .expect("command is routed by its type id");
+            handler(Arc::try_unwrap(cmd).unwrap_or_else(|cmd| C::clone(&cmd)))
+        };
+        self.handlers.insert(TypeId::of::<C>(), Box::new(handler));
+    }
+
+    /// Checks whether a handler is registered for the commands of type `C`.
+    pub fn is_registered<C: 'static>(&self) -> bool {
+        self.handlers.contains_key(&TypeId::of::<C>())
+    }
+
+    pub fn len(&self) -> usize {
+        self.handlers.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handlers.is_empty()
+    }
+}
+
+impl Dispatcher<DynCmd> for DynDispatcher {
+    fn dispatch(&mut self, cmd: DynCmd) -> Result<(), DispatchError> {
+        // Type id must be taken from the command and not from the pointer
+        let type_id = (*cmd).type_id();
+        let handler = self
+            .handlers
+            .get_mut(&type_id)
+            .ok_or(DispatchError::UnknownCommand)?;
+        handler(cmd)
+    }
+}
+
+/// Command from a set known at compile time, routed by [`StaticDispatcher`].
+#[cfg(feature = "phf")]
+pub trait Command {
+    /// Name of the command kind, used as a key in the routing map.
+    fn name(&self) -> &'static str;
+}
+
+/// Handler of the commands routed by [`StaticDispatcher`].
+#[cfg(feature = "phf")]
+pub type StaticHandler<Cmd> = fn(Cmd) -> Result<(), DispatchError>;
+
+/// Dispatcher routing commands from a set known at compile time by their
+/// [`Command::name`] using a static perfect hash map, which requires no
+/// allocations:
+///
+/// ```ignore
+/// static ROUTES: phf::Map<&'static str, StaticHandler<Cmd>> = phf::phf_map! {
+///     "subscribe" => on_subscribe,
+///     "publish" => on_publish,
+/// };
+///
+/// let mut dispatcher = StaticDispatcher::new(&ROUTES);
+/// ```
+#[cfg(feature = "phf")]
+pub struct StaticDispatcher<Cmd: Command + 'static> {
+    routes: &'static phf::Map<&'static str, StaticHandler<Cmd>>,
+}
+
+#[cfg(feature = "phf")]
+impl<Cmd: Command + 'static> StaticDispatcher<Cmd> {
+    pub fn new(routes: &'static phf::Map<&'static str, StaticHandler<Cmd>>) -> Self {
+        Self { routes }
+    }
+
+    /// Checks whether a handler is registered for the commands with the name.
+    pub fn is_registered(&self, name: &str) -> bool {
+        self.routes.contains_key(name)
+    }
+}
+
+#[cfg(feature = "phf")]
+impl<Cmd: Command + 'static> Dispatcher<Cmd> for StaticDispatcher<Cmd> {
+    fn dispatch(&mut self, cmd: Cmd) -> Result<(), DispatchError> {
+        let handler = self
+            .routes
+            .get(cmd.name())
+            .ok_or(DispatchError::UnknownCommand)?;
+        handler(cmd)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    struct Subscribe(&'static str);
+
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    struct Publish(&'static str, Vec<u8>);
+
+    #[derive(Debug, Display, Error)]
+    #[display("empty message")]
+    struct EmptyMessage;
+
+    #[test]
+    fn routes_by_type() {
+        let (subscribe_send, subscribe_recv) = mpsc::channel();
+        let (publish_send, publish_recv) = mpsc::channel();
+        let mut dispatcher = DynDispatcher::new();
+        dispatcher.register(move |cmd: Subscribe| {
+            subscribe_send.send(cmd).unwrap();
+            Ok(())
+        });
+        dispatcher.register(move |cmd: Publish| {
+            if cmd.1.is_empty() {
+                return Err(DispatchError::Rejected(Box::new(EmptyMessage)));
+            }
+            publish_send.send(cmd).unwrap();
+            Ok(())
+        });
+        assert_eq!(dispatcher.len(), 2);
+        assert!(dispatcher.is_registered::<Subscribe>());
+
+        dispatcher.dispatch(Arc::new(Subscribe("news"))).unwrap();
+        dispatcher
+            .dispatch(Arc::new(Publish("news", b"hello".to_vec())))
+            .unwrap();
+        assert!(matches!(
+            dispatcher.dispatch(Arc::new(Publish("news", vec![]))),
+            Err(DispatchError::Rejected(_))
+        ));
+        assert_eq!(subscribe_recv.try_recv().unwrap(), Subscribe("news"));
+        assert_eq!(
+            publish_recv.try_recv().unwrap(),
+            Publish("news", b"hello".to_vec())
+        );
+        assert!(publish_recv.try_recv().is_err());
+    }
+
+    #[test]
+    fn unknown_command() {
+        let mut dispatcher = DynDispatcher::new();
+        assert!(matches!(
+            dispatcher.dispatch(Arc::new(Subscribe("news"))),
+            Err(DispatchError::UnknownCommand)
+        ));
+        dispatcher.register(|_: Subscribe| Ok(()));
+        dispatcher.dispatch(Arc::new(Subscribe("news"))).unwrap();
+        assert!(!dispatcher.is_registered::<Publish>());
+        assert!(matches!(
+            dispatcher.dispatch(Arc::new(Publish("news", vec![1]))),
+            Err(DispatchError::UnknownCommand)
+        ));
+        // Commands are routed by their own type and not by the type of the
+        // pointer they are wrapped into
+        assert!(matches!(
+            dispatcher.dispatch(Arc::new(Arc::new(Subscribe("news")))),
+            Err(DispatchError::UnknownCommand)
+        ));
+    }
+
+    #[cfg(feature = "phf")]
+    mod fixed {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        use super::*;
+
+        static SUBSCRIBED: AtomicUsize = AtomicUsize::new(0);
+
+        enum Cmd {
+            Subscribe(&'static str),
+            Publish(&'static str, Vec<u8>),
+            Unsubscribe(&'static str),
+        }
+
+        impl Command for Cmd {
+            fn name(&self) -> &'static str {
+                match self {
+                    Cmd::Subscribe(_) => "subscribe",
+                    Cmd::Publish(..) => "publish",
+                    Cmd::Unsubscribe(_) => "unsubscribe",
+                }
+            }
+        }
+
+        fn on_subscribe(_: Cmd) -> Result<(), DispatchError> {
+            SUBSCRIBED.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+
+        fn on_publish(cmd: Cmd) -> Result<(), DispatchError> {
+            match cmd {
+                Cmd::Publish(_, data) if data.is_empty() => {
+                    Err(DispatchError::Rejected(Box::new(EmptyMessage)))
+                }
+                _ => Ok(()),
+            }
+        }
+
+        static ROUTES: phf::Map<&'static str, StaticHandler<Cmd>> = phf::phf_map! {
+            "subscribe" => on_subscribe,
+            "publish" => on_publish,
+        };
+
+        #[test]
+        fn static_routing() {
+            let mut dispatcher = StaticDispatcher::new(&ROUTES);
+            dispatcher.dispatch(Cmd::Subscribe("news")).unwrap();
+            dispatcher.dispatch(Cmd::Subscribe("sport")).unwrap();
+            assert_eq!(SUBSCRIBED.load(Ordering::SeqCst), 2);
+            dispatcher
+                .dispatch(Cmd::Publish("news", b"hello".to_vec()))
+                .unwrap();
+            assert!(matches!(
+                dispatcher.dispatch(Cmd::Publish("news", vec![])),
+                Err(DispatchError::Rejected(_))
+            ));
+
+            assert!(!dispatcher.is_registered("unsubscribe"));
+            assert!(matches!(
+                dispatcher.dispatch(Cmd::Unsubscribe("news")),
+                Err(DispatchError::UnknownCommand)
+            ));
+        }
+    }
+}
@@ -32,11 +32,32 @@
 extern crate amplify;
 
 pub mod actors;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod dispatch;
+#[cfg(feature = "metrics")]
+pub mod metrics;
+#[cfg(feature = "otel")]
+pub mod otel;
 mod reactor;
 pub mod schedulers;
+pub mod testing;
 mod util;
 
-pub use actors::Actor;
-pub use reactor::{Controller, Handler, InternalError, Layout, Pool, Reactor, ReactorApi};
+pub use actors::{Actor, ActorSnapshot, Migrate};
+#[cfg(feature = "config")]
+pub use config::{ConfigError, ReactorConfig};
+pub use dispatch::{DispatchError, Dispatcher, DynCmd, DynDispatcher};
+#[cfg(feature = "metrics")]
+pub use metrics::MetricsHandler;
+#[cfg(feature = "otel")]
+pub use otel::TracedHandler;
+pub use reactor::{
+    BroadcastFilter, Controller, EventEntry, EventKind, EventLog, Handler, InternalError, Layout,
+    Pool, Reactor, ReactorApi, ReactorMetrics, StallDetector, TimerToken, WatchdogReactor,
+    DEFAULT_MAX_IO_EVENTS, DEFAULT_MAX_IO_EVENTS_PER_ACTOR, DEFAULT_QUERY_TIMEOUT,
+    DEFAULT_SHUTDOWN_GRACE, EVENT_LOG_LEN, MAX_CONTROL_EVENTS, MAX_ENTRY_DETAIL_LEN,
+    MAX_ENTRY_ID_LEN,
+};
 pub use schedulers::Scheduler;
 pub use util::timeout::TimeoutManager;
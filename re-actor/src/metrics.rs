@@ -0,0 +1,430 @@
+//! Export of the re-actor pool statistics to [Prometheus].
+//!
+//! [`MetricsHandler`] wraps the [`Handler`] of a pool and updates the
+//! Prometheus metrics from the handler callbacks before passing them to the
+//! wrapped handler:
+//!
+//! - `reactor_resources_total`: gauge of the actors run by the pool;
+//! - `reactor_io_events_total`: counter of the I/O events dispatched to the
+//!   actors, labeled with `event` being either `readable` or `writable`;
+//! - `reactor_errors_total`: counter of the errors and panics reported to the
+//!   handler;
+//! - `reactor_control_events_total`: counter of the processed control events;
+//! - `reactor_io_loop_duration_seconds`: histogram of the event loop iteration
+//!   times, not including the time spent waiting for I/O.
+//!
+//! [Prometheus]: https://prometheus.io
+
+use std::any::Any;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use prometheus::proto::MetricFamily;
+use prometheus::{
+    exponential_buckets, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, Opts,
+    Registry,
+};
+
+use crate::actors::{DisconnectReason, IoEv};
+use crate::{Actor, Controller, Handler, InternalError, Layout, TimerToken};
+
+/// Pool handler wrapper maintaining Prometheus metrics of the pool runtime.
+///
+/// Actors taken out of the pool with [`crate::ReactorApi::take_actor`] are
+/// not reported to the handler, and thus are still counted by the
+/// `reactor_resources_total` gauge.
+pub struct MetricsHandler<L: Layout, H: Handler<L>> {
+    inner: H,
+    registry: Registry,
+    resources: IntGauge,
+    io_events: IntCounterVec,
+    errors: IntCounter,
+    control_events: IntCounter,
+    loop_duration: Histogram,
+    _phantom: PhantomData<L>,
+}
+
+impl<L: Layout, H: Handler<L>> MetricsHandler<L, H> {
+    /// Wraps the handler, registering the pool metrics with the `registry`.
+    ///
+    /// Metrics names are fixed, so handlers of different pools must use
+    /// different registries (for instance, ones constructed with
+    /// [`Registry::new_custom`] providing the pool name as a label).
+    pub fn new(inner: H, registry: &Registry) -> prometheus::Result<Self> {
+        let resources = IntGauge::new(
+            "reactor_resources_total",
+            "Number of the actors run by the pool",
+        )?;
+        let io_events = IntCounterVec::new(
+            Opts::new(
+                "reactor_io_events_total",
+                "Number of the I/O events dispatched to the actors",
+            ),
+            &["event"],
+        )?;
+        let errors = IntCounter::new(
+            "reactor_errors_total",
+            "Number of the errors and actor panics reported by the pool",
+        )?;
+        let control_events = IntCounter::new(
+            "reactor_control_events_total",
+            "Number of the control events processed by the pool",
+        )?;
+        let loop_duration = Histogram::with_opts(
+            HistogramOpts::new(
+                "reactor_io_loop_duration_seconds",
+                "Duration of the event loop iterations, excluding waiting for I/O",
+            )
+            .buckets(exponential_buckets(1e-6, 4.0, 10)?),
+        )?;
+
+        registry.register(Box::new(resources.clone()))?;
+        registry.register(Box::new(io_events.clone()))?;
+        registry.register(Box::new(errors.clone()))?;
+        registry.register(Box::new(control_events.clone()))?;
+        registry.register(Box::new(loop_duration.clone()))?;
+
+        Ok(MetricsHandler {
+            inner,
+            registry: registry.clone(),
+            resources,
+            io_events,
+            errors,
+            control_events,
+            loop_duration,
+            _phantom: PhantomData,
+        })
+    }
+
+    /// Returns the wrapped handler.
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    /// Collects current values of all metrics of the registry, which can be
+    /// serialized into Prometheus text format with
+    /// [`prometheus::TextEncoder`].
+    pub fn gather(&self) -> Vec<MetricFamily> {
+        self.registry.gather()
+    }
+}
+
+impl<L: Layout, H: Handler<L>> Handler<L> for MetricsHandler<L, H> {
+    fn handle_err(&mut self, err: InternalError<L>) {
+        self.errors.inc();
+        self.inner.handle_err(err)
+    }
+
+    fn on_startup(&mut self, controller: Controller<L>) {
+        self.inner.on_startup(controller)
+    }
+
+    fn handle_panic(&mut self, id: &<L::RootActor as Actor>::Id, payload: Box<dyn Any + Send>) {
+        self.errors.inc();
+        self.inner.handle_panic(id, payload)
+    }
+
+    fn on_timer(&mut self, token: TimerToken) {
+        self.inner.on_timer(token)
+    }
+
+    fn on_connect(&mut self, id: &<L::RootActor as Actor>::Id) {
+        self.resources.inc();
+        self.inner.on_connect(id)
+    }
+
+    fn on_disconnect(&mut self, id: &<L::RootActor as Actor>::Id, reason: &DisconnectReason) {
+        self.resources.dec();
+        self.inner.on_disconnect(id, reason)
+    }
+
+    fn on_hangup(&mut self, actor: L::RootActor) {
+        self.inner.on_hangup(actor)
+    }
+
+    fn on_disconnect_all(&mut self, count: usize) {
+        self.inner.on_disconnect_all(count)
+    }
+
+    fn on_circuit_open(&mut self, id: &<L::RootActor as Actor>::Id) {
+        self.inner.on_circuit_open(id)
+    }
+
+    fn on_circuit_close(&mut self, id: &<L::RootActor as Actor>::Id) {
+        self.inner.on_circuit_close(id)
+    }
+
+    fn on_idle(&mut self) {
+        self.inner.on_idle()
+    }
+
+    fn on_io(&mut self, id: &<L::RootActor as Actor>::Id, io: IoEv) {
+        if io.is_readable {
+            self.io_events.with_label_values(&["readable"]).inc();
+        }
+        if io.is_writable {
+            self.io_events.with_label_values(&["writable"]).inc();
+        }
+        self.inner.on_io(id, io)
+    }
+
+    fn on_io_done(&mut self, id: &<L::RootActor as Actor>::Id) {
+        self.inner.on_io_done(id)
+    }
+
+    fn on_control(&mut self, kind: &str) {
+        self.control_events.inc();
+        self.inner.on_control(kind)
+    }
+
+    fn on_control_done(&mut self) {
+        self.inner.on_control_done()
+    }
+
+    fn on_iteration(&mut self, duration: Duration) {
+        self.loop_duration.observe(duration.as_secs_f64());
+        self.inner.on_iteration(duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::io;
+    use std::sync::{Arc, OnceLock};
+    use std::thread;
+    use std::time::Instant;
+
+    use prometheus::{Encoder, TextEncoder};
+
+    use super::*;
+    use crate::actors::mem::{AsMemSocket, MemQueue, MemSocket};
+    use crate::schedulers::MemScheduler;
+    use crate::{Pool, Reactor, ReactorApi};
+
+    /// Time during which the test workload is run.
+    const RUN_TIME: Duration = Duration::from_millis(500);
+    /// Each command with this number is sent empty, causing an actor error.
+    const ERROR_EACH: usize = 10;
+
+    /// Pools are constructed by [`Layout::default_pools`], so the registry
+    /// can't be passed to the handler otherwise.
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+
+    fn registry() -> &'static Registry {
+        REGISTRY.get_or_init(Registry::new)
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+    #[display(Debug)]
+    enum MetricsPool {
+        Main,
+    }
+
+    impl From<u32> for MetricsPool {
+        fn from(_: u32) -> Self {
+            MetricsPool::Main
+        }
+    }
+
+    impl From<MetricsPool> for u32 {
+        fn from(_: MetricsPool) -> Self {
+            0
+        }
+    }
+
+    impl Layout for MetricsPool {
+        type RootActor = Writer;
+
+        fn default_pools() -> Vec<Pool<Self::RootActor, Self>> {
+            let handler = MetricsHandler::new(IgnoreHandler, registry()).unwrap();
+            vec![Pool::new(MetricsPool::Main, MemScheduler::new(), handler)]
+        }
+
+        fn convert(_: Box<dyn Any>) -> MemSocket<MetricsPool> {
+            unreachable!()
+        }
+    }
+
+    struct IgnoreHandler;
+
+    impl Handler<MetricsPool> for IgnoreHandler {
+        fn handle_err(&mut self, _: InternalError<MetricsPool>) {}
+    }
+
+    /// Socket which writes frames received as commands once it gets writable
+    /// and discards the frames it reads. Empty frames are rejected.
+    struct Writer {
+        socket: MemSocket<MetricsPool>,
+        queue: VecDeque<Vec<u8>>,
+    }
+
+    impl AsMemSocket for Writer {
+        fn inbox(&self) -> &Arc<MemQueue> {
+            self.socket.inbox()
+        }
+    }
+
+    impl Actor for Writer {
+        type Layout = MetricsPool;
+        type Id = u64;
+        type Context = MemSocket<MetricsPool>;
+        type Cmd = Vec<u8>;
+        type Error = io::Error;
+
+        fn with(socket: Self::Context, _: Controller<MetricsPool>) -> Result<Self, Self::Error> {
+            Ok(Writer {
+                socket,
+                queue: empty!(),
+            })
+        }
+
+        fn id(&self) -> Self::Id {
+            self.socket.id()
+        }
+
+        fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+            self.socket.io_ready(io)?;
+            while self.socket.recv_frame().is_some() {}
+            if io.is_writable {
+                for frame in self.queue.drain(..) {
+                    self.socket.send_frame(frame);
+                }
+            }
+            Ok(())
+        }
+
+        fn handle_cmd(&mut self, frame: Self::Cmd) -> Result<(), Self::Error> {
+            if frame.is_empty() {
+                return Err(io::ErrorKind::InvalidInput.into());
+            }
+            self.queue.push_back(frame);
+            Ok(())
+        }
+
+        fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+            Err(err)
+        }
+
+        fn interests(&self) -> IoEv {
+            IoEv {
+                is_readable: true,
+                is_writable: !self.queue.is_empty(),
+                is_hangup: false,
+                is_error: false,
+            }
+        }
+
+        fn disconnect(&mut self) -> Result<(), Self::Error> {
+            self.socket.disconnect()
+        }
+    }
+
+    /// Returns value of the metric with the given `event` label (if any); for
+    /// histograms returns number of the samples. Metrics which were never
+    /// updated are not gathered, and thus are reported as zero.
+    fn value(name: &str, event: Option<&str>) -> f64 {
+        let families = registry().gather();
+        let Some(family) = families.iter().find(|family| family.get_name() == name) else {
+            return 0.0;
+        };
+        let metric = family.get_metric().iter().find(|metric| {
+            event.map_or(true, |event| {
+                metric
+                    .get_label()
+                    .iter()
+                    .any(|label| label.get_value() == event)
+            })
+        });
+        match (name, metric) {
+            (_, None) => 0.0,
+            ("reactor_resources_total", Some(metric)) => metric.get_gauge().get_value(),
+            ("reactor_io_loop_duration_seconds", Some(metric)) => {
+                metric.get_histogram().get_sample_count() as f64
+            }
+            (_, Some(metric)) => metric.get_counter().get_value(),
+        }
+    }
+
+    fn wait_for(name: &str, event: Option<&str>, expected: usize) {
+        let start = Instant::now();
+        while value(name, event) != expected as f64 {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "{name} has not reached {expected}: {}",
+                value(name, event)
+            );
+            thread::yield_now();
+        }
+    }
+
+    fn assert_close(name: &str, event: Option<&str>, expected: usize) {
+        let actual = value(name, event);
+        let expected = expected as f64;
+        assert!(
+            (actual - expected).abs() <= expected * 0.05,
+            "{name}: {actual} instead of {expected}"
+        );
+    }
+
+    #[test]
+    fn workload() {
+        let mut reactor = Reactor::<MetricsPool>::new().unwrap();
+        let mut controller = reactor.controller();
+        let (writer_socket, reader_socket) = MemSocket::pair();
+        let writer = writer_socket.id();
+        controller
+            .start_actor(MetricsPool::Main, writer_socket)
+            .unwrap();
+        controller
+            .start_actor(MetricsPool::Main, reader_socket)
+            .unwrap();
+        wait_for("reactor_resources_total", None, 2);
+
+        // Each frame is written and read in a lockstep, such that the number
+        // of the events is known in advance
+        let mut frames = 0;
+        let mut errors = 0;
+        let start = Instant::now();
+        while start.elapsed() < RUN_TIME {
+            if (frames + errors) % ERROR_EACH == ERROR_EACH - 1 {
+                controller.send(writer, vec![]).unwrap();
+                errors += 1;
+                wait_for("reactor_errors_total", None, errors);
+            } else {
+                controller.send(writer, vec![0xAB]).unwrap();
+                frames += 1;
+                wait_for("reactor_io_events_total", Some("readable"), frames);
+            }
+        }
+        assert!(frames > 100, "reactor is too slow");
+
+        assert_close("reactor_resources_total", None, 2);
+        assert_close("reactor_io_events_total", Some("readable"), frames);
+        assert_close("reactor_io_events_total", Some("writable"), frames);
+        assert_close("reactor_errors_total", None, errors);
+        assert_close("reactor_control_events_total", None, 2 + frames + errors);
+
+        let metrics = controller.metrics(MetricsPool::Main).unwrap();
+        assert_close(
+            "reactor_io_loop_duration_seconds",
+            None,
+            metrics.iterations as usize,
+        );
+
+        controller.stop_actor(writer).unwrap();
+        wait_for("reactor_resources_total", None, 1);
+
+        let mut text = vec![];
+        TextEncoder::new()
+            .encode(&registry().gather(), &mut text)
+            .unwrap();
+        let text = String::from_utf8(text).unwrap();
+        assert!(text.contains(&format!(
+            "reactor_io_events_total{{event=\"writable\"}} {frames}"
+        )));
+        assert!(text.contains("reactor_resources_total 1"));
+
+        reactor.shutdown().unwrap();
+    }
+}
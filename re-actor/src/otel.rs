@@ -0,0 +1,409 @@
+//! Tracing of the re-actor pool events with [OpenTelemetry].
+//!
+//! [`TracedHandler`] wraps the [`Handler`] of a pool and creates a span for
+//! each processed control event and for each I/O event dispatched to an actor:
+//!
+//! - `control` spans carry the `control.kind` attribute naming the request,
+//!   like `send` or `disconnect`;
+//! - `io_ready` spans carry the `resource.id`, `io.readable` and
+//!   `io.writable` attributes.
+//!
+//! If processing of the event fails, its span gets the `error` attribute with
+//! the error description and the error status.
+//!
+//! [OpenTelemetry]: https://opentelemetry.io
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use opentelemetry::global::{self, BoxedSpan, BoxedTracer};
+use opentelemetry::trace::{Span, Status, Tracer};
+use opentelemetry::{Context, KeyValue};
+
+use crate::actors::{DisconnectReason, IoEv};
+use crate::{Actor, Controller, Handler, InternalError, Layout, TimerToken};
+
+/// Provider of the trace context headers (like W3C `traceparent`) of an
+/// actor, see [`TracedHandler::with_carrier`].
+pub type Carrier<A> = Box<dyn FnMut(&<A as Actor>::Id) -> HashMap<String, String> + Send>;
+
+/// Pool handler wrapper reporting the pool events as OpenTelemetry spans.
+pub struct TracedHandler<L: Layout, H: Handler<L>> {
+    inner: H,
+    tracer: BoxedTracer,
+    carrier: Option<Carrier<L::RootActor>>,
+    span: Option<BoxedSpan>,
+    _phantom: PhantomData<L>,
+}
+
+impl<L: Layout, H: Handler<L>> TracedHandler<L, H> {
+    /// Wraps the handler, creating spans with the provided tracer (for
+    /// instance, one returned by [`global::tracer`]).
+    pub fn with_tracer(inner: H, tracer: BoxedTracer) -> Self {
+        TracedHandler {
+            inner,
+            tracer,
+            carrier: None,
+            span: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Sets provider of the trace context headers of the actors, which are
+    /// usually received from the remote peer. The context is extracted with
+    /// the global propagator (see [`global::set_text_map_propagator`]), like
+    /// the W3C TraceContext one, and becomes the parent of the `io_ready`
+    /// spans of the actor. Without the headers, or without the propagator
+    /// set, the spans are children of the current context.
+    pub fn with_carrier(
+        mut self,
+        carrier: impl FnMut(&<L::RootActor as Actor>::Id) -> HashMap<String, String> + Send + 'static,
+    ) -> Self {
+        self.carrier = Some(Box::new(carrier));
+        self
+    }
+
+    /// Returns the wrapped handler.
+    pub fn inner(&self) -> &H {
+        &self.inner
+    }
+
+    fn start(&mut self, name: &'static str, parent: &Context, attributes: Vec<KeyValue>) {
+        let mut span = self.tracer.start_with_context(name, parent);
+        span.set_attributes(attributes);
+        // Events are not nested, so there is no open span here
+        if let Some(mut span) = self.span.replace(span) {
+            span.end();
+        }
+    }
+
+    fn fail(&mut self, error: String) {
+        if let Some(span) = &mut self.span {
+            span.set_attribute(KeyValue::new("error", error.clone()));
+            span.set_status(Status::error(error));
+        }
+    }
+
+    fn end(&mut self) {
+        if let Some(mut span) = self.span.take() {
+            span.end();
+        }
+    }
+}
+
+impl<L: Layout, H: Handler<L>> Handler<L> for TracedHandler<L, H> {
+    fn handle_err(&mut self, err: InternalError<L>) {
+        self.fail(err.to_string());
+        self.inner.handle_err(err)
+    }
+
+    fn on_startup(&mut self, controller: Controller<L>) {
+        self.inner.on_startup(controller)
+    }
+
+    fn handle_panic(&mut self, id: &<L::RootActor as Actor>::Id, payload: Box<dyn Any + Send>) {
+        self.fail(format!("actor {id} has panicked"));
+        self.inner.handle_panic(id, payload)
+    }
+
+    fn on_timer(&mut self, token: TimerToken) {
+        self.inner.on_timer(token)
+    }
+
+    fn on_connect(&mut self, id: &<L::RootActor as Actor>::Id) {
+        self.inner.on_connect(id)
+    }
+
+    fn on_disconnect(&mut self, id: &<L::RootActor as Actor>::Id, reason: &DisconnectReason) {
+        self.inner.on_disconnect(id, reason)
+    }
+
+    fn on_hangup(&mut self, actor: L::RootActor) {
+        self.inner.on_hangup(actor)
+    }
+
+    fn on_disconnect_all(&mut self, count: usize) {
+        self.inner.on_disconnect_all(count)
+    }
+
+    fn on_circuit_open(&mut self, id: &<L::RootActor as Actor>::Id) {
+        self.inner.on_circuit_open(id)
+    }
+
+    fn on_circuit_close(&mut self, id: &<L::RootActor as Actor>::Id) {
+        self.inner.on_circuit_close(id)
+    }
+
+    fn on_idle(&mut self) {
+        self.inner.on_idle()
+    }
+
+    fn on_io(&mut self, id: &<L::RootActor as Actor>::Id, io: IoEv) {
+        let headers = self.carrier.as_mut().map(|carrier| carrier(id));
+        let parent = match headers {
+            Some(headers) if !headers.is_empty() => {
+                global::get_text_map_propagator(|propagator| propagator.extract(&headers))
+            }
+            _ => Context::current(),
+        };
+        self.start(
+            "io_ready",
+            &parent,
+            vec![
+                KeyValue::new("resource.id", id.to_string()),
+                KeyValue::new("io.readable", io.is_readable),
+                KeyValue::new("io.writable", io.is_writable),
+            ],
+        );
+        self.inner.on_io(id, io)
+    }
+
+    fn on_io_done(&mut self, id: &<L::RootActor as Actor>::Id) {
+        self.end();
+        self.inner.on_io_done(id)
+    }
+
+    fn on_control(&mut self, kind: &str) {
+        self.start(
+            "control",
+            &Context::current(),
+            vec![KeyValue::new("control.kind", kind.to_owned())],
+        );
+        self.inner.on_control(kind)
+    }
+
+    fn on_control_done(&mut self) {
+        self.end();
+        self.inner.on_control_done()
+    }
+
+    fn on_iteration(&mut self, duration: Duration) {
+        self.inner.on_iteration(duration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, OnceLock};
+    use std::thread;
+    use std::time::Instant;
+
+    use opentelemetry::trace::{SpanId, TraceId, TracerProvider};
+    use opentelemetry::Value;
+    use opentelemetry_sdk::propagation::TraceContextPropagator;
+    use opentelemetry_sdk::trace::{InMemorySpanExporter, SdkTracerProvider, SpanData};
+
+    use super::*;
+    use crate::actors::mem::{AsMemSocket, MemQueue, MemSocket};
+    use crate::schedulers::MemScheduler;
+    use crate::{Pool, Reactor, ReactorApi};
+
+    /// Number of messages sent by the test.
+    const MESSAGES: usize = 10;
+    /// Trace context of the remote peer of the actors.
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const PARENT_ID: &str = "00f067aa0ba902b7";
+
+    /// Pools are constructed by [`Layout::default_pools`], so the tracer
+    /// can't be passed to the handler otherwise.
+    static TRACING: OnceLock<(SdkTracerProvider, InMemorySpanExporter)> = OnceLock::new();
+
+    fn tracing() -> &'static (SdkTracerProvider, InMemorySpanExporter) {
+        TRACING.get_or_init(|| {
+            let exporter = InMemorySpanExporter::default();
+            let provider = SdkTracerProvider::builder()
+                .with_simple_exporter(exporter.clone())
+                .build();
+            (provider, exporter)
+        })
+    }
+
+    #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+    #[display(Debug)]
+    enum TracedPool {
+        Main,
+    }
+
+    impl From<u32> for TracedPool {
+        fn from(_: u32) -> Self {
+            TracedPool::Main
+        }
+    }
+
+    impl From<TracedPool> for u32 {
+        fn from(_: TracedPool) -> Self {
+            0
+        }
+    }
+
+    impl Layout for TracedPool {
+        type RootActor = Peer;
+
+        fn default_pools() -> Vec<Pool<Self::RootActor, Self>> {
+            let tracer = BoxedTracer::new(Box::new(tracing().0.tracer("re-actor")));
+            let handler = TracedHandler::with_tracer(IgnoreHandler, tracer).with_carrier(|_| {
+                let traceparent = format!("00-{TRACE_ID}-{PARENT_ID}-01");
+                HashMap::from([("traceparent".to_owned(), traceparent)])
+            });
+            vec![Pool::new(TracedPool::Main, MemScheduler::new(), handler)]
+        }
+
+        fn convert(_: Box<dyn Any>) -> MemSocket<TracedPool> {
+            unreachable!()
+        }
+    }
+
+    struct IgnoreHandler;
+
+    impl Handler<TracedPool> for IgnoreHandler {
+        fn handle_err(&mut self, _: InternalError<TracedPool>) {}
+    }
+
+    /// Socket which fails on receiving an empty frame.
+    struct Peer(MemSocket<TracedPool>);
+
+    impl AsMemSocket for Peer {
+        fn inbox(&self) -> &Arc<MemQueue> {
+            self.0.inbox()
+        }
+    }
+
+    impl Actor for Peer {
+        type Layout = TracedPool;
+        type Id = u64;
+        type Context = MemSocket<TracedPool>;
+        type Cmd = Vec<u8>;
+        type Error = io::Error;
+
+        fn with(socket: Self::Context, _: Controller<TracedPool>) -> Result<Self, Self::Error> {
+            Ok(Peer(socket))
+        }
+
+        fn id(&self) -> Self::Id {
+            self.0.id()
+        }
+
+        fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+            self.0.io_ready(io)?;
+            while let Some(frame) = self.0.recv_frame() {
+                if frame.is_empty() {
+                    return Err(io::ErrorKind::InvalidData.into());
+                }
+            }
+            Ok(())
+        }
+
+        fn handle_cmd(&mut self, frame: Self::Cmd) -> Result<(), Self::Error> {
+            self.0.handle_cmd(frame)
+        }
+
+        fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+            Err(err)
+        }
+
+        fn interests(&self) -> IoEv {
+            self.0.interests()
+        }
+
+        fn disconnect(&mut self) -> Result<(), Self::Error> {
+            self.0.disconnect()
+        }
+    }
+
+    fn attribute(span: &SpanData, key: &str) -> Option<Value> {
+        span.attributes
+            .iter()
+            .find(|kv| kv.key.as_str() == key)
+            .map(|kv| kv.value.clone())
+    }
+
+    /// Returns finished `io_ready` spans of the actor.
+    fn io_spans(id: u64) -> Vec<SpanData> {
+        tracing()
+            .1
+            .get_finished_spans()
+            .unwrap()
+            .into_iter()
+            .filter(|span| {
+                span.name == "io_ready"
+                    && attribute(span, "resource.id") == Some(id.to_string().into())
+            })
+            .collect()
+    }
+
+    fn wait_for_io_spans(id: u64, count: usize) -> Vec<SpanData> {
+        let start = Instant::now();
+        loop {
+            let spans = io_spans(id);
+            if spans.len() >= count {
+                return spans;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "only {} of {count} spans were reported",
+                spans.len()
+            );
+            thread::yield_now();
+        }
+    }
+
+    #[test]
+    fn spans() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let mut reactor = Reactor::<TracedPool>::new().unwrap();
+        let mut controller = reactor.controller();
+        let (client_socket, server_socket) = MemSocket::pair();
+        let client = client_socket.id();
+        let server = server_socket.id();
+        controller
+            .start_actor(TracedPool::Main, client_socket)
+            .unwrap();
+        controller
+            .start_actor(TracedPool::Main, server_socket)
+            .unwrap();
+        // Actors are added asynchronously by the pool thread
+        while !controller.contains_actor(&client).unwrap() {
+            thread::yield_now();
+        }
+
+        // Messages are sent in a lockstep, so each of them is reported with a
+        // separate I/O event
+        for no in 1..=MESSAGES {
+            controller.send(client, vec![no as u8]).unwrap();
+            wait_for_io_spans(server, no);
+        }
+        let spans = io_spans(server);
+        assert_eq!(spans.len(), MESSAGES);
+        for span in &spans {
+            assert_eq!(attribute(span, "io.readable"), Some(true.into()));
+            assert_eq!(attribute(span, "io.writable"), Some(false.into()));
+            assert_eq!(attribute(span, "error"), None);
+            assert_eq!(span.status, Status::Unset);
+            // Context provided by the carrier is propagated
+            assert_eq!(
+                span.span_context.trace_id(),
+                TraceId::from_hex(TRACE_ID).unwrap()
+            );
+            assert_eq!(span.parent_span_id, SpanId::from_hex(PARENT_ID).unwrap());
+        }
+        let sent = tracing()
+            .1
+            .get_finished_spans()
+            .unwrap()
+            .into_iter()
+            .filter(|span| attribute(span, "control.kind") == Some("send".into()))
+            .count();
+        assert_eq!(sent, MESSAGES);
+
+        controller.send(client, vec![]).unwrap();
+        let spans = wait_for_io_spans(server, MESSAGES + 1);
+        let failed = &spans[MESSAGES];
+        assert!(attribute(failed, "error").is_some());
+        assert!(matches!(failed.status, Status::Error { .. }));
+
+        reactor.shutdown().unwrap();
+    }
+}
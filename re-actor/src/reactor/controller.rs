@@ -1,10 +1,40 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use crossbeam_channel as chan;
 
-use super::runtime::ControlEvent;
+use super::runtime::{
+    is_pool_thread, BroadcastFilter, ControlEvent, QueryKind, QueryResponse, ReactorMetrics,
+};
+use crate::actors::{ActorSnapshot, Migrate};
+use crate::schedulers::{IoStats, Waker};
 use crate::{Actor, InternalError, Layout, Reactor};
 
+/// Default time for which [`Controller`] waits for the responses to queries.
+pub const DEFAULT_QUERY_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Identifier of a timer set with [`ReactorApi::set_timer`]. It is passed to
+/// [`Handler::on_timer`] once the timer expires and can be used to cancel the
+/// timer with [`ReactorApi::cancel_timer`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Display)]
+#[display("timer#{0}")]
+pub struct TimerToken(u64);
+
+impl TimerToken {
+    /// Reconstructs token from its raw representation, for instance used by
+    /// the schedulers to identify kernel timers.
+    pub(crate) fn from_raw(raw: u64) -> Self {
+        TimerToken(raw)
+    }
+
+    /// Returns raw representation of the token.
+    pub(crate) fn to_raw(self) -> u64 {
+        self.0
+    }
+}
+
 /// API for controlling the [`Reactor`] by the re-actor instance or through
 /// multiple [`Controller`]s constructed by [`Reactor::controller`].
 pub trait ReactorApi {
@@ -21,46 +51,376 @@ pub trait ReactorApi {
         ctx: <Self::Actor as Actor>::Context,
     ) -> Result<(), InternalError<Self::Pool>>;
 
-    /// Disconnects from a resource, providing a reason.
+    /// Connects new resource like [`ReactorApi::start_actor`], reporting the
+    /// outcome back via the returned channel: either the id of the started
+    /// actor or the error, which in this case is not passed to the
+    /// [`Handler::handle_err`].
+    fn start_actor_and_report(
+        &mut self,
+        pool: Self::Pool,
+        ctx: <Self::Actor as Actor>::Context,
+    ) -> Result<
+        chan::Receiver<Result<<Self::Actor as Actor>::Id, InternalError<Self::Pool>>>,
+        InternalError<Self::Pool>,
+    >
+    where
+        Self::Pool: 'static,
+        <Self::Actor as Actor>::Id: 'static,
+        <Self::Actor as Actor>::Error: Send + 'static;
+
+    /// Connects new resource like [`ReactorApi::start_actor`], retrying up to
+    /// `max_attempts` times if the actor construction fails. The first attempt
+    /// is made after `backoff` interval; each subsequent attempt waits for the
+    /// previous interval multiplied by `multiplier`.
+    ///
+    /// Once all attempts are exhausted, [`Handler::handle_err`] is called with
+    /// [`InternalError::ReconnectExhausted`], and then [`Handler::on_disconnect`]
+    /// is called for the provided `id` - usually the id of the actor which
+    /// connection has dropped - with [`DisconnectReason::ReconnectExhausted`].
+    fn reconnect(
+        &mut self,
+        pool: Self::Pool,
+        id: <Self::Actor as Actor>::Id,
+        ctx: <Self::Actor as Actor>::Context,
+        backoff: Duration,
+        multiplier: f64,
+        max_attempts: u32,
+    ) -> Result<(), InternalError<Self::Pool>>
+    where
+        <Self::Actor as Actor>::Context: Clone + 'static;
+
+    /// Adds already constructed actor, for instance previously taken with
+    /// [`ReactorApi::take_actor`], to the pool.
+    fn insert_actor(
+        &mut self,
+        pool: Self::Pool,
+        actor: Self::Actor,
+    ) -> Result<(), InternalError<Self::Pool>>
+    where
+        Self::Actor: Send + 'static;
+
+    /// Removes actor from the re-actor without disconnecting it, returning the
+    /// actor via the channel. This allows to temporarily process actor I/O
+    /// outside of the re-actor and to add it back later with
+    /// [`ReactorApi::insert_actor`].
+    ///
+    /// If the actor is not known to the re-actor the channel gets closed
+    /// without sending anything.
+    fn take_actor(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+    ) -> Result<chan::Receiver<Self::Actor>, InternalError<Self::Pool>>
+    where
+        Self::Actor: Send + 'static;
+
+    /// Replaces actor with another one without disconnecting it, returning
+    /// the replaced actor via the channel. The new actor may operate on the
+    /// same I/O resource, for instance upgrading the connection protocol, or
+    /// on a different one, and may have a different id. The replacement is
+    /// not reported to the [`Handler`].
+    ///
+    /// Commands sent to the actor before the call are processed by the
+    /// replaced actor, and the ones sent after the call - by the new actor.
+    ///
+    /// If the actor is not known to the re-actor, or the new actor can't be
+    /// added to it, the new actor is dropped and the channel gets closed
+    /// without sending anything.
+    fn replace_actor(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+        actor: Self::Actor,
+    ) -> Result<chan::Receiver<Self::Actor>, InternalError<Self::Pool>>
+    where
+        Self::Actor: Send + 'static;
+
+    /// Moves actor to the pool with the same id in another re-actor,
+    /// controlled by `target`, for instance for load balancing. The actor
+    /// state is passed there as a snapshot (see [`Migrate::snapshot`]), out of
+    /// which an equivalent actor is constructed with [`Migrate::restore`].
+    /// Once the snapshot is sent, the actor is disconnected locally, which is
+    /// reported to the [`Handler::on_disconnect`] with
+    /// [`DisconnectReason::Migrated`].
+    ///
+    /// If the snapshot can't be taken or sent to the target re-actor, the
+    /// actor keeps running and the error is reported to the
+    /// [`Handler::handle_err`].
+    fn migrate_actor(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+        target: Controller<Self::Pool>,
+    ) -> Result<(), InternalError<Self::Pool>>
+    where
+        Self::Actor: Migrate;
+
+    /// Adds actor restored from the `snapshot` with [`Migrate::restore`] to
+    /// the pool, for instance the snapshot taken before a process restart.
+    fn restore_actor(
+        &mut self,
+        pool: Self::Pool,
+        snapshot: ActorSnapshot,
+    ) -> Result<(), InternalError<Self::Pool>>
+    where
+        Self::Actor: Migrate;
+
+    /// Disconnects from a resource, providing a reason. Actors which are being
+    /// gracefully disconnected with [`ReactorApi::stop_actor_gracefully`] get
+    /// disconnected at once, without waiting for their output to be written.
     fn stop_actor(
         &mut self,
         id: <Self::Actor as Actor>::Id,
     ) -> Result<(), InternalError<Self::Pool>>;
 
+    /// Disconnects from a resource once it writes out its pending output (see
+    /// [`Actor::has_pending_output`]), waiting for at most `timeout`; after
+    /// that the actor gets disconnected anyway.
+    ///
+    /// Since the call the actor does not accept new commands, which fail with
+    /// [`InternalError::ActorDraining`].
+    fn stop_actor_gracefully(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+        timeout: Duration,
+    ) -> Result<(), InternalError<Self::Pool>>;
+
+    /// Shuts down writing half of the actor connection (see
+    /// [`Actor::shutdown_write`]), signalling the remote peer that no more
+    /// data will be sent, while still receiving data from it.
+    fn half_close(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+    ) -> Result<(), InternalError<Self::Pool>>;
+
+    /// Disconnects all actors in all pools at once, including the ones which
+    /// are being gracefully disconnected. Unlike the shutdown, actors are not
+    /// given a grace period to write out pending data, and the re-actor keeps
+    /// running and accepts new actors.
+    ///
+    /// Blocks until all pools respond or the query timeout expires; thus fails
+    /// with [`InternalError::WouldBlockPool`] if called from the re-actor pool
+    /// threads.
+    ///
+    /// # Returns
+    ///
+    /// Number of the disconnected actors.
+    fn disconnect_all(&mut self) -> Result<usize, InternalError<Self::Pool>>;
+
     /// Set one-time timer which will call [`Handler::on_timer`] upon expiration.
-    fn set_timer(&mut self, pool: Self::Pool) -> Result<(), InternalError<Self::Pool>>;
+    ///
+    /// # Returns
+    ///
+    /// Token identifying the timer, which can be used to cancel it with
+    /// [`ReactorApi::cancel_timer`].
+    fn set_timer(
+        &mut self,
+        pool: Self::Pool,
+        duration: Duration,
+    ) -> Result<TimerToken, InternalError<Self::Pool>>;
+
+    /// Cancels timer previously set with [`ReactorApi::set_timer`]. Does
+    /// nothing if the timer has already expired.
+    fn cancel_timer(
+        &mut self,
+        pool: Self::Pool,
+        token: TimerToken,
+    ) -> Result<(), InternalError<Self::Pool>>;
 
     /// Send data to the resource.
+    ///
+    /// If the control queue of the actor pool is full, blocks until the pool
+    /// processes some of the queued events. When called from a re-actor pool
+    /// thread, for instance by an actor, fails with
+    /// [`InternalError::ControlQueueFull`] instead, since the blocked pool
+    /// may never get its queue emptied.
     fn send(
         &mut self,
         id: <Self::Actor as Actor>::Id,
         cmd: <Self::Actor as Actor>::Cmd,
     ) -> Result<(), InternalError<Self::Pool>>;
+
+    /// Send data to the resource like [`ReactorApi::send`], but fails with
+    /// [`InternalError::ControlQueueFull`] instead of blocking if the control
+    /// queue of the actor pool is full.
+    fn try_send(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+        cmd: <Self::Actor as Actor>::Cmd,
+    ) -> Result<(), InternalError<Self::Pool>>;
+
+    /// Send data to the resource like [`ReactorApi::send`], but blocks for at
+    /// most `timeout` if the control queue of the actor pool is full, failing
+    /// with [`InternalError::ControlQueueFull`] afterwards.
+    fn send_with_timeout(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+        cmd: <Self::Actor as Actor>::Cmd,
+        timeout: Duration,
+    ) -> Result<(), InternalError<Self::Pool>>;
+
+    /// Sends the same command to all actors in all pools.
+    ///
+    /// Blocks until all pools respond or the query timeout expires; thus fails
+    /// with [`InternalError::WouldBlockPool`] if called from the re-actor pool
+    /// threads.
+    ///
+    /// # Returns
+    ///
+    /// Number of actors which have received the command.
+    fn broadcast(
+        &mut self,
+        cmd: <Self::Actor as Actor>::Cmd,
+    ) -> Result<usize, InternalError<Self::Pool>>;
+
+    /// Sends the same command to all actors in all pools for which the filter
+    /// returns `true` - for instance, to all peers except the one the
+    /// command originates from.
+    ///
+    /// Blocks until all pools respond or the query timeout expires; thus fails
+    /// with [`InternalError::WouldBlockPool`] if called from the re-actor pool
+    /// threads.
+    ///
+    /// # Returns
+    ///
+    /// Number of actors matching the filter which have received the command.
+    fn broadcast_filtered(
+        &mut self,
+        cmd: <Self::Actor as Actor>::Cmd,
+        filter: impl Fn(&<Self::Actor as Actor>::Id) -> bool + Send + Sync + 'static,
+    ) -> Result<usize, InternalError<Self::Pool>>;
 }
 
 /// Instance of re-actor controller which may be transferred between threads
 pub struct Controller<L: Layout> {
-    actor_map: HashMap<<L::RootActor as Actor>::Id, L>,
+    actor_map: Arc<Mutex<HashMap<<L::RootActor as Actor>::Id, L>>>,
+    /// Actors which are being gracefully disconnected.
+    draining: Arc<Mutex<HashSet<<L::RootActor as Actor>::Id>>>,
     channels: HashMap<L, chan::Sender<ControlEvent<L::RootActor>>>,
+    wakers: HashMap<L, Arc<dyn Waker>>,
+    timer_seq: Arc<AtomicU64>,
+    query_timeout: Duration,
 }
 
 impl<L: Layout> Clone for Controller<L> {
     fn clone(&self) -> Self {
         Controller {
             actor_map: self.actor_map.clone(),
+            draining: self.draining.clone(),
             channels: self.channels.clone(),
+            wakers: self.wakers.clone(),
+            timer_seq: self.timer_seq.clone(),
+            query_timeout: self.query_timeout,
         }
     }
 }
 
 impl<L: Layout> Controller<L> {
-    pub(super) fn new() -> Self {
+    pub(crate) fn new() -> Self {
         Controller {
-            actor_map: empty!(),
+            actor_map: Arc::new(Mutex::new(empty!())),
+            draining: Arc::new(Mutex::new(empty!())),
             channels: empty!(),
+            wakers: empty!(),
+            timer_seq: Arc::new(AtomicU64::new(0)),
+            query_timeout: DEFAULT_QUERY_TIMEOUT,
+        }
+    }
+
+    /// Sets time for which the controller waits for the re-actor pools to
+    /// respond to queries ([`Controller::actor_count`],
+    /// [`Controller::contains_actor`]).
+    pub fn set_query_timeout(&mut self, timeout: Duration) {
+        self.query_timeout = timeout;
+    }
+
+    /// Returns number of actors run by all re-actor pools.
+    ///
+    /// Blocks until all pools respond or the query timeout expires; thus must
+    /// not be called from the re-actor pool threads.
+    pub fn actor_count(&self) -> Result<usize, InternalError<L>> {
+        let mut count = 0;
+        for pool in self.channels.keys() {
+            match self.query(*pool, QueryKind::Count)? {
+                QueryResponse::Count(c) => count += c,
+                _ => panic!("re-actor pool has responded with a wrong query response"),
+            }
+        }
+        Ok(count)
+    }
+
+    /// Detects whether an actor with the given id is run by any of the
+    /// re-actor pools.
+    ///
+    /// Blocks until all pools respond or the query timeout expires; thus must
+    /// not be called from the re-actor pool threads.
+    pub fn contains_actor(
+        &self,
+        id: &<L::RootActor as Actor>::Id,
+    ) -> Result<bool, InternalError<L>> {
+        for pool in self.channels.keys() {
+            match self.query(*pool, QueryKind::Contains(id.clone()))? {
+                QueryResponse::Contains(true) => return Ok(true),
+                QueryResponse::Contains(false) => {}
+                _ => panic!("re-actor pool has responded with a wrong query response"),
+            }
+        }
+        Ok(false)
+    }
+
+    /// Returns ids of the actors run by all re-actor pools, as known to the
+    /// pools at the moment of the request. Actors which are being started or
+    /// are taken out of the pools are not listed.
+    ///
+    /// Blocks until all pools respond or the query timeout expires; thus must
+    /// not be called from the re-actor pool threads.
+    pub fn actors(&self) -> Result<Vec<<L::RootActor as Actor>::Id>, InternalError<L>> {
+        let mut actors = vec![];
+        for pool in self.channels.keys() {
+            match self.query(*pool, QueryKind::List)? {
+                QueryResponse::List(ids) => actors.extend(ids),
+                _ => panic!("re-actor pool has responded with a wrong query response"),
+            }
+        }
+        Ok(actors)
+    }
+
+    /// Returns snapshot of the metrics collected by the pool runtime since its
+    /// start.
+    ///
+    /// Blocks until the pool responds or the query timeout expires; thus must
+    /// not be called from the re-actor pool threads.
+    pub fn metrics(&self, pool: L) -> Result<ReactorMetrics, InternalError<L>> {
+        match self.query(pool, QueryKind::Metrics)? {
+            QueryResponse::Metrics(metrics) => Ok(metrics),
+            _ => panic!("re-actor pool has responded with a wrong query response"),
+        }
+    }
+
+    /// Returns counters of the I/O events delivered to each of the pool actors
+    /// by its scheduler (see [`crate::Scheduler::io_stats`]).
+    ///
+    /// Blocks until the pool responds or the query timeout expires; thus must
+    /// not be called from the re-actor pool threads.
+    pub fn io_stats(
+        &self,
+        pool: L,
+    ) -> Result<HashMap<<L::RootActor as Actor>::Id, IoStats>, InternalError<L>> {
+        match self.query(pool, QueryKind::IoStats)? {
+            QueryResponse::IoStats(stats) => Ok(stats.into_iter().collect()),
+            _ => panic!("re-actor pool has responded with a wrong query response"),
         }
     }
 
+    fn query(
+        &self,
+        pool: L,
+        kind: QueryKind<<L::RootActor as Actor>::Id>,
+    ) -> Result<QueryResponse<<L::RootActor as Actor>::Id>, InternalError<L>> {
+        let (reply_send, reply_recv) = chan::bounded(1);
+        self.send_event(pool, ControlEvent::Query(kind, reply_send))?;
+        reply_recv
+            .recv_timeout(self.query_timeout)
+            .map_err(|_| InternalError::QueryTimeout(pool))
+    }
+
     pub(super) fn channel_for(
         &self,
         pool: L,
@@ -73,37 +433,182 @@ impl<L: Layout> Controller<L> {
     /// Returns in which pool an actor is run in.
     pub fn pool_for(&self, id: <L::RootActor as Actor>::Id) -> Result<L, InternalError<L>> {
         self.actor_map
+            .lock()
+            .expect("actor map lock is poisoned")
             .get(&id)
-            .ok_or(InternalError::UnknownActor(id))
             .copied()
+            .ok_or(InternalError::UnknownActor(id))
     }
 
-    fn register_actor(
-        &mut self,
+    /// Detects whether an actor is being gracefully disconnected, failing
+    /// with [`InternalError::ActorDraining`] in this case.
+    fn check_draining(&self, id: &<L::RootActor as Actor>::Id) -> Result<(), InternalError<L>> {
+        if self
+            .draining
+            .lock()
+            .expect("draining set lock is poisoned")
+            .contains(id)
+        {
+            return Err(InternalError::ActorDraining(id.clone()));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn register_actor(
+        &self,
         id: <L::RootActor as Actor>::Id,
         pool: L,
     ) -> Result<(), InternalError<L>> {
         if !self.channels.contains_key(&pool) {
             return Err(InternalError::UnknownPool(pool));
         }
-        if self.actor_map.contains_key(&id) {
+        let mut actor_map = self.actor_map.lock().expect("actor map lock is poisoned");
+        if actor_map.contains_key(&id) {
             return Err(InternalError::RepeatedActor(id));
         }
-        self.actor_map.insert(id, pool);
+        actor_map.insert(id, pool);
         Ok(())
     }
 
-    pub(super) fn register_pool(
+    pub(crate) fn unregister_actor(&self, id: &<L::RootActor as Actor>::Id) {
+        self.actor_map
+            .lock()
+            .expect("actor map lock is poisoned")
+            .remove(id);
+        self.draining
+            .lock()
+            .expect("draining set lock is poisoned")
+            .remove(id);
+    }
+
+    pub(crate) fn register_pool(
         &mut self,
         pool: L,
         channel: chan::Sender<ControlEvent<L::RootActor>>,
+        waker: Arc<dyn Waker>,
     ) -> Result<(), InternalError<L>> {
         if self.channels.contains_key(&pool) {
             return Err(InternalError::RepeatedPoll(pool));
         }
         self.channels.insert(pool, channel);
+        self.wakers.insert(pool, waker);
         Ok(())
     }
+
+    /// Wakes up the pool runtime, so it processes new control events without
+    /// waiting for I/O.
+    pub(super) fn wake(&self, pool: L) -> Result<(), InternalError<L>> {
+        self.wakers
+            .get(&pool)
+            .ok_or(InternalError::UnknownPool(pool))?
+            .wake()
+            .map_err(|_| InternalError::WakeFailed(pool))
+    }
+
+    pub(crate) fn send_event(
+        &self,
+        pool: L,
+        event: ControlEvent<L::RootActor>,
+    ) -> Result<(), InternalError<L>> {
+        let channel = self.channel_for(pool)?;
+        match channel.try_send(event) {
+            Ok(()) => {}
+            Err(chan::TrySendError::Full(_)) if is_pool_thread() => {
+                // Blocking here may deadlock the pool, since this may be the
+                // thread which has to empty the queue
+                return Err(InternalError::ControlQueueFull(pool));
+            }
+            Err(chan::TrySendError::Full(event)) => {
+                // The pool must be processing its queue while we are blocked
+                self.wake(pool)?;
+                channel.send(event)?;
+            }
+            Err(chan::TrySendError::Disconnected(_)) => {
+                return Err(InternalError::ControlChannelBroken)
+            }
+        }
+        self.wake(pool)
+    }
+
+    fn try_send_event(
+        &self,
+        pool: L,
+        event: ControlEvent<L::RootActor>,
+    ) -> Result<(), InternalError<L>> {
+        self.channel_for(pool)?
+            .try_send(event)
+            .map_err(|err| match err {
+                chan::TrySendError::Full(_) => InternalError::ControlQueueFull(pool),
+                chan::TrySendError::Disconnected(_) => InternalError::ControlChannelBroken,
+            })?;
+        self.wake(pool)
+    }
+
+    fn send_event_timeout(
+        &self,
+        pool: L,
+        event: ControlEvent<L::RootActor>,
+        timeout: Duration,
+    ) -> Result<(), InternalError<L>> {
+        let channel = self.channel_for(pool)?;
+        match channel.try_send(event) {
+            Ok(()) => {}
+            Err(chan::TrySendError::Full(_)) if is_pool_thread() => {
+                return Err(InternalError::ControlQueueFull(pool));
+            }
+            Err(chan::TrySendError::Full(event)) => {
+                // The pool must be processing its queue while we are blocked
+                self.wake(pool)?;
+                channel
+                    .send_timeout(event, timeout)
+                    .map_err(|err| match err {
+                        chan::SendTimeoutError::Timeout(_) => InternalError::ControlQueueFull(pool),
+                        chan::SendTimeoutError::Disconnected(_) => {
+                            InternalError::ControlChannelBroken
+                        }
+                    })?;
+            }
+            Err(chan::TrySendError::Disconnected(_)) => {
+                return Err(InternalError::ControlChannelBroken)
+            }
+        }
+        self.wake(pool)
+    }
+
+    fn broadcast_event(
+        &self,
+        cmd: <L::RootActor as Actor>::Cmd,
+        filter: Option<BroadcastFilter<L::RootActor>>,
+    ) -> Result<usize, InternalError<L>> {
+        // The pool can't answer while its thread waits for the answers
+        if is_pool_thread() {
+            return Err(InternalError::WouldBlockPool);
+        }
+        let mut replies = Vec::with_capacity(self.channels.len());
+        for pool in self.channels.keys() {
+            let (reply_send, reply_recv) = chan::bounded(1);
+            self.send_event(
+                *pool,
+                ControlEvent::Broadcast(cmd.clone(), filter.clone(), reply_send),
+            )?;
+            replies.push((*pool, reply_recv));
+        }
+        self.sum_replies(replies)
+    }
+
+    /// Waits for the counts sent back by the pools, returning their sum.
+    fn sum_replies(
+        &self,
+        replies: Vec<(L, chan::Receiver<usize>)>,
+    ) -> Result<usize, InternalError<L>> {
+        let mut count = 0;
+        for (pool, reply) in replies {
+            count += reply
+                .recv_timeout(self.query_timeout)
+                .map_err(|_| InternalError::QueryTimeout(pool))?;
+        }
+        Ok(count)
+    }
 }
 
 impl<L: Layout> ReactorApi for Controller<L> {
@@ -115,18 +620,191 @@ impl<L: Layout> ReactorApi for Controller<L> {
         pool: L,
         ctx: <Self::Actor as Actor>::Context,
     ) -> Result<(), InternalError<L>> {
-        self.channel_for(pool)?.send(ControlEvent::Connect(ctx))?;
+        self.send_event(pool, ControlEvent::Connect(ctx))?;
         Ok(())
     }
 
+    fn start_actor_and_report(
+        &mut self,
+        pool: L,
+        ctx: <Self::Actor as Actor>::Context,
+    ) -> Result<
+        chan::Receiver<Result<<Self::Actor as Actor>::Id, InternalError<L>>>,
+        InternalError<L>,
+    >
+    where
+        L: 'static,
+        <Self::Actor as Actor>::Id: 'static,
+        <Self::Actor as Actor>::Error: Send + 'static,
+    {
+        let (send, recv) = chan::bounded(1);
+        let callback = Box::new(move |res| {
+            // Nothing to do if the receiver was dropped: the caller is not
+            // interested in the outcome
+            let _ = send.send(res);
+        });
+        self.send_event(pool, ControlEvent::ConnectReport(ctx, callback))?;
+        Ok(recv)
+    }
+
+    fn reconnect(
+        &mut self,
+        pool: L,
+        id: <Self::Actor as Actor>::Id,
+        ctx: <Self::Actor as Actor>::Context,
+        backoff: Duration,
+        multiplier: f64,
+        max_attempts: u32,
+    ) -> Result<(), InternalError<L>>
+    where
+        <Self::Actor as Actor>::Context: Clone + 'static,
+    {
+        self.send_event(
+            pool,
+            ControlEvent::Reconnect {
+                id,
+                context: Box::new(move || ctx.clone()),
+                backoff,
+                multiplier,
+                remaining: max_attempts,
+            },
+        )?;
+        Ok(())
+    }
+
+    fn insert_actor(&mut self, pool: L, actor: Self::Actor) -> Result<(), InternalError<L>>
+    where
+        Self::Actor: Send + 'static,
+    {
+        self.send_event(pool, ControlEvent::Insert(Box::new(move || actor)))
+    }
+
+    fn take_actor(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+    ) -> Result<chan::Receiver<Self::Actor>, InternalError<L>>
+    where
+        Self::Actor: Send + 'static,
+    {
+        let pool = self.pool_for(id.clone())?;
+        let (send, recv) = chan::bounded(1);
+        let callback = Box::new(move |actor| {
+            // Nothing to do if the receiver was dropped: the actor is dropped too
+            let _ = send.send(actor);
+        });
+        self.send_event(pool, ControlEvent::Take(id, callback))?;
+        Ok(recv)
+    }
+
+    fn replace_actor(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+        actor: Self::Actor,
+    ) -> Result<chan::Receiver<Self::Actor>, InternalError<L>>
+    where
+        Self::Actor: Send + 'static,
+    {
+        let pool = self.pool_for(id.clone())?;
+        let (send, recv) = chan::bounded(1);
+        let callback = Box::new(move |actor| {
+            // Nothing to do if the receiver was dropped: the actor is dropped too
+            let _ = send.send(actor);
+        });
+        self.send_event(
+            pool,
+            ControlEvent::Replace(id, Box::new(move || actor), callback),
+        )?;
+        Ok(recv)
+    }
+
+    fn migrate_actor(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+        target: Controller<L>,
+    ) -> Result<(), InternalError<L>>
+    where
+        Self::Actor: Migrate,
+    {
+        let pool = self.pool_for(id.clone())?;
+        if !target.channels.contains_key(&pool) {
+            return Err(InternalError::UnknownPool(pool));
+        }
+        self.send_event(
+            pool,
+            ControlEvent::Migrate(
+                id,
+                target,
+                <Self::Actor as Migrate>::snapshot,
+                <Self::Actor as Migrate>::restore,
+            ),
+        )
+    }
+
+    fn restore_actor(&mut self, pool: L, snapshot: ActorSnapshot) -> Result<(), InternalError<L>>
+    where
+        Self::Actor: Migrate,
+    {
+        self.send_event(
+            pool,
+            ControlEvent::Restore(snapshot, <Self::Actor as Migrate>::restore),
+        )
+    }
+
     fn stop_actor(&mut self, id: <Self::Actor as Actor>::Id) -> Result<(), InternalError<L>> {
         let pool = self.pool_for(id.clone())?;
-        self.channel_for(pool)?.send(ControlEvent::Disconnect(id))?;
+        self.send_event(pool, ControlEvent::Disconnect(id))?;
         Ok(())
     }
 
-    fn set_timer(&mut self, pool: L) -> Result<(), InternalError<L>> {
-        self.channel_for(pool)?.send(ControlEvent::SetTimer())?;
+    fn stop_actor_gracefully(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+        timeout: Duration,
+    ) -> Result<(), InternalError<L>> {
+        let pool = self.pool_for(id.clone())?;
+        self.draining
+            .lock()
+            .expect("draining set lock is poisoned")
+            .insert(id.clone());
+        let res = self.send_event(pool, ControlEvent::DisconnectGraceful(id.clone(), timeout));
+        if res.is_err() {
+            self.draining
+                .lock()
+                .expect("draining set lock is poisoned")
+                .remove(&id);
+        }
+        res
+    }
+
+    fn half_close(&mut self, id: <Self::Actor as Actor>::Id) -> Result<(), InternalError<L>> {
+        let pool = self.pool_for(id.clone())?;
+        self.send_event(pool, ControlEvent::HalfClose(id))
+    }
+
+    fn disconnect_all(&mut self) -> Result<usize, InternalError<L>> {
+        if is_pool_thread() {
+            return Err(InternalError::WouldBlockPool);
+        }
+        let mut replies = Vec::with_capacity(self.channels.len());
+        for pool in self.channels.keys() {
+            let (reply_send, reply_recv) = chan::bounded(1);
+            self.send_event(*pool, ControlEvent::DisconnectAll(reply_send))?;
+            replies.push((*pool, reply_recv));
+        }
+        self.sum_replies(replies)
+    }
+
+    fn set_timer(&mut self, pool: L, duration: Duration) -> Result<TimerToken, InternalError<L>> {
+        let token = TimerToken(self.timer_seq.fetch_add(1, Ordering::Relaxed));
+        self.send_event(
+            pool,
+            ControlEvent::SetTimer(token, Instant::now() + duration),
+        )?;
+        Ok(token)
+    }
+
+    fn cancel_timer(&mut self, pool: L, token: TimerToken) -> Result<(), InternalError<L>> {
+        self.send_event(pool, ControlEvent::CancelTimer(token))?;
         Ok(())
     }
 
@@ -136,9 +814,43 @@ impl<L: Layout> ReactorApi for Controller<L> {
         cmd: <Self::Actor as Actor>::Cmd,
     ) -> Result<(), InternalError<L>> {
         let pool = self.pool_for(id.clone())?;
-        self.channel_for(pool)?.send(ControlEvent::Send(id, cmd))?;
+        self.check_draining(&id)?;
+        self.send_event(pool, ControlEvent::Send(id, cmd))?;
         Ok(())
     }
+
+    fn try_send(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+        cmd: <Self::Actor as Actor>::Cmd,
+    ) -> Result<(), InternalError<L>> {
+        let pool = self.pool_for(id.clone())?;
+        self.check_draining(&id)?;
+        self.try_send_event(pool, ControlEvent::Send(id, cmd))
+    }
+
+    fn send_with_timeout(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+        cmd: <Self::Actor as Actor>::Cmd,
+        timeout: Duration,
+    ) -> Result<(), InternalError<L>> {
+        let pool = self.pool_for(id.clone())?;
+        self.check_draining(&id)?;
+        self.send_event_timeout(pool, ControlEvent::Send(id, cmd), timeout)
+    }
+
+    fn broadcast(&mut self, cmd: <Self::Actor as Actor>::Cmd) -> Result<usize, InternalError<L>> {
+        self.broadcast_event(cmd, None)
+    }
+
+    fn broadcast_filtered(
+        &mut self,
+        cmd: <Self::Actor as Actor>::Cmd,
+        filter: impl Fn(&<Self::Actor as Actor>::Id) -> bool + Send + Sync + 'static,
+    ) -> Result<usize, InternalError<L>> {
+        self.broadcast_event(cmd, Some(Arc::new(filter)))
+    }
 }
 
 impl<L: Layout> ReactorApi for Reactor<L> {
@@ -153,12 +865,110 @@ impl<L: Layout> ReactorApi for Reactor<L> {
         self.controller.start_actor(pool, ctx)
     }
 
+    fn start_actor_and_report(
+        &mut self,
+        pool: L,
+        ctx: <Self::Actor as Actor>::Context,
+    ) -> Result<
+        chan::Receiver<Result<<Self::Actor as Actor>::Id, InternalError<L>>>,
+        InternalError<L>,
+    >
+    where
+        L: 'static,
+        <Self::Actor as Actor>::Id: 'static,
+        <Self::Actor as Actor>::Error: Send + 'static,
+    {
+        self.controller.start_actor_and_report(pool, ctx)
+    }
+
+    fn reconnect(
+        &mut self,
+        pool: L,
+        id: <Self::Actor as Actor>::Id,
+        ctx: <Self::Actor as Actor>::Context,
+        backoff: Duration,
+        multiplier: f64,
+        max_attempts: u32,
+    ) -> Result<(), InternalError<L>>
+    where
+        <Self::Actor as Actor>::Context: Clone + 'static,
+    {
+        self.controller
+            .reconnect(pool, id, ctx, backoff, multiplier, max_attempts)
+    }
+
+    fn insert_actor(&mut self, pool: L, actor: Self::Actor) -> Result<(), InternalError<L>>
+    where
+        Self::Actor: Send + 'static,
+    {
+        self.controller.insert_actor(pool, actor)
+    }
+
+    fn take_actor(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+    ) -> Result<chan::Receiver<Self::Actor>, InternalError<L>>
+    where
+        Self::Actor: Send + 'static,
+    {
+        self.controller.take_actor(id)
+    }
+
+    fn replace_actor(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+        actor: Self::Actor,
+    ) -> Result<chan::Receiver<Self::Actor>, InternalError<L>>
+    where
+        Self::Actor: Send + 'static,
+    {
+        self.controller.replace_actor(id, actor)
+    }
+
+    fn migrate_actor(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+        target: Controller<L>,
+    ) -> Result<(), InternalError<L>>
+    where
+        Self::Actor: Migrate,
+    {
+        self.controller.migrate_actor(id, target)
+    }
+
+    fn restore_actor(&mut self, pool: L, snapshot: ActorSnapshot) -> Result<(), InternalError<L>>
+    where
+        Self::Actor: Migrate,
+    {
+        self.controller.restore_actor(pool, snapshot)
+    }
+
     fn stop_actor(&mut self, id: <Self::Actor as Actor>::Id) -> Result<(), InternalError<L>> {
         self.controller.stop_actor(id)
     }
 
-    fn set_timer(&mut self, pool: L) -> Result<(), InternalError<L>> {
-        self.controller.set_timer(pool)
+    fn stop_actor_gracefully(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+        timeout: Duration,
+    ) -> Result<(), InternalError<L>> {
+        self.controller.stop_actor_gracefully(id, timeout)
+    }
+
+    fn half_close(&mut self, id: <Self::Actor as Actor>::Id) -> Result<(), InternalError<L>> {
+        self.controller.half_close(id)
+    }
+
+    fn disconnect_all(&mut self) -> Result<usize, InternalError<L>> {
+        self.controller.disconnect_all()
+    }
+
+    fn set_timer(&mut self, pool: L, duration: Duration) -> Result<TimerToken, InternalError<L>> {
+        self.controller.set_timer(pool, duration)
+    }
+
+    fn cancel_timer(&mut self, pool: L, token: TimerToken) -> Result<(), InternalError<L>> {
+        self.controller.cancel_timer(pool, token)
     }
 
     fn send(
@@ -168,4 +978,33 @@ impl<L: Layout> ReactorApi for Reactor<L> {
     ) -> Result<(), InternalError<L>> {
         self.controller.send(id, cmd)
     }
+
+    fn try_send(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+        cmd: <Self::Actor as Actor>::Cmd,
+    ) -> Result<(), InternalError<L>> {
+        self.controller.try_send(id, cmd)
+    }
+
+    fn send_with_timeout(
+        &mut self,
+        id: <Self::Actor as Actor>::Id,
+        cmd: <Self::Actor as Actor>::Cmd,
+        timeout: Duration,
+    ) -> Result<(), InternalError<L>> {
+        self.controller.send_with_timeout(id, cmd, timeout)
+    }
+
+    fn broadcast(&mut self, cmd: <Self::Actor as Actor>::Cmd) -> Result<usize, InternalError<L>> {
+        self.controller.broadcast(cmd)
+    }
+
+    fn broadcast_filtered(
+        &mut self,
+        cmd: <Self::Actor as Actor>::Cmd,
+        filter: impl Fn(&<Self::Actor as Actor>::Id) -> bool + Send + Sync + 'static,
+    ) -> Result<usize, InternalError<L>> {
+        self.controller.broadcast_filtered(cmd, filter)
+    }
 }
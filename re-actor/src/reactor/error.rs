@@ -16,9 +16,15 @@ pub enum InternalError<L: Layout> {
     /// control channel is broken; unable to send request
     ControlChannelBroken,
 
+    /// control queue of pool {0} is full
+    ControlQueueFull(L),
+
     /// actor with id {0} is not known to the re-actor
     UnknownActor(<L::RootActor as Actor>::Id),
 
+    /// actor with id {0} is being disconnected and does not accept commands
+    ActorDraining(<L::RootActor as Actor>::Id),
+
     /// unknown pool {0}
     UnknownPool(L),
 
@@ -33,6 +39,18 @@ pub enum InternalError<L: Layout> {
 
     /// error joining thread pool runtime {0}
     ThreadError(L),
+
+    /// pool {0} has not responded to the query in time
+    QueryTimeout(L),
+
+    /// request waiting for the pools can't be made from a pool thread
+    WouldBlockPool,
+
+    /// unable to wake up pool {0}
+    WakeFailed(L),
+
+    /// all reconnection attempts on pool {0} have failed. Last error: {1}
+    ReconnectExhausted(L, <L::RootActor as Actor>::Error),
 }
 
 // Required due to Derive macro adding L::RootActor: Debug unnecessary constraint
@@ -45,10 +63,18 @@ impl<L: Layout> Debug for InternalError<L> {
             InternalError::ControlChannelBroken => f
                 .debug_tuple("InternalError::ControlChannelBroken")
                 .finish(),
+            InternalError::ControlQueueFull(pool) => f
+                .debug_tuple("InternalError::ControlQueueFull")
+                .field(pool)
+                .finish(),
             InternalError::UnknownActor(id) => f
                 .debug_tuple("InternalError::UnknownActor")
                 .field(id)
                 .finish(),
+            InternalError::ActorDraining(id) => f
+                .debug_tuple("InternalError::ActorDraining")
+                .field(id)
+                .finish(),
             InternalError::UnknownPool(pool) => f
                 .debug_tuple("InternalError::UnknownPool")
                 .field(pool)
@@ -70,6 +96,22 @@ impl<L: Layout> Debug for InternalError<L> {
                 .debug_tuple("InternalError::ThreadError")
                 .field(pool)
                 .finish(),
+            InternalError::QueryTimeout(pool) => f
+                .debug_tuple("InternalError::QueryTimeout")
+                .field(pool)
+                .finish(),
+            InternalError::WouldBlockPool => {
+                f.debug_tuple("InternalError::WouldBlockPool").finish()
+            }
+            InternalError::WakeFailed(pool) => f
+                .debug_tuple("InternalError::WakeFailed")
+                .field(pool)
+                .finish(),
+            InternalError::ReconnectExhausted(pool, err) => f
+                .debug_tuple("InternalError::ReconnectExhausted")
+                .field(pool)
+                .field(err)
+                .finish(),
         }
     }
 }
@@ -0,0 +1,327 @@
+use std::fmt::{self, Display, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::actors::IoEv;
+use crate::{InternalError, Layout};
+
+/// Number of the entries kept by the event log of a [`crate::Reactor`].
+pub const EVENT_LOG_LEN: usize = 1024;
+
+/// Maximal length of the resource id stored in an [`EventEntry`]; longer ids
+/// are truncated.
+pub const MAX_ENTRY_ID_LEN: usize = 64;
+
+/// Maximal length of the error description stored in an [`EventEntry`];
+/// longer descriptions are truncated.
+pub const MAX_ENTRY_DETAIL_LEN: usize = 128;
+
+/// Kind of an event recorded in the [`EventLog`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum EventKind {
+    /// Control event processed by a pool, named like `send` or `disconnect`.
+    Control(&'static str),
+    /// I/O event dispatched to an actor.
+    Io(IoEv),
+    /// Error reported to the pool handler.
+    Error,
+}
+
+/// String stored inline, such that recording it does not allocate.
+#[derive(Copy, Clone)]
+struct InlineStr<const CAP: usize> {
+    buf: [u8; CAP],
+    len: usize,
+}
+
+impl<const CAP: usize> InlineStr<CAP> {
+    const fn new() -> Self {
+        InlineStr {
+            buf: [0u8; CAP],
+            len: 0,
+        }
+    }
+
+    /// Formats value into the string, truncating it at a character boundary
+    /// if it does not fit.
+    fn format(value: &dyn Display) -> Self {
+        let mut s = Self::new();
+        // Error only signals truncation
+        let _ = write!(s, "{value}");
+        s
+    }
+
+    fn as_str(&self) -> &str {
+        std::str::from_utf8(&self.buf[..self.len]).expect("only complete characters are stored")
+    }
+}
+
+impl<const CAP: usize> Write for InlineStr<CAP> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let mut len = s.len().min(CAP - self.len);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        self.buf[self.len..self.len + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        if len < s.len() {
+            return Err(fmt::Error);
+        }
+        Ok(())
+    }
+}
+
+/// Event recorded in the [`EventLog`].
+#[derive(Copy, Clone)]
+pub struct EventEntry {
+    kind: EventKind,
+    time: SystemTime,
+    id: InlineStr<MAX_ENTRY_ID_LEN>,
+    detail: InlineStr<MAX_ENTRY_DETAIL_LEN>,
+}
+
+impl EventEntry {
+    /// Returns kind of the event.
+    pub fn kind(&self) -> EventKind {
+        self.kind
+    }
+
+    /// Returns wall-clock time of the event.
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+
+    /// Returns id of the actor the event relates to, or an empty string if
+    /// the event does not relate to a specific actor.
+    pub fn resource_id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    /// Returns description of the error for [`EventKind::Error`] events, or
+    /// an empty string otherwise.
+    pub fn detail(&self) -> &str {
+        self.detail.as_str()
+    }
+
+    fn write_json(&self, json: &mut String) {
+        let nanos = self
+            .time
+            .duration_since(UNIX_EPOCH)
+            .map(|time| time.as_nanos())
+            .unwrap_or_default();
+        let _ = write!(json, r#"{{"time_ns":{nanos},"kind":"#);
+        match self.kind {
+            EventKind::Control(name) => {
+                json.push_str(r#""control","name":"#);
+                write_json_str(json, name);
+            }
+            EventKind::Io(io) => {
+                let _ = write!(
+                    json,
+                    r#""io","readable":{},"writable":{},"hangup":{},"error":{}"#,
+                    io.is_readable, io.is_writable, io.is_hangup, io.is_error
+                );
+            }
+            EventKind::Error => {
+                json.push_str(r#""error","detail":"#);
+                write_json_str(json, self.detail());
+            }
+        }
+        json.push_str(r#","resource_id":"#);
+        write_json_str(json, self.resource_id());
+        json.push('}');
+    }
+}
+
+impl fmt::Debug for EventEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventEntry")
+            .field("kind", &self.kind)
+            .field("time", &self.time)
+            .field("resource_id", &self.resource_id())
+            .field("detail", &self.detail())
+            .finish()
+    }
+}
+
+/// Writes string as a JSON string literal.
+fn write_json_str(json: &mut String, s: &str) {
+    json.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+/// Ring buffer keeping the last `N` events processed by the re-actor pools,
+/// for post-mortem debugging.
+///
+/// Entries have a fixed size and the buffer is allocated once, so recording
+/// events does not allocate; ids and error descriptions are truncated to
+/// [`MAX_ENTRY_ID_LEN`] and [`MAX_ENTRY_DETAIL_LEN`] bytes.
+#[derive(Clone, Debug)]
+pub struct EventLog<const N: usize> {
+    entries: Vec<EventEntry>,
+    /// Position of the oldest entry once the buffer is full.
+    start: usize,
+    recorded: u64,
+}
+
+impl<const N: usize> Default for EventLog<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> EventLog<N> {
+    pub fn new() -> Self {
+        EventLog {
+            entries: Vec::with_capacity(N),
+            start: 0,
+            recorded: 0,
+        }
+    }
+
+    /// Returns number of the entries in the log.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Detects whether no events were recorded yet.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Returns number of the events recorded since the log creation,
+    /// including the ones which were already evicted from the log.
+    pub fn recorded(&self) -> u64 {
+        self.recorded
+    }
+
+    /// Iterates over the entries from the oldest to the most recent one.
+    pub fn entries(&self) -> impl Iterator<Item = &EventEntry> {
+        let (newer, older) = self.entries.split_at(self.start);
+        older.iter().chain(newer)
+    }
+
+    /// Serializes the entries, from the oldest to the most recent one, as a
+    /// JSON array.
+    pub fn dump_json(&self) -> String {
+        let mut json = String::from("[");
+        for (no, entry) in self.entries().enumerate() {
+            if no > 0 {
+                json.push(',');
+            }
+            entry.write_json(&mut json);
+        }
+        json.push(']');
+        json
+    }
+
+    /// Records event related to the actor with the given id, if any.
+    pub(crate) fn record(&mut self, kind: EventKind, id: Option<&dyn Display>) {
+        self.push(EventEntry {
+            kind,
+            time: SystemTime::now(),
+            id: id.map(InlineStr::format).unwrap_or(InlineStr::new()),
+            detail: InlineStr::new(),
+        })
+    }
+
+    /// Records error reported by a pool.
+    pub(crate) fn record_err<L: Layout>(&mut self, err: &InternalError<L>) {
+        let id = match err {
+            InternalError::UnknownActor(id)
+            | InternalError::ActorDraining(id)
+            | InternalError::RepeatedActor(id) => InlineStr::format(id),
+            _ => InlineStr::new(),
+        };
+        self.push(EventEntry {
+            kind: EventKind::Error,
+            time: SystemTime::now(),
+            id,
+            detail: InlineStr::format(err),
+        })
+    }
+
+    fn push(&mut self, entry: EventEntry) {
+        if N == 0 {
+            return;
+        }
+        self.recorded += 1;
+        if self.entries.len() < N {
+            self.entries.push(entry);
+        } else {
+            self.entries[self.start] = entry;
+            self.start = (self.start + 1) % N;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_most_recent_entries() {
+        let mut log = EventLog::<EVENT_LOG_LEN>::new();
+        let capacity = log.entries.capacity();
+        for no in 0..EVENT_LOG_LEN + 500 {
+            log.record(EventKind::Control("send"), Some(&no));
+        }
+        // Buffer is never reallocated
+        assert_eq!(log.entries.capacity(), capacity);
+        assert_eq!(log.len(), EVENT_LOG_LEN);
+        assert_eq!(log.recorded(), EVENT_LOG_LEN as u64 + 500);
+        let ids = log
+            .entries()
+            .map(|entry| entry.resource_id().parse::<usize>().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(ids, (500..EVENT_LOG_LEN + 500).collect::<Vec<_>>());
+        assert!(log
+            .entries()
+            .zip(log.entries().skip(1))
+            .all(|(a, b)| a.time() <= b.time()));
+    }
+
+    #[test]
+    fn truncated_id() {
+        let mut log = EventLog::<1>::new();
+        // Multi-byte characters are not split
+        let id = "é".repeat(MAX_ENTRY_ID_LEN);
+        log.record(EventKind::Error, Some(&id));
+        let entry = log.entries().next().unwrap();
+        assert_eq!(entry.resource_id(), "é".repeat(MAX_ENTRY_ID_LEN / 2));
+    }
+
+    #[test]
+    fn json() {
+        let mut log = EventLog::<2>::new();
+        assert_eq!(log.dump_json(), "[]");
+        log.record(EventKind::Control("send"), Some(&"a\"b"));
+        log.record(
+            EventKind::Io(IoEv {
+                is_readable: true,
+                is_writable: false,
+                is_hangup: false,
+                is_error: false,
+            }),
+            None,
+        );
+        let json = log.dump_json();
+        assert!(json.starts_with(r#"[{"time_ns":"#));
+        assert!(json.contains(r#""kind":"control","name":"send","resource_id":"a\"b"}"#));
+        assert!(json.contains(
+            r#""kind":"io","readable":true,"writable":false,"hangup":false,"error":false,"resource_id":""}]"#
+        ));
+    }
+}
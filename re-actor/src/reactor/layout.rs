@@ -1,7 +1,9 @@
 use std::any::Any;
 use std::fmt::{Debug, Display};
 use std::hash::Hash;
+use std::time::Duration;
 
+use super::runtime::{DEFAULT_MAX_IO_EVENTS, DEFAULT_MAX_IO_EVENTS_PER_ACTOR};
 use super::Handler;
 use crate::{Actor, Scheduler};
 
@@ -10,6 +12,10 @@ pub struct Pool<R: Actor, L: Layout> {
     pub(super) id: L,
     pub(super) scheduler: Box<dyn Scheduler<R>>,
     pub(super) handler: Box<dyn Handler<L>>,
+    pub(super) idle_timeout: Option<Duration>,
+    pub(super) actor_idle: bool,
+    pub(super) max_io_events: usize,
+    pub(super) max_io_events_per_actor: usize,
 }
 
 impl<R: Actor, L: Layout> Pool<R, L> {
@@ -23,8 +29,39 @@ impl<R: Actor, L: Layout> Pool<R, L> {
             id,
             scheduler: Box::new(scheduler),
             handler: Box::new(handler),
+            idle_timeout: None,
+            actor_idle: false,
+            max_io_events: DEFAULT_MAX_IO_EVENTS,
+            max_io_events_per_actor: DEFAULT_MAX_IO_EVENTS_PER_ACTOR,
         }
     }
+
+    /// Makes the pool runtime call [`Handler::on_idle`] once per `timeout` in
+    /// which there were neither I/O events nor fired timers.
+    pub fn with_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Makes the pool runtime to call [`Actor::on_idle`] for each of the
+    /// actors when the pool is idle, in addition to [`Handler::on_idle`].
+    pub fn with_actor_idle(mut self) -> Self {
+        self.actor_idle = true;
+        self
+    }
+
+    /// Limits number of I/O events the pool runtime dispatches in a single
+    /// iteration of its event loop, in total and to a single actor, such that
+    /// a flooding connection does not delay the commands and the I/O of other
+    /// actors. Events over the budget are dispatched in the next iterations,
+    /// after processing the pending control events. Zero limits are treated
+    /// as one. Defaults to [`DEFAULT_MAX_IO_EVENTS`] and
+    /// [`DEFAULT_MAX_IO_EVENTS_PER_ACTOR`].
+    pub fn with_io_budget(mut self, max_events: usize, max_events_per_actor: usize) -> Self {
+        self.max_io_events = max_events;
+        self.max_io_events_per_actor = max_events_per_actor;
+        self
+    }
 }
 
 /// Trait layout out the structure for the re-actor runtime.
@@ -1,53 +1,223 @@
 mod controller;
 mod error;
+mod event_log;
 mod layout;
 mod runtime;
+#[cfg(test)]
+pub(crate) mod tests;
+mod watchdog;
 
+use std::any::Any;
 use std::collections::HashMap;
-use std::thread;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, Mutex};
 use std::thread::JoinHandle;
+use std::time::Duration;
+use std::{mem, thread};
 
 use crossbeam_channel as chan;
 
-pub use controller::{Controller, ReactorApi};
+pub use controller::{Controller, ReactorApi, TimerToken, DEFAULT_QUERY_TIMEOUT};
 pub use error::InternalError;
+pub use event_log::{
+    EventEntry, EventKind, EventLog, EVENT_LOG_LEN, MAX_ENTRY_DETAIL_LEN, MAX_ENTRY_ID_LEN,
+};
 pub use layout::{Layout, Pool};
 
-use self::runtime::{ControlEvent, PoolRuntime};
-use crate::Scheduler;
+use self::runtime::PoolRuntime;
+pub use self::runtime::{
+    BroadcastFilter, ReactorMetrics, DEFAULT_MAX_IO_EVENTS, DEFAULT_MAX_IO_EVENTS_PER_ACTOR,
+    MAX_CONTROL_EVENTS,
+};
+pub(crate) use self::runtime::{ContextFactory, ControlEvent, QueryKind, QueryResponse};
+pub use self::watchdog::{StallDetector, WatchdogReactor};
+use crate::actors::{DisconnectReason, IoEv};
+#[cfg(feature = "config")]
+use crate::config::ReactorConfig;
+use crate::{Actor, Scheduler};
 
 /// Callbacks called in a context of the re-actor runtime threads.
 pub trait Handler<L: Layout>: Send {
     /// Called on non-actor-specific errors - or on errors which were not held
     /// by the actors
     fn handle_err(&mut self, err: InternalError<L>);
+
+    /// Called once when the pool runtime starts, before it processes any I/O
+    /// or control events. The handler may keep the controller and use it to
+    /// start the initial set of actors of the pool itself.
+    ///
+    /// With a bounded control queue (see [`Reactor::with_capacity`]) requests
+    /// sent to the same pool from this callback fail with
+    /// [`InternalError::ControlQueueFull`] once the queue is full, since it is
+    /// emptied only after the callback returns.
+    fn on_startup(&mut self, _controller: Controller<L>) {}
+
+    /// Called when an actor panics while handling an I/O event or a command,
+    /// with the panic payload. By this time the actor is already removed from
+    /// the re-actor and dropped without being disconnected, while
+    /// [`Handler::on_disconnect`] is called with
+    /// [`DisconnectReason::Panicked`]. Other actors of the pool keep running.
+    fn handle_panic(&mut self, _id: &<L::RootActor as Actor>::Id, _payload: Box<dyn Any + Send>) {}
+
+    /// Called when a timer set with [`ReactorApi::set_timer`] expires.
+    fn on_timer(&mut self, _token: TimerToken) {}
+
+    /// Called each time an actor is added to the pool: once it is started
+    /// (including reconnections) or inserted with [`ReactorApi::insert_actor`].
+    fn on_connect(&mut self, _id: &<L::RootActor as Actor>::Id) {}
+
+    /// Called before an actor is removed from the pool and disconnected, with
+    /// the reason of the disconnection. Actors taken out of the pool with
+    /// [`ReactorApi::take_actor`] or replaced with
+    /// [`ReactorApi::replace_actor`] are not reported.
+    fn on_disconnect(&mut self, _id: &<L::RootActor as Actor>::Id, _reason: &DisconnectReason) {}
+
+    /// Called when the scheduler reports a hang-up or an error condition on
+    /// the actor I/O, after the event was passed to [`Actor::io_ready`] and
+    /// [`Handler::on_disconnect`] was called. By this time the actor is
+    /// already removed from the re-actor; it is handed
+    /// over to the handler so the data buffered by it are not lost. Default
+    /// implementation just drops the actor.
+    fn on_hangup(&mut self, _actor: L::RootActor) {}
+
+    /// Called after all actors of the pool were disconnected with
+    /// [`ReactorApi::disconnect_all`], providing number of the disconnected
+    /// actors.
+    fn on_disconnect_all(&mut self, _count: usize) {}
+
+    /// Called once the circuit breaker of an actor opens after its repeated
+    /// failures (see [`Actor::is_circuit_open`]), so the actor stops
+    /// processing I/O events until the circuit gets closed.
+    fn on_circuit_open(&mut self, _id: &<L::RootActor as Actor>::Id) {}
+
+    /// Called once the circuit breaker of an actor reported with
+    /// [`Handler::on_circuit_open`] closes after a successful probe.
+    fn on_circuit_close(&mut self, _id: &<L::RootActor as Actor>::Id) {}
+
+    /// Called once the pool has neither dispatched I/O events nor fired timers
+    /// during the idle timeout set with [`Pool::with_idle_timeout`], and then
+    /// after each further idle timeout. Never called for pools without the
+    /// idle timeout.
+    fn on_idle(&mut self) {}
+
+    /// Called for each I/O event before it is dispatched to the actor,
+    /// including the actors being gracefully disconnected.
+    fn on_io(&mut self, _id: &<L::RootActor as Actor>::Id, _io: IoEv) {}
+
+    /// Called once the I/O event reported with [`Handler::on_io`] is
+    /// processed. Errors and disconnections caused by the event are reported
+    /// to the handler before this call.
+    fn on_io_done(&mut self, _id: &<L::RootActor as Actor>::Id) {}
+
+    /// Called for each control event processed by the pool: a request sent
+    /// with [`ReactorApi`] or a command to an actor. The `kind` names the
+    /// request, like `send` or `disconnect`.
+    fn on_control(&mut self, _kind: &str) {}
+
+    /// Called once the control event reported with [`Handler::on_control`]
+    /// is processed. Errors caused by the event are reported to the handler
+    /// before this call.
+    fn on_control_done(&mut self) {}
+
+    /// Called at the end of each event loop iteration with the time it took,
+    /// excluding the time spent waiting for I/O.
+    fn on_iteration(&mut self, _duration: Duration) {}
 }
 
+/// Default time given to the actors to write out pending data during the
+/// re-actor shutdown.
+pub const DEFAULT_SHUTDOWN_GRACE: Duration = Duration::from_secs(3);
+
 /// Reactor, which provisioned with information about schedulers thread
 /// [`Layout`] can run the re-actor runtime with [`Reactor::run`].
 pub struct Reactor<L: Layout> {
     /// Threads running schedulers, one per pool.
     scheduler_threads: HashMap<L, JoinHandle<()>>,
-    shutdown_send: chan::Sender<()>,
+    /// Dropped together with the re-actor, signalling the pools to shut down.
+    shutdown_send: Option<chan::Sender<()>>,
     shutdown_recv: chan::Receiver<()>,
     controller: Controller<L>,
+    /// Log of the most recent events processed by all the pools.
+    event_log: Arc<Mutex<EventLog<EVENT_LOG_LEN>>>,
+    /// Counters of the event loop iterations of each pool.
+    heartbeats: HashMap<L, Arc<AtomicU64>>,
     locked: bool,
 }
 
 impl<L: Layout> Reactor<L> {
     /// Constructs re-actor and runs it in a thread, returning [`Self`] as a
     /// controller exposing the API ([`ReactorApi`]).
+    ///
+    /// Uses [`DEFAULT_SHUTDOWN_GRACE`] as the shutdown grace period.
     pub fn new() -> Result<Self, InternalError<L>>
     where
         L: 'static,
     {
-        let (shutdown_send, shutdown_recv) = chan::bounded(1);
+        Self::with(DEFAULT_SHUTDOWN_GRACE)
+    }
+
+    /// Constructs re-actor and runs it in a thread, returning [`Self`] as a
+    /// controller exposing the API ([`ReactorApi`]).
+    ///
+    /// During [`Reactor::shutdown`] actors with pending output are given
+    /// `shutdown_grace` period to write it out, after which they get
+    /// disconnected anyway.
+    pub fn with(shutdown_grace: Duration) -> Result<Self, InternalError<L>>
+    where
+        L: 'static,
+    {
+        Self::init(shutdown_grace, None, |pool| pool)
+    }
+
+    /// Constructs re-actor like [`Reactor::with`], limiting control queue of
+    /// each pool to `control_capacity` events.
+    ///
+    /// Once the queue is full, [`ReactorApi`] requests block until the pool
+    /// processes some of the queued events, while [`ReactorApi::try_send`]
+    /// and [`ReactorApi::send_with_timeout`] fail with
+    /// [`InternalError::ControlQueueFull`]. Requests sent from the pool
+    /// threads, like the ones sent by the actors, the handlers or during the
+    /// actor migration, never block and fail with the same error instead.
+    pub fn with_capacity(
+        shutdown_grace: Duration,
+        control_capacity: usize,
+    ) -> Result<Self, InternalError<L>>
+    where
+        L: 'static,
+    {
+        Self::init(shutdown_grace, Some(control_capacity), |pool| pool)
+    }
+
+    /// Constructs re-actor like [`Reactor::with_capacity`], taking the
+    /// parameters from the `config` and applying its pool settings to each of
+    /// the pools provided by [`Layout::default_pools`].
+    #[cfg(feature = "config")]
+    pub fn with_config(config: &ReactorConfig) -> Result<Self, InternalError<L>>
+    where
+        L: 'static,
+    {
+        Self::init(config.shutdown_grace, config.control_capacity, |pool| {
+            config.pool.apply(pool)
+        })
+    }
+
+    fn init(
+        shutdown_grace: Duration,
+        control_capacity: Option<usize>,
+        configure: impl Fn(Pool<L::RootActor, L>) -> Pool<L::RootActor, L>,
+    ) -> Result<Self, InternalError<L>>
+    where
+        L: 'static,
+    {
+        let (shutdown_send, shutdown_recv) = chan::unbounded();
 
         let mut reactor = Reactor {
             scheduler_threads: empty!(),
-            shutdown_send,
+            shutdown_send: Some(shutdown_send),
             shutdown_recv: shutdown_recv.clone(),
             controller: Controller::new(),
+            event_log: default!(),
+            heartbeats: empty!(),
             locked: false,
         };
 
@@ -61,12 +231,20 @@ impl<L: Layout> Reactor<L> {
             control_send: chan::Sender<ControlEvent<L::RootActor>>,
             shutdown: chan::Receiver<()>,
             handler: Box<dyn Handler<L>>,
+            idle_timeout: Option<Duration>,
+            actor_idle: bool,
+            max_io_events: usize,
+            max_io_events_per_actor: usize,
         }
 
-        for info in L::default_pools() {
-            let (control_send, control_recv) = chan::unbounded();
+        for info in L::default_pools().into_iter().map(configure) {
+            let (control_send, control_recv) = match control_capacity {
+                Some(capacity) => chan::bounded(capacity),
+                None => chan::unbounded(),
+            };
             let shutdown = shutdown_recv.clone();
             let control = control_send.clone();
+            let waker = info.scheduler.waker();
 
             pools.push(Info {
                 id: info.id,
@@ -75,13 +253,20 @@ impl<L: Layout> Reactor<L> {
                 control_send,
                 shutdown,
                 handler: info.handler,
+                idle_timeout: info.idle_timeout,
+                actor_idle: info.actor_idle,
+                max_io_events: info.max_io_events,
+                max_io_events_per_actor: info.max_io_events_per_actor,
             });
 
-            reactor.controller.register_pool(info.id, control)?;
+            reactor.controller.register_pool(info.id, control, waker)?;
         }
 
         for info in pools {
             let controller = reactor.controller();
+            let event_log = reactor.event_log();
+            let heartbeat = Arc::new(AtomicU64::new(0));
+            reactor.heartbeats.insert(info.id, heartbeat.clone());
             let id = info.id;
             let thread = thread::spawn(move || {
                 PoolRuntime::new(
@@ -90,13 +275,18 @@ impl<L: Layout> Reactor<L> {
                     info.control_recv,
                     info.control_send,
                     info.shutdown,
+                    shutdown_grace,
                     info.handler,
                 )
+                .with_idle(info.idle_timeout, info.actor_idle)
+                .with_io_budget(info.max_io_events, info.max_io_events_per_actor)
+                .with_event_log(event_log)
+                .with_heartbeat(heartbeat)
                 .run(controller)
             });
-            reactor.scheduler_threads.insert(id, thread)
-                .ok_or(())
-                .expect("controller logic for pool management doesn't account for errors with repeated pool creation");
+            if reactor.scheduler_threads.insert(id, thread).is_some() {
+                panic!("controller logic for pool management doesn't account for errors with repeated pool creation");
+            }
         }
 
         Ok(reactor)
@@ -111,9 +301,19 @@ impl<L: Layout> Reactor<L> {
         self.controller.clone()
     }
 
+    /// Returns log of the last [`EVENT_LOG_LEN`] control events, I/O events
+    /// and errors processed by the re-actor pools.
+    ///
+    /// The log is shared by all pools and keeps being updated while the
+    /// re-actor runs; it can be dumped with [`EventLog::dump_json`] for
+    /// post-mortem debugging.
+    pub fn event_log(&self) -> Arc<Mutex<EventLog<EVENT_LOG_LEN>>> {
+        self.event_log.clone()
+    }
+
     /// Joins all re-actor threads.
-    pub fn join(self) -> Result<(), InternalError<L>> {
-        for (pool, scheduler_thread) in self.scheduler_threads {
+    pub fn join(mut self) -> Result<(), InternalError<L>> {
+        for (pool, scheduler_thread) in mem::take(&mut self.scheduler_threads) {
             scheduler_thread
                 .join()
                 .map_err(|_| InternalError::ThreadError(pool))?;
@@ -121,12 +321,37 @@ impl<L: Layout> Reactor<L> {
         Ok(())
     }
 
-    /// Shut downs the re-actor.
+    /// Shut downs the re-actor, disconnecting all actors in all pools, and
+    /// waits for the pool threads to complete.
     pub fn shutdown(self) -> Result<(), InternalError<L>> {
-        self.shutdown_send
-            .send(())
-            .map_err(|_| InternalError::ShutdownChanelBroken)?;
+        let shutdown_send = self
+            .shutdown_send
+            .as_ref()
+            .ok_or(InternalError::ShutdownChanelBroken)?;
+        for _ in 0..self.scheduler_threads.len() {
+            shutdown_send
+                .send(())
+                .map_err(|_| InternalError::ShutdownChanelBroken)?;
+        }
+        for pool in self.scheduler_threads.keys() {
+            self.controller.wake(*pool)?;
+        }
         self.join()?;
         Ok(())
     }
 }
+
+impl<L: Layout> Drop for Reactor<L> {
+    /// Dropping re-actor without calling [`Reactor::shutdown`] shuts down
+    /// all its pools without waiting for their threads to complete.
+    fn drop(&mut self) {
+        // Pool runtimes treat disconnected shutdown channel as a shutdown
+        // request
+        self.shutdown_send = None;
+        for pool in self.scheduler_threads.keys() {
+            // The pool may have already terminated, so there is nothing to do
+            // on errors
+            let _ = self.controller.wake(*pool);
+        }
+    }
+}
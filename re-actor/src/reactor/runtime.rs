@@ -1,23 +1,326 @@
 use crossbeam_channel as chan;
-use std::collections::HashMap;
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Display;
+use std::io;
+use std::mem;
+use std::ops::{Deref, DerefMut};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
 use std::time::{Duration, Instant};
 
-use crate::{Actor, Controller, Handler, InternalError, Layout, Scheduler, TimeoutManager};
+use super::event_log::{EventKind, EventLog, EVENT_LOG_LEN};
+use crate::actors::{ActorSnapshot, DisconnectReason, IoEv, IoSrc};
+use crate::schedulers::IoStats;
+use crate::{
+    Actor, Controller, Handler, InternalError, Layout, Scheduler, TimeoutManager, TimerToken,
+};
 
-/// Events send by [`Controller`] and [`ReactorApi`] to the [`Runtime`].
+/// Maximal number of control events processed by the runtime in a single
+/// iteration of the event loop, such that a flood of control events does not
+/// starve I/O processing.
+pub const MAX_CONTROL_EVENTS: usize = 1024;
+
+/// Default maximal number of I/O events dispatched by the runtime in a single
+/// iteration of the event loop, such that a flood of I/O events does not delay
+/// processing of the control events and timers.
+pub const DEFAULT_MAX_IO_EVENTS: usize = 1024;
+
+/// Default maximal number of I/O events dispatched to a single actor in one
+/// iteration of the event loop.
+pub const DEFAULT_MAX_IO_EVENTS_PER_ACTOR: usize = 64;
+
+thread_local! {
+    /// Whether the current thread runs a re-actor pool, set by
+    /// [`PoolRuntime::run`].
+    static POOL_THREAD: Cell<bool> = Cell::new(false);
+}
+
+/// Detects whether the current thread runs a re-actor pool, which includes
+/// the actors and the handlers called by the pool runtime.
+pub(super) fn is_pool_thread() -> bool {
+    POOL_THREAD.with(Cell::get)
+}
+
+/// Information about the pool runtime state which can be requested by the
+/// [`Controller`].
 #[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub enum QueryKind<Id> {
+    /// Number of actors run by the pool
+    Count,
+
+    /// Whether an actor with the given id is run by the pool
+    Contains(Id),
+
+    /// Ids of the actors run by the pool
+    List,
+
+    /// Metrics of the pool runtime
+    Metrics,
+
+    /// Counters of the I/O events delivered by the pool scheduler for each of
+    /// the actors (see [`Scheduler::io_stats`])
+    IoStats,
+}
+
+/// Responses to [`QueryKind`] requests.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub enum QueryResponse<Id> {
+    /// Number of actors run by the pool
+    Count(usize),
+
+    /// Whether an actor with the requested id is run by the pool
+    Contains(bool),
+
+    /// Ids of the actors run by the pool
+    List(Vec<Id>),
+
+    /// Metrics of the pool runtime
+    Metrics(ReactorMetrics),
+
+    /// Counters of the I/O events delivered by the pool scheduler for each of
+    /// the actors
+    IoStats(Vec<(Id, IoStats)>),
+}
+
+/// Snapshot of the metrics collected by a pool runtime since its start, which
+/// can be requested with [`Controller::metrics`].
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug, Default)]
+pub struct ReactorMetrics {
+    /// Number of the event loop iterations
+    pub iterations: u64,
+
+    /// Number of I/O events dispatched to the actors
+    pub io_events: u64,
+
+    /// Number of processed control events
+    pub control_events: u64,
+
+    /// Time spent waiting for I/O events
+    pub poll_time: Duration,
+
+    /// Time spent dispatching I/O events to the actors
+    pub dispatch_time: Duration,
+
+    /// Number of control events waiting in the queue at the moment of the
+    /// request
+    pub control_queue_len: usize,
+
+    /// Number of actors run by the pool at the moment of the request,
+    /// excluding the ones being gracefully disconnected
+    pub actors: usize,
+
+    /// Number of actors being gracefully disconnected at the moment of the
+    /// request
+    pub draining: usize,
+}
+
+/// Factory producing actor context for each of the reconnection attempts.
+pub type ContextFactory<A> = Box<dyn Fn() -> <A as Actor>::Context + Send>;
+
+/// Predicate selecting actors which should receive a broadcasted command.
+pub type BroadcastFilter<A> = Arc<dyn Fn(&<A as Actor>::Id) -> bool + Send + Sync>;
+
+/// Callback receiving actor taken out of the re-actor. Type erasure allows to
+/// avoid requiring all actors to be `Send`.
+pub type TakeCallback<A> = Box<dyn FnOnce(A) + Send>;
+
+/// Callback receiving outcome of the actor construction and registration.
+/// Type erasure allows to avoid requiring all actor errors to be `Send`.
+pub type StartCallback<A> =
+    Box<dyn FnOnce(Result<<A as Actor>::Id, InternalError<<A as Actor>::Layout>>) + Send>;
+
+/// Provider of an already constructed actor to be inserted into the re-actor.
+/// Type erasure allows to avoid requiring all actors to be `Send`.
+pub type ActorProvider<A> = Box<dyn FnOnce() -> A + Send>;
+
+/// Function taking snapshot of an actor being migrated, i.e.
+/// [`Migrate::snapshot`]. Type erasure allows to avoid requiring all actors to
+/// implement [`Migrate`].
+///
+/// [`Migrate`]: crate::actors::Migrate
+/// [`Migrate::snapshot`]: crate::actors::Migrate::snapshot
+pub type SnapshotFn<A> = fn(&A) -> Result<ActorSnapshot, <A as Actor>::Error>;
+
+/// Function restoring actor from its snapshot, i.e. [`Migrate::restore`].
+/// Type erasure allows to avoid requiring all actors to implement
+/// [`Migrate`].
+///
+/// [`Migrate`]: crate::actors::Migrate
+/// [`Migrate::restore`]: crate::actors::Migrate::restore
+pub type RestoreFn<A> =
+    fn(ActorSnapshot, Controller<<A as Actor>::Layout>) -> Result<A, <A as Actor>::Error>;
+
+/// Events send by [`Controller`] and [`ReactorApi`] to the [`Runtime`].
 pub enum ControlEvent<A: Actor> {
     /// Request re-actor to connect to the resource with some context
     Connect(A::Context),
 
+    /// Request re-actor to connect to the resource with some context,
+    /// reporting the outcome to the callback
+    ConnectReport(A::Context, StartCallback<A>),
+
     /// Request re-actor to disconnect from a resource
     Disconnect(A::Id),
 
-    /// Ask re-actor to wake up after certain interval
-    SetTimer(),
+    /// Request re-actor to disconnect from a resource once it writes out its
+    /// pending output, waiting for at most the provided time
+    DisconnectGraceful(A::Id, Duration),
+
+    /// Request re-actor to shut down writing half of the actor connection
+    HalfClose(A::Id),
+
+    /// Request re-actor to disconnect all actors of the pool, sending back the
+    /// number of the disconnected actors via the provided channel
+    DisconnectAll(chan::Sender<usize>),
+
+    /// Request re-actor to add already constructed actor
+    Insert(ActorProvider<A>),
+
+    /// Request re-actor to remove actor without disconnecting it and to pass
+    /// it to the callback
+    Take(A::Id, TakeCallback<A>),
+
+    /// Request re-actor to replace actor with another one without
+    /// disconnecting it, passing the replaced actor to the callback
+    Replace(A::Id, ActorProvider<A>, TakeCallback<A>),
+
+    /// Request re-actor to move actor to the pool with the same id in another
+    /// re-actor, controlled by the provided controller
+    Migrate(A::Id, Controller<A::Layout>, SnapshotFn<A>, RestoreFn<A>),
+
+    /// Request re-actor to add actor restored from a snapshot
+    Restore(ActorSnapshot, RestoreFn<A>),
+
+    /// Request re-actor to connect to the resource, retrying with exponential
+    /// backoff in case of failures. The id is reported to the handler once all
+    /// the attempts fail.
+    Reconnect {
+        id: A::Id,
+        context: ContextFactory<A>,
+        backoff: Duration,
+        multiplier: f64,
+        remaining: u32,
+    },
+
+    /// Ask re-actor to wake up at a certain deadline
+    SetTimer(TimerToken, Instant),
+
+    /// Cancel previously set timer
+    CancelTimer(TimerToken),
 
     /// Request re-actor to send the data to the resource
     Send(A::Id, A::Cmd),
+
+    /// Request re-actor to send the command to all actors of the pool, or only
+    /// to the ones matching the filter, if provided, sending back the number
+    /// of the actors which have received the command via the provided channel
+    Broadcast(A::Cmd, Option<BroadcastFilter<A>>, chan::Sender<usize>),
+
+    /// Request information about the pool state, which should be sent back
+    /// via the provided channel
+    Query(QueryKind<A::Id>, chan::Sender<QueryResponse<A::Id>>),
+}
+
+impl<A: Actor> ControlEvent<A> {
+    /// Returns name of the event kind, used to report the event to the
+    /// [`Handler`].
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            ControlEvent::Connect(_) => "connect",
+            ControlEvent::ConnectReport(..) => "connect",
+            ControlEvent::Disconnect(_) => "disconnect",
+            ControlEvent::DisconnectGraceful(..) => "disconnect_graceful",
+            ControlEvent::HalfClose(_) => "half_close",
+            ControlEvent::DisconnectAll(_) => "disconnect_all",
+            ControlEvent::Insert(_) => "insert",
+            ControlEvent::Take(..) => "take",
+            ControlEvent::Replace(..) => "replace",
+            ControlEvent::Migrate(..) => "migrate",
+            ControlEvent::Restore(..) => "restore",
+            ControlEvent::Reconnect { .. } => "reconnect",
+            ControlEvent::SetTimer(..) => "set_timer",
+            ControlEvent::CancelTimer(_) => "cancel_timer",
+            ControlEvent::Send(..) => "send",
+            ControlEvent::Broadcast(..) => "broadcast",
+            ControlEvent::Query(..) => "query",
+        }
+    }
+
+    /// Returns id of the actor the event is addressed to, if any.
+    fn actor_id(&self) -> Option<&A::Id> {
+        match self {
+            ControlEvent::Disconnect(id)
+            | ControlEvent::DisconnectGraceful(id, _)
+            | ControlEvent::HalfClose(id)
+            | ControlEvent::Take(id, _)
+            | ControlEvent::Replace(id, ..)
+            | ControlEvent::Migrate(id, ..)
+            | ControlEvent::Send(id, _) => Some(id),
+            _ => None,
+        }
+    }
+}
+
+/// Shared log of the events processed by the re-actor pools.
+pub(crate) type SharedEventLog = Arc<Mutex<EventLog<EVENT_LOG_LEN>>>;
+
+/// Pool handler recording the events reported to it in the event log. Other
+/// handler callbacks are passed to the wrapped handler as they are.
+struct LoggedHandler<L: Layout> {
+    inner: Box<dyn Handler<L>>,
+    event_log: SharedEventLog,
+}
+
+impl<L: Layout> LoggedHandler<L> {
+    fn log(&self) -> MutexGuard<EventLog<EVENT_LOG_LEN>> {
+        // Log entries are always consistent, so the poisoned lock is ignored
+        self.event_log
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn handle_err(&mut self, err: InternalError<L>) {
+        self.log().record_err(&err);
+        self.inner.handle_err(err)
+    }
+
+    fn on_io(&mut self, id: &<L::RootActor as Actor>::Id, io: IoEv) {
+        self.log().record(EventKind::Io(io), Some(id));
+        self.inner.on_io(id, io)
+    }
+
+    fn on_control(&mut self, event: &ControlEvent<L::RootActor>) {
+        let id = event.actor_id().map(|id| id as &dyn Display);
+        self.log().record(EventKind::Control(event.name()), id);
+        self.inner.on_control(event.name())
+    }
+}
+
+impl<L: Layout> Deref for LoggedHandler<L> {
+    type Target = dyn Handler<L>;
+
+    fn deref(&self) -> &Self::Target {
+        self.inner.as_ref()
+    }
+}
+
+impl<L: Layout> DerefMut for LoggedHandler<L> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.inner.as_mut()
+    }
+}
+
+/// Reconnection which is waiting for its next attempt.
+struct PendingReconnect<A: Actor> {
+    id: A::Id,
+    deadline: Instant,
+    context: ContextFactory<A>,
+    backoff: Duration,
+    multiplier: f64,
+    remaining: u32,
 }
 
 /// Runtime represents the re-actor event loop with its state handled in a
@@ -27,12 +330,38 @@ pub enum ControlEvent<A: Actor> {
 pub struct PoolRuntime<L: Layout> {
     id: L,
     actors: HashMap<<L::RootActor as Actor>::Id, L::RootActor>,
+    /// Actors which are being gracefully disconnected and are writing out
+    /// their pending output.
+    draining: HashMap<<L::RootActor as Actor>::Id, L::RootActor>,
     scheduler: Box<dyn Scheduler<L::RootActor>>,
-    handler: Box<dyn Handler<L>>,
+    handler: LoggedHandler<L>,
     control_recv: chan::Receiver<ControlEvent<L::RootActor>>,
     control_send: chan::Sender<ControlEvent<L::RootActor>>,
     shutdown: chan::Receiver<()>,
-    timeouts: TimeoutManager<()>,
+    shutdown_grace: Duration,
+    timeouts: TimeoutManager<TimerToken>,
+    deadlines: TimeoutManager<<L::RootActor as Actor>::Id>,
+    drain_timeouts: TimeoutManager<<L::RootActor as Actor>::Id>,
+    /// Actors which have reported their circuit breaker open.
+    open_circuits: HashSet<<L::RootActor as Actor>::Id>,
+    reconnects: Vec<PendingReconnect<L::RootActor>>,
+    idle_timeout: Option<Duration>,
+    actor_idle: bool,
+    /// Time the pool has dispatched an I/O event or fired a timer at, or has
+    /// been reported idle at.
+    last_active: Instant,
+    max_io_events: usize,
+    max_io_events_per_actor: usize,
+    /// Events which exceeded the budget of their actor in the previous
+    /// iteration of the event loop.
+    deferred_io: VecDeque<IoSrc<<L::RootActor as Actor>::Id>>,
+    /// Whether the scheduler may still have events which were not dispatched
+    /// in the previous iteration due to the exhausted budget.
+    pending_io: bool,
+    /// Number of the event loop iterations started, observed by the
+    /// watchdog.
+    heartbeat: Arc<AtomicU64>,
+    metrics: ReactorMetrics,
 }
 
 impl<L: Layout> PoolRuntime<L> {
@@ -42,109 +371,926 @@ impl<L: Layout> PoolRuntime<L> {
         control_recv: chan::Receiver<ControlEvent<L::RootActor>>,
         control_send: chan::Sender<ControlEvent<L::RootActor>>,
         shutdown: chan::Receiver<()>,
+        shutdown_grace: Duration,
         handler: Box<dyn Handler<L>>,
     ) -> Self {
         PoolRuntime {
             id,
             scheduler,
             actors: empty!(),
+            draining: empty!(),
             control_recv,
             control_send,
             shutdown,
-            handler,
+            shutdown_grace,
+            handler: LoggedHandler {
+                inner: handler,
+                event_log: default!(),
+            },
             timeouts: TimeoutManager::new(Duration::from_secs(0)),
+            deadlines: TimeoutManager::new(Duration::from_secs(0)),
+            drain_timeouts: TimeoutManager::new(Duration::from_secs(0)),
+            open_circuits: empty!(),
+            reconnects: empty!(),
+            idle_timeout: None,
+            actor_idle: false,
+            last_active: Instant::now(),
+            max_io_events: DEFAULT_MAX_IO_EVENTS,
+            max_io_events_per_actor: DEFAULT_MAX_IO_EVENTS_PER_ACTOR,
+            deferred_io: empty!(),
+            pending_io: false,
+            heartbeat: default!(),
+            metrics: default!(),
         }
     }
 
-    pub fn run(mut self, controller: Controller<L>) -> ! {
+    /// Sets time without I/O events and timers after which the runtime becomes
+    /// idle and whether the actors are notified about the idleness.
+    pub fn with_idle(mut self, idle_timeout: Option<Duration>, actor_idle: bool) -> Self {
+        self.idle_timeout = idle_timeout;
+        self.actor_idle = actor_idle;
+        self
+    }
+
+    /// Sets counter of the event loop iterations, incremented at the start of
+    /// each iteration.
+    pub fn with_heartbeat(mut self, heartbeat: Arc<AtomicU64>) -> Self {
+        self.heartbeat = heartbeat;
+        self
+    }
+
+    /// Sets log recording the events processed by the pool.
+    pub fn with_event_log(mut self, event_log: SharedEventLog) -> Self {
+        self.handler.event_log = event_log;
+        self
+    }
+
+    /// Sets maximal number of I/O events dispatched in a single iteration of
+    /// the event loop, in total and to a single actor. Events exceeding the
+    /// budget are dispatched in the next iterations, after processing the
+    /// control events.
+    pub fn with_io_budget(mut self, max_events: usize, max_events_per_actor: usize) -> Self {
+        self.max_io_events = max_events.max(1);
+        self.max_io_events_per_actor = max_events_per_actor.max(1);
+        self
+    }
+
+    /// Runs the event loop until the re-actor shutdown is requested.
+    pub fn run(mut self, controller: Controller<L>) {
+        POOL_THREAD.with(|flag| flag.set(true));
+        self.handler.on_startup(controller.clone());
         loop {
-            let now = Instant::now();
-            if let Err(err) = self.scheduler.wait_io(self.timeouts.next(now)) {
+            self.heartbeat.fetch_add(1, Ordering::Relaxed);
+            self.metrics.iterations += 1;
+            let start = Instant::now();
+            let poll_time = self.metrics.poll_time;
+            self.process_io(&controller, self.next_timeout());
+            self.process_timers();
+            self.process_deadlines(&controller);
+            self.process_drain_timeouts(&controller);
+            self.process_reconnects(&controller);
+            // TODO: Should we process control events before dispatching input?
+            let connected = self.process_control(&controller, MAX_CONTROL_EVENTS);
+            self.process_idle(&controller);
+            let waited = self.metrics.poll_time - poll_time;
+            self.handler.on_iteration(start.elapsed() - waited);
+            if self.process_shutdown(&controller, !connected) {
+                break;
+            }
+        }
+    }
+
+    fn next_timeout(&self) -> Option<Duration> {
+        // Control events and I/O events left from the previous iteration must
+        // not wait for I/O
+        if !self.control_recv.is_empty() || self.pending_io || !self.deferred_io.is_empty() {
+            return Some(Duration::ZERO);
+        }
+        let now = Instant::now();
+        let reconnect = self
+            .reconnects
+            .iter()
+            .map(|reconnect| reconnect.deadline.saturating_duration_since(now))
+            .min();
+        [
+            self.timeouts.next(now),
+            self.deadlines.next(now),
+            self.drain_timeouts.next(now),
+            reconnect,
+            self.idle_timeout
+                .map(|timeout| (self.last_active + timeout).saturating_duration_since(now)),
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// Waits for I/O and dispatches the events to the actors.
+    ///
+    /// At most `max_io_events` events are dispatched, with at most
+    /// `max_io_events_per_actor` of them to a single actor. The rest of the
+    /// events is left in the scheduler, or is deferred if it exceeds the actor
+    /// budget, and is dispatched by the next calls before waiting for new I/O.
+    fn process_io(&mut self, controller: &Controller<L>, timeout: Option<Duration>) {
+        let poll_start = Instant::now();
+        if !self.pending_io && self.deferred_io.is_empty() {
+            if let Err(err) = self.scheduler.wait_io(timeout) {
                 self.handler
                     .handle_err(InternalError::ActorError(self.id, err));
             }
-            for ev in &mut self.scheduler {
-                let res = self
-                    .actors
-                    .get_mut(&ev.source)
-                    .expect("resource management inconsistency");
-                res.io_ready(ev.io)
-                    .or_else(|err| res.handle_err(err))
-                    .unwrap_or_else(|err| {
-                        self.handler
-                            .handle_err(InternalError::ActorError(self.id, err))
-                    });
+        }
+        let dispatch_start = Instant::now();
+        self.metrics.poll_time += dispatch_start - poll_start;
+        // Events deferred by this call are put after the ones deferred
+        // previously, and must not be taken again
+        let mut deferred = self.deferred_io.len();
+        let mut dispatched = 0;
+        let mut per_actor = HashMap::<_, usize>::new();
+        loop {
+            if dispatched >= self.max_io_events {
+                self.pending_io = true;
+                break;
+            }
+            let ev = match deferred {
+                0 => None,
+                _ => {
+                    deferred -= 1;
+                    self.deferred_io.pop_front()
+                }
+            };
+            let Some(ev) = ev.or_else(|| self.scheduler.next()) else {
+                self.pending_io = false;
+                break;
+            };
+            let count = per_actor.entry(ev.source.clone()).or_default();
+            if *count >= self.max_io_events_per_actor {
+                self.deferred_io.push_back(ev);
+                continue;
             }
-            // TODO: Should we process control events before dispatching input?
-            self.process_control(&controller);
-            self.process_shutdown();
+            *count += 1;
+            dispatched += 1;
+            self.metrics.io_events += 1;
+            let id = ev.source.clone();
+            self.handler.on_io(&id, ev.io);
+            self.dispatch_io(controller, ev);
+            self.handler.on_io_done(&id);
+        }
+        self.metrics.dispatch_time += dispatch_start.elapsed();
+        if dispatched > 0 {
+            self.last_active = Instant::now();
         }
     }
 
-    fn process_control(&mut self, controller: &Controller<L>) {
-        loop {
-            match self.control_recv.try_recv() {
-                Err(chan::TryRecvError::Disconnected) => {
-                    panic!("re-actor shutdown channel was dropper")
+    /// Dispatches I/O event to the actor, removing the actor on hang-up.
+    fn dispatch_io(&mut self, controller: &Controller<L>, ev: IoSrc<<L::RootActor as Actor>::Id>) {
+        if self.draining.contains_key(&ev.source) {
+            self.process_draining_io(controller, ev);
+            return;
+        }
+        // Actor may be already removed on a hang-up reported earlier
+        // within the same batch of events
+        let Some(res) = self.actors.get_mut(&ev.source) else {
+            return;
+        };
+        match panic::catch_unwind(AssertUnwindSafe(|| {
+            res.io_ready(ev.io).or_else(|err| res.handle_err(err))
+        })) {
+            Ok(Ok(())) => {}
+            Ok(Err(err)) => self
+                .handler
+                .handle_err(InternalError::ActorError(self.id, err)),
+            Err(payload) => {
+                self.remove_panicked(controller, ev.source, payload);
+                return;
+            }
+        }
+        if ev.io.is_hangup || ev.io.is_error {
+            if let Some(actor) = self.actors.remove(&ev.source) {
+                self.hang_up(controller, ev.source, actor, ev.io);
+            }
+            return;
+        }
+        self.update_interest(&ev.source);
+    }
+
+    /// Dispatches I/O event to an actor which is being gracefully
+    /// disconnected, completing the disconnection once the actor has written
+    /// out all of its output or has failed.
+    fn process_draining_io(
+        &mut self,
+        controller: &Controller<L>,
+        ev: IoSrc<<L::RootActor as Actor>::Id>,
+    ) {
+        let Some(actor) = self.draining.get_mut(&ev.source) else {
+            return;
+        };
+        let res = match panic::catch_unwind(AssertUnwindSafe(|| {
+            actor.io_ready(ev.io).or_else(|err| actor.handle_err(err))
+        })) {
+            Ok(res) => res,
+            Err(payload) => {
+                self.remove_panicked(controller, ev.source, payload);
+                return;
+            }
+        };
+        let is_done = res.is_err() || !actor.has_pending_output();
+        let interest = actor.interests();
+        if let Err(err) = res {
+            self.handler
+                .handle_err(InternalError::ActorError(self.id, err));
+        }
+        if ev.io.is_hangup || ev.io.is_error {
+            if let Some(actor) = self.draining.remove(&ev.source) {
+                self.drain_timeouts.cancel(&ev.source);
+                self.hang_up(controller, ev.source, actor, ev.io);
+            }
+        } else if is_done {
+            if let Some(actor) = self.draining.remove(&ev.source) {
+                self.drain_timeouts.cancel(&ev.source);
+                self.disconnect(controller, ev.source, actor, DisconnectReason::OnDemand);
+            }
+        } else if let Err(err) = self.scheduler.set_interest(&ev.source, interest) {
+            self.handler
+                .handle_err(InternalError::ActorError(self.id, err));
+        }
+    }
+
+    /// Removes actor, which was already removed from the actor map, after the
+    /// scheduler has reported a hang-up or an error condition on it.
+    fn hang_up(
+        &mut self,
+        controller: &Controller<L>,
+        id: <L::RootActor as Actor>::Id,
+        mut actor: L::RootActor,
+        io: IoEv,
+    ) {
+        // The connection is already closed, so there is nothing to flush or
+        // shut down; the actor is handed over to the handler
+        let reason = if io.is_error {
+            let err = io::Error::new(
+                io::ErrorKind::Other,
+                "error condition reported by the scheduler",
+            );
+            DisconnectReason::ConnectionError(err)
+        } else {
+            DisconnectReason::Hangup
+        };
+        self.handler.on_disconnect(&id, &reason);
+        controller.unregister_actor(&id);
+        self.unregister_io(&id)
+            .or_else(|err| actor.handle_err(err))
+            .unwrap_or_else(|err| {
+                self.handler
+                    .handle_err(InternalError::ActorError(self.id, err))
+            });
+        self.handler.on_hangup(actor);
+    }
+
+    /// Removes actor which has panicked, reporting the panic payload to the
+    /// handler. The actor is dropped without being disconnected, since its
+    /// state may be inconsistent.
+    fn remove_panicked(
+        &mut self,
+        controller: &Controller<L>,
+        id: <L::RootActor as Actor>::Id,
+        payload: Box<dyn Any + Send>,
+    ) {
+        let actor = match self.actors.remove(&id) {
+            Some(actor) => actor,
+            None => match self.draining.remove(&id) {
+                Some(actor) => {
+                    self.drain_timeouts.cancel(&id);
+                    actor
+                }
+                None => return,
+            },
+        };
+        self.handler.on_disconnect(&id, &DisconnectReason::Panicked);
+        controller.unregister_actor(&id);
+        self.unregister_io(&id).unwrap_or_else(|err| {
+            self.handler
+                .handle_err(InternalError::ActorError(self.id, err))
+        });
+        drop(actor);
+        self.handler.handle_panic(&id, payload);
+    }
+
+    /// Unregisters actor from the scheduler, dropping its deferred I/O events
+    /// and circuit state, which must not be applied to a new actor reusing the
+    /// same id.
+    fn unregister_io(
+        &mut self,
+        id: &<L::RootActor as Actor>::Id,
+    ) -> Result<(), <L::RootActor as Actor>::Error> {
+        self.deferred_io.retain(|ev| ev.source != *id);
+        self.open_circuits.remove(id);
+        self.scheduler.unregister_actor(id)
+    }
+
+    /// Passes actor interest in I/O events to the scheduler.
+    fn update_interest(&mut self, id: &<L::RootActor as Actor>::Id) {
+        let Some(actor) = self.actors.get_mut(id) else {
+            return;
+        };
+        self.scheduler
+            .set_interest(id, actor.interests())
+            .or_else(|err| actor.handle_err(err))
+            .unwrap_or_else(|err| {
+                self.handler
+                    .handle_err(InternalError::ActorError(self.id, err))
+            });
+        self.update_circuit(id);
+    }
+
+    /// Reports changes of the actor circuit breaker state to the handler.
+    /// While the circuit is open its deadline, at which the circuit is probed
+    /// again, is re-registered, since it changes on each failure.
+    fn update_circuit(&mut self, id: &<L::RootActor as Actor>::Id) {
+        let Some(actor) = self.actors.get(id) else {
+            return;
+        };
+        let is_open = actor.is_circuit_open();
+        if is_open {
+            if let Some(deadline) = actor.deadline() {
+                self.deadlines.cancel(id);
+                self.deadlines.register(id.clone(), deadline);
+            }
+        }
+        match (self.open_circuits.contains(id), is_open) {
+            (false, true) => {
+                self.open_circuits.insert(id.clone());
+                self.handler.on_circuit_open(id);
+            }
+            (true, false) => {
+                self.open_circuits.remove(id);
+                self.handler.on_circuit_close(id);
+            }
+            _ => {}
+        }
+    }
+
+    /// Notifies about the idle pool once it has neither dispatched I/O events
+    /// nor fired timers during the idle timeout.
+    fn process_idle(&mut self, controller: &Controller<L>) {
+        match self.idle_timeout {
+            Some(timeout) if self.last_active.elapsed() >= timeout => {}
+            _ => return,
+        }
+        self.last_active = Instant::now();
+        self.handler.on_idle();
+        if !self.actor_idle {
+            return;
+        }
+        let mut panicked = vec![];
+        for (id, actor) in &mut self.actors {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(|| actor.on_idle())) {
+                panicked.push((id.clone(), payload));
+            }
+        }
+        for (id, payload) in panicked {
+            self.remove_panicked(controller, id, payload);
+        }
+    }
+
+    fn process_timers(&mut self) {
+        let mut fired = vec![];
+        self.scheduler.fired_timers(&mut fired);
+        self.timeouts.check_now(&mut fired);
+        if !fired.is_empty() {
+            self.last_active = Instant::now();
+        }
+        for token in fired {
+            self.handler.on_timer(token);
+        }
+    }
+
+    /// Notifies actors which have not met their deadlines, disconnecting them
+    /// if they fail to handle it.
+    fn process_deadlines(&mut self, controller: &Controller<L>) {
+        let now = Instant::now();
+        let mut expired = vec![];
+        self.deadlines.check(now, &mut expired);
+        for id in expired {
+            // The actor may be already removed or may have met the deadline
+            let Some(actor) = self.actors.get_mut(&id) else {
+                continue;
+            };
+            match actor.deadline() {
+                None => continue,
+                Some(deadline) if deadline > now => {
+                    self.deadlines.register(id, deadline);
+                    continue;
                 }
+                Some(_) => {}
+            }
+            let res = match panic::catch_unwind(AssertUnwindSafe(|| {
+                actor.on_deadline().or_else(|err| actor.handle_err(err))
+            })) {
+                Ok(res) => res,
+                Err(payload) => {
+                    self.remove_panicked(controller, id, payload);
+                    continue;
+                }
+            };
+            if let Err(err) = res {
+                let reason = actor.deadline_reason();
+                self.handler
+                    .handle_err(InternalError::ActorError(self.id, err));
+                if let Some(actor) = self.actors.remove(&id) {
+                    self.disconnect(controller, id, actor, reason);
+                }
+                continue;
+            }
+            // Actors with recurring deadlines, like heartbeats, set the next
+            // one while handling the current deadline
+            if let Some(deadline) = actor.deadline().filter(|deadline| *deadline > now) {
+                self.deadlines.register(id.clone(), deadline);
+            }
+            self.update_interest(&id);
+        }
+    }
+
+    /// Disconnects actors which have not managed to write out their pending
+    /// output within the time given by the graceful disconnection request.
+    fn process_drain_timeouts(&mut self, controller: &Controller<L>) {
+        let mut expired = vec![];
+        self.drain_timeouts.check_now(&mut expired);
+        for id in expired {
+            if let Some(actor) = self.draining.remove(&id) {
+                self.disconnect(controller, id, actor, DisconnectReason::OnDemand);
+            }
+        }
+    }
+
+    fn process_reconnects(&mut self, controller: &Controller<L>) {
+        let now = Instant::now();
+        let (due, pending): (Vec<_>, Vec<_>) = mem::take(&mut self.reconnects)
+            .into_iter()
+            .partition(|reconnect| reconnect.deadline <= now);
+        self.reconnects = pending;
+        for mut reconnect in due {
+            reconnect.remaining -= 1;
+            // Failures to add the constructed actor to the pool are reported
+            // at once, and count against the attempts like the failures to
+            // construct the actor
+            let err = match L::RootActor::with((reconnect.context)(), controller.clone()) {
+                Ok(actor) => match self.start(controller, actor) {
+                    Ok(_) => continue,
+                    Err(err) => {
+                        self.handler.handle_err(err);
+                        None
+                    }
+                },
+                Err(err) => Some(err),
+            };
+            if reconnect.remaining > 0 {
+                reconnect.backoff = reconnect.backoff.mul_f64(reconnect.multiplier);
+                reconnect.deadline = now + reconnect.backoff;
+                self.reconnects.push(reconnect);
+                continue;
+            }
+            if let Some(err) = err {
+                self.handler
+                    .handle_err(InternalError::ReconnectExhausted(self.id, err));
+            }
+            self.handler
+                .on_disconnect(&reconnect.id, &DisconnectReason::ReconnectExhausted);
+        }
+    }
+
+    fn connect(
+        &mut self,
+        controller: &Controller<L>,
+        context: <L::RootActor as Actor>::Context,
+    ) -> Result<<L::RootActor as Actor>::Id, InternalError<L>> {
+        let actor = L::RootActor::with(context, controller.clone())
+            .map_err(|err| InternalError::ActorError(self.id, err))?;
+        self.start(controller, actor)
+    }
+
+    /// Adds actor to the pool.
+    ///
+    /// # Errors
+    ///
+    /// If the actor with the same id is already run by the re-actor, or if
+    /// the scheduler fails to register the actor. In this case the new actor
+    /// is dropped, while the actor which is already registered under the same
+    /// id stays untouched.
+    fn start(
+        &mut self,
+        controller: &Controller<L>,
+        actor: L::RootActor,
+    ) -> Result<<L::RootActor as Actor>::Id, InternalError<L>> {
+        let id = self.register(controller, actor)?;
+        self.handler.on_connect(&id);
+        Ok(id)
+    }
+
+    /// Adds actor to the pool like [`PoolRuntime::start`] without notifying
+    /// the handler.
+    fn register(
+        &mut self,
+        controller: &Controller<L>,
+        actor: L::RootActor,
+    ) -> Result<<L::RootActor as Actor>::Id, InternalError<L>> {
+        let id = actor.id();
+        controller.register_actor(id.clone(), self.id)?;
+        if let Err(err) = self.scheduler.register_actor(&actor) {
+            // Actor which is not polled by the scheduler would never advance
+            controller.unregister_actor(&id);
+            return Err(InternalError::ActorError(self.id, err));
+        }
+        self.scheduler.set_priority(&id, actor.priority());
+        if let Some(deadline) = actor.deadline() {
+            self.deadlines.register(id.clone(), deadline);
+        }
+        self.actors.insert(id.clone(), actor);
+        Ok(id)
+    }
+
+    /// Replaces actor with a new one, passing the replaced actor to the
+    /// callback. If the new actor can't be added, the replaced actor is
+    /// restored, and the new one is dropped.
+    fn replace(
+        &mut self,
+        controller: &Controller<L>,
+        id: <L::RootActor as Actor>::Id,
+        actor: L::RootActor,
+        callback: TakeCallback<L::RootActor>,
+    ) {
+        let Some(mut replaced) = self.actors.remove(&id) else {
+            self.handler.handle_err(InternalError::UnknownActor(id));
+            return;
+        };
+        controller.unregister_actor(&id);
+        // The actors may share the same I/O resource, so the replaced one
+        // must be unregistered from the scheduler first
+        if let Err(err) = self.unregister_io(&id) {
+            replaced.handle_err(err).unwrap_or_else(|err| {
+                self.handler
+                    .handle_err(InternalError::ActorError(self.id, err))
+            });
+            return;
+        }
+        match self.register(controller, actor) {
+            Ok(_) => callback(replaced),
+            Err(err) => {
+                self.handler.handle_err(err);
+                if let Err(err) = self.register(controller, replaced) {
+                    self.handler.handle_err(err);
+                }
+            }
+        }
+    }
+
+    /// Moves actor to the pool with the same id in the re-actor controlled by
+    /// `target`, disconnecting it locally once its snapshot is sent there. If
+    /// the snapshot can't be taken or sent, the actor is kept in the pool.
+    fn migrate(
+        &mut self,
+        controller: &Controller<L>,
+        id: <L::RootActor as Actor>::Id,
+        target: Controller<L>,
+        snapshot: SnapshotFn<L::RootActor>,
+        restore: RestoreFn<L::RootActor>,
+    ) {
+        let Some(actor) = self.actors.get(&id) else {
+            self.handler.handle_err(InternalError::UnknownActor(id));
+            return;
+        };
+        let snapshot = match snapshot(actor) {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                self.handler
+                    .handle_err(InternalError::ActorError(self.id, err));
+                return;
+            }
+        };
+        let Some(mut actor) = self.actors.remove(&id) else {
+            return;
+        };
+        controller.unregister_actor(&id);
+        // Both actors may operate on the same I/O resource, so the actor must
+        // be unregistered from the scheduler before it is restored
+        if let Err(err) = self.unregister_io(&id) {
+            actor.handle_err(err).unwrap_or_else(|err| {
+                self.handler
+                    .handle_err(InternalError::ActorError(self.id, err))
+            });
+            return;
+        }
+        if let Err(err) = target.send_event(self.id, ControlEvent::Restore(snapshot, restore)) {
+            self.handler.handle_err(err);
+            if let Err(err) = self.register(controller, actor) {
+                self.handler.handle_err(err);
+            }
+            return;
+        }
+        self.handler.on_disconnect(&id, &DisconnectReason::Migrated);
+        actor
+            .disconnect()
+            .or_else(|err| actor.handle_err(err))
+            .unwrap_or_else(|err| {
+                self.handler
+                    .handle_err(InternalError::ActorError(self.id, err))
+            });
+    }
+
+    /// Processes at most `max` control events.
+    ///
+    /// # Returns
+    ///
+    /// `false` if the control channel is disconnected, which is treated as a
+    /// shutdown request.
+    fn process_control(&mut self, controller: &Controller<L>, max: usize) -> bool {
+        for _ in 0..max {
+            let event = match self.control_recv.try_recv() {
+                Err(chan::TryRecvError::Disconnected) => return false,
                 Err(chan::TryRecvError::Empty) => break,
-                Ok(event) => match event {
-                    ControlEvent::Connect(context) => {
-                        match L::RootActor::with(context, controller.clone()) {
-                            Err(err) => self
-                                .handler
-                                .handle_err(InternalError::ActorError(self.id, err)),
-                            Ok(mut resource) => {
-                                self.scheduler
-                                    .register_actor(&resource)
-                                    .or_else(|err| resource.handle_err(err))
-                                    .unwrap_or_else(|err| {
-                                        self.handler
-                                            .handle_err(InternalError::ActorError(self.id, err))
-                                    });
-                                self.actors.insert(resource.id(), resource);
+                Ok(event) => event,
+            };
+            self.metrics.control_events += 1;
+            self.handler.on_control(&event);
+            match event {
+                ControlEvent::Connect(context) => {
+                    if let Err(err) = self.connect(controller, context) {
+                        self.handler.handle_err(err);
+                    }
+                }
+                ControlEvent::ConnectReport(context, callback) => {
+                    callback(self.connect(controller, context))
+                }
+                ControlEvent::Insert(provider) => {
+                    if let Err(err) = self.start(controller, provider()) {
+                        self.handler.handle_err(err);
+                    }
+                }
+                ControlEvent::Take(id, callback) => match self.actors.remove(&id) {
+                    Some(mut actor) => {
+                        controller.unregister_actor(&id);
+                        match self.unregister_io(&id) {
+                            Ok(()) => callback(actor),
+                            Err(err) => {
+                                actor.handle_err(err).unwrap_or_else(|err| {
+                                    self.handler
+                                        .handle_err(InternalError::ActorError(self.id, err))
+                                });
+                            }
+                        }
+                    }
+                    None => self.handler.handle_err(InternalError::UnknownActor(id)),
+                },
+                ControlEvent::Replace(id, provider, callback) => {
+                    self.replace(controller, id, provider(), callback)
+                }
+                ControlEvent::Migrate(id, target, snapshot, restore) => {
+                    self.migrate(controller, id, target, snapshot, restore)
+                }
+                ControlEvent::Restore(snapshot, restore) => {
+                    match restore(snapshot, controller.clone()) {
+                        Ok(actor) => {
+                            if let Err(err) = self.start(controller, actor) {
+                                self.handler.handle_err(err);
                             }
-                        };
-                        // TODO: Consider to error to the user if the resource was already present
+                        }
+                        Err(err) => self
+                            .handler
+                            .handle_err(InternalError::ActorError(self.id, err)),
+                    }
+                }
+                ControlEvent::Reconnect {
+                    id,
+                    context,
+                    backoff,
+                    multiplier,
+                    remaining,
+                } => {
+                    if remaining > 0 {
+                        self.reconnects.push(PendingReconnect {
+                            id,
+                            deadline: Instant::now() + backoff,
+                            context,
+                            backoff,
+                            multiplier,
+                            remaining,
+                        });
+                    }
+                }
+                ControlEvent::Disconnect(id) => match self.actors.remove(&id) {
+                    Some(actor) => {
+                        self.disconnect(controller, id, actor, DisconnectReason::OnDemand)
+                    }
+                    // Actor which is being gracefully disconnected does not
+                    // wait for its output to be written anymore
+                    None => match self.draining.remove(&id) {
+                        Some(actor) => {
+                            self.drain_timeouts.cancel(&id);
+                            self.disconnect(controller, id, actor, DisconnectReason::OnDemand)
+                        }
+                        None => self.handler.handle_err(InternalError::UnknownActor(id)),
+                    },
+                },
+                ControlEvent::DisconnectGraceful(id, timeout) => match self.actors.remove(&id) {
+                    Some(actor) if actor.has_pending_output() => {
+                        self.drain_timeouts
+                            .register(id.clone(), Instant::now() + timeout);
+                        self.draining.insert(id, actor);
+                    }
+                    Some(actor) => {
+                        self.disconnect(controller, id, actor, DisconnectReason::OnDemand)
+                    }
+                    None => self.handler.handle_err(InternalError::UnknownActor(id)),
+                },
+                ControlEvent::HalfClose(id) => match self.actors.get_mut(&id) {
+                    Some(actor) => {
+                        actor
+                            .shutdown_write()
+                            .or_else(|err| actor.handle_err(err))
+                            .unwrap_or_else(|err| {
+                                self.handler
+                                    .handle_err(InternalError::ActorError(self.id, err))
+                            });
+                        self.update_interest(&id);
+                    }
+                    None => self.handler.handle_err(InternalError::UnknownActor(id)),
+                },
+                ControlEvent::DisconnectAll(reply) => {
+                    // Actors are disconnected at once, since waiting for their
+                    // output would block the pool
+                    let count = self.actors.len() + self.draining.len();
+                    self.disconnect_all(controller);
+                    self.handler.on_disconnect_all(count);
+                    // The requester may have already timed out and dropped the receiver
+                    let _ = reply.send(count);
+                }
+                ControlEvent::SetTimer(token, deadline) => {
+                    let duration = deadline.saturating_duration_since(Instant::now());
+                    match self.scheduler.set_timer(token, duration) {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            self.timeouts.register(token, deadline);
+                        }
+                        Err(err) => {
+                            self.handler
+                                .handle_err(InternalError::ActorError(self.id, err));
+                            self.timeouts.register(token, deadline);
+                        }
                     }
-                    ControlEvent::Disconnect(id) => {
-                        self.scheduler.unregister_actor(&id).unwrap_or_else(|err| {
+                }
+                ControlEvent::CancelTimer(token) => {
+                    if !self.timeouts.cancel(&token) {
+                        self.scheduler.cancel_timer(token).unwrap_or_else(|err| {
                             self.handler
                                 .handle_err(InternalError::ActorError(self.id, err))
                         });
-                        self.actors.remove(&id);
-                        // TODO: Don't we need to shutdown the resource?
                     }
-                    ControlEvent::SetTimer() => {
-                        // TODO: Add timeout manager
+                }
+                ControlEvent::Query(kind, reply) => {
+                    let response = match kind {
+                        QueryKind::Count => QueryResponse::Count(self.actors.len()),
+                        QueryKind::Contains(id) => {
+                            QueryResponse::Contains(self.actors.contains_key(&id))
+                        }
+                        QueryKind::List => {
+                            QueryResponse::List(self.actors.keys().cloned().collect())
+                        }
+                        QueryKind::Metrics => QueryResponse::Metrics(ReactorMetrics {
+                            control_queue_len: self.control_recv.len(),
+                            actors: self.actors.len(),
+                            draining: self.draining.len(),
+                            ..self.metrics
+                        }),
+                        QueryKind::IoStats => {
+                            QueryResponse::IoStats(self.scheduler.io_stats().into_iter().collect())
+                        }
+                    };
+                    // The requester may have already timed out and dropped the receiver
+                    let _ = reply.send(response);
+                }
+                ControlEvent::Broadcast(cmd, filter, reply) => {
+                    let mut count = 0;
+                    let mut panicked = vec![];
+                    for (id, actor) in self.actors.iter_mut() {
+                        if matches!(&filter, Some(filter) if !filter(id)) {
+                            continue;
+                        }
+                        count += 1;
+                        match panic::catch_unwind(AssertUnwindSafe(|| {
+                            actor
+                                .handle_cmd(cmd.clone())
+                                .or_else(|err| actor.handle_err(err))
+                        })) {
+                            Ok(Ok(())) => {}
+                            Ok(Err(err)) => self
+                                .handler
+                                .handle_err(InternalError::ActorError(self.id, err)),
+                            Err(payload) => panicked.push((id.clone(), payload)),
+                        }
+                    }
+                    for (id, payload) in panicked {
+                        self.remove_panicked(controller, id, payload);
                     }
-                    ControlEvent::Send(id, data) => {
-                        if let Some(resource) = self.actors.get_mut(&id) {
+                    let ids = self.actors.keys().cloned().collect::<Vec<_>>();
+                    for id in ids {
+                        self.update_interest(&id);
+                    }
+                    // The requester may have already timed out and dropped the receiver
+                    let _ = reply.send(count);
+                }
+                ControlEvent::Send(id, data) => {
+                    if let Some(resource) = self.actors.get_mut(&id) {
+                        match panic::catch_unwind(AssertUnwindSafe(|| {
                             resource
                                 .handle_cmd(data)
                                 .or_else(|err| resource.handle_err(err))
-                                .unwrap_or_else(|err| {
-                                    self.handler
-                                        .handle_err(InternalError::ActorError(self.id, err))
-                                });
+                        })) {
+                            Ok(Ok(())) => self.update_interest(&id),
+                            Ok(Err(err)) => {
+                                self.handler
+                                    .handle_err(InternalError::ActorError(self.id, err));
+                                self.update_interest(&id);
+                            }
+                            Err(payload) => self.remove_panicked(controller, id, payload),
                         }
+                    } else if self.draining.contains_key(&id) {
+                        self.handler.handle_err(InternalError::ActorDraining(id));
                     }
-                },
+                }
             }
+            self.handler.on_control_done();
         }
+        true
     }
 
-    fn process_shutdown(&mut self) {
-        match self.shutdown.try_recv() {
-            Err(chan::TryRecvError::Empty) => {
-                // Nothing to do here
-            }
-            Ok(()) => {
-                // TODO: Disconnect all resources
-            }
-            Err(chan::TryRecvError::Disconnected) => {
-                panic!("re-actor shutdown channel was dropper")
+    /// Control events which were sent before the shutdown request are still
+    /// processed before disconnecting the actors. Actors having pending output
+    /// are given a grace period to write it out; after that period they get
+    /// disconnected anyway.
+    ///
+    /// Dropped [`Reactor`] (and thus the disconnected shutdown channel) is
+    /// treated as a shutdown request, as well as `forced` flag.
+    ///
+    /// # Returns
+    ///
+    /// Whether the shutdown was requested and all actors were disconnected.
+    fn process_shutdown(&mut self, controller: &Controller<L>, forced: bool) -> bool {
+        let requested = match self.shutdown.try_recv() {
+            Ok(()) | Err(chan::TryRecvError::Disconnected) => true,
+            Err(chan::TryRecvError::Empty) => forced,
+        };
+        if requested {
+            self.process_control(controller, self.control_recv.len());
+            self.drain(controller);
+            self.disconnect_all(controller);
+        }
+        requested
+    }
+
+    fn drain(&mut self, controller: &Controller<L>) {
+        let deadline = Instant::now() + self.shutdown_grace;
+        while self
+            .actors
+            .values()
+            .chain(self.draining.values())
+            .any(Actor::has_pending_output)
+        {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
             }
+            self.process_io(controller, Some(deadline - now));
+        }
+    }
+
+    fn disconnect_all(&mut self, controller: &Controller<L>) {
+        let actors = self
+            .actors
+            .drain()
+            .chain(self.draining.drain())
+            .collect::<Vec<_>>();
+        for (id, actor) in actors {
+            self.drain_timeouts.cancel(&id);
+            self.disconnect(controller, id, actor, DisconnectReason::OnDemand);
         }
     }
+
+    /// Unregisters actor, which was already removed from the actor map, and
+    /// shuts down its I/O.
+    fn disconnect(
+        &mut self,
+        controller: &Controller<L>,
+        id: <L::RootActor as Actor>::Id,
+        mut actor: L::RootActor,
+        reason: DisconnectReason,
+    ) {
+        self.handler.on_disconnect(&id, &reason);
+        controller.unregister_actor(&id);
+        self.unregister_io(&id)
+            .and_then(|_| actor.disconnect())
+            .or_else(|err| actor.handle_err(err))
+            .unwrap_or_else(|err| {
+                self.handler
+                    .handle_err(InternalError::ActorError(self.id, err))
+            });
+    }
 }
@@ -0,0 +1,2002 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+#[cfg(unix)]
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{io, mem, thread};
+
+use crossbeam_channel as chan;
+
+use crate::actors::{AsRawSource, DisconnectReason, IoEv, IoSrc, RawSource, DEFAULT_PRIORITY};
+use crate::schedulers::{IoStats, IoStatsMap, PriorityScheduler, Waker};
+use crate::{
+    Actor, Controller, EventKind, Handler, InternalError, Layout, Pool, Reactor, ReactorApi,
+    Scheduler, StallDetector, TimerToken, WatchdogReactor, DEFAULT_QUERY_TIMEOUT,
+    DEFAULT_SHUTDOWN_GRACE,
+};
+
+/// Time for which the tests wait for the events from the re-actor.
+const TICK: Duration = Duration::from_millis(10);
+
+/// Events reported by the test actors and handlers.
+#[derive(Clone, Ord, PartialOrd, Eq, PartialEq, Debug)]
+pub enum Event {
+    Timer(TimerToken),
+    Cmd(u32),
+    Io(u32),
+    Disconnected(u32),
+    HalfClosed(u32),
+    DisconnectedAll(usize),
+    Idle(u32),
+    PoolIdle,
+    Deadline(u32),
+    OnConnect(u32),
+    OnDisconnect(u32, String),
+    Panicked(u32, String),
+    Error(String),
+}
+
+thread_local! {
+    /// Channel for reporting events, set up by [`reactor`] for the test thread.
+    static EVENTS: RefCell<Option<chan::Sender<Event>>> = RefCell::new(None);
+
+    /// Configuration of the pool constructed by [`TestPool::default_pools`],
+    /// set up by [`reactor_with`] for the test thread.
+    static CONFIG: RefCell<TestConfig> = RefCell::new(TestConfig::default());
+}
+
+fn events() -> chan::Sender<Event> {
+    EVENTS.with(|events| {
+        events
+            .borrow()
+            .clone()
+            .expect("test re-actor is not constructed")
+    })
+}
+
+/// Configuration of the test re-actor and of its [`TestPool::Main`] pool.
+#[derive(Clone, Debug)]
+pub struct TestConfig {
+    /// Shutdown grace period of the re-actor.
+    pub shutdown_grace: Duration,
+    /// Capacity of the control queue, which is unbounded if not set.
+    pub capacity: Option<usize>,
+    /// Time after which the pool becomes idle, notifying both the handler and
+    /// the actors.
+    pub idle_timeout: Option<Duration>,
+    /// Whether the handler reports actors added to and removed from the pool.
+    pub lifecycle: bool,
+    /// Ids of the actors started by the handler once the pool runtime starts.
+    pub startup: Vec<u32>,
+    /// Number of readable events the scheduler generates for each actor once
+    /// it is registered. Each actor sends a command to itself on its first
+    /// I/O event.
+    pub flood: usize,
+    /// Maximal number of I/O events dispatched by the pool in a single
+    /// iteration, in total and to a single actor.
+    pub io_budget: Option<(usize, usize)>,
+    /// Whether the scheduler dispatches the events in the order of the actor
+    /// priorities.
+    pub priorities: bool,
+    /// Number of actors the scheduler is able to register at once.
+    pub scheduler_capacity: Option<usize>,
+    /// Number of the first actor registrations the scheduler refuses.
+    pub scheduler_refusals: usize,
+}
+
+impl Default for TestConfig {
+    fn default() -> Self {
+        TestConfig {
+            shutdown_grace: DEFAULT_SHUTDOWN_GRACE,
+            capacity: None,
+            idle_timeout: None,
+            lifecycle: false,
+            startup: vec![],
+            flood: 0,
+            io_budget: None,
+            priorities: false,
+            scheduler_capacity: None,
+            scheduler_refusals: 0,
+        }
+    }
+}
+
+/// Constructs re-actor with a single [`TestPool::Main`] pool, returning it
+/// together with the receiver of the events happening in that pool.
+pub fn reactor() -> (Reactor<TestPool>, chan::Receiver<Event>) {
+    reactor_with(TestConfig::default())
+}
+
+/// Constructs test re-actor like [`reactor`] using the given configuration.
+pub fn reactor_with(config: TestConfig) -> (Reactor<TestPool>, chan::Receiver<Event>) {
+    let recv = init_events();
+    let (shutdown_grace, capacity) = (config.shutdown_grace, config.capacity);
+    CONFIG.with(|cell| *cell.borrow_mut() = config);
+    let reactor = match capacity {
+        Some(capacity) => Reactor::with_capacity(shutdown_grace, capacity),
+        None => Reactor::with(shutdown_grace),
+    }
+    .expect("unable to construct re-actor");
+    (reactor, recv)
+}
+
+fn init_events() -> chan::Receiver<Event> {
+    let (send, recv) = chan::unbounded();
+    EVENTS.with(|events| *events.borrow_mut() = Some(send));
+    recv
+}
+
+/// Starts actors with the given ids and waits until they get registered with
+/// the re-actor.
+pub fn start_actors(controller: &mut Controller<TestPool>, ids: impl IntoIterator<Item = u32>) {
+    let ids = ids.into_iter().collect::<Vec<_>>();
+    for id in &ids {
+        controller
+            .start_actor(TestPool::Main, TestCtx::new(*id))
+            .unwrap();
+    }
+    while !ids.iter().all(|id| controller.pool_for(*id).is_ok()) {
+        thread::sleep(TICK);
+    }
+}
+
+/// Collects events reported so far, waiting for at most `timeout` between
+/// them.
+pub fn collect(events: &chan::Receiver<Event>, timeout: Duration) -> Vec<Event> {
+    let mut collected = vec![];
+    while let Ok(event) = events.recv_timeout(timeout) {
+        collected.push(event);
+    }
+    collected
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display(Debug)]
+pub enum TestPool {
+    Main,
+}
+
+impl From<u32> for TestPool {
+    fn from(pool: u32) -> Self {
+        match pool {
+            0 => TestPool::Main,
+            _ => panic!("unknown test pool {pool}"),
+        }
+    }
+}
+
+impl From<TestPool> for u32 {
+    fn from(pool: TestPool) -> Self {
+        match pool {
+            TestPool::Main => 0,
+        }
+    }
+}
+
+impl Layout for TestPool {
+    type RootActor = TestActor;
+
+    fn default_pools() -> Vec<Pool<TestActor, Self>> {
+        let config = CONFIG.with(|cell| cell.take());
+        let handler = TestHandler {
+            events: events(),
+            reports_lifecycle: config.lifecycle,
+            startup: config.startup.into_iter().map(TestCtx::new).collect(),
+        };
+        let scheduler = IdleScheduler {
+            flood: config.flood,
+            capacity: config.scheduler_capacity,
+            refusals: config.scheduler_refusals,
+            ..default!()
+        };
+        let mut pool = if config.priorities {
+            Pool::new(TestPool::Main, PriorityScheduler::new(scheduler), handler)
+        } else {
+            Pool::new(TestPool::Main, scheduler, handler)
+        };
+        if let Some((max_events, max_events_per_actor)) = config.io_budget {
+            pool = pool.with_io_budget(max_events, max_events_per_actor);
+        }
+        if let Some(timeout) = config.idle_timeout {
+            pool = pool.with_idle_timeout(timeout).with_actor_idle();
+        }
+        vec![pool]
+    }
+
+    fn convert(other_ctx: Box<dyn Any>) -> <TestActor as Actor>::Context {
+        *other_ctx
+            .downcast()
+            .expect("test actors do not support context conversion")
+    }
+}
+
+/// Context for constructing [`TestActor`].
+#[derive(Clone)]
+pub struct TestCtx {
+    id: u32,
+    events: chan::Sender<Event>,
+    hung: bool,
+    failures: Arc<AtomicU32>,
+    gate: Option<chan::Receiver<()>>,
+    freeze: Option<Arc<Mutex<()>>>,
+    deadline: Option<Duration>,
+    panics: bool,
+    priority: u8,
+    echoes: usize,
+    broadcasts: bool,
+}
+
+impl TestCtx {
+    /// Context for an actor which operates normally.
+    pub fn new(id: u32) -> Self {
+        TestCtx {
+            id,
+            events: events(),
+            hung: false,
+            failures: Arc::new(AtomicU32::new(0)),
+            gate: None,
+            freeze: None,
+            deadline: None,
+            panics: false,
+            priority: DEFAULT_PRIORITY,
+            echoes: 0,
+            broadcasts: false,
+        }
+    }
+
+    /// Context for an actor which fails to get constructed the first
+    /// `failures` times.
+    pub fn failing(id: u32, failures: u32) -> Self {
+        TestCtx {
+            failures: Arc::new(AtomicU32::new(failures)),
+            ..TestCtx::new(id)
+        }
+    }
+
+    /// Context for an actor which blocks after reporting each command until it
+    /// receives a message from the `gate` or the gate gets closed.
+    pub fn gated(id: u32, gate: chan::Receiver<()>) -> Self {
+        TestCtx {
+            gate: Some(gate),
+            ..TestCtx::new(id)
+        }
+    }
+
+    /// Context for an actor which locks the `freeze` mutex on each command,
+    /// blocking the pool while the mutex is held elsewhere.
+    pub fn frozen(id: u32, freeze: Arc<Mutex<()>>) -> Self {
+        TestCtx {
+            freeze: Some(freeze),
+            ..TestCtx::new(id)
+        }
+    }
+
+    /// Context for an actor which misses its deadline in `timeout` after
+    /// its construction, failing to handle it.
+    pub fn expiring(id: u32, timeout: Duration) -> Self {
+        TestCtx {
+            deadline: Some(timeout),
+            ..TestCtx::new(id)
+        }
+    }
+
+    /// Context for an actor which panics on each command, deadline and idle
+    /// notification.
+    pub fn panicking(id: u32) -> Self {
+        TestCtx {
+            panics: true,
+            ..TestCtx::new(id)
+        }
+    }
+
+    /// Context for an actor with the given priority of its I/O events.
+    pub fn prioritized(id: u32, priority: u8) -> Self {
+        TestCtx {
+            priority,
+            ..TestCtx::new(id)
+        }
+    }
+
+    /// Context for an actor which sends `echoes` commands to itself once it
+    /// receives its first command, reporting the first failed one.
+    pub fn echoing(id: u32, echoes: usize) -> Self {
+        TestCtx {
+            echoes,
+            ..TestCtx::new(id)
+        }
+    }
+
+    /// Context for an actor which tries to broadcast a command and to
+    /// disconnect all actors on each command, reporting the failures.
+    pub fn broadcasting(id: u32) -> Self {
+        TestCtx {
+            broadcasts: true,
+            ..TestCtx::new(id)
+        }
+    }
+
+    /// Context for an actor which never completes writing its output.
+    pub fn hung(id: u32) -> Self {
+        TestCtx {
+            hung: true,
+            ..TestCtx::new(id)
+        }
+    }
+}
+
+/// Actor which does no I/O and just reports its lifecycle events.
+pub struct TestActor {
+    id: u32,
+    events: chan::Sender<Event>,
+    hung: bool,
+    gate: Option<chan::Receiver<()>>,
+    freeze: Option<Arc<Mutex<()>>>,
+    deadline: Option<Instant>,
+    panics: bool,
+    priority: u8,
+    echoes: usize,
+    broadcasts: bool,
+    controller: Controller<TestPool>,
+    io_events: usize,
+}
+
+impl Actor for TestActor {
+    type Layout = TestPool;
+    type Id = u32;
+    type Context = TestCtx;
+    type Cmd = ();
+    type Error = io::Error;
+
+    fn with(ctx: Self::Context, controller: Controller<Self::Layout>) -> Result<Self, Self::Error> {
+        if ctx
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+        {
+            return Err(io::ErrorKind::ConnectionRefused.into());
+        }
+        Ok(TestActor {
+            id: ctx.id,
+            events: ctx.events,
+            hung: ctx.hung,
+            gate: ctx.gate,
+            freeze: ctx.freeze,
+            deadline: ctx.deadline.map(|timeout| Instant::now() + timeout),
+            panics: ctx.panics,
+            priority: ctx.priority,
+            echoes: ctx.echoes,
+            broadcasts: ctx.broadcasts,
+            controller,
+            io_events: 0,
+        })
+    }
+
+    fn id(&self) -> Self::Id {
+        self.id
+    }
+
+    fn io_ready(&mut self, _io: IoEv) -> Result<(), Self::Error> {
+        self.io_events += 1;
+        if self.io_events == 1 {
+            self.controller
+                .send(self.id, ())
+                .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        }
+        self.events
+            .send(Event::Io(self.id))
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn handle_cmd(&mut self, _cmd: Self::Cmd) -> Result<(), Self::Error> {
+        if self.panics {
+            panic!("test actor {} panics", self.id);
+        }
+        self.events
+            .send(Event::Cmd(self.id))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        for _ in 0..mem::take(&mut self.echoes) {
+            if let Err(err) = self.controller.send(self.id, ()) {
+                let _ = self.events.send(Event::Error(err.to_string()));
+                break;
+            }
+        }
+        if self.broadcasts {
+            for res in [
+                self.controller.broadcast(()),
+                self.controller.disconnect_all(),
+            ] {
+                if let Err(err) = res {
+                    let _ = self.events.send(Event::Error(err.to_string()));
+                }
+            }
+        }
+        if let Some(gate) = &self.gate {
+            let _ = gate.recv();
+        }
+        if let Some(freeze) = &self.freeze {
+            drop(freeze.lock());
+        }
+        Ok(())
+    }
+
+    fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+        Err(err)
+    }
+
+    fn has_pending_output(&self) -> bool {
+        self.hung
+    }
+
+    fn shutdown_write(&mut self) -> Result<(), Self::Error> {
+        self.events
+            .send(Event::HalfClosed(self.id))
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+
+    fn on_idle(&mut self) {
+        if self.panics {
+            panic!("test actor {} panics", self.id);
+        }
+        let _ = self.events.send(Event::Idle(self.id));
+    }
+
+    fn priority(&self) -> u8 {
+        self.priority
+    }
+
+    fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    fn on_deadline(&mut self) -> Result<(), Self::Error> {
+        if self.panics {
+            panic!("test actor {} panics", self.id);
+        }
+        let _ = self.events.send(Event::Deadline(self.id));
+        Err(io::ErrorKind::TimedOut.into())
+    }
+
+    fn disconnect(&mut self) -> Result<(), Self::Error> {
+        self.events
+            .send(Event::Disconnected(self.id))
+            .map_err(|_| io::ErrorKind::BrokenPipe.into())
+    }
+}
+
+pub struct TestHandler {
+    events: chan::Sender<Event>,
+    /// Whether to report actors added to and removed from the pool, which
+    /// happens in most of the tests.
+    reports_lifecycle: bool,
+    /// Actors to start once the pool runtime starts.
+    startup: Vec<TestCtx>,
+}
+
+impl Handler<TestPool> for TestHandler {
+    fn handle_err(&mut self, err: InternalError<TestPool>) {
+        let _ = self.events.send(Event::Error(err.to_string()));
+    }
+
+    fn on_startup(&mut self, mut controller: Controller<TestPool>) {
+        for ctx in mem::take(&mut self.startup) {
+            if let Err(err) = controller.start_actor(TestPool::Main, ctx) {
+                let _ = self.events.send(Event::Error(err.to_string()));
+            }
+        }
+    }
+
+    fn handle_panic(&mut self, id: &u32, payload: Box<dyn Any + Send>) {
+        let message = payload
+            .downcast::<String>()
+            .map(|message| *message)
+            .unwrap_or_default();
+        let _ = self.events.send(Event::Panicked(*id, message));
+    }
+
+    fn on_timer(&mut self, token: TimerToken) {
+        let _ = self.events.send(Event::Timer(token));
+    }
+
+    fn on_connect(&mut self, id: &u32) {
+        if self.reports_lifecycle {
+            let _ = self.events.send(Event::OnConnect(*id));
+        }
+    }
+
+    fn on_disconnect(&mut self, id: &u32, reason: &DisconnectReason) {
+        if self.reports_lifecycle {
+            let _ = self
+                .events
+                .send(Event::OnDisconnect(*id, reason.to_string()));
+        }
+    }
+
+    fn on_disconnect_all(&mut self, count: usize) {
+        let _ = self.events.send(Event::DisconnectedAll(count));
+    }
+
+    fn on_idle(&mut self) {
+        let _ = self.events.send(Event::PoolIdle);
+    }
+}
+
+/// Scheduler which does no I/O and just blocks until the timeout or until
+/// woken up. It generates no I/O events, unless configured with
+/// [`TestConfig::flood`].
+pub struct IdleScheduler<Id> {
+    wake_send: chan::Sender<()>,
+    wake_recv: chan::Receiver<()>,
+    flood: usize,
+    capacity: Option<usize>,
+    refusals: usize,
+    registered: HashSet<Id>,
+    events: VecDeque<IoSrc<Id>>,
+    stats: IoStatsMap<Id>,
+}
+
+impl<Id> Default for IdleScheduler<Id> {
+    fn default() -> Self {
+        let (wake_send, wake_recv) = chan::bounded(1);
+        IdleScheduler {
+            wake_send,
+            wake_recv,
+            flood: 0,
+            capacity: None,
+            refusals: 0,
+            registered: empty!(),
+            events: empty!(),
+            stats: default!(),
+        }
+    }
+}
+
+impl<Id: Clone + Eq + Hash> Iterator for IdleScheduler<Id> {
+    type Item = IoSrc<Id>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.stats.deliver(self.events.pop_front())
+    }
+}
+
+impl<R: Actor> Scheduler<R> for IdleScheduler<R::Id>
+where
+    R::Error: From<io::Error>,
+{
+    fn has_actor(&self, _id: &R::Id) -> bool {
+        false
+    }
+
+    fn register_actor(&mut self, actor: &R) -> Result<(), R::Error> {
+        if matches!(self.capacity, Some(capacity) if self.registered.len() >= capacity) {
+            return Err(io::Error::new(io::ErrorKind::Other, "scheduler is full").into());
+        }
+        if self.refusals > 0 {
+            self.refusals -= 1;
+            return Err(io::Error::new(io::ErrorKind::Other, "scheduler refuses the actor").into());
+        }
+        self.registered.insert(actor.id());
+        let io = IoEv {
+            is_readable: true,
+            is_writable: false,
+            is_hangup: false,
+            is_error: false,
+        };
+        for _ in 0..self.flood {
+            self.events.push_back(IoSrc {
+                source: actor.id(),
+                io,
+            });
+        }
+        self.stats.register(actor.id());
+        Ok(())
+    }
+
+    fn unregister_actor(&mut self, id: &R::Id) -> Result<(), R::Error> {
+        self.events.retain(|ev| ev.source != *id);
+        self.registered.remove(id);
+        self.stats.unregister(id);
+        Ok(())
+    }
+
+    fn set_interest(&mut self, _id: &R::Id, _interest: IoEv) -> Result<(), R::Error> {
+        Ok(())
+    }
+
+    fn wait_io(&mut self, timeout: Option<Duration>) -> Result<bool, R::Error> {
+        if !self.events.is_empty() {
+            return Ok(false);
+        }
+        let woken = match timeout {
+            Some(timeout) => self.wake_recv.recv_timeout(timeout).is_ok(),
+            None => self.wake_recv.recv().is_ok(),
+        };
+        Ok(!woken)
+    }
+
+    fn waker(&self) -> Arc<dyn Waker> {
+        Arc::new(IdleWaker(self.wake_send.clone()))
+    }
+
+    fn io_stats(&self) -> HashMap<R::Id, IoStats> {
+        self.stats.snapshot()
+    }
+}
+
+struct IdleWaker(chan::Sender<()>);
+
+impl Waker for IdleWaker {
+    fn wake(&self) -> io::Result<()> {
+        // Scheduler is already going to be woken if the channel is full
+        let _ = self.0.try_send(());
+        Ok(())
+    }
+}
+
+/// File descriptor (or socket on Windows) used as an actor id in the
+/// scheduler tests.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display(Debug)]
+pub struct Fd(pub RawSource);
+
+#[cfg(unix)]
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+#[cfg(feature = "polling")]
+impl polling::Source for Fd {
+    fn raw(&self) -> RawSource {
+        self.0
+    }
+}
+
+/// Actor used only for registering file descriptors with the schedulers,
+/// which are driven directly by the tests.
+pub struct FdActor {
+    fd: Fd,
+    interest: IoEv,
+}
+
+impl FdActor {
+    /// Actor interested in both read and write events.
+    pub fn new(fd: &impl AsRawSource) -> Self {
+        FdActor {
+            fd: Fd(fd.as_raw_source()),
+            interest: IoEv {
+                is_readable: true,
+                is_writable: true,
+                is_hangup: false,
+                is_error: false,
+            },
+        }
+    }
+}
+
+impl Actor for FdActor {
+    type Layout = TestPool;
+    type Id = Fd;
+    type Context = ();
+    type Cmd = ();
+    type Error = io::Error;
+
+    fn with(_: (), _: Controller<TestPool>) -> Result<Self, Self::Error> {
+        unreachable!("file descriptor actors are constructed by the tests")
+    }
+
+    fn id(&self) -> Self::Id {
+        self.fd
+    }
+
+    fn io_ready(&mut self, _: IoEv) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn handle_cmd(&mut self, _: ()) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+        Err(err)
+    }
+
+    fn interests(&self) -> IoEv {
+        self.interest
+    }
+}
+
+/// Waits for I/O with the scheduler, returning events reported for `fd`.
+fn wait_events(scheduler: &mut impl Scheduler<FdActor>, fd: Fd) -> Vec<IoEv> {
+    scheduler.wait_io(Some(Duration::from_millis(100))).unwrap();
+    scheduler
+        .filter(|ev| ev.source == fd)
+        .map(|ev| ev.io)
+        .collect()
+}
+
+/// Checks that the scheduler does not report events the actor is not
+/// interested in, such that an idle connection does not make the runtime to
+/// spin on write events.
+pub fn check_idle_connection(scheduler: &mut impl Scheduler<FdActor>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let fd = Fd(server.as_raw_source());
+    let read_only = IoEv {
+        is_readable: true,
+        is_writable: false,
+        is_hangup: false,
+        is_error: false,
+    };
+
+    scheduler.register_actor(&FdActor::new(&server)).unwrap();
+    let events = wait_events(scheduler, fd);
+    assert!(events.iter().any(|io| io.is_writable));
+
+    scheduler.set_interest(&fd, read_only).unwrap();
+    // Events generated before the interest change are dropped
+    wait_events(scheduler, fd);
+    for _ in 0..3 {
+        assert_eq!(wait_events(scheduler, fd), vec![]);
+    }
+
+    client.write_all(&[1]).unwrap();
+    assert_eq!(wait_events(scheduler, fd), vec![read_only]);
+
+    scheduler
+        .set_interest(
+            &fd,
+            IoEv {
+                is_readable: false,
+                is_writable: true,
+                is_hangup: false,
+                is_error: false,
+            },
+        )
+        .unwrap();
+    let events = wait_events(scheduler, fd);
+    assert!(!events.is_empty());
+    assert!(events.iter().all(|io| io.is_writable));
+    scheduler.unregister_actor(&fd).unwrap();
+}
+
+/// Checks that the scheduler reports the connection closed by the remote peer
+/// as a hang-up.
+pub fn check_hangup(scheduler: &mut impl Scheduler<FdActor>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server, _) = listener.accept().unwrap();
+    let fd = Fd(server.as_raw_source());
+    let mut actor = FdActor::new(&server);
+    actor.interest.is_writable = false;
+
+    scheduler.register_actor(&actor).unwrap();
+    assert_eq!(wait_events(scheduler, fd), vec![]);
+
+    drop(client);
+    let events = wait_events(scheduler, fd);
+    assert!(events.iter().any(|io| io.is_readable && io.is_hangup));
+    assert!(events.iter().all(|io| !io.is_error));
+    scheduler.unregister_actor(&fd).unwrap();
+}
+
+/// Checks that the scheduler does not report events of an actor which was
+/// unregistered while processing events of the same batch, as it happens when
+/// one actor disconnects another one from its `io_ready`.
+pub fn check_unregister_pending(scheduler: &mut impl Scheduler<FdActor>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let _client1 = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server1, _) = listener.accept().unwrap();
+    let _client2 = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server2, _) = listener.accept().unwrap();
+    let fds = [Fd(server1.as_raw_source()), Fd(server2.as_raw_source())];
+
+    // Both connections are writable, so they get reported within one batch
+    scheduler.register_actor(&FdActor::new(&server1)).unwrap();
+    scheduler.register_actor(&FdActor::new(&server2)).unwrap();
+    thread::sleep(TICK);
+    scheduler.wait_io(Some(Duration::from_millis(100))).unwrap();
+    let first = scheduler.next().expect("no I/O events").source;
+    let other = if first == fds[0] { fds[1] } else { fds[0] };
+
+    scheduler.unregister_actor(&other).unwrap();
+    assert!(scheduler.all(|ev| ev.source != other));
+    scheduler.unregister_actor(&first).unwrap();
+}
+
+/// Checks that the scheduler counts the events delivered to each of the
+/// actors, such that an actor generating a lot of I/O stands out against an
+/// idle one.
+pub fn check_io_stats(scheduler: &mut impl Scheduler<FdActor>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut busy_client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (mut busy_server, _) = listener.accept().unwrap();
+    let _idle_client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (idle_server, _) = listener.accept().unwrap();
+    busy_server.set_nonblocking(true).unwrap();
+    let busy = Fd(busy_server.as_raw_source());
+    let idle = Fd(idle_server.as_raw_source());
+    for server in [&busy_server, &idle_server] {
+        let mut actor = FdActor::new(server);
+        actor.interest.is_writable = false;
+        scheduler.register_actor(&actor).unwrap();
+    }
+
+    let start = Instant::now();
+    while start.elapsed() < Duration::from_millis(500) {
+        busy_client.write_all(&[1]).unwrap();
+        scheduler.wait_io(Some(Duration::from_millis(100))).unwrap();
+        if scheduler.by_ref().any(|ev| ev.source == busy) {
+            let _ = busy_server.read(&mut [0u8; 64]);
+        }
+    }
+    let stats = scheduler.io_stats();
+    let (busy_stats, idle_stats) = (stats[&busy], stats[&idle]);
+    assert!(busy_stats.readable_events >= 100);
+    assert!(busy_stats.readable_events >= 100 * idle_stats.readable_events);
+    assert_eq!(busy_stats.writable_events, 0);
+    assert!(busy_stats.last_event > idle_stats.last_event);
+    assert!(busy_stats.events_per_second(Duration::from_millis(500)) > 0.0);
+    assert_eq!(idle_stats.events_per_second(Duration::from_secs(1)), 0.0);
+
+    scheduler.unregister_actor(&busy).unwrap();
+    scheduler.unregister_actor(&idle).unwrap();
+    assert!(scheduler.io_stats().is_empty());
+}
+
+/// Checks that the scheduler reports events of the same batch in the order of
+/// the actor priorities.
+pub fn check_priorities(scheduler: &mut impl Scheduler<FdActor>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let _client1 = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server1, _) = listener.accept().unwrap();
+    let _client2 = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (server2, _) = listener.accept().unwrap();
+    let low = Fd(server1.as_raw_source());
+    let high = Fd(server2.as_raw_source());
+
+    // Both connections are writable, so they get reported within one batch
+    scheduler.register_actor(&FdActor::new(&server1)).unwrap();
+    scheduler.set_priority(&low, 50);
+    scheduler.register_actor(&FdActor::new(&server2)).unwrap();
+    scheduler.set_priority(&high, 200);
+    thread::sleep(TICK);
+    scheduler.wait_io(Some(Duration::from_millis(100))).unwrap();
+    let sources = scheduler.by_ref().map(|ev| ev.source).collect::<Vec<_>>();
+    assert_eq!(sources, vec![high, low]);
+
+    scheduler.unregister_actor(&low).unwrap();
+    scheduler.unregister_actor(&high).unwrap();
+}
+
+#[test]
+fn shutdown_disconnects_actors() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    for id in 0..3 {
+        controller
+            .start_actor(TestPool::Main, TestCtx::new(id))
+            .unwrap();
+    }
+    reactor.shutdown().unwrap();
+
+    let disconnected = collect(&events, TICK)
+        .into_iter()
+        .map(|event| match event {
+            Event::Disconnected(id) => id,
+            event => panic!("unexpected event {event:?}"),
+        })
+        .collect::<HashSet<_>>();
+    assert_eq!(disconnected, HashSet::from([0, 1, 2]));
+}
+
+#[test]
+fn shutdown_drops_hung_actors_after_grace_period() {
+    let grace = Duration::from_millis(100);
+    let (mut reactor, events) = reactor_with(TestConfig {
+        shutdown_grace: grace,
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    controller
+        .start_actor(TestPool::Main, TestCtx::hung(1))
+        .unwrap();
+
+    let start = Instant::now();
+    reactor.shutdown().unwrap();
+    let elapsed = start.elapsed();
+    assert!(
+        elapsed >= grace,
+        "shutdown has not waited for the hung actor"
+    );
+    assert!(
+        elapsed < DEFAULT_SHUTDOWN_GRACE,
+        "hung actor was not dropped"
+    );
+    assert_eq!(collect(&events, TICK), vec![Event::Disconnected(1)]);
+}
+
+#[test]
+fn broadcast_reaches_all_actors() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, 0..3);
+
+    assert_eq!(controller.broadcast(()).unwrap(), 3);
+    let mut received = collect(&events, TICK);
+    received.sort();
+    assert_eq!(received, vec![Event::Cmd(0), Event::Cmd(1), Event::Cmd(2)]);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn broadcast_counts_actors_which_received_command() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    // Actors are not yet registered with the controller, but are started by
+    // the pool before it handles the broadcast
+    for id in 0..3 {
+        controller
+            .start_actor(TestPool::Main, TestCtx::new(id))
+            .unwrap();
+    }
+
+    assert_eq!(controller.broadcast(()).unwrap(), 3);
+    let mut received = collect(&events, TICK);
+    received.sort();
+    assert_eq!(received, vec![Event::Cmd(0), Event::Cmd(1), Event::Cmd(2)]);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn broadcast_from_pool_thread_fails() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    controller
+        .start_actor(TestPool::Main, TestCtx::broadcasting(1))
+        .unwrap();
+    while controller.pool_for(1).is_err() {
+        thread::sleep(TICK);
+    }
+
+    let start = Instant::now();
+    controller.send(1, ()).unwrap();
+    let err = Event::Error(InternalError::<TestPool>::WouldBlockPool.to_string());
+    assert_eq!(
+        collect(&events, TICK),
+        vec![Event::Cmd(1), err.clone(), err]
+    );
+    // The pool does not wait for itself until the query timeout
+    assert!(start.elapsed() < DEFAULT_QUERY_TIMEOUT);
+    assert!(controller.contains_actor(&1).unwrap());
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn broadcast_filtered_skips_excluded_actors() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, 0..4);
+
+    // Gossip-like exclusion of the actor the command originates from
+    let origin = 1;
+    assert_eq!(
+        controller
+            .broadcast_filtered((), move |id| *id != origin)
+            .unwrap(),
+        3
+    );
+    let mut received = collect(&events, TICK);
+    received.sort();
+    assert_eq!(received, vec![Event::Cmd(0), Event::Cmd(2), Event::Cmd(3)]);
+
+    assert_eq!(controller.broadcast_filtered((), |_| false).unwrap(), 0);
+    assert_eq!(collect(&events, TICK), vec![]);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn repeated_actor_is_refused() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, [1]);
+    controller
+        .start_actor(TestPool::Main, TestCtx::hung(1))
+        .unwrap();
+
+    assert_eq!(
+        collect(&events, TICK),
+        vec![Event::Error(
+            InternalError::<TestPool>::RepeatedActor(1).to_string()
+        )]
+    );
+    // The original actor must not be replaced with the hung one, otherwise
+    // the shutdown would wait for the whole grace period
+    let start = Instant::now();
+    reactor.shutdown().unwrap();
+    assert!(start.elapsed() < DEFAULT_SHUTDOWN_GRACE);
+    assert_eq!(collect(&events, TICK), vec![Event::Disconnected(1)]);
+}
+
+#[test]
+fn stopped_actor_is_disconnected() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, 0..2);
+
+    controller.stop_actor(0).unwrap();
+    assert_eq!(collect(&events, TICK), vec![Event::Disconnected(0)]);
+    assert!(controller.pool_for(0).is_err());
+    reactor.shutdown().unwrap();
+    assert_eq!(collect(&events, TICK), vec![Event::Disconnected(1)]);
+}
+
+#[test]
+fn half_closed_actor_is_kept() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, [0]);
+
+    controller.half_close(0).unwrap();
+    assert_eq!(collect(&events, TICK), vec![Event::HalfClosed(0)]);
+    assert!(controller.contains_actor(&0).unwrap());
+    controller.send(0, ()).unwrap();
+    assert_eq!(collect(&events, TICK), vec![Event::Cmd(0)]);
+    assert!(matches!(
+        controller.half_close(1),
+        Err(InternalError::UnknownActor(1))
+    ));
+
+    reactor.shutdown().unwrap();
+    assert_eq!(collect(&events, TICK), vec![Event::Disconnected(0)]);
+}
+
+#[test]
+fn gracefully_stopped_actor_drains_output() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, [0]);
+    controller
+        .start_actor(TestPool::Main, TestCtx::hung(1))
+        .unwrap();
+    while controller.pool_for(1).is_err() {
+        thread::sleep(TICK);
+    }
+
+    // Actor without pending output is disconnected at once
+    controller
+        .stop_actor_gracefully(0, Duration::from_secs(10))
+        .unwrap();
+    assert_eq!(collect(&events, TICK), vec![Event::Disconnected(0)]);
+    assert!(controller.pool_for(0).is_err());
+
+    // Hung actor is kept until the timeout, refusing new commands
+    let timeout = Duration::from_millis(200);
+    let start = Instant::now();
+    controller.stop_actor_gracefully(1, timeout).unwrap();
+    assert!(matches!(
+        controller.send(1, ()),
+        Err(InternalError::ActorDraining(1))
+    ));
+    assert_eq!(
+        events.recv_timeout(timeout * 5).unwrap(),
+        Event::Disconnected(1)
+    );
+    assert!(start.elapsed() >= timeout);
+    assert!(controller.pool_for(1).is_err());
+    assert!(matches!(
+        controller.send(1, ()),
+        Err(InternalError::UnknownActor(1))
+    ));
+
+    reactor.shutdown().unwrap();
+    assert_eq!(collect(&events, TICK), vec![]);
+}
+
+#[test]
+fn draining_actor_is_stopped_at_once() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    controller
+        .start_actor(TestPool::Main, TestCtx::hung(1))
+        .unwrap();
+    while controller.pool_for(1).is_err() {
+        thread::sleep(TICK);
+    }
+
+    let start = Instant::now();
+    controller
+        .stop_actor_gracefully(1, Duration::from_secs(10))
+        .unwrap();
+    controller.stop_actor(1).unwrap();
+    assert_eq!(
+        events.recv_timeout(Duration::from_secs(1)),
+        Ok(Event::Disconnected(1))
+    );
+    assert!(start.elapsed() < Duration::from_secs(1));
+    assert!(controller.pool_for(1).is_err());
+    assert_eq!(controller.metrics(TestPool::Main).unwrap().draining, 0);
+
+    reactor.shutdown().unwrap();
+    assert_eq!(collect(&events, TICK), vec![]);
+}
+
+#[test]
+fn disconnect_all_actors() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, 0..5);
+
+    assert_eq!(controller.disconnect_all().unwrap(), 5);
+    start_actors(&mut controller, 5..7);
+    assert_eq!(controller.actor_count().unwrap(), 2);
+
+    let mut disconnected = collect(&events, TICK);
+    assert_eq!(disconnected.pop(), Some(Event::DisconnectedAll(5)));
+    disconnected.sort();
+    assert_eq!(
+        disconnected,
+        (0..5).map(Event::Disconnected).collect::<Vec<_>>()
+    );
+    for id in 0..5 {
+        assert!(controller.pool_for(id).is_err());
+    }
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn idle_pool_notifications() {
+    let (mut reactor, events) = reactor_with(TestConfig {
+        idle_timeout: Some(TICK),
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, 0..2);
+    events.try_iter().for_each(drop);
+
+    thread::sleep(Duration::from_millis(200));
+    let events = events.try_iter().collect::<Vec<_>>();
+    let count = |event: Event| events.iter().filter(|e| **e == event).count();
+    let pool_idle = count(Event::PoolIdle);
+    assert!(pool_idle >= 5, "pool was idle only {pool_idle} times");
+    // Actors are notified once per each pool idle iteration; the iteration
+    // may be split by the start of the measurement window
+    for id in 0..2 {
+        assert!(count(Event::Idle(id)).abs_diff(pool_idle) <= 1);
+    }
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn timers_do_not_make_pool_idle() {
+    let (mut reactor, events) = reactor_with(TestConfig {
+        idle_timeout: Some(TICK * 10),
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+
+    // Timers firing more often than the idle timeout keep the pool active
+    for _ in 0..5 {
+        let token = controller.set_timer(TestPool::Main, TICK * 5).unwrap();
+        assert_eq!(
+            events.recv_timeout(Duration::from_secs(1)).unwrap(),
+            Event::Timer(token)
+        );
+    }
+    let token = controller.set_timer(TestPool::Main, TICK).unwrap();
+    assert_eq!(
+        events.recv_timeout(Duration::from_secs(1)).unwrap(),
+        Event::Timer(token)
+    );
+    // The pool becomes idle only once the idle timeout passes after the last
+    // timer
+    let start = Instant::now();
+    assert_eq!(
+        events.recv_timeout(Duration::from_secs(1)).unwrap(),
+        Event::PoolIdle
+    );
+    assert!(start.elapsed() >= TICK * 9);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn disconnect_all_does_not_wait_for_output() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, [0]);
+    controller
+        .start_actor(TestPool::Main, TestCtx::hung(1))
+        .unwrap();
+    controller
+        .start_actor(TestPool::Main, TestCtx::hung(2))
+        .unwrap();
+    while controller.pool_for(1).is_err() || controller.pool_for(2).is_err() {
+        thread::sleep(TICK);
+    }
+    controller
+        .stop_actor_gracefully(2, Duration::from_secs(10))
+        .unwrap();
+
+    let start = Instant::now();
+    assert_eq!(controller.disconnect_all().unwrap(), 3);
+    assert!(start.elapsed() < Duration::from_secs(1));
+    let mut disconnected = collect(&events, TICK);
+    assert_eq!(disconnected.pop(), Some(Event::DisconnectedAll(3)));
+    disconnected.sort();
+    assert_eq!(
+        disconnected,
+        (0..3).map(Event::Disconnected).collect::<Vec<_>>()
+    );
+
+    // The pool keeps processing control events
+    start_actors(&mut controller, [3]);
+    controller.send(3, ()).unwrap();
+    assert_eq!(collect(&events, TICK), vec![Event::Cmd(3)]);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn query_actors() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    for id in 0..5 {
+        controller
+            .start_actor(TestPool::Main, TestCtx::new(id))
+            .unwrap();
+    }
+    assert_eq!(controller.actor_count().unwrap(), 5);
+    assert!(controller.contains_actor(&3).unwrap());
+    let mut actors = controller.actors().unwrap();
+    actors.sort();
+    assert_eq!(actors, vec![0, 1, 2, 3, 4]);
+
+    controller.stop_actor(3).unwrap();
+    assert_eq!(controller.actor_count().unwrap(), 4);
+    assert!(!controller.contains_actor(&3).unwrap());
+    let mut actors = controller.actors().unwrap();
+    actors.sort();
+    assert_eq!(actors, vec![0, 1, 2, 4]);
+    assert_eq!(collect(&events, TICK), vec![Event::Disconnected(3)]);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn reconnect_with_backoff() {
+    let (mut reactor, events) = reactor_with(TestConfig {
+        lifecycle: true,
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    let backoff = Duration::from_millis(20);
+
+    let start = Instant::now();
+    controller
+        .reconnect(TestPool::Main, 1, TestCtx::failing(1, 2), backoff, 2.0, 3)
+        .unwrap();
+    assert_eq!(
+        events.recv_timeout(Duration::from_secs(1)),
+        Ok(Event::OnConnect(1)),
+        "actor was not reconnected"
+    );
+    // Three attempts: after 20 ms, 40 ms and 80 ms
+    assert!(start.elapsed() >= backoff * 7);
+    assert!(controller.contains_actor(&1).unwrap());
+
+    controller
+        .reconnect(TestPool::Main, 2, TestCtx::failing(2, 3), backoff, 2.0, 2)
+        .unwrap();
+    assert_eq!(
+        collect(&events, Duration::from_millis(200)),
+        vec![
+            Event::Error(
+                InternalError::<TestPool>::ReconnectExhausted(
+                    TestPool::Main,
+                    io::ErrorKind::ConnectionRefused.into()
+                )
+                .to_string()
+            ),
+            Event::OnDisconnect(2, DisconnectReason::ReconnectExhausted.to_string()),
+        ]
+    );
+    assert!(!controller.contains_actor(&2).unwrap());
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn reconnect_retries_refused_actor() {
+    let (mut reactor, events) = reactor_with(TestConfig {
+        lifecycle: true,
+        scheduler_refusals: 4,
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    let backoff = Duration::from_millis(20);
+    let refused = Event::Error(
+        InternalError::<TestPool>::ActorError(
+            TestPool::Main,
+            io::Error::new(io::ErrorKind::Other, "scheduler refuses the actor"),
+        )
+        .to_string(),
+    );
+    let mut recv = |count| {
+        (0..count)
+            .map(|_| events.recv_timeout(Duration::from_secs(1)).unwrap())
+            .collect::<Vec<_>>()
+    };
+
+    // Refused attempts count against the attempts
+    controller
+        .reconnect(TestPool::Main, 1, TestCtx::new(1), backoff, 2.0, 2)
+        .unwrap();
+    assert_eq!(
+        recv(3),
+        vec![
+            refused.clone(),
+            refused.clone(),
+            Event::OnDisconnect(1, DisconnectReason::ReconnectExhausted.to_string()),
+        ]
+    );
+    assert!(!controller.contains_actor(&1).unwrap());
+
+    // Refused attempts are retried
+    controller
+        .reconnect(TestPool::Main, 2, TestCtx::new(2), backoff, 2.0, 3)
+        .unwrap();
+    assert_eq!(recv(3), vec![refused.clone(), refused, Event::OnConnect(2)]);
+    assert!(controller.contains_actor(&2).unwrap());
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn control_events_do_not_wait_for_io() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    // Nothing would wake up the scheduler for a long time, unless the control
+    // events are able to do that
+    controller
+        .set_timer(TestPool::Main, Duration::from_secs(60))
+        .unwrap();
+    start_actors(&mut controller, [1]);
+
+    let start = Instant::now();
+    controller.send(1, ()).unwrap();
+    assert_eq!(
+        events.recv_timeout(Duration::from_secs(1)),
+        Ok(Event::Cmd(1))
+    );
+    assert!(start.elapsed() < Duration::from_millis(100));
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn io_budget_interleaves_control_events() {
+    const FLOOD: usize = 10;
+    let (mut reactor, events) = reactor_with(TestConfig {
+        flood: FLOOD,
+        io_budget: Some((3, 2)),
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, [1, 2]);
+
+    let mut collected = vec![];
+    while let Ok(event) = events.recv_timeout(Duration::from_secs(1)) {
+        collected.push(event);
+        if collected.len() == 2 * (FLOOD + 1) {
+            break;
+        }
+    }
+    for id in [1, 2] {
+        assert_eq!(
+            collected.iter().filter(|ev| **ev == Event::Io(id)).count(),
+            FLOOD
+        );
+        // The command is sent on the first I/O event and is processed once
+        // the budget of the iteration is exhausted
+        let cmd = collected
+            .iter()
+            .position(|ev| *ev == Event::Cmd(id))
+            .unwrap();
+        let before = collected[..cmd]
+            .iter()
+            .filter(|ev| **ev == Event::Io(id))
+            .count();
+        assert!(
+            before <= 2,
+            "{before} I/O events are dispatched before the command"
+        );
+    }
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn reactor_metrics() {
+    const FLOOD: usize = 10;
+    let (mut reactor, events) = reactor_with(TestConfig {
+        flood: FLOOD,
+        io_budget: Some((3, 2)),
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, [1, 2]);
+    // Each actor gets its flood of I/O events and a command
+    let collected = collect(&events, TICK);
+    assert_eq!(collected.len(), 2 * (FLOOD + 1));
+
+    let metrics = controller.metrics(TestPool::Main).unwrap();
+    assert_eq!(metrics.actors, 2);
+    assert_eq!(metrics.draining, 0);
+    assert_eq!(metrics.io_events, 2 * FLOOD as u64);
+    // Two actor starts and two commands
+    assert!(metrics.control_events >= 4);
+    // With the budget of three events the flood takes several iterations
+    assert!(metrics.iterations >= (2 * FLOOD as u64) / 3);
+    assert!(metrics.dispatch_time > Duration::ZERO);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn reactor_io_stats() {
+    const FLOOD: usize = 10;
+    let (mut reactor, events) = reactor_with(TestConfig {
+        flood: FLOOD,
+        io_budget: Some((3, 2)),
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, [1, 2]);
+    collect(&events, TICK);
+
+    let stats = controller.io_stats(TestPool::Main).unwrap();
+    assert_eq!(stats.len(), 2);
+    for id in [1, 2] {
+        assert_eq!(stats[&id].readable_events, FLOOD as u64);
+        assert_eq!(stats[&id].writable_events, 0);
+    }
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn take_and_insert_actor() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, [1]);
+
+    let actor = controller
+        .take_actor(1)
+        .unwrap()
+        .recv_timeout(Duration::from_secs(1))
+        .unwrap();
+    assert_eq!(actor.id(), 1);
+    assert!(!controller.contains_actor(&1).unwrap());
+    assert!(controller.send(1, ()).is_err());
+
+    controller.insert_actor(TestPool::Main, actor).unwrap();
+    assert!(controller.contains_actor(&1).unwrap());
+    controller.send(1, ()).unwrap();
+    // The actor must not get disconnected while it was taken
+    assert_eq!(collect(&events, TICK), vec![Event::Cmd(1)]);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn panicking_actor_is_removed() {
+    let (mut reactor, events) = reactor_with(TestConfig {
+        lifecycle: true,
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, [0, 2]);
+    controller
+        .start_actor(TestPool::Main, TestCtx::panicking(1))
+        .unwrap();
+    while controller.pool_for(1).is_err() {
+        thread::sleep(TICK);
+    }
+    collect(&events, TICK);
+
+    assert_eq!(controller.broadcast(()).unwrap(), 3);
+    // Printing the panic message may take a while, so the events are awaited
+    // one by one
+    let mut received = (0..4)
+        .map(|_| events.recv_timeout(Duration::from_secs(1)).unwrap())
+        .collect::<Vec<_>>();
+    received.sort();
+    assert_eq!(
+        received,
+        vec![
+            Event::Cmd(0),
+            Event::Cmd(2),
+            Event::OnDisconnect(1, s!("actor has panicked")),
+            Event::Panicked(1, s!("test actor 1 panics")),
+        ]
+    );
+    assert!(!controller.contains_actor(&1).unwrap());
+
+    // Sibling actors keep operating
+    controller.send(0, ()).unwrap();
+    controller.send(2, ()).unwrap();
+    assert_eq!(collect(&events, TICK), vec![Event::Cmd(0), Event::Cmd(2)]);
+    assert_eq!(controller.actor_count().unwrap(), 2);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn actor_panicking_on_deadline_is_removed() {
+    let (mut reactor, events) = reactor_with(TestConfig {
+        lifecycle: true,
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, [0]);
+    controller
+        .start_actor(
+            TestPool::Main,
+            TestCtx {
+                deadline: Some(TICK),
+                ..TestCtx::panicking(1)
+            },
+        )
+        .unwrap();
+
+    let mut received = (0..4)
+        .map(|_| events.recv_timeout(Duration::from_secs(1)).unwrap())
+        .collect::<Vec<_>>();
+    received.sort();
+    assert_eq!(
+        received,
+        vec![
+            Event::OnConnect(0),
+            Event::OnConnect(1),
+            Event::OnDisconnect(1, s!("actor has panicked")),
+            Event::Panicked(1, s!("test actor 1 panics")),
+        ]
+    );
+    assert_eq!(controller.actors().unwrap(), vec![0]);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn actor_panicking_on_idle_is_removed() {
+    let (mut reactor, events) = reactor_with(TestConfig {
+        idle_timeout: Some(TICK),
+        lifecycle: true,
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, [0]);
+    controller
+        .start_actor(TestPool::Main, TestCtx::panicking(1))
+        .unwrap();
+
+    let mut received = vec![];
+    while !received.contains(&Event::Panicked(1, s!("test actor 1 panics"))) {
+        received.push(events.recv_timeout(Duration::from_secs(1)).unwrap());
+    }
+    assert!(received.contains(&Event::OnDisconnect(1, s!("actor has panicked"))));
+    assert_eq!(controller.actors().unwrap(), vec![0]);
+
+    // Sibling actor keeps being notified
+    events.try_iter().for_each(drop);
+    while events.recv_timeout(Duration::from_secs(1)).unwrap() != Event::Idle(0) {}
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn handler_starts_actors_on_startup() {
+    let (mut reactor, events) = reactor_with(TestConfig {
+        startup: vec![0, 1],
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    // Actors are started without any requests from the re-actor owner
+    while ![0, 1].iter().all(|id| controller.pool_for(*id).is_ok()) {
+        thread::sleep(TICK);
+    }
+
+    assert_eq!(controller.broadcast(()).unwrap(), 2);
+    let mut received = collect(&events, TICK);
+    received.sort();
+    assert_eq!(received, vec![Event::Cmd(0), Event::Cmd(1)]);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn timers_fire_in_order() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    // Timers are set out of order to ensure the order of firing depends only
+    // on their deadlines
+    let second = controller
+        .set_timer(TestPool::Main, Duration::from_millis(100))
+        .unwrap();
+    let third = controller
+        .set_timer(TestPool::Main, Duration::from_millis(200))
+        .unwrap();
+    let first = controller
+        .set_timer(TestPool::Main, Duration::from_millis(50))
+        .unwrap();
+
+    let fired = collect(&events, Duration::from_millis(300));
+    assert_eq!(
+        fired,
+        vec![
+            Event::Timer(first),
+            Event::Timer(second),
+            Event::Timer(third)
+        ]
+    );
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn lifecycle_notifications() {
+    let (mut reactor, events) = reactor_with(TestConfig {
+        lifecycle: true,
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, 0..3);
+    controller
+        .start_actor(TestPool::Main, TestCtx::expiring(3, TICK * 5))
+        .unwrap();
+    controller.stop_actor(0).unwrap();
+    thread::sleep(TICK * 10);
+    assert_eq!(controller.disconnect_all().unwrap(), 2);
+    start_actors(&mut controller, [4]);
+    reactor.shutdown().unwrap();
+
+    let mut connected = vec![];
+    let mut disconnected = vec![];
+    for event in collect(&events, TICK) {
+        match event {
+            Event::OnConnect(id) => connected.push(id),
+            Event::OnDisconnect(id, reason) => disconnected.push((id, reason)),
+            _ => {}
+        }
+    }
+    connected.sort();
+    disconnected.sort();
+    assert_eq!(connected, vec![0, 1, 2, 3, 4]);
+    let on_demand = DisconnectReason::OnDemand.to_string();
+    let timeout = DisconnectReason::ConnectionError(io::ErrorKind::TimedOut.into()).to_string();
+    assert_eq!(
+        disconnected,
+        vec![
+            (0, on_demand.clone()),
+            (1, on_demand.clone()),
+            (2, on_demand.clone()),
+            (3, timeout),
+            (4, on_demand),
+        ]
+    );
+}
+
+#[test]
+fn missed_deadline_disconnects_actor() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, [1]);
+    controller
+        .start_actor(TestPool::Main, TestCtx::expiring(2, TICK * 5))
+        .unwrap();
+
+    let mut collected = collect(&events, TICK * 10);
+    assert!(matches!(collected.get(1), Some(Event::Error(_))));
+    collected.remove(1);
+    assert_eq!(collected, vec![Event::Deadline(2), Event::Disconnected(2)]);
+    assert!(controller.pool_for(2).is_err());
+    assert_eq!(controller.pool_for(1).unwrap(), TestPool::Main);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn full_control_queue() {
+    let (mut reactor, events) = reactor_with(TestConfig {
+        capacity: Some(2),
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    let (gate_send, gate_recv) = chan::unbounded();
+    controller
+        .start_actor(TestPool::Main, TestCtx::gated(1, gate_recv))
+        .unwrap();
+    while controller.pool_for(1).is_err() {
+        thread::sleep(TICK);
+    }
+
+    // Once the command is reported the pool is blocked by the actor and the
+    // queue is empty
+    controller.send(1, ()).unwrap();
+    assert_eq!(
+        events.recv_timeout(Duration::from_secs(1)),
+        Ok(Event::Cmd(1))
+    );
+    controller.try_send(1, ()).unwrap();
+    controller.try_send(1, ()).unwrap();
+    assert_eq!(
+        controller.try_send(1, ()).unwrap_err().to_string(),
+        InternalError::<TestPool>::ControlQueueFull(TestPool::Main).to_string()
+    );
+
+    drop(gate_send);
+    assert_eq!(collect(&events, TICK), vec![Event::Cmd(1), Event::Cmd(1)]);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn actor_overflowing_control_queue() {
+    let (mut reactor, events) = reactor_with(TestConfig {
+        capacity: Some(2),
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    controller
+        .start_actor(TestPool::Main, TestCtx::echoing(1, 5))
+        .unwrap();
+    while controller.pool_for(1).is_err() {
+        thread::sleep(TICK);
+    }
+
+    // The pool thread can't wait for its own queue to get emptied, so the
+    // actor fails to send the third command instead of deadlocking the pool
+    controller.send(1, ()).unwrap();
+    assert_eq!(
+        collect(&events, TICK),
+        vec![
+            Event::Cmd(1),
+            Event::Error(InternalError::<TestPool>::ControlQueueFull(TestPool::Main).to_string()),
+            Event::Cmd(1),
+            Event::Cmd(1),
+        ]
+    );
+    assert!(controller.contains_actor(&1).unwrap());
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn send_with_timeout_to_full_queue() {
+    let (mut reactor, events) = reactor_with(TestConfig {
+        capacity: Some(1),
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    let (gate_send, gate_recv) = chan::unbounded();
+    controller
+        .start_actor(TestPool::Main, TestCtx::gated(1, gate_recv))
+        .unwrap();
+    while controller.pool_for(1).is_err() {
+        thread::sleep(TICK);
+    }
+
+    controller.send(1, ()).unwrap();
+    assert_eq!(
+        events.recv_timeout(Duration::from_secs(1)),
+        Ok(Event::Cmd(1))
+    );
+    controller.send_with_timeout(1, (), TICK).unwrap();
+    let start = Instant::now();
+    assert_eq!(
+        controller
+            .send_with_timeout(1, (), TICK)
+            .unwrap_err()
+            .to_string(),
+        InternalError::<TestPool>::ControlQueueFull(TestPool::Main).to_string()
+    );
+    assert!(start.elapsed() >= TICK);
+
+    drop(gate_send);
+    assert_eq!(collect(&events, TICK), vec![Event::Cmd(1)]);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn dropped_reactor_shuts_down_pools() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, 0..2);
+
+    drop(reactor);
+    let mut disconnected = collect(&events, TICK);
+    disconnected.sort();
+    assert_eq!(
+        disconnected,
+        vec![Event::Disconnected(0), Event::Disconnected(1)]
+    );
+    // Terminated pool runtime drops the receiving end of its control channel
+    assert_eq!(
+        controller
+            .start_actor(TestPool::Main, TestCtx::new(2))
+            .unwrap_err()
+            .to_string(),
+        InternalError::<TestPool>::ControlChannelBroken.to_string()
+    );
+}
+
+#[test]
+fn start_actor_reports_outcome() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    let mut start = |ctx| {
+        controller
+            .start_actor_and_report(TestPool::Main, ctx)
+            .unwrap()
+            .recv_timeout(Duration::from_secs(1))
+            .unwrap()
+            .map_err(|err| err.to_string())
+    };
+
+    assert_eq!(start(TestCtx::new(1)), Ok(1));
+    assert_eq!(
+        start(TestCtx::new(1)),
+        Err(InternalError::<TestPool>::RepeatedActor(1).to_string())
+    );
+    assert_eq!(
+        start(TestCtx::failing(2, 1)),
+        Err(InternalError::<TestPool>::ActorError(
+            TestPool::Main,
+            io::ErrorKind::ConnectionRefused.into()
+        )
+        .to_string())
+    );
+    // Reported errors are not passed to the handler
+    assert_eq!(collect(&events, TICK), vec![]);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn event_log_records_events() {
+    let (mut reactor, events) = reactor();
+    let mut controller = reactor.controller();
+    start_actors(&mut controller, [1]);
+
+    controller.send(1, ()).unwrap();
+    controller
+        .start_actor(TestPool::Main, TestCtx::new(1))
+        .unwrap();
+    let err = InternalError::<TestPool>::RepeatedActor(1).to_string();
+    assert_eq!(
+        collect(&events, TICK),
+        vec![Event::Cmd(1), Event::Error(err.clone())]
+    );
+
+    let event_log = reactor.event_log();
+    let log = event_log.lock().unwrap();
+    let entries = log
+        .entries()
+        .filter(|entry| !matches!(entry.kind(), EventKind::Io(_)))
+        .map(|entry| (entry.kind(), entry.resource_id(), entry.detail()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        entries,
+        vec![
+            (EventKind::Control("connect"), "", ""),
+            (EventKind::Control("send"), "1", ""),
+            (EventKind::Control("connect"), "", ""),
+            (EventKind::Error, "1", err.as_str()),
+        ]
+    );
+    assert!(log
+        .dump_json()
+        .contains(r#""kind":"control","name":"send","resource_id":"1"}"#));
+    drop(log);
+    reactor.shutdown().unwrap();
+}
+
+#[test]
+fn actor_refused_by_scheduler_is_not_started() {
+    let (mut reactor, events) = reactor_with(TestConfig {
+        scheduler_capacity: Some(1),
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    let start = |controller: &mut Controller<TestPool>, ctx| {
+        controller
+            .start_actor_and_report(TestPool::Main, ctx)
+            .unwrap()
+            .recv_timeout(Duration::from_secs(1))
+            .unwrap()
+            .map_err(|err| err.to_string())
+    };
+
+    assert_eq!(start(&mut controller, TestCtx::new(1)), Ok(1));
+    assert_eq!(
+        start(&mut controller, TestCtx::new(2)),
+        Err(InternalError::<TestPool>::ActorError(
+            TestPool::Main,
+            io::Error::new(io::ErrorKind::Other, "scheduler is full")
+        )
+        .to_string())
+    );
+    assert!(controller.pool_for(2).is_err());
+    assert_eq!(controller.actor_count().unwrap(), 1);
+
+    // The refused actor is dropped, so its id can be used again
+    controller.stop_actor(1).unwrap();
+    assert_eq!(collect(&events, TICK), vec![Event::Disconnected(1)]);
+    assert_eq!(start(&mut controller, TestCtx::new(2)), Ok(2));
+    reactor.shutdown().unwrap();
+    assert_eq!(collect(&events, TICK), vec![Event::Disconnected(2)]);
+}
+
+#[test]
+fn watchdog_detects_stalled_pool() {
+    const DEADLINE: Duration = Duration::from_millis(200);
+
+    let (mut reactor, events) = reactor();
+    let freeze = Arc::new(Mutex::new(()));
+    let mut controller = reactor.controller();
+    controller
+        .start_actor(TestPool::Main, TestCtx::frozen(1, freeze.clone()))
+        .unwrap();
+    while controller.pool_for(1).is_err() {
+        thread::sleep(TICK);
+    }
+    let (stall_send, stall_recv) = chan::unbounded();
+    let detector = StallDetector::with_deadline(DEADLINE, move || {
+        let _ = stall_send.send(Instant::now());
+    });
+    let reactor = WatchdogReactor::new(reactor, detector);
+
+    // Idle pool is not stalled
+    assert_eq!(
+        stall_recv.recv_timeout(3 * DEADLINE),
+        Err(chan::RecvTimeoutError::Timeout)
+    );
+
+    let guard = freeze.lock().unwrap();
+    controller.send(1, ()).unwrap();
+    assert_eq!(
+        events.recv_timeout(Duration::from_secs(1)),
+        Ok(Event::Cmd(1))
+    );
+    let frozen = Instant::now();
+    let stalled = stall_recv.recv_timeout(2 * DEADLINE).unwrap();
+    assert!(stalled - frozen >= DEADLINE / 2);
+    // The stall is reported once
+    assert_eq!(
+        stall_recv.recv_timeout(2 * DEADLINE),
+        Err(chan::RecvTimeoutError::Timeout)
+    );
+
+    drop(guard);
+    reactor.shutdown().unwrap();
+    assert_eq!(collect(&events, TICK), vec![Event::Disconnected(1)]);
+    assert_eq!(stall_recv.try_recv(), Err(chan::TryRecvError::Disconnected));
+}
+
+#[test]
+fn higher_priority_actor_is_dispatched_first() {
+    let (mut reactor, events) = reactor_with(TestConfig {
+        flood: 1,
+        priorities: true,
+        ..default!()
+    });
+    let mut controller = reactor.controller();
+    // Blocking the pool, so both actors get registered before the next wait
+    let (gate, gate_recv) = chan::bounded(0);
+    controller
+        .start_actor(TestPool::Main, TestCtx::gated(3, gate_recv))
+        .unwrap();
+    assert_eq!(collect(&events, TICK), vec![Event::Io(3), Event::Cmd(3)]);
+
+    controller
+        .start_actor(TestPool::Main, TestCtx::prioritized(1, 50))
+        .unwrap();
+    controller
+        .start_actor(TestPool::Main, TestCtx::prioritized(2, 200))
+        .unwrap();
+    gate.send(()).unwrap();
+    assert_eq!(
+        collect(&events, TICK),
+        vec![Event::Io(2), Event::Io(1), Event::Cmd(2), Event::Cmd(1)]
+    );
+    reactor.shutdown().unwrap();
+}
@@ -0,0 +1,134 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::Ordering;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crossbeam_channel as chan;
+
+use super::{InternalError, Layout, Reactor};
+
+/// Configuration of the [`WatchdogReactor`]: the maximal time a pool event
+/// loop may spend in a single iteration and the callback notified once it
+/// is exceeded.
+pub struct StallDetector {
+    deadline: Duration,
+    on_stall: Box<dyn Fn() + Send>,
+}
+
+impl StallDetector {
+    /// Constructs detector calling `on_stall` once an event loop does not
+    /// start a new iteration for `deadline`. The callback runs in the
+    /// watchdog thread and may log the stall, panic or signal the process.
+    pub fn with_deadline(deadline: Duration, on_stall: impl Fn() + Send + 'static) -> Self {
+        StallDetector {
+            deadline,
+            on_stall: Box::new(on_stall),
+        }
+    }
+}
+
+/// Re-actor watched by a thread detecting stalled pool event loops, for
+/// instance when the scheduler blocks in the kernel or an actor does not
+/// return from its callback.
+///
+/// Each pool runtime increments its heartbeat at the start of every event
+/// loop iteration. The watchdog wakes the pools several times per
+/// [`StallDetector`] deadline, so the idle ones keep iterating, and calls the
+/// `on_stall` callback once a heartbeat has not advanced for the deadline.
+/// The callback is called once per stall, and is called again only after the
+/// pool recovers and stalls anew.
+///
+/// The re-actor is accessible through [`Deref`] and [`DerefMut`].
+pub struct WatchdogReactor<L: Layout> {
+    /// Dropped together with the watchdog reactor before the re-actor,
+    /// stopping the watchdog.
+    stop: chan::Sender<()>,
+    thread: JoinHandle<()>,
+    reactor: Reactor<L>,
+}
+
+impl<L: Layout> WatchdogReactor<L> {
+    /// Starts the watchdog for the pools of the re-actor.
+    pub fn new(reactor: Reactor<L>, detector: StallDetector) -> Self
+    where
+        L: 'static,
+    {
+        let (stop, stop_recv) = chan::bounded(1);
+        let controller = reactor.controller.clone();
+        let heartbeats = reactor
+            .heartbeats
+            .iter()
+            .map(|(pool, heartbeat)| (*pool, heartbeat.clone()))
+            .collect::<Vec<_>>();
+        let StallDetector { deadline, on_stall } = detector;
+        let interval = deadline / 4;
+
+        let thread = thread::spawn(move || {
+            let now = Instant::now();
+            // Last observed value of each heartbeat, the time it was observed
+            // and whether its stall was already reported
+            let mut observed = heartbeats
+                .iter()
+                .map(|(_, heartbeat)| (heartbeat.load(Ordering::Relaxed), now, false))
+                .collect::<Vec<_>>();
+            loop {
+                for (pool, _) in &heartbeats {
+                    // The pool may have already terminated, which is not a
+                    // stall
+                    let _ = controller.wake(*pool);
+                }
+                if stop_recv.recv_timeout(interval) != Err(chan::RecvTimeoutError::Timeout) {
+                    break;
+                }
+                let now = Instant::now();
+                for ((_, heartbeat), (last, since, reported)) in
+                    heartbeats.iter().zip(&mut observed)
+                {
+                    let tick = heartbeat.load(Ordering::Relaxed);
+                    if tick != *last {
+                        *last = tick;
+                        *since = now;
+                        *reported = false;
+                    } else if !*reported && now - *since >= deadline {
+                        *reported = true;
+                        on_stall();
+                    }
+                }
+            }
+        });
+
+        WatchdogReactor {
+            stop,
+            thread,
+            reactor,
+        }
+    }
+
+    /// Stops the watchdog and shuts down the re-actor with
+    /// [`Reactor::shutdown`].
+    pub fn shutdown(self) -> Result<(), InternalError<L>> {
+        let WatchdogReactor {
+            stop,
+            thread,
+            reactor,
+        } = self;
+        // Pools stop iterating during the shutdown, which is not a stall
+        drop(stop);
+        let _ = thread.join();
+        reactor.shutdown()
+    }
+}
+
+impl<L: Layout> Deref for WatchdogReactor<L> {
+    type Target = Reactor<L>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.reactor
+    }
+}
+
+impl<L: Layout> DerefMut for WatchdogReactor<L> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.reactor
+    }
+}
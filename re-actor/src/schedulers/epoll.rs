@@ -0,0 +1,387 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{io, ptr};
+
+use crate::actors::{IoEv, IoSrc, DEFAULT_PRIORITY};
+use crate::schedulers::{IoStats, IoStatsMap, PipeWaker, WakeReceiver, Waker};
+use crate::{Actor, Scheduler};
+
+/// Maximal number of events read from the kernel with a single call.
+const EVENT_BATCH: usize = 1024;
+
+/// Manager for a set of resources which are polled for an event loop by the
+/// re-actor by using `epoll` interface of Linux kernel.
+///
+/// By default the interests are registered as level-triggered, such that an
+/// actor which has not read all of the data is reported as readable again by
+/// the next wait. Schedulers constructed with [`EpollScheduler::with`] may use
+/// edge-triggered mode (`EPOLLET`) instead, in which an event is reported only
+/// once per readiness change.
+///
+/// # Edge-triggered mode
+///
+/// In edge-triggered mode actors must read from (write to) their sockets
+/// until the operation fails with [`io::ErrorKind::WouldBlock`] each time
+/// they are reported readable (writable). Data which is left unread do not
+/// generate new events, so the actor will not be called again until the
+/// remote peer sends more data. In return, actors may defer processing of the
+/// read data without being woken up on each wait.
+///
+/// # Priorities
+///
+/// The kernel reports events in no particular order, so the events read by
+/// each wait are sorted by the actor priorities (see [`Actor::priority`]) in
+/// software.
+pub struct EpollScheduler<R>
+where
+    R: Actor,
+    R::Id: AsRawFd,
+{
+    epoll: OwnedFd,
+    edge_triggered: bool,
+    actors: HashMap<RawFd, (R::Id, IoEv)>,
+    priorities: HashMap<RawFd, u8>,
+    read_events: Vec<libc::epoll_event>,
+    events: VecDeque<IoSrc<R::Id>>,
+    stats: IoStatsMap<R::Id>,
+    waker: Arc<PipeWaker>,
+    wake_recv: WakeReceiver,
+}
+
+// Raw epoll event structures contain only the flags and the file descriptors
+unsafe impl<R> Send for EpollScheduler<R>
+where
+    R: Actor,
+    R::Id: AsRawFd + Send,
+{
+}
+
+impl<R> EpollScheduler<R>
+where
+    R: Actor,
+    R::Id: AsRawFd,
+{
+    /// Constructs scheduler using level-triggered mode.
+    pub fn new() -> io::Result<Self> {
+        Self::with(false)
+    }
+
+    /// Constructs scheduler using either edge-triggered or level-triggered
+    /// mode. See [`EpollScheduler`] documentation on the requirements to the
+    /// actors in edge-triggered mode.
+    pub fn with(edge_triggered: bool) -> io::Result<Self> {
+        let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let epoll = unsafe { OwnedFd::from_raw_fd(fd) };
+        let (waker, wake_recv) = PipeWaker::pair()?;
+        let scheduler = Self {
+            epoll,
+            edge_triggered,
+            actors: empty!(),
+            priorities: empty!(),
+            read_events: Vec::with_capacity(EVENT_BATCH),
+            events: empty!(),
+            stats: default!(),
+            waker,
+            wake_recv,
+        };
+        // The wake receiver is always drained, so it works in both modes
+        scheduler.ctl(
+            libc::EPOLL_CTL_ADD,
+            scheduler.wake_recv.as_raw_fd(),
+            libc::EPOLLIN as u32,
+        )?;
+        Ok(scheduler)
+    }
+
+    /// Detects whether the scheduler uses edge-triggered mode.
+    pub fn is_edge_triggered(&self) -> bool {
+        self.edge_triggered
+    }
+
+    fn flags(&self, interest: IoEv) -> u32 {
+        // Hang-ups and errors are always reported by the kernel, except the
+        // peer closing its writing half of the connection
+        let mut flags = libc::EPOLLRDHUP;
+        if interest.is_readable {
+            flags |= libc::EPOLLIN;
+        }
+        if interest.is_writable {
+            flags |= libc::EPOLLOUT;
+        }
+        if self.edge_triggered {
+            flags |= libc::EPOLLET;
+        }
+        flags as u32
+    }
+
+    fn ctl(&self, op: libc::c_int, fd: RawFd, flags: u32) -> io::Result<()> {
+        let mut ev = libc::epoll_event {
+            events: flags,
+            u64: fd as u64,
+        };
+        let ev_ptr = if op == libc::EPOLL_CTL_DEL {
+            ptr::null_mut()
+        } else {
+            &mut ev as *mut libc::epoll_event
+        };
+        let res = unsafe { libc::epoll_ctl(self.epoll.as_raw_fd(), op, fd, ev_ptr) };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl<R> Scheduler<R> for EpollScheduler<R>
+where
+    R: Actor,
+    R::Id: AsRawFd + Send,
+    R::Error: From<io::Error>,
+{
+    fn has_actor(&self, id: &R::Id) -> bool {
+        self.actors.contains_key(&id.as_raw_fd())
+    }
+
+    fn register_actor(&mut self, resource: &R) -> Result<(), R::Error> {
+        let id = resource.id();
+        let interest = resource.interests();
+        let fd = id.as_raw_fd();
+        self.ctl(libc::EPOLL_CTL_ADD, fd, self.flags(interest))?;
+        self.stats.register(id.clone());
+        self.actors.insert(fd, (id, interest));
+        Ok(())
+    }
+
+    fn set_interest(&mut self, id: &R::Id, interest: IoEv) -> Result<(), R::Error> {
+        let fd = id.as_raw_fd();
+        let flags = self.flags(interest);
+        let Some((_, current)) = self.actors.get_mut(&fd) else {
+            return Ok(());
+        };
+        if *current != interest {
+            *current = interest;
+            self.ctl(libc::EPOLL_CTL_MOD, fd, flags)?;
+        }
+        Ok(())
+    }
+
+    fn unregister_actor(&mut self, id: &R::Id) -> Result<(), R::Error> {
+        self.events.retain(|ev| ev.source != *id);
+        self.stats.unregister(id);
+        let fd = id.as_raw_fd();
+        self.priorities.remove(&fd);
+        if self.actors.remove(&fd).is_none() {
+            return Ok(());
+        }
+        match self.ctl(libc::EPOLL_CTL_DEL, fd, 0) {
+            // Closed file descriptors are removed from the epoll automatically
+            Err(err)
+                if err.raw_os_error() == Some(libc::ENOENT)
+                    || err.raw_os_error() == Some(libc::EBADF) =>
+            {
+                Ok(())
+            }
+            Err(err) => Err(err.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn set_priority(&mut self, id: &R::Id, priority: u8) {
+        self.priorities.insert(id.as_raw_fd(), priority);
+    }
+
+    fn wait_io(&mut self, timeout: Option<Duration>) -> Result<bool, R::Error> {
+        // Rounding up, so the sub-millisecond timeouts do not turn into busy
+        // loop
+        let timeout_ms = timeout
+            .map(|timeout| {
+                let ms = timeout.as_millis() + (timeout.subsec_nanos() % 1_000_000 != 0) as u128;
+                ms.min(libc::c_int::MAX as u128) as libc::c_int
+            })
+            .unwrap_or(-1);
+
+        // Blocking call
+        let count = unsafe {
+            libc::epoll_wait(
+                self.epoll.as_raw_fd(),
+                self.read_events.as_mut_ptr(),
+                EVENT_BATCH as _,
+                timeout_ms,
+            )
+        };
+        if count < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(true);
+            }
+            return Err(err.into());
+        }
+        unsafe { self.read_events.set_len(count as usize) };
+
+        let wake_fd = self.wake_recv.as_raw_fd();
+        // Events left from the previous calls are not reordered
+        let batch_start = self.events.len();
+        let mut timed_out = true;
+        let mut error = None;
+        for ev in self.read_events.drain(..) {
+            let fd = ev.u64 as RawFd;
+            if fd == wake_fd {
+                error = self.wake_recv.reset().err();
+                continue;
+            }
+            let Some((id, _)) = self.actors.get(&fd) else {
+                continue;
+            };
+            timed_out = false;
+            let flags = ev.events as libc::c_int;
+            self.events.push_back(IoSrc {
+                source: id.clone(),
+                io: IoEv {
+                    is_readable: flags & (libc::EPOLLIN | libc::EPOLLRDHUP) != 0,
+                    is_writable: flags & libc::EPOLLOUT != 0,
+                    is_hangup: flags & (libc::EPOLLHUP | libc::EPOLLRDHUP) != 0,
+                    is_error: flags & libc::EPOLLERR != 0,
+                },
+            });
+        }
+        let priorities = &self.priorities;
+        self.events.make_contiguous()[batch_start..].sort_by_key(|ev| {
+            let priority = priorities.get(&ev.source.as_raw_fd());
+            Reverse(priority.copied().unwrap_or(DEFAULT_PRIORITY))
+        });
+
+        match error {
+            Some(err) => Err(err.into()),
+            None => Ok(timed_out),
+        }
+    }
+
+    fn waker(&self) -> Arc<dyn Waker> {
+        self.waker.clone()
+    }
+
+    fn io_stats(&self) -> HashMap<R::Id, IoStats> {
+        self.stats.snapshot()
+    }
+}
+
+impl<R> Iterator for EpollScheduler<R>
+where
+    R: Actor,
+    R::Id: AsRawFd,
+{
+    type Item = IoSrc<R::Id>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.stats.deliver(self.events.pop_front())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::time::Instant;
+
+    use super::*;
+    use crate::actors::stdtcp::TcpConnection;
+    use crate::reactor::tests::{
+        check_hangup, check_idle_connection, check_io_stats, check_priorities,
+        check_unregister_pending, reactor,
+    };
+
+    #[test]
+    fn idle_connection() {
+        check_idle_connection(&mut EpollScheduler::new().unwrap());
+        check_idle_connection(&mut EpollScheduler::with(true).unwrap());
+    }
+
+    #[test]
+    fn io_stats() {
+        check_io_stats(&mut EpollScheduler::new().unwrap());
+    }
+
+    #[test]
+    fn hangup() {
+        check_hangup(&mut EpollScheduler::new().unwrap());
+        check_hangup(&mut EpollScheduler::with(true).unwrap());
+    }
+
+    #[test]
+    fn unregister_pending() {
+        check_unregister_pending(&mut EpollScheduler::new().unwrap());
+        check_unregister_pending(&mut EpollScheduler::with(true).unwrap());
+    }
+
+    #[test]
+    fn priorities() {
+        check_priorities(&mut EpollScheduler::new().unwrap());
+        check_priorities(&mut EpollScheduler::with(true).unwrap());
+    }
+
+    #[test]
+    fn edge_triggered_reads_are_not_lost() {
+        let (mut reactor, _) = reactor();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let mut conn = TcpConnection::accept(stream, reactor.controller()).unwrap();
+        let mut scheduler = EpollScheduler::with(true).unwrap();
+        scheduler.register_actor(&conn).unwrap();
+
+        // Both messages arrive before the scheduler is polled, generating a
+        // single edge
+        client.write_all(b"first").unwrap();
+        client.write_all(b"second").unwrap();
+        let mut received = vec![];
+        let start = Instant::now();
+        while received.len() < b"firstsecond".len() {
+            assert!(start.elapsed() < Duration::from_secs(1), "data is lost");
+            scheduler.wait_io(Some(Duration::from_millis(100))).unwrap();
+            for ev in &mut scheduler {
+                conn.io_ready(ev.io).unwrap();
+            }
+            conn.read_to_end(&mut received).unwrap();
+        }
+        assert_eq!(received, b"firstsecond");
+
+        // All the data was read out, so there are no more events until the
+        // peer sends new data
+        assert!(scheduler.wait_io(Some(Duration::from_millis(100))).unwrap());
+        client.write_all(b"third").unwrap();
+        assert!(!scheduler.wait_io(Some(Duration::from_secs(1))).unwrap());
+        for ev in &mut scheduler {
+            assert!(ev.io.is_readable);
+            conn.io_ready(ev.io).unwrap();
+        }
+        received.clear();
+        conn.read_to_end(&mut received).unwrap();
+        assert_eq!(received, b"third");
+
+        reactor.shutdown().unwrap();
+    }
+
+    #[test]
+    fn level_triggered_reports_unread_data() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        let (mut reactor, _) = reactor();
+        let conn = TcpConnection::accept(server, reactor.controller()).unwrap();
+        let mut scheduler = EpollScheduler::new().unwrap();
+        scheduler.register_actor(&conn).unwrap();
+
+        client.write_all(b"data").unwrap();
+        for _ in 0..3 {
+            assert!(!scheduler.wait_io(Some(Duration::from_secs(1))).unwrap());
+            assert!((&mut scheduler).any(|ev| ev.io.is_readable));
+        }
+        reactor.shutdown().unwrap();
+    }
+}
@@ -0,0 +1,433 @@
+use std::collections::{HashMap, VecDeque};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::Arc;
+use std::time::Duration;
+use std::{io, mem, ptr};
+
+use crate::actors::{IoEv, IoSrc};
+use crate::schedulers::{IoStats, IoStatsMap, PipeWaker, WakeReceiver, Waker};
+use crate::{Actor, Scheduler, TimerToken};
+
+/// Maximal number of events read from the kernel with a single call.
+const EVENT_BATCH: usize = 1024;
+
+/// Manager for a set of resources which are polled for an event loop by the
+/// re-actor by using `kqueue` interface of macOS and BSD kernels.
+///
+/// Each actor is registered with `EVFILT_READ` and `EVFILT_WRITE` filters,
+/// which are disabled when the actor is not interested in the corresponding
+/// events. Events of both filters are merged, so the actor gets a single
+/// [`IoEv`] per wait, like with the other schedulers. End of file (`EV_EOF`) is
+/// reported as a hang-up.
+/// Timers set by the re-actor are delegated to the kernel `EVFILT_TIMER`
+/// filter.
+pub struct KqueueScheduler<R>
+where
+    R: Actor,
+    R::Id: AsRawFd,
+{
+    kqueue: OwnedFd,
+    actors: HashMap<RawFd, (R::Id, IoEv)>,
+    changes: Vec<libc::kevent>,
+    read_events: Vec<libc::kevent>,
+    events: VecDeque<IoSrc<R::Id>>,
+    stats: IoStatsMap<R::Id>,
+    timers: Vec<TimerToken>,
+    waker: Arc<PipeWaker>,
+    wake_recv: WakeReceiver,
+}
+
+// Raw kevent structures contain `udata` pointer, which is never used by the
+// scheduler.
+unsafe impl<R> Send for KqueueScheduler<R>
+where
+    R: Actor,
+    R::Id: AsRawFd + Send,
+{
+}
+
+fn kevent(ident: usize, filter: i16, flags: u16) -> libc::kevent {
+    // Zeroed initialization is used since the structure fields differ between
+    // the platforms
+    let mut ev: libc::kevent = unsafe { mem::zeroed() };
+    ev.ident = ident as _;
+    ev.filter = filter as _;
+    ev.flags = flags as _;
+    ev
+}
+
+fn filter_flags(interested: bool) -> u16 {
+    if interested {
+        libc::EV_ENABLE
+    } else {
+        libc::EV_DISABLE
+    }
+}
+
+impl<R> KqueueScheduler<R>
+where
+    R: Actor,
+    R::Id: AsRawFd,
+{
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::kqueue() };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let kqueue = unsafe { OwnedFd::from_raw_fd(fd) };
+        let (waker, wake_recv) = PipeWaker::pair()?;
+        let mut scheduler = Self {
+            kqueue,
+            actors: empty!(),
+            changes: empty!(),
+            read_events: Vec::with_capacity(EVENT_BATCH),
+            events: empty!(),
+            stats: default!(),
+            timers: empty!(),
+            waker,
+            wake_recv,
+        };
+        let wake_fd = scheduler.wake_recv.as_raw_fd() as usize;
+        scheduler
+            .changes
+            .push(kevent(wake_fd, libc::EVFILT_READ, libc::EV_ADD));
+        Ok(scheduler)
+    }
+
+    /// Applies changes to the kernel event list immediately, without waiting
+    /// for the events.
+    fn apply(&mut self, changes: &[libc::kevent]) -> io::Result<()> {
+        let res = unsafe {
+            libc::kevent(
+                self.kqueue.as_raw_fd(),
+                changes.as_ptr(),
+                changes.len() as _,
+                ptr::null_mut(),
+                0,
+                ptr::null(),
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl<R> Scheduler<R> for KqueueScheduler<R>
+where
+    R: Actor,
+    R::Id: AsRawFd + Send,
+    R::Error: From<io::Error>,
+{
+    fn has_actor(&self, id: &R::Id) -> bool {
+        self.actors.contains_key(&id.as_raw_fd())
+    }
+
+    fn register_actor(&mut self, resource: &R) -> Result<(), R::Error> {
+        let id = resource.id();
+        let interest = resource.interests();
+        let fd = id.as_raw_fd();
+        self.changes.push(kevent(
+            fd as usize,
+            libc::EVFILT_READ,
+            libc::EV_ADD | filter_flags(interest.is_readable),
+        ));
+        self.changes.push(kevent(
+            fd as usize,
+            libc::EVFILT_WRITE,
+            libc::EV_ADD | filter_flags(interest.is_writable),
+        ));
+        self.stats.register(id.clone());
+        self.actors.insert(fd, (id, interest));
+        Ok(())
+    }
+
+    fn set_interest(&mut self, id: &R::Id, interest: IoEv) -> Result<(), R::Error> {
+        let fd = id.as_raw_fd();
+        let Some((_, current)) = self.actors.get_mut(&fd) else {
+            return Ok(());
+        };
+        if current.is_readable != interest.is_readable {
+            self.changes.push(kevent(
+                fd as usize,
+                libc::EVFILT_READ,
+                filter_flags(interest.is_readable),
+            ));
+        }
+        if current.is_writable != interest.is_writable {
+            self.changes.push(kevent(
+                fd as usize,
+                libc::EVFILT_WRITE,
+                filter_flags(interest.is_writable),
+            ));
+        }
+        *current = interest;
+        Ok(())
+    }
+
+    fn unregister_actor(&mut self, id: &R::Id) -> Result<(), R::Error> {
+        self.events.retain(|ev| ev.source != *id);
+        self.stats.unregister(id);
+        let fd = id.as_raw_fd();
+        if self.actors.remove(&fd).is_none() {
+            return Ok(());
+        }
+        // Registration may be still pending, so we just cancel it
+        self.changes.retain(|ev| {
+            ev.ident as RawFd != fd
+                || (ev.filter != libc::EVFILT_READ && ev.filter != libc::EVFILT_WRITE)
+        });
+        for filter in [libc::EVFILT_READ, libc::EVFILT_WRITE] {
+            match self.apply(&[kevent(fd as usize, filter, libc::EV_DELETE)]) {
+                // The filter was not registered yet or the file descriptor is
+                // already closed, which removes it from the kqueue
+                Err(err)
+                    if err.raw_os_error() == Some(libc::ENOENT)
+                        || err.raw_os_error() == Some(libc::EBADF) => {}
+                Err(err) => return Err(err.into()),
+                Ok(()) => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn wait_io(&mut self, timeout: Option<Duration>) -> Result<bool, R::Error> {
+        let ts = timeout.map(|timeout| libc::timespec {
+            tv_sec: timeout.as_secs() as _,
+            tv_nsec: timeout.subsec_nanos() as _,
+        });
+        let ts_ptr = ts
+            .as_ref()
+            .map(|ts| ts as *const libc::timespec)
+            .unwrap_or(ptr::null());
+
+        // Blocking call
+        let count = unsafe {
+            libc::kevent(
+                self.kqueue.as_raw_fd(),
+                self.changes.as_ptr(),
+                self.changes.len() as _,
+                self.read_events.as_mut_ptr(),
+                EVENT_BATCH as _,
+                ts_ptr,
+            )
+        };
+        if count < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(true);
+            }
+            return Err(err.into());
+        }
+        self.changes.clear();
+        unsafe { self.read_events.set_len(count as usize) };
+
+        let wake_fd = self.wake_recv.as_raw_fd();
+        let mut timed_out = true;
+        let mut error = None;
+        // Positions of the events generated by this call in the event queue
+        let mut positions = HashMap::<RawFd, usize>::new();
+        for ev in self.read_events.drain(..) {
+            if ev.flags & libc::EV_ERROR != 0 {
+                // Errors of the individual changes do not prevent processing
+                // of the rest of the events
+                error.get_or_insert(io::Error::from_raw_os_error(ev.data as i32));
+                continue;
+            }
+            if ev.filter == libc::EVFILT_TIMER {
+                self.timers.push(TimerToken::from_raw(ev.ident as u64));
+                continue;
+            }
+            let fd = ev.ident as RawFd;
+            if fd == wake_fd {
+                error = self.wake_recv.reset().err().or(error);
+                continue;
+            }
+            let Some((id, _)) = self.actors.get(&fd) else {
+                continue;
+            };
+            timed_out = false;
+            let io = IoEv {
+                // End of file is reported as readable too, so the actor can
+                // read out the data left in the socket buffer
+                is_readable: ev.filter == libc::EVFILT_READ,
+                is_writable: ev.filter == libc::EVFILT_WRITE,
+                is_hangup: ev.flags & libc::EV_EOF != 0,
+                // On end of file the socket error, if any, is put into the
+                // filter flags
+                is_error: ev.flags & libc::EV_EOF != 0 && ev.fflags != 0,
+            };
+            match positions.get(&fd) {
+                Some(&pos) => {
+                    let merged = &mut self.events[pos].io;
+                    merged.is_readable |= io.is_readable;
+                    merged.is_writable |= io.is_writable;
+                    merged.is_hangup |= io.is_hangup;
+                    merged.is_error |= io.is_error;
+                }
+                None => {
+                    positions.insert(fd, self.events.len());
+                    self.events.push_back(IoSrc {
+                        source: id.clone(),
+                        io,
+                    });
+                }
+            }
+        }
+
+        match error {
+            Some(err) => Err(err.into()),
+            None => Ok(timed_out && self.timers.is_empty()),
+        }
+    }
+
+    fn waker(&self) -> Arc<dyn Waker> {
+        self.waker.clone()
+    }
+
+    fn io_stats(&self) -> HashMap<R::Id, IoStats> {
+        self.stats.snapshot()
+    }
+
+    fn set_timer(&mut self, token: TimerToken, duration: Duration) -> Result<bool, R::Error> {
+        let mut ev = kevent(
+            token.to_raw() as usize,
+            libc::EVFILT_TIMER,
+            libc::EV_ADD | libc::EV_ONESHOT,
+        );
+        // Default timer units are milliseconds on all supported platforms
+        ev.data = duration.as_millis() as _;
+        self.changes.push(ev);
+        Ok(true)
+    }
+
+    fn cancel_timer(&mut self, token: TimerToken) -> Result<(), R::Error> {
+        let ident = token.to_raw() as usize;
+        let len = self.changes.len();
+        self.changes
+            .retain(|ev| !(ev.filter == libc::EVFILT_TIMER && ev.ident as usize == ident));
+        if self.changes.len() != len {
+            return Ok(());
+        }
+        match self.apply(&[kevent(ident, libc::EVFILT_TIMER, libc::EV_DELETE)]) {
+            // Timer has already expired
+            Err(err) if err.raw_os_error() == Some(libc::ENOENT) => Ok(()),
+            Err(err) => Err(err.into()),
+            Ok(()) => Ok(()),
+        }
+    }
+
+    fn fired_timers(&mut self, fired: &mut Vec<TimerToken>) {
+        fired.append(&mut self.timers);
+    }
+}
+
+impl<R> Iterator for KqueueScheduler<R>
+where
+    R: Actor,
+    R::Id: AsRawFd,
+{
+    type Item = IoSrc<R::Id>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.stats.deliver(self.events.pop_front())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::reactor::tests::{
+        check_hangup, check_idle_connection, check_io_stats, check_unregister_pending, Fd, FdActor,
+    };
+
+    /// Number of simultaneous connections.
+    const CONNECTIONS: usize = 500;
+
+    #[test]
+    fn readiness_of_many_connections() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut scheduler = KqueueScheduler::<FdActor>::new().unwrap();
+
+        let mut clients = Vec::with_capacity(CONNECTIONS);
+        let mut servers = Vec::with_capacity(CONNECTIONS);
+        for _ in 0..CONNECTIONS {
+            clients.push(TcpStream::connect(addr).unwrap());
+            let (server, _) = listener.accept().unwrap();
+            scheduler.register_actor(&FdActor::new(&server)).unwrap();
+            servers.push(server);
+        }
+        let all = servers
+            .iter()
+            .map(|server| Fd(server.as_raw_fd()))
+            .collect::<HashSet<_>>();
+
+        // Idle connections must be writable but not readable
+        let mut writable = HashSet::new();
+        while writable.len() < CONNECTIONS {
+            assert!(!scheduler.wait_io(Some(Duration::from_secs(1))).unwrap());
+            for ev in &mut scheduler {
+                assert!(!ev.io.is_readable);
+                assert!(ev.io.is_writable);
+                writable.insert(ev.source);
+            }
+        }
+        assert_eq!(writable, all);
+
+        for client in &mut clients {
+            client.write_all(&[1]).unwrap();
+        }
+        let mut readable = HashSet::new();
+        while readable.len() < CONNECTIONS {
+            assert!(!scheduler.wait_io(Some(Duration::from_secs(1))).unwrap());
+            for ev in &mut scheduler {
+                // Both filters are reported with a single event
+                assert!(ev.io.is_writable);
+                assert!(!ev.io.is_hangup);
+                if ev.io.is_readable {
+                    readable.insert(ev.source);
+                }
+            }
+        }
+        assert_eq!(readable, all);
+    }
+
+    #[test]
+    fn kernel_timers() {
+        let mut scheduler = KqueueScheduler::<FdActor>::new().unwrap();
+        let token = TimerToken::from_raw(1);
+        assert!(scheduler
+            .set_timer(token, Duration::from_millis(10))
+            .unwrap());
+        assert!(!scheduler.wait_io(Some(Duration::from_secs(1))).unwrap());
+        let mut fired = vec![];
+        scheduler.fired_timers(&mut fired);
+        assert_eq!(fired, vec![token]);
+    }
+
+    #[test]
+    fn idle_connection() {
+        check_idle_connection(&mut KqueueScheduler::new().unwrap());
+    }
+
+    #[test]
+    fn io_stats() {
+        check_io_stats(&mut KqueueScheduler::new().unwrap());
+    }
+
+    #[test]
+    fn hangup() {
+        check_hangup(&mut KqueueScheduler::new().unwrap());
+    }
+
+    #[test]
+    fn unregister_pending() {
+        check_unregister_pending(&mut KqueueScheduler::new().unwrap());
+    }
+}
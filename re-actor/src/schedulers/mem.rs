@@ -0,0 +1,459 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::actors::mem::{AsMemSocket, MemQueue, MemSignal};
+use crate::actors::{IoEv, IoSrc};
+use crate::schedulers::{IoStats, IoStatsMap, Waker};
+use crate::{Actor, Scheduler};
+
+/// Manager for a set of in-memory sockets (see [`crate::actors::mem`]), which
+/// allows to test the re-actor business logic without network access.
+///
+/// Actors are reported readable while their sockets have unread frames, and
+/// writable always when they are interested in it. If there are no events,
+/// the scheduler blocks until a frame is sent to one of its sockets or until
+/// it is woken up.
+pub struct MemScheduler<R: Actor> {
+    signal: Arc<MemSignal>,
+    actors: HashMap<R::Id, (Arc<MemQueue>, IoEv)>,
+    events: VecDeque<IoSrc<R::Id>>,
+    stats: IoStatsMap<R::Id>,
+}
+
+impl<R: Actor> Default for MemScheduler<R> {
+    fn default() -> Self {
+        MemScheduler {
+            signal: Arc::default(),
+            actors: empty!(),
+            events: empty!(),
+            stats: default!(),
+        }
+    }
+}
+
+impl<R: Actor> MemScheduler<R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Collects events for all actors, returning whether there were any.
+    fn collect(&mut self) -> bool {
+        let len = self.events.len();
+        for (id, (inbox, interest)) in &self.actors {
+            let io = IoEv {
+                is_readable: interest.is_readable && inbox.has_frames(),
+                is_writable: interest.is_writable,
+                is_hangup: false,
+                is_error: false,
+            };
+            if io.is_readable || io.is_writable {
+                self.events.push_back(IoSrc {
+                    source: id.clone(),
+                    io,
+                });
+            }
+        }
+        self.events.len() > len
+    }
+}
+
+impl<R> Scheduler<R> for MemScheduler<R>
+where
+    R: Actor + AsMemSocket,
+    R::Id: Send,
+{
+    fn has_actor(&self, id: &R::Id) -> bool {
+        self.actors.contains_key(id)
+    }
+
+    fn register_actor(&mut self, actor: &R) -> Result<(), R::Error> {
+        let inbox = actor.inbox().clone();
+        inbox.set_signal(Some(self.signal.clone()));
+        self.stats.register(actor.id());
+        self.actors.insert(actor.id(), (inbox, actor.interests()));
+        Ok(())
+    }
+
+    fn unregister_actor(&mut self, id: &R::Id) -> Result<(), R::Error> {
+        self.events.retain(|ev| ev.source != *id);
+        self.stats.unregister(id);
+        if let Some((inbox, _)) = self.actors.remove(id) {
+            inbox.set_signal(None);
+        }
+        Ok(())
+    }
+
+    fn set_interest(&mut self, id: &R::Id, interest: IoEv) -> Result<(), R::Error> {
+        if let Some((_, current)) = self.actors.get_mut(id) {
+            *current = interest;
+        }
+        Ok(())
+    }
+
+    fn wait_io(&mut self, timeout: Option<Duration>) -> Result<bool, R::Error> {
+        // The signal is not reset here: the notification may come from the
+        // controller after the runtime has checked the control queue, and
+        // clearing it would leave the control event unprocessed until some
+        // I/O happens. Stale notifications only cause a spurious wake-up.
+        if self.collect() {
+            return Ok(false);
+        }
+        let notified = self.signal.wait(timeout);
+        self.collect();
+        Ok(!notified)
+    }
+
+    fn waker(&self) -> Arc<dyn Waker> {
+        self.signal.clone()
+    }
+
+    fn io_stats(&self) -> HashMap<R::Id, IoStats> {
+        self.stats.snapshot()
+    }
+}
+
+impl<R: Actor> Iterator for MemScheduler<R> {
+    type Item = IoSrc<R::Id>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.stats.deliver(self.events.pop_front())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+    use std::time::Instant;
+
+    use super::*;
+    use crate::actors::mem::MemSocket;
+    #[cfg(feature = "snapshot")]
+    use crate::actors::{ActorSnapshot, Migrate};
+    use crate::{Controller, Handler, InternalError, Layout, Pool, Reactor, ReactorApi};
+
+    /// Number of frames exchanged by the test.
+    const FRAMES: u32 = 100;
+
+    #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+    #[display(Debug)]
+    enum MemPool {
+        Main,
+    }
+
+    impl From<u32> for MemPool {
+        fn from(_: u32) -> Self {
+            MemPool::Main
+        }
+    }
+
+    impl From<MemPool> for u32 {
+        fn from(_: MemPool) -> Self {
+            0
+        }
+    }
+
+    impl Layout for MemPool {
+        type RootActor = Echo;
+
+        fn default_pools() -> Vec<Pool<Self::RootActor, Self>> {
+            vec![Pool::new(MemPool::Main, MemScheduler::new(), PanicHandler)]
+        }
+
+        fn convert(_: Box<dyn Any>) -> MemSocket<MemPool> {
+            unreachable!()
+        }
+    }
+
+    struct PanicHandler;
+
+    impl Handler<MemPool> for PanicHandler {
+        fn handle_err(&mut self, err: InternalError<MemPool>) {
+            panic!("unexpected re-actor error: {err}")
+        }
+    }
+
+    /// Socket which either echoes the received frames back or collects them.
+    /// Sessions with a non-zero key encrypt the frames by xoring them with
+    /// the key.
+    struct Echo {
+        socket: MemSocket<MemPool>,
+        echo: bool,
+        key: u8,
+        collected: Vec<Vec<u8>>,
+    }
+
+    impl Echo {
+        fn new(socket: MemSocket<MemPool>, echo: bool, key: u8) -> Self {
+            Echo {
+                socket,
+                echo,
+                key,
+                collected: vec![],
+            }
+        }
+
+        fn cipher(&self, mut frame: Vec<u8>) -> Vec<u8> {
+            frame.iter_mut().for_each(|byte| *byte ^= self.key);
+            frame
+        }
+    }
+
+    impl AsMemSocket for Echo {
+        fn inbox(&self) -> &Arc<MemQueue> {
+            self.socket.inbox()
+        }
+    }
+
+    impl Actor for Echo {
+        type Layout = MemPool;
+        type Id = u64;
+        type Context = MemSocket<MemPool>;
+        type Cmd = Vec<u8>;
+        type Error = io::Error;
+
+        fn with(socket: Self::Context, _: Controller<MemPool>) -> Result<Self, Self::Error> {
+            Ok(Echo::new(socket, true, 0))
+        }
+
+        fn id(&self) -> Self::Id {
+            self.socket.id()
+        }
+
+        fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+            self.socket.io_ready(io)?;
+            while let Some(frame) = self.socket.recv_frame() {
+                let frame = self.cipher(frame);
+                if self.echo {
+                    let frame = self.cipher(frame);
+                    self.socket.send_frame(frame);
+                } else {
+                    self.collected.push(frame);
+                }
+            }
+            Ok(())
+        }
+
+        fn handle_cmd(&mut self, frame: Self::Cmd) -> Result<(), Self::Error> {
+            let frame = self.cipher(frame);
+            self.socket.handle_cmd(frame)
+        }
+
+        fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+            Err(err)
+        }
+
+        fn interests(&self) -> IoEv {
+            self.socket.interests()
+        }
+
+        fn disconnect(&mut self) -> Result<(), Self::Error> {
+            self.socket.disconnect()
+        }
+    }
+
+    #[cfg(feature = "snapshot")]
+    impl Migrate for Echo {
+        fn snapshot(&self) -> Result<ActorSnapshot, Self::Error> {
+            let socket = self.socket.snapshot()?.into_bytes();
+            ActorSnapshot::encode(&(socket, self.echo, self.key, &self.collected))
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        }
+
+        fn restore(
+            snapshot: ActorSnapshot,
+            controller: Controller<MemPool>,
+        ) -> Result<Self, Self::Error> {
+            let (socket, echo, key, collected): (Vec<u8>, bool, u8, Vec<Vec<u8>>) = snapshot
+                .decode()
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            Ok(Echo {
+                socket: MemSocket::restore(socket.into(), controller)?,
+                echo,
+                key,
+                collected,
+            })
+        }
+    }
+
+    #[test]
+    fn frames_exchange() {
+        let mut client_reactor = Reactor::<MemPool>::new().unwrap();
+        let mut server_reactor = Reactor::<MemPool>::new().unwrap();
+        let mut client = client_reactor.controller();
+        let (client_socket, server_socket) = MemSocket::pair();
+        let id = client_socket.id();
+        client
+            .insert_actor(MemPool::Main, Echo::new(client_socket, false, 0))
+            .unwrap();
+        server_reactor
+            .start_actor(MemPool::Main, server_socket)
+            .unwrap();
+        assert!(client.contains_actor(&id).unwrap());
+
+        for no in 0..FRAMES {
+            client.send(id, no.to_be_bytes().to_vec()).unwrap();
+        }
+
+        // Frames are delivered in order and are not merged or split
+        assert_eq!(collect_echoed(&mut client, id), frames(0..FRAMES));
+
+        client_reactor.shutdown().unwrap();
+        server_reactor.shutdown().unwrap();
+    }
+
+    #[test]
+    fn upgrade_to_encrypted_session() {
+        const KEY: u8 = 0x5A;
+
+        let mut client_reactor = Reactor::<MemPool>::new().unwrap();
+        let mut server_reactor = Reactor::<MemPool>::new().unwrap();
+        let mut client = client_reactor.controller();
+        let mut server = server_reactor.controller();
+        let (client_socket, server_socket) = MemSocket::pair();
+        let (client_id, server_id) = (client_socket.id(), server_socket.id());
+        let client_upgrade = Echo::new(client_socket.duplicate(), false, KEY);
+        let server_upgrade = Echo::new(server_socket.duplicate(), true, KEY);
+        client
+            .insert_actor(MemPool::Main, Echo::new(client_socket, false, 0))
+            .unwrap();
+        server
+            .insert_actor(MemPool::Main, Echo::new(server_socket, true, 0))
+            .unwrap();
+        assert!(client.contains_actor(&client_id).unwrap());
+        assert!(server.contains_actor(&server_id).unwrap());
+        assert!(matches!(
+            client.replace_actor(server_id, Echo::new(MemSocket::pair().0, false, 0)),
+            Err(InternalError::UnknownActor(id)) if id == server_id
+        ));
+
+        for no in 0..FRAMES {
+            client.send(client_id, no.to_be_bytes().to_vec()).unwrap();
+        }
+        assert_eq!(collect_echoed(&mut client, client_id), frames(0..FRAMES));
+
+        let plain = server
+            .replace_actor(server_id, server_upgrade)
+            .unwrap()
+            .recv_timeout(Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(plain.key, 0);
+        let replaced = client.replace_actor(client_id, client_upgrade).unwrap();
+        // Commands queued right after the replacement are sent by the new
+        // session, so the server decrypts them
+        for no in FRAMES..2 * FRAMES {
+            client.send(client_id, no.to_be_bytes().to_vec()).unwrap();
+        }
+        let plain = replaced.recv_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(plain.key, 0);
+        assert!(plain.collected.is_empty());
+        assert_eq!(
+            collect_echoed(&mut client, client_id),
+            frames(FRAMES..2 * FRAMES)
+        );
+
+        client_reactor.shutdown().unwrap();
+        server_reactor.shutdown().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "snapshot")]
+    fn migrate_mid_session() {
+        const KEY: u8 = 0x5A;
+
+        let mut client_reactor = Reactor::<MemPool>::new().unwrap();
+        let mut source_reactor = Reactor::<MemPool>::new().unwrap();
+        let mut target_reactor = Reactor::<MemPool>::new().unwrap();
+        let mut client = client_reactor.controller();
+        let mut source = source_reactor.controller();
+        let mut target = target_reactor.controller();
+        let (client_socket, server_socket) = MemSocket::pair();
+        let (client_id, server_id) = (client_socket.id(), server_socket.id());
+        client
+            .insert_actor(MemPool::Main, Echo::new(client_socket, false, KEY))
+            .unwrap();
+        source
+            .insert_actor(MemPool::Main, Echo::new(server_socket, false, KEY))
+            .unwrap();
+        assert!(source.contains_actor(&server_id).unwrap());
+        assert!(matches!(
+            source.migrate_actor(client_id, target.clone()),
+            Err(InternalError::UnknownActor(id)) if id == client_id
+        ));
+
+        for no in 0..FRAMES {
+            client.send(client_id, no.to_be_bytes().to_vec()).unwrap();
+        }
+        source.migrate_actor(server_id, target.clone()).unwrap();
+        // Frames sent during the migration are received either by the
+        // migrated actor or by the restored one
+        for no in FRAMES..2 * FRAMES {
+            client.send(client_id, no.to_be_bytes().to_vec()).unwrap();
+        }
+        assert!(!source.contains_actor(&server_id).unwrap());
+        assert!(target.contains_actor(&server_id).unwrap());
+
+        // The restored actor keeps the session key and the frames collected
+        // before the migration
+        let start = Instant::now();
+        let mut server = loop {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "not all frames were received"
+            );
+            let server = target
+                .take_actor(server_id)
+                .unwrap()
+                .recv_timeout(Duration::from_secs(1))
+                .unwrap();
+            if server.collected.len() == 2 * FRAMES as usize {
+                break server;
+            }
+            target.insert_actor(MemPool::Main, server).unwrap();
+            assert!(target.contains_actor(&server_id).unwrap());
+        };
+        assert_eq!(server.collected, frames(0..2 * FRAMES));
+
+        server.collected.clear();
+        server.echo = true;
+        target.insert_actor(MemPool::Main, server).unwrap();
+        for no in 2 * FRAMES..3 * FRAMES {
+            client.send(client_id, no.to_be_bytes().to_vec()).unwrap();
+        }
+        assert_eq!(
+            collect_echoed(&mut client, client_id),
+            frames(2 * FRAMES..3 * FRAMES)
+        );
+
+        client_reactor.shutdown().unwrap();
+        source_reactor.shutdown().unwrap();
+        target_reactor.shutdown().unwrap();
+    }
+
+    fn frames(range: std::ops::Range<u32>) -> Vec<Vec<u8>> {
+        range.map(|no| no.to_be_bytes().to_vec()).collect()
+    }
+
+    /// Collects frames echoed to the actor until they match the number of the
+    /// frames sent by the test.
+    fn collect_echoed(client: &mut Controller<MemPool>, id: u64) -> Vec<Vec<u8>> {
+        let start = Instant::now();
+        let mut collected = vec![];
+        while collected.len() < FRAMES as usize {
+            assert!(
+                start.elapsed() < Duration::from_secs(1),
+                "not all frames were echoed"
+            );
+            let mut actor = client
+                .take_actor(id)
+                .unwrap()
+                .recv_timeout(Duration::from_secs(1))
+                .unwrap();
+            collected.append(&mut actor.collected);
+            client.insert_actor(MemPool::Main, actor).unwrap();
+            assert!(client.contains_actor(&id).unwrap());
+        }
+        collected
+    }
+}
@@ -1,23 +1,54 @@
-#[cfg(feature = "epoll")]
+#[cfg(all(feature = "epoll", any(target_os = "linux", target_os = "android")))]
 mod epoll;
+#[cfg(all(
+    feature = "kqueue",
+    any(target_os = "macos", target_os = "freebsd", target_os = "openbsd")
+))]
+mod kqueue;
+mod mem;
 #[cfg(feature = "mio")]
 mod mio;
 #[cfg(feature = "polling")]
 mod polling;
 #[cfg(feature = "popol")]
 mod popol;
+mod priority;
+mod stats;
 mod threaded;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod uring;
+#[cfg(unix)]
+mod waker;
 #[cfg(feature = "zmq")]
 mod zeromq;
 
+#[cfg(all(feature = "epoll", any(target_os = "linux", target_os = "android")))]
+pub use self::epoll::EpollScheduler;
+#[cfg(all(
+    feature = "kqueue",
+    any(target_os = "macos", target_os = "freebsd", target_os = "openbsd")
+))]
+pub use self::kqueue::KqueueScheduler;
+pub use self::mem::MemScheduler;
 #[cfg(feature = "polling")]
 pub use self::polling::PollingScheduler;
 #[cfg(feature = "popol")]
 pub use self::popol::PopolScheduler;
+pub use self::priority::PriorityScheduler;
+pub use self::stats::IoStats;
+pub(crate) use self::stats::IoStatsMap;
+#[cfg(all(feature = "uring", target_os = "linux"))]
+pub use self::uring::UringScheduler;
+#[cfg(unix)]
+pub use self::waker::{PipeWaker, WakeReceiver};
 
+use std::collections::HashMap;
+use std::io;
+use std::sync::Arc;
 use std::time::Duration;
 
-use crate::actors::{Actor, IoSrc};
+use crate::actors::{Actor, IoEv, IoSrc};
+use crate::TimerToken;
 
 /// Implements specific way of scheduling how multiple actors under a
 /// [`Reactor`] run in a concurrent way.
@@ -25,7 +56,8 @@ pub trait Scheduler<R: Actor>: Iterator<Item = IoSrc<R::Id>> + Send {
     /// Detects whether a resource under the given id is known to the manager.
     fn has_actor(&self, id: &R::Id) -> bool;
 
-    /// Adds already operating/connected actor to the scheduler.
+    /// Adds already operating/connected actor to the scheduler, polling it
+    /// for the events returned by [`Actor::interests`].
     ///
     /// # I/O
     ///
@@ -36,20 +68,95 @@ pub trait Scheduler<R: Actor>: Iterator<Item = IoSrc<R::Id>> + Send {
     /// Removes previously added actor from the scheduler without generating
     /// any events. Stops actor run scheduling.
     ///
+    /// Events for the actor which were already collected by
+    /// [`Scheduler::wait_io`] but not yet returned by the iterator are
+    /// discarded: the actor may be unregistered by the runtime in the middle
+    /// of the event iteration, and its id may be reused by a new actor.
+    ///
     /// # I/O
     ///
     /// Implementations must not block on the operation or generate any I/O
     /// events.
     fn unregister_actor(&mut self, id: &R::Id) -> Result<(), R::Error>;
 
+    /// Changes set of I/O events the actor is polled for. Called by the
+    /// re-actor runtime each time the actor has processed I/O or a command;
+    /// thus implementations must do nothing if the interest is not changed.
+    ///
+    /// # I/O
+    ///
+    /// Implementations must not block on the operation. If the actor is
+    /// already ready for the new interest, the event must be reported by the
+    /// next [`Scheduler::wait_io`] call.
+    fn set_interest(&mut self, id: &R::Id, interest: IoEv) -> Result<(), R::Error>;
+
+    /// Sets priority of the actor I/O events (see [`Actor::priority`]). Called
+    /// by the re-actor runtime once the actor is registered.
+    ///
+    /// Schedulers supporting priorities must return the events collected by a
+    /// single [`Scheduler::wait_io`] call in the order of descending priority,
+    /// keeping the order of the events with the same priority. Default
+    /// implementation ignores priorities; schedulers not supporting them can
+    /// be wrapped into [`PriorityScheduler`].
+    fn set_priority(&mut self, _id: &R::Id, _priority: u8) {}
+
     /// Waits for I/O events from all actors under this scheduler.
     ///
+    /// Events collected by the previous calls which were not yet consumed via
+    /// the iterator must be kept and returned before the new ones, since the
+    /// runtime may leave some of the events for its next iteration.
+    ///
     /// # Returns
     ///
     /// Whether the function has timed out.
     ///
     /// # I/O
     ///
-    /// Blocks until the timeout.
+    /// Blocks until the timeout or until woken up with the [`Waker`] returned
+    /// by [`Scheduler::waker`].
     fn wait_io(&mut self, timeout: Option<Duration>) -> Result<bool, R::Error>;
+
+    /// Returns waker which can be used from other threads to interrupt
+    /// blocking [`Scheduler::wait_io`] call. The events used for waking up
+    /// must not be returned by the scheduler iterator.
+    fn waker(&self) -> Arc<dyn Waker>;
+
+    /// Sets one-time timer using the kernel timers, if they are supported by
+    /// the scheduler. Expired timers must be returned by
+    /// [`Scheduler::fired_timers`].
+    ///
+    /// # Returns
+    ///
+    /// `false` if the scheduler does not support kernel timers, in which case
+    /// the timer is tracked by the re-actor runtime itself.
+    fn set_timer(&mut self, _token: TimerToken, _duration: Duration) -> Result<bool, R::Error> {
+        Ok(false)
+    }
+
+    /// Cancels timer previously set with [`Scheduler::set_timer`].
+    fn cancel_timer(&mut self, _token: TimerToken) -> Result<(), R::Error> {
+        Ok(())
+    }
+
+    /// Moves the timers set with [`Scheduler::set_timer`] which has expired
+    /// during the last [`Scheduler::wait_io`] call into `fired`.
+    fn fired_timers(&mut self, _fired: &mut Vec<TimerToken>) {}
+
+    /// Returns counters of the I/O events delivered via the iterator for each
+    /// of the registered actors. Counting starts from zero once the actor is
+    /// registered.
+    ///
+    /// Default implementation returns no statistics, for schedulers which do
+    /// not track them.
+    fn io_stats(&self) -> HashMap<R::Id, IoStats> {
+        HashMap::new()
+    }
+}
+
+/// Interrupts blocking [`Scheduler::wait_io`] call from other threads.
+pub trait Waker: Send + Sync {
+    /// Makes the scheduler to return from [`Scheduler::wait_io`] - or, if it
+    /// does not wait at the moment, to return from the next call without
+    /// blocking.
+    fn wake(&self) -> io::Result<()>;
 }
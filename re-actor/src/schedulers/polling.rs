@@ -1,22 +1,29 @@
 use polling::{Event, Poller, Source};
-use std::collections::{HashSet, VecDeque};
+use std::collections::{HashMap, VecDeque};
 use std::io;
-use std::os::unix::io::{FromRawFd, RawFd};
+use std::sync::Arc;
 use std::time::Duration;
 
-use crate::actors::{IoEv, IoSrc};
+use crate::actors::{IoEv, IoSrc, RawSource};
+use crate::schedulers::{IoStats, IoStatsMap, Waker};
 use crate::{Actor, Scheduler};
 
 /// Manager for a set of resources which are polled for an event loop by the
 /// re-actor by using [`polling`] library.
+///
+/// Unlike the other schedulers, this one is not limited to unix systems: on
+/// Windows actor ids must be sockets (see [`RawSource`]).
 pub struct PollingScheduler<R>
 where
     R: Actor,
     R::Id: Source,
 {
-    poll: Poller,
-    actors: HashSet<R::Id>,
+    poll: Arc<Poller>,
+    actors: HashMap<R::Id, IoEv>,
+    /// Actor ids by the keys of the events generated by the poller.
+    keys: HashMap<usize, R::Id>,
     events: VecDeque<IoSrc<R::Id>>,
+    stats: IoStatsMap<R::Id>,
     read_events: Vec<Event>,
 }
 
@@ -27,38 +34,65 @@ where
 {
     pub fn new() -> io::Result<Self> {
         Ok(Self {
-            poll: Poller::new()?,
+            poll: Arc::new(Poller::new()?),
             actors: empty!(),
+            keys: empty!(),
             events: empty!(),
+            stats: default!(),
             read_events: empty!(),
         })
     }
 }
 
+fn event(raw: RawSource, interest: IoEv) -> Event {
+    Event {
+        key: raw as usize,
+        readable: interest.is_readable,
+        writable: interest.is_writable,
+    }
+}
+
 impl<R> Scheduler<R> for PollingScheduler<R>
 where
     R: Actor,
-    R::Id: Source + FromRawFd,
+    R::Id: Source,
     R::Error: From<io::Error>,
 {
     fn has_actor(&self, id: &R::Id) -> bool {
-        self.actors.contains(id)
+        self.actors.contains_key(id)
     }
 
     fn register_actor(&mut self, resource: &R) -> Result<(), R::Error> {
         let id = resource.id();
+        let interest = resource.interests();
         let raw = id.raw();
-        self.poll.add(id, Event::all(raw as usize))?;
-        self.actors.insert(resource.id());
+        self.poll.add(raw, event(raw, interest))?;
+        self.keys.insert(raw as usize, id.clone());
+        self.stats.register(id.clone());
+        self.actors.insert(id, interest);
         Ok(())
     }
 
     fn unregister_actor(&mut self, id: &R::Id) -> Result<(), R::Error> {
+        self.events.retain(|ev| ev.source != *id);
+        self.stats.unregister(id);
         self.actors.remove(id);
+        self.keys.remove(&(id.raw() as usize));
         self.poll.delete(id.raw())?;
         Ok(())
     }
 
+    fn set_interest(&mut self, id: &R::Id, interest: IoEv) -> Result<(), R::Error> {
+        match self.actors.get_mut(id) {
+            Some(current) if *current != interest => {
+                *current = interest;
+                self.poll.modify(id.raw(), event(id.raw(), interest))?;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
     fn wait_io(&mut self, timeout: Option<Duration>) -> Result<bool, R::Error> {
         // Blocking call
         self.poll.wait(&mut self.read_events, timeout)?;
@@ -68,11 +102,25 @@ where
         }
 
         for ev in &self.read_events {
+            // Events for the actors unregistered since the poller was armed
+            // are ignored
+            let Some(id) = self.keys.get(&ev.key) else {
+                continue;
+            };
+            // Poller operates in oneshot mode, so the interest must be re-armed
+            if let Some(interest) = self.actors.get(id) {
+                self.poll.modify(id.raw(), event(id.raw(), *interest))?;
+            }
+            let id = id.clone();
             self.events.push_back(IoSrc {
-                source: unsafe { R::Id::from_raw_fd(ev.key as RawFd) },
+                source: id,
                 io: IoEv {
                     is_readable: ev.readable,
                     is_writable: ev.writable,
+                    // Poller does not distinguish hang-ups and errors: they
+                    // are reported as readable events
+                    is_hangup: false,
+                    is_error: false,
                 },
             })
         }
@@ -80,6 +128,21 @@ where
 
         Ok(false)
     }
+
+    fn waker(&self) -> Arc<dyn Waker> {
+        self.poll.clone()
+    }
+
+    fn io_stats(&self) -> HashMap<R::Id, IoStats> {
+        self.stats.snapshot()
+    }
+}
+
+impl Waker for Poller {
+    fn wake(&self) -> io::Result<()> {
+        // Notification does not generate any events for the poller
+        self.notify()
+    }
 }
 
 impl<R> Iterator for PollingScheduler<R>
@@ -90,6 +153,77 @@ where
     type Item = IoSrc<R::Id>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.events.pop_front()
+        self.stats.deliver(self.events.pop_front())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::net::{TcpListener, TcpStream};
+
+    use super::*;
+    use crate::actors::AsRawSource;
+    use crate::reactor::tests::{
+        check_idle_connection, check_io_stats, check_priorities, check_unregister_pending, Fd,
+        FdActor,
+    };
+    use crate::schedulers::PriorityScheduler;
+
+    #[test]
+    fn idle_connection() {
+        check_idle_connection(&mut PollingScheduler::new().unwrap());
+    }
+
+    #[test]
+    fn io_stats() {
+        check_io_stats(&mut PollingScheduler::new().unwrap());
+        check_io_stats(&mut PriorityScheduler::new(
+            PollingScheduler::new().unwrap(),
+        ));
+    }
+
+    #[test]
+    fn unregister_pending() {
+        check_unregister_pending(&mut PollingScheduler::new().unwrap());
+    }
+
+    #[test]
+    fn priorities() {
+        let scheduler = PollingScheduler::new().unwrap();
+        check_priorities(&mut PriorityScheduler::new(scheduler));
+    }
+
+    #[test]
+    fn events_are_mapped_to_actors() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut scheduler = PollingScheduler::<FdActor>::new().unwrap();
+
+        let mut clients = vec![];
+        let mut servers = vec![];
+        for _ in 0..10 {
+            clients.push(TcpStream::connect(addr).unwrap());
+            let (server, _) = listener.accept().unwrap();
+            scheduler.register_actor(&FdActor::new(&server)).unwrap();
+            servers.push(server);
+        }
+        let all = servers
+            .iter()
+            .map(|server| Fd(server.as_raw_source()))
+            .collect::<HashSet<_>>();
+
+        let mut writable = HashSet::new();
+        while writable.len() < servers.len() {
+            assert!(!scheduler.wait_io(Some(Duration::from_secs(1))).unwrap());
+            writable.extend((&mut scheduler).map(|ev| ev.source));
+        }
+        assert_eq!(writable, all);
+
+        // Events of the unregistered actors are not reported
+        let removed = Fd(servers[0].as_raw_source());
+        scheduler.unregister_actor(&removed).unwrap();
+        scheduler.wait_io(Some(Duration::from_millis(100))).unwrap();
+        assert!((&mut scheduler).all(|ev| ev.source != removed));
     }
 }
@@ -1,11 +1,20 @@
-use std::collections::VecDeque;
+use std::collections::{HashMap, VecDeque};
 use std::io;
 use std::os::unix::io::AsRawFd;
+use std::sync::Arc;
 use std::time::Duration;
 
 use crate::actors::{IoEv, IoSrc};
+use crate::schedulers::{IoStats, IoStatsMap, PipeWaker, WakeReceiver, Waker};
 use crate::{Actor, Scheduler};
 
+/// Keys for the sources polled by [`PopolScheduler`].
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+enum Key<Id> {
+    Waker,
+    Actor(Id),
+}
+
 /// Manager for a set of resources which are polled for an event loop by the
 /// re-actor by using [`popol`] library.
 pub struct PopolScheduler<R>
@@ -13,8 +22,12 @@ where
     R: Actor,
     R::Id: AsRawFd,
 {
-    poll: popol::Poll<R::Id>,
+    poll: popol::Poll<Key<R::Id>>,
+    interests: HashMap<R::Id, IoEv>,
     events: VecDeque<IoSrc<R::Id>>,
+    stats: IoStatsMap<R::Id>,
+    waker: Arc<PipeWaker>,
+    wake_recv: WakeReceiver,
 }
 
 impl<R> PopolScheduler<R>
@@ -22,10 +35,34 @@ where
     R: Actor,
     R::Id: AsRawFd,
 {
-    pub fn new() -> Self {
-        Self {
-            poll: popol::Poll::new(),
+    pub fn new() -> io::Result<Self> {
+        let (waker, wake_recv) = PipeWaker::pair()?;
+        let mut poll = popol::Poll::new();
+        poll.register(Key::Waker, &wake_recv, popol::event::READ);
+        Ok(Self {
+            poll,
+            interests: empty!(),
             events: empty!(),
+            stats: default!(),
+            waker,
+            wake_recv,
+        })
+    }
+
+    fn register(&mut self, id: &R::Id, interest: IoEv) {
+        let events = match (interest.is_readable, interest.is_writable) {
+            (true, true) => popol::event::ALL,
+            (true, false) => popol::event::READ,
+            (false, true) => popol::event::WRITE,
+            // Actors without any interest are not polled at all
+            (false, false) => return,
+        };
+        self.poll.register(Key::Actor(id.clone()), id, events);
+    }
+
+    fn unregister(&mut self, id: &R::Id, interest: IoEv) {
+        if interest.is_readable || interest.is_writable {
+            self.poll.unregister(&Key::Actor(id.clone()));
         }
     }
 }
@@ -37,17 +74,36 @@ where
     R::Error: From<io::Error>,
 {
     fn has_actor(&self, id: &R::Id) -> bool {
-        self.poll.get(id).is_some()
+        self.interests.contains_key(id)
     }
 
     fn register_actor(&mut self, resource: &R) -> Result<(), R::Error> {
         let id = resource.id();
-        self.poll.register(id.clone(), &id, popol::event::ALL);
+        let interest = resource.interests();
+        self.register(&id, interest);
+        self.stats.register(id.clone());
+        self.interests.insert(id, interest);
         Ok(())
     }
 
     fn unregister_actor(&mut self, id: &R::Id) -> Result<(), R::Error> {
-        self.poll.unregister(id);
+        self.events.retain(|ev| ev.source != *id);
+        self.stats.unregister(id);
+        if let Some(interest) = self.interests.remove(id) {
+            self.unregister(id, interest);
+        }
+        Ok(())
+    }
+
+    fn set_interest(&mut self, id: &R::Id, interest: IoEv) -> Result<(), R::Error> {
+        match self.interests.get(id).copied() {
+            Some(current) if current != interest => {
+                self.unregister(id, current);
+                self.register(id, interest);
+                self.interests.insert(id.clone(), interest);
+            }
+            _ => {}
+        }
         Ok(())
     }
 
@@ -57,18 +113,31 @@ where
             return Ok(true);
         }
 
-        for (id, ev) in self.poll.events() {
-            self.events.push_back(IoSrc {
-                source: id.clone(),
-                io: IoEv {
-                    is_readable: ev.is_readable(),
-                    is_writable: ev.is_writable(),
-                },
-            })
+        for (key, ev) in self.poll.events() {
+            match key {
+                Key::Waker => self.wake_recv.reset()?,
+                Key::Actor(id) => self.events.push_back(IoSrc {
+                    source: id.clone(),
+                    io: IoEv {
+                        is_readable: ev.is_readable(),
+                        is_writable: ev.is_writable(),
+                        is_hangup: ev.is_hangup(),
+                        is_error: ev.is_error(),
+                    },
+                }),
+            }
         }
 
         Ok(false)
     }
+
+    fn waker(&self) -> Arc<dyn Waker> {
+        self.waker.clone()
+    }
+
+    fn io_stats(&self) -> HashMap<R::Id, IoStats> {
+        self.stats.snapshot()
+    }
 }
 
 impl<R> Iterator for PopolScheduler<R>
@@ -79,6 +148,6 @@ where
     type Item = IoSrc<R::Id>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.events.pop_front()
+        self.stats.deliver(self.events.pop_front())
     }
 }
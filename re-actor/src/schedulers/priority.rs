@@ -0,0 +1,107 @@
+use std::cmp::Reverse;
+use std::collections::{HashMap, VecDeque};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::actors::{IoEv, IoSrc, DEFAULT_PRIORITY};
+use crate::schedulers::{IoStats, Waker};
+use crate::{Actor, Scheduler, TimerToken};
+
+/// Scheduler wrapping other scheduler, which does not support priorities, and
+/// reordering its events by the actor priorities (see [`Actor::priority`]).
+///
+/// Events collected by each [`Scheduler::wait_io`] call are sorted by the
+/// descending priority of their actors; events of the actors with the same
+/// priority keep the order of the wrapped scheduler. Events left from the
+/// previous calls are returned first.
+pub struct PriorityScheduler<R: Actor, S: Scheduler<R>> {
+    inner: S,
+    priorities: HashMap<R::Id, u8>,
+    events: VecDeque<IoSrc<R::Id>>,
+    _phantom: PhantomData<fn(R)>,
+}
+
+impl<R: Actor, S: Scheduler<R>> PriorityScheduler<R, S> {
+    pub fn new(inner: S) -> Self {
+        PriorityScheduler {
+            inner,
+            priorities: empty!(),
+            events: empty!(),
+            _phantom: default!(),
+        }
+    }
+
+    fn priority(&self, id: &R::Id) -> u8 {
+        self.priorities.get(id).copied().unwrap_or(DEFAULT_PRIORITY)
+    }
+}
+
+impl<R: Actor, S: Scheduler<R>> Scheduler<R> for PriorityScheduler<R, S>
+where
+    R::Id: Send,
+{
+    fn has_actor(&self, id: &R::Id) -> bool {
+        self.inner.has_actor(id)
+    }
+
+    fn register_actor(&mut self, actor: &R) -> Result<(), R::Error> {
+        self.inner.register_actor(actor)
+    }
+
+    fn unregister_actor(&mut self, id: &R::Id) -> Result<(), R::Error> {
+        self.events.retain(|ev| ev.source != *id);
+        self.priorities.remove(id);
+        self.inner.unregister_actor(id)
+    }
+
+    fn set_interest(&mut self, id: &R::Id, interest: IoEv) -> Result<(), R::Error> {
+        self.inner.set_interest(id, interest)
+    }
+
+    fn set_priority(&mut self, id: &R::Id, priority: u8) {
+        self.priorities.insert(id.clone(), priority);
+        self.inner.set_priority(id, priority);
+    }
+
+    fn wait_io(&mut self, timeout: Option<Duration>) -> Result<bool, R::Error> {
+        // Events left by the runtime are returned before the new ones, so
+        // they are not reordered
+        let timed_out = self.inner.wait_io(timeout)?;
+        let mut batch = self.inner.by_ref().collect::<Vec<_>>();
+        // Sorting is stable, keeping the order of the same-priority events
+        batch.sort_by_key(|ev| Reverse(self.priority(&ev.source)));
+        self.events.extend(batch);
+        Ok(timed_out)
+    }
+
+    fn waker(&self) -> Arc<dyn Waker> {
+        self.inner.waker()
+    }
+
+    fn set_timer(&mut self, token: TimerToken, duration: Duration) -> Result<bool, R::Error> {
+        self.inner.set_timer(token, duration)
+    }
+
+    fn cancel_timer(&mut self, token: TimerToken) -> Result<(), R::Error> {
+        self.inner.cancel_timer(token)
+    }
+
+    fn fired_timers(&mut self, fired: &mut Vec<TimerToken>) {
+        self.inner.fired_timers(fired)
+    }
+
+    /// Returns statistics of the wrapped scheduler, which counts the events
+    /// once they are reordered.
+    fn io_stats(&self) -> HashMap<R::Id, IoStats> {
+        self.inner.io_stats()
+    }
+}
+
+impl<R: Actor, S: Scheduler<R>> Iterator for PriorityScheduler<R, S> {
+    type Item = IoSrc<R::Id>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.events.pop_front()
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::time::{Duration, Instant};
+
+use crate::actors::{IoEv, IoSrc};
+
+/// Duration of the time slots in which [`IoStats`] counts the events for
+/// [`IoStats::events_per_second`].
+const RATE_SLOT: Duration = Duration::from_millis(100);
+/// Number of the most recent time slots kept by [`IoStats`], limiting the
+/// window of [`IoStats::events_per_second`] to 10 seconds.
+const RATE_SLOTS: usize = 100;
+
+/// Counters of the I/O events delivered by a scheduler for a single actor (see
+/// [`crate::Scheduler::io_stats`]).
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub struct IoStats {
+    /// Number of the delivered events in which the actor is readable
+    pub readable_events: u64,
+
+    /// Number of the delivered events in which the actor is writable
+    pub writable_events: u64,
+
+    /// Time of the last delivered event; the time of the actor registration if
+    /// no events were delivered yet
+    pub last_event: Instant,
+
+    /// Time the slots are counted from
+    registered: Instant,
+
+    /// Number of the events delivered within each of the recent time slots,
+    /// indexed by the slot number modulo [`RATE_SLOTS`]
+    slots: [u32; RATE_SLOTS],
+}
+
+impl IoStats {
+    fn new() -> Self {
+        let now = Instant::now();
+        IoStats {
+            readable_events: 0,
+            writable_events: 0,
+            last_event: now,
+            registered: now,
+            slots: [0; RATE_SLOTS],
+        }
+    }
+
+    /// Returns number of the events delivered per second within the `window`
+    /// preceding the current moment.
+    ///
+    /// The window is rounded down to 100 ms and is limited to the range from
+    /// 100 ms to 10 seconds.
+    pub fn events_per_second(&self, window: Duration) -> f64 {
+        let window = window.clamp(RATE_SLOT, RATE_SLOT * RATE_SLOTS as u32);
+        let slots = (window.as_nanos() / RATE_SLOT.as_nanos()) as u64;
+        let now = self.slot_at(Instant::now());
+        let last = self.slot_at(self.last_event);
+        // Slots after the last event are empty, and the ones preceding the
+        // window are never older than the kept ones
+        let events = ((now + 1).saturating_sub(slots)..=last)
+            .map(|no| self.slots[no as usize % RATE_SLOTS] as u64)
+            .sum::<u64>();
+        events as f64 / (RATE_SLOT * slots as u32).as_secs_f64()
+    }
+
+    fn slot_at(&self, time: Instant) -> u64 {
+        (time.saturating_duration_since(self.registered).as_nanos() / RATE_SLOT.as_nanos()) as u64
+    }
+
+    fn record(&mut self, io: IoEv) {
+        let now = Instant::now();
+        let slot = self.slot_at(now);
+        let last = self.slot_at(self.last_event);
+        // Slots which have passed since the last event are reused
+        for no in (last + 1)..=slot.min(last + RATE_SLOTS as u64) {
+            self.slots[no as usize % RATE_SLOTS] = 0;
+        }
+        let count = &mut self.slots[slot as usize % RATE_SLOTS];
+        *count = count.saturating_add(1);
+
+        self.readable_events += io.is_readable as u64;
+        self.writable_events += io.is_writable as u64;
+        self.last_event = now;
+    }
+}
+
+/// [`IoStats`] of the actors registered with a scheduler.
+#[derive(Debug)]
+pub(crate) struct IoStatsMap<Id>(HashMap<Id, IoStats>);
+
+impl<Id> Default for IoStatsMap<Id> {
+    fn default() -> Self {
+        IoStatsMap(HashMap::new())
+    }
+}
+
+impl<Id: Clone + Eq + Hash> IoStatsMap<Id> {
+    /// Starts counting the events of the actor from zero.
+    pub fn register(&mut self, id: Id) {
+        self.0.insert(id, IoStats::new());
+    }
+
+    pub fn unregister(&mut self, id: &Id) {
+        self.0.remove(id);
+    }
+
+    /// Counts the event, if any, as delivered, passing it through.
+    pub fn deliver(&mut self, event: Option<IoSrc<Id>>) -> Option<IoSrc<Id>> {
+        if let Some(ev) = &event {
+            if let Some(stats) = self.0.get_mut(&ev.source) {
+                stats.record(ev.io);
+            }
+        }
+        event
+    }
+
+    pub fn snapshot(&self) -> HashMap<Id, IoStats> {
+        self.0.clone()
+    }
+}
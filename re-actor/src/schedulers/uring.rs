@@ -0,0 +1,384 @@
+use std::collections::{HashMap, VecDeque};
+use std::io;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::Arc;
+use std::time::Duration;
+
+use io_uring::{opcode, squeue, types, IoUring};
+
+use crate::actors::{IoEv, IoSrc};
+use crate::schedulers::{IoStats, IoStatsMap, PipeWaker, WakeReceiver, Waker};
+use crate::{Actor, Scheduler};
+
+/// Default number of entries in the submission queue.
+const DEFAULT_QUEUE_SIZE: u32 = 1024;
+
+/// User data for the completion of the waker poll requests.
+const WAKER_DATA: u64 = u64::MAX;
+/// User data for the completion of the poll removal requests.
+const REMOVE_DATA: u64 = u64::MAX - 1;
+
+/// Actor registered with [`UringScheduler`].
+struct Registration<Id> {
+    id: Id,
+    interest: IoEv,
+    /// Generation of the poll requests, which changes each time the interest
+    /// is changed, allowing to filter out completions of outdated requests.
+    generation: u32,
+}
+
+/// Constructs user data of poll requests for actor file descriptor.
+fn user_data(fd: RawFd, generation: u32) -> u64 {
+    (generation as u64) << 32 | fd as u32 as u64
+}
+
+fn poll_mask(interest: IoEv) -> u32 {
+    let mut mask = 0;
+    if interest.is_readable {
+        mask |= libc::POLLIN | libc::POLLRDHUP;
+    }
+    if interest.is_writable {
+        mask |= libc::POLLOUT;
+    }
+    mask as u32
+}
+
+/// Manager for a set of resources which are polled for an event loop by the
+/// re-actor by using Linux `io_uring` interface (via [`io_uring`] library).
+///
+/// Each actor is polled with one-shot `IORING_OP_POLL_ADD` requests, which
+/// are re-submitted once the actor's I/O event is reported to the runtime.
+pub struct UringScheduler<R>
+where
+    R: Actor,
+    R::Id: AsRawFd,
+{
+    ring: IoUring,
+    actors: HashMap<RawFd, Registration<R::Id>>,
+    events: VecDeque<IoSrc<R::Id>>,
+    stats: IoStatsMap<R::Id>,
+    fixed_buffers: Vec<Box<[u8]>>,
+    waker: Arc<PipeWaker>,
+    wake_recv: WakeReceiver,
+}
+
+impl<R> UringScheduler<R>
+where
+    R: Actor,
+    R::Id: AsRawFd,
+{
+    /// Constructs scheduler with the default size of the submission queue.
+    ///
+    /// Fails on kernels which do not support `io_uring` (before 5.1) or where
+    /// it is disabled by the system administrator; use
+    /// [`UringScheduler::is_supported`] to fall back to other schedulers in
+    /// such cases.
+    pub fn new() -> io::Result<Self> {
+        Self::with_queue_size(DEFAULT_QUEUE_SIZE)
+    }
+
+    /// Detects whether the running kernel allows to construct the scheduler.
+    pub fn is_supported() -> bool {
+        IoUring::new(2).is_ok()
+    }
+
+    /// Constructs scheduler with a custom size of the submission queue.
+    pub fn with_queue_size(queue_size: u32) -> io::Result<Self> {
+        let (waker, wake_recv) = PipeWaker::pair()?;
+        let mut scheduler = Self {
+            ring: IoUring::new(queue_size)?,
+            actors: empty!(),
+            events: empty!(),
+            stats: default!(),
+            fixed_buffers: empty!(),
+            waker,
+            wake_recv,
+        };
+        scheduler.poll_add(
+            scheduler.wake_recv.as_raw_fd(),
+            libc::POLLIN as u32,
+            WAKER_DATA,
+        )?;
+        Ok(scheduler)
+    }
+
+    /// Constructs scheduler pre-registering `count` buffers of `buf_size`
+    /// bytes with the kernel.
+    ///
+    /// The scheduler only registers the buffers and gives access to them via
+    /// [`UringScheduler::fixed_buffer`]; it does not submit any reads into
+    /// them, so actors still read from their file descriptors on their own.
+    pub fn with_fixed_buffers(count: usize, buf_size: usize) -> io::Result<Self> {
+        let mut scheduler = Self::new()?;
+        scheduler.fixed_buffers = (0..count)
+            .map(|_| vec![0u8; buf_size].into_boxed_slice())
+            .collect();
+        let iovecs = scheduler
+            .fixed_buffers
+            .iter_mut()
+            .map(|buf| libc::iovec {
+                iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+                iov_len: buf.len(),
+            })
+            .collect::<Vec<_>>();
+        // The buffers are owned by the scheduler and are never reallocated, so
+        // they outlive their registration with the ring.
+        unsafe { scheduler.ring.submitter().register_buffers(&iovecs)? };
+        Ok(scheduler)
+    }
+
+    /// Returns number of the buffers registered with
+    /// [`UringScheduler::with_fixed_buffers`].
+    pub fn fixed_buffer_count(&self) -> usize {
+        self.fixed_buffers.len()
+    }
+
+    /// Returns buffer registered under `index` with
+    /// [`UringScheduler::with_fixed_buffers`].
+    pub fn fixed_buffer(&mut self, index: usize) -> Option<&mut [u8]> {
+        self.fixed_buffers.get_mut(index).map(Box::as_mut)
+    }
+
+    fn poll_add(&mut self, fd: RawFd, mask: u32, user_data: u64) -> io::Result<()> {
+        let entry = opcode::PollAdd::new(types::Fd(fd), mask)
+            .build()
+            .user_data(user_data);
+        self.push(entry)
+    }
+
+    fn poll_remove(&mut self, user_data: u64) -> io::Result<()> {
+        let entry = opcode::PollRemove::new(user_data)
+            .build()
+            .user_data(REMOVE_DATA);
+        self.push(entry)
+    }
+
+    fn push(&mut self, entry: squeue::Entry) -> io::Result<()> {
+        // The entry does not reference any memory which may be released
+        // before the request completion.
+        if unsafe { self.ring.submission().push(&entry) }.is_err() {
+            self.ring.submit()?;
+            unsafe { self.ring.submission().push(&entry) }
+                .map_err(|_| io::Error::from(io::ErrorKind::WouldBlock))?;
+        }
+        Ok(())
+    }
+}
+
+impl<R> Scheduler<R> for UringScheduler<R>
+where
+    R: Actor,
+    R::Id: AsRawFd,
+    R::Error: From<io::Error>,
+{
+    fn has_actor(&self, id: &R::Id) -> bool {
+        self.actors.contains_key(&id.as_raw_fd())
+    }
+
+    fn register_actor(&mut self, resource: &R) -> Result<(), R::Error> {
+        let id = resource.id();
+        let interest = resource.interests();
+        let fd = id.as_raw_fd();
+        let mask = poll_mask(interest);
+        if mask != 0 {
+            self.poll_add(fd, mask, user_data(fd, 0))?;
+        }
+        self.stats.register(id.clone());
+        self.actors.insert(
+            fd,
+            Registration {
+                id,
+                interest,
+                generation: 0,
+            },
+        );
+        Ok(())
+    }
+
+    fn unregister_actor(&mut self, id: &R::Id) -> Result<(), R::Error> {
+        self.events.retain(|ev| ev.source != *id);
+        self.stats.unregister(id);
+        let fd = id.as_raw_fd();
+        if let Some(registration) = self.actors.remove(&fd) {
+            if poll_mask(registration.interest) != 0 {
+                self.poll_remove(user_data(fd, registration.generation))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn set_interest(&mut self, id: &R::Id, interest: IoEv) -> Result<(), R::Error> {
+        let fd = id.as_raw_fd();
+        let Some(registration) = self.actors.get_mut(&fd) else {
+            return Ok(());
+        };
+        if registration.interest == interest {
+            return Ok(());
+        }
+        let prev = user_data(fd, registration.generation);
+        let prev_mask = poll_mask(registration.interest);
+        registration.interest = interest;
+        registration.generation = registration.generation.wrapping_add(1);
+        let generation = registration.generation;
+
+        if prev_mask != 0 {
+            self.poll_remove(prev)?;
+        }
+        let mask = poll_mask(interest);
+        if mask != 0 {
+            self.poll_add(fd, mask, user_data(fd, generation))?;
+        }
+        Ok(())
+    }
+
+    fn wait_io(&mut self, timeout: Option<Duration>) -> Result<bool, R::Error> {
+        // Blocking call
+        let res = match timeout {
+            None => self.ring.submit_and_wait(1),
+            Some(timeout) => {
+                let ts = types::Timespec::new()
+                    .sec(timeout.as_secs())
+                    .nsec(timeout.subsec_nanos());
+                let args = types::SubmitArgs::new().timespec(&ts);
+                self.ring.submitter().submit_with_args(1, &args)
+            }
+        };
+        match res {
+            Ok(_) => {}
+            Err(err) if err.raw_os_error() == Some(libc::ETIME) => return Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => return Ok(true),
+            Err(err) => return Err(err.into()),
+        }
+
+        let completions = self
+            .ring
+            .completion()
+            .map(|cqe| (cqe.user_data(), cqe.result()))
+            .collect::<Vec<_>>();
+        let mut timed_out = true;
+        let mut error = None;
+        for (user_data, result) in completions {
+            match user_data {
+                REMOVE_DATA => {}
+                WAKER_DATA => {
+                    self.wake_recv.reset()?;
+                    self.poll_add(self.wake_recv.as_raw_fd(), libc::POLLIN as u32, WAKER_DATA)?;
+                }
+                data => {
+                    let fd = data as u32 as RawFd;
+                    // Completions for actors which were unregistered or which
+                    // interest has changed, including cancelled poll requests,
+                    // are ignored.
+                    let Some(registration) = self.actors.get(&fd) else {
+                        continue;
+                    };
+                    if registration.generation != (data >> 32) as u32 {
+                        continue;
+                    }
+                    if result < 0 {
+                        // The remaining completions still has to be processed
+                        error.get_or_insert(io::Error::from_raw_os_error(-result));
+                        continue;
+                    }
+                    let mask = result as i16;
+                    let interest = poll_mask(registration.interest);
+                    self.events.push_back(IoSrc {
+                        source: registration.id.clone(),
+                        io: IoEv {
+                            is_readable: mask & (libc::POLLIN | libc::POLLHUP | libc::POLLERR) != 0,
+                            is_writable: mask & libc::POLLOUT != 0,
+                            is_hangup: mask & (libc::POLLHUP | libc::POLLRDHUP) != 0,
+                            is_error: mask & libc::POLLERR != 0,
+                        },
+                    });
+                    timed_out = false;
+                    self.poll_add(fd, interest, data)?;
+                }
+            }
+        }
+
+        match error {
+            Some(err) => Err(err.into()),
+            None => Ok(timed_out),
+        }
+    }
+
+    fn waker(&self) -> Arc<dyn Waker> {
+        self.waker.clone()
+    }
+
+    fn io_stats(&self) -> HashMap<R::Id, IoStats> {
+        self.stats.snapshot()
+    }
+}
+
+impl<R> Iterator for UringScheduler<R>
+where
+    R: Actor,
+    R::Id: AsRawFd,
+{
+    type Item = IoSrc<R::Id>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.stats.deliver(self.events.pop_front())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reactor::tests::{
+        check_hangup, check_idle_connection, check_io_stats, check_unregister_pending, FdActor,
+    };
+
+    /// Constructs the scheduler, or returns `None` if `io_uring` is not
+    /// available in the running kernel, making the test a no-op.
+    fn scheduler() -> Option<UringScheduler<FdActor>> {
+        if !UringScheduler::<FdActor>::is_supported() {
+            eprintln!("io_uring is not supported by the kernel, skipping the test");
+            return None;
+        }
+        Some(UringScheduler::new().unwrap())
+    }
+
+    #[test]
+    fn idle_connection() {
+        let Some(mut scheduler) = scheduler() else {
+            return;
+        };
+        check_idle_connection(&mut scheduler);
+    }
+
+    #[test]
+    fn io_stats() {
+        let Some(mut scheduler) = scheduler() else {
+            return;
+        };
+        check_io_stats(&mut scheduler);
+    }
+
+    #[test]
+    fn kernel_support() {
+        let Some(_) = scheduler() else { return };
+        let mut scheduler = UringScheduler::<FdActor>::with_fixed_buffers(2, 64).unwrap();
+        assert_eq!(scheduler.fixed_buffer_count(), 2);
+        assert_eq!(scheduler.fixed_buffer(1).map(|buf| buf.len()), Some(64));
+        assert!(scheduler.fixed_buffer(2).is_none());
+    }
+
+    #[test]
+    fn hangup() {
+        let Some(mut scheduler) = scheduler() else {
+            return;
+        };
+        check_hangup(&mut scheduler);
+    }
+
+    #[test]
+    fn unregister_pending() {
+        let Some(mut scheduler) = scheduler() else {
+            return;
+        };
+        check_unregister_pending(&mut scheduler);
+    }
+}
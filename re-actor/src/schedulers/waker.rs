@@ -0,0 +1,61 @@
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+
+use super::Waker;
+
+/// Waker based on a non-blocking unix socket pair, which can be used by the
+/// schedulers which do not provide own waking mechanism. The scheduler must
+/// poll the [`WakeReceiver`] for read events, resetting it and filtering the
+/// events out from the events returned to the runtime.
+pub struct PipeWaker {
+    writer: UnixStream,
+}
+
+/// Receiving end of the [`PipeWaker`], which has to be polled by a scheduler.
+pub struct WakeReceiver {
+    reader: UnixStream,
+}
+
+impl PipeWaker {
+    /// Constructs waker together with its receiving end.
+    pub fn pair() -> io::Result<(Arc<PipeWaker>, WakeReceiver)> {
+        let (writer, reader) = UnixStream::pair()?;
+        writer.set_nonblocking(true)?;
+        reader.set_nonblocking(true)?;
+        Ok((Arc::new(PipeWaker { writer }), WakeReceiver { reader }))
+    }
+}
+
+impl Waker for PipeWaker {
+    fn wake(&self) -> io::Result<()> {
+        match (&self.writer).write(&[1]) {
+            // If the buffer is full the scheduler is going to be woken anyway
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(()),
+            Err(err) => Err(err),
+            Ok(_) => Ok(()),
+        }
+    }
+}
+
+impl WakeReceiver {
+    /// Drains all wake signals, so the receiver stops being readable.
+    pub fn reset(&self) -> io::Result<()> {
+        let mut buf = [0u8; 64];
+        loop {
+            match (&self.reader).read(&mut buf) {
+                Ok(0) => return Ok(()),
+                Ok(_) => continue,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+}
+
+impl AsRawFd for WakeReceiver {
+    fn as_raw_fd(&self) -> RawFd {
+        self.reader.as_raw_fd()
+    }
+}
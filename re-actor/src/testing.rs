@@ -0,0 +1,1261 @@
+//! Deterministic harness for testing actors without threads, sockets and
+//! sleeps.
+//!
+//! [`TestScheduler`] runs actors over in-memory byte streams ([`TestStream`])
+//! and reports I/O events only when the test asks for them.
+//! [`TestRunner`] drives the actors of a pool one [`TestRunner::step`] at a
+//! time instead of the blocking event loop of the re-actor, checking actor
+//! deadlines, timers and other timeouts against a [`VirtualClock`] which is
+//! advanced by the test.
+
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crossbeam_channel as chan;
+
+use crate::actors::{IoEv, IoSrc};
+use crate::reactor::{ContextFactory, ControlEvent, QueryKind, QueryResponse};
+use crate::schedulers::Waker;
+use crate::{
+    Actor, Controller, InternalError, Layout, ReactorMetrics, Scheduler, TimeoutManager, TimerToken,
+};
+
+/// Sequence used to generate unique ids of the streams.
+static STREAM_SEQ: AtomicU64 = AtomicU64::new(0);
+
+/// Clock which stands still until it is advanced by the test.
+///
+/// The clock converts into [`Instant`], so it can be passed to the
+/// [`TimeoutManager`] methods instead of the current time:
+///
+/// ```
+/// use std::time::Duration;
+/// use re_actor::testing::VirtualClock;
+/// use re_actor::TimeoutManager;
+///
+/// let clock = VirtualClock::new();
+/// let mut tm = TimeoutManager::new(Duration::ZERO);
+/// tm.register(0xA, clock.now() + Duration::from_secs(10));
+///
+/// let mut fired = vec![];
+/// assert_eq!(tm.check(&clock, &mut fired), 0);
+/// assert_eq!(tm.next(&clock), Some(Duration::from_secs(10)));
+///
+/// clock.advance(Duration::from_secs(10));
+/// assert_eq!(tm.check(&clock, &mut fired), 1);
+/// assert_eq!(fired, vec![0xA]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct VirtualClock(Arc<Mutex<Instant>>);
+
+impl Default for VirtualClock {
+    fn default() -> Self {
+        VirtualClock(Arc::new(Mutex::new(Instant::now())))
+    }
+}
+
+impl VirtualClock {
+    /// Constructs clock starting at the current time. Clones of the clock
+    /// share the time.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns time shown by the clock.
+    pub fn now(&self) -> Instant {
+        *self.0.lock().expect("clock lock is poisoned")
+    }
+
+    /// Moves the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().expect("clock lock is poisoned") += duration;
+    }
+}
+
+impl From<&VirtualClock> for Instant {
+    fn from(clock: &VirtualClock) -> Self {
+        clock.now()
+    }
+}
+
+#[derive(Debug, Default)]
+struct StreamBuffers {
+    input: VecDeque<u8>,
+    output: Vec<u8>,
+    is_closed: bool,
+}
+
+/// In-memory byte stream connecting an actor with the test: the data fed by
+/// the test are read by the actor, and the data written by the actor are
+/// collected for the test to inspect. Clones of the stream share the data.
+///
+/// Like a non-blocking socket, the stream fails with
+/// [`io::ErrorKind::WouldBlock`] when there is nothing to read, unless it is
+/// closed with [`TestStream::close`], in which case it reports the end of
+/// stream.
+#[derive(Clone, Debug)]
+pub struct TestStream {
+    id: u64,
+    buffers: Arc<Mutex<StreamBuffers>>,
+}
+
+impl Default for TestStream {
+    fn default() -> Self {
+        TestStream {
+            id: STREAM_SEQ.fetch_add(1, Ordering::Relaxed),
+            buffers: default!(),
+        }
+    }
+}
+
+impl TestStream {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns unique id of the stream, which can be used as the actor id.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Appends data to be read by the actor.
+    pub fn feed(&self, data: &[u8]) {
+        self.buffers
+            .lock()
+            .expect("stream lock is poisoned")
+            .input
+            .extend(data);
+    }
+
+    /// Closes the stream, such that the actor reads the end of stream once it
+    /// has read all the fed data.
+    pub fn close(&self) {
+        self.buffers
+            .lock()
+            .expect("stream lock is poisoned")
+            .is_closed = true;
+    }
+
+    /// Takes the data written by the actor since the previous call.
+    pub fn take_written(&self) -> Vec<u8> {
+        let mut buffers = self.buffers.lock().expect("stream lock is poisoned");
+        std::mem::take(&mut buffers.output)
+    }
+}
+
+impl Read for TestStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut buffers = self.buffers.lock().expect("stream lock is poisoned");
+        if buffers.input.is_empty() && !buffers.is_closed {
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        buffers.input.read(buf)
+    }
+}
+
+impl Write for TestStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut buffers = self.buffers.lock().expect("stream lock is poisoned");
+        if buffers.is_closed {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        buffers.output.extend(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Actors which are run by a [`TestScheduler`], providing access to their
+/// stream.
+pub trait AsTestStream {
+    /// Returns stream the actor performs its I/O on.
+    fn test_stream(&self) -> &TestStream;
+}
+
+impl AsTestStream for TestStream {
+    fn test_stream(&self) -> &TestStream {
+        self
+    }
+}
+
+/// Waker of the [`TestScheduler`], which never blocks.
+struct NoopWaker;
+
+impl Waker for NoopWaker {
+    fn wake(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Actor registered with a [`TestScheduler`].
+struct TestSource<Id> {
+    id: Id,
+    stream: TestStream,
+    interest: IoEv,
+    /// Events requested by the test which are not reported yet.
+    ready: IoEv,
+}
+
+/// Scheduler which reports I/O events only when they are requested by the
+/// test, such that the actors run deterministically.
+///
+/// Each requested event is reported once, by the first
+/// [`Scheduler::wait_io`] call made while the actor is interested in it.
+/// Hang-ups are reported regardless of the interest. Events are reported in
+/// the order of the actor registration. [`Scheduler::wait_io`] never blocks,
+/// timing out at once if there are no events.
+pub struct TestScheduler<R: Actor> {
+    actors: Vec<TestSource<R::Id>>,
+    events: VecDeque<IoSrc<R::Id>>,
+}
+
+impl<R: Actor> Default for TestScheduler<R> {
+    fn default() -> Self {
+        TestScheduler {
+            actors: empty!(),
+            events: empty!(),
+        }
+    }
+}
+
+impl<R: Actor> TestScheduler<R> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// # Panics
+    ///
+    /// If the actor is not registered with the scheduler.
+    fn source_mut(&mut self, id: &R::Id) -> &mut TestSource<R::Id> {
+        self.actors
+            .iter_mut()
+            .find(|source| source.id == *id)
+            .unwrap_or_else(|| panic!("actor {id} is not registered with the test scheduler"))
+    }
+
+    /// Feeds data to the actor stream and makes the actor readable.
+    ///
+    /// # Panics
+    ///
+    /// If the actor is not registered with the scheduler.
+    pub fn feed(&mut self, id: &R::Id, data: &[u8]) {
+        let source = self.source_mut(id);
+        source.stream.feed(data);
+        source.ready.is_readable = true;
+    }
+
+    /// Makes the actor readable without feeding any data.
+    ///
+    /// # Panics
+    ///
+    /// If the actor is not registered with the scheduler.
+    pub fn make_readable(&mut self, id: &R::Id) {
+        self.source_mut(id).ready.is_readable = true;
+    }
+
+    /// Makes the actor writable.
+    ///
+    /// # Panics
+    ///
+    /// If the actor is not registered with the scheduler.
+    pub fn make_writable(&mut self, id: &R::Id) {
+        self.source_mut(id).ready.is_writable = true;
+    }
+
+    /// Closes the actor stream and reports a hang-up of the remote peer.
+    ///
+    /// # Panics
+    ///
+    /// If the actor is not registered with the scheduler.
+    pub fn hang_up(&mut self, id: &R::Id) {
+        let source = self.source_mut(id);
+        source.stream.close();
+        source.ready.is_hangup = true;
+    }
+
+    /// Takes the data written by the actor since the previous call.
+    ///
+    /// # Panics
+    ///
+    /// If the actor is not registered with the scheduler.
+    pub fn written(&mut self, id: &R::Id) -> Vec<u8> {
+        self.source_mut(id).stream.take_written()
+    }
+}
+
+impl<R> Scheduler<R> for TestScheduler<R>
+where
+    R: Actor + AsTestStream,
+    R::Id: Send,
+{
+    fn has_actor(&self, id: &R::Id) -> bool {
+        self.actors.iter().any(|source| source.id == *id)
+    }
+
+    fn register_actor(&mut self, actor: &R) -> Result<(), R::Error> {
+        let id = actor.id();
+        self.actors.retain(|source| source.id != id);
+        self.actors.push(TestSource {
+            id,
+            stream: actor.test_stream().clone(),
+            interest: actor.interests(),
+            ready: IoEv {
+                is_readable: false,
+                is_writable: false,
+                is_hangup: false,
+                is_error: false,
+            },
+        });
+        Ok(())
+    }
+
+    fn unregister_actor(&mut self, id: &R::Id) -> Result<(), R::Error> {
+        self.events.retain(|ev| ev.source != *id);
+        self.actors.retain(|source| source.id != *id);
+        Ok(())
+    }
+
+    fn set_interest(&mut self, id: &R::Id, interest: IoEv) -> Result<(), R::Error> {
+        if let Some(source) = self.actors.iter_mut().find(|source| source.id == *id) {
+            source.interest = interest;
+        }
+        Ok(())
+    }
+
+    fn wait_io(&mut self, _timeout: Option<Duration>) -> Result<bool, R::Error> {
+        for source in &mut self.actors {
+            let io = IoEv {
+                is_readable: source.ready.is_readable && source.interest.is_readable,
+                is_writable: source.ready.is_writable && source.interest.is_writable,
+                is_hangup: source.ready.is_hangup,
+                is_error: source.ready.is_error,
+            };
+            if !(io.is_readable || io.is_writable || io.is_hangup || io.is_error) {
+                continue;
+            }
+            source.ready.is_readable &= !io.is_readable;
+            source.ready.is_writable &= !io.is_writable;
+            source.ready.is_hangup = false;
+            source.ready.is_error = false;
+            self.events.push_back(IoSrc {
+                source: source.id.clone(),
+                io,
+            });
+        }
+        Ok(self.events.is_empty())
+    }
+
+    fn waker(&self) -> Arc<dyn Waker> {
+        Arc::new(NoopWaker)
+    }
+}
+
+impl<R: Actor> Iterator for TestScheduler<R> {
+    type Item = IoSrc<R::Id>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.events.pop_front()
+    }
+}
+
+/// Reconnection which is waiting for its next attempt on the
+/// [`VirtualClock`].
+struct PendingReconnect<A: Actor> {
+    deadline: Instant,
+    context: ContextFactory<A>,
+    backoff: Duration,
+    multiplier: f64,
+    remaining: u32,
+}
+
+/// Runs actors of a single pool within the test thread, using
+/// [`TestScheduler`] for their I/O and [`VirtualClock`] for their deadlines.
+///
+/// Nothing happens until the test calls [`TestRunner::step`], which
+/// processes the control events sent via the [`TestRunner::controller`],
+/// calls [`Actor::on_deadline`] for the actors whose deadlines have passed on
+/// the clock, and dispatches the I/O events requested from the scheduler.
+///
+/// Commands sent to the actors run by the runner are delivered to them;
+/// commands sent to the actors registered with
+/// [`TestRunner::register_remote`] are collected and returned by
+/// [`TestRunner::take_sent`]. Timers which would be reported to the
+/// [`Handler`] are returned by [`TestRunner::take_timers`], and errors by
+/// [`TestRunner::take_errors`].
+///
+/// The runner supports all control events. Timers, graceful disconnection
+/// timeouts and reconnection backoff are measured with the clock. Requests
+/// which wait for the response, like queries, broadcasts and disconnection of
+/// all actors, must be made from another thread, since the controller blocks
+/// until the response is sent by [`TestRunner::step`].
+///
+/// [`Handler`]: crate::Handler
+pub struct TestRunner<L: Layout>
+where
+    L::RootActor: AsTestStream,
+{
+    pool: L,
+    controller: Controller<L>,
+    control_recv: chan::Receiver<ControlEvent<L::RootActor>>,
+    scheduler: TestScheduler<L::RootActor>,
+    clock: VirtualClock,
+    actors: HashMap<<L::RootActor as Actor>::Id, L::RootActor>,
+    /// Actors which are being gracefully disconnected and are writing out
+    /// their pending output.
+    draining: HashMap<<L::RootActor as Actor>::Id, L::RootActor>,
+    deadlines: TimeoutManager<<L::RootActor as Actor>::Id>,
+    drain_timeouts: TimeoutManager<<L::RootActor as Actor>::Id>,
+    timers: TimeoutManager<TimerToken>,
+    reconnects: Vec<PendingReconnect<L::RootActor>>,
+    sent: Vec<(<L::RootActor as Actor>::Id, <L::RootActor as Actor>::Cmd)>,
+    fired: Vec<TimerToken>,
+    errors: Vec<InternalError<L>>,
+}
+
+impl<L: Layout> TestRunner<L>
+where
+    L::RootActor: AsTestStream,
+{
+    /// Constructs runner for the actors of the `pool`.
+    pub fn new(pool: L) -> Self {
+        let (control_send, control_recv) = chan::unbounded();
+        let mut controller = Controller::new();
+        controller
+            .register_pool(pool, control_send, Arc::new(NoopWaker))
+            .expect("controller has no pools");
+        TestRunner {
+            pool,
+            controller,
+            control_recv,
+            scheduler: TestScheduler::new(),
+            clock: VirtualClock::new(),
+            actors: empty!(),
+            draining: empty!(),
+            deadlines: TimeoutManager::new(Duration::ZERO),
+            drain_timeouts: TimeoutManager::new(Duration::ZERO),
+            timers: TimeoutManager::new(Duration::ZERO),
+            reconnects: empty!(),
+            sent: empty!(),
+            fired: empty!(),
+            errors: empty!(),
+        }
+    }
+
+    /// Returns controller which can be used to construct actors and to send
+    /// control events to the runner.
+    pub fn controller(&self) -> Controller<L> {
+        self.controller.clone()
+    }
+
+    /// Returns clock used for checking actor deadlines.
+    pub fn clock(&self) -> &VirtualClock {
+        &self.clock
+    }
+
+    /// Returns scheduler, which allows to drive actor I/O.
+    pub fn scheduler(&mut self) -> &mut TestScheduler<L::RootActor> {
+        &mut self.scheduler
+    }
+
+    /// Returns actor run by the runner.
+    pub fn actor(&self, id: &<L::RootActor as Actor>::Id) -> Option<&L::RootActor> {
+        self.actors.get(id)
+    }
+
+    /// Returns actor which is being gracefully disconnected.
+    pub fn draining_actor(&self, id: &<L::RootActor as Actor>::Id) -> Option<&L::RootActor> {
+        self.draining.get(id)
+    }
+
+    /// Constructs actor from the context and starts running it.
+    pub fn start_actor(
+        &mut self,
+        context: <L::RootActor as Actor>::Context,
+    ) -> Result<<L::RootActor as Actor>::Id, InternalError<L>> {
+        let actor = L::RootActor::with(context, self.controller.clone())
+            .map_err(|err| InternalError::ActorError(self.pool, err))?;
+        let id = actor.id();
+        self.insert_actor(actor)?;
+        Ok(id)
+    }
+
+    /// Starts running already constructed actor.
+    pub fn insert_actor(&mut self, actor: L::RootActor) -> Result<(), InternalError<L>> {
+        let id = actor.id();
+        self.controller.register_actor(id.clone(), self.pool)?;
+        if let Err(err) = self.scheduler.register_actor(&actor) {
+            self.controller.unregister_actor(&id);
+            return Err(InternalError::ActorError(self.pool, err));
+        }
+        if let Some(deadline) = actor.deadline() {
+            self.deadlines.register(id.clone(), deadline);
+        }
+        self.actors.insert(id, actor);
+        Ok(())
+    }
+
+    /// Makes the controller to accept commands for an actor which is not run
+    /// by the runner, such that the actors may send commands to it. The
+    /// commands are returned by [`TestRunner::take_sent`].
+    pub fn register_remote(
+        &mut self,
+        id: <L::RootActor as Actor>::Id,
+    ) -> Result<(), InternalError<L>> {
+        self.controller.register_actor(id, self.pool)
+    }
+
+    /// Takes the commands sent to the actors registered with
+    /// [`TestRunner::register_remote`] since the previous call.
+    pub fn take_sent(
+        &mut self,
+    ) -> Vec<(<L::RootActor as Actor>::Id, <L::RootActor as Actor>::Cmd)> {
+        std::mem::take(&mut self.sent)
+    }
+
+    /// Takes the timers which have fired since the previous call.
+    pub fn take_timers(&mut self) -> Vec<TimerToken> {
+        std::mem::take(&mut self.fired)
+    }
+
+    /// Takes the errors which has happened since the previous call.
+    pub fn take_errors(&mut self) -> Vec<InternalError<L>> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Processes the pending control events, the reconnection attempts, the
+    /// timers, the expired deadlines and the I/O events requested from the
+    /// scheduler.
+    ///
+    /// # Returns
+    ///
+    /// Number of the processed events; zero if there was nothing to do.
+    pub fn step(&mut self) -> usize {
+        let mut processed = 0;
+        while let Ok(event) = self.control_recv.try_recv() {
+            processed += 1;
+            self.process_control(event);
+        }
+
+        let now = self.clock.now();
+        processed += self.process_reconnects(now);
+        processed += self.timers.check(now, &mut self.fired);
+
+        let mut expired = vec![];
+        processed += self.drain_timeouts.check(now, &mut expired);
+        for id in expired {
+            self.stop_actor(&id);
+        }
+
+        let mut expired = vec![];
+        self.deadlines.check(now, &mut expired);
+        for id in expired {
+            let Some(actor) = self.actors.get_mut(&id) else {
+                continue;
+            };
+            match actor.deadline() {
+                None => continue,
+                Some(deadline) if deadline > now => {
+                    self.deadlines.register(id, deadline);
+                    continue;
+                }
+                Some(_) => {}
+            }
+            processed += 1;
+            if let Err(err) = actor.on_deadline().or_else(|err| actor.handle_err(err)) {
+                self.errors.push(InternalError::ActorError(self.pool, err));
+                self.stop_actor(&id);
+            }
+        }
+
+        self.scheduler
+            .wait_io(Some(Duration::ZERO))
+            .expect("test scheduler never fails");
+        while let Some(ev) = self.scheduler.next() {
+            processed += 1;
+            if self.draining.contains_key(&ev.source) {
+                self.process_draining_io(ev);
+                continue;
+            }
+            let Some(actor) = self.actors.get_mut(&ev.source) else {
+                continue;
+            };
+            if let Err(err) = actor.io_ready(ev.io).or_else(|err| actor.handle_err(err)) {
+                self.errors.push(InternalError::ActorError(self.pool, err));
+            }
+            if ev.io.is_hangup || ev.io.is_error {
+                // The connection is already closed, so the actor is just
+                // removed
+                self.remove_actor(&ev.source);
+            } else {
+                self.update_interest(&ev.source);
+            }
+        }
+        processed
+    }
+
+    /// Dispatches I/O event to an actor which is being gracefully
+    /// disconnected, disconnecting it once it has written out all of its
+    /// output or has failed.
+    fn process_draining_io(&mut self, ev: IoSrc<<L::RootActor as Actor>::Id>) {
+        let Some(actor) = self.draining.get_mut(&ev.source) else {
+            return;
+        };
+        let res = actor.io_ready(ev.io).or_else(|err| actor.handle_err(err));
+        let is_done = res.is_err() || !actor.has_pending_output();
+        if let Err(err) = res {
+            self.errors.push(InternalError::ActorError(self.pool, err));
+        }
+        if ev.io.is_hangup || ev.io.is_error {
+            self.remove_draining(&ev.source);
+        } else if is_done {
+            self.stop_actor(&ev.source);
+        } else if let Some(actor) = self.draining.get(&ev.source) {
+            let interest = actor.interests();
+            self.scheduler
+                .set_interest(&ev.source, interest)
+                .expect("test scheduler never fails");
+        }
+    }
+
+    /// Makes the reconnection attempts which are due at `now`, returning the
+    /// number of the attempts.
+    fn process_reconnects(&mut self, now: Instant) -> usize {
+        let (due, pending): (Vec<_>, Vec<_>) = mem::take(&mut self.reconnects)
+            .into_iter()
+            .partition(|reconnect| reconnect.deadline <= now);
+        self.reconnects = pending;
+        let attempts = due.len();
+        for mut reconnect in due {
+            reconnect.remaining -= 1;
+            let err = match L::RootActor::with((reconnect.context)(), self.controller.clone()) {
+                Ok(actor) => match self.insert_actor(actor) {
+                    Ok(_) => continue,
+                    Err(err) => {
+                        self.errors.push(err);
+                        None
+                    }
+                },
+                Err(err) => Some(err),
+            };
+            if reconnect.remaining > 0 {
+                reconnect.backoff = reconnect.backoff.mul_f64(reconnect.multiplier);
+                reconnect.deadline = now + reconnect.backoff;
+                self.reconnects.push(reconnect);
+            } else if let Some(err) = err {
+                self.errors
+                    .push(InternalError::ReconnectExhausted(self.pool, err));
+            }
+        }
+        attempts
+    }
+
+    fn process_control(&mut self, event: ControlEvent<L::RootActor>) {
+        match event {
+            ControlEvent::Connect(context) => {
+                if let Err(err) = self.start_actor(context) {
+                    self.errors.push(err);
+                }
+            }
+            ControlEvent::ConnectReport(context, callback) => callback(self.start_actor(context)),
+            ControlEvent::Insert(provider) => {
+                if let Err(err) = self.insert_actor(provider()) {
+                    self.errors.push(err);
+                }
+            }
+            ControlEvent::Disconnect(id) => self.stop_actor(&id),
+            ControlEvent::DisconnectGraceful(id, timeout) => {
+                match self.actors.get(&id).map(Actor::has_pending_output) {
+                    Some(true) => {
+                        let actor = self.actors.remove(&id).expect("actor is present");
+                        self.deadlines.cancel(&id);
+                        self.drain_timeouts
+                            .register(id.clone(), self.clock.now() + timeout);
+                        self.draining.insert(id, actor);
+                    }
+                    Some(false) | None => self.stop_actor(&id),
+                }
+            }
+            ControlEvent::HalfClose(id) => match self.actors.get_mut(&id) {
+                Some(actor) => {
+                    if let Err(err) = actor.shutdown_write().or_else(|err| actor.handle_err(err)) {
+                        self.errors.push(InternalError::ActorError(self.pool, err));
+                    }
+                    self.update_interest(&id);
+                }
+                None => self.errors.push(InternalError::UnknownActor(id)),
+            },
+            ControlEvent::DisconnectAll(reply) => {
+                let ids = self
+                    .actors
+                    .keys()
+                    .chain(self.draining.keys())
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let count = ids.len();
+                for id in ids {
+                    self.stop_actor(&id);
+                }
+                // The requester may have already timed out and dropped the receiver
+                let _ = reply.send(count);
+            }
+            ControlEvent::Take(id, callback) => match self.remove_actor(&id) {
+                Some(actor) => callback(actor),
+                None => self.errors.push(InternalError::UnknownActor(id)),
+            },
+            ControlEvent::Replace(id, provider, callback) => {
+                let Some(replaced) = self.remove_actor(&id) else {
+                    self.errors.push(InternalError::UnknownActor(id));
+                    return;
+                };
+                match self.insert_actor(provider()) {
+                    Ok(()) => callback(replaced),
+                    Err(err) => {
+                        self.errors.push(err);
+                        if let Err(err) = self.insert_actor(replaced) {
+                            self.errors.push(err);
+                        }
+                    }
+                }
+            }
+            ControlEvent::Migrate(id, target, snapshot, restore) => {
+                let Some(actor) = self.actors.get(&id) else {
+                    self.errors.push(InternalError::UnknownActor(id));
+                    return;
+                };
+                let snapshot = match snapshot(actor) {
+                    Ok(snapshot) => snapshot,
+                    Err(err) => {
+                        self.errors.push(InternalError::ActorError(self.pool, err));
+                        return;
+                    }
+                };
+                let actor = self.remove_actor(&id).expect("actor is present");
+                match target.send_event(self.pool, ControlEvent::Restore(snapshot, restore)) {
+                    Ok(()) => self.disconnect(actor),
+                    Err(err) => {
+                        self.errors.push(err);
+                        if let Err(err) = self.insert_actor(actor) {
+                            self.errors.push(err);
+                        }
+                    }
+                }
+            }
+            ControlEvent::Restore(snapshot, restore) => {
+                match restore(snapshot, self.controller.clone()) {
+                    Ok(actor) => {
+                        if let Err(err) = self.insert_actor(actor) {
+                            self.errors.push(err);
+                        }
+                    }
+                    Err(err) => self.errors.push(InternalError::ActorError(self.pool, err)),
+                }
+            }
+            ControlEvent::Reconnect {
+                id: _,
+                context,
+                backoff,
+                multiplier,
+                remaining,
+            } => {
+                if remaining > 0 {
+                    self.reconnects.push(PendingReconnect {
+                        deadline: self.clock.now() + backoff,
+                        context,
+                        backoff,
+                        multiplier,
+                        remaining,
+                    });
+                }
+            }
+            ControlEvent::SetTimer(token, deadline) => {
+                // Deadline is set by the controller with the system time
+                let duration = deadline.saturating_duration_since(Instant::now());
+                self.timers.register(token, self.clock.now() + duration);
+            }
+            ControlEvent::CancelTimer(token) => {
+                self.timers.cancel(&token);
+            }
+            ControlEvent::Send(id, cmd) => match self.actors.get_mut(&id) {
+                Some(actor) => {
+                    if let Err(err) = actor.handle_cmd(cmd).or_else(|err| actor.handle_err(err)) {
+                        self.errors.push(InternalError::ActorError(self.pool, err));
+                    }
+                    self.update_interest(&id);
+                }
+                None => self.sent.push((id, cmd)),
+            },
+            ControlEvent::Broadcast(cmd, filter, reply) => {
+                let ids = self
+                    .actors
+                    .keys()
+                    .filter(|id| filter.as_ref().map(|filter| filter(id)).unwrap_or(true))
+                    .cloned()
+                    .collect::<Vec<_>>();
+                let count = ids.len();
+                for id in ids {
+                    let actor = self.actors.get_mut(&id).expect("actor is present");
+                    if let Err(err) = actor
+                        .handle_cmd(cmd.clone())
+                        .or_else(|err| actor.handle_err(err))
+                    {
+                        self.errors.push(InternalError::ActorError(self.pool, err));
+                    }
+                    self.update_interest(&id);
+                }
+                // The requester may have already timed out and dropped the receiver
+                let _ = reply.send(count);
+            }
+            ControlEvent::Query(kind, reply) => {
+                let response = match kind {
+                    QueryKind::Count => QueryResponse::Count(self.actors.len()),
+                    QueryKind::Contains(id) => {
+                        QueryResponse::Contains(self.actors.contains_key(&id))
+                    }
+                    QueryKind::List => QueryResponse::List(self.actors.keys().cloned().collect()),
+                    QueryKind::Metrics => QueryResponse::Metrics(ReactorMetrics {
+                        actors: self.actors.len(),
+                        draining: self.draining.len(),
+                        ..default!()
+                    }),
+                    QueryKind::IoStats => QueryResponse::IoStats(empty!()),
+                };
+                // The requester may have already timed out and dropped the receiver
+                let _ = reply.send(response);
+            }
+        }
+    }
+
+    fn update_interest(&mut self, id: &<L::RootActor as Actor>::Id) {
+        if let Some(actor) = self.actors.get(id) {
+            let interest = actor.interests();
+            self.scheduler
+                .set_interest(id, interest)
+                .expect("test scheduler never fails");
+        }
+    }
+
+    /// Disconnects actor and removes it from the runner. Actor which is being
+    /// gracefully disconnected does not wait for its output anymore.
+    fn stop_actor(&mut self, id: &<L::RootActor as Actor>::Id) {
+        let Some(actor) = self.remove_actor(id).or_else(|| self.remove_draining(id)) else {
+            self.errors.push(InternalError::UnknownActor(id.clone()));
+            return;
+        };
+        self.disconnect(actor);
+    }
+
+    /// Disconnects actor which is already removed from the runner.
+    fn disconnect(&mut self, mut actor: L::RootActor) {
+        if let Err(err) = actor.disconnect().or_else(|err| actor.handle_err(err)) {
+            self.errors.push(InternalError::ActorError(self.pool, err));
+        }
+    }
+
+    fn remove_draining(&mut self, id: &<L::RootActor as Actor>::Id) -> Option<L::RootActor> {
+        let actor = self.draining.remove(id)?;
+        self.controller.unregister_actor(id);
+        self.drain_timeouts.cancel(id);
+        self.scheduler
+            .unregister_actor(id)
+            .expect("test scheduler never fails");
+        Some(actor)
+    }
+
+    fn remove_actor(&mut self, id: &<L::RootActor as Actor>::Id) -> Option<L::RootActor> {
+        let actor = self.actors.remove(id)?;
+        self.controller.unregister_actor(id);
+        self.deadlines.cancel(id);
+        self.scheduler
+            .unregister_actor(id)
+            .expect("test scheduler never fails");
+        Some(actor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::any::Any;
+
+    use std::thread;
+
+    use super::*;
+    use crate::{Pool, ReactorApi};
+
+    /// Id of the actor which is notified about the completed handshakes.
+    const SESSIONS: u64 = u64::MAX;
+
+    const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+    #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+    #[display(Debug)]
+    enum TestPool {
+        Main,
+    }
+
+    impl From<u32> for TestPool {
+        fn from(_: u32) -> Self {
+            TestPool::Main
+        }
+    }
+
+    impl From<TestPool> for u32 {
+        fn from(_: TestPool) -> Self {
+            0
+        }
+    }
+
+    impl Layout for TestPool {
+        type RootActor = Handshake;
+
+        fn default_pools() -> Vec<Pool<Self::RootActor, Self>> {
+            vec![]
+        }
+
+        fn convert(_: Box<dyn Any>) -> (TestStream, Instant) {
+            unreachable!()
+        }
+    }
+
+    /// Actor expecting `HELLO\n` from the remote peer before the deadline, to
+    /// which it replies with `WELCOME\n` and notifies the [`SESSIONS`] actor.
+    /// Commands are queued and written to the stream as they are once it
+    /// becomes writable.
+    struct Handshake {
+        stream: TestStream,
+        received: Vec<u8>,
+        outbox: Vec<u8>,
+        deadline: Instant,
+        is_complete: bool,
+        controller: Controller<TestPool>,
+    }
+
+    impl AsTestStream for Handshake {
+        fn test_stream(&self) -> &TestStream {
+            &self.stream
+        }
+    }
+
+    impl Actor for Handshake {
+        type Layout = TestPool;
+        type Id = u64;
+        type Context = (TestStream, Instant);
+        type Cmd = Vec<u8>;
+        type Error = io::Error;
+
+        fn with(
+            (stream, deadline): Self::Context,
+            controller: Controller<TestPool>,
+        ) -> Result<Self, Self::Error> {
+            Ok(Handshake {
+                stream,
+                received: vec![],
+                outbox: vec![],
+                deadline,
+                is_complete: false,
+                controller,
+            })
+        }
+
+        fn id(&self) -> Self::Id {
+            self.stream.id()
+        }
+
+        fn io_ready(&mut self, io: IoEv) -> Result<(), Self::Error> {
+            if io.is_writable {
+                self.stream.write_all(&mem::take(&mut self.outbox))?;
+            }
+            if !io.is_readable || self.is_complete {
+                return Ok(());
+            }
+            let mut buf = [0u8; 16];
+            loop {
+                match self.stream.read(&mut buf) {
+                    Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+                    Ok(len) => self.received.extend(&buf[..len]),
+                    Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(err) => return Err(err),
+                }
+            }
+            if !b"HELLO\n".starts_with(&self.received) {
+                return Err(io::ErrorKind::InvalidData.into());
+            }
+            if self.received == b"HELLO\n" {
+                self.is_complete = true;
+                self.stream.write_all(b"WELCOME\n")?;
+                self.controller
+                    .send(SESSIONS, self.id().to_be_bytes().to_vec())
+                    .map_err(|_| io::ErrorKind::NotConnected)?;
+            }
+            Ok(())
+        }
+
+        fn handle_cmd(&mut self, cmd: Self::Cmd) -> Result<(), Self::Error> {
+            self.outbox.extend(cmd);
+            Ok(())
+        }
+
+        fn handle_err(&mut self, err: Self::Error) -> Result<(), Self::Error> {
+            Err(err)
+        }
+
+        fn deadline(&self) -> Option<Instant> {
+            Some(self.deadline).filter(|_| !self.is_complete)
+        }
+
+        fn on_deadline(&mut self) -> Result<(), Self::Error> {
+            Err(io::ErrorKind::TimedOut.into())
+        }
+
+        fn has_pending_output(&self) -> bool {
+            !self.outbox.is_empty()
+        }
+
+        fn interests(&self) -> IoEv {
+            IoEv {
+                is_readable: true,
+                is_writable: self.has_pending_output(),
+                is_hangup: false,
+                is_error: false,
+            }
+        }
+
+        fn disconnect(&mut self) -> Result<(), Self::Error> {
+            self.stream.close();
+            Ok(())
+        }
+    }
+
+    fn runner() -> TestRunner<TestPool> {
+        let mut runner = TestRunner::new(TestPool::Main);
+        runner.register_remote(SESSIONS).unwrap();
+        runner
+    }
+
+    fn start(runner: &mut TestRunner<TestPool>) -> u64 {
+        let deadline = runner.clock().now() + HANDSHAKE_TIMEOUT;
+        runner.start_actor((TestStream::new(), deadline)).unwrap()
+    }
+
+    #[test]
+    fn scripted_handshake() {
+        let mut runner = runner();
+        let id = start(&mut runner);
+        assert_eq!(runner.step(), 0);
+
+        // Handshake message may arrive in pieces
+        runner.scheduler().feed(&id, b"HEL");
+        assert_eq!(runner.step(), 1);
+        assert!(runner.scheduler().written(&id).is_empty());
+        assert!(runner.take_sent().is_empty());
+
+        runner.scheduler().feed(&id, b"LO\n");
+        assert_eq!(runner.step(), 1);
+        assert_eq!(runner.scheduler().written(&id), b"WELCOME\n");
+        // Commands sent by the actor are processed with the next step
+        assert!(runner.take_sent().is_empty());
+        assert_eq!(runner.step(), 1);
+        assert_eq!(
+            runner.take_sent(),
+            vec![(SESSIONS, id.to_be_bytes().to_vec())]
+        );
+
+        // Completed handshake is not subject to the deadline
+        runner.clock().advance(HANDSHAKE_TIMEOUT);
+        assert_eq!(runner.step(), 0);
+        assert!(runner.take_errors().is_empty());
+        assert!(runner.actor(&id).unwrap().is_complete);
+    }
+
+    #[test]
+    fn invalid_handshake() {
+        let mut runner = runner();
+        let id = start(&mut runner);
+        runner.scheduler().feed(&id, b"HELP\n");
+        runner.step();
+        assert!(matches!(
+            &runner.take_errors()[..],
+            [InternalError::ActorError(_, err)] if err.kind() == io::ErrorKind::InvalidData
+        ));
+        assert!(runner.scheduler().written(&id).is_empty());
+        assert!(runner.take_sent().is_empty());
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        let mut runner = runner();
+        let id = start(&mut runner);
+        runner.scheduler().feed(&id, b"HEL");
+        runner.step();
+
+        runner
+            .clock()
+            .advance(HANDSHAKE_TIMEOUT - Duration::from_millis(1));
+        assert_eq!(runner.step(), 0);
+        assert!(runner.actor(&id).is_some());
+
+        runner.clock().advance(Duration::from_millis(1));
+        assert_eq!(runner.step(), 1);
+        assert!(matches!(
+            &runner.take_errors()[..],
+            [InternalError::ActorError(_, err)] if err.kind() == io::ErrorKind::TimedOut
+        ));
+        assert!(runner.actor(&id).is_none());
+    }
+
+    #[test]
+    fn commands_and_hangup() {
+        let mut runner = runner();
+        let id = start(&mut runner);
+        let mut controller = runner.controller();
+        controller.send(id, b"ping".to_vec()).unwrap();
+        assert!(runner.scheduler().written(&id).is_empty());
+        assert_eq!(runner.step(), 1);
+        assert!(runner.scheduler().written(&id).is_empty());
+        runner.scheduler().make_writable(&id);
+        assert_eq!(runner.step(), 1);
+        assert_eq!(runner.scheduler().written(&id), b"ping");
+
+        runner.scheduler().hang_up(&id);
+        assert_eq!(runner.step(), 1);
+        assert!(runner.actor(&id).is_none());
+        assert!(matches!(
+            controller.send(id, b"ping".to_vec()),
+            Err(InternalError::UnknownActor(_))
+        ));
+    }
+
+    #[test]
+    fn timers() {
+        let mut runner = runner();
+        let mut controller = runner.controller();
+        let first = controller
+            .set_timer(TestPool::Main, Duration::from_secs(1))
+            .unwrap();
+        let second = controller
+            .set_timer(TestPool::Main, Duration::from_secs(2))
+            .unwrap();
+        let cancelled = controller
+            .set_timer(TestPool::Main, Duration::from_secs(1))
+            .unwrap();
+        controller.cancel_timer(TestPool::Main, cancelled).unwrap();
+        assert_eq!(runner.step(), 4);
+        assert!(runner.take_timers().is_empty());
+
+        runner.clock().advance(Duration::from_secs(1));
+        assert_eq!(runner.step(), 1);
+        assert_eq!(runner.take_timers(), vec![first]);
+
+        runner.clock().advance(Duration::from_secs(1));
+        assert_eq!(runner.step(), 1);
+        assert_eq!(runner.take_timers(), vec![second]);
+        assert!(runner.take_errors().is_empty());
+    }
+
+    #[test]
+    fn graceful_disconnect() {
+        let mut runner = runner();
+        let flushed = start(&mut runner);
+        let timed_out = start(&mut runner);
+        let mut controller = runner.controller();
+        controller.send(flushed, b"bye".to_vec()).unwrap();
+        controller.send(timed_out, b"bye".to_vec()).unwrap();
+        controller
+            .stop_actor_gracefully(flushed, Duration::from_secs(1))
+            .unwrap();
+        controller
+            .stop_actor_gracefully(timed_out, Duration::from_secs(1))
+            .unwrap();
+        runner.step();
+        assert!(runner.actor(&flushed).is_none());
+        assert!(runner.draining_actor(&flushed).is_some());
+        assert!(matches!(
+            controller.send(flushed, b"late".to_vec()),
+            Err(InternalError::ActorDraining(_))
+        ));
+
+        // Actor is disconnected once it writes out its pending output
+        runner.scheduler().make_writable(&flushed);
+        assert_eq!(runner.step(), 1);
+        assert!(runner.draining_actor(&flushed).is_none());
+        assert_eq!(runner.scheduler.actors.len(), 1);
+
+        // Actor which can't write is disconnected by the timeout
+        runner.clock().advance(Duration::from_secs(1));
+        assert_eq!(runner.step(), 1);
+        assert!(runner.draining_actor(&timed_out).is_none());
+        assert!(runner.scheduler.actors.is_empty());
+        assert!(runner.take_errors().is_empty());
+    }
+
+    #[test]
+    fn half_close_take_and_replace() {
+        let mut runner = runner();
+        let id = start(&mut runner);
+        let mut controller = runner.controller();
+        controller.half_close(id).unwrap();
+        runner.step();
+        assert!(runner.take_errors().is_empty());
+
+        let taken = controller.take_actor(id).unwrap();
+        runner.step();
+        let actor = taken.try_recv().unwrap();
+        assert!(runner.actor(&id).is_none());
+
+        controller.insert_actor(TestPool::Main, actor).unwrap();
+        runner.step();
+        let deadline = runner.clock().now() + HANDSHAKE_TIMEOUT;
+        let stream = runner.actor(&id).unwrap().stream.clone();
+        let replacement = Handshake::with((stream, deadline), runner.controller()).unwrap();
+        let replaced = controller.replace_actor(id, replacement).unwrap();
+        runner.step();
+        assert_eq!(replaced.try_recv().unwrap().id(), id);
+        assert!(runner.actor(&id).is_some());
+        assert!(runner.take_errors().is_empty());
+    }
+
+    #[test]
+    fn reconnect_after_backoff() {
+        let mut runner = runner();
+        let mut controller = runner.controller();
+        let stream = TestStream::new();
+        let deadline = runner.clock().now() + HANDSHAKE_TIMEOUT;
+        controller
+            .reconnect(
+                TestPool::Main,
+                stream.id(),
+                (stream.clone(), deadline),
+                Duration::from_secs(1),
+                2.0,
+                3,
+            )
+            .unwrap();
+        assert_eq!(runner.step(), 1);
+        assert!(runner.actor(&stream.id()).is_none());
+
+        runner.clock().advance(Duration::from_secs(1));
+        assert_eq!(runner.step(), 1);
+        assert!(runner.actor(&stream.id()).is_some());
+    }
+
+    #[test]
+    fn disconnect_all_from_another_thread() {
+        let mut runner = runner();
+        let ids = [start(&mut runner), start(&mut runner)];
+        let mut controller = runner.controller();
+        let requester = thread::spawn(move || controller.disconnect_all());
+        while !requester.is_finished() {
+            runner.step();
+        }
+        assert_eq!(requester.join().unwrap().unwrap(), 2);
+        for id in ids {
+            assert!(runner.actor(&id).is_none());
+        }
+    }
+}
@@ -70,6 +70,20 @@ impl<K> TimeoutManager<K> {
         true
     }
 
+    /// Removes timeout registered under the given key.
+    ///
+    /// # Returns
+    ///
+    /// Whether a timeout with the key was present.
+    pub fn cancel(&mut self, key: &K) -> bool
+    where
+        K: PartialEq,
+    {
+        let len = self.timeouts.len();
+        self.timeouts.retain(|(k, _)| k != key);
+        self.timeouts.len() != len
+    }
+
     /// Get the minimum time duration we should wait for at least one timeout
     /// to be reached.  Returns `None` if there are no timeouts.
     ///
@@ -107,7 +121,8 @@ impl<K> TimeoutManager<K> {
 
     /// Given a specific time, add to the input vector keys that
     /// have timed out by that time. Returns the number of keys that timed out.
-    pub fn check(&mut self, time: Instant, fired: &mut Vec<K>) -> usize {
+    pub fn check(&mut self, time: impl Into<Instant>, fired: &mut Vec<K>) -> usize {
+        let time = time.into();
         let before = fired.len();
 
         while let Some((k, t)) = self.timeouts.pop() {
@@ -1,13 +1,139 @@
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::hash::Hash;
 use std::io;
+use std::sync::{Arc, RwLock};
 
 use cyphernet::crypto::ed25519::{PublicKey, Signature};
 
+/// Reason for rejecting a remote peer.
+#[derive(Clone, Eq, PartialEq, Hash, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum RejectReason {
+    /// peer credentials are invalid
+    InvalidCredentials,
+
+    /// peer key is in the deny list
+    Denied,
+
+    /// peer key is not in the allow list
+    NotAllowed,
+
+    /// {0}
+    Other(String),
+}
+
+/// Verdict of an [`AuthPolicy`] on a remote peer.
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub enum AuthVerdict {
+    /// Peer is accepted and may exchange the application data.
+    Accept,
+    /// Peer is rejected and the connection must be closed.
+    Reject(RejectReason),
+    /// The verdict is yet to be made by the application. The connection is
+    /// kept without exchanging the application data until then.
+    Defer,
+}
+
+/// Policy deciding on the remote peers by their key `K`.
+///
+/// A pair of policies rejects or defers the peer if any of them does, with
+/// the verdict of the first policy taking precedence.
+pub trait AuthPolicy<K>: Send + Sync + Debug {
+    fn verdict(&self, key: &K) -> AuthVerdict;
+}
+
+impl<K, A: AuthPolicy<K>, B: AuthPolicy<K>> AuthPolicy<K> for (A, B) {
+    fn verdict(&self, key: &K) -> AuthVerdict {
+        match self.0.verdict(key) {
+            AuthVerdict::Accept => self.1.verdict(key),
+            verdict => verdict,
+        }
+    }
+}
+
+/// Policy accepting only the peers with the listed keys, rejecting the rest
+/// with [`RejectReason::NotAllowed`].
+///
+/// Clones of the list share the keys, so the list may be updated while used
+/// by the sessions.
+#[derive(Clone, Debug)]
+pub struct AllowList<K: Eq + Hash>(Arc<RwLock<HashSet<K>>>);
+
+/// Policy rejecting the peers with the listed keys with
+/// [`RejectReason::Denied`], accepting the rest.
+///
+/// Clones of the list share the keys, so the list may be updated while used
+/// by the sessions.
+#[derive(Clone, Debug)]
+pub struct DenyList<K: Eq + Hash>(Arc<RwLock<HashSet<K>>>);
+
+macro_rules! key_list {
+    ($list:ident) => {
+        impl<K: Eq + Hash> Default for $list<K> {
+            fn default() -> Self {
+                Self::new()
+            }
+        }
+
+        impl<K: Eq + Hash> FromIterator<K> for $list<K> {
+            fn from_iter<I: IntoIterator<Item = K>>(iter: I) -> Self {
+                Self(Arc::new(RwLock::new(iter.into_iter().collect())))
+            }
+        }
+
+        impl<K: Eq + Hash> $list<K> {
+            pub fn new() -> Self {
+                Self(Arc::new(RwLock::new(HashSet::new())))
+            }
+
+            /// Adds key to the list, returning whether it was not listed.
+            pub fn insert(&self, key: K) -> bool {
+                self.0.write().expect("poisoned key list lock").insert(key)
+            }
+
+            /// Removes key from the list, returning whether it was listed.
+            pub fn remove(&self, key: &K) -> bool {
+                self.0.write().expect("poisoned key list lock").remove(key)
+            }
+
+            pub fn contains(&self, key: &K) -> bool {
+                self.0.read().expect("poisoned key list lock").contains(key)
+            }
+        }
+    };
+}
+
+key_list!(AllowList);
+key_list!(DenyList);
+
+impl<K: Eq + Hash + Send + Sync + Debug> AuthPolicy<K> for AllowList<K> {
+    fn verdict(&self, key: &K) -> AuthVerdict {
+        match self.contains(key) {
+            true => AuthVerdict::Accept,
+            false => AuthVerdict::Reject(RejectReason::NotAllowed),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Send + Sync + Debug> AuthPolicy<K> for DenyList<K> {
+    fn verdict(&self, key: &K) -> AuthVerdict {
+        match self.contains(key) {
+            true => AuthVerdict::Reject(RejectReason::Denied),
+            false => AuthVerdict::Accept,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct Authenticator {
     sent: bool,
     pubkey: PublicKey,
     signature: Signature,
     remote_id: Option<PublicKey>,
+    /// Key of the peer with valid credentials, on which the policy has
+    /// deferred its verdict.
+    deferred_id: Option<PublicKey>,
 }
 
 impl Authenticator {
@@ -17,19 +143,32 @@ impl Authenticator {
             pubkey,
             signature,
             remote_id: None,
+            deferred_id: None,
         }
     }
 
     pub fn certify(&mut self, writer: &mut impl io::Write) -> io::Result<()> {
+        #[cfg(feature = "log")]
         log::debug!(target: "authentication", "Sending auth credentials: {}, {}", self.pubkey, self.signature);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(target: "authentication", pubkey = %self.pubkey, "Sending auth credentials");
         writer.write_all(self.pubkey.as_slice())?;
         writer.write_all(self.signature.as_slice())?;
         self.sent = true;
         Ok(())
     }
 
+    /// Reads the credentials of the remote peer and, if they are valid,
+    /// returns the verdict of the `policy` on its key.
+    ///
+    /// Accepted key becomes the remote id, while the key on which the verdict
+    /// is deferred is kept until [`Authenticator::resolve`].
     // TODO: Do the real authentication with challenge
-    pub fn verify(&mut self, reader: &mut impl io::Read) -> io::Result<Option<PublicKey>> {
+    pub fn verify(
+        &mut self,
+        reader: &mut impl io::Read,
+        policy: &impl AuthPolicy<PublicKey>,
+    ) -> io::Result<AuthVerdict> {
         let mut buf = [0u8; 64];
         reader.read_exact(&mut buf[..32])?;
 
@@ -40,13 +179,58 @@ impl Authenticator {
         let sig = Signature::from(buf);
 
         if pubkey.verify(pubkey.as_slice(), &sig).is_err() {
+            #[cfg(feature = "log")]
             log::error!(target: "authentication", "Authentication of {pubkey} failed with sig {sig}");
-            return Ok(None);
+            #[cfg(feature = "tracing")]
+            tracing::error!(target: "authentication", %pubkey, "Authentication failed");
+            return Ok(AuthVerdict::Reject(RejectReason::InvalidCredentials));
+        }
+        let verdict = policy.verdict(&pubkey);
+        self.apply(pubkey, &verdict);
+        Ok(verdict)
+    }
+
+    /// Applies the verdict made by the application on the deferred key.
+    ///
+    /// # Panics
+    ///
+    /// If there is no deferred key.
+    pub fn resolve(&mut self, verdict: AuthVerdict) -> AuthVerdict {
+        let pubkey = self.deferred_id.take().expect("no deferred peer key");
+        self.apply(pubkey, &verdict);
+        verdict
+    }
+
+    fn apply(&mut self, pubkey: PublicKey, verdict: &AuthVerdict) {
+        match verdict {
+            AuthVerdict::Accept => {
+                #[cfg(feature = "log")]
+                log::info!(target: "authentication", "Peer {pubkey} authenticated");
+                #[cfg(feature = "tracing")]
+                tracing::info!(target: "authentication", %pubkey, "Peer authenticated");
+
+                self.remote_id = Some(pubkey);
+            }
+            AuthVerdict::Reject(_reason) => {
+                #[cfg(feature = "log")]
+                log::warn!(target: "authentication", "Peer {pubkey} is rejected: {_reason}");
+                #[cfg(feature = "tracing")]
+                tracing::warn!(target: "authentication", %pubkey, reason = %_reason, "Peer is rejected");
+            }
+            AuthVerdict::Defer => {
+                #[cfg(feature = "log")]
+                log::debug!(target: "authentication", "Verdict on peer {pubkey} is deferred");
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "authentication", %pubkey, "Verdict on peer is deferred");
+
+                self.deferred_id = Some(pubkey);
+            }
         }
-        log::info!(target: "authentication", "Peer {pubkey} signature {sig} authenticated");
+    }
 
-        self.remote_id = Some(pubkey);
-        Ok(self.remote_id)
+    /// Returns key of the peer on which the verdict is deferred.
+    pub fn deferred_id(&self) -> Option<PublicKey> {
+        self.deferred_id
     }
 
     pub fn remote_id(&self) -> Option<PublicKey> {
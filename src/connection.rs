@@ -8,8 +8,9 @@ use std::{io, net};
 
 use cyphernet::addr::{Addr, HostName, NetAddr};
 
+use crate::proxy_protocol::ProxyAddrs;
 use crate::resources::{SplitIo, SplitIoError};
-use crate::socks5::ToSocks5Dst;
+use crate::socks5::{Socks5Dst, ToSocks5Dst};
 
 pub trait Address: Addr + Clone + Eq + Hash + Debug + Display {}
 impl<T> Address for T where T: Addr + Clone + Eq + Hash + Debug + Display {}
@@ -21,6 +22,11 @@ pub trait Proxy: ToSocketAddrs {
 
     #[cfg(feature = "socket2")]
     fn connect_nonblocking<A: ToSocks5Dst>(&self, addr: A) -> Result<TcpStream, Self::Error>;
+
+    /// Performs the proxy handshake over a blocking stream, which is already
+    /// connected to the proxy (possibly through other proxies), requesting it
+    /// to connect to the destination.
+    fn tunnel(&self, stream: &mut TcpStream, dst: Socks5Dst) -> Result<(), Self::Error>;
 }
 
 /// Network stream is an abstraction of TCP stream object.
@@ -58,6 +64,14 @@ pub trait NetConnection: Send + SplitIo + io::Read + io::Write + AsRawFd + Debug
     where
         Self: Sized;
     fn take_error(&self) -> io::Result<Option<io::Error>>;
+
+    /// Returns the original addresses of a connection passed through a proxy
+    /// speaking PROXY protocol (see [`crate::ProxyStream`]).
+    ///
+    /// Connections which are not proxied return `None`.
+    fn proxy_addrs(&self) -> Option<ProxyAddrs> {
+        None
+    }
 }
 
 impl SplitIo for TcpStream {
@@ -1,4 +1,7 @@
+#[cfg(feature = "compression")]
+use std::borrow::Cow;
 use std::collections::VecDeque;
+use std::convert::Infallible;
 use std::io::{self, Read, Write};
 
 pub trait Frame: Send + Sized {
@@ -11,10 +14,70 @@ pub trait Frame: Send + Sized {
     fn marshall(&self, writer: impl Write) -> Result<usize, Self::Error>;
 }
 
-#[derive(Clone, Debug, Default)]
+/// Default maximal size of a frame accepted by [`Marshaller`].
+pub const DEFAULT_MAX_FRAME_BYTES: usize = 4 * 1024 * 1024;
+
+/// Errors reading and writing frames with [`Marshaller`] and its wrappers.
+#[derive(Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum FrameError<E: std::error::Error> {
+    /// frame of {actual} bytes exceeds the limit of {limit} bytes
+    FrameTooLarge {
+        /// Maximal frame size allowed by the marshaller.
+        limit: usize,
+        /// Size of the frame (or of its part received so far, see
+        /// [`Marshaller::pop`]).
+        actual: usize,
+    },
+
+    /// frame checksum mismatch: frame carries {expected:#010x}, while its data
+    /// hash to {got:#010x}
+    ChecksumMismatch {
+        /// Checksum sent along with the frame.
+        expected: u32,
+        /// Checksum computed over the received frame data.
+        got: u32,
+    },
+
+    /// frame authentication code is invalid
+    InvalidMac,
+
+    /// frame length {0} is shorter than its integrity trailer
+    InvalidLength(u32),
+
+    /// frame payload does not contain a complete frame
+    IncompleteFrame,
+
+    /// frame #{id} is truncated after {received} bytes
+    Truncated {
+        /// Number of the frame (see [`FrameChunk::id`]).
+        id: u64,
+        /// Number of the frame bytes received before the end of the stream,
+        /// including the frame header.
+        received: usize,
+    },
+
+    /// frame #{id} is being streamed and must be taken with `pop_stream`
+    StreamInProgress {
+        /// Number of the streamed frame (see [`FrameChunk::id`]).
+        id: u64,
+    },
+
+    /// invalid frame: {0}
+    Frame(E),
+}
+
+#[derive(Clone, Debug)]
 pub struct Marshaller {
     read_queue: VecDeque<u8>,
     write_queue: VecDeque<u8>,
+    max_frame_bytes: usize,
+}
+
+impl Default for Marshaller {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Marshaller {
@@ -22,6 +85,7 @@ impl Marshaller {
         Self {
             read_queue: VecDeque::new(),
             write_queue: VecDeque::new(),
+            max_frame_bytes: DEFAULT_MAX_FRAME_BYTES,
         }
     }
 
@@ -29,24 +93,70 @@ impl Marshaller {
         Self {
             read_queue: VecDeque::with_capacity(capacity),
             write_queue: VecDeque::with_capacity(capacity),
+            max_frame_bytes: DEFAULT_MAX_FRAME_BYTES,
         }
     }
 
-    pub fn push<F: Frame>(&mut self, frame: F) {
+    /// Sets maximal size of the sent and received frames, protecting from
+    /// peers making the marshaller to buffer unbounded amount of data.
+    pub fn with_max_frame_bytes(mut self, limit: usize) -> Self {
+        self.max_frame_bytes = limit;
+        self
+    }
+
+    /// Returns maximal size of the sent and received frames.
+    pub fn max_frame_bytes(&self) -> usize {
+        self.max_frame_bytes
+    }
+
+    /// # Errors
+    ///
+    /// If the marshalled frame exceeds the maximal frame size (see
+    /// [`Marshaller::with_max_frame_bytes`]) fails with
+    /// [`FrameError::FrameTooLarge`] without queueing any part of the frame.
+    pub fn push<F: Frame>(&mut self, frame: F) -> Result<(), FrameError<F::Error>> {
+        let queued = self.write_queue.len();
         frame
             .marshall(&mut self.write_queue)
             .expect("in-memory write operation");
+        let actual = self.write_queue.len() - queued;
+        if actual > self.max_frame_bytes {
+            self.write_queue.truncate(queued);
+            return Err(FrameError::FrameTooLarge {
+                limit: self.max_frame_bytes,
+                actual,
+            });
+        }
+        Ok(())
     }
 
-    pub fn pop<F: Frame>(&mut self) -> Result<Option<F>, F::Error> {
+    /// # Errors
+    ///
+    /// If the frame exceeds the maximal frame size (see
+    /// [`Marshaller::with_max_frame_bytes`]) fails with
+    /// [`FrameError::FrameTooLarge`] leaving the read queue intact. Since the
+    /// frame length is not known until the frame is complete, incomplete
+    /// frames are detected to be too large once the buffered data exceed the
+    /// limit; in this case the error reports the size of the buffered data.
+    pub fn pop<F: Frame>(&mut self) -> Result<Option<F>, FrameError<F::Error>> {
+        let limit = self.max_frame_bytes;
         let slice = self.read_queue.make_contiguous();
-        let mut cursor = io::Cursor::new(slice.as_ref());
-        let frame = F::unmarshall(&mut cursor)?;
+        let buffered = slice.len();
+        let mut cursor = io::Cursor::new(slice);
+        let frame = F::unmarshall(&mut cursor).map_err(FrameError::Frame)?;
         let pos = cursor.position() as usize;
-        if frame.is_some() {
-            self.read_queue.drain(..pos);
+        match frame {
+            Some(_) if pos > limit => Err(FrameError::FrameTooLarge { limit, actual: pos }),
+            None if buffered > limit => Err(FrameError::FrameTooLarge {
+                limit,
+                actual: buffered,
+            }),
+            Some(frame) => {
+                self.read_queue.drain(..pos);
+                Ok(Some(frame))
+            }
+            None => Ok(None),
         }
-        return Ok(frame);
     }
 
     pub fn queue_len(&self) -> usize {
@@ -82,3 +192,1691 @@ impl Write for Marshaller {
         Ok(())
     }
 }
+
+/// Stage of a [`CodecPipeline`] transforming marshalled frames on their way to
+/// and from the wire: length-prefixing, compression, integrity protection etc.
+///
+/// Codecs read their input from and write their output to in-memory buffers,
+/// so they may assume that writes do not fail. Pipelines configured at runtime
+/// may use `Box<dyn Codec<Error = E>>` as their stages.
+pub trait Codec: Send {
+    type Error: std::error::Error + Send;
+
+    /// Encodes marshalled frame `data`, writing the result to `writer`.
+    fn encode(&mut self, data: &[u8], writer: &mut dyn Write);
+
+    /// Decodes frame encoded with [`Codec::encode`] from `reader`, writing the
+    /// frame data to `writer`.
+    ///
+    /// If the reader doesn't contain the whole encoded frame yet must return
+    /// `Ok(false)` without writing anything.
+    fn decode(
+        &mut self,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+    ) -> Result<bool, Self::Error>;
+}
+
+impl<C: Codec + ?Sized> Codec for Box<C> {
+    type Error = C::Error;
+
+    fn encode(&mut self, data: &[u8], writer: &mut dyn Write) {
+        (**self).encode(data, writer)
+    }
+
+    fn decode(
+        &mut self,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+    ) -> Result<bool, Self::Error> {
+        (**self).decode(reader, writer)
+    }
+}
+
+/// Errors decoding frames with [`CodecPipeline`].
+#[derive(Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum PipelineError<E1: std::error::Error, E2: std::error::Error> {
+    #[display(inner)]
+    First(E1),
+
+    #[display(inner)]
+    Second(E2),
+
+    /// output of the second codec does not contain a complete frame
+    IncompleteFrame,
+}
+
+/// Pair of codecs applied one after another: frames are encoded with the
+/// first codec and its output is encoded with the second one, while decoding
+/// goes in the reverse order.
+///
+/// Longer pipelines are constructed by nesting pipelines, which is done by
+/// the [`pipeline!`] macro. The output of the intermediary stages is kept in a
+/// buffer reused between the frames.
+#[derive(Clone, Debug, Default)]
+pub struct CodecPipeline<C1: Codec, C2: Codec> {
+    first: C1,
+    second: C2,
+    buffer: Vec<u8>,
+}
+
+impl<C1: Codec, C2: Codec> CodecPipeline<C1, C2> {
+    pub fn new(first: C1, second: C2) -> Self {
+        Self {
+            first,
+            second,
+            buffer: empty!(),
+        }
+    }
+
+    /// Returns the codec applied first when encoding frames.
+    pub fn first(&self) -> &C1 {
+        &self.first
+    }
+
+    /// Returns the codec applied second when encoding frames.
+    pub fn second(&self) -> &C2 {
+        &self.second
+    }
+
+    /// Returns the codecs of the pipeline.
+    pub fn into_inner(self) -> (C1, C2) {
+        (self.first, self.second)
+    }
+}
+
+impl<C1: Codec, C2: Codec> Codec for CodecPipeline<C1, C2> {
+    type Error = PipelineError<C1::Error, C2::Error>;
+
+    fn encode(&mut self, data: &[u8], writer: &mut dyn Write) {
+        self.buffer.clear();
+        self.first.encode(data, &mut self.buffer);
+        self.second.encode(&self.buffer, writer);
+    }
+
+    fn decode(
+        &mut self,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+    ) -> Result<bool, Self::Error> {
+        self.buffer.clear();
+        if !self
+            .second
+            .decode(reader, &mut self.buffer)
+            .map_err(PipelineError::Second)?
+        {
+            return Ok(false);
+        }
+        if !self
+            .first
+            .decode(&mut self.buffer.as_slice(), writer)
+            .map_err(PipelineError::First)?
+        {
+            return Err(PipelineError::IncompleteFrame);
+        }
+        Ok(true)
+    }
+}
+
+/// Constructs [`CodecPipeline`] out of two or more codecs, listed in the order
+/// they are applied when encoding frames: `pipeline!(a, b, c)` is
+/// `CodecPipeline::new(a, CodecPipeline::new(b, c))`.
+#[macro_export]
+macro_rules! pipeline {
+    ($first:expr, $second:expr $(,)?) => {
+        $crate::CodecPipeline::new($first, $second)
+    };
+    ($first:expr, $($rest:expr),+ $(,)?) => {
+        $crate::CodecPipeline::new($first, $crate::pipeline!($($rest),+))
+    };
+}
+
+/// Errors reading frames with [`CodecMarshaller`].
+#[derive(Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum CodecError<C: std::error::Error, E: std::error::Error> {
+    #[display(inner)]
+    Codec(C),
+
+    /// frame payload does not contain a complete frame
+    IncompleteFrame,
+
+    /// invalid frame: {0}
+    Frame(E),
+}
+
+/// Wrapper around [`Marshaller`] encoding frames with a [`Codec`], which is
+/// usually a [`CodecPipeline`].
+///
+/// Decoding errors leave the read queue at the frame which has failed to
+/// decode, so they must be treated as fatal for the connection.
+#[derive(Clone, Debug)]
+pub struct CodecMarshaller<C: Codec> {
+    inner: Marshaller,
+    codec: C,
+    buffer: Vec<u8>,
+}
+
+impl<C: Codec> CodecMarshaller<C> {
+    pub fn new(codec: C) -> Self {
+        Self::with(Marshaller::new(), codec)
+    }
+
+    /// Wraps marshaller, which must not contain any data yet.
+    pub fn with(inner: Marshaller, codec: C) -> Self {
+        Self {
+            inner,
+            codec,
+            buffer: empty!(),
+        }
+    }
+
+    /// Returns the codec used by the marshaller.
+    pub fn codec(&self) -> &C {
+        &self.codec
+    }
+
+    /// # Errors
+    ///
+    /// If the marshalled frame, before it is encoded, exceeds the maximal
+    /// frame size of the wrapped marshaller fails with
+    /// [`FrameError::FrameTooLarge`] without queueing the frame.
+    pub fn push<F: Frame>(&mut self, frame: F) -> Result<(), FrameError<F::Error>> {
+        self.buffer.clear();
+        frame
+            .marshall(&mut self.buffer)
+            .expect("in-memory write operation");
+        check_size(&self.buffer, self.inner.max_frame_bytes)?;
+        self.codec.encode(&self.buffer, &mut self.inner.write_queue);
+        Ok(())
+    }
+
+    pub fn pop<F: Frame>(&mut self) -> Result<Option<F>, CodecError<C::Error, F::Error>> {
+        self.buffer.clear();
+        let queue = &mut self.inner.read_queue;
+        let mut cursor = io::Cursor::new(queue.make_contiguous());
+        if !self
+            .codec
+            .decode(&mut cursor, &mut self.buffer)
+            .map_err(CodecError::Codec)?
+        {
+            return Ok(None);
+        }
+        let pos = cursor.position() as usize;
+        queue.drain(..pos);
+        F::unmarshall(self.buffer.as_slice())
+            .map_err(CodecError::Frame)?
+            .map(Some)
+            .ok_or(CodecError::IncompleteFrame)
+    }
+
+    pub fn queue_len(&self) -> usize {
+        self.inner.queue_len()
+    }
+
+    /// Returns the wrapped marshaller.
+    pub fn into_inner(self) -> Marshaller {
+        self.inner
+    }
+}
+
+impl<C: Codec> Read for CodecMarshaller<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl<C: Codec> Write for CodecMarshaller<C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Default size of the marshalled frames below which [`CompressedMarshaller`]
+/// does not compress them.
+#[cfg(feature = "compression")]
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 256;
+
+/// Flag of a frame sent by [`CompressedMarshaller`] without compression.
+#[cfg(feature = "compression")]
+const FLAG_RAW: u8 = 0;
+/// Flag of a frame sent by [`CompressedMarshaller`] compressed with LZ4.
+#[cfg(feature = "compression")]
+const FLAG_LZ4: u8 = 1;
+/// Length of the header preceding each frame sent by [`CompressedMarshaller`]:
+/// a flag byte and the payload length as a big-endian `u32`.
+#[cfg(feature = "compression")]
+const HEADER_LEN: usize = 5;
+
+/// Errors reading frames with [`CompressedMarshaller`].
+#[cfg(feature = "compression")]
+#[derive(Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum DecompressionError<E: std::error::Error> {
+    /// unknown compression flag {0:#04x}
+    UnknownFlag(u8),
+
+    /// corrupted compressed frame: {0}
+    Corrupted(lz4_flex::block::DecompressError),
+
+    /// frame of {actual} bytes exceeds the limit of {limit} bytes
+    FrameTooLarge {
+        /// Maximal frame size allowed by the marshaller.
+        limit: usize,
+        /// Size of the frame, compressed or uncompressed.
+        actual: usize,
+    },
+
+    /// frame payload does not contain a complete frame
+    IncompleteFrame,
+
+    /// invalid frame: {0}
+    Frame(E),
+}
+
+/// Statistics of the data passed through a [`CompressedMarshaller`].
+#[cfg(feature = "compression")]
+#[derive(Copy, Clone, PartialEq, Debug, Default)]
+pub struct CompressionStats {
+    /// Size of the frames (including their headers) as they were sent and
+    /// received.
+    pub compressed_bytes: u64,
+    /// Size of the frames before compression and after decompression.
+    pub uncompressed_bytes: u64,
+    /// Ratio of the compressed size to the uncompressed one; `1.0` if no
+    /// frames were processed yet.
+    pub ratio: f64,
+}
+
+/// Wrapper around [`Marshaller`] transparently compressing frames with LZ4.
+///
+/// Each frame is sent with a header containing a flag specifying whether the
+/// frame is compressed and the length of the frame payload. Frames smaller
+/// than the threshold (see [`CompressedMarshaller::with_threshold`]) are sent
+/// without compression, since for them the compression overhead outweighs the
+/// gains.
+#[cfg(feature = "compression")]
+#[derive(Clone, Debug)]
+pub struct CompressedMarshaller {
+    inner: Marshaller,
+    threshold: usize,
+    compressed_bytes: u64,
+    uncompressed_bytes: u64,
+}
+
+#[cfg(feature = "compression")]
+impl Default for CompressedMarshaller {
+    fn default() -> Self {
+        Self::with(Marshaller::new())
+    }
+}
+
+#[cfg(feature = "compression")]
+impl CompressedMarshaller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps marshaller, which must not contain any data yet.
+    pub fn with(inner: Marshaller) -> Self {
+        Self {
+            inner,
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+            compressed_bytes: 0,
+            uncompressed_bytes: 0,
+        }
+    }
+
+    /// Sets size of the marshalled frames below which they are sent without
+    /// compression.
+    pub fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Sets maximal size of the sent and received frames, applied both to the
+    /// compressed payload and to the frame size after decompression.
+    pub fn with_max_frame_bytes(mut self, limit: usize) -> Self {
+        self.inner = self.inner.with_max_frame_bytes(limit);
+        self
+    }
+
+    /// Compresses marshalled frame unless it is smaller than the threshold,
+    /// returning the frame header and payload. Frames which do not shrink
+    /// after compression are sent as is, so the payload never exceeds the
+    /// frame size.
+    fn compress<'data>(&mut self, data: &'data [u8]) -> ([u8; HEADER_LEN], Cow<'data, [u8]>) {
+        let compressed = (data.len() >= self.threshold)
+            .then(|| lz4_flex::compress_prepend_size(data))
+            .filter(|compressed| compressed.len() < data.len());
+        let (flag, payload) = match compressed {
+            Some(compressed) => (FLAG_LZ4, Cow::Owned(compressed)),
+            None => (FLAG_RAW, Cow::Borrowed(data)),
+        };
+        let len = u32::try_from(payload.len()).expect("frame exceeds 4GB");
+        let mut header = [flag, 0, 0, 0, 0];
+        header[1..].copy_from_slice(&len.to_be_bytes());
+        self.compressed_bytes += (HEADER_LEN + payload.len()) as u64;
+        self.uncompressed_bytes += data.len() as u64;
+        (header, payload)
+    }
+
+    /// # Errors
+    ///
+    /// If the marshalled frame exceeds the maximal frame size fails with
+    /// [`FrameError::FrameTooLarge`] without queueing the frame.
+    pub fn push<F: Frame>(&mut self, frame: F) -> Result<(), FrameError<F::Error>> {
+        let data = marshall(frame);
+        check_size(&data, self.inner.max_frame_bytes)?;
+        let (header, payload) = self.compress(&data);
+        let queue = &mut self.inner.write_queue;
+        queue.extend(header);
+        queue.extend(payload.iter());
+        Ok(())
+    }
+
+    pub fn pop<F: Frame>(&mut self) -> Result<Option<F>, DecompressionError<F::Error>> {
+        let limit = self.inner.max_frame_bytes;
+        let queue = &mut self.inner.read_queue;
+        let mut cursor = io::Cursor::new(queue.make_contiguous());
+        let (len, data) = match read_compressed(&mut cursor, limit)? {
+            Some(frame) => frame,
+            None => return Ok(None),
+        };
+        queue.drain(..len);
+        self.compressed_bytes += len as u64;
+        self.uncompressed_bytes += data.len() as u64;
+
+        F::unmarshall(data.as_slice())
+            .map_err(DecompressionError::Frame)?
+            .map(Some)
+            .ok_or(DecompressionError::IncompleteFrame)
+    }
+
+    pub fn queue_len(&self) -> usize {
+        self.inner.queue_len()
+    }
+
+    /// Returns statistics of the frames sent and received so far.
+    pub fn stats(&self) -> CompressionStats {
+        let ratio = if self.uncompressed_bytes == 0 {
+            1.0
+        } else {
+            self.compressed_bytes as f64 / self.uncompressed_bytes as f64
+        };
+        CompressionStats {
+            compressed_bytes: self.compressed_bytes,
+            uncompressed_bytes: self.uncompressed_bytes,
+            ratio,
+        }
+    }
+
+    /// Returns the wrapped marshaller.
+    pub fn into_inner(self) -> Marshaller {
+        self.inner
+    }
+}
+
+/// Reads frame sent by [`CompressedMarshaller`], returning the size of the
+/// frame as it was received and its decompressed data, or `Ok(None)` if the
+/// reader does not contain the whole frame yet.
+#[cfg(feature = "compression")]
+fn read_compressed<E: std::error::Error>(
+    reader: &mut dyn Read,
+    limit: usize,
+) -> Result<Option<(usize, Vec<u8>)>, DecompressionError<E>> {
+    let mut header = [0u8; HEADER_LEN];
+    if reader.read_exact(&mut header).is_err() {
+        return Ok(None);
+    }
+    let flag = header[0];
+    let mut len = [0u8; 4];
+    len.copy_from_slice(&header[1..]);
+    let len = u32::from_be_bytes(len) as usize;
+    if len > limit {
+        return Err(DecompressionError::FrameTooLarge { limit, actual: len });
+    }
+    let mut payload = Vec::with_capacity(len);
+    if reader.take(len as u64).read_to_end(&mut payload).is_err() || payload.len() < len {
+        return Ok(None);
+    }
+    let data = match flag {
+        FLAG_RAW => payload,
+        FLAG_LZ4 => {
+            // Size prepended to the compressed data is checked before
+            // decompressing, so it does not allocate more than the limit
+            let (size, _) = lz4_flex::block::uncompressed_size(&payload)
+                .map_err(DecompressionError::Corrupted)?;
+            if size > limit {
+                return Err(DecompressionError::FrameTooLarge {
+                    limit,
+                    actual: size,
+                });
+            }
+            lz4_flex::decompress_size_prepended(&payload).map_err(DecompressionError::Corrupted)?
+        }
+        flag => return Err(DecompressionError::UnknownFlag(flag)),
+    };
+    Ok(Some((HEADER_LEN + len, data)))
+}
+
+/// When used as a stage of a [`CodecPipeline`] only the configuration and the
+/// statistics of the marshaller are used, while its queues stay empty.
+#[cfg(feature = "compression")]
+impl Codec for CompressedMarshaller {
+    type Error = DecompressionError<Infallible>;
+
+    fn encode(&mut self, data: &[u8], writer: &mut dyn Write) {
+        let (header, payload) = self.compress(data);
+        writer
+            .write_all(&header)
+            .and_then(|_| writer.write_all(&payload))
+            .expect("in-memory write operation");
+    }
+
+    fn decode(
+        &mut self,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+    ) -> Result<bool, Self::Error> {
+        let (len, data) = match read_compressed(reader, self.inner.max_frame_bytes)? {
+            Some(frame) => frame,
+            None => return Ok(false),
+        };
+        self.compressed_bytes += len as u64;
+        self.uncompressed_bytes += data.len() as u64;
+        writer.write_all(&data).expect("in-memory write operation");
+        Ok(true)
+    }
+}
+
+#[cfg(feature = "compression")]
+impl Read for CompressedMarshaller {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(feature = "compression")]
+impl Write for CompressedMarshaller {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Length of the big-endian `u32` length prefix of the frames sent by
+/// [`LengthPrefixMarshaller`], [`ChecksummedMarshaller`] and
+/// [`AuthenticatedMarshaller`].
+const LEN_PREFIX_LEN: usize = 4;
+/// Length of the CRC32 checksum appended to each frame by
+/// [`ChecksummedMarshaller`].
+#[cfg(feature = "checksum")]
+const CHECKSUM_LEN: usize = 4;
+/// Length of the HMAC-SHA256 tag appended to each frame by
+/// [`AuthenticatedMarshaller`].
+#[cfg(feature = "checksum")]
+const MAC_LEN: usize = 32;
+
+/// Marshalls frame into a new buffer.
+fn marshall<F: Frame>(frame: F) -> Vec<u8> {
+    let mut data = vec![];
+    frame
+        .marshall(&mut data)
+        .expect("in-memory write operation");
+    data
+}
+
+/// Checks that the marshalled frame `data` fits into the frame size `limit`
+/// before it is sent.
+fn check_size<E: std::error::Error>(data: &[u8], limit: usize) -> Result<(), FrameError<E>> {
+    if data.len() > limit {
+        return Err(FrameError::FrameTooLarge {
+            limit,
+            actual: data.len(),
+        });
+    }
+    Ok(())
+}
+
+/// Unmarshalls frame which must take the whole `data`.
+fn unmarshall<F: Frame>(data: &[u8]) -> Result<Option<F>, FrameError<F::Error>> {
+    F::unmarshall(data)
+        .map_err(FrameError::Frame)?
+        .map(Some)
+        .ok_or(FrameError::IncompleteFrame)
+}
+
+/// Returns length prefix of a frame consisting of `data` and `trailer`.
+fn len_prefix(data: &[u8], trailer_len: usize) -> [u8; LEN_PREFIX_LEN] {
+    u32::try_from(data.len() + trailer_len)
+        .expect("frame exceeds 4GB")
+        .to_be_bytes()
+}
+
+/// Writes frame consisting of `data` and `trailer` prefixed with its length.
+fn write_frame(writer: &mut dyn Write, data: &[u8], trailer: &[u8]) {
+    writer
+        .write_all(&len_prefix(data, trailer.len()))
+        .and_then(|_| writer.write_all(data))
+        .and_then(|_| writer.write_all(trailer))
+        .expect("in-memory write operation");
+}
+
+/// Reads next frame from the reader, returning the frame data followed by the
+/// trailer of `trailer_len` bytes. Returns `Ok(None)` if the reader does not
+/// contain the whole frame yet.
+fn read_frame<E: std::error::Error>(
+    reader: &mut dyn Read,
+    trailer_len: usize,
+    limit: usize,
+) -> Result<Option<Vec<u8>>, FrameError<E>> {
+    let mut len = [0u8; LEN_PREFIX_LEN];
+    if reader.read_exact(&mut len).is_err() {
+        return Ok(None);
+    }
+    let len = u32::from_be_bytes(len);
+    if (len as usize) < trailer_len {
+        return Err(FrameError::InvalidLength(len));
+    }
+    let actual = len as usize - trailer_len;
+    if actual > limit {
+        return Err(FrameError::FrameTooLarge { limit, actual });
+    }
+    let mut data = Vec::with_capacity(len as usize);
+    if reader.take(len as u64).read_to_end(&mut data).is_err() || data.len() < len as usize {
+        return Ok(None);
+    }
+    Ok(Some(data))
+}
+
+/// Takes next frame from the read queue of the marshaller, returning the frame
+/// data followed by the trailer of `trailer_len` bytes. Returns `Ok(None)` if
+/// the queue does not contain the whole frame yet.
+fn pop_frame<E: std::error::Error>(
+    marshaller: &mut Marshaller,
+    trailer_len: usize,
+) -> Result<Option<Vec<u8>>, FrameError<E>> {
+    let limit = marshaller.max_frame_bytes;
+    let queue = &mut marshaller.read_queue;
+    let mut cursor = io::Cursor::new(queue.make_contiguous());
+    let frame = read_frame(&mut cursor, trailer_len, limit)?;
+    if frame.is_some() {
+        let pos = cursor.position() as usize;
+        queue.drain(..pos);
+    }
+    Ok(frame)
+}
+
+/// Part of a large frame delivered by [`LengthPrefixMarshaller::pop_stream`]
+/// before the whole frame is received.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct FrameChunk {
+    /// Number of the frame among all frames received by the marshaller,
+    /// starting from zero.
+    pub id: u64,
+    /// Position of the chunk within the frame data.
+    pub offset: usize,
+    /// Frame data.
+    pub bytes: Vec<u8>,
+    /// Whether this is the final chunk of the frame.
+    pub last: bool,
+}
+
+/// Frame or a part of it returned by [`LengthPrefixMarshaller::pop_stream`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Streamed<F> {
+    /// Frame which was received completely.
+    Frame(F),
+    /// Part of a frame exceeding the streaming threshold.
+    Chunk(FrameChunk),
+}
+
+/// Frame which is being delivered in chunks.
+#[derive(Copy, Clone, Debug)]
+struct StreamedFrame {
+    id: u64,
+    len: usize,
+    offset: usize,
+}
+
+/// Wrapper around [`Marshaller`] sending each frame prefixed with its length
+/// as a big-endian `u32`.
+///
+/// Unlike [`Marshaller`], it does not rely on the frames to be self-delimiting
+/// and is able to check the frame size against the limit before the frame is
+/// received. It is usually the first stage of a [`CodecPipeline`], which makes
+/// the frame boundaries known to the following stages.
+///
+/// Large frames may be received without buffering them as a whole: see
+/// [`LengthPrefixMarshaller::with_streaming`].
+#[derive(Clone, Debug, Default)]
+pub struct LengthPrefixMarshaller {
+    inner: Marshaller,
+    stream_threshold: Option<usize>,
+    stream: Option<StreamedFrame>,
+    received: u64,
+}
+
+impl LengthPrefixMarshaller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps marshaller, which must not contain any data yet.
+    pub fn with(inner: Marshaller) -> Self {
+        Self {
+            inner,
+            ..Self::default()
+        }
+    }
+
+    /// Sets maximal size of the sent and received frames, not including the
+    /// length prefix.
+    pub fn with_max_frame_bytes(mut self, limit: usize) -> Self {
+        self.inner = self.inner.with_max_frame_bytes(limit);
+        self
+    }
+
+    /// Enables delivery of the received frames longer than `threshold` bytes
+    /// in chunks, as their data arrive, with
+    /// [`LengthPrefixMarshaller::pop_stream`]. Since such frames are not
+    /// buffered, they are not checked against the maximal frame size.
+    pub fn with_streaming(mut self, threshold: usize) -> Self {
+        self.stream_threshold = Some(threshold);
+        self
+    }
+
+    /// # Errors
+    ///
+    /// If the marshalled frame exceeds the maximal frame size fails with
+    /// [`FrameError::FrameTooLarge`] without queueing the frame.
+    pub fn push<F: Frame>(&mut self, frame: F) -> Result<(), FrameError<F::Error>> {
+        let data = marshall(frame);
+        check_size(&data, self.inner.max_frame_bytes)?;
+        write_frame(&mut self.inner.write_queue, &data, &[]);
+        Ok(())
+    }
+
+    /// Takes next complete frame from the read queue.
+    ///
+    /// With streaming enabled, frames must be taken with
+    /// [`LengthPrefixMarshaller::pop_stream`] instead.
+    ///
+    /// # Errors
+    ///
+    /// Fails with [`FrameError::StreamInProgress`] if the last frame was not
+    /// fully taken with [`LengthPrefixMarshaller::pop_stream`].
+    pub fn pop<F: Frame>(&mut self) -> Result<Option<F>, FrameError<F::Error>> {
+        if let Some(stream) = self.stream {
+            return Err(FrameError::StreamInProgress { id: stream.id });
+        }
+        match pop_frame(&mut self.inner, 0)? {
+            Some(data) => {
+                self.received += 1;
+                unmarshall(&data)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Takes next frame, or next chunk of a frame exceeding the streaming
+    /// threshold (see [`LengthPrefixMarshaller::with_streaming`]), from the
+    /// read queue. Chunks contain all data of the frame received so far, and
+    /// the frames which follow a streamed frame are returned only after its
+    /// last chunk.
+    pub fn pop_stream<F: Frame>(&mut self) -> Result<Option<Streamed<F>>, FrameError<F::Error>> {
+        let mut stream = match self.stream {
+            Some(stream) => stream,
+            None => match self.next_len() {
+                Some(len) if len > self.stream_threshold.unwrap_or(usize::MAX) => {
+                    self.inner.read_queue.drain(..LEN_PREFIX_LEN);
+                    StreamedFrame {
+                        id: self.received,
+                        len,
+                        offset: 0,
+                    }
+                }
+                Some(_) => return self.pop().map(|frame| frame.map(Streamed::Frame)),
+                None => return Ok(None),
+            },
+        };
+        let queue = &mut self.inner.read_queue;
+        let bytes = queue
+            .drain(..queue.len().min(stream.len - stream.offset))
+            .collect::<Vec<_>>();
+        let chunk = FrameChunk {
+            id: stream.id,
+            offset: stream.offset,
+            last: stream.offset + bytes.len() == stream.len,
+            bytes,
+        };
+        stream.offset += chunk.bytes.len();
+        if chunk.last {
+            self.stream = None;
+            self.received += 1;
+        } else {
+            self.stream = Some(stream);
+            if chunk.bytes.is_empty() {
+                return Ok(None);
+            }
+        }
+        Ok(Some(Streamed::Chunk(chunk)))
+    }
+
+    /// Returns length of the next frame in the read queue, if its length
+    /// prefix is already received.
+    fn next_len(&self) -> Option<usize> {
+        let queue = &self.inner.read_queue;
+        if queue.len() < LEN_PREFIX_LEN {
+            return None;
+        }
+        let mut len = [0u8; LEN_PREFIX_LEN];
+        for (byte, src) in len.iter_mut().zip(queue) {
+            *byte = *src;
+        }
+        Some(u32::from_be_bytes(len) as usize)
+    }
+
+    /// Signals that the connection is closed and no more data will arrive.
+    ///
+    /// # Errors
+    ///
+    /// If a frame was received only partially, which for the streamed frames
+    /// means that its last chunk was not delivered, fails with
+    /// [`FrameError::Truncated`].
+    pub fn end_of_stream(&mut self) -> Result<(), FrameError<Infallible>> {
+        let buffered = self.inner.read_queue.len();
+        match self.stream.take() {
+            Some(stream) => Err(FrameError::Truncated {
+                id: stream.id,
+                received: LEN_PREFIX_LEN + stream.offset + buffered,
+            }),
+            None if buffered > 0 => Err(FrameError::Truncated {
+                id: self.received,
+                received: buffered,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    pub fn queue_len(&self) -> usize {
+        self.inner.queue_len()
+    }
+
+    /// Returns the wrapped marshaller.
+    pub fn into_inner(self) -> Marshaller {
+        self.inner
+    }
+}
+
+/// When used as a stage of a [`CodecPipeline`] only the frame size limit of
+/// the marshaller is used, while its queues stay empty.
+impl Codec for LengthPrefixMarshaller {
+    type Error = FrameError<Infallible>;
+
+    fn encode(&mut self, data: &[u8], writer: &mut dyn Write) {
+        write_frame(writer, data, &[]);
+    }
+
+    fn decode(
+        &mut self,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+    ) -> Result<bool, Self::Error> {
+        match read_frame(reader, 0, self.inner.max_frame_bytes)? {
+            Some(data) => {
+                writer.write_all(&data).expect("in-memory write operation");
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+impl Read for LengthPrefixMarshaller {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+impl Write for LengthPrefixMarshaller {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Wrapper around [`Marshaller`] protecting frames from accidental corruption
+/// with a CRC32 checksum.
+///
+/// Each frame is sent prefixed with its length as a big-endian `u32` and
+/// followed by the little-endian CRC32 (IEEE) checksum of the frame data; the
+/// length covers the checksum. Frames which do not match their checksum are
+/// dropped and reported with [`FrameError::ChecksumMismatch`].
+///
+/// The checksum does not protect from deliberate tampering; use
+/// [`AuthenticatedMarshaller`] for that.
+#[cfg(feature = "checksum")]
+#[derive(Clone, Debug, Default)]
+pub struct ChecksummedMarshaller {
+    inner: Marshaller,
+}
+
+#[cfg(feature = "checksum")]
+impl ChecksummedMarshaller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps marshaller, which must not contain any data yet.
+    pub fn with(inner: Marshaller) -> Self {
+        Self { inner }
+    }
+
+    /// Sets maximal size of the sent and received frames, not including the
+    /// checksum.
+    pub fn with_max_frame_bytes(mut self, limit: usize) -> Self {
+        self.inner = self.inner.with_max_frame_bytes(limit);
+        self
+    }
+
+    /// # Errors
+    ///
+    /// If the marshalled frame exceeds the maximal frame size fails with
+    /// [`FrameError::FrameTooLarge`] without queueing the frame.
+    pub fn push<F: Frame>(&mut self, frame: F) -> Result<(), FrameError<F::Error>> {
+        let data = marshall(frame);
+        check_size(&data, self.inner.max_frame_bytes)?;
+        let checksum = crc32fast::hash(&data);
+        write_frame(&mut self.inner.write_queue, &data, &checksum.to_le_bytes());
+        Ok(())
+    }
+
+    pub fn pop<F: Frame>(&mut self) -> Result<Option<F>, FrameError<F::Error>> {
+        match pop_frame(&mut self.inner, CHECKSUM_LEN)? {
+            Some(data) => unmarshall(&Self::verify(data)?),
+            None => Ok(None),
+        }
+    }
+
+    /// Verifies checksum trailing the frame data, returning the data without
+    /// the checksum.
+    fn verify<E: std::error::Error>(mut data: Vec<u8>) -> Result<Vec<u8>, FrameError<E>> {
+        let trailer = data.split_off(data.len() - CHECKSUM_LEN);
+        let mut checksum = [0u8; CHECKSUM_LEN];
+        checksum.copy_from_slice(&trailer);
+        let expected = u32::from_le_bytes(checksum);
+        let got = crc32fast::hash(&data);
+        if expected != got {
+            return Err(FrameError::ChecksumMismatch { expected, got });
+        }
+        Ok(data)
+    }
+
+    pub fn queue_len(&self) -> usize {
+        self.inner.queue_len()
+    }
+
+    /// Returns the wrapped marshaller.
+    pub fn into_inner(self) -> Marshaller {
+        self.inner
+    }
+}
+
+/// When used as a stage of a [`CodecPipeline`] only the frame size limit of
+/// the marshaller is used, while its queues stay empty.
+#[cfg(feature = "checksum")]
+impl Codec for ChecksummedMarshaller {
+    type Error = FrameError<Infallible>;
+
+    fn encode(&mut self, data: &[u8], writer: &mut dyn Write) {
+        write_frame(writer, data, &crc32fast::hash(data).to_le_bytes());
+    }
+
+    fn decode(
+        &mut self,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+    ) -> Result<bool, Self::Error> {
+        match read_frame(reader, CHECKSUM_LEN, self.inner.max_frame_bytes)? {
+            Some(data) => {
+                let data = Self::verify(data)?;
+                writer.write_all(&data).expect("in-memory write operation");
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(feature = "checksum")]
+impl Read for ChecksummedMarshaller {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(feature = "checksum")]
+impl Write for ChecksummedMarshaller {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Wrapper around [`Marshaller`] protecting frames from tampering with
+/// HMAC-SHA256 keyed by a secret shared by both sides of the connection.
+///
+/// Frames are sent in the same way as by [`ChecksummedMarshaller`], but are
+/// followed by a 32-byte authentication code instead of the checksum. The code
+/// covers the frame number, the length prefix and the frame data, such that
+/// frames which are modified, reordered, replayed or dropped fail to
+/// authenticate and are reported with [`FrameError::InvalidMac`].
+#[cfg(feature = "checksum")]
+#[derive(Clone)]
+pub struct AuthenticatedMarshaller {
+    inner: Marshaller,
+    mac: hmac::Hmac<sha2::Sha256>,
+    sent: u64,
+    received: u64,
+}
+
+#[cfg(feature = "checksum")]
+impl std::fmt::Debug for AuthenticatedMarshaller {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // Do not leak the key
+        f.debug_struct("AuthenticatedMarshaller")
+            .field("inner", &self.inner)
+            .field("sent", &self.sent)
+            .field("received", &self.received)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(feature = "checksum")]
+impl AuthenticatedMarshaller {
+    pub fn new(key: &[u8]) -> Self {
+        Self::with(Marshaller::new(), key)
+    }
+
+    /// Wraps marshaller, which must not contain any data yet.
+    pub fn with(inner: Marshaller, key: &[u8]) -> Self {
+        use hmac::Mac;
+
+        Self {
+            inner,
+            mac: hmac::Hmac::new_from_slice(key).expect("HMAC accepts keys of any length"),
+            sent: 0,
+            received: 0,
+        }
+    }
+
+    /// Sets maximal size of the sent and received frames, not including the
+    /// authentication code.
+    pub fn with_max_frame_bytes(mut self, limit: usize) -> Self {
+        self.inner = self.inner.with_max_frame_bytes(limit);
+        self
+    }
+
+    /// Computes authentication code of the frame with the given number.
+    fn mac(&self, no: u64, len: [u8; LEN_PREFIX_LEN], data: &[u8]) -> hmac::Hmac<sha2::Sha256> {
+        use hmac::Mac;
+
+        let mut mac = self.mac.clone();
+        mac.update(&no.to_be_bytes());
+        mac.update(&len);
+        mac.update(data);
+        mac
+    }
+
+    /// Returns authentication code of the next sent frame.
+    fn sign(&mut self, data: &[u8]) -> [u8; MAC_LEN] {
+        use hmac::Mac;
+
+        let len = len_prefix(data, MAC_LEN);
+        let tag = self.mac(self.sent, len, data).finalize().into_bytes();
+        self.sent += 1;
+        tag.into()
+    }
+
+    /// Verifies authentication code trailing the data of the next received
+    /// frame, returning the data without the code.
+    fn verify<E: std::error::Error>(
+        &mut self,
+        mut data: Vec<u8>,
+    ) -> Result<Vec<u8>, FrameError<E>> {
+        use hmac::Mac;
+
+        let tag = data.split_off(data.len() - MAC_LEN);
+        let len = len_prefix(&data, MAC_LEN);
+        let no = self.received;
+        self.received += 1;
+        self.mac(no, len, &data)
+            .verify_slice(&tag)
+            .map_err(|_| FrameError::InvalidMac)?;
+        Ok(data)
+    }
+
+    /// # Errors
+    ///
+    /// If the marshalled frame exceeds the maximal frame size fails with
+    /// [`FrameError::FrameTooLarge`] without queueing the frame; the frame
+    /// does not take a frame number then.
+    pub fn push<F: Frame>(&mut self, frame: F) -> Result<(), FrameError<F::Error>> {
+        let data = marshall(frame);
+        check_size(&data, self.inner.max_frame_bytes)?;
+        let tag = self.sign(&data);
+        write_frame(&mut self.inner.write_queue, &data, &tag);
+        Ok(())
+    }
+
+    pub fn pop<F: Frame>(&mut self) -> Result<Option<F>, FrameError<F::Error>> {
+        match pop_frame(&mut self.inner, MAC_LEN)? {
+            Some(data) => unmarshall(&self.verify(data)?),
+            None => Ok(None),
+        }
+    }
+
+    pub fn queue_len(&self) -> usize {
+        self.inner.queue_len()
+    }
+
+    /// Returns the wrapped marshaller.
+    pub fn into_inner(self) -> Marshaller {
+        self.inner
+    }
+}
+
+/// When used as a stage of a [`CodecPipeline`] only the key, the frame
+/// counters and the frame size limit of the marshaller are used, while its
+/// queues stay empty.
+#[cfg(feature = "checksum")]
+impl Codec for AuthenticatedMarshaller {
+    type Error = FrameError<Infallible>;
+
+    fn encode(&mut self, data: &[u8], writer: &mut dyn Write) {
+        let tag = self.sign(data);
+        write_frame(writer, data, &tag);
+    }
+
+    fn decode(
+        &mut self,
+        reader: &mut dyn Read,
+        writer: &mut dyn Write,
+    ) -> Result<bool, Self::Error> {
+        match read_frame(reader, MAC_LEN, self.inner.max_frame_bytes)? {
+            Some(data) => {
+                let data = self.verify(data)?;
+                writer.write_all(&data).expect("in-memory write operation");
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(feature = "checksum")]
+impl Read for AuthenticatedMarshaller {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.read(buf)
+    }
+}
+
+#[cfg(feature = "checksum")]
+impl Write for AuthenticatedMarshaller {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "compression")]
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// Frame carrying arbitrary bytes prefixed with their length.
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    struct Bytes(Vec<u8>);
+
+    impl Frame for Bytes {
+        type Error = io::Error;
+
+        fn unmarshall(mut reader: impl Read) -> Result<Option<Self>, Self::Error> {
+            let mut len = [0u8; 4];
+            if reader.read_exact(&mut len).is_err() {
+                return Ok(None);
+            }
+            let mut data = vec![0u8; u32::from_be_bytes(len) as usize];
+            if reader.read_exact(&mut data).is_err() {
+                return Ok(None);
+            }
+            Ok(Some(Bytes(data)))
+        }
+
+        fn marshall(&self, mut writer: impl Write) -> Result<usize, Self::Error> {
+            writer.write_all(&(self.0.len() as u32).to_be_bytes())?;
+            writer.write_all(&self.0)?;
+            Ok(4 + self.0.len())
+        }
+    }
+
+    /// Passes frames through a pair of marshallers, delivering the data in
+    /// two parts split at `split` ratio.
+    #[cfg(feature = "compression")]
+    fn transfer(frames: &[Bytes], threshold: usize, split: f64) -> Vec<Bytes> {
+        let mut sender = CompressedMarshaller::new().with_threshold(threshold);
+        for frame in frames {
+            sender.push(frame.clone()).unwrap();
+        }
+        let mut wire = vec![];
+        sender.read_to_end(&mut wire).unwrap();
+
+        let mut receiver = CompressedMarshaller::new();
+        let (first, second) = wire.split_at((wire.len() as f64 * split) as usize);
+        let mut received = vec![];
+        for part in [first, second] {
+            receiver.write_all(part).unwrap();
+            while let Some(frame) = receiver.pop::<Bytes>().unwrap() {
+                received.push(frame);
+            }
+        }
+        assert_eq!(receiver.stats(), sender.stats());
+        received
+    }
+
+    #[cfg(feature = "compression")]
+    proptest! {
+        #[test]
+        fn frames_round_trip(
+            data in proptest::collection::vec(
+                proptest::collection::vec(any::<u8>(), 0..4096),
+                0..8,
+            ),
+            threshold in 0usize..1024,
+            split in 0.0f64..1.0,
+        ) {
+            let frames = data.into_iter().map(Bytes).collect::<Vec<_>>();
+            prop_assert_eq!(transfer(&frames, threshold, split), frames);
+        }
+    }
+
+    #[test]
+    fn max_frame_size() {
+        let mut sender = Marshaller::new();
+        sender.push(Bytes(vec![0xAA; 12])).unwrap();
+        sender.push(Bytes(vec![0xBB; 13])).unwrap();
+        let mut wire = vec![];
+        sender.read_to_end(&mut wire).unwrap();
+
+        let mut receiver = Marshaller::new().with_max_frame_bytes(16);
+        receiver.write_all(&wire).unwrap();
+        // Frame taking exactly the limit is accepted
+        assert_eq!(
+            receiver.pop::<Bytes>().unwrap(),
+            Some(Bytes(vec![0xAA; 12]))
+        );
+        assert!(matches!(
+            receiver.pop::<Bytes>(),
+            Err(FrameError::FrameTooLarge {
+                limit: 16,
+                actual: 17
+            })
+        ));
+        // Read queue is not advanced on error
+        assert_eq!(receiver.drain().unwrap(), wire[16..]);
+    }
+
+    #[test]
+    fn incomplete_frame_exceeding_max_size() {
+        let mut receiver = Marshaller::new().with_max_frame_bytes(16);
+        receiver.write_all(&100u32.to_be_bytes()).unwrap();
+        receiver.write_all(&[0; 12]).unwrap();
+        assert_eq!(receiver.pop::<Bytes>().unwrap(), None);
+        receiver.write_all(&[0]).unwrap();
+        assert!(matches!(
+            receiver.pop::<Bytes>(),
+            Err(FrameError::FrameTooLarge {
+                limit: 16,
+                actual: 17
+            })
+        ));
+    }
+
+    #[test]
+    fn sent_frame_exceeding_max_size() {
+        let mut sender = Marshaller::new().with_max_frame_bytes(16);
+        sender.push(Bytes(vec![0xAA; 12])).unwrap();
+        assert!(matches!(
+            sender.push(Bytes(vec![0xBB; 13])),
+            Err(FrameError::FrameTooLarge {
+                limit: 16,
+                actual: 17
+            })
+        ));
+        // Refused frame is not queued, even partially
+        assert_eq!(sender.queue_len(), 16);
+
+        let mut sender = LengthPrefixMarshaller::new().with_max_frame_bytes(16);
+        sender.push(Bytes(vec![0xAA; 12])).unwrap();
+        assert!(matches!(
+            sender.push(Bytes(vec![0xBB; 13])),
+            Err(FrameError::FrameTooLarge {
+                limit: 16,
+                actual: 17
+            })
+        ));
+        assert_eq!(sender.queue_len(), LEN_PREFIX_LEN + 16);
+    }
+
+    #[test]
+    fn length_prefix_split_across_reads() {
+        let mut sender = LengthPrefixMarshaller::new();
+        sender.push(Bytes(vec![0xAA; 12])).unwrap();
+        sender.push(Bytes(vec![0xBB; 13])).unwrap();
+        let mut wire = vec![];
+        sender.read_to_end(&mut wire).unwrap();
+        let second = LEN_PREFIX_LEN + 16;
+
+        let mut receiver = LengthPrefixMarshaller::new().with_max_frame_bytes(16);
+        receiver.write_all(&wire[..2]).unwrap();
+        assert_eq!(receiver.pop::<Bytes>().unwrap(), None);
+        receiver.write_all(&wire[2..second]).unwrap();
+        // Frame taking exactly the limit is accepted
+        assert_eq!(
+            receiver.pop::<Bytes>().unwrap(),
+            Some(Bytes(vec![0xAA; 12]))
+        );
+
+        // Frame is refused as soon as the second part of its length prefix
+        // arrives, without waiting for the frame data
+        receiver.write_all(&wire[second..second + 3]).unwrap();
+        assert_eq!(receiver.pop::<Bytes>().unwrap(), None);
+        receiver.write_all(&wire[second + 3..second + 4]).unwrap();
+        assert!(matches!(
+            receiver.pop::<Bytes>(),
+            Err(FrameError::FrameTooLarge {
+                limit: 16,
+                actual: 17
+            })
+        ));
+    }
+
+    #[test]
+    fn streamed_frames() {
+        let large = Bytes((0..100u8).collect());
+        let mut sender = LengthPrefixMarshaller::new();
+        sender.push(Bytes(b"before".to_vec())).unwrap();
+        sender.push(large.clone()).unwrap();
+        sender.push(Bytes(b"after".to_vec())).unwrap();
+        let mut wire = vec![];
+        sender.read_to_end(&mut wire).unwrap();
+
+        let mut receiver = LengthPrefixMarshaller::new().with_streaming(32);
+        let mut frames = vec![];
+        let mut data = vec![];
+        for part in wire.chunks(30) {
+            receiver.write_all(part).unwrap();
+            while let Some(item) = receiver.pop_stream::<Bytes>().unwrap() {
+                match item {
+                    Streamed::Frame(frame) => frames.push(frame),
+                    Streamed::Chunk(chunk) => {
+                        assert_eq!(chunk.id, 1);
+                        assert_eq!(chunk.offset, data.len());
+                        // Chunk is delivered as soon as its data arrive
+                        assert!(chunk.bytes.len() <= 30);
+                        data.extend(chunk.bytes);
+                        assert_eq!(chunk.last, data.len() == 104);
+                    }
+                }
+            }
+        }
+        // Small frames around the streamed one are delivered in order
+        assert_eq!(
+            frames,
+            vec![Bytes(b"before".to_vec()), Bytes(b"after".to_vec())]
+        );
+        assert_eq!(Bytes::unmarshall(data.as_slice()).unwrap(), Some(large));
+        assert!(receiver.end_of_stream().is_ok());
+    }
+
+    #[test]
+    fn truncated_streamed_frame() {
+        let mut sender = LengthPrefixMarshaller::new();
+        sender.push(Bytes(vec![0xAA; 100])).unwrap();
+        let mut wire = vec![];
+        sender.read_to_end(&mut wire).unwrap();
+
+        let mut receiver = LengthPrefixMarshaller::new().with_streaming(32);
+        receiver.write_all(&wire[..50]).unwrap();
+        assert!(matches!(
+            receiver.pop_stream::<Bytes>().unwrap(),
+            Some(Streamed::Chunk(FrameChunk {
+                id: 0,
+                offset: 0,
+                last: false,
+                ..
+            }))
+        ));
+        // Rest of the streamed frame can't be taken as a whole
+        assert!(matches!(
+            receiver.pop::<Bytes>(),
+            Err(FrameError::StreamInProgress { id: 0 })
+        ));
+
+        assert!(matches!(
+            receiver.end_of_stream(),
+            Err(FrameError::Truncated {
+                id: 0,
+                received: 50
+            })
+        ));
+
+        // Frames which are not streamed are reported truncated as well
+        let mut receiver = LengthPrefixMarshaller::new();
+        receiver.write_all(&wire[..2]).unwrap();
+        assert_eq!(receiver.pop::<Bytes>().unwrap(), None);
+        assert!(matches!(
+            receiver.end_of_stream(),
+            Err(FrameError::Truncated { id: 0, received: 2 })
+        ));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn small_frames_are_not_compressed() {
+        let mut marshaller = CompressedMarshaller::new();
+        marshaller.push(Bytes(vec![0; 16])).unwrap();
+        let mut wire = vec![];
+        marshaller.read_to_end(&mut wire).unwrap();
+        assert_eq!(wire[0], FLAG_RAW);
+        assert_eq!(wire.len(), HEADER_LEN + 4 + 16);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compression_stats() {
+        let mut marshaller = CompressedMarshaller::new();
+        assert_eq!(marshaller.stats().ratio, 1.0);
+        marshaller.push(Bytes(vec![0; 64 * 1024])).unwrap();
+        let stats = marshaller.stats();
+        assert_eq!(stats.uncompressed_bytes, 4 + 64 * 1024);
+        assert_eq!(stats.compressed_bytes, marshaller.queue_len() as u64);
+        assert!(stats.ratio < 0.1);
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn unknown_flag() {
+        let mut marshaller = CompressedMarshaller::new();
+        marshaller.write_all(&[0xFF, 0, 0, 0, 0]).unwrap();
+        assert!(matches!(
+            marshaller.pop::<Bytes>(),
+            Err(DecompressionError::UnknownFlag(0xFF))
+        ));
+    }
+
+    #[cfg(feature = "checksum")]
+    #[test]
+    fn checksummed_frames() {
+        let mut sender = ChecksummedMarshaller::new();
+        sender.push(Bytes(b"first".to_vec())).unwrap();
+        sender.push(Bytes(b"second".to_vec())).unwrap();
+        let mut wire = vec![];
+        sender.read_to_end(&mut wire).unwrap();
+        // Length prefix covers the frame and the checksum
+        assert_eq!(
+            &wire[..LEN_PREFIX_LEN],
+            &(4 + 5 + CHECKSUM_LEN as u32).to_be_bytes()
+        );
+
+        let mut receiver = ChecksummedMarshaller::new();
+        receiver.write_all(&wire[..7]).unwrap();
+        assert_eq!(receiver.pop::<Bytes>().unwrap(), None);
+        receiver.write_all(&wire[7..]).unwrap();
+        assert_eq!(
+            receiver.pop::<Bytes>().unwrap(),
+            Some(Bytes(b"first".to_vec()))
+        );
+        assert_eq!(
+            receiver.pop::<Bytes>().unwrap(),
+            Some(Bytes(b"second".to_vec()))
+        );
+        assert_eq!(receiver.pop::<Bytes>().unwrap(), None);
+    }
+
+    #[cfg(feature = "checksum")]
+    #[test]
+    fn corrupted_frame_is_detected() {
+        let mut sender = ChecksummedMarshaller::new();
+        sender.push(Bytes(b"corrupted".to_vec())).unwrap();
+        sender.push(Bytes(b"intact".to_vec())).unwrap();
+        let mut wire = vec![];
+        sender.read_to_end(&mut wire).unwrap();
+        wire[LEN_PREFIX_LEN + 6] ^= 0x01;
+
+        let mut receiver = ChecksummedMarshaller::new();
+        receiver.write_all(&wire).unwrap();
+        assert!(matches!(
+            receiver.pop::<Bytes>(),
+            Err(FrameError::ChecksumMismatch { expected, got }) if expected != got
+        ));
+        // Corrupted frame is dropped, and the following one is still readable
+        assert_eq!(
+            receiver.pop::<Bytes>().unwrap(),
+            Some(Bytes(b"intact".to_vec()))
+        );
+    }
+
+    #[cfg(feature = "checksum")]
+    #[test]
+    fn authenticated_frames() {
+        let mut sender = AuthenticatedMarshaller::new(b"secret");
+        sender.push(Bytes(b"first".to_vec())).unwrap();
+        sender.push(Bytes(b"second".to_vec())).unwrap();
+        let mut wire = vec![];
+        sender.read_to_end(&mut wire).unwrap();
+
+        let mut receiver = AuthenticatedMarshaller::new(b"secret");
+        receiver.write_all(&wire).unwrap();
+        assert_eq!(
+            receiver.pop::<Bytes>().unwrap(),
+            Some(Bytes(b"first".to_vec()))
+        );
+        assert_eq!(
+            receiver.pop::<Bytes>().unwrap(),
+            Some(Bytes(b"second".to_vec()))
+        );
+
+        let mut receiver = AuthenticatedMarshaller::new(b"other secret");
+        receiver.write_all(&wire).unwrap();
+        assert!(matches!(
+            receiver.pop::<Bytes>(),
+            Err(FrameError::InvalidMac)
+        ));
+    }
+
+    #[cfg(feature = "checksum")]
+    #[test]
+    fn tampered_frames_are_detected() {
+        let mut sender = AuthenticatedMarshaller::new(b"secret");
+        sender.push(Bytes(b"first".to_vec())).unwrap();
+        let mut first = vec![];
+        sender.read_to_end(&mut first).unwrap();
+
+        let mut tampered = first.clone();
+        tampered[LEN_PREFIX_LEN + 4] ^= 0x01;
+        let mut receiver = AuthenticatedMarshaller::new(b"secret");
+        receiver.write_all(&tampered).unwrap();
+        assert!(matches!(
+            receiver.pop::<Bytes>(),
+            Err(FrameError::InvalidMac)
+        ));
+
+        // Replayed frame does not authenticate
+        let mut receiver = AuthenticatedMarshaller::new(b"secret");
+        receiver.write_all(&first).unwrap();
+        receiver.write_all(&first).unwrap();
+        assert!(receiver.pop::<Bytes>().unwrap().is_some());
+        assert!(matches!(
+            receiver.pop::<Bytes>(),
+            Err(FrameError::InvalidMac)
+        ));
+    }
+
+    /// Xorshift generator making the randomized tests reproducible.
+    #[cfg(all(feature = "compression", feature = "checksum"))]
+    struct Rng(u64);
+
+    #[cfg(all(feature = "compression", feature = "checksum"))]
+    impl Rng {
+        fn next(&mut self, max: u64) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0 % max
+        }
+    }
+
+    #[cfg(all(feature = "compression", feature = "checksum"))]
+    #[test]
+    fn pipeline_round_trip() {
+        let codec = || {
+            pipeline!(
+                LengthPrefixMarshaller::new(),
+                CompressedMarshaller::new(),
+                ChecksummedMarshaller::new(),
+            )
+        };
+        let mut rng = Rng(0x5EED);
+        // Payloads repeat random patterns, so the longer ones are compressible
+        let frames = (0..1000)
+            .map(|_| {
+                let len = rng.next(2048) as usize;
+                let pattern = (0..1 + rng.next(64))
+                    .map(|_| rng.next(256) as u8)
+                    .collect::<Vec<_>>();
+                Bytes(pattern.into_iter().cycle().take(len).collect())
+            })
+            .collect::<Vec<_>>();
+
+        let mut sender = CodecMarshaller::new(codec());
+        for frame in &frames {
+            sender.push(frame.clone()).unwrap();
+        }
+        let mut wire = vec![];
+        sender.read_to_end(&mut wire).unwrap();
+
+        let mut receiver = CodecMarshaller::new(codec());
+        let mut received = vec![];
+        let mut pos = 0;
+        while pos < wire.len() {
+            let chunk = (1 + rng.next(4096) as usize).min(wire.len() - pos);
+            receiver.write_all(&wire[pos..pos + chunk]).unwrap();
+            pos += chunk;
+            while let Some(frame) = receiver.pop::<Bytes>().unwrap() {
+                received.push(frame);
+            }
+        }
+        assert_eq!(received, frames);
+        let compression = receiver.codec().second().first().stats();
+        assert_eq!(compression, sender.codec().second().first().stats());
+        assert!(compression.ratio < 1.0);
+    }
+
+    #[cfg(all(feature = "compression", feature = "checksum"))]
+    #[test]
+    fn pipeline_detects_corruption() {
+        let mut sender = CodecMarshaller::new(pipeline!(
+            CompressedMarshaller::new(),
+            ChecksummedMarshaller::new()
+        ));
+        sender.push(Bytes(vec![0; 1024])).unwrap();
+        let mut wire = vec![];
+        sender.read_to_end(&mut wire).unwrap();
+        wire[LEN_PREFIX_LEN + 2] ^= 0x01;
+
+        let mut receiver = CodecMarshaller::new(pipeline!(
+            Box::new(CompressedMarshaller::new()) as Box<dyn Codec<Error = _>>,
+            ChecksummedMarshaller::new()
+        ));
+        receiver.write_all(&wire).unwrap();
+        assert!(matches!(
+            receiver.pop::<Bytes>(),
+            Err(CodecError::Codec(PipelineError::Second(
+                FrameError::ChecksumMismatch { .. }
+            )))
+        ));
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn compressed_frame_exceeding_max_size() {
+        let mut sender = CompressedMarshaller::new();
+        sender.push(Bytes(vec![0; 64 * 1024])).unwrap();
+        let mut wire = vec![];
+        sender.read_to_end(&mut wire).unwrap();
+
+        // Compressed frame is small, but it is refused before decompression
+        let mut receiver = CompressedMarshaller::new().with_max_frame_bytes(1024);
+        receiver.write_all(&wire).unwrap();
+        assert!(matches!(
+            receiver.pop::<Bytes>(),
+            Err(DecompressionError::FrameTooLarge {
+                limit: 1024,
+                actual,
+            }) if actual == 4 + 64 * 1024
+        ));
+
+        let mut receiver = CompressedMarshaller::new().with_max_frame_bytes(16);
+        receiver.write_all(&wire[..HEADER_LEN]).unwrap();
+        assert!(matches!(
+            receiver.pop::<Bytes>(),
+            Err(DecompressionError::FrameTooLarge { limit: 16, .. })
+        ));
+    }
+
+    #[cfg(feature = "checksum")]
+    #[test]
+    fn checksummed_frame_exceeding_max_size() {
+        let mut sender = ChecksummedMarshaller::new();
+        sender.push(Bytes(vec![0; 13])).unwrap();
+        let mut wire = vec![];
+        sender.read_to_end(&mut wire).unwrap();
+
+        // Frame is refused once its length is known, before it is received
+        let mut receiver = ChecksummedMarshaller::new().with_max_frame_bytes(16);
+        receiver.write_all(&wire[..LEN_PREFIX_LEN]).unwrap();
+        assert!(matches!(
+            receiver.pop::<Bytes>(),
+            Err(FrameError::FrameTooLarge {
+                limit: 16,
+                actual: 17
+            })
+        ));
+
+        let mut receiver = ChecksummedMarshaller::new().with_max_frame_bytes(17);
+        receiver.write_all(&wire).unwrap();
+        assert_eq!(receiver.pop::<Bytes>().unwrap(), Some(Bytes(vec![0; 13])));
+    }
+}
@@ -0,0 +1,560 @@
+use std::fmt::{self, Debug, Formatter};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::{io, option};
+
+use crate::connection::Proxy;
+use crate::socks5::{Socks5Dst, ToSocks5Dst};
+
+/// Maximal length of the status line of the proxy response.
+const MAX_STATUS_LINE_LEN: usize = 1024;
+/// Maximal length of the proxy response headers, including the status line.
+const MAX_RESPONSE_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum HttpConnectError {
+    #[from]
+    #[from(io::ErrorKind)]
+    #[display(inner)]
+    Io(io::Error),
+
+    /// proxy sent invalid HTTP response
+    InvalidResponse,
+
+    /// proxy requires authentication or rejected the provided credentials
+    AuthRequired,
+
+    /// proxy does not allow connections to the destination
+    Forbidden,
+
+    /// proxy failed to connect to the destination
+    BadGateway,
+
+    /// proxy is unavailable
+    Unavailable,
+
+    /// proxy timed out connecting to the destination
+    GatewayTimeout,
+
+    /// proxy refused the connection with HTTP status {0}
+    Refused(u16),
+
+    /// username must not contain colons, and credentials must not contain
+    /// control characters
+    InvalidCredentials,
+
+    /// destination address {0} is not supported
+    InvalidDestination(String),
+}
+
+impl HttpConnectError {
+    fn with_status(status: u16) -> Self {
+        match status {
+            403 => HttpConnectError::Forbidden,
+            407 => HttpConnectError::AuthRequired,
+            502 => HttpConnectError::BadGateway,
+            503 => HttpConnectError::Unavailable,
+            504 => HttpConnectError::GatewayTimeout,
+            status => HttpConnectError::Refused(status),
+        }
+    }
+
+    /// Returns status of the proxy response if the error is caused by a
+    /// non-2xx status.
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            HttpConnectError::Forbidden => Some(403),
+            HttpConnectError::AuthRequired => Some(407),
+            HttpConnectError::BadGateway => Some(502),
+            HttpConnectError::Unavailable => Some(503),
+            HttpConnectError::GatewayTimeout => Some(504),
+            HttpConnectError::Refused(status) => Some(*status),
+            _ => None,
+        }
+    }
+}
+
+/// HTTP proxy tunnelling connections with the `CONNECT` method.
+#[derive(Clone, Eq, PartialEq)]
+pub struct HttpConnect {
+    proxy: SocketAddr,
+    credentials: Option<(String, String)>,
+}
+
+impl Debug for HttpConnect {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        // Password must not leak into the logs
+        f.debug_struct("HttpConnect")
+            .field("proxy", &self.proxy)
+            .field("username", &self.credentials.as_ref().map(|(user, _)| user))
+            .finish_non_exhaustive()
+    }
+}
+
+impl HttpConnect {
+    pub fn new(proxy_addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            proxy: proxy_addr
+                .to_socket_addrs()?
+                .next()
+                .ok_or(io::ErrorKind::InvalidInput)?,
+            credentials: None,
+        })
+    }
+
+    /// Sets credentials for the proxy basic authentication.
+    ///
+    /// # Errors
+    ///
+    /// If the username contains colons or the credentials contain control
+    /// characters, which can't be sent in the basic authentication header.
+    pub fn with_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Result<Self, HttpConnectError> {
+        let (username, password) = (username.into(), password.into());
+        if username.contains(':')
+            || username.contains(char::is_control)
+            || password.contains(char::is_control)
+        {
+            return Err(HttpConnectError::InvalidCredentials);
+        }
+        self.credentials = Some((username, password));
+        Ok(self)
+    }
+
+    /// Returns username used for the proxy authentication, if any.
+    pub fn username(&self) -> Option<&str> {
+        self.credentials
+            .as_ref()
+            .map(|(username, _)| username.as_str())
+    }
+
+    fn connect_request(&self, dst: &Socks5Dst) -> Result<String, HttpConnectError> {
+        let authority = match dst {
+            // IPv6 addresses are put into brackets
+            Socks5Dst::Ip(addr) => addr.to_string(),
+            Socks5Dst::Domain(host, port)
+                if !host.is_empty()
+                    && !host.contains(|c: char| c.is_control() || c.is_whitespace()) =>
+            {
+                format!("{host}:{port}")
+            }
+            Socks5Dst::Domain(host, port) => {
+                return Err(HttpConnectError::InvalidDestination(format!(
+                    "{host}:{port}"
+                )))
+            }
+        };
+        let mut request = format!("CONNECT {authority} HTTP/1.1\r\nHost: {authority}\r\n");
+        if let Some((username, password)) = &self.credentials {
+            let credentials = base64_encode(format!("{username}:{password}").as_bytes());
+            request.push_str(&format!("Proxy-Authorization: Basic {credentials}\r\n"));
+        }
+        request.push_str("\r\n");
+        Ok(request)
+    }
+
+    /// Constructs handshake requesting the proxy to connect to the
+    /// destination.
+    ///
+    /// # Errors
+    ///
+    /// If the destination can't be put into the `CONNECT` request.
+    pub fn handshake(&self, dst: &Socks5Dst) -> Result<HttpConnectHandshake, HttpConnectError> {
+        Ok(HttpConnectHandshake::with_request(
+            self.connect_request(dst)?.into_bytes(),
+        ))
+    }
+}
+
+/// HTTP `CONNECT` handshake state machine, which does not perform I/O on its
+/// own, similar to [`crate::socks5::Socks5Handshake`]. This allows to drive
+/// the handshake from non-blocking reads and writes.
+///
+/// The handshake starts by sending [`HttpConnectHandshake::request`] to the
+/// proxy. After that, all data read from the proxy are fed into
+/// [`HttpConnectHandshake::advance`] until the handshake is complete. The
+/// length of the proxy response is not known in advance, so the last read
+/// may contain data following the response headers: these data belong to
+/// the destination connection and must be delivered to the session. Reading
+/// no more than [`HttpConnectHandshake::next_read_len`] bytes at once ensures
+/// that nothing is read past the headers.
+#[derive(Clone, Debug)]
+pub struct HttpConnectHandshake {
+    request: Vec<u8>,
+    status_line: Vec<u8>,
+    /// Number of bytes of the "\r\n\r\n" sequence matched so far.
+    terminator: usize,
+    /// Number of bytes of the response received so far.
+    received: usize,
+}
+
+impl HttpConnectHandshake {
+    fn with_request(request: Vec<u8>) -> Self {
+        HttpConnectHandshake {
+            request,
+            status_line: Vec::with_capacity(64),
+            terminator: 0,
+            received: 0,
+        }
+    }
+
+    /// Returns the `CONNECT` request which must be sent to the proxy.
+    pub fn request(&self) -> &[u8] {
+        &self.request
+    }
+
+    /// Detects whether the proxy has connected to the destination.
+    pub fn is_complete(&self) -> bool {
+        self.terminator == 4
+    }
+
+    /// Returns maximal number of bytes which can be read from the proxy
+    /// without reading past the end of the response headers; zero once the
+    /// handshake is complete.
+    pub fn next_read_len(&self) -> usize {
+        4 - self.terminator
+    }
+
+    /// Processes data received from the proxy, returning the number of bytes
+    /// which belong to the proxy response. Once the handshake is complete,
+    /// the rest of the input belongs to the destination connection.
+    ///
+    /// Response headers are skipped without being stored.
+    ///
+    /// # Errors
+    ///
+    /// If the proxy refuses to connect to the destination, responding with a
+    /// non-2xx status, or if it sends an invalid response.
+    pub fn advance(&mut self, input: &[u8]) -> Result<usize, HttpConnectError> {
+        for (pos, byte) in input.iter().enumerate() {
+            if self.is_complete() {
+                return Ok(pos);
+            }
+            if self.received == MAX_RESPONSE_LEN {
+                return Err(HttpConnectError::InvalidResponse);
+            }
+            self.received += 1;
+            self.terminator = match (self.terminator, byte) {
+                (0 | 2, b'\r') => self.terminator + 1,
+                (1 | 3, b'\n') => self.terminator + 1,
+                (_, b'\r') => 1,
+                _ => 0,
+            };
+            if self.status_line.last() != Some(&b'\n') {
+                if self.status_line.len() == MAX_STATUS_LINE_LEN {
+                    return Err(HttpConnectError::InvalidResponse);
+                }
+                self.status_line.push(*byte);
+            }
+            if self.is_complete() {
+                Self::check_status(&self.status_line)?;
+            }
+        }
+        Ok(input.len())
+    }
+
+    /// Reads the proxy response from a blocking stream. Nothing is read past
+    /// the end of the headers: all the following data belong to the tunnel.
+    fn read_response(&mut self, stream: &mut impl Read) -> Result<(), HttpConnectError> {
+        let mut buf = [0u8; 4];
+        while !self.is_complete() {
+            let buf = &mut buf[..self.next_read_len()];
+            stream.read_exact(buf)?;
+            self.advance(buf)?;
+        }
+        Ok(())
+    }
+
+    /// Parses the status line in `HTTP/1.x SSS [reason]\r\n` format.
+    fn check_status(line: &[u8]) -> Result<(), HttpConnectError> {
+        let line = std::str::from_utf8(line).map_err(|_| HttpConnectError::InvalidResponse)?;
+        let mut parts = line.trim_end().splitn(3, ' ');
+        match parts.next() {
+            Some("HTTP/1.0" | "HTTP/1.1") => {}
+            _ => return Err(HttpConnectError::InvalidResponse),
+        }
+        let status = parts
+            .next()
+            .filter(|status| status.len() == 3)
+            .and_then(|status| status.parse::<u16>().ok())
+            .ok_or(HttpConnectError::InvalidResponse)?;
+        match status {
+            200..=299 => Ok(()),
+            status => Err(HttpConnectError::with_status(status)),
+        }
+    }
+}
+
+/// Encodes data with the standard base64 alphabet and padding.
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity((data.len() / 3 + 1) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for no in 0..4 {
+            if no <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - 6 * no) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+impl ToSocketAddrs for HttpConnect {
+    type Iter = option::IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+        Ok(Some(self.proxy).into_iter())
+    }
+}
+
+impl Proxy for HttpConnect {
+    type Error = HttpConnectError;
+
+    fn connect_blocking<A: ToSocks5Dst>(&self, addr: A) -> Result<TcpStream, Self::Error> {
+        let dst = addr
+            .to_socks5_dst()
+            .map_err(|err| HttpConnectError::InvalidDestination(err.to_string()))?;
+        let mut stream = TcpStream::connect(self.proxy)?;
+        self.tunnel(&mut stream, dst)?;
+        Ok(stream)
+    }
+
+    /// Performs the proxy handshake in blocking mode, returning the stream
+    /// switched to non-blocking mode once the tunnel is established.
+    #[cfg(feature = "socket2")]
+    fn connect_nonblocking<A: ToSocks5Dst>(&self, addr: A) -> Result<TcpStream, Self::Error> {
+        let stream = self.connect_blocking(addr)?;
+        stream.set_nonblocking(true)?;
+        Ok(stream)
+    }
+
+    fn tunnel(&self, stream: &mut TcpStream, dst: Socks5Dst) -> Result<(), Self::Error> {
+        let mut handshake = self.handshake(&dst)?;
+        stream.write_all(handshake.request())?;
+        handshake.read_response(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    /// Runs proxy accepting a single `CONNECT` request, to which it replies
+    /// with the given response followed by the data sent through the tunnel.
+    /// Returns the proxy address and a handle providing the request headers.
+    fn proxy(response: &'static str) -> (SocketAddr, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![];
+            let mut byte = [0u8; 1];
+            while !request.ends_with(b"\r\n\r\n") {
+                stream.read_exact(&mut byte).unwrap();
+                request.push(byte[0]);
+            }
+            let mut reply = response.as_bytes().to_vec();
+            reply.extend(b"tunnelled");
+            stream.write_all(&reply).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (addr, handle)
+    }
+
+    #[test]
+    fn base64() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foobar"), "Zm9vYmFy");
+        assert_eq!(base64_encode(b"user:secret"), "dXNlcjpzZWNyZXQ=");
+    }
+
+    #[test]
+    fn unauthenticated() {
+        let (addr, handle) = proxy(
+            "HTTP/1.1 200 Connection established\r\n\
+             Proxy-Agent: test-proxy/1.0\r\n\
+             Via: 1.1 proxy.example.com\r\n\r\n",
+        );
+        let proxy = HttpConnect::new(addr).unwrap();
+        let mut stream = proxy
+            .connect_blocking("example.com:443".to_owned())
+            .unwrap();
+        // Data following the response headers are not consumed by the
+        // handshake
+        let mut data = vec![];
+        stream.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"tunnelled");
+        assert_eq!(
+            handle.join().unwrap(),
+            "CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn authenticated() {
+        let (addr, handle) = proxy("HTTP/1.0 200 OK\r\n\r\n");
+        let proxy = HttpConnect::new(addr)
+            .unwrap()
+            .with_credentials("user", "secret")
+            .unwrap();
+        proxy.connect_blocking("[::1]:8080".to_owned()).unwrap();
+        assert_eq!(
+            handle.join().unwrap(),
+            "CONNECT [::1]:8080 HTTP/1.1\r\nHost: [::1]:8080\r\n\
+             Proxy-Authorization: Basic dXNlcjpzZWNyZXQ=\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn auth_required() {
+        let (addr, handle) = proxy(
+            "HTTP/1.1 407 Proxy Authentication Required\r\n\
+             Proxy-Authenticate: Basic realm=\"proxy\"\r\n\
+             Content-Length: 0\r\n\r\n",
+        );
+        let proxy = HttpConnect::new(addr).unwrap();
+        assert!(matches!(
+            proxy.connect_blocking("example.com:443".to_owned()),
+            Err(HttpConnectError::AuthRequired)
+        ));
+        handle.join().unwrap();
+    }
+
+    #[test]
+    fn error_responses() {
+        let check = |response: &str| {
+            HttpConnectHandshake::with_request(vec![]).read_response(&mut response.as_bytes())
+        };
+        assert!(matches!(
+            check("HTTP/1.1 503 Service Unavailable\r\n\r\n"),
+            Err(HttpConnectError::Unavailable)
+        ));
+        assert!(matches!(
+            check("HTTP/1.1 403 Forbidden\r\nX-Reason: policy\r\n\r\n"),
+            Err(HttpConnectError::Forbidden)
+        ));
+        assert!(matches!(
+            check("HTTP/1.1 418 I'm a teapot\r\n\r\n"),
+            Err(HttpConnectError::Refused(418))
+        ));
+        assert_eq!(
+            check("HTTP/1.1 502 Bad Gateway\r\n\r\n")
+                .unwrap_err()
+                .status(),
+            Some(502)
+        );
+        assert!(matches!(
+            check("SSH-2.0-OpenSSH_9.0\r\n\r\n"),
+            Err(HttpConnectError::InvalidResponse)
+        ));
+        assert!(matches!(
+            check("HTTP/1.1 200 OK\r\n"),
+            Err(HttpConnectError::Io(err)) if err.kind() == io::ErrorKind::UnexpectedEof
+        ));
+        let long_line = format!("HTTP/1.1 200 {}\r\n\r\n", "a".repeat(MAX_STATUS_LINE_LEN));
+        assert!(matches!(
+            check(&long_line),
+            Err(HttpConnectError::InvalidResponse)
+        ));
+        let endless = format!(
+            "HTTP/1.1 200 OK\r\n{}",
+            "X-Header: a\r\n".repeat(MAX_RESPONSE_LEN)
+        );
+        assert!(matches!(
+            check(&endless),
+            Err(HttpConnectError::InvalidResponse)
+        ));
+    }
+
+    #[test]
+    fn invalid_credentials() {
+        let proxy = HttpConnect::new("127.0.0.1:8080").unwrap();
+        assert!(matches!(
+            proxy.clone().with_credentials("us:er", "secret"),
+            Err(HttpConnectError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            proxy.with_credentials("user", "sec\r\nret"),
+            Err(HttpConnectError::InvalidCredentials)
+        ));
+    }
+
+    #[test]
+    fn handshake_partial_reads() {
+        let proxy = HttpConnect::new("127.0.0.1:8080").unwrap();
+        let mut handshake = proxy
+            .handshake(&Socks5Dst::Domain(s!("example.com"), 443))
+            .unwrap();
+        assert_eq!(
+            handshake.request(),
+            b"CONNECT example.com:443 HTTP/1.1\r\nHost: example.com:443\r\n\r\n"
+        );
+
+        assert_eq!(handshake.next_read_len(), 4);
+        assert_eq!(handshake.advance(b"HTTP/1.1 2").unwrap(), 10);
+        assert_eq!(handshake.advance(b"00 OK\r").unwrap(), 6);
+        assert_eq!(handshake.next_read_len(), 3);
+        assert_eq!(handshake.advance(b"\nVia: proxy\r\n").unwrap(), 13);
+        assert_eq!(handshake.next_read_len(), 2);
+        assert!(!handshake.is_complete());
+
+        // Data following the headers are not consumed by the handshake
+        assert_eq!(handshake.advance(b"\r\ntunnelled").unwrap(), 2);
+        assert!(handshake.is_complete());
+        assert_eq!(handshake.next_read_len(), 0);
+        assert_eq!(handshake.advance(b"tunnelled").unwrap(), 0);
+    }
+
+    #[test]
+    fn nonblocking() {
+        let (addr, handle) = proxy("HTTP/1.1 200 Connection established\r\n\r\n");
+        let proxy = HttpConnect::new(addr).unwrap();
+        let mut handshake = proxy
+            .handshake(&Socks5Dst::Domain(s!("example.com"), 443))
+            .unwrap();
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(handshake.request()).unwrap();
+        stream.set_nonblocking(true).unwrap();
+
+        let mut session_data = vec![];
+        let mut buf = [0u8; 1024];
+        loop {
+            let len = match stream.read(&mut buf) {
+                Ok(0) => break,
+                Ok(len) => len,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    thread::yield_now();
+                    continue;
+                }
+                Err(err) => panic!("{err}"),
+            };
+            let consumed = if handshake.is_complete() {
+                0
+            } else {
+                handshake.advance(&buf[..len]).unwrap()
+            };
+            session_data.extend_from_slice(&buf[consumed..len]);
+        }
+        assert!(handshake.is_complete());
+        assert_eq!(session_data, b"tunnelled");
+        handle.join().unwrap();
+    }
+}
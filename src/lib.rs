@@ -1,5 +1,6 @@
 #[macro_use]
 extern crate amplify;
+#[cfg(feature = "log")]
 extern crate log_crate as log;
 
 #[cfg(feature = "re-actor")]
@@ -11,17 +12,41 @@ pub mod resources;
 mod auth;
 mod connection;
 mod frame;
+pub mod http_connect;
 mod listener;
 pub mod noise;
+#[cfg(feature = "io-reactor")]
+mod pool;
+pub mod proxy_chain;
+pub mod proxy_protocol;
 mod session;
+pub mod socks4;
 pub mod socks5;
-mod transcoders;
+#[cfg(all(feature = "io-reactor", feature = "socket2"))]
+pub mod socks5_server;
+pub mod transcoders;
 pub mod tunnel;
 
-pub use auth::Authenticator;
+pub use auth::{AllowList, AuthPolicy, AuthVerdict, Authenticator, DenyList, RejectReason};
 pub use connection::{Address, NetConnection, Proxy};
-pub use frame::{Frame, Marshaller};
-pub use listener::NetListener;
+#[cfg(feature = "checksum")]
+pub use frame::{AuthenticatedMarshaller, ChecksummedMarshaller};
+pub use frame::{
+    Codec, CodecError, CodecMarshaller, CodecPipeline, Frame, FrameChunk, FrameError,
+    LengthPrefixMarshaller, Marshaller, PipelineError, Streamed, DEFAULT_MAX_FRAME_BYTES,
+};
+#[cfg(feature = "compression")]
+pub use frame::{
+    CompressedMarshaller, CompressionStats, DecompressionError, DEFAULT_COMPRESSION_THRESHOLD,
+};
+pub use listener::{
+    BoundedListener, CapReached, ConnectionCounter, IpConnectionCounter, IpConnectionGuard,
+    IpNetwork, NetListener, PeerLimitReached, RateLimited, RateLimitedListener,
+    DEFAULT_MAX_CONNECTIONS, DEFAULT_MAX_CONNECTIONS_PER_IP, DEFAULT_RATE_LIMIT_WINDOW,
+};
+#[cfg(feature = "io-reactor")]
+pub use pool::{ConnectionPool, PoolError, PoolStats, PooledConnection};
+pub use proxy_protocol::{ProxyAddrs, ProxyHeaderError, ProxyListener, ProxyReader, ProxyStream};
 #[cfg(feature = "io-reactor")]
-pub use resources::{ListenerEvent, NetAccept, NetResource, SessionEvent};
-pub use session::NetSession;
+pub use resources::{ListenerEvent, NetAccept, NetResource, ReconnectPolicy, SessionEvent};
+pub use session::{NetSession, SessionStats};
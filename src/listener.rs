@@ -1,6 +1,11 @@
-use std::io;
-use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
-use std::os::unix::io::AsRawFd;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{io, mem};
 
 use crate::connection::NetConnection;
 
@@ -11,6 +16,24 @@ pub trait NetListener: AsRawFd + Send {
     where
         Self: Sized;
 
+    /// Binds listener to the address with `SO_REUSEPORT` socket option set,
+    /// such that multiple processes (or multiple listeners within a process)
+    /// can bind the same address at the same time. This allows a new instance
+    /// of a service to start accepting connections before the old one shuts
+    /// down, providing zero-downtime restarts. On Linux the kernel
+    /// load-balances inbound `SYN` packets between all the listeners bound to
+    /// the address.
+    ///
+    /// All the listeners sharing the address must set the option and must be
+    /// owned by the same user.
+    ///
+    /// On platforms not supporting `SO_REUSEPORT` only `SO_REUSEADDR` is set
+    /// and a warning is logged; binding an address which is already in use
+    /// fails there with [`io::ErrorKind::AddrInUse`].
+    fn with_reuseport(addr: SocketAddr) -> io::Result<Self>
+    where
+        Self: Sized;
+
     fn accept(&self) -> io::Result<Self::Stream>;
 
     fn local_addr(&self) -> SocketAddr;
@@ -26,6 +49,124 @@ pub trait NetListener: AsRawFd + Send {
     fn take_error(&self) -> io::Result<Option<io::Error>>;
 }
 
+/// Maximal length of the queue of pending connections of the listeners bound
+/// with [`NetListener::with_reuseport`].
+const LISTEN_BACKLOG: libc::c_int = 128;
+
+#[cfg(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly",
+))]
+const SO_REUSEPORT: Option<libc::c_int> = Some(libc::SO_REUSEPORT);
+#[cfg(not(any(
+    target_os = "linux",
+    target_os = "android",
+    target_os = "macos",
+    target_os = "ios",
+    target_os = "freebsd",
+    target_os = "openbsd",
+    target_os = "netbsd",
+    target_os = "dragonfly",
+)))]
+const SO_REUSEPORT: Option<libc::c_int> = None;
+
+fn set_sockopt(fd: RawFd, opt: libc::c_int) -> io::Result<()> {
+    let enable: libc::c_int = 1;
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            opt,
+            &enable as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Creates TCP listener with the `SO_REUSEPORT` option set before binding it
+/// to the address (see [`NetListener::with_reuseport`]).
+fn bind_reuseport(addr: SocketAddr) -> io::Result<TcpListener> {
+    let domain = match addr {
+        SocketAddr::V4(_) => libc::AF_INET,
+        SocketAddr::V6(_) => libc::AF_INET6,
+    };
+    let fd = unsafe { libc::socket(domain, libc::SOCK_STREAM, 0) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Takes ownership of the socket, closing it on errors
+    let listener = unsafe { TcpListener::from_raw_fd(fd) };
+
+    set_sockopt(fd, libc::SO_REUSEADDR)?;
+    if let Some(opt) = SO_REUSEPORT {
+        set_sockopt(fd, opt)?;
+    } else {
+        #[cfg(feature = "log")]
+        log::warn!(
+            target: "listener",
+            "SO_REUSEPORT is not supported on this platform, binding {addr} with SO_REUSEADDR only"
+        );
+        #[cfg(feature = "tracing")]
+        tracing::warn!(
+            target: "listener",
+            %addr,
+            "SO_REUSEPORT is not supported on this platform, binding with SO_REUSEADDR only"
+        );
+    }
+
+    let res = match addr {
+        SocketAddr::V4(addr) => {
+            let mut sockaddr: libc::sockaddr_in = unsafe { mem::zeroed() };
+            sockaddr.sin_family = libc::AF_INET as libc::sa_family_t;
+            sockaddr.sin_port = addr.port().to_be();
+            sockaddr.sin_addr = libc::in_addr {
+                s_addr: u32::from_ne_bytes(addr.ip().octets()),
+            };
+            unsafe {
+                libc::bind(
+                    fd,
+                    &sockaddr as *const libc::sockaddr_in as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+                )
+            }
+        }
+        SocketAddr::V6(addr) => {
+            let mut sockaddr: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+            sockaddr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sockaddr.sin6_port = addr.port().to_be();
+            sockaddr.sin6_flowinfo = addr.flowinfo();
+            sockaddr.sin6_addr = libc::in6_addr {
+                s6_addr: addr.ip().octets(),
+            };
+            sockaddr.sin6_scope_id = addr.scope_id();
+            unsafe {
+                libc::bind(
+                    fd,
+                    &sockaddr as *const libc::sockaddr_in6 as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_in6>() as libc::socklen_t,
+                )
+            }
+        }
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if unsafe { libc::listen(fd, LISTEN_BACKLOG) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(listener)
+}
+
 impl NetListener for TcpListener {
     type Stream = TcpStream;
 
@@ -36,6 +177,13 @@ impl NetListener for TcpListener {
         TcpListener::bind(addr)
     }
 
+    fn with_reuseport(addr: SocketAddr) -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        bind_reuseport(addr)
+    }
+
     fn accept(&self) -> io::Result<Self::Stream> {
         Ok(TcpListener::accept(self)?.0)
     }
@@ -89,6 +237,13 @@ impl NetListener for socket2::Socket {
         Ok(socket)
     }
 
+    fn with_reuseport(addr: SocketAddr) -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        bind_reuseport(addr).map(socket2::Socket::from)
+    }
+
     fn accept(&self) -> io::Result<Self::Stream> {
         Ok(socket2::Socket::accept(self)?.0)
     }
@@ -123,3 +278,885 @@ impl NetListener for socket2::Socket {
         socket2::Socket::take_error(self)
     }
 }
+
+/// Default number of connections a single IP address may open within
+/// [`DEFAULT_RATE_LIMIT_WINDOW`] to a [`RateLimitedListener`].
+pub const DEFAULT_MAX_CONNECTIONS_PER_IP: u32 = 16;
+/// Default time window of a [`RateLimitedListener`].
+pub const DEFAULT_RATE_LIMIT_WINDOW: Duration = Duration::from_secs(1);
+
+/// Number of tracked IP addresses after which the addresses with expired
+/// windows are forgotten by a [`RateLimitedListener`].
+const RATE_LIMIT_PRUNE_THRESHOLD: usize = 1024;
+
+/// Error returned by [`RateLimitedListener::accept`] inside
+/// [`io::Error`] of [`io::ErrorKind::ConnectionRefused`] kind.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display("connection from {0} was dropped due to exceeded rate limit")]
+pub struct RateLimited(pub IpAddr);
+
+impl RateLimited {
+    /// Detects whether the error returned by a listener is caused by the
+    /// rate limiting, returning the IP address which has exceeded the limit.
+    pub fn from_io_error(err: &io::Error) -> Option<IpAddr> {
+        err.get_ref()
+            .and_then(|err| err.downcast_ref::<RateLimited>())
+            .map(|limited| limited.0)
+    }
+}
+
+/// Network of IP addresses, defined by an address and a prefix length.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display("{addr}/{prefix_len}")]
+pub struct IpNetwork {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl From<IpAddr> for IpNetwork {
+    /// Constructs network consisting of a single address.
+    fn from(addr: IpAddr) -> Self {
+        let prefix_len = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        IpNetwork { addr, prefix_len }
+    }
+}
+
+impl IpNetwork {
+    /// Constructs network from an address and a prefix length, returning
+    /// `None` if the prefix length exceeds the length of the address.
+    pub fn new(addr: IpAddr, prefix_len: u8) -> Option<Self> {
+        let max = match addr {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        if prefix_len > max {
+            return None;
+        }
+        Some(IpNetwork { addr, prefix_len })
+    }
+
+    pub fn addr(&self) -> IpAddr {
+        self.addr
+    }
+
+    pub fn prefix_len(&self) -> u8 {
+        self.prefix_len
+    }
+
+    /// Checks whether the address belongs to the network. Addresses of a
+    /// different family never belong to it.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, ip) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_len as u32)
+                    .unwrap_or(0);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Returns IP address of the remote peer of a connected socket.
+pub(crate) fn peer_ip(fd: RawFd) -> io::Result<IpAddr> {
+    let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getpeername(
+            fd,
+            &mut storage as *mut libc::sockaddr_storage as *mut libc::sockaddr,
+            &mut len,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    match storage.ss_family as libc::c_int {
+        libc::AF_INET => {
+            let addr = unsafe {
+                &*(&storage as *const libc::sockaddr_storage as *const libc::sockaddr_in)
+            };
+            Ok(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr)).into())
+        }
+        libc::AF_INET6 => {
+            let addr = unsafe {
+                &*(&storage as *const libc::sockaddr_storage as *const libc::sockaddr_in6)
+            };
+            Ok(Ipv6Addr::from(addr.sin6_addr.s6_addr).into())
+        }
+        _ => Err(io::ErrorKind::InvalidInput.into()),
+    }
+}
+
+/// Makes the socket to send RST instead of the graceful shutdown once it is
+/// closed.
+pub(crate) fn set_reset_on_close(fd: RawFd) -> io::Result<()> {
+    let linger = libc::linger {
+        l_onoff: 1,
+        l_linger: 0,
+    };
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_LINGER,
+            &linger as *const libc::linger as *const libc::c_void,
+            mem::size_of::<libc::linger>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Listener which limits the number of connections accepted from a single IP
+/// address within a time window, mitigating connection floods.
+///
+/// Each IP address may open up to `max_connections_per_ip` connections within
+/// a window, which starts with its first connection. Connections exceeding
+/// the limit (including the rejected ones, so a flooding peer stays limited)
+/// are reset right after being accepted, and [`NetListener::accept`] returns
+/// an error of [`io::ErrorKind::ConnectionRefused`] kind containing
+/// [`RateLimited`], which is reported by [`crate::NetAccept`] as
+/// [`crate::ListenerEvent::RateLimited`]. Addresses from the allowlist are
+/// never limited.
+#[derive(Debug)]
+pub struct RateLimitedListener<L: NetListener> {
+    listener: L,
+    max_connections_per_ip: u32,
+    window: Duration,
+    allowlist: Vec<IpNetwork>,
+    counters: RefCell<HashMap<IpAddr, (u32, Instant)>>,
+}
+
+impl<L: NetListener> AsRawFd for RateLimitedListener<L> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl<L: NetListener> RateLimitedListener<L> {
+    pub fn new(listener: L, max_connections_per_ip: u32, window: Duration) -> Self {
+        RateLimitedListener {
+            listener,
+            max_connections_per_ip,
+            window,
+            allowlist: empty!(),
+            counters: empty!(),
+        }
+    }
+
+    /// Sets networks which connections are not rate limited.
+    pub fn with_allowlist(mut self, allowlist: impl IntoIterator<Item = IpNetwork>) -> Self {
+        self.allowlist = allowlist.into_iter().collect();
+        self
+    }
+
+    pub fn max_connections_per_ip(&self) -> u32 {
+        self.max_connections_per_ip
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    pub fn allowlist(&self) -> &[IpNetwork] {
+        &self.allowlist
+    }
+
+    /// Registers connection from the IP address, returning whether it exceeds
+    /// the limit.
+    fn is_limited(&self, ip: IpAddr) -> bool {
+        if self.allowlist.iter().any(|net| net.contains(ip)) {
+            return false;
+        }
+        let now = Instant::now();
+        let mut counters = self.counters.borrow_mut();
+        if counters.len() >= RATE_LIMIT_PRUNE_THRESHOLD {
+            counters.retain(|_, (_, start)| now.duration_since(*start) < self.window);
+        }
+        let (count, start) = counters.entry(ip).or_insert((0, now));
+        if now.duration_since(*start) >= self.window {
+            *count = 0;
+            *start = now;
+        }
+        *count = count.saturating_add(1);
+        *count > self.max_connections_per_ip
+    }
+}
+
+impl<L: NetListener> NetListener for RateLimitedListener<L> {
+    type Stream = L::Stream;
+
+    /// Binds listener with [`DEFAULT_MAX_CONNECTIONS_PER_IP`] and
+    /// [`DEFAULT_RATE_LIMIT_WINDOW`] limits.
+    fn bind(addr: &impl ToSocketAddrs) -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        L::bind(addr).map(|listener| {
+            Self::new(
+                listener,
+                DEFAULT_MAX_CONNECTIONS_PER_IP,
+                DEFAULT_RATE_LIMIT_WINDOW,
+            )
+        })
+    }
+
+    /// Binds listener with [`DEFAULT_MAX_CONNECTIONS_PER_IP`] and
+    /// [`DEFAULT_RATE_LIMIT_WINDOW`] limits.
+    fn with_reuseport(addr: SocketAddr) -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        L::with_reuseport(addr).map(|listener| {
+            Self::new(
+                listener,
+                DEFAULT_MAX_CONNECTIONS_PER_IP,
+                DEFAULT_RATE_LIMIT_WINDOW,
+            )
+        })
+    }
+
+    fn accept(&self) -> io::Result<Self::Stream> {
+        let stream = self.listener.accept()?;
+        let ip = peer_ip(stream.as_raw_fd())?;
+        if self.is_limited(ip) {
+            // The stream is closed with RST once dropped
+            set_reset_on_close(stream.as_raw_fd())?;
+            return Err(io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                RateLimited(ip),
+            ));
+        }
+        Ok(stream)
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr()
+    }
+
+    fn ttl(&self) -> io::Result<u32> {
+        self.listener.ttl()
+    }
+
+    fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        self.listener.set_ttl(ttl)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.listener.set_nonblocking(nonblocking)
+    }
+
+    /// Clones the listener together with the current state of the rate
+    /// limits, which is not shared between the clones afterwards.
+    fn try_clone(&self) -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(RateLimitedListener {
+            listener: self.listener.try_clone()?,
+            max_connections_per_ip: self.max_connections_per_ip,
+            window: self.window,
+            allowlist: self.allowlist.clone(),
+            counters: self.counters.clone(),
+        })
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.listener.take_error()
+    }
+}
+
+/// Default maximal number of connections of a [`BoundedListener`].
+pub const DEFAULT_MAX_CONNECTIONS: usize = 1024;
+
+/// Error returned by [`BoundedListener::accept`] inside [`io::Error`] of
+/// [`io::ErrorKind::ConnectionRefused`] kind.
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display("connection was dropped since {current} connections out of {max} allowed are open")]
+pub struct CapReached {
+    pub current: usize,
+    pub max: usize,
+}
+
+impl CapReached {
+    /// Detects whether the error returned by a listener is caused by the
+    /// reached connection cap.
+    pub fn from_io_error(err: &io::Error) -> Option<CapReached> {
+        err.get_ref()
+            .and_then(|err| err.downcast_ref::<CapReached>())
+            .copied()
+    }
+}
+
+/// Number of open connections, shared between a [`BoundedListener`] and the
+/// code tracking disconnection of the connections accepted by it.
+#[derive(Clone, Debug, Default)]
+pub struct ConnectionCounter(Arc<AtomicUsize>);
+
+impl ConnectionCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns current number of open connections.
+    pub fn current(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+
+    /// Registers a disconnected connection, allowing a new one to be
+    /// accepted.
+    pub fn release(&self) {
+        let _ = self
+            .0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+    }
+
+    /// Registers new connection if the number of connections is below `max`.
+    ///
+    /// # Errors
+    ///
+    /// If the cap is reached.
+    fn acquire(&self, max: usize) -> Result<(), CapReached> {
+        self.0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                if n < max {
+                    Some(n + 1)
+                } else {
+                    None
+                }
+            })
+            .map(|_| ())
+            .map_err(|current| CapReached { current, max })
+    }
+}
+
+/// Listener which limits the total number of open connections accepted by it,
+/// for instance to protect a device with scarce resources.
+///
+/// Each accepted connection is counted by the [`ConnectionCounter`], which
+/// must be released with [`ConnectionCounter::release`] once the connection is
+/// disconnected (for instance when the transport is handed over by the
+/// reactor after being unregistered). Once the cap is reached, new connections
+/// are reset right after being accepted, and [`NetListener::accept`] returns
+/// an error of [`io::ErrorKind::ConnectionRefused`] kind containing
+/// [`CapReached`], which is reported by [`crate::NetAccept`] as
+/// [`crate::ListenerEvent::CapReached`].
+#[derive(Debug)]
+pub struct BoundedListener<L: NetListener> {
+    listener: L,
+    max: usize,
+    counter: ConnectionCounter,
+}
+
+impl<L: NetListener> AsRawFd for BoundedListener<L> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl<L: NetListener> BoundedListener<L> {
+    pub fn new(listener: L, max: usize) -> Self {
+        Self::with_counter(listener, max, ConnectionCounter::new())
+    }
+
+    /// Constructs listener using existing counter, which allows to limit the
+    /// total number of connections accepted by multiple listeners.
+    pub fn with_counter(listener: L, max: usize, counter: ConnectionCounter) -> Self {
+        BoundedListener {
+            listener,
+            max,
+            counter,
+        }
+    }
+
+    /// Returns counter of the connections, which should be used to release
+    /// them on disconnection.
+    pub fn counter(&self) -> ConnectionCounter {
+        self.counter.clone()
+    }
+
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    pub fn current(&self) -> usize {
+        self.counter.current()
+    }
+
+    /// Returns number of connections which can be accepted before the cap is
+    /// reached.
+    pub fn headroom(&self) -> usize {
+        self.max.saturating_sub(self.current())
+    }
+}
+
+impl<L: NetListener> NetListener for BoundedListener<L> {
+    type Stream = L::Stream;
+
+    /// Binds listener limited to [`DEFAULT_MAX_CONNECTIONS`].
+    fn bind(addr: &impl ToSocketAddrs) -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        L::bind(addr).map(|listener| Self::new(listener, DEFAULT_MAX_CONNECTIONS))
+    }
+
+    /// Binds listener limited to [`DEFAULT_MAX_CONNECTIONS`].
+    fn with_reuseport(addr: SocketAddr) -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        L::with_reuseport(addr).map(|listener| Self::new(listener, DEFAULT_MAX_CONNECTIONS))
+    }
+
+    fn accept(&self) -> io::Result<Self::Stream> {
+        let stream = self.listener.accept()?;
+        if let Err(cap) = self.counter.acquire(self.max) {
+            // The stream is closed with RST once dropped
+            set_reset_on_close(stream.as_raw_fd())?;
+            return Err(io::Error::new(io::ErrorKind::ConnectionRefused, cap));
+        }
+        Ok(stream)
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr()
+    }
+
+    fn ttl(&self) -> io::Result<u32> {
+        self.listener.ttl()
+    }
+
+    fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        self.listener.set_ttl(ttl)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.listener.set_nonblocking(nonblocking)
+    }
+
+    /// Clones the listener, which shares the connection counter with the
+    /// original one.
+    fn try_clone(&self) -> io::Result<Self>
+    where
+        Self: Sized,
+    {
+        Ok(BoundedListener {
+            listener: self.listener.try_clone()?,
+            max: self.max,
+            counter: self.counter.clone(),
+        })
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.listener.take_error()
+    }
+}
+
+/// Error returned inside [`io::Error`] of [`io::ErrorKind::ConnectionRefused`]
+/// kind when a connection is dropped since its IP address has reached the
+/// limit of simultaneous connections (see [`IpConnectionCounter`]).
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(
+    "connection from {ip} was dropped since {current} connections out of {max} allowed are open"
+)]
+pub struct PeerLimitReached {
+    pub ip: IpAddr,
+    pub current: usize,
+    pub max: usize,
+}
+
+impl PeerLimitReached {
+    /// Detects whether the error returned when accepting a connection is
+    /// caused by the reached per-IP limit.
+    pub fn from_io_error(err: &io::Error) -> Option<PeerLimitReached> {
+        err.get_ref()
+            .and_then(|err| err.downcast_ref::<PeerLimitReached>())
+            .copied()
+    }
+}
+
+#[derive(Debug, Default)]
+struct IpConnections {
+    per_ip: HashMap<IpAddr, usize>,
+    total: usize,
+    rejected: u64,
+}
+
+/// Number of open connections per IP address, which are limited by a
+/// [`crate::NetAccept`]. Each of the accepted connections is counted until its
+/// [`IpConnectionGuard`] is dropped.
+///
+/// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`), which are reported for IPv4
+/// peers of dual-stack sockets, are counted as the corresponding IPv4
+/// addresses.
+#[derive(Clone, Debug, Default)]
+pub struct IpConnectionCounter(Arc<Mutex<IpConnections>>);
+
+impl IpConnectionCounter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns current number of open connections from the IP address.
+    pub fn current(&self, ip: IpAddr) -> usize {
+        let connections = self.0.lock().expect("poisoned connection counter");
+        connections
+            .per_ip
+            .get(&ip.to_canonical())
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Returns current number of open connections from all IP addresses.
+    pub fn total(&self) -> usize {
+        self.0.lock().expect("poisoned connection counter").total
+    }
+
+    /// Returns number of connections which were dropped since they have
+    /// exceeded the limits.
+    pub fn rejected(&self) -> u64 {
+        self.0.lock().expect("poisoned connection counter").rejected
+    }
+
+    /// Registers a disconnected connection from the IP address, allowing a
+    /// new one to be accepted. Not required for the connections released by
+    /// their [`IpConnectionGuard`].
+    pub fn release(&self, ip: IpAddr) {
+        let mut connections = self.0.lock().expect("poisoned connection counter");
+        let ip = ip.to_canonical();
+        let Some(count) = connections.per_ip.get_mut(&ip) else {
+            return;
+        };
+        *count -= 1;
+        if *count == 0 {
+            connections.per_ip.remove(&ip);
+        }
+        connections.total -= 1;
+    }
+
+    /// Registers new connection from the IP address if the number of the
+    /// connections from it is below `max_per_ip` and the total number of
+    /// connections is below `max_total`.
+    ///
+    /// The connection is counted until the returned guard is dropped.
+    ///
+    /// # Errors
+    ///
+    /// Error of [`io::ErrorKind::ConnectionRefused`] kind containing either
+    /// [`PeerLimitReached`] or [`CapReached`] if one of the limits is reached.
+    pub(crate) fn acquire(
+        &self,
+        ip: IpAddr,
+        max_per_ip: usize,
+        max_total: Option<usize>,
+    ) -> io::Result<IpConnectionGuard> {
+        let mut connections = self.0.lock().expect("poisoned connection counter");
+        let ip = ip.to_canonical();
+        let current = connections.per_ip.get(&ip).copied().unwrap_or_default();
+        let err = match max_total {
+            Some(max) if connections.total >= max => io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                CapReached {
+                    current: connections.total,
+                    max,
+                },
+            ),
+            _ if current >= max_per_ip => io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                PeerLimitReached {
+                    ip,
+                    current,
+                    max: max_per_ip,
+                },
+            ),
+            _ => {
+                *connections.per_ip.entry(ip).or_default() += 1;
+                connections.total += 1;
+                return Ok(IpConnectionGuard {
+                    counter: self.clone(),
+                    ip,
+                });
+            }
+        };
+        connections.rejected += 1;
+        Err(err)
+    }
+}
+
+/// Connection counted by an [`IpConnectionCounter`], which is released once
+/// the guard is dropped together with the resource of the connection.
+#[derive(Debug)]
+pub struct IpConnectionGuard {
+    counter: IpConnectionCounter,
+    ip: IpAddr,
+}
+
+impl IpConnectionGuard {
+    /// Returns IP address the connection is counted for.
+    pub fn ip(&self) -> IpAddr {
+        self.ip
+    }
+}
+
+impl Drop for IpConnectionGuard {
+    fn drop(&mut self) {
+        self.counter.release(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::process::{Command, Stdio};
+    use std::time::Duration;
+    use std::{env, thread};
+
+    use super::*;
+
+    /// Environment variable passing the port to the child process.
+    const REUSEPORT_ENV: &str = "NETSERVICES_TEST_REUSEPORT";
+
+    /// Accepts connections, writing `marker` to each of them.
+    fn serve(listener: TcpListener, marker: u8) {
+        loop {
+            if let Ok(mut stream) = NetListener::accept(&listener) {
+                let _ = stream.write_all(&[marker]);
+            }
+        }
+    }
+
+    /// Body of the child process spawned by [`reuseport`]; does nothing when
+    /// run as a part of the test suite.
+    #[test]
+    fn reuseport_child() {
+        let port = match env::var(REUSEPORT_ENV) {
+            Ok(port) => port,
+            Err(_) => return,
+        };
+        let addr = SocketAddr::from(([127, 0, 0, 1], port.parse().unwrap()));
+        let listener = TcpListener::with_reuseport(addr).unwrap();
+        println!("bound");
+        serve(listener, b'c');
+    }
+
+    // The kernel distributes the connections between the listeners on Linux
+    // only
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn reuseport() {
+        let listener = TcpListener::with_reuseport(([127, 0, 0, 1], 0).into()).unwrap();
+        let addr = NetListener::local_addr(&listener);
+        assert_eq!(
+            TcpListener::bind(addr).unwrap_err().kind(),
+            io::ErrorKind::AddrInUse
+        );
+
+        let mut child = Command::new(env::current_exe().unwrap())
+            .args(["--exact", "listener::tests::reuseport_child", "--nocapture"])
+            .env(REUSEPORT_ENV, addr.port().to_string())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let bound = (&mut stdout)
+            .lines()
+            .map(Result::unwrap)
+            // Test harness prints the test name on the same line
+            .any(|line| line.ends_with("bound"));
+        thread::spawn(move || serve(listener, b'p'));
+
+        let mut markers = vec![];
+        for _ in 0..100 {
+            // Errors must not panic before the child process is killed
+            let marker = TcpStream::connect(addr).and_then(|mut stream| {
+                stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+                let mut marker = [0u8; 1];
+                stream.read_exact(&mut marker)?;
+                Ok(marker[0])
+            });
+            match marker {
+                Ok(marker) => markers.push(marker),
+                Err(_) => break,
+            }
+            if markers.contains(&b'p') && markers.contains(&b'c') {
+                break;
+            }
+        }
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        assert!(bound, "child process has failed to bind the address");
+        assert!(
+            markers.contains(&b'p'),
+            "parent has not accepted connections"
+        );
+        assert!(
+            markers.contains(&b'c'),
+            "child has not accepted connections"
+        );
+    }
+
+    #[test]
+    fn ip_network() {
+        let net = IpNetwork::new("10.1.0.0".parse().unwrap(), 16).unwrap();
+        assert!(net.contains("10.1.2.3".parse().unwrap()));
+        assert!(!net.contains("10.2.0.1".parse().unwrap()));
+        assert!(!net.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(IpNetwork::new("10.1.0.0".parse().unwrap(), 33).is_none());
+
+        let any = IpNetwork::new("::".parse().unwrap(), 0).unwrap();
+        assert!(any.contains("2001:db8::1".parse().unwrap()));
+        let host = IpNetwork::from("2001:db8::1".parse::<IpAddr>().unwrap());
+        assert!(host.contains("2001:db8::1".parse().unwrap()));
+        assert!(!host.contains("2001:db8::2".parse().unwrap()));
+    }
+
+    #[test]
+    fn rate_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = NetListener::local_addr(&listener);
+        let listener = RateLimitedListener::new(listener, 5, Duration::from_secs(60));
+
+        let mut accepted = 0;
+        let mut limited = vec![];
+        for _ in 0..20 {
+            let _client = TcpStream::connect(addr).unwrap();
+            match listener.accept() {
+                Ok(_) => accepted += 1,
+                Err(err) => {
+                    assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+                    limited.push(RateLimited::from_io_error(&err).unwrap());
+                }
+            }
+        }
+        assert_eq!(accepted, 5);
+        assert_eq!(limited, vec![IpAddr::from([127, 0, 0, 1]); 15]);
+    }
+
+    #[test]
+    fn rate_limit_window() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = NetListener::local_addr(&listener);
+        let listener = RateLimitedListener::new(listener, 1, Duration::from_millis(100));
+
+        let _client = TcpStream::connect(addr).unwrap();
+        assert!(listener.accept().is_ok());
+        let _client = TcpStream::connect(addr).unwrap();
+        assert!(listener.accept().is_err());
+        thread::sleep(Duration::from_millis(100));
+        let _client = TcpStream::connect(addr).unwrap();
+        assert!(listener.accept().is_ok());
+    }
+
+    #[test]
+    fn rate_limit_allowlist() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = NetListener::local_addr(&listener);
+        let listener = RateLimitedListener::new(listener, 1, Duration::from_secs(60))
+            .with_allowlist([IpNetwork::new(IpAddr::from([127, 0, 0, 0]), 8).unwrap()]);
+
+        for _ in 0..20 {
+            let _client = TcpStream::connect(addr).unwrap();
+            assert!(listener.accept().is_ok());
+        }
+    }
+
+    #[test]
+    fn connection_cap() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = NetListener::local_addr(&listener);
+        let listener = BoundedListener::new(listener, 2);
+        let counter = listener.counter();
+
+        let mut clients = vec![];
+        for headroom in [2, 1] {
+            assert_eq!(listener.headroom(), headroom);
+            clients.push(TcpStream::connect(addr).unwrap());
+            assert!(listener.accept().is_ok());
+        }
+        assert_eq!(listener.headroom(), 0);
+
+        let mut client = TcpStream::connect(addr).unwrap();
+        let err = listener.accept().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(
+            CapReached::from_io_error(&err),
+            Some(CapReached { current: 2, max: 2 })
+        );
+        // The refused connection is reset
+        assert!(client.read(&mut [0u8; 1]).map_or(true, |len| len == 0));
+        assert_eq!(counter.current(), 2);
+
+        counter.release();
+        assert_eq!(listener.headroom(), 1);
+        let _client = TcpStream::connect(addr).unwrap();
+        assert!(listener.accept().is_ok());
+        assert_eq!(listener.current(), 2);
+
+        // Releasing more connections than were accepted does not underflow
+        for _ in 0..3 {
+            counter.release();
+        }
+        assert_eq!(listener.headroom(), 2);
+    }
+
+    #[test]
+    fn ip_connection_counter() {
+        let ipv4 = IpAddr::from([127, 0, 0, 1]);
+        let mapped = "::ffff:127.0.0.1".parse().unwrap();
+        let ipv6 = "::1".parse().unwrap();
+        let counter = IpConnectionCounter::new();
+
+        // IPv4-mapped address is the same host as the IPv4 one
+        let guard = counter.acquire(mapped, 2, Some(3)).unwrap();
+        let _ipv4 = counter.acquire(ipv4, 2, Some(3)).unwrap();
+        let err = counter.acquire(mapped, 2, Some(3)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(
+            PeerLimitReached::from_io_error(&err),
+            Some(PeerLimitReached {
+                ip: ipv4,
+                current: 2,
+                max: 2
+            })
+        );
+        assert_eq!(counter.current(ipv4), 2);
+        assert_eq!(counter.current(mapped), 2);
+
+        let _ipv6 = counter.acquire(ipv6, 2, Some(3)).unwrap();
+        let err = counter.acquire(ipv6, 2, Some(3)).unwrap_err();
+        assert_eq!(
+            CapReached::from_io_error(&err),
+            Some(CapReached { current: 3, max: 3 })
+        );
+        assert_eq!(counter.total(), 3);
+        assert_eq!(counter.rejected(), 2);
+
+        assert_eq!(guard.ip(), ipv4);
+        drop(guard);
+        assert_eq!(counter.current(ipv4), 1);
+        let _ipv6 = counter.acquire(ipv6, 2, Some(3)).unwrap();
+        assert_eq!(counter.current(ipv6), 2);
+
+        // Releasing connections which were not accepted does not underflow
+        for _ in 0..3 {
+            counter.release(ipv4);
+        }
+        assert_eq!(counter.current(ipv4), 0);
+        assert_eq!(counter.total(), 2);
+    }
+}
@@ -2,8 +2,10 @@ use amplify::Wrapper;
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::Hash;
 use std::io::{self, Read, Write};
+use std::mem;
 use std::net::{self, TcpStream, ToSocketAddrs};
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
 use cyphernet::addr::{Addr, Host, PeerAddr, ToSocketAddr};
@@ -12,9 +14,12 @@ use cyphernet::crypto::{ed25519, EcPk, Ecdh};
 use cyphernet::noise::framing::{NoiseDecryptor, NoiseEncryptor, NoiseState, NoiseTranscoder};
 use cyphernet::noise::xk::NoiseXkState;
 use ed25519_compact::x25519;
+pub use snow::Keypair;
+use snow::{Builder, HandshakeState, StatelessTransportState};
 
 use crate::auth::Authenticator;
 use crate::connection::Proxy;
+use crate::proxy_protocol::ProxyAddrs;
 use crate::resources::{SplitIo, SplitIoError};
 use crate::{Address, NetConnection, NetSession};
 
@@ -167,12 +172,34 @@ impl<E: Ecdh, S: NetConnection> Write for NoiseXkWriter<E, S> {
     }
 }
 
+/// Session running Noise_XK handshake, in which the initiator knows the
+/// static key of the responder in advance and transmits its own static key
+/// only in the third handshake message.
+///
+/// The handshake is driven by non-blocking reads and writes: each read
+/// receives at most one handshake message, buffering its parts until the
+/// message is complete. Handshake failures are returned as
+/// [`io::ErrorKind::ConnectionAborted`] errors, reported by
+/// [`crate::NetResource`] as [`crate::SessionEvent::Terminated`].
 #[derive(Debug)]
 pub struct NoiseXk<E: Ecdh, S: NetConnection = TcpStream> {
     remote_addr: XkAddr<E::Pk, S::Addr>,
     connection: S,
     transcoder: NoiseTranscoder<NoiseXkState>,
     authenticator: Authenticator,
+    /// Part of the next handshake message received so far.
+    handshake_input: Vec<u8>,
+}
+
+impl<E: Ecdh, S: NetConnection> NoiseXk<E, S> {
+    /// Returns static key of the remote peer once the handshake and the
+    /// authentication are complete.
+    pub fn remote_static_key(&self) -> Option<&E::Pk> {
+        if !self.transcoder.is_handshake_complete() || !self.authenticator.is_auth_complete() {
+            return None;
+        }
+        self.remote_addr.peer_id()
+    }
 }
 
 impl<E: Ecdh, S: NetConnection> AsRawFd for NoiseXk<E, S> {
@@ -187,9 +214,25 @@ where
 {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         if !self.transcoder.is_handshake_complete() {
-            let mut input = vec![0u8; self.transcoder.next_handshake_len()];
-            self.connection.read_exact(&mut input)?;
+            // Non-blocking connection may return only a part of the handshake
+            // message, which is kept until the rest of it arrives
+            let expected = self.transcoder.next_handshake_len();
+            if self.handshake_input.len() < expected {
+                let mut input = vec![0u8; expected - self.handshake_input.len()];
+                let len = self.connection.read(&mut input)?;
+                if len == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                self.handshake_input.extend_from_slice(&input[..len]);
+                if self.handshake_input.len() < expected {
+                    return Ok(0);
+                }
+            }
+            let input = mem::take(&mut self.handshake_input);
+            #[cfg(feature = "log")]
             log::trace!(target: "handshake", "Received {input:02x?}");
+            #[cfg(feature = "tracing")]
+            tracing::trace!(target: "handshake", len = input.len(), "Received handshake act");
             let act = self
                 .transcoder
                 .advance_handshake(&input)
@@ -197,7 +240,10 @@ where
             return match act {
                 None => Ok(0),
                 Some(act) => {
+                    #[cfg(feature = "log")]
                     log::trace!(target: "handshake", "Sent {act:02x?}");
+                    #[cfg(feature = "tracing")]
+                    tracing::trace!(target: "handshake", len = act.len(), "Sent handshake act");
                     self.connection.write_all(&act)?;
                     Ok(0)
                 }
@@ -210,9 +256,14 @@ where
                 (Some(remote_id), Some(peer_id))
                     if &E::Pk::from(remote_id.into_inner()) != peer_id =>
                 {
+                    #[cfg(feature = "log")]
                     log::error!(target: "authentication",
                         "Remote peer has a different identity {remote_id} than expected",
                     );
+                    #[cfg(feature = "tracing")]
+                    tracing::error!(target: "authentication", %remote_id,
+                        "Remote peer has a different identity than expected",
+                    );
                     Err(io::ErrorKind::ConnectionReset.into())
                 }
                 (None, _) => Err(io::ErrorKind::InvalidInput.into()),
@@ -234,7 +285,10 @@ impl<E: Ecdh, S: NetConnection> Write for NoiseXk<E, S> {
                 .advance_handshake(&[])
                 .map_err(|err| io::Error::new(io::ErrorKind::ConnectionAborted, err))?;
             if let Some(next_act) = act {
+                #[cfg(feature = "log")]
                 log::trace!(target: "handshake", "Sent {next_act:02x?}");
+                #[cfg(feature = "tracing")]
+                tracing::trace!(target: "handshake", len = next_act.len(), "Sent handshake act");
                 self.connection.write_all(&next_act)?
             }
             return Err(io::ErrorKind::Interrupted.into());
@@ -310,6 +364,7 @@ impl<E: Ecdh, S: NetConnection> SplitIo for NoiseXk<E, S> {
             transcoder: NoiseTranscoder::with_split(write.encryptor, read.decryptor),
             connection: S::from_split_io(read.reader, write.writer),
             authenticator: write.authenticator,
+            handshake_input: vec![],
         }
     }
 }
@@ -329,6 +384,7 @@ impl<S: NetConnection> NetSession for NoiseXk<ed25519::PrivateKey, S> {
             remote_addr: XkAddr::Partial(connection.remote_addr()),
             connection,
             transcoder: NoiseTranscoder::with_xk_responder(ecdh),
+            handshake_input: vec![],
         })
     }
 
@@ -341,6 +397,9 @@ impl<S: NetConnection> NetSession for NoiseXk<ed25519::PrivateKey, S> {
             x25519::SecretKey::from_ed25519(context.0.as_inner()).expect("invalid local node key");
         let remote_key = x25519::PublicKey::from_ed25519(peer_addr.id().as_inner())
             .map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+        #[cfg(feature = "tracing")]
+        let _span =
+            tracing::debug_span!(target: "handshake", "noise_xk", peer = %peer_addr).entered();
         let mut connection = S::connect_blocking(peer_addr.addr().clone(), proxy)?;
         let mut transcoder = NoiseTranscoder::with_xk_initiator(ecdh, remote_key);
 
@@ -351,13 +410,19 @@ impl<S: NetConnection> NetSession for NoiseXk<ed25519::PrivateKey, S> {
                 .advance_handshake(&input)
                 .map_err(|err| io::Error::new(io::ErrorKind::ConnectionAborted, err))?;
             if let Some(act) = act {
+                #[cfg(feature = "log")]
                 log::trace!(target: "handshake", "Sent {act:02x?}");
+                #[cfg(feature = "tracing")]
+                tracing::trace!(target: "handshake", len = act.len(), "Sent handshake act");
                 connection.write_all(&act)?;
             }
             if !transcoder.is_handshake_complete() {
                 input = vec![0u8; transcoder.next_handshake_len()];
                 connection.read_exact(&mut input)?;
+                #[cfg(feature = "log")]
                 log::trace!(target: "handshake", "Received {input:02x?}");
+                #[cfg(feature = "tracing")]
+                tracing::trace!(target: "handshake", len = input.len(), "Received handshake act");
             }
         }
 
@@ -366,14 +431,18 @@ impl<S: NetConnection> NetSession for NoiseXk<ed25519::PrivateKey, S> {
             authenticator.certify(&mut connection)?;
             match authenticator.verify(&mut connection)? {
                 None => {
+                    #[cfg(feature = "log")]
                     log::error!(target: "authentication", "The remote peer has failed validation");
-                    // TODO: Return proper error type
-                    panic!()
+                    #[cfg(feature = "tracing")]
+                    tracing::error!(target: "authentication", "The remote peer has failed validation");
+                    return Err(io::Error::from(io::ErrorKind::InvalidInput).into());
                 }
                 Some(id) if id != *peer_addr.id() => {
+                    #[cfg(feature = "log")]
                     log::error!(target: "authentication", "The remote peer has a different identity than expected");
-                    // TODO: Return proper error type
-                    panic!()
+                    #[cfg(feature = "tracing")]
+                    tracing::error!(target: "authentication", "The remote peer has a different identity than expected");
+                    return Err(io::Error::from(io::ErrorKind::ConnectionReset).into());
                 }
                 _ => {}
             }
@@ -384,6 +453,7 @@ impl<S: NetConnection> NetSession for NoiseXk<ed25519::PrivateKey, S> {
             remote_addr: XkAddr::Full(peer_addr),
             connection,
             transcoder,
+            handshake_input: vec![],
         })
     }
 
@@ -403,6 +473,7 @@ impl<S: NetConnection> NetSession for NoiseXk<ed25519::PrivateKey, S> {
             remote_addr: XkAddr::Full(peer_addr),
             connection: socket,
             transcoder: NoiseTranscoder::with_xk_initiator(ecdh, remote_key),
+            handshake_input: vec![],
         })
     }
 
@@ -452,7 +523,1297 @@ impl<S: NetConnection> NetSession for NoiseXk<ed25519::PrivateKey, S> {
         self.connection.set_nonblocking(nonblocking)
     }
 
+    fn proxy_addrs(&self) -> Option<ProxyAddrs> {
+        self.connection.proxy_addrs()
+    }
+
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.connection.shutdown(net::Shutdown::Write)
+    }
+
     fn disconnect(mut self) -> io::Result<()> {
         self.connection.shutdown(net::Shutdown::Both)
     }
 }
+
+/// Noise protocol parameters of the [`NoiseXx`] sessions, matching the ones
+/// used by libp2p.
+pub const XX_PARAMS: &str = "Noise_XX_25519_ChaChaPoly_SHA256";
+
+/// Maximal length of a Noise message, including the authentication tag.
+const NOISE_MAX_MSG_LEN: usize = u16::MAX as usize;
+/// Length of the authentication tag of an encrypted Noise message.
+const NOISE_TAG_LEN: usize = 16;
+
+/// Generates static keypair to be used with [`NoiseXx`] sessions.
+pub fn xx_keypair() -> Keypair {
+    xx_builder()
+        .generate_keypair()
+        .expect("default resolver supports X25519")
+}
+
+fn xx_builder<'builder>() -> Builder<'builder> {
+    Builder::new(XX_PARAMS.parse().expect("valid Noise parameters"))
+}
+
+fn aborted(err: snow::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::ConnectionAborted, err)
+}
+
+/// Reads a Noise message prefixed with its length as a big-endian `u16`.
+/// Parts of the message received so far are kept in `input` until the
+/// message is complete.
+///
+/// Returns `None` if the connection was closed before the message has
+/// started.
+fn read_noise_msg(reader: &mut impl Read, input: &mut Vec<u8>) -> io::Result<Option<Vec<u8>>> {
+    loop {
+        let expected = match input.len() {
+            len if len < 2 => 2,
+            _ => 2 + u16::from_be_bytes([input[0], input[1]]) as usize,
+        };
+        if input.len() == expected {
+            let msg = input.split_off(2);
+            input.clear();
+            return Ok(Some(msg));
+        }
+        let mut buf = vec![0u8; expected - input.len()];
+        match reader.read(&mut buf)? {
+            0 if input.is_empty() => return Ok(None),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            len => input.extend_from_slice(&buf[..len]),
+        }
+    }
+}
+
+/// State of the [`NoiseXx`] handshake, which consists of the following
+/// messages:
+///
+/// ```text
+/// -> e
+/// <- e, ee, s, es
+/// -> s, se
+/// ```
+#[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
+pub enum XxState {
+    /// Initiator is yet to send its ephemeral key.
+    Initiate,
+    /// Responder awaits the ephemeral key of the initiator.
+    AwaitingE,
+    /// Initiator awaits the ephemeral and the static keys of the responder.
+    AwaitingEsEe,
+    /// Responder awaits the static key of the initiator.
+    AwaitingEs,
+    /// Handshake is complete; the data are encrypted with the transport keys.
+    Transport,
+}
+
+/// Type of a [`NoiseXx`] transport message carrying application data in the
+/// [`RekeyMode::Signalled`] mode.
+const XX_MSG_DATA: u8 = 0x00;
+/// Type of a [`NoiseXx`] transport message signalling that the sender has
+/// rekeyed its sending key, which is used for all subsequent messages, in the
+/// [`RekeyMode::Signalled`] mode.
+const XX_MSG_REKEY: u8 = 0xFF;
+
+/// Default number of messages transmitted by a [`NoiseXx`] session in one
+/// direction after which the key of that direction is rekeyed.
+pub const DEFAULT_REKEY_MESSAGES: u64 = 1 << 32;
+/// Default number of bytes transmitted by a [`NoiseXx`] session in one
+/// direction after which the key of that direction is rekeyed.
+pub const DEFAULT_REKEY_BYTES: u64 = 1 << 30;
+
+/// Amount of data transmitted with the same key after which [`NoiseXx`]
+/// session performs Noise `Rekey()` of that key, whichever comes first.
+///
+/// The sending and the receiving keys are rekeyed independently, each once
+/// the threshold is reached by the messages transmitted in its direction.
+/// Unless the rekeying is signalled (see [`RekeyMode`]), both parties of the
+/// session must use the same threshold: otherwise the receiving party fails to
+/// decrypt the data.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct RekeyThreshold {
+    pub messages: u64,
+    pub bytes: u64,
+}
+
+impl Default for RekeyThreshold {
+    fn default() -> Self {
+        Self {
+            messages: DEFAULT_REKEY_MESSAGES,
+            bytes: DEFAULT_REKEY_BYTES,
+        }
+    }
+}
+
+/// Way in which [`NoiseXx`] session keeps rekeying of its receiving key in
+/// sync with the peer rekeying its sending key. Both parties of the session
+/// must use the same mode.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub enum RekeyMode {
+    /// Receiving key is rekeyed once the [`RekeyThreshold`] is reached by the
+    /// received data, without any signalling from the peer.
+    #[default]
+    Threshold,
+
+    /// Decrypted transport messages start with their type, which
+    /// distinguishes the application data (`0x00`) from the signal that the
+    /// sender has rekeyed its sending key (`0xFF`). The receiving key is
+    /// rekeyed only on the signal, so the parties may use different
+    /// thresholds.
+    Signalled,
+}
+
+/// Statistics of the [`NoiseXx`] transport.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct XxTransportStats {
+    pub messages_sent: u64,
+    pub messages_received: u64,
+    /// Number of messages sent since the last rekeying of the sending key.
+    pub messages_since_rekey: u64,
+    /// Number of bytes sent since the last rekeying of the sending key.
+    pub bytes_since_rekey: u64,
+    /// Number of messages received since the last rekeying of the receiving
+    /// key.
+    pub messages_received_since_rekey: u64,
+    /// Number of bytes received since the last rekeying of the receiving
+    /// key.
+    pub bytes_received_since_rekey: u64,
+    /// Number of times the sending key was rekeyed.
+    pub rekeys_sent: u64,
+    /// Number of times the receiving key was rekeyed.
+    pub rekeys_received: u64,
+}
+
+/// Counts data transmitted in one direction of a [`NoiseXx`] session with
+/// the same key, detecting when the key has to be rekeyed.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+struct RekeyCounter {
+    threshold: RekeyThreshold,
+    messages: u64,
+    bytes: u64,
+    rekeys: u64,
+}
+
+impl RekeyCounter {
+    fn new(threshold: RekeyThreshold) -> Self {
+        Self {
+            threshold,
+            messages: 0,
+            bytes: 0,
+            rekeys: 0,
+        }
+    }
+
+    /// Detects whether the key must be rekeyed before transmitting the next
+    /// message.
+    fn is_due(&self) -> bool {
+        self.messages >= self.threshold.messages || self.bytes >= self.threshold.bytes
+    }
+
+    fn count(&mut self, len: usize) {
+        self.messages += 1;
+        self.bytes += len as u64;
+    }
+
+    fn reset(&mut self) {
+        self.messages = 0;
+        self.bytes = 0;
+        self.rekeys += 1;
+    }
+}
+
+/// Transport keys of a [`NoiseXx`] session shared by its receiving and
+/// sending halves.
+#[derive(Debug)]
+struct XxTransport {
+    state: Mutex<StatelessTransportState>,
+    remote_static: Option<Vec<u8>>,
+    handshake_hash: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct XxDecryptor {
+    transport: Arc<XxTransport>,
+    nonce: u64,
+    /// Part of the next encrypted message received so far.
+    input: Vec<u8>,
+    /// Decrypted message, with the data starting after its type in the
+    /// [`RekeyMode::Signalled`] mode, which were not read yet.
+    plaintext: Vec<u8>,
+    pos: usize,
+    messages: u64,
+    rekey_mode: RekeyMode,
+    rekey: RekeyCounter,
+}
+
+impl XxDecryptor {
+    fn new(
+        transport: Arc<XxTransport>,
+        rekey_mode: RekeyMode,
+        rekey_threshold: RekeyThreshold,
+    ) -> Self {
+        Self {
+            transport,
+            nonce: 0,
+            input: vec![],
+            plaintext: vec![],
+            pos: 0,
+            messages: 0,
+            rekey_mode,
+            rekey: RekeyCounter::new(rekey_threshold),
+        }
+    }
+
+    fn read(&mut self, reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.plaintext.len() {
+            let msg = match read_noise_msg(reader, &mut self.input)? {
+                Some(msg) => msg,
+                None => return Ok(0),
+            };
+            if self.rekey_mode == RekeyMode::Threshold && self.rekey.is_due() {
+                self.rekey();
+            }
+            self.plaintext.resize(msg.len(), 0);
+            let len = self
+                .transport
+                .state
+                .lock()
+                .expect("poisoned transport lock")
+                .read_message(self.nonce, &msg, &mut self.plaintext)
+                .map_err(aborted)?;
+            self.plaintext.truncate(len);
+            self.nonce += 1;
+            self.pos = match self.rekey_mode {
+                RekeyMode::Threshold => 0,
+                RekeyMode::Signalled => match self.plaintext.first() {
+                    Some(&XX_MSG_DATA) => 1,
+                    Some(&XX_MSG_REKEY) => {
+                        self.rekey();
+                        self.plaintext.clear();
+                        self.pos = 0;
+                        continue;
+                    }
+                    _ => return Err(io::ErrorKind::InvalidData.into()),
+                },
+            };
+            self.messages += 1;
+            self.rekey.count(len - self.pos);
+        }
+        let len = buf.len().min(self.plaintext.len() - self.pos);
+        buf[..len].copy_from_slice(&self.plaintext[self.pos..self.pos + len]);
+        self.pos += len;
+        Ok(len)
+    }
+
+    /// Rekeys the receiving key following the peer, which has rekeyed its
+    /// sending key after the same amount of data or has signalled the rekeying.
+    fn rekey(&mut self) {
+        #[cfg(feature = "log")]
+        log::debug!(target: "noise", "Rekeying receiving key after {} messages", self.rekey.messages);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(target: "noise", messages = self.rekey.messages, bytes = self.rekey.bytes, "Rekeying receiving key");
+
+        self.transport
+            .state
+            .lock()
+            .expect("poisoned transport lock")
+            .rekey_incoming();
+        self.rekey.reset();
+    }
+}
+
+#[derive(Debug)]
+struct XxEncryptor {
+    transport: Arc<XxTransport>,
+    nonce: u64,
+    /// Encrypted messages which were not written out in full yet.
+    output: Vec<u8>,
+    pos: usize,
+    messages: u64,
+    rekey_mode: RekeyMode,
+    rekey: RekeyCounter,
+}
+
+impl XxEncryptor {
+    fn new(
+        transport: Arc<XxTransport>,
+        rekey_mode: RekeyMode,
+        rekey_threshold: RekeyThreshold,
+    ) -> Self {
+        Self {
+            transport,
+            nonce: 0,
+            output: vec![],
+            pos: 0,
+            messages: 0,
+            rekey_mode,
+            rekey: RekeyCounter::new(rekey_threshold),
+        }
+    }
+
+    fn write(&mut self, writer: &mut impl Write, buf: &[u8]) -> io::Result<usize> {
+        self.flush(writer)?;
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.rekey.is_due() {
+            self.rekey()?;
+        }
+        let ty = match self.rekey_mode {
+            RekeyMode::Threshold => None,
+            RekeyMode::Signalled => Some(XX_MSG_DATA),
+        };
+        let len = buf
+            .len()
+            .min(NOISE_MAX_MSG_LEN - NOISE_TAG_LEN - ty.iter().count());
+        self.encrypt(ty, &buf[..len])?;
+        self.messages += 1;
+        self.rekey.count(len);
+        // The data are accepted once encrypted: the rest of the message is
+        // written out with the next write or flush
+        match self.flush(writer) {
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(len),
+            Err(err) => Err(err),
+            Ok(_) => Ok(len),
+        }
+    }
+
+    /// Rekeys the sending key. In the [`RekeyMode::Signalled`] mode the peer
+    /// is signalled to rekey its receiving key with a message encrypted with
+    /// the current key; otherwise, the peer rekeys it after the same amount of
+    /// data.
+    fn rekey(&mut self) -> io::Result<()> {
+        #[cfg(feature = "log")]
+        log::debug!(target: "noise", "Rekeying sending key after {} messages", self.rekey.messages);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(target: "noise", messages = self.rekey.messages, bytes = self.rekey.bytes, "Rekeying sending key");
+
+        if self.rekey_mode == RekeyMode::Signalled {
+            self.encrypt(Some(XX_MSG_REKEY), &[])?;
+        }
+        self.transport
+            .state
+            .lock()
+            .expect("poisoned transport lock")
+            .rekey_outgoing();
+        self.rekey.reset();
+        Ok(())
+    }
+
+    /// Appends encrypted message, prefixed with its type if the type is
+    /// given, to the output.
+    fn encrypt(&mut self, ty: Option<u8>, data: &[u8]) -> io::Result<()> {
+        let mut plaintext = Vec::with_capacity(1 + data.len());
+        plaintext.extend(ty);
+        plaintext.extend_from_slice(data);
+        let start = self.output.len();
+        self.output
+            .resize(start + 2 + plaintext.len() + NOISE_TAG_LEN, 0);
+        let msg_len = self
+            .transport
+            .state
+            .lock()
+            .expect("poisoned transport lock")
+            .write_message(self.nonce, &plaintext, &mut self.output[start + 2..])
+            .map_err(aborted)?;
+        self.output[start..start + 2].copy_from_slice(&(msg_len as u16).to_be_bytes());
+        self.output.truncate(start + 2 + msg_len);
+        self.nonce += 1;
+        Ok(())
+    }
+
+    /// Detects whether an encrypted message is not yet completely written.
+    fn has_pending_output(&self) -> bool {
+        self.pos < self.output.len()
+    }
+
+    fn flush(&mut self, writer: &mut impl Write) -> io::Result<()> {
+        while self.pos < self.output.len() {
+            match writer.write(&self.output[self.pos..])? {
+                0 => return Err(io::ErrorKind::WriteZero.into()),
+                len => self.pos += len,
+            }
+        }
+        self.output.clear();
+        self.pos = 0;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct NoiseXxReader<S: NetConnection = TcpStream> {
+    reader: S::Read,
+    decryptor: XxDecryptor,
+}
+
+#[derive(Debug)]
+pub struct NoiseXxWriter<S: NetConnection = TcpStream> {
+    remote_addr: S::Addr,
+    writer: S::Write,
+    encryptor: XxEncryptor,
+}
+
+impl<S: NetConnection> Read for NoiseXxReader<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decryptor.read(&mut self.reader, buf)
+    }
+}
+
+impl<S: NetConnection> Write for NoiseXxWriter<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.encryptor.write(&mut self.writer, buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.encryptor.flush(&mut self.writer)?;
+        self.writer.flush()
+    }
+}
+
+/// Session running Noise_XX handshake, in which both parties transmit their
+/// static keys, providing mutual authentication with forward secrecy.
+///
+/// Handshake and transport messages are prefixed with their length as a
+/// big-endian `u16`, like in libp2p. Transport keys are rekeyed after the
+/// amount of data given by [`RekeyThreshold`], either at the same point by
+/// both parties or on an explicit signal of the sender (see [`RekeyMode`]),
+/// which is transparent to the users of the session. The handshake is driven by
+/// non-blocking reads and writes in the same way as for [`NoiseXk`]. Once it
+/// is complete, the static key of the remote peer is available from
+/// [`NoiseXx::remote_static_key`].
+#[derive(Debug)]
+pub struct NoiseXx<S: NetConnection = TcpStream> {
+    remote_addr: S::Addr,
+    connection: S,
+    state: XxState,
+    handshake: Option<HandshakeState>,
+    /// Part of the next handshake message received so far.
+    handshake_input: Vec<u8>,
+    rekey_mode: RekeyMode,
+    rekey_threshold: RekeyThreshold,
+    encryptor: Option<XxEncryptor>,
+    decryptor: Option<XxDecryptor>,
+}
+
+impl<S: NetConnection> NoiseXx<S> {
+    /// Starts the handshake as the initiator over an established connection.
+    /// The first handshake message is sent with the first write.
+    pub fn initiate(connection: S, keys: &Keypair) -> io::Result<Self> {
+        let handshake = xx_builder()
+            .local_private_key(&keys.private)
+            .build_initiator()
+            .map_err(aborted)?;
+        Ok(Self::with_handshake(
+            connection,
+            handshake,
+            XxState::Initiate,
+        ))
+    }
+
+    fn with_handshake(connection: S, handshake: HandshakeState, state: XxState) -> Self {
+        Self {
+            remote_addr: connection.remote_addr(),
+            connection,
+            state,
+            handshake: Some(handshake),
+            handshake_input: vec![],
+            rekey_mode: RekeyMode::default(),
+            rekey_threshold: RekeyThreshold::default(),
+            encryptor: None,
+            decryptor: None,
+        }
+    }
+
+    /// Sets amount of data transmitted with the same key after which the key
+    /// is rekeyed. Unless the rekeying is signalled, the peer must use the
+    /// same threshold, see [`RekeyThreshold`].
+    pub fn with_rekey_threshold(mut self, rekey_threshold: RekeyThreshold) -> Self {
+        self.set_rekey_threshold(rekey_threshold);
+        self
+    }
+
+    /// Sets the way the rekeying is synchronized with the peer, which must use
+    /// the same mode. The mode can't be changed once the handshake is
+    /// complete.
+    pub fn with_rekey_mode(mut self, rekey_mode: RekeyMode) -> Self {
+        debug_assert_ne!(
+            self.state,
+            XxState::Transport,
+            "rekey mode of the established session"
+        );
+        self.rekey_mode = rekey_mode;
+        self
+    }
+
+    pub fn state(&self) -> XxState {
+        self.state
+    }
+
+    /// Returns static key of the remote peer once the handshake is complete.
+    pub fn remote_static_key(&self) -> Option<&[u8]> {
+        self.decryptor
+            .as_ref()
+            .and_then(|decryptor| decryptor.transport.remote_static.as_deref())
+    }
+
+    /// Returns hash of the handshake transcript once the handshake is
+    /// complete. The hash is the same for both parties and can be used for
+    /// channel binding.
+    pub fn handshake_hash(&self) -> Option<&[u8]> {
+        self.decryptor
+            .as_ref()
+            .map(|decryptor| decryptor.transport.handshake_hash.as_slice())
+    }
+
+    fn send_handshake(&mut self) -> io::Result<()> {
+        let handshake = self.handshake.as_mut().expect("handshake is complete");
+        let mut msg = vec![0u8; 2 + NOISE_MAX_MSG_LEN];
+        let len = handshake
+            .write_message(&[], &mut msg[2..])
+            .map_err(aborted)?;
+        msg[..2].copy_from_slice(&(len as u16).to_be_bytes());
+        msg.truncate(2 + len);
+        #[cfg(feature = "log")]
+        log::trace!(target: "handshake", "Sent {msg:02x?}");
+        #[cfg(feature = "tracing")]
+        tracing::trace!(target: "handshake", len = msg.len(), "Sent handshake act");
+        self.connection.write_all(&msg)?;
+
+        match self.state {
+            XxState::Initiate => {
+                self.state = XxState::AwaitingEsEe;
+                Ok(())
+            }
+            XxState::AwaitingE => {
+                self.state = XxState::AwaitingEs;
+                Ok(())
+            }
+            XxState::AwaitingEsEe => self.start_transport(),
+            XxState::AwaitingEs | XxState::Transport => {
+                unreachable!("handshake message sent in {:?} state", self.state)
+            }
+        }
+    }
+
+    fn receive_handshake(&mut self) -> io::Result<()> {
+        let msg = match read_noise_msg(&mut self.connection, &mut self.handshake_input)? {
+            Some(msg) => msg,
+            None => return Err(io::ErrorKind::UnexpectedEof.into()),
+        };
+        #[cfg(feature = "log")]
+        log::trace!(target: "handshake", "Received {msg:02x?}");
+        #[cfg(feature = "tracing")]
+        tracing::trace!(target: "handshake", len = msg.len(), "Received handshake act");
+
+        let handshake = self.handshake.as_mut().expect("handshake is complete");
+        let mut payload = vec![0u8; NOISE_MAX_MSG_LEN];
+        handshake
+            .read_message(&msg, &mut payload)
+            .map_err(aborted)?;
+
+        match self.state {
+            XxState::AwaitingE | XxState::AwaitingEsEe => self.send_handshake(),
+            XxState::AwaitingEs => self.start_transport(),
+            XxState::Initiate | XxState::Transport => {
+                unreachable!("handshake message received in {:?} state", self.state)
+            }
+        }
+    }
+
+    fn start_transport(&mut self) -> io::Result<()> {
+        let handshake = self.handshake.take().expect("handshake is complete");
+        let remote_static = handshake.get_remote_static().map(<[u8]>::to_vec);
+        let handshake_hash = handshake.get_handshake_hash().to_vec();
+        let state = handshake.into_stateless_transport_mode().map_err(aborted)?;
+        let transport = Arc::new(XxTransport {
+            state: Mutex::new(state),
+            remote_static,
+            handshake_hash,
+        });
+        #[cfg(feature = "log")]
+        log::debug!(target: "handshake", "Noise_XX handshake with {} is complete", self.remote_addr);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(target: "handshake", remote = %self.remote_addr, "Noise_XX handshake is complete");
+
+        self.encryptor = Some(XxEncryptor::new(
+            transport.clone(),
+            self.rekey_mode,
+            self.rekey_threshold,
+        ));
+        self.decryptor = Some(XxDecryptor::new(
+            transport,
+            self.rekey_mode,
+            self.rekey_threshold,
+        ));
+        self.state = XxState::Transport;
+        Ok(())
+    }
+
+    /// Returns statistics of the transport once the handshake is complete.
+    pub fn transport_stats(&self) -> Option<XxTransportStats> {
+        match (&self.encryptor, &self.decryptor) {
+            (Some(encryptor), Some(decryptor)) => Some(XxTransportStats {
+                messages_sent: encryptor.messages,
+                messages_received: decryptor.messages,
+                messages_since_rekey: encryptor.rekey.messages,
+                bytes_since_rekey: encryptor.rekey.bytes,
+                messages_received_since_rekey: decryptor.rekey.messages,
+                bytes_received_since_rekey: decryptor.rekey.bytes,
+                rekeys_sent: encryptor.rekey.rekeys,
+                rekeys_received: decryptor.rekey.rekeys,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl<S: NetConnection> AsRawFd for NoiseXx<S> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.connection.as_raw_fd()
+    }
+}
+
+impl<S: NetConnection> Read for NoiseXx<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(decryptor) = &mut self.decryptor {
+            return decryptor.read(&mut self.connection, buf);
+        }
+        if self.state == XxState::Initiate {
+            return Ok(0);
+        }
+        match self.receive_handshake() {
+            // Non-blocking connection may return only a part of the handshake
+            // message, which is kept until the rest of it arrives
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(0),
+            Err(err) => Err(err),
+            Ok(_) => Ok(0),
+        }
+    }
+}
+
+impl<S: NetConnection> Write for NoiseXx<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if let Some(encryptor) = &mut self.encryptor {
+            return encryptor.write(&mut self.connection, buf);
+        }
+        if self.state == XxState::Initiate {
+            self.send_handshake()?;
+        }
+        Err(io::ErrorKind::Interrupted.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if let Some(encryptor) = &mut self.encryptor {
+            encryptor.flush(&mut self.connection)?;
+        }
+        self.connection.flush()
+    }
+}
+
+impl<S: NetConnection> SplitIo for NoiseXx<S> {
+    type Read = NoiseXxReader<S>;
+    type Write = NoiseXxWriter<S>;
+
+    fn split_io(mut self) -> Result<(Self::Read, Self::Write), SplitIoError<Self>> {
+        if self.state != XxState::Transport {
+            return Err(SplitIoError {
+                original: self,
+                error: io::ErrorKind::NotConnected.into(),
+            });
+        }
+
+        let (reader, writer) = match self.connection.split_io() {
+            Ok((reader, writer)) => (reader, writer),
+            Err(SplitIoError { original, error }) => {
+                self.connection = original;
+                return Err(SplitIoError {
+                    original: self,
+                    error,
+                });
+            }
+        };
+
+        Ok((
+            NoiseXxReader {
+                reader,
+                decryptor: self.decryptor.expect("transport state"),
+            },
+            NoiseXxWriter {
+                remote_addr: self.remote_addr,
+                writer,
+                encryptor: self.encryptor.expect("transport state"),
+            },
+        ))
+    }
+
+    fn from_split_io(read: Self::Read, write: Self::Write) -> Self {
+        if !Arc::ptr_eq(&read.decryptor.transport, &write.encryptor.transport) {
+            panic!("merging unrelated objects");
+        }
+        Self {
+            remote_addr: write.remote_addr,
+            connection: S::from_split_io(read.reader, write.writer),
+            state: XxState::Transport,
+            handshake: None,
+            handshake_input: vec![],
+            rekey_mode: write.encryptor.rekey_mode,
+            rekey_threshold: write.encryptor.rekey.threshold,
+            encryptor: Some(write.encryptor),
+            decryptor: Some(read.decryptor),
+        }
+    }
+}
+
+impl<S: NetConnection> NetSession for NoiseXx<S> {
+    type Context = Keypair;
+    type Connection = S;
+    type Id = [u8; 32];
+    type PeerAddr = S::Addr;
+    type TransientAddr = S::Addr;
+
+    fn accept(connection: S, context: &Self::Context) -> io::Result<Self> {
+        let handshake = xx_builder()
+            .local_private_key(&context.private)
+            .build_responder()
+            .map_err(aborted)?;
+        Ok(Self::with_handshake(
+            connection,
+            handshake,
+            XxState::AwaitingE,
+        ))
+    }
+
+    fn connect_blocking<P: Proxy>(
+        addr: Self::PeerAddr,
+        context: &Self::Context,
+        proxy: &P,
+    ) -> Result<Self, P::Error> {
+        #[cfg(feature = "tracing")]
+        let _span = tracing::debug_span!(target: "handshake", "noise_xx", peer = %addr).entered();
+        let connection = S::connect_blocking(addr, proxy)?;
+        let mut session = Self::initiate(connection, context)?;
+        while session.state != XxState::Transport {
+            if session.state == XxState::Initiate {
+                session.send_handshake()?;
+            } else {
+                session.receive_handshake()?;
+            }
+        }
+        Ok(session)
+    }
+
+    #[cfg(feature = "socket2")]
+    fn connect_nonblocking<P: Proxy>(
+        addr: Self::PeerAddr,
+        context: &Self::Context,
+        proxy: &P,
+    ) -> Result<Self, P::Error> {
+        let connection = S::connect_nonblocking(addr, proxy)?;
+        Self::initiate(connection, context).map_err(P::Error::from)
+    }
+
+    fn session_id(&self) -> Option<Self::Id> {
+        self.remote_static_key()
+            .and_then(|key| <[u8; 32]>::try_from(key).ok())
+    }
+
+    fn is_session_established(&self) -> bool {
+        self.state == XxState::Transport
+    }
+
+    fn transient_addr(&self) -> Self::TransientAddr {
+        self.remote_addr.clone()
+    }
+
+    fn peer_addr(&self) -> Option<Self::PeerAddr> {
+        Some(self.remote_addr.clone())
+    }
+
+    fn local_addr(&self) -> <Self::Connection as NetConnection>::Addr {
+        self.connection.local_addr()
+    }
+
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        self.connection.read_timeout()
+    }
+
+    fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        self.connection.write_timeout()
+    }
+
+    fn set_read_timeout(&mut self, dur: Option<Duration>) -> io::Result<()> {
+        self.connection.set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&mut self, dur: Option<Duration>) -> io::Result<()> {
+        self.connection.set_write_timeout(dur)
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.connection.set_nonblocking(nonblocking)
+    }
+
+    fn set_rekey_threshold(&mut self, threshold: RekeyThreshold) {
+        self.rekey_threshold = threshold;
+        if let Some(encryptor) = &mut self.encryptor {
+            encryptor.rekey.threshold = threshold;
+        }
+        if let Some(decryptor) = &mut self.decryptor {
+            decryptor.rekey.threshold = threshold;
+        }
+    }
+
+    fn has_pending_output(&self) -> bool {
+        self.encryptor
+            .as_ref()
+            .map(XxEncryptor::has_pending_output)
+            .unwrap_or_default()
+    }
+
+    fn proxy_addrs(&self) -> Option<ProxyAddrs> {
+        self.connection.proxy_addrs()
+    }
+
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.flush()?;
+        self.connection.shutdown(net::Shutdown::Write)
+    }
+
+    fn disconnect(mut self) -> io::Result<()> {
+        self.connection.shutdown(net::Shutdown::Both)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::net::{Ipv4Addr, Shutdown};
+
+    use cyphernet::addr::{HostName, NetAddr};
+
+    use super::*;
+
+    /// Data sent in one direction of a [`MemSocket`] pair.
+    #[derive(Debug, Default)]
+    struct MemPipe {
+        data: VecDeque<u8>,
+        closed: bool,
+    }
+
+    /// In-memory non-blocking connection, which doesn't need network access
+    /// to run the sessions.
+    #[derive(Clone, Debug)]
+    struct MemSocket {
+        addr: NetAddr<HostName>,
+        remote_addr: NetAddr<HostName>,
+        input: Arc<Mutex<MemPipe>>,
+        output: Arc<Mutex<MemPipe>>,
+    }
+
+    impl MemSocket {
+        /// Returns pair of connected sockets.
+        fn pair() -> (Self, Self) {
+            let addr = |port| NetAddr {
+                host: HostName::Ip(Ipv4Addr::LOCALHOST.into()),
+                port,
+            };
+            let (a, b) = (
+                Arc::<Mutex<MemPipe>>::default(),
+                Arc::<Mutex<MemPipe>>::default(),
+            );
+            let client = Self {
+                addr: addr(1),
+                remote_addr: addr(2),
+                input: a.clone(),
+                output: b.clone(),
+            };
+            let server = Self {
+                addr: addr(2),
+                remote_addr: addr(1),
+                input: b,
+                output: a,
+            };
+            (client, server)
+        }
+    }
+
+    impl Read for MemSocket {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let mut input = self.input.lock().unwrap();
+            if input.data.is_empty() && !buf.is_empty() {
+                return match input.closed {
+                    true => Ok(0),
+                    false => Err(io::ErrorKind::WouldBlock.into()),
+                };
+            }
+            let len = buf.len().min(input.data.len());
+            for (byte, data) in buf.iter_mut().zip(input.data.drain(..len)) {
+                *byte = data;
+            }
+            Ok(len)
+        }
+    }
+
+    impl Write for MemSocket {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut output = self.output.lock().unwrap();
+            if output.closed {
+                return Err(io::ErrorKind::BrokenPipe.into());
+            }
+            output.data.extend(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Sockets are never polled by a reactor, so they have no file
+    /// descriptor.
+    impl AsRawFd for MemSocket {
+        fn as_raw_fd(&self) -> RawFd {
+            -1
+        }
+    }
+
+    impl SplitIo for MemSocket {
+        type Read = Self;
+        type Write = Self;
+
+        fn split_io(self) -> Result<(Self::Read, Self::Write), SplitIoError<Self>> {
+            Ok((self.clone(), self))
+        }
+
+        fn from_split_io(_read: Self::Read, write: Self::Write) -> Self {
+            write
+        }
+    }
+
+    impl NetConnection for MemSocket {
+        type Addr = NetAddr<HostName>;
+
+        fn connect_blocking<P: Proxy>(_: Self::Addr, _: &P) -> Result<Self, P::Error> {
+            unimplemented!("in-memory sockets are created in connected pairs")
+        }
+
+        #[cfg(feature = "socket2")]
+        fn connect_nonblocking<P: Proxy>(_: Self::Addr, _: &P) -> Result<Self, P::Error> {
+            unimplemented!("in-memory sockets are created in connected pairs")
+        }
+
+        fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+            if how != Shutdown::Write {
+                self.input.lock().unwrap().closed = true;
+            }
+            if how != Shutdown::Read {
+                self.output.lock().unwrap().closed = true;
+            }
+            Ok(())
+        }
+
+        fn remote_addr(&self) -> Self::Addr {
+            self.remote_addr.clone()
+        }
+        fn local_addr(&self) -> Self::Addr {
+            self.addr.clone()
+        }
+
+        fn set_read_timeout(&mut self, _: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+        fn set_write_timeout(&mut self, _: Option<Duration>) -> io::Result<()> {
+            Ok(())
+        }
+        fn read_timeout(&self) -> io::Result<Option<Duration>> {
+            Ok(None)
+        }
+        fn write_timeout(&self) -> io::Result<Option<Duration>> {
+            Ok(None)
+        }
+
+        fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+            let input = self.input.lock().unwrap();
+            let len = buf.len().min(input.data.len());
+            for (byte, data) in buf.iter_mut().zip(&input.data) {
+                *byte = *data;
+            }
+            Ok(len)
+        }
+
+        fn set_nodelay(&mut self, _: bool) -> io::Result<()> {
+            Ok(())
+        }
+        fn nodelay(&self) -> io::Result<bool> {
+            Ok(true)
+        }
+        fn set_ttl(&mut self, _: u32) -> io::Result<()> {
+            Ok(())
+        }
+        fn ttl(&self) -> io::Result<u32> {
+            Ok(64)
+        }
+        fn set_nonblocking(&mut self, _: bool) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn try_clone(&self) -> io::Result<Self> {
+            Ok(self.clone())
+        }
+        fn take_error(&self) -> io::Result<Option<io::Error>> {
+            Ok(None)
+        }
+    }
+
+    /// Drives the handshake the way [`crate::NetResource`] does it until both
+    /// sessions are established.
+    fn handshake(initiator: &mut NoiseXx<MemSocket>, responder: &mut NoiseXx<MemSocket>) {
+        for _ in 0..1000 {
+            if initiator.is_session_established() && responder.is_session_established() {
+                return;
+            }
+            let _ = initiator.write(&[]);
+            assert_eq!(initiator.read(&mut []).unwrap(), 0);
+            assert_eq!(responder.read(&mut []).unwrap(), 0);
+        }
+        panic!("handshake has not completed");
+    }
+
+    fn read_exact(reader: &mut impl Read, len: usize) -> Vec<u8> {
+        let mut data = vec![0u8; len];
+        let mut pos = 0;
+        while pos < len {
+            match reader.read(&mut data[pos..]) {
+                Ok(0) => panic!("connection closed"),
+                Ok(read) => pos += read,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(err) => panic!("{err}"),
+            }
+        }
+        data
+    }
+
+    #[test]
+    fn xx_handshake() {
+        let (initiator_keys, responder_keys) = (xx_keypair(), xx_keypair());
+        let (client, server) = MemSocket::pair();
+        let mut initiator = NoiseXx::initiate(client, &initiator_keys).unwrap();
+        let mut responder = NoiseXx::accept(server, &responder_keys).unwrap();
+        assert_eq!(initiator.state(), XxState::Initiate);
+        assert_eq!(responder.state(), XxState::AwaitingE);
+        assert_eq!(initiator.remote_static_key(), None);
+
+        handshake(&mut initiator, &mut responder);
+        assert_eq!(initiator.state(), XxState::Transport);
+        assert_eq!(responder.state(), XxState::Transport);
+        assert_eq!(
+            initiator.remote_static_key(),
+            Some(responder_keys.public.as_slice())
+        );
+        assert_eq!(
+            responder.remote_static_key(),
+            Some(initiator_keys.public.as_slice())
+        );
+        assert_eq!(
+            responder.session_id().unwrap().as_slice(),
+            initiator_keys.public
+        );
+        assert!(initiator.handshake_hash().is_some());
+        assert_eq!(initiator.handshake_hash(), responder.handshake_hash());
+
+        // Both sides must agree on the transport keys for the data to be
+        // decrypted, including the ones exceeding maximal Noise message size
+        initiator.write_all(b"ping").unwrap();
+        initiator.flush().unwrap();
+        assert_eq!(read_exact(&mut responder, 4), b"ping");
+        let data = (0..100_000).map(|no| no as u8).collect::<Vec<_>>();
+        responder.write_all(&data).unwrap();
+        responder.flush().unwrap();
+        assert_eq!(read_exact(&mut initiator, data.len()), data);
+    }
+
+    #[test]
+    fn xx_split() {
+        let (client, server) = MemSocket::pair();
+        let mut initiator = NoiseXx::initiate(client, &xx_keypair()).unwrap();
+        let mut responder = NoiseXx::accept(server, &xx_keypair()).unwrap();
+        handshake(&mut initiator, &mut responder);
+
+        let (mut reader, mut writer) = initiator.split_io().unwrap();
+        writer.write_all(b"ping").unwrap();
+        assert_eq!(read_exact(&mut responder, 4), b"ping");
+        responder.write_all(b"pong").unwrap();
+        assert_eq!(read_exact(&mut reader, 4), b"pong");
+
+        let mut initiator = NoiseXx::from_split_io(reader, writer);
+        initiator.write_all(b"ping").unwrap();
+        assert_eq!(read_exact(&mut responder, 4), b"ping");
+    }
+
+    #[test]
+    fn xx_rekey() {
+        const MESSAGES: u64 = 1000;
+        let threshold = RekeyThreshold {
+            messages: MESSAGES,
+            bytes: u64::MAX,
+        };
+
+        let (client, server) = MemSocket::pair();
+        let mut initiator = NoiseXx::initiate(client, &xx_keypair()).unwrap();
+        let mut responder = NoiseXx::accept(server, &xx_keypair()).unwrap();
+        initiator.set_rekey_threshold(threshold);
+        responder.set_rekey_threshold(threshold);
+        handshake(&mut initiator, &mut responder);
+
+        for no in 0..MESSAGES {
+            initiator.write_all(&no.to_be_bytes()).unwrap();
+            assert_eq!(read_exact(&mut responder, 8), no.to_be_bytes());
+        }
+        let stats = initiator.transport_stats().unwrap();
+        assert_eq!(stats.messages_since_rekey, MESSAGES);
+        assert_eq!(stats.rekeys_sent, 0);
+
+        // Next message triggers the rekeying, on both sides without any
+        // signalling
+        for no in MESSAGES..2 * MESSAGES + 10 {
+            initiator.write_all(&no.to_be_bytes()).unwrap();
+            assert_eq!(read_exact(&mut responder, 8), no.to_be_bytes());
+        }
+        let stats = initiator.transport_stats().unwrap();
+        assert_eq!(stats.messages_sent, 2 * MESSAGES + 10);
+        assert_eq!(stats.messages_since_rekey, 10);
+        assert_eq!(stats.rekeys_sent, 2);
+        let stats = responder.transport_stats().unwrap();
+        assert_eq!(stats.messages_received, 2 * MESSAGES + 10);
+        assert_eq!(stats.messages_received_since_rekey, 10);
+        assert_eq!(stats.rekeys_received, 2);
+
+        // Keys in the opposite direction are rekeyed independently
+        assert_eq!(stats.rekeys_sent, 0);
+        responder.write_all(b"pong").unwrap();
+        assert_eq!(read_exact(&mut initiator, 4), b"pong");
+    }
+
+    #[test]
+    fn xx_rekey_by_bytes() {
+        let threshold = RekeyThreshold {
+            messages: u64::MAX,
+            bytes: 100_000,
+        };
+        let (client, server) = MemSocket::pair();
+        let mut initiator = NoiseXx::initiate(client, &xx_keypair())
+            .unwrap()
+            .with_rekey_threshold(threshold);
+        let mut responder = NoiseXx::accept(server, &xx_keypair())
+            .unwrap()
+            .with_rekey_threshold(threshold);
+        handshake(&mut initiator, &mut responder);
+
+        let data = vec![0xA5u8; 60_000];
+        for _ in 0..5 {
+            responder.write_all(&data).unwrap();
+            assert_eq!(read_exact(&mut initiator, data.len()), data);
+            initiator.write_all(&data).unwrap();
+            assert_eq!(read_exact(&mut responder, data.len()), data);
+        }
+        for session in [&initiator, &responder] {
+            let stats = session.transport_stats().unwrap();
+            assert_eq!(stats.rekeys_sent, 2);
+            assert_eq!(stats.rekeys_received, 2);
+            assert_eq!(stats.bytes_since_rekey, stats.bytes_received_since_rekey);
+        }
+    }
+
+    #[test]
+    fn xx_rekey_threshold_mismatch() {
+        let (client, server) = MemSocket::pair();
+        let mut initiator = NoiseXx::initiate(client, &xx_keypair())
+            .unwrap()
+            .with_rekey_threshold(RekeyThreshold {
+                messages: 1,
+                bytes: u64::MAX,
+            });
+        let mut responder = NoiseXx::accept(server, &xx_keypair()).unwrap();
+        handshake(&mut initiator, &mut responder);
+
+        initiator.write_all(b"ping").unwrap();
+        assert_eq!(read_exact(&mut responder, 4), b"ping");
+        // Responder does not rekey its receiving key together with the
+        // initiator
+        initiator.write_all(b"ping").unwrap();
+        let err = loop {
+            match responder.read(&mut [0u8; 16]) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                res => break res.unwrap_err(),
+            }
+        };
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    }
+
+    #[test]
+    fn xx_signalled_rekey() {
+        const MESSAGES: u64 = 100_000;
+
+        let (client, server) = MemSocket::pair();
+        let mut initiator = NoiseXx::initiate(client, &xx_keypair())
+            .unwrap()
+            .with_rekey_mode(RekeyMode::Signalled)
+            .with_rekey_threshold(RekeyThreshold {
+                messages: MESSAGES,
+                bytes: u64::MAX,
+            });
+        // Responder follows the signals of the initiator regardless of its
+        // own threshold
+        let mut responder = NoiseXx::accept(server, &xx_keypair())
+            .unwrap()
+            .with_rekey_mode(RekeyMode::Signalled);
+        handshake(&mut initiator, &mut responder);
+
+        for no in 0..MESSAGES {
+            initiator.write_all(&no.to_be_bytes()).unwrap();
+            assert_eq!(read_exact(&mut responder, 8), no.to_be_bytes());
+        }
+        let stats = initiator.transport_stats().unwrap();
+        assert_eq!(stats.messages_since_rekey, MESSAGES);
+        assert_eq!(stats.rekeys_sent, 0);
+
+        // Next message triggers the rekeying
+        for no in MESSAGES..MESSAGES + 10 {
+            initiator.write_all(&no.to_be_bytes()).unwrap();
+            assert_eq!(read_exact(&mut responder, 8), no.to_be_bytes());
+        }
+        let stats = initiator.transport_stats().unwrap();
+        assert_eq!(stats.messages_sent, MESSAGES + 10);
+        assert_eq!(stats.messages_since_rekey, 10);
+        assert_eq!(stats.rekeys_sent, 1);
+        let stats = responder.transport_stats().unwrap();
+        assert_eq!(stats.messages_received, MESSAGES + 10);
+        assert_eq!(stats.messages_received_since_rekey, 10);
+        assert_eq!(stats.rekeys_received, 1);
+
+        // Keys in the opposite direction are not affected
+        responder.write_all(b"pong").unwrap();
+        assert_eq!(read_exact(&mut initiator, 4), b"pong");
+    }
+
+    #[test]
+    fn xx_tampered_data() {
+        let (client, server) = MemSocket::pair();
+        let mut initiator = NoiseXx::initiate(client, &xx_keypair()).unwrap();
+        let mut responder = NoiseXx::accept(server, &xx_keypair()).unwrap();
+        handshake(&mut initiator, &mut responder);
+
+        let mut msg = vec![0u8, 20];
+        msg.extend([0xA5; 20]);
+        initiator.connection.write_all(&msg).unwrap();
+        let err = loop {
+            match responder.read(&mut [0u8; 16]) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                res => break res.unwrap_err(),
+            }
+        };
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionAborted);
+    }
+}
@@ -0,0 +1,434 @@
+use std::collections::HashSet;
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use reactor::{Resource, TimeoutManager};
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum PoolError {
+    /// no connection was released to the pool within the timeout
+    Timeout,
+
+    /// unable to establish a new pooled connection: {0}
+    #[from]
+    Connect(io::Error),
+}
+
+/// Statistics of a [`ConnectionPool`], returned by [`ConnectionPool::stats`].
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub struct PoolStats {
+    /// Number of the connections currently acquired from the pool.
+    pub active: usize,
+    /// Number of the connections waiting in the pool to be acquired.
+    pub idle: usize,
+    /// Number of the successful [`ConnectionPool::acquire`] calls.
+    pub total_acquired: u64,
+    /// Number of the idle connections closed by the pool after the idle
+    /// timeout.
+    pub total_evicted: u64,
+}
+
+struct PoolState<R: Resource> {
+    /// Idle connections, the most recently released one being the last.
+    idle: Vec<R>,
+    /// Ids of the acquired connections.
+    active: HashSet<R::Id>,
+    /// Number of the connections being established.
+    connecting: usize,
+    /// Idle timeouts of the connections in [`PoolState::idle`].
+    timeouts: TimeoutManager<R::Id>,
+    total_acquired: u64,
+    total_evicted: u64,
+    is_closed: bool,
+}
+
+impl<R: Resource> PoolState<R> {
+    fn len(&self) -> usize {
+        self.idle.len() + self.active.len() + self.connecting
+    }
+}
+
+struct Shared<R: Resource> {
+    state: Mutex<PoolState<R>>,
+    /// Notified each time a slot or an idle connection becomes available.
+    released: Condvar,
+    /// Notified each time an idle timeout is added or the pool is closed.
+    rescheduled: Condvar,
+    idle_timeout: Duration,
+}
+
+impl<R: Resource> Shared<R> {
+    fn lock(&self) -> MutexGuard<'_, PoolState<R>> {
+        self.state.lock().expect("poisoned connection pool lock")
+    }
+
+    fn check_out(self: &Arc<Self>, state: &mut PoolState<R>, conn: R) -> PooledConnection<R> {
+        let is_new = state.active.insert(conn.id());
+        assert!(is_new, "connection {} is acquired twice", conn.id());
+        state.total_acquired += 1;
+        PooledConnection {
+            conn: Some(conn),
+            shared: self.clone(),
+        }
+    }
+
+    fn release(&self, conn: R, reuse: bool) {
+        let id = conn.id();
+        let mut state = self.lock();
+        let was_active = state.active.remove(&id);
+        assert!(was_active, "connection {id} is released twice");
+        let conn = if reuse && !state.is_closed {
+            state.timeouts.register(id, unix_time() + self.idle_timeout);
+            state.idle.push(conn);
+            None
+        } else {
+            Some(conn)
+        };
+        drop(state);
+        self.released.notify_one();
+        self.rescheduled.notify_one();
+        if let Some(conn) = conn {
+            close(conn);
+        }
+    }
+}
+
+/// Pool keeping up to `max_size` connections to a peer open, such that they
+/// can be reused without establishing a new connection each time.
+///
+/// Connections are established on demand by the function provided to
+/// [`ConnectionPool::new`] and are handed out as [`PooledConnection`] guards
+/// returning them to the pool once dropped. Connections which stay idle in
+/// the pool for longer than the idle timeout are closed by a background
+/// thread, which tracks them with the reactor [`TimeoutManager`].
+///
+/// The pool is `Send + Sync` and can be shared between threads with an
+/// [`Arc`].
+pub struct ConnectionPool<R: Resource> {
+    shared: Arc<Shared<R>>,
+    connect: Box<dyn Fn() -> io::Result<R> + Send + Sync>,
+    max_size: usize,
+    evictor: Option<JoinHandle<()>>,
+}
+
+impl<R: Resource + 'static> ConnectionPool<R> {
+    /// Constructs pool establishing new connections with `connect` and
+    /// starts the thread evicting the connections staying idle for
+    /// `idle_timeout`.
+    ///
+    /// # Panics
+    ///
+    /// If `max_size` is zero.
+    pub fn new(
+        max_size: usize,
+        idle_timeout: Duration,
+        connect: impl Fn() -> io::Result<R> + Send + Sync + 'static,
+    ) -> Self {
+        assert!(max_size > 0, "zero size of the connection pool");
+        let shared = Arc::new(Shared {
+            state: Mutex::new(PoolState {
+                idle: empty!(),
+                active: empty!(),
+                connecting: 0,
+                timeouts: TimeoutManager::new(Duration::ZERO),
+                total_acquired: 0,
+                total_evicted: 0,
+                is_closed: false,
+            }),
+            released: Condvar::new(),
+            rescheduled: Condvar::new(),
+            idle_timeout,
+        });
+        let evictor = {
+            let shared = shared.clone();
+            thread::spawn(move || evict(shared))
+        };
+        ConnectionPool {
+            shared,
+            connect: Box::new(connect),
+            max_size,
+            evictor: Some(evictor),
+        }
+    }
+}
+
+impl<R: Resource> ConnectionPool<R> {
+    /// Returns the maximal number of the open connections.
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Acquires the most recently released idle connection or, if there are
+    /// none, establishes a new one. If the pool has already reached its
+    /// maximal size, blocks until a connection is released, failing with
+    /// [`PoolError::Timeout`] if this does not happen within `timeout`.
+    pub fn acquire(&self, timeout: Duration) -> Result<PooledConnection<R>, PoolError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.lock();
+        loop {
+            if let Some(conn) = state.idle.pop() {
+                state.timeouts.cancel(&conn.id());
+                return Ok(self.shared.check_out(&mut state, conn));
+            }
+            if state.len() < self.max_size {
+                // The slot is reserved while the lock is released, so a slow
+                // connection does not block the other users of the pool
+                state.connecting += 1;
+                drop(state);
+                let res = (self.connect)();
+                let mut state = self.shared.lock();
+                state.connecting -= 1;
+                return match res {
+                    Ok(conn) => Ok(self.shared.check_out(&mut state, conn)),
+                    Err(err) => {
+                        drop(state);
+                        self.shared.released.notify_one();
+                        Err(PoolError::Connect(err))
+                    }
+                };
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(PoolError::Timeout);
+            }
+            state = self
+                .shared
+                .released
+                .wait_timeout(state, deadline - now)
+                .expect("poisoned connection pool lock")
+                .0;
+        }
+    }
+
+    /// Returns statistics of the pool.
+    pub fn stats(&self) -> PoolStats {
+        let state = self.shared.lock();
+        PoolStats {
+            active: state.active.len(),
+            idle: state.idle.len(),
+            total_acquired: state.total_acquired,
+            total_evicted: state.total_evicted,
+        }
+    }
+}
+
+impl<R: Resource> Drop for ConnectionPool<R> {
+    /// Stops the eviction thread and closes the idle connections; the
+    /// acquired connections are closed once released.
+    fn drop(&mut self) {
+        let idle = {
+            let mut state = self.shared.lock();
+            state.is_closed = true;
+            std::mem::take(&mut state.idle)
+        };
+        self.shared.rescheduled.notify_one();
+        if let Some(evictor) = self.evictor.take() {
+            let _ = evictor.join();
+        }
+        idle.into_iter().for_each(close);
+    }
+}
+
+/// Connection acquired from a [`ConnectionPool`], which is returned to the
+/// pool once the guard is dropped.
+pub struct PooledConnection<R: Resource> {
+    /// Always `Some` until the guard is dropped or discarded.
+    conn: Option<R>,
+    shared: Arc<Shared<R>>,
+}
+
+impl<R: Resource> PooledConnection<R> {
+    /// Closes the connection instead of returning it to the pool, which must
+    /// be done once the connection is broken.
+    pub fn discard(mut self) {
+        let conn = self.conn.take().expect("pooled connection is present");
+        self.shared.release(conn, false);
+    }
+}
+
+impl<R: Resource> Deref for PooledConnection<R> {
+    type Target = R;
+
+    fn deref(&self) -> &Self::Target {
+        self.conn.as_ref().expect("pooled connection is present")
+    }
+}
+
+impl<R: Resource> DerefMut for PooledConnection<R> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.conn.as_mut().expect("pooled connection is present")
+    }
+}
+
+impl<R: Resource> Drop for PooledConnection<R> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            self.shared.release(conn, true);
+        }
+    }
+}
+
+/// Body of the eviction thread, closing the connections once their idle
+/// timeouts fire.
+fn evict<R: Resource>(shared: Arc<Shared<R>>) {
+    let mut fired = vec![];
+    let mut state = shared.lock();
+    while !state.is_closed {
+        let now = unix_time();
+        if state.timeouts.check(now, &mut fired) > 0 {
+            let mut evicted = Vec::with_capacity(fired.len());
+            for id in fired.drain(..) {
+                if let Some(pos) = state.idle.iter().position(|conn| conn.id() == id) {
+                    evicted.push(state.idle.remove(pos));
+                }
+            }
+            state.total_evicted += evicted.len() as u64;
+            drop(state);
+            shared.released.notify_all();
+            evicted.into_iter().for_each(close);
+            state = shared.lock();
+            continue;
+        }
+        state = match state.timeouts.next(now) {
+            Some(timeout) => {
+                shared
+                    .rescheduled
+                    .wait_timeout(state, timeout)
+                    .expect("poisoned connection pool lock")
+                    .0
+            }
+            None => shared
+                .rescheduled
+                .wait(state)
+                .expect("poisoned connection pool lock"),
+        };
+    }
+}
+
+fn close<R: Resource>(conn: R) {
+    let id = conn.id();
+    if let Err(err) = conn.disconnect() {
+        #[cfg(feature = "log")]
+        log::warn!(target: "pool", "Error closing pooled connection {id}: {err}");
+        #[cfg(feature = "tracing")]
+        tracing::warn!(target: "pool", %id, %err, "Error closing pooled connection");
+        #[cfg(not(any(feature = "log", feature = "tracing")))]
+        let _ = (id, err);
+    }
+}
+
+fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time")
+}
+
+#[cfg(test)]
+#[cfg(feature = "socket2")]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+    use std::os::fd::RawFd;
+
+    use super::*;
+    use crate::NetResource;
+
+    fn pool(max_size: usize, idle_timeout: Duration) -> ConnectionPool<NetResource<TcpStream>> {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Accepted connections are kept open until the process exits
+        thread::spawn(move || listener.incoming().map(Result::unwrap).collect::<Vec<_>>());
+        ConnectionPool::new(max_size, idle_timeout, move || {
+            NetResource::new(TcpStream::connect(addr)?)
+        })
+    }
+
+    #[test]
+    fn is_send_sync() {
+        fn check<T: Send + Sync>() {}
+        check::<ConnectionPool<NetResource<TcpStream>>>();
+    }
+
+    #[test]
+    fn concurrent_use() {
+        const THREADS: usize = 8;
+        const ACQUISITIONS: usize = 50;
+        const MAX_SIZE: usize = 4;
+
+        let pool = Arc::new(pool(MAX_SIZE, Duration::from_secs(60)));
+        let in_use = Arc::new(Mutex::new(HashSet::<RawFd>::new()));
+        let threads = (0..THREADS)
+            .map(|_| {
+                let pool = pool.clone();
+                let in_use = in_use.clone();
+                thread::spawn(move || {
+                    for _ in 0..ACQUISITIONS {
+                        let conn = pool.acquire(Duration::from_secs(10)).unwrap();
+                        let id = conn.id();
+                        assert!(in_use.lock().unwrap().insert(id), "{id} is used twice");
+                        thread::yield_now();
+                        assert!(in_use.lock().unwrap().remove(&id));
+                        drop(conn);
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let stats = pool.stats();
+        assert_eq!(stats.active, 0);
+        assert!(stats.idle > 0 && stats.idle <= MAX_SIZE);
+        assert_eq!(stats.total_acquired, (THREADS * ACQUISITIONS) as u64);
+        assert_eq!(stats.total_evicted, 0);
+    }
+
+    #[test]
+    fn acquire_timeout() {
+        let pool = pool(1, Duration::from_secs(60));
+        let conn = pool.acquire(Duration::from_secs(1)).unwrap();
+        assert!(matches!(
+            pool.acquire(Duration::from_millis(100)),
+            Err(PoolError::Timeout)
+        ));
+
+        // Released connection is reused
+        let id = conn.id();
+        drop(conn);
+        assert_eq!(pool.acquire(Duration::from_millis(100)).unwrap().id(), id);
+
+        // Discarded connection frees the slot
+        pool.acquire(Duration::from_millis(100)).unwrap().discard();
+        assert_eq!(pool.stats().idle, 0);
+        pool.acquire(Duration::from_millis(100)).unwrap();
+        assert_eq!(pool.stats().total_acquired, 4);
+    }
+
+    #[test]
+    fn idle_eviction() {
+        let pool = pool(2, Duration::from_millis(100));
+        let first = pool.acquire(Duration::from_secs(1)).unwrap();
+        let second = pool.acquire(Duration::from_secs(1)).unwrap();
+        drop(first);
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(
+            pool.stats(),
+            PoolStats {
+                active: 1,
+                idle: 0,
+                total_acquired: 2,
+                total_evicted: 1,
+            }
+        );
+
+        drop(second);
+        assert_eq!(pool.stats().idle, 1);
+        thread::sleep(Duration::from_millis(300));
+        assert_eq!(pool.stats().idle, 0);
+        assert_eq!(pool.stats().total_evicted, 2);
+    }
+}
@@ -0,0 +1,232 @@
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::{io, option};
+
+use crate::connection::Proxy;
+use crate::http_connect::{HttpConnect, HttpConnectError};
+use crate::socks4::{Socks4, Socks4Error};
+use crate::socks5::{Socks5, Socks5Dst, Socks5Error, ToSocks5Dst};
+
+/// Maximal number of proxies in a [`ProxyChain`].
+pub const MAX_CHAIN_LEN: usize = 8;
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum ProxyError {
+    #[from]
+    #[from(io::ErrorKind)]
+    #[display(inner)]
+    Io(io::Error),
+
+    /// invalid destination: {0}
+    InvalidDestination(Socks5Error),
+
+    /// SOCKS5 proxy #{0} has failed: {1}
+    Socks5(usize, Socks5Error),
+
+    /// SOCKS4 proxy #{0} has failed: {1}
+    Socks4(usize, Socks4Error),
+
+    /// HTTP proxy #{0} has failed: {1}
+    HttpConnect(usize, HttpConnectError),
+
+    /// proxy chain must contain at least one proxy
+    EmptyChain,
+
+    /// proxy chain of {0} proxies exceeds the maximal length of {MAX_CHAIN_LEN}
+    ChainTooLong(usize),
+}
+
+/// Proxy which can be used in a [`ProxyChain`].
+#[derive(Clone, Eq, PartialEq, Debug, From)]
+pub enum ChainedProxy {
+    #[from]
+    Socks5(Socks5),
+
+    #[from]
+    Socks4(Socks4),
+
+    #[from]
+    HttpConnect(HttpConnect),
+}
+
+impl ChainedProxy {
+    /// Returns address of the proxy.
+    pub fn addr(&self) -> SocketAddr {
+        let addr = match self {
+            ChainedProxy::Socks5(proxy) => proxy.to_socket_addrs(),
+            ChainedProxy::Socks4(proxy) => proxy.to_socket_addrs(),
+            ChainedProxy::HttpConnect(proxy) => proxy.to_socket_addrs(),
+        };
+        addr.ok()
+            .and_then(|mut addr| addr.next())
+            .expect("proxies are constructed with a known address")
+    }
+
+    /// Performs handshake with the proxy, which is number `no` in the chain.
+    fn tunnel(&self, no: usize, stream: &mut TcpStream, dst: Socks5Dst) -> Result<(), ProxyError> {
+        match self {
+            ChainedProxy::Socks5(proxy) => proxy
+                .tunnel(stream, dst)
+                .map_err(|err| ProxyError::Socks5(no, err)),
+            ChainedProxy::Socks4(proxy) => proxy
+                .tunnel(stream, dst)
+                .map_err(|err| ProxyError::Socks4(no, err)),
+            ChainedProxy::HttpConnect(proxy) => proxy
+                .tunnel(stream, dst)
+                .map_err(|err| ProxyError::HttpConnect(no, err)),
+        }
+    }
+}
+
+/// Sequence of proxies a connection is routed through: the first proxy is
+/// requested to connect to the second one, which is requested to connect to
+/// the next one through the established tunnel, and so on until the last
+/// proxy connects to the destination.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct ProxyChain(Vec<ChainedProxy>);
+
+impl ProxyChain {
+    /// # Errors
+    ///
+    /// If the chain is empty or contains more than [`MAX_CHAIN_LEN`] proxies.
+    pub fn new(proxies: impl IntoIterator<Item = ChainedProxy>) -> Result<Self, ProxyError> {
+        let proxies = proxies.into_iter().collect::<Vec<_>>();
+        if proxies.is_empty() {
+            return Err(ProxyError::EmptyChain);
+        }
+        if proxies.len() > MAX_CHAIN_LEN {
+            return Err(ProxyError::ChainTooLong(proxies.len()));
+        }
+        Ok(Self(proxies))
+    }
+
+    /// Returns proxies of the chain in the order the connection is routed
+    /// through them.
+    pub fn proxies(&self) -> &[ChainedProxy] {
+        &self.0
+    }
+}
+
+impl ToSocketAddrs for ProxyChain {
+    type Iter = option::IntoIter<SocketAddr>;
+
+    /// Returns address of the first proxy of the chain.
+    fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+        Ok(Some(self.0[0].addr()).into_iter())
+    }
+}
+
+impl Proxy for ProxyChain {
+    type Error = ProxyError;
+
+    fn connect_blocking<A: ToSocks5Dst>(&self, addr: A) -> Result<TcpStream, Self::Error> {
+        let dst = addr
+            .to_socks5_dst()
+            .map_err(ProxyError::InvalidDestination)?;
+        let mut stream = TcpStream::connect(self.0[0].addr())?;
+        self.tunnel(&mut stream, dst)?;
+        Ok(stream)
+    }
+
+    /// Performs the proxy handshakes in blocking mode, returning the stream
+    /// switched to non-blocking mode once the connection is established.
+    #[cfg(feature = "socket2")]
+    fn connect_nonblocking<A: ToSocks5Dst>(&self, addr: A) -> Result<TcpStream, Self::Error> {
+        let stream = self.connect_blocking(addr)?;
+        stream.set_nonblocking(true)?;
+        Ok(stream)
+    }
+
+    fn tunnel(&self, stream: &mut TcpStream, dst: Socks5Dst) -> Result<(), Self::Error> {
+        // Each proxy is requested to connect to the next one, with the last
+        // one connecting to the destination
+        let hops = self
+            .0
+            .iter()
+            .skip(1)
+            .map(|proxy| Socks5Dst::Ip(proxy.addr()))
+            .chain([dst]);
+        for (no, (proxy, dst)) in self.0.iter().zip(hops).enumerate() {
+            proxy.tunnel(no, stream, dst)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, Shutdown, TcpListener};
+    use std::thread;
+
+    use super::*;
+
+    /// Runs SOCKS5 proxy without authentication accepting a single
+    /// connection, which it relays to the requested IPv4 destination.
+    fn socks5_proxy() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut client, _) = listener.accept().unwrap();
+            let mut greeting = [0u8; 3];
+            client.read_exact(&mut greeting).unwrap();
+            client.write_all(&[0x05, 0x00]).unwrap();
+
+            let mut request = [0u8; 10];
+            client.read_exact(&mut request).unwrap();
+            assert_eq!(request[..4], [0x05, 0x01, 0x00, 0x01]);
+            let ip = Ipv4Addr::new(request[4], request[5], request[6], request[7]);
+            let port = u16::from_be_bytes([request[8], request[9]]);
+            let mut remote = TcpStream::connect((ip, port)).unwrap();
+            client
+                .write_all(&[0x05, 0x00, 0x00, 0x01, 127, 0, 0, 1, 0, 0])
+                .unwrap();
+
+            let mut client_read = client.try_clone().unwrap();
+            let mut remote_write = remote.try_clone().unwrap();
+            thread::spawn(move || {
+                let _ = io::copy(&mut client_read, &mut remote_write);
+                let _ = remote_write.shutdown(Shutdown::Write);
+            });
+            let _ = io::copy(&mut remote, &mut client);
+            let _ = client.shutdown(Shutdown::Write);
+        });
+        addr
+    }
+
+    #[test]
+    fn two_socks5_proxies() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = server.accept().unwrap();
+            let mut ping = [0u8; 4];
+            stream.read_exact(&mut ping).unwrap();
+            stream.write_all(b"pong").unwrap();
+            ping
+        });
+
+        let chain = ProxyChain::new([
+            Socks5::new(socks5_proxy()).unwrap().into(),
+            Socks5::new(socks5_proxy()).unwrap().into(),
+        ])
+        .unwrap();
+        let mut stream = chain.connect_blocking(server_addr).unwrap();
+        stream.write_all(b"ping").unwrap();
+        let mut pong = [0u8; 4];
+        stream.read_exact(&mut pong).unwrap();
+        assert_eq!(&pong, b"pong");
+        assert_eq!(&handle.join().unwrap(), b"ping");
+    }
+
+    #[test]
+    fn chain_length() {
+        let proxy = ChainedProxy::from(Socks5::new("127.0.0.1:9050").unwrap());
+        assert!(matches!(ProxyChain::new([]), Err(ProxyError::EmptyChain)));
+        assert!(ProxyChain::new(vec![proxy.clone(); MAX_CHAIN_LEN]).is_ok());
+        assert!(matches!(
+            ProxyChain::new(vec![proxy; MAX_CHAIN_LEN + 1]),
+            Err(ProxyError::ChainTooLong(len)) if len == MAX_CHAIN_LEN + 1
+        ));
+    }
+}
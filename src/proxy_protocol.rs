@@ -0,0 +1,765 @@
+//! Support of the [PROXY protocol] v1 (text) and v2 (binary) headers, which
+//! load balancers like HAProxy prepend to the inbound connections to pass the
+//! original client address.
+//!
+//! Listeners wrapped into [`ProxyListener`] produce [`ProxyStream`]s, which
+//! parse the header with the first reads from the connection and pass the data
+//! following it to the session running over the stream. The addresses from
+//! the header are available from [`NetConnection::proxy_addrs`] once the
+//! header is parsed.
+//!
+//! [PROXY protocol]: https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt
+
+use std::io::{self, Read, Write};
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, SocketAddrV4, SocketAddrV6, ToSocketAddrs,
+};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::connection::Proxy;
+use crate::resources::{SplitIo, SplitIoError};
+use crate::{NetConnection, NetListener};
+
+/// Signature starting the PROXY protocol v2 headers.
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+/// Length of the fixed part of the PROXY protocol v2 headers.
+const V2_HEADER_LEN: usize = 16;
+/// Prefix of the PROXY protocol v1 headers.
+const V1_PREFIX: &[u8] = b"PROXY ";
+/// Maximal length of the PROXY protocol v1 headers, including the trailing
+/// CRLF.
+const V1_MAX_LEN: usize = 107;
+/// Maximal length of the addresses and TLVs following the fixed part of the
+/// PROXY protocol v2 header accepted by [`ProxyStream`].
+pub const MAX_PROXY_V2_PAYLOAD: usize = 4096;
+/// Maximal length of the PROXY protocol header accepted by [`ProxyStream`].
+const MAX_HEADER_LEN: usize = V2_HEADER_LEN + MAX_PROXY_V2_PAYLOAD;
+
+/// Original addresses of a connection passed through a proxy.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Display)]
+#[display("{source} -> {destination}")]
+pub struct ProxyAddrs {
+    /// Address of the client which has connected to the proxy.
+    pub source: SocketAddr,
+    /// Address of the proxy the client has connected to.
+    pub destination: SocketAddr,
+}
+
+/// Error parsing PROXY protocol header, returned by [`ProxyStream`] reads
+/// inside [`io::Error`] of [`io::ErrorKind::InvalidData`] kind (or
+/// [`io::ErrorKind::UnexpectedEof`] for [`ProxyHeaderError::Truncated`]).
+#[derive(Copy, Clone, Eq, PartialEq, Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ProxyHeaderError {
+    /// connection does not start with a PROXY protocol header
+    Missing,
+
+    /// malformed PROXY protocol header: {0}
+    Malformed(&'static str),
+
+    /// unsupported PROXY protocol version {0}
+    UnsupportedVersion(u8),
+
+    /// PROXY protocol header with {0} bytes of addresses exceeds the size limit
+    TooLong(usize),
+
+    /// connection was closed before the PROXY protocol header was complete
+    Truncated,
+}
+
+impl ProxyHeaderError {
+    /// Detects whether the error returned by a [`ProxyStream`] (or a session
+    /// running over it) is caused by an invalid PROXY protocol header.
+    pub fn from_io_error(err: &io::Error) -> Option<ProxyHeaderError> {
+        err.get_ref()
+            .and_then(|err| err.downcast_ref::<ProxyHeaderError>())
+            .copied()
+    }
+}
+
+impl From<ProxyHeaderError> for io::Error {
+    fn from(err: ProxyHeaderError) -> Self {
+        let kind = match err {
+            ProxyHeaderError::Truncated => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+/// Parses PROXY protocol header at the start of the buffer.
+///
+/// # Returns
+///
+/// `None` if the buffer contains only a part of the header; otherwise the
+/// addresses from the header (which are absent for the connections
+/// originating from the proxy itself or when the proxy doesn't know them) and
+/// the length of the header.
+pub fn parse_header(buf: &[u8]) -> Result<Option<(Option<ProxyAddrs>, usize)>, ProxyHeaderError> {
+    if buf.is_empty() {
+        return Ok(None);
+    }
+    if V2_SIGNATURE.starts_with(&buf[..buf.len().min(V2_SIGNATURE.len())]) {
+        parse_v2(buf)
+    } else if V1_PREFIX.starts_with(&buf[..buf.len().min(V1_PREFIX.len())]) {
+        parse_v1(buf)
+    } else {
+        Err(ProxyHeaderError::Missing)
+    }
+}
+
+fn parse_v1(buf: &[u8]) -> Result<Option<(Option<ProxyAddrs>, usize)>, ProxyHeaderError> {
+    let head = &buf[..buf.len().min(V1_MAX_LEN)];
+    let Some(end) = head.windows(2).position(|crlf| crlf == b"\r\n") else {
+        return match buf.len() >= V1_MAX_LEN {
+            true => Err(ProxyHeaderError::Malformed("v1 header is not terminated")),
+            false => Ok(None),
+        };
+    };
+    let line = std::str::from_utf8(&buf[V1_PREFIX.len()..end])
+        .map_err(|_| ProxyHeaderError::Malformed("v1 header is not a text"))?;
+    let fields = line.split(' ').collect::<Vec<_>>();
+    let addrs = match fields[..] {
+        ["UNKNOWN", ..] => None,
+        ["TCP4", src, dst, src_port, dst_port] => Some(ProxyAddrs {
+            source: v1_addr::<Ipv4Addr>(src, src_port)?,
+            destination: v1_addr::<Ipv4Addr>(dst, dst_port)?,
+        }),
+        ["TCP6", src, dst, src_port, dst_port] => Some(ProxyAddrs {
+            source: v1_addr::<Ipv6Addr>(src, src_port)?,
+            destination: v1_addr::<Ipv6Addr>(dst, dst_port)?,
+        }),
+        _ => return Err(ProxyHeaderError::Malformed("invalid v1 header fields")),
+    };
+    Ok(Some((addrs, end + 2)))
+}
+
+fn v1_addr<A: FromStr + Into<IpAddr>>(
+    ip: &str,
+    port: &str,
+) -> Result<SocketAddr, ProxyHeaderError> {
+    let ip = A::from_str(ip).map_err(|_| ProxyHeaderError::Malformed("invalid v1 address"))?;
+    let port = match port.bytes().all(|c| c.is_ascii_digit()) {
+        true => u16::from_str(port).ok(),
+        false => None,
+    }
+    .ok_or(ProxyHeaderError::Malformed("invalid v1 port"))?;
+    Ok(SocketAddr::new(ip.into(), port))
+}
+
+fn parse_v2(buf: &[u8]) -> Result<Option<(Option<ProxyAddrs>, usize)>, ProxyHeaderError> {
+    if buf.len() < V2_HEADER_LEN {
+        return Ok(None);
+    }
+    let version = buf[12] >> 4;
+    if version != 2 {
+        return Err(ProxyHeaderError::UnsupportedVersion(version));
+    }
+    let len = u16::from_be_bytes([buf[14], buf[15]]) as usize;
+    if len > MAX_PROXY_V2_PAYLOAD {
+        return Err(ProxyHeaderError::TooLong(len));
+    }
+    let Some(payload) = buf.get(V2_HEADER_LEN..V2_HEADER_LEN + len) else {
+        return Ok(None);
+    };
+    let is_local = match buf[12] & 0x0F {
+        0x0 => true,
+        0x1 => false,
+        _ => return Err(ProxyHeaderError::Malformed("unknown v2 command")),
+    };
+    if buf[13] & 0x0F > 0x2 {
+        return Err(ProxyHeaderError::Malformed("unknown v2 transport protocol"));
+    }
+    let addrs = match buf[13] >> 4 {
+        // Connections originating from the proxy itself (for instance, health
+        // checks) do not carry the addresses
+        _ if is_local => None,
+        // Unspecified and unix socket addresses
+        0x0 | 0x3 => None,
+        0x1 => {
+            let addrs = payload
+                .get(..12)
+                .ok_or(ProxyHeaderError::Malformed("truncated v2 IPv4 addresses"))?;
+            let ip =
+                |pos: usize| Ipv4Addr::from(<[u8; 4]>::try_from(&addrs[pos..pos + 4]).unwrap());
+            let port = |pos: usize| u16::from_be_bytes([addrs[pos], addrs[pos + 1]]);
+            Some(ProxyAddrs {
+                source: SocketAddrV4::new(ip(0), port(8)).into(),
+                destination: SocketAddrV4::new(ip(4), port(10)).into(),
+            })
+        }
+        0x2 => {
+            let addrs = payload
+                .get(..36)
+                .ok_or(ProxyHeaderError::Malformed("truncated v2 IPv6 addresses"))?;
+            let ip =
+                |pos: usize| Ipv6Addr::from(<[u8; 16]>::try_from(&addrs[pos..pos + 16]).unwrap());
+            let port = |pos: usize| u16::from_be_bytes([addrs[pos], addrs[pos + 1]]);
+            Some(ProxyAddrs {
+                source: SocketAddrV6::new(ip(0), port(32), 0, 0).into(),
+                destination: SocketAddrV6::new(ip(16), port(34), 0, 0).into(),
+            })
+        }
+        _ => return Err(ProxyHeaderError::Malformed("unknown v2 address family")),
+    };
+    Ok(Some((addrs, V2_HEADER_LEN + len)))
+}
+
+/// State of the PROXY protocol header of a [`ProxyStream`].
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+enum HeaderState {
+    Pending,
+    Parsed(Option<ProxyAddrs>),
+    Failed(ProxyHeaderError),
+}
+
+/// Connection expecting a PROXY protocol header, which is parsed with the
+/// first reads from the connection, before any data are returned to the
+/// reader. The header may arrive across multiple reads: until it is complete,
+/// the reads from a non-blocking connection return
+/// [`io::ErrorKind::WouldBlock`]. Missing or malformed header fails the reads
+/// with [`ProxyHeaderError`].
+///
+/// The header is located by peeking into the connection, so the data
+/// following it are never consumed together with the header and stay in the
+/// socket buffer, where they are seen by the poller.
+///
+/// Outgoing connections established with [`NetConnection::connect_blocking`]
+/// and [`NetConnection::connect_nonblocking`] do not expect the header.
+#[derive(Debug)]
+pub struct ProxyStream<C = std::net::TcpStream> {
+    connection: C,
+    state: HeaderState,
+    /// Part of the header consumed from the connection so far.
+    header: Vec<u8>,
+}
+
+impl<C> ProxyStream<C> {
+    /// Wraps connection, which must start with a PROXY protocol header.
+    pub fn expect_header(connection: C) -> Self {
+        ProxyStream {
+            connection,
+            state: HeaderState::Pending,
+            header: vec![],
+        }
+    }
+
+    /// Wraps connection which doesn't carry PROXY protocol header.
+    pub fn without_header(connection: C) -> Self {
+        ProxyStream {
+            connection,
+            state: HeaderState::Parsed(None),
+            header: vec![],
+        }
+    }
+
+    /// Detects whether the PROXY protocol header is already parsed.
+    pub fn is_header_parsed(&self) -> bool {
+        matches!(self.state, HeaderState::Parsed(_))
+    }
+
+    /// Returns the original addresses of the connection from the PROXY
+    /// protocol header, if the header was parsed and has provided them.
+    pub fn proxy_addrs(&self) -> Option<ProxyAddrs> {
+        match self.state {
+            HeaderState::Parsed(addrs) => addrs,
+            HeaderState::Pending | HeaderState::Failed(_) => None,
+        }
+    }
+
+    pub fn as_inner(&self) -> &C {
+        &self.connection
+    }
+
+    pub fn into_inner(self) -> C {
+        self.connection
+    }
+}
+
+impl<C: NetConnection> ProxyStream<C> {
+    /// Reads from the connection until the header is complete.
+    fn read_header(&mut self) -> io::Result<()> {
+        if self.is_header_parsed() {
+            return Ok(());
+        }
+        let mut peeked = vec![0u8; MAX_HEADER_LEN];
+        loop {
+            match self.state {
+                HeaderState::Parsed(_) => return Ok(()),
+                HeaderState::Failed(err) => return Err(err.into()),
+                HeaderState::Pending => {}
+            }
+            let len = self
+                .connection
+                .peek(&mut peeked[..MAX_HEADER_LEN - self.header.len()])?;
+            if len == 0 {
+                self.state = HeaderState::Failed(ProxyHeaderError::Truncated);
+                continue;
+            }
+            let consumed = self.header.len();
+            self.header.extend_from_slice(&peeked[..len]);
+            match parse_header(&self.header) {
+                Ok(Some((addrs, header_len))) => {
+                    self.consume(header_len - consumed)?;
+                    self.header = vec![];
+                    self.state = HeaderState::Parsed(addrs);
+                }
+                // All the peeked data belong to the header, so they are
+                // consumed to wait for the rest of it
+                Ok(None) => self.consume(len)?,
+                Err(err) => self.state = HeaderState::Failed(err),
+            }
+        }
+    }
+
+    /// Consumes data which were already peeked from the connection.
+    fn consume(&mut self, len: usize) -> io::Result<()> {
+        let mut buf = [0u8; MAX_HEADER_LEN];
+        self.connection.read_exact(&mut buf[..len])
+    }
+}
+
+impl<C: NetConnection> Read for ProxyStream<C> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.read_header()?;
+        self.connection.read(buf)
+    }
+}
+
+impl<C: Write> Write for ProxyStream<C> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.connection.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.connection.flush()
+    }
+}
+
+impl<C: AsRawFd> AsRawFd for ProxyStream<C> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.connection.as_raw_fd()
+    }
+}
+
+/// Reading half of a split [`ProxyStream`], keeping the addresses from its
+/// PROXY protocol header.
+#[derive(Debug)]
+pub struct ProxyReader<R> {
+    reader: R,
+    addrs: Option<ProxyAddrs>,
+}
+
+impl<R> ProxyReader<R> {
+    pub fn proxy_addrs(&self) -> Option<ProxyAddrs> {
+        self.addrs
+    }
+}
+
+impl<R: Read> Read for ProxyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+}
+
+/// Stream can be split only once its header is parsed, which always happens
+/// before the session over it is established.
+impl<C: NetConnection> SplitIo for ProxyStream<C> {
+    type Read = ProxyReader<C::Read>;
+    type Write = C::Write;
+
+    fn split_io(self) -> Result<(Self::Read, Self::Write), SplitIoError<Self>> {
+        let HeaderState::Parsed(addrs) = self.state else {
+            return Err(SplitIoError {
+                original: self,
+                error: io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "PROXY protocol header is not parsed yet",
+                ),
+            });
+        };
+        match self.connection.split_io() {
+            Ok((reader, writer)) => Ok((ProxyReader { reader, addrs }, writer)),
+            Err(SplitIoError { original, error }) => Err(SplitIoError {
+                original: ProxyStream {
+                    connection: original,
+                    state: self.state,
+                    header: self.header,
+                },
+                error,
+            }),
+        }
+    }
+
+    fn from_split_io(read: Self::Read, write: Self::Write) -> Self {
+        ProxyStream {
+            connection: C::from_split_io(read.reader, write),
+            state: HeaderState::Parsed(read.addrs),
+            header: vec![],
+        }
+    }
+}
+
+impl<C: NetConnection> NetConnection for ProxyStream<C> {
+    type Addr = C::Addr;
+
+    fn connect_blocking<P: Proxy>(addr: Self::Addr, proxy: &P) -> Result<Self, P::Error> {
+        C::connect_blocking(addr, proxy).map(Self::without_header)
+    }
+
+    #[cfg(feature = "socket2")]
+    fn connect_nonblocking<P: Proxy>(addr: Self::Addr, proxy: &P) -> Result<Self, P::Error> {
+        C::connect_nonblocking(addr, proxy).map(Self::without_header)
+    }
+
+    fn shutdown(&mut self, how: Shutdown) -> io::Result<()> {
+        self.connection.shutdown(how)
+    }
+
+    /// Returns address of the proxy; the address of the client is provided by
+    /// [`NetConnection::proxy_addrs`].
+    fn remote_addr(&self) -> Self::Addr {
+        self.connection.remote_addr()
+    }
+
+    fn local_addr(&self) -> Self::Addr {
+        self.connection.local_addr()
+    }
+
+    fn set_read_timeout(&mut self, dur: Option<Duration>) -> io::Result<()> {
+        self.connection.set_read_timeout(dur)
+    }
+
+    fn set_write_timeout(&mut self, dur: Option<Duration>) -> io::Result<()> {
+        self.connection.set_write_timeout(dur)
+    }
+
+    fn read_timeout(&self) -> io::Result<Option<Duration>> {
+        self.connection.read_timeout()
+    }
+
+    fn write_timeout(&self) -> io::Result<Option<Duration>> {
+        self.connection.write_timeout()
+    }
+
+    /// Peeks the data following the header. Until the header is parsed there
+    /// is nothing to peek, so [`io::ErrorKind::WouldBlock`] is returned.
+    fn peek(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.state {
+            HeaderState::Pending => Err(io::ErrorKind::WouldBlock.into()),
+            HeaderState::Failed(err) => Err(err.into()),
+            HeaderState::Parsed(_) => self.connection.peek(buf),
+        }
+    }
+
+    fn set_nodelay(&mut self, nodelay: bool) -> io::Result<()> {
+        self.connection.set_nodelay(nodelay)
+    }
+
+    fn nodelay(&self) -> io::Result<bool> {
+        self.connection.nodelay()
+    }
+
+    fn set_ttl(&mut self, ttl: u32) -> io::Result<()> {
+        self.connection.set_ttl(ttl)
+    }
+
+    fn ttl(&self) -> io::Result<u32> {
+        self.connection.ttl()
+    }
+
+    fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()> {
+        self.connection.set_nonblocking(nonblocking)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        Ok(ProxyStream {
+            connection: self.connection.try_clone()?,
+            state: self.state,
+            header: self.header.clone(),
+        })
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.connection.take_error()
+    }
+
+    fn proxy_addrs(&self) -> Option<ProxyAddrs> {
+        ProxyStream::proxy_addrs(self)
+    }
+}
+
+/// Listener for the connections made through a proxy speaking PROXY protocol,
+/// which start with the PROXY protocol v1 or v2 header. The header is parsed
+/// by the [`ProxyStream`]s returned by the listener.
+#[derive(Debug)]
+pub struct ProxyListener<L: NetListener> {
+    listener: L,
+}
+
+impl<L: NetListener> ProxyListener<L> {
+    pub fn new(listener: L) -> Self {
+        ProxyListener { listener }
+    }
+
+    pub fn as_inner(&self) -> &L {
+        &self.listener
+    }
+
+    pub fn into_inner(self) -> L {
+        self.listener
+    }
+}
+
+impl<L: NetListener> AsRawFd for ProxyListener<L> {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
+impl<L: NetListener> NetListener for ProxyListener<L>
+where
+    L::Stream: NetConnection,
+{
+    type Stream = ProxyStream<L::Stream>;
+
+    fn bind(addr: &impl ToSocketAddrs) -> io::Result<Self> {
+        L::bind(addr).map(Self::new)
+    }
+
+    fn with_reuseport(addr: SocketAddr) -> io::Result<Self> {
+        L::with_reuseport(addr).map(Self::new)
+    }
+
+    fn accept(&self) -> io::Result<Self::Stream> {
+        self.listener.accept().map(ProxyStream::expect_header)
+    }
+
+    fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr()
+    }
+
+    fn ttl(&self) -> io::Result<u32> {
+        self.listener.ttl()
+    }
+
+    fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+        self.listener.set_ttl(ttl)
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.listener.set_nonblocking(nonblocking)
+    }
+
+    fn try_clone(&self) -> io::Result<Self> {
+        self.listener.try_clone().map(Self::new)
+    }
+
+    fn take_error(&self) -> io::Result<Option<io::Error>> {
+        self.listener.take_error()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{TcpListener, TcpStream};
+
+    use super::*;
+
+    fn addrs(source: &str, destination: &str) -> Option<ProxyAddrs> {
+        Some(ProxyAddrs {
+            source: source.parse().unwrap(),
+            destination: destination.parse().unwrap(),
+        })
+    }
+
+    fn v2_header(command: u8, family: u8, payload: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.push(0x20 | command);
+        header.push(family);
+        header.extend((payload.len() as u16).to_be_bytes());
+        header.extend(payload);
+        header
+    }
+
+    #[test]
+    fn v1() {
+        let header = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\n";
+        assert_eq!(
+            parse_header(header),
+            Ok(Some((
+                addrs("192.0.2.1:56324", "198.51.100.2:443"),
+                header.len()
+            )))
+        );
+        let header = b"PROXY TCP6 2001:db8::1 2001:db8::2 56324 443\r\nGET /";
+        assert_eq!(
+            parse_header(header),
+            Ok(Some((
+                addrs("[2001:db8::1]:56324", "[2001:db8::2]:443"),
+                header.len() - 5
+            )))
+        );
+        assert_eq!(parse_header(b"PROXY UNKNOWN\r\n"), Ok(Some((None, 15))));
+        assert_eq!(
+            parse_header(b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n"),
+            Ok(Some((None, 35)))
+        );
+    }
+
+    #[test]
+    fn v2() {
+        let mut payload = vec![192, 0, 2, 1, 198, 51, 100, 2];
+        payload.extend(56324u16.to_be_bytes());
+        payload.extend(443u16.to_be_bytes());
+        // TLVs are skipped
+        payload.extend([0x04, 0x00, 0x01, 0xFF]);
+        let header = v2_header(0x1, 0x11, &payload);
+        assert_eq!(
+            parse_header(&header),
+            Ok(Some((
+                addrs("192.0.2.1:56324", "198.51.100.2:443"),
+                header.len()
+            )))
+        );
+
+        let mut payload = "2001:db8::1".parse::<Ipv6Addr>().unwrap().octets().to_vec();
+        payload.extend("2001:db8::2".parse::<Ipv6Addr>().unwrap().octets());
+        payload.extend(56324u16.to_be_bytes());
+        payload.extend(443u16.to_be_bytes());
+        let header = v2_header(0x1, 0x21, &payload);
+        assert_eq!(
+            parse_header(&header),
+            Ok(Some((
+                addrs("[2001:db8::1]:56324", "[2001:db8::2]:443"),
+                header.len()
+            )))
+        );
+
+        // Health checks from the proxy itself
+        let header = v2_header(0x0, 0x00, &[]);
+        assert_eq!(parse_header(&header), Ok(Some((None, 16))));
+    }
+
+    #[test]
+    fn partial() {
+        let header = b"PROXY TCP4 192.0.2.1 198.51.100.2 56324 443\r\n";
+        for len in 0..header.len() {
+            assert_eq!(parse_header(&header[..len]), Ok(None));
+        }
+        let header = v2_header(0x1, 0x11, &[0; 12]);
+        for len in 0..header.len() {
+            assert_eq!(parse_header(&header[..len]), Ok(None));
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        assert_eq!(
+            parse_header(b"GET / HTTP/1.1\r\n"),
+            Err(ProxyHeaderError::Missing)
+        );
+        assert_eq!(parse_header(b"\x16\x03"), Err(ProxyHeaderError::Missing));
+        assert!(matches!(
+            parse_header(b"PROXY TCP4 192.0.2.1 198.51.100.2 56324\r\n"),
+            Err(ProxyHeaderError::Malformed(_))
+        ));
+        assert!(matches!(
+            parse_header(b"PROXY TCP4 2001:db8::1 198.51.100.2 56324 443\r\n"),
+            Err(ProxyHeaderError::Malformed(_))
+        ));
+        assert!(matches!(
+            parse_header(b"PROXY TCP4 192.0.2.1 198.51.100.2 +5632 443\r\n"),
+            Err(ProxyHeaderError::Malformed(_))
+        ));
+        assert!(matches!(
+            parse_header(&[b"PROXY UNKNOWN ".as_slice(), &[b'x'; 100]].concat()),
+            Err(ProxyHeaderError::Malformed(_))
+        ));
+
+        let mut header = v2_header(0x1, 0x11, &[0; 12]);
+        header[12] = 0x11;
+        assert_eq!(
+            parse_header(&header),
+            Err(ProxyHeaderError::UnsupportedVersion(1))
+        );
+        let header = v2_header(0x1, 0x21, &[0; 12]);
+        assert!(matches!(
+            parse_header(&header),
+            Err(ProxyHeaderError::Malformed(_))
+        ));
+        let header = v2_header(0x1, 0x11, &[0; MAX_PROXY_V2_PAYLOAD + 1]);
+        assert_eq!(
+            parse_header(&header[..V2_HEADER_LEN]),
+            Err(ProxyHeaderError::TooLong(MAX_PROXY_V2_PAYLOAD + 1))
+        );
+    }
+
+    /// Returns connected pair of the client socket and the accepted
+    /// non-blocking stream expecting PROXY protocol header.
+    fn stream_pair() -> (TcpStream, ProxyStream) {
+        let listener = ProxyListener::<TcpListener>::bind(&"127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr()).unwrap();
+        let mut stream = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        (client, stream)
+    }
+
+    /// Reads from the non-blocking stream until data arrive.
+    fn read(stream: &mut ProxyStream, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match stream.read(buf) {
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    std::thread::sleep(Duration::from_millis(10))
+                }
+                res => return res,
+            }
+        }
+    }
+
+    #[test]
+    fn stream() {
+        let (mut client, mut stream) = stream_pair();
+        let mut buf = [0u8; 16];
+        client.write_all(b"PROXY TCP4 192.0.2.1 198.51").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        let err = stream.read(&mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(!stream.is_header_parsed());
+
+        client.write_all(b".100.2 56324 443\r\nhello").unwrap();
+        assert_eq!(read(&mut stream, &mut buf).unwrap(), 5);
+        assert_eq!(&buf[..5], b"hello");
+        assert_eq!(
+            stream.proxy_addrs(),
+            addrs("192.0.2.1:56324", "198.51.100.2:443")
+        );
+
+        let (mut client, mut stream) = stream_pair();
+        client.write_all(b"PROXY TCP4 192.0.2.1").unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let err = read(&mut stream, &mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(
+            ProxyHeaderError::from_io_error(&err),
+            Some(ProxyHeaderError::Truncated)
+        );
+        // Failure is sticky
+        let err = stream.read(&mut buf).unwrap_err();
+        assert_eq!(
+            ProxyHeaderError::from_io_error(&err),
+            Some(ProxyHeaderError::Truncated)
+        );
+
+        let (mut client, mut stream) = stream_pair();
+        client.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let err = read(&mut stream, &mut buf).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(
+            ProxyHeaderError::from_io_error(&err),
+            Some(ProxyHeaderError::Missing)
+        );
+    }
+}
@@ -1,15 +1,26 @@
+use std::collections::hash_map::RandomState;
 use std::collections::VecDeque;
 use std::fmt::{Debug, Display, Formatter};
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
 use std::io::{Read, Write};
-use std::net::{TcpListener, ToSocketAddrs};
-use std::os::unix::io::{AsRawFd, RawFd};
-use std::time::Duration;
+use std::net::{IpAddr, TcpListener, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{io, net};
 
 use reactor::poller::IoType;
 use reactor::{Io, Resource, WriteAtomic, WriteError};
 
-use crate::{NetConnection, NetListener, NetSession};
+use crate::auth::{AuthPolicy, AuthVerdict, RejectReason};
+use crate::listener::{
+    peer_ip, set_reset_on_close, CapReached, IpConnectionCounter, IpConnectionGuard,
+    PeerLimitReached, RateLimited,
+};
+use crate::noise::RekeyThreshold;
+use crate::proxy_protocol::ProxyAddrs;
+use crate::{NetConnection, NetListener, NetSession, SessionStats};
 
 /// Socket read buffer size.
 const READ_BUFFER_SIZE: usize = u16::MAX as usize;
@@ -18,9 +29,55 @@ const READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(6);
 /// Maximum time to wait when writing to a socket.
 const WRITE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(3);
 
+/// Default time within which a [`NetResource`] session must complete its
+/// handshake, after which [`SessionEvent::HandshakeTimeout`] is reported.
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+/// Default size of the [`NetResource`] write queue above which
+/// [`SessionEvent::Paused`] is reported.
+pub const DEFAULT_HIGH_WATERMARK: usize = 1024 * 1024;
+/// Default size of the [`NetResource`] write queue below which
+/// [`SessionEvent::Resumed`] is reported.
+pub const DEFAULT_LOW_WATERMARK: usize = 256 * 1024;
+/// Default time for which [`NetAccept`] stops accepting connections once the
+/// process runs out of file descriptors.
+pub const DEFAULT_ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
 #[derive(Debug)]
 pub enum ListenerEvent<S: NetSession> {
-    Accepted(S),
+    /// Connection was accepted. If [`NetAccept`] limits the connections, the
+    /// guard keeps the connection counted until it is dropped, and must be
+    /// moved into the resource of the connection (see
+    /// [`NetResource::with_connection_guard`]).
+    Accepted(S, Option<IpConnectionGuard>),
+    /// Connection from the IP address was dropped by a
+    /// [`crate::RateLimitedListener`] since the address has exceeded the
+    /// rate limit.
+    RateLimited(IpAddr),
+    /// Connection was dropped by a [`crate::BoundedListener`] or by the
+    /// connection limits of [`NetAccept`] since the number of open
+    /// connections has reached the cap.
+    CapReached {
+        current: usize,
+        max: usize,
+    },
+    /// Connection was dropped by [`NetAccept`] since the number of open
+    /// connections from its IP address has reached the limit (see
+    /// [`NetAccept::with_connection_limits`]).
+    PeerLimitReached {
+        ip: IpAddr,
+        current: usize,
+        max: usize,
+    },
+    /// Process or system has run out of file descriptors (`EMFILE` or
+    /// `ENFILE`), so the listener stops accepting connections for the backoff
+    /// period instead of spinning on the pending connection it can't accept.
+    Paused {
+        err: io::Error,
+        backoff: Duration,
+    },
+    /// Listener has resumed accepting connections after
+    /// [`ListenerEvent::Paused`].
+    Resumed,
     Failure(io::Error),
 }
 
@@ -28,6 +85,18 @@ pub enum ListenerEvent<S: NetSession> {
 pub struct NetAccept<S: NetSession, L: NetListener<Stream = S::Connection> = TcpListener> {
     session_context: S::Context,
     listener: L,
+    auth_policy: Option<Arc<dyn AuthPolicy<S::Id>>>,
+    accept_backoff: Duration,
+    paused_until: Option<Duration>,
+    limits: Option<ConnectionLimits>,
+}
+
+/// Limits of the simultaneous connections accepted by [`NetAccept`].
+#[derive(Clone, Debug)]
+struct ConnectionLimits {
+    counter: IpConnectionCounter,
+    max_per_ip: usize,
+    max_total: Option<usize>,
 }
 
 impl<L: NetListener<Stream = S::Connection>, S: NetSession> AsRawFd for NetAccept<S, L> {
@@ -58,27 +127,121 @@ impl<L: NetListener<Stream = S::Connection>, S: NetSession> WriteAtomic for NetA
 
 impl<L: NetListener<Stream = S::Connection>, S: NetSession> NetAccept<S, L> {
     pub fn bind(addr: &impl ToSocketAddrs, session_context: S::Context) -> io::Result<Self> {
-        let listener = L::bind(addr)?;
+        Self::with_listener(L::bind(addr)?, session_context)
+    }
+
+    /// Constructs resource from already bound listener, switching it to
+    /// non-blocking mode.
+    pub fn with_listener(listener: L, session_context: S::Context) -> io::Result<Self> {
         listener.set_nonblocking(true)?;
         Ok(Self {
             session_context,
             listener,
+            auth_policy: None,
+            accept_backoff: DEFAULT_ACCEPT_BACKOFF,
+            paused_until: None,
+            limits: None,
         })
     }
 
+    /// Sets time for which accepting connections is paused once the process
+    /// runs out of file descriptors (see [`ListenerEvent::Paused`]).
+    pub fn with_accept_backoff(mut self, backoff: Duration) -> Self {
+        self.accept_backoff = backoff;
+        self
+    }
+
+    /// Detects whether accepting connections is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused_until.is_some()
+    }
+
+    /// Sets policy deciding on the peers of the accepted sessions, see
+    /// [`NetSession::set_auth_policy`].
+    pub fn with_auth_policy(mut self, policy: impl AuthPolicy<S::Id> + 'static) -> Self {
+        self.auth_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Limits the number of simultaneous connections from a single IP address
+    /// to `max_per_ip` and, optionally, the total number of simultaneous
+    /// connections to `max_total`.
+    ///
+    /// Connections exceeding the limits are reset right after being accepted
+    /// and reported as [`ListenerEvent::PeerLimitReached`] or
+    /// [`ListenerEvent::CapReached`]. Accepted connections are counted by the
+    /// [`IpConnectionCounter`] returned by [`NetAccept::connection_counter`]
+    /// until the [`IpConnectionGuard`] reported with
+    /// [`ListenerEvent::Accepted`] is dropped.
+    pub fn with_connection_limits(mut self, max_per_ip: usize, max_total: Option<usize>) -> Self {
+        self.limits = Some(ConnectionLimits {
+            counter: IpConnectionCounter::new(),
+            max_per_ip,
+            max_total,
+        });
+        self
+    }
+
+    /// Returns counter of the connections accepted under the connection
+    /// limits, if they are set.
+    pub fn connection_counter(&self) -> Option<IpConnectionCounter> {
+        self.limits.as_ref().map(|limits| limits.counter.clone())
+    }
+
     pub fn local_addr(&self) -> net::SocketAddr {
         self.listener.local_addr()
     }
 
-    fn handle_accept(&mut self) -> io::Result<S> {
-        let mut stream = self.listener.accept()?;
+    fn handle_accept(&mut self) -> io::Result<(S, Option<IpConnectionGuard>)> {
+        let stream = self.listener.accept()?;
+        let Some(limits) = &self.limits else {
+            return Ok((self.accept_session(stream)?, None));
+        };
+        let ip = peer_ip(stream.as_raw_fd())?;
+        let guard = match limits
+            .counter
+            .acquire(ip, limits.max_per_ip, limits.max_total)
+        {
+            Ok(guard) => guard,
+            Err(err) => {
+                // The stream is closed with RST once dropped
+                set_reset_on_close(stream.as_raw_fd())?;
+                return Err(err);
+            }
+        };
+        // The connection is released by the guard if the session fails
+        Ok((self.accept_session(stream)?, Some(guard)))
+    }
+
+    fn accept_session(&self, mut stream: S::Connection) -> io::Result<S> {
         stream.set_read_timeout(Some(READ_TIMEOUT))?;
         stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
         stream.set_nonblocking(true)?;
-        S::accept(stream, &self.session_context)
+        let mut session = S::accept(stream, &self.session_context)?;
+        if let Some(policy) = &self.auth_policy {
+            session.set_auth_policy(policy.clone());
+        }
+        Ok(session)
+    }
+
+    fn pause(&mut self, err: io::Error) -> ListenerEvent<S> {
+        let backoff = self.accept_backoff;
+
+        #[cfg(feature = "log")]
+        log::warn!(target: "listener", "Out of file descriptors on {}, pausing accepting connections for {backoff:?}: {err}", self.local_addr());
+        #[cfg(feature = "tracing")]
+        tracing::warn!(target: "listener", addr = %self.local_addr(), ?backoff, %err, "Out of file descriptors, pausing accepting connections");
+
+        self.paused_until = Some(unix_time() + backoff);
+        ListenerEvent::Paused { err, backoff }
     }
 }
 
+/// Detects errors caused by running out of file descriptors.
+fn is_fd_exhausted(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::EMFILE) | Some(libc::ENFILE))
+}
+
 impl<L: NetListener<Stream = S::Connection>, S: NetSession> Resource for NetAccept<S, L> {
     type Id = net::SocketAddr;
     type Event = ListenerEvent<S>;
@@ -87,20 +250,59 @@ impl<L: NetListener<Stream = S::Connection>, S: NetSession> Resource for NetAcce
         self.listener.local_addr()
     }
 
+    /// Stops watching for the incoming connections while accepting is
+    /// paused.
     fn interests(&self) -> IoType {
-        IoType::read_only()
+        match self.paused_until {
+            Some(_) => IoType::none(),
+            None => IoType::read_only(),
+        }
     }
 
     fn handle_io(&mut self, io: Io) -> Option<Self::Event> {
         match io {
+            Io::Read if self.is_paused() => None,
             Io::Read => Some(match self.handle_accept() {
-                Err(err) => ListenerEvent::Failure(err),
-                Ok(session) => ListenerEvent::Accepted(session),
+                Err(err) => {
+                    if is_fd_exhausted(&err) {
+                        self.pause(err)
+                    } else if let Some(ip) = RateLimited::from_io_error(&err) {
+                        ListenerEvent::RateLimited(ip)
+                    } else if let Some(CapReached { current, max }) =
+                        CapReached::from_io_error(&err)
+                    {
+                        ListenerEvent::CapReached { current, max }
+                    } else if let Some(PeerLimitReached { ip, current, max }) =
+                        PeerLimitReached::from_io_error(&err)
+                    {
+                        ListenerEvent::PeerLimitReached { ip, current, max }
+                    } else {
+                        ListenerEvent::Failure(err)
+                    }
+                }
+                Ok((session, guard)) => ListenerEvent::Accepted(session, guard),
             }),
             Io::Write => None,
         }
     }
 
+    /// End of the pause in accepting connections.
+    fn deadline(&self) -> Option<Duration> {
+        self.paused_until
+    }
+
+    fn handle_timeout(&mut self, time: Duration) -> Option<Self::Event> {
+        self.paused_until.filter(|until| *until <= time)?;
+        self.paused_until = None;
+
+        #[cfg(feature = "log")]
+        log::info!(target: "listener", "Resuming accepting connections on {}", self.local_addr());
+        #[cfg(feature = "tracing")]
+        tracing::info!(target: "listener", addr = %self.local_addr(), "Resuming accepting connections");
+
+        Some(ListenerEvent::Resumed)
+    }
+
     fn disconnect(self) -> io::Result<()> {
         // We disconnect by dropping the self
         Ok(())
@@ -108,16 +310,187 @@ impl<L: NetListener<Stream = S::Connection>, S: NetSession> Resource for NetAcce
 }
 
 pub enum SessionEvent<S: NetSession> {
-    Established(S::Id),
+    /// Session is established and its peer is authorized. Carries the
+    /// original addresses of the peer connected through a proxy speaking
+    /// PROXY protocol (see [`crate::ProxyListener`]).
+    Established(S::Id, Option<ProxyAddrs>),
     Data(Vec<u8>),
+    /// Write queue of the session has grown above the high watermark. The
+    /// application should stop feeding the session with data, for instance
+    /// by pausing reads from the resources whose data it relays to the session
+    /// with [`reactor::Controller::pause_read`].
+    Paused,
+    /// Write queue of the session has drained below the low watermark, so
+    /// the data flow stopped after [`SessionEvent::Paused`] may be resumed.
+    Resumed,
+    /// Authorization policy has rejected the peer of the established
+    /// session. The session is terminated and should be unregistered.
+    Rejected(S::Id, RejectReason),
+    /// Authorization policy has deferred its verdict on the peer of the
+    /// established session. The session is parked without exchanging any
+    /// data until the application resumes it with
+    /// [`reactor::Controller::resume_read`], after which the policy is
+    /// asked again.
+    Deferred(S::Id),
+    /// Session has not completed its handshake within the handshake timeout,
+    /// which has elapsed since the resource was constructed. The session is
+    /// terminated and should be unregistered.
+    HandshakeTimeout(Duration),
+    /// Outbound session with a [`ReconnectPolicy`] was dropped and will be
+    /// re-dialed once the delay elapses. The resource stays registered and
+    /// keeps its id; [`SessionEvent::Established`] is reported once the
+    /// re-dialed session completes its handshake.
+    Reconnecting {
+        attempt: u32,
+        delay: Duration,
+        reason: io::Error,
+    },
+    /// All re-dial attempts allowed by the [`ReconnectPolicy`] have failed.
+    /// The session is terminated and should be unregistered.
+    ReconnectExhausted {
+        attempts: u32,
+        reason: io::Error,
+    },
+    /// Reactor is draining (see [`reactor::Controller::drain`]). The
+    /// application should finish its work with the session, for instance by
+    /// sending a "going away" message to the peer, and unregister it; sessions
+    /// left by the drain deadline are dropped together with the reactor.
+    Draining,
     Terminated(io::Error),
 }
 
+/// Policy of re-dialing outbound [`NetResource`] sessions dropped due to a
+/// network failure, attached with [`NetResource::with_reconnect`].
+///
+/// The delay before each attempt grows exponentially from the base delay up
+/// to the maximal one, and is randomly shortened by up to the `jitter`
+/// fraction of it, so the sessions dropped at once are not re-dialed at once.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct ReconnectPolicy {
+    /// Number of re-dial attempts after which
+    /// [`SessionEvent::ReconnectExhausted`] is reported. The counter is reset
+    /// each time the session gets established.
+    pub max_retries: u32,
+    /// Delay before the first attempt.
+    pub base_delay: Duration,
+    /// Maximal delay between the attempts.
+    pub max_delay: Duration,
+    /// Fraction of the delay, from 0 to 1, by which it is randomly shortened.
+    pub jitter: f64,
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        ReconnectPolicy {
+            max_retries: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            jitter: 0.1,
+        }
+    }
+}
+
+impl ReconnectPolicy {
+    /// Returns delay before the re-dial attempt, counting from 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        let delay = self.base_delay.saturating_mul(factor).min(self.max_delay);
+        // Hashers of the standard library are randomly seeded
+        let random = RandomState::new().build_hasher().finish() >> 11;
+        let jitter = self.jitter.clamp(0.0, 1.0) * random as f64 / (1u64 << 53) as f64;
+        delay.mul_f64(1.0 - jitter)
+    }
+
+    /// Detects whether a session terminated with the error is re-dialed.
+    /// These are the errors caused by the network or the remote peer, and not
+    /// the local ones like invalid addresses or exhausted file descriptors.
+    pub fn is_retriable(err: &io::Error) -> bool {
+        use io::ErrorKind::*;
+
+        matches!(
+            err.kind(),
+            ConnectionRefused
+                | ConnectionReset
+                | ConnectionAborted
+                | NotConnected
+                | BrokenPipe
+                | TimedOut
+                | UnexpectedEof
+        ) || matches!(
+            err.raw_os_error(),
+            Some(libc::ENETUNREACH | libc::EHOSTUNREACH | libc::ENETDOWN | libc::EHOSTDOWN)
+        )
+    }
+}
+
+/// Re-dialing state of an outbound [`NetResource`].
+struct Redial<S> {
+    policy: ReconnectPolicy,
+    dial: Box<dyn FnMut() -> io::Result<S> + Send>,
+    /// Descriptor the resource is polled with, which is re-pointed to the
+    /// socket of each re-dialed session, so the resource keeps its id.
+    fd: OwnedFd,
+    /// Number of re-dial attempts since the session was last established.
+    attempt: u32,
+    /// Time of the next attempt, as a duration since the UNIX epoch.
+    retry_at: Option<Duration>,
+    /// Whether the descriptor was re-pointed to a re-dialed session, which
+    /// is not yet reported to the reactor.
+    fd_replaced: bool,
+}
+
+impl<S> Debug for Redial<S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Redial")
+            .field("policy", &self.policy)
+            .field("fd", &self.fd)
+            .field("attempt", &self.attempt)
+            .field("retry_at", &self.retry_at)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Re-points descriptor to the file referenced by another descriptor,
+/// keeping its number.
+fn redirect_fd(src: RawFd, fd: RawFd) -> io::Result<()> {
+    // SAFETY: both descriptors are open, and the target one is owned by the
+    // caller
+    if unsafe { libc::dup2(src, fd) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // Descriptors produced by `dup2` are not closed on exec
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) } < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Takes the pending error of a socket, which is the reason it has hung up.
+fn socket_error(fd: RawFd) -> Option<io::Error> {
+    let mut err: libc::c_int = 0;
+    let mut len = std::mem::size_of::<libc::c_int>() as libc::socklen_t;
+    // SAFETY: the buffer and its length match the option value type
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_ERROR,
+            &mut err as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    (res == 0 && err != 0).then(|| io::Error::from_raw_os_error(err))
+}
+
 #[derive(Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Hash, Debug)]
 pub enum TransportState {
     Init,
     Handshake,
+    /// Handshake is complete, but the verdict on the peer is deferred.
+    Deferred,
     Active,
+    /// Session was dropped and waits to be re-dialed.
+    Reconnecting,
     Terminated,
 }
 
@@ -133,6 +506,18 @@ pub struct NetResource<S: NetSession> {
     read_buffer: Vec<u8>,
     read_buffer_len: usize,
     write_buffer: VecDeque<u8>,
+    high_watermark: usize,
+    low_watermark: usize,
+    is_paused: bool,
+    auth_policy: Option<Arc<dyn AuthPolicy<S::Id>>>,
+    stats: SessionStats,
+    /// Time the handshake has started at.
+    handshake_started: Instant,
+    handshake_timeout: Duration,
+    reconnect: Option<Redial<S>>,
+    /// Keeps the accepted connection counted by the limits of [`NetAccept`]
+    /// until the resource is dropped.
+    connection_guard: Option<IpConnectionGuard>,
 }
 
 impl<S: NetSession> Display for NetResource<S> {
@@ -147,7 +532,10 @@ impl<S: NetSession> Display for NetResource<S> {
 
 impl<S: NetSession> AsRawFd for NetResource<S> {
     fn as_raw_fd(&self) -> RawFd {
-        self.session.as_raw_fd()
+        match &self.reconnect {
+            Some(redial) => redial.fd.as_raw_fd(),
+            None => self.session.as_raw_fd(),
+        }
     }
 }
 
@@ -221,6 +609,30 @@ impl<S: NetSession> NetSession for NetResource<S> {
         self.session.set_nonblocking(nonblocking)
     }
 
+    fn set_rekey_threshold(&mut self, threshold: RekeyThreshold) {
+        self.session.set_rekey_threshold(threshold)
+    }
+
+    fn set_auth_policy(&mut self, policy: Arc<dyn AuthPolicy<Self::Id>>) {
+        self.auth_policy = Some(policy);
+    }
+
+    fn stats(&self) -> Option<&SessionStats> {
+        Some(&self.stats)
+    }
+
+    fn has_pending_output(&self) -> bool {
+        !self.write_buffer.is_empty() || self.session.has_pending_output()
+    }
+
+    fn proxy_addrs(&self) -> Option<ProxyAddrs> {
+        self.session.proxy_addrs()
+    }
+
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.session.shutdown_write()
+    }
+
     fn disconnect(self) -> io::Result<()> {
         self.session.disconnect()
     }
@@ -240,6 +652,15 @@ impl<S: NetSession> NetResource<S> {
             read_buffer: vec![],
             read_buffer_len: 0,
             write_buffer: empty!(),
+            high_watermark: DEFAULT_HIGH_WATERMARK,
+            low_watermark: DEFAULT_LOW_WATERMARK,
+            is_paused: false,
+            auth_policy: None,
+            stats: SessionStats::new(),
+            handshake_started: Instant::now(),
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            reconnect: None,
+            connection_guard: None,
         }
     }
 
@@ -260,9 +681,91 @@ impl<S: NetSession> NetResource<S> {
             read_buffer: vec![0; READ_BUFFER_SIZE],
             read_buffer_len: 0,
             write_buffer: VecDeque::new(),
+            high_watermark: DEFAULT_HIGH_WATERMARK,
+            low_watermark: DEFAULT_LOW_WATERMARK,
+            is_paused: false,
+            auth_policy: None,
+            stats: SessionStats::new(),
+            handshake_started: Instant::now(),
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            reconnect: None,
+            connection_guard: None,
         })
     }
 
+    /// Sets sizes of the write queue above which [`SessionEvent::Paused`] and
+    /// below which [`SessionEvent::Resumed`] are reported.
+    ///
+    /// # Panics
+    ///
+    /// If the low watermark exceeds the high one.
+    pub fn with_watermarks(mut self, high_watermark: usize, low_watermark: usize) -> Self {
+        assert!(
+            low_watermark <= high_watermark,
+            "low watermark exceeds the high watermark"
+        );
+        self.high_watermark = high_watermark;
+        self.low_watermark = low_watermark;
+        self
+    }
+
+    /// Sets policy deciding whether the peer is accepted once the handshake
+    /// is complete. Without the policy all peers are accepted.
+    pub fn with_auth_policy(mut self, policy: impl AuthPolicy<S::Id> + 'static) -> Self {
+        self.auth_policy = Some(Arc::new(policy));
+        self
+    }
+
+    /// Sets time within which the session must complete its handshake,
+    /// counting from the resource construction; defaults to
+    /// [`DEFAULT_HANDSHAKE_TIMEOUT`].
+    pub fn with_handshake_timeout(mut self, timeout: Duration) -> Self {
+        self.handshake_timeout = timeout;
+        self
+    }
+
+    /// Makes the resource keep the accepted connection counted by the limits
+    /// of [`NetAccept`] until the resource is dropped, which happens once it
+    /// is disconnected.
+    pub fn with_connection_guard(mut self, guard: Option<IpConnectionGuard>) -> Self {
+        self.connection_guard = guard;
+        self
+    }
+
+    /// Makes the outbound resource re-dial its session with `dial` when the
+    /// session is dropped due to a network failure (see
+    /// [`ReconnectPolicy::is_retriable`]), as allowed by the policy. Each
+    /// attempt is reported with [`SessionEvent::Reconnecting`].
+    ///
+    /// The resource is polled through a duplicate of the session descriptor,
+    /// which is re-pointed to the socket of each re-dialed session, so the
+    /// resource keeps its id across the re-dials. The reactor re-registers
+    /// the descriptor with its poller after each re-dial (see
+    /// [`Resource::take_fd_replaced`]), so the pollers based on `epoll`, which
+    /// watch the files rather than the descriptor numbers, are supported as
+    /// well. Since this changes the id,
+    /// the method must be called before the resource is registered in the
+    /// reactor. Pending re-dials are cancelled once the resource is
+    /// unregistered, for instance with [`reactor::Controller::disconnect`].
+    pub fn with_reconnect(
+        mut self,
+        policy: ReconnectPolicy,
+        dial: impl FnMut() -> io::Result<S> + Send + 'static,
+    ) -> io::Result<Self> {
+        // SAFETY: the descriptor is owned by the session and is open
+        let fd =
+            unsafe { BorrowedFd::borrow_raw(self.session.as_raw_fd()) }.try_clone_to_owned()?;
+        self.reconnect = Some(Redial {
+            policy,
+            dial: Box::new(dial),
+            fd,
+            attempt: 0,
+            retry_at: None,
+            fd_replaced: false,
+        });
+        Ok(self)
+    }
+
     pub fn is_inbound(&self) -> bool {
         self.inbound
     }
@@ -295,6 +798,23 @@ impl<S: NetSession> NetResource<S> {
         self.session.expect_id()
     }
 
+    /// Zeroes the counters of the session statistics returned by
+    /// [`NetSession::stats`].
+    pub fn reset_stats(&mut self) {
+        self.stats.reset()
+    }
+
+    /// Returns number of bytes queued for sending.
+    pub fn queued_len(&self) -> usize {
+        self.write_buffer.len()
+    }
+
+    /// Detects whether the write queue has grown above the high watermark and
+    /// [`SessionEvent::Resumed`] was not yet reported.
+    pub fn is_paused(&self) -> bool {
+        self.is_paused
+    }
+
     pub fn drain_read_buffer(&mut self) -> Vec<u8> {
         let len = self.read_buffer_len;
         self.read_buffer_len = 0;
@@ -304,22 +824,175 @@ impl<S: NetSession> NetResource<S> {
     fn terminate(&mut self, reason: io::Error) -> SessionEvent<S> {
         #[cfg(feature = "log")]
         log::trace!(target: "transport", "Terminating connection {self} due to {reason:?}");
+        #[cfg(feature = "tracing")]
+        tracing::debug!(target: "transport", transport = %self, %reason, "Terminating connection");
 
         self.state = TransportState::Terminated;
         SessionEvent::Terminated(reason)
     }
 
+    /// Asks the authorization policy whether the peer of the established
+    /// session is accepted, activating the session if it is.
+    fn authorize(&mut self) -> Option<SessionEvent<S>> {
+        let id = self.session.expect_id();
+        let verdict = match &self.auth_policy {
+            Some(policy) => policy.verdict(&id),
+            None => AuthVerdict::Accept,
+        };
+        match verdict {
+            AuthVerdict::Accept => {
+                // We just got connected; may need to send output
+                self.write_intent = true;
+                self.state = TransportState::Active;
+                self.stats = SessionStats::new();
+                if let Some(redial) = &mut self.reconnect {
+                    redial.attempt = 0;
+                }
+                Some(SessionEvent::Established(id, self.session.proxy_addrs()))
+            }
+            AuthVerdict::Reject(reason) => {
+                #[cfg(feature = "log")]
+                log::warn!(target: "transport", "Peer {self} is rejected: {reason}");
+                #[cfg(feature = "tracing")]
+                tracing::warn!(target: "transport", transport = %self, %reason, "Peer is rejected");
+
+                self.state = TransportState::Terminated;
+                Some(SessionEvent::Rejected(id, reason))
+            }
+            AuthVerdict::Defer if self.state == TransportState::Deferred => None,
+            AuthVerdict::Defer => {
+                #[cfg(feature = "log")]
+                log::debug!(target: "transport", "Verdict on peer {self} is deferred");
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "transport", transport = %self, "Verdict on peer is deferred");
+
+                self.state = TransportState::Deferred;
+                Some(SessionEvent::Deferred(id))
+            }
+        }
+    }
+
+    /// Intercepts the events terminating the session, scheduling its re-dial
+    /// if the resource has a reconnection policy.
+    fn redial_on_termination(&mut self, event: Option<SessionEvent<S>>) -> Option<SessionEvent<S>> {
+        if self.reconnect.is_none() {
+            return event;
+        }
+        let reason = match event {
+            Some(SessionEvent::Terminated(reason)) if ReconnectPolicy::is_retriable(&reason) => {
+                reason
+            }
+            Some(SessionEvent::HandshakeTimeout(elapsed)) => io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("handshake has not completed within {elapsed:?}"),
+            ),
+            event => return event,
+        };
+        Some(self.schedule_redial(reason))
+    }
+
+    fn schedule_redial(&mut self, reason: io::Error) -> SessionEvent<S> {
+        let redial = self
+            .reconnect
+            .as_mut()
+            .expect("resource has no reconnection policy");
+        // The dropped connection is replaced with a placeholder, so it is not
+        // reported by the poller until the re-dial. The descriptor of the
+        // session is replaced as well, closing the connection right away:
+        // pollers based on `epoll` keep watching a file until all its
+        // descriptors are closed.
+        let session_fd = self.session.as_raw_fd();
+        if let Err(err) = File::open("/dev/null").and_then(|null| {
+            redirect_fd(null.as_raw_fd(), redial.fd.as_raw_fd())?;
+            redirect_fd(null.as_raw_fd(), session_fd)
+        }) {
+            return self.terminate(err);
+        }
+        redial.attempt += 1;
+        let attempt = redial.attempt;
+        if attempt > redial.policy.max_retries {
+            let attempts = redial.policy.max_retries;
+
+            #[cfg(feature = "log")]
+            log::warn!(target: "transport", "All {attempts} attempts to re-dial {self} have failed: {reason}");
+            #[cfg(feature = "tracing")]
+            tracing::warn!(target: "transport", transport = %self, attempts, %reason, "Re-dial attempts are exhausted");
+
+            self.state = TransportState::Terminated;
+            return SessionEvent::ReconnectExhausted { attempts, reason };
+        }
+        let delay = redial.policy.delay(attempt);
+        redial.retry_at = Some(unix_time() + delay);
+
+        #[cfg(feature = "log")]
+        log::debug!(target: "transport", "Connection {self} is dropped due to {reason}, re-dialing in {delay:?} (attempt {attempt})");
+        #[cfg(feature = "tracing")]
+        tracing::debug!(target: "transport", transport = %self, %reason, ?delay, attempt, "Connection is dropped, re-dialing");
+
+        self.state = TransportState::Reconnecting;
+        SessionEvent::Reconnecting {
+            attempt,
+            delay,
+            reason,
+        }
+    }
+
+    /// Re-dials the dropped session once the delay has elapsed.
+    fn redial(&mut self, time: Duration) -> Option<SessionEvent<S>> {
+        let redial = self.reconnect.as_mut()?;
+        redial.retry_at.filter(|retry_at| *retry_at <= time)?;
+        redial.retry_at = None;
+        let res = (redial.dial)().and_then(|mut session| {
+            session.set_read_timeout(Some(READ_TIMEOUT))?;
+            session.set_write_timeout(Some(WRITE_TIMEOUT))?;
+            redirect_fd(session.as_raw_fd(), redial.fd.as_raw_fd())?;
+            Ok(session)
+        });
+        match res {
+            Ok(session) => {
+                redial.fd_replaced = true;
+                // Dropping the session closes the dropped connection
+                let _ = std::mem::replace(&mut self.session, session);
+                self.state = TransportState::Init;
+                self.write_intent = false;
+                self.read_buffer_len = 0;
+                self.write_buffer.clear();
+                self.is_paused = false;
+                self.stats = SessionStats::new();
+                self.handshake_started = Instant::now();
+
+                #[cfg(feature = "log")]
+                log::debug!(target: "transport", "Re-dialed {self}");
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "transport", transport = %self, "Re-dialed");
+
+                None
+            }
+            Err(err) => self.redial_on_termination(Some(SessionEvent::Terminated(err))),
+        }
+    }
+
     fn handle_writable(&mut self) -> Option<SessionEvent<S>> {
         if !self.session.is_session_established() {
             let _ = self.session.write(&[]);
             self.write_intent = true;
             return None;
         }
-        match self.flush() {
-            Ok(_) => {
-                self.write_intent = false;
-                None
+        let res = self.flush_write_buffer().and_then(|_| self.session.flush());
+        // Write events are watched only while there are data to write, so
+        // idle sessions are not woken up by the socket being writable
+        self.write_intent = self.has_pending_output();
+        match res {
+            Ok(_) if self.is_paused && self.write_buffer.len() <= self.low_watermark => {
+                #[cfg(feature = "log")]
+                log::debug!(target: "transport", "Write queue of {self} has drained, resuming");
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "transport", transport = %self, "Write queue has drained");
+
+                self.is_paused = false;
+                Some(SessionEvent::Resumed)
             }
+            Ok(_) => None,
             // In this case, the write couldn't complete. Leave `needs_flush` set
             // to be notified when the socket is ready to write again.
             Err(err)
@@ -332,6 +1005,24 @@ impl<S: NetSession> NetResource<S> {
         }
     }
 
+    /// Writes out as much of the write queue as the session accepts without
+    /// blocking, requesting write events if some data remain in the queue.
+    fn flush_write_buffer(&mut self) -> io::Result<()> {
+        while !self.write_buffer.is_empty() {
+            match self.session.write(self.write_buffer.make_contiguous()) {
+                Ok(0) => break,
+                Ok(len) => {
+                    self.write_buffer.drain(..len);
+                    self.stats.bytes_sent += len as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) => return Err(err),
+            }
+        }
+        self.write_intent = self.has_pending_output();
+        Ok(())
+    }
+
     fn handle_readable(&mut self) -> Option<SessionEvent<S>> {
         // Nb. Since `poll`, which this reactor is based on, is *level-triggered*,
         // we will be notified again if there is still data to be read on the socket.
@@ -347,12 +1038,15 @@ impl<S: NetSession> NetResource<S> {
             )),
             Ok(len) => {
                 self.read_buffer_len += len;
+                self.stats.bytes_received += len as u64;
+                self.stats.reads += 1;
                 Some(SessionEvent::Data(self.drain_read_buffer()))
             }
             Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
                 // This shouldn't normally happen, since this function is only called
                 // when there's data on the socket. We leave it here in case external
                 // conditions change.
+                #[cfg(feature = "log")]
                 log::warn!(target: "transport",
                     "WOULD_BLOCK on resource which had read intent - probably normal thing to happen"
                 );
@@ -368,13 +1062,15 @@ impl<S: NetSession> Resource for NetResource<S> {
     type Event = SessionEvent<S>;
 
     fn id(&self) -> Self::Id {
-        self.session.as_raw_fd()
+        self.as_raw_fd()
     }
 
     fn interests(&self) -> IoType {
         match self.state {
             TransportState::Init => IoType::write_only(),
-            TransportState::Terminated => IoType::none(),
+            TransportState::Deferred
+            | TransportState::Reconnecting
+            | TransportState::Terminated => IoType::none(),
             TransportState::Active | TransportState::Handshake if self.write_intent => {
                 IoType::read_write()
             }
@@ -393,6 +1089,8 @@ impl<S: NetSession> Resource for NetResource<S> {
         if self.state == TransportState::Init {
             #[cfg(feature = "log")]
             log::debug!(target: "transport", "Transport {self} is connected, initializing handshake");
+            #[cfg(feature = "tracing")]
+            tracing::debug!(target: "transport", transport = %self, "Connected, initializing handshake");
 
             force_write_intent = true;
             self.state = TransportState::Handshake;
@@ -401,6 +1099,8 @@ impl<S: NetSession> Resource for NetResource<S> {
             debug_assert!(!self.session.is_session_established());
             #[cfg(feature = "log")]
             log::trace!(target: "transport", "Transport {self} got I/O while in handshake mode");
+            #[cfg(feature = "tracing")]
+            tracing::trace!(target: "transport", transport = %self, ?io, "Handshake I/O");
         }
 
         let resp = match io {
@@ -413,41 +1113,141 @@ impl<S: NetSession> Resource for NetResource<S> {
             self.write_intent = true;
         }
 
-        if matches!(&resp, Some(SessionEvent::Terminated(e)) if e.kind() == io::ErrorKind::ConnectionReset)
+        let resp = if matches!(&resp, Some(SessionEvent::Terminated(e)) if e.kind() == io::ErrorKind::ConnectionReset)
             && self.state != TransportState::Handshake
         {
             #[cfg(feature = "log")]
             log::debug!(target: "transport", "Peer {self} has reset the connection");
+            #[cfg(feature = "tracing")]
+            tracing::debug!(target: "transport", transport = %self, "Peer has reset the connection");
 
             self.state = TransportState::Terminated;
             resp
         } else if self.session.is_session_established() && self.state == TransportState::Handshake {
             #[cfg(feature = "log")]
             log::debug!(target: "transport", "Handshake with {self} is complete");
+            #[cfg(feature = "tracing")]
+            tracing::debug!(target: "transport", transport = %self, "Handshake is complete");
 
-            // We just got connected; may need to send output
-            self.write_intent = true;
-            self.state = TransportState::Active;
-            Some(SessionEvent::Established(self.session.expect_id()))
+            self.authorize()
         } else {
             resp
+        };
+        self.redial_on_termination(resp)
+    }
+
+    fn handle_queued(&mut self) -> Option<Self::Event> {
+        if self.is_paused || self.write_buffer.len() <= self.high_watermark {
+            return None;
         }
+
+        #[cfg(feature = "log")]
+        log::debug!(target: "transport", "Write queue of {self} has reached {} bytes, pausing", self.write_buffer.len());
+        #[cfg(feature = "tracing")]
+        tracing::debug!(target: "transport", transport = %self, queued = self.write_buffer.len(), "Write queue is full");
+
+        self.is_paused = true;
+        Some(SessionEvent::Paused)
+    }
+
+    fn handle_resume(&mut self) -> Option<Self::Event> {
+        match self.state {
+            TransportState::Deferred => self.authorize(),
+            _ => None,
+        }
+    }
+
+    /// Deadline of the handshake, which is cancelled once the handshake
+    /// completes.
+    ///
+    /// The time left for the handshake is measured with a monotonic clock and
+    /// is converted to the system time only when reported to the reactor, so
+    /// adjustments of the system clock don't make the handshake time out
+    /// earlier or later.
+    fn deadline(&self) -> Option<Duration> {
+        match self.state {
+            TransportState::Init | TransportState::Handshake => {
+                let now = unix_time();
+                Some(
+                    (now + self.handshake_timeout).saturating_sub(self.handshake_started.elapsed()),
+                )
+            }
+            TransportState::Reconnecting => self.reconnect.as_ref().and_then(|r| r.retry_at),
+            TransportState::Deferred | TransportState::Active | TransportState::Terminated => None,
+        }
+    }
+
+    fn handle_timeout(&mut self, time: Duration) -> Option<Self::Event> {
+        if self.state == TransportState::Reconnecting {
+            return self.redial(time);
+        }
+        // Handshake may have completed since the deadline was checked
+        if !matches!(self.state, TransportState::Init | TransportState::Handshake) {
+            return None;
+        }
+        let elapsed = self.handshake_started.elapsed();
+        if elapsed < self.handshake_timeout {
+            return None;
+        }
+
+        #[cfg(feature = "log")]
+        log::warn!(target: "transport", "Handshake with {self} has not completed within {elapsed:?}");
+        #[cfg(feature = "tracing")]
+        tracing::warn!(target: "transport", transport = %self, ?elapsed, "Handshake has timed out");
+
+        self.state = TransportState::Terminated;
+        self.redial_on_termination(Some(SessionEvent::HandshakeTimeout(elapsed)))
+    }
+
+    /// Schedules re-dial of the session if the resource has a reconnection
+    /// policy.
+    /// Reports re-dials of the session, after which the resource has to be
+    /// re-registered with the pollers based on `epoll`.
+    fn take_fd_replaced(&mut self) -> bool {
+        self.reconnect
+            .as_mut()
+            .map(|redial| std::mem::take(&mut redial.fd_replaced))
+            .unwrap_or_default()
+    }
+
+    fn handle_hangup(&mut self) -> Option<Self::Event> {
+        if self.reconnect.is_none() || self.state == TransportState::Terminated {
+            return None;
+        }
+        let reason = socket_error(self.session.as_raw_fd())
+            .unwrap_or_else(|| io::ErrorKind::ConnectionReset.into());
+        Some(self.schedule_redial(reason))
+    }
+
+    fn handle_drain(&mut self) -> Option<Self::Event> {
+        Some(SessionEvent::Draining)
     }
 
     fn disconnect(self) -> io::Result<()> {
-        self.session.disconnect()
+        match self.state {
+            // Session was already dropped
+            TransportState::Reconnecting => Ok(()),
+            _ => self.session.disconnect(),
+        }
     }
 }
 
+fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time")
+}
+
 /// This implementation is used by a reactor and can be used only when the resource
 /// is unregistered from the reactor.
 // TODO: Consider removing this implementation
 impl<S: NetSession> Read for NetResource<S> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         match self.state {
-            TransportState::Init | TransportState::Handshake => {
-                Err(io::ErrorKind::NotConnected.into())
-            }
+            TransportState::Init
+            | TransportState::Handshake
+            | TransportState::Deferred
+            | TransportState::Reconnecting => Err(io::ErrorKind::NotConnected.into()),
             TransportState::Active => self.session.read(buf),
             TransportState::Terminated => Err(io::ErrorKind::ConnectionAborted.into()),
         }
@@ -474,21 +1274,10 @@ impl<S: NetSession> WriteAtomic for NetResource<S> {
     }
 
     fn write_or_buffer(&mut self, buf: &[u8]) -> io::Result<()> {
-        let len = self.session.write(self.write_buffer.make_contiguous())?;
-        if len < self.write_buffer.len() {
-            self.write_buffer.drain(..len);
-            self.write_buffer.extend(buf);
-            return Ok(());
-        }
-        self.write_buffer.drain(..);
-        match self.session.write(&buf) {
-            Err(err) => Err(err),
-            Ok(len) if len < buf.len() => {
-                self.write_buffer.extend(&buf[len..]);
-                Ok(())
-            }
-            Ok(_) => Ok(()),
-        }
+        // Preserving ordering: new data go after the pending ones
+        self.write_buffer.extend(buf);
+        self.stats.messages_sent += 1;
+        self.flush_write_buffer()
     }
 }
 
@@ -596,9 +1385,1047 @@ mod split {
                 read_buffer: vec![0u8; READ_BUFFER_SIZE],
                 read_buffer_len: 0,
                 write_buffer: VecDeque::new(),
+                high_watermark: DEFAULT_HIGH_WATERMARK,
+                low_watermark: DEFAULT_LOW_WATERMARK,
+                is_paused: false,
+                auth_policy: None,
+                stats: SessionStats::new(),
+                handshake_started: Instant::now(),
+                handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+                reconnect: None,
+                connection_guard: None,
             }
         }
     }
 }
 use crate::connection::Proxy;
 pub use split::*;
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpStream;
+    use std::os::unix::io::FromRawFd;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::mpsc;
+
+    use reactor::poller::{popol, IoFail, Poll};
+    use reactor::{Action, Handler, MultiReactor, Reactor};
+
+    use super::*;
+    use crate::noise::{xx_keypair, Keypair, NoiseXx};
+    use crate::{AllowList, DenyList, ProxyHeaderError, ProxyListener, ProxyStream};
+
+    /// Policy accepting the peers from the allow list and deferring the
+    /// verdict on the rest of them.
+    #[derive(Debug)]
+    struct Approval(AllowList<[u8; 32]>);
+
+    impl AuthPolicy<[u8; 32]> for Approval {
+        fn verdict(&self, key: &[u8; 32]) -> AuthVerdict {
+            match self.0.contains(key) {
+                true => AuthVerdict::Accept,
+                false => AuthVerdict::Defer,
+            }
+        }
+    }
+
+    fn peer_key(keys: &Keypair) -> [u8; 32] {
+        keys.public.as_slice().try_into().unwrap()
+    }
+
+    /// Returns pair of Noise_XX resources over connected non-blocking
+    /// sockets, with the responder using the authorization policy.
+    fn noise_pair(
+        initiator_keys: &Keypair,
+        policy: impl AuthPolicy<[u8; 32]> + 'static,
+    ) -> (NetResource<NoiseXx>, NetResource<NoiseXx>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        client.set_nonblocking(true).unwrap();
+        server.set_nonblocking(true).unwrap();
+        let initiator =
+            NetResource::new(NoiseXx::initiate(client, initiator_keys).unwrap()).unwrap();
+        let responder = NetResource::new(NoiseXx::accept(server, &xx_keypair()).unwrap())
+            .unwrap()
+            .with_auth_policy(policy);
+        (initiator, responder)
+    }
+
+    /// Listener failing to accept connections with `EMFILE` for the given
+    /// number of times, as if the process has run out of file descriptors.
+    #[derive(Debug)]
+    struct Exhausted {
+        listener: TcpListener,
+        failures: AtomicUsize,
+    }
+
+    impl AsRawFd for Exhausted {
+        fn as_raw_fd(&self) -> RawFd {
+            self.listener.as_raw_fd()
+        }
+    }
+
+    impl NetListener for Exhausted {
+        type Stream = TcpStream;
+
+        fn bind(addr: &impl ToSocketAddrs) -> io::Result<Self> {
+            Ok(Self {
+                listener: TcpListener::bind(addr)?,
+                failures: AtomicUsize::new(1),
+            })
+        }
+
+        fn with_reuseport(addr: net::SocketAddr) -> io::Result<Self> {
+            Self::bind(&addr)
+        }
+
+        fn accept(&self) -> io::Result<TcpStream> {
+            let failures = self.failures.load(Ordering::Relaxed);
+            if failures > 0 {
+                self.failures.store(failures - 1, Ordering::Relaxed);
+                return Err(io::Error::from_raw_os_error(libc::EMFILE));
+            }
+            NetListener::accept(&self.listener)
+        }
+
+        fn local_addr(&self) -> net::SocketAddr {
+            NetListener::local_addr(&self.listener)
+        }
+
+        fn ttl(&self) -> io::Result<u32> {
+            self.listener.ttl()
+        }
+
+        fn set_ttl(&self, ttl: u32) -> io::Result<()> {
+            self.listener.set_ttl(ttl)
+        }
+
+        fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+            self.listener.set_nonblocking(nonblocking)
+        }
+
+        fn try_clone(&self) -> io::Result<Self> {
+            Ok(Self {
+                listener: self.listener.try_clone()?,
+                failures: AtomicUsize::new(self.failures.load(Ordering::Relaxed)),
+            })
+        }
+
+        fn take_error(&self) -> io::Result<Option<io::Error>> {
+            self.listener.take_error()
+        }
+    }
+
+    /// Poller based on `epoll`, which, unlike `poll`, watches the registered
+    /// files rather than the descriptor numbers.
+    struct Epoll {
+        epoll: OwnedFd,
+        events: VecDeque<(RawFd, Result<IoType, IoFail>)>,
+    }
+
+    impl Epoll {
+        fn new() -> Self {
+            let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+            assert!(fd >= 0, "{}", io::Error::last_os_error());
+            Self {
+                // SAFETY: the descriptor was just created and is not owned by
+                // anything else
+                epoll: unsafe { OwnedFd::from_raw_fd(fd) },
+                events: empty!(),
+            }
+        }
+
+        /// Performs the control operation, returning whether it succeeded. The
+        /// operation fails for the descriptors which can't be polled or are not
+        /// registered, which is ignored in the same way as by `popol`.
+        fn ctl(&self, op: libc::c_int, fd: RawFd, interest: IoType) -> bool {
+            let mut events = 0;
+            if interest.read {
+                events |= libc::EPOLLIN;
+            }
+            if interest.write {
+                events |= libc::EPOLLOUT;
+            }
+            let mut event = libc::epoll_event {
+                events: events as u32,
+                u64: fd as u64,
+            };
+            unsafe { libc::epoll_ctl(self.epoll.as_raw_fd(), op, fd, &mut event) == 0 }
+        }
+    }
+
+    impl Poll for Epoll {
+        fn register(&mut self, fd: &impl AsRawFd, interest: IoType) {
+            self.ctl(libc::EPOLL_CTL_ADD, fd.as_raw_fd(), interest);
+        }
+
+        fn unregister(&mut self, fd: &impl AsRawFd) {
+            self.ctl(libc::EPOLL_CTL_DEL, fd.as_raw_fd(), IoType::none());
+        }
+
+        fn set_interest(&mut self, fd: &impl AsRawFd, interest: IoType) -> bool {
+            self.ctl(libc::EPOLL_CTL_MOD, fd.as_raw_fd(), interest)
+        }
+
+        fn poll(&mut self, timeout: Option<Duration>) -> io::Result<usize> {
+            let mut events = [libc::epoll_event { events: 0, u64: 0 }; 64];
+            let timeout = timeout.map(|t| t.as_millis() as libc::c_int).unwrap_or(-1);
+            let count = unsafe {
+                libc::epoll_wait(
+                    self.epoll.as_raw_fd(),
+                    events.as_mut_ptr(),
+                    events.len() as libc::c_int,
+                    timeout,
+                )
+            };
+            if count < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            for event in &events[..count as usize] {
+                let (fd, fired) = (event.u64 as RawFd, event.events as libc::c_int);
+                let res = if fired & libc::EPOLLHUP != 0 {
+                    Err(IoFail::Connectivity(fired as i16))
+                } else if fired & libc::EPOLLERR != 0 {
+                    Err(IoFail::Os(fired as i16))
+                } else {
+                    Ok(IoType {
+                        read: fired & libc::EPOLLIN != 0,
+                        write: fired & libc::EPOLLOUT != 0,
+                    })
+                };
+                self.events.push_back((fd, res));
+            }
+            Ok(count as usize)
+        }
+    }
+
+    impl Iterator for Epoll {
+        type Item = (RawFd, Result<IoType, IoFail>);
+
+        fn next(&mut self) -> Option<Self::Item> {
+            self.events.pop_front()
+        }
+    }
+
+    /// Handler forwarding the transport events to the test.
+    #[derive(Clone)]
+    struct Events(mpsc::Sender<SessionEvent<NoiseXx>>);
+
+    impl Iterator for Events {
+        type Item = Action<NetAccept<NoiseXx>, NetResource<NoiseXx>>;
+
+        fn next(&mut self) -> Option<Self::Item> {
+            None
+        }
+    }
+
+    impl Handler for Events {
+        type Listener = NetAccept<NoiseXx>;
+        type Transport = NetResource<NoiseXx>;
+        type Command = ();
+
+        fn tick(&mut self, _time: Duration) {}
+        fn handle_wakeup(&mut self) {}
+        fn handle_listener_event(
+            &mut self,
+            _: net::SocketAddr,
+            _: ListenerEvent<NoiseXx>,
+            _: Duration,
+        ) {
+        }
+        fn handle_transport_event(&mut self, _: RawFd, event: SessionEvent<NoiseXx>, _: Duration) {
+            self.0.send(event).unwrap();
+        }
+        fn handle_command(&mut self, _: ()) {}
+        fn handle_error(&mut self, _: reactor::Error<Self::Listener, Self::Transport>) {}
+        fn handover_listener(&mut self, _: Self::Listener) {}
+        fn handover_transport(&mut self, _: Self::Transport) {}
+    }
+
+    /// Drives the handshake until both resources report its outcome.
+    fn handshake<C: NetConnection>(
+        initiator: &mut NetResource<NoiseXx>,
+        responder: &mut NetResource<NoiseXx<C>>,
+    ) -> (SessionEvent<NoiseXx>, SessionEvent<NoiseXx<C>>) {
+        let mut events = (None, None);
+        for _ in 0..1000 {
+            if events.0.is_none() {
+                events.0 = initiator
+                    .handle_io(Io::Write)
+                    .or_else(|| initiator.handle_io(Io::Read));
+            }
+            if events.1.is_none() {
+                events.1 = responder
+                    .handle_io(Io::Write)
+                    .or_else(|| responder.handle_io(Io::Read));
+            }
+            if let (Some(initiator), Some(responder)) = events {
+                return (initiator, responder);
+            }
+        }
+        panic!("handshake has not completed");
+    }
+
+    #[test]
+    fn rejected_peer() {
+        let keys = xx_keypair();
+        let denied = DenyList::from_iter([peer_key(&keys)]);
+        let (mut initiator, mut responder) = noise_pair(&keys, denied.clone());
+        let (_, event) = handshake(&mut initiator, &mut responder);
+        assert!(
+            matches!(event, SessionEvent::Rejected(id, RejectReason::Denied) if id == peer_key(&keys))
+        );
+        assert_eq!(responder.state(), TransportState::Terminated);
+        assert_eq!(responder.interests(), IoType::none());
+
+        // Private swarm accepts only the allowed peers which are not denied
+        let allowed = AllowList::from_iter([peer_key(&keys)]);
+        let (mut initiator, mut responder) =
+            noise_pair(&xx_keypair(), (denied.clone(), allowed.clone()));
+        let (_, event) = handshake(&mut initiator, &mut responder);
+        assert!(matches!(
+            event,
+            SessionEvent::Rejected(_, RejectReason::NotAllowed)
+        ));
+
+        assert!(denied.remove(&peer_key(&keys)));
+        let (mut initiator, mut responder) = noise_pair(&keys, (denied, allowed));
+        let (event, _) = handshake(&mut initiator, &mut responder);
+        assert!(matches!(event, SessionEvent::Established(..)));
+        assert_eq!(responder.state(), TransportState::Active);
+    }
+
+    #[test]
+    fn deferred_peer() {
+        let keys = xx_keypair();
+        let approved = AllowList::new();
+        let (mut initiator, mut responder) = noise_pair(&keys, Approval(approved.clone()));
+        let (event, deferred) = handshake(&mut initiator, &mut responder);
+        assert!(matches!(event, SessionEvent::Established(..)));
+        assert!(matches!(deferred, SessionEvent::Deferred(id) if id == peer_key(&keys)));
+
+        // No data flow while the verdict is deferred
+        assert_eq!(responder.state(), TransportState::Deferred);
+        assert_eq!(responder.interests(), IoType::none());
+        assert!(matches!(
+            responder.write_atomic(b"ping"),
+            Err(WriteError::NotReady)
+        ));
+        initiator.write_atomic(b"ping").unwrap();
+        assert!(responder.handle_resume().is_none());
+        assert_eq!(responder.state(), TransportState::Deferred);
+
+        approved.insert(peer_key(&keys));
+        let event = responder.handle_resume();
+        assert!(
+            matches!(event, Some(SessionEvent::Established(id, None)) if id == peer_key(&keys))
+        );
+        let data = loop {
+            if let Some(SessionEvent::Data(data)) = responder.handle_io(Io::Read) {
+                break data;
+            }
+        };
+        assert_eq!(data, b"ping");
+    }
+
+    #[test]
+    fn backpressure() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let mut resource =
+            NetResource::with_session(stream, false).with_watermarks(64 * 1024, 16 * 1024);
+
+        // The peer doesn't read, so once the socket buffers are full the data
+        // get queued
+        let chunk = [0xA5u8; 4096];
+        let event = loop {
+            resource.write_atomic(&chunk).unwrap();
+            if let Some(event) = resource.handle_queued() {
+                break event;
+            }
+        };
+        assert!(matches!(event, SessionEvent::Paused));
+        assert!(resource.is_paused());
+        assert!(resource.queued_len() > 64 * 1024);
+        assert!(resource.interests().write);
+        // Transition is reported only once
+        resource.write_atomic(&chunk).unwrap();
+        assert!(resource.handle_queued().is_none());
+
+        let mut buf = vec![0u8; 64 * 1024];
+        let event = loop {
+            assert!(peer.read(&mut buf).unwrap() > 0);
+            if let Some(event) = resource.handle_io(Io::Write) {
+                break event;
+            }
+        };
+        assert!(matches!(event, SessionEvent::Resumed));
+        assert!(!resource.is_paused());
+        assert!(resource.queued_len() <= 16 * 1024);
+    }
+
+    #[test]
+    fn session_stats() {
+        const MESSAGES: usize = 1000;
+
+        let denied = DenyList::from_iter([]);
+        let (mut initiator, mut responder) = noise_pair(&xx_keypair(), denied);
+        let (event, _) = handshake(&mut initiator, &mut responder);
+        assert!(matches!(event, SessionEvent::Established(..)));
+        // Handshake messages are not counted
+        assert_eq!(initiator.stats().unwrap().bytes_sent, 0);
+        assert_eq!(responder.stats().unwrap().bytes_received, 0);
+
+        let mut len = 0;
+        for no in 0..MESSAGES {
+            let message = vec![no as u8; no % 64 + 1];
+            len += message.len();
+            initiator.write_atomic(&message).unwrap();
+        }
+        let mut received = 0;
+        let mut reads = 0;
+        while received < len {
+            if initiator.queued_len() > 0 {
+                assert!(initiator.handle_io(Io::Write).is_none());
+            }
+            match responder.handle_io(Io::Read) {
+                Some(SessionEvent::Data(data)) => {
+                    received += data.len();
+                    reads += 1;
+                }
+                None => {}
+                Some(_) => panic!("session is terminated"),
+            }
+        }
+
+        let stats = *initiator.stats().unwrap();
+        assert_eq!(stats.bytes_sent, len as u64);
+        assert_eq!(stats.messages_sent, MESSAGES as u64);
+        let peer_stats = responder.stats().unwrap();
+        assert_eq!(peer_stats.bytes_received, len as u64);
+        assert_eq!(peer_stats.reads, reads);
+
+        initiator.reset_stats();
+        let reset = initiator.stats().unwrap();
+        assert_eq!(reset.bytes_sent, 0);
+        assert_eq!(reset.messages_sent, 0);
+        assert_eq!(reset.established_at, stats.established_at);
+    }
+
+    #[test]
+    fn handshake_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        // The peer connects, but never sends the handshake
+        let _peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        let timeout = Duration::from_millis(200);
+        let resource = NetResource::new(NoiseXx::accept(stream, &xx_keypair()).unwrap())
+            .unwrap()
+            .with_handshake_timeout(timeout);
+
+        let (sender, events) = mpsc::channel();
+        let reactor = Reactor::new(Events(sender), popol::Poller::new()).unwrap();
+        reactor.controller().register_transport(resource).unwrap();
+        match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+            SessionEvent::HandshakeTimeout(elapsed) => assert!(elapsed >= timeout),
+            _ => panic!("handshake timeout is not reported"),
+        }
+    }
+
+    #[test]
+    fn incomplete_handshake_timeout() {
+        let timeout = Duration::from_millis(300);
+        let (mut initiator, responder) = noise_pair(&xx_keypair(), DenyList::from_iter([]));
+        let mut responder = responder.with_handshake_timeout(timeout);
+        let deadline = responder.deadline().unwrap();
+        assert!(deadline <= unix_time() + timeout);
+
+        // Only the first handshake message arrives
+        assert!(initiator.handle_io(Io::Write).is_none());
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(responder.handle_io(Io::Read).is_none());
+        assert_eq!(responder.state(), TransportState::Handshake);
+
+        // Time passed by the reactor doesn't affect the time left for the
+        // handshake, so a jump of the system clock doesn't end it early
+        assert!(responder
+            .handle_timeout(deadline + Duration::from_secs(3600))
+            .is_none());
+        assert!(responder.deadline().unwrap() > unix_time());
+
+        std::thread::sleep(timeout);
+        let event = responder.handle_timeout(unix_time());
+        assert!(
+            matches!(event, Some(SessionEvent::HandshakeTimeout(elapsed)) if elapsed >= timeout)
+        );
+        assert_eq!(responder.state(), TransportState::Terminated);
+        assert_eq!(responder.deadline(), None);
+
+        // Completed handshake cancels the deadline
+        let (mut initiator, mut responder) = noise_pair(&xx_keypair(), DenyList::from_iter([]));
+        assert!(initiator.deadline().is_some());
+        handshake(&mut initiator, &mut responder);
+        assert_eq!(initiator.deadline(), None);
+        assert_eq!(responder.deadline(), None);
+    }
+
+    #[test]
+    fn fd_exhaustion_pauses_accepting() {
+        let backoff = Duration::from_millis(50);
+        let listener = Exhausted::bind(&"127.0.0.1:0").unwrap();
+        let mut accept = NetAccept::<NoiseXx, _>::with_listener(listener, xx_keypair())
+            .unwrap()
+            .with_accept_backoff(backoff);
+        let _client = TcpStream::connect(accept.local_addr()).unwrap();
+
+        let started = unix_time();
+        match accept.handle_io(Io::Read) {
+            Some(ListenerEvent::Paused {
+                err,
+                backoff: paused,
+            }) => {
+                assert_eq!(err.raw_os_error(), Some(libc::EMFILE));
+                assert_eq!(paused, backoff);
+            }
+            _ => panic!("listener is not paused"),
+        }
+        assert!(accept.is_paused());
+        assert!(accept.interests().is_none());
+        let deadline = accept.deadline().unwrap();
+        assert!(deadline >= started + backoff);
+        // Spurious events while paused are ignored
+        assert!(accept.handle_io(Io::Read).is_none());
+        assert!(accept
+            .handle_timeout(deadline - Duration::from_millis(1))
+            .is_none());
+        assert!(accept.is_paused());
+
+        assert!(matches!(
+            accept.handle_timeout(deadline),
+            Some(ListenerEvent::Resumed)
+        ));
+        assert!(accept.interests().is_read_only());
+        assert_eq!(accept.deadline(), None);
+        assert!(matches!(
+            accept.handle_io(Io::Read),
+            Some(ListenerEvent::Accepted(..))
+        ));
+    }
+
+    #[test]
+    fn per_ip_connection_limit() {
+        const LIMIT: usize = 3;
+
+        let localhost = IpAddr::from([127, 0, 0, 1]);
+        let mut accept = NetAccept::<NoiseXx>::bind(&"127.0.0.1:0", xx_keypair())
+            .unwrap()
+            .with_connection_limits(LIMIT, None);
+        let counter = accept.connection_counter().unwrap();
+
+        let mut clients = vec![];
+        let mut accepted = vec![];
+        let mut rejected = vec![];
+        for _ in 0..=LIMIT {
+            clients.push(TcpStream::connect(accept.local_addr()).unwrap());
+            match accept.handle_io(Io::Read) {
+                Some(ListenerEvent::Accepted(session, guard)) => accepted.push(
+                    NetResource::new(session)
+                        .unwrap()
+                        .with_connection_guard(guard),
+                ),
+                Some(ListenerEvent::PeerLimitReached { ip, current, max }) => {
+                    rejected.push((ip, current, max))
+                }
+                _ => panic!("unexpected listener event"),
+            }
+        }
+        assert_eq!(accepted.len(), LIMIT);
+        assert_eq!(rejected, vec![(localhost, LIMIT, LIMIT)]);
+        assert_eq!(counter.current(localhost), LIMIT);
+        assert_eq!(counter.rejected(), 1);
+        // The rejected connection is reset
+        let mut client = clients.pop().unwrap();
+        assert!(client.read(&mut [0u8; 1]).map_or(true, |len| len == 0));
+
+        // Disconnected connection allows a new one
+        Resource::disconnect(accepted.pop().unwrap()).unwrap();
+        assert_eq!(counter.current(localhost), LIMIT - 1);
+        let _client = TcpStream::connect(accept.local_addr()).unwrap();
+        let Some(ListenerEvent::Accepted(session, guard)) = accept.handle_io(Io::Read) else {
+            panic!("connection is not accepted");
+        };
+        assert_eq!(guard.as_ref().map(IpConnectionGuard::ip), Some(localhost));
+        assert_eq!(counter.current(localhost), LIMIT);
+
+        // Connection which is dropped without being disconnected is released
+        // as well
+        drop(
+            NetResource::new(session)
+                .unwrap()
+                .with_connection_guard(guard),
+        );
+        drop(accepted);
+        assert_eq!(counter.current(localhost), 0);
+        assert_eq!(counter.total(), 0);
+    }
+
+    #[test]
+    fn total_connection_cap() {
+        let mut accept = NetAccept::<NoiseXx>::bind(&"127.0.0.1:0", xx_keypair())
+            .unwrap()
+            .with_connection_limits(usize::MAX, Some(2));
+
+        let mut clients = vec![];
+        let mut accepted = vec![];
+        for _ in 0..2 {
+            clients.push(TcpStream::connect(accept.local_addr()).unwrap());
+            let Some(ListenerEvent::Accepted(session, guard)) = accept.handle_io(Io::Read) else {
+                panic!("connection is not accepted");
+            };
+            accepted.push((session, guard));
+        }
+        let _client = TcpStream::connect(accept.local_addr()).unwrap();
+        assert!(matches!(
+            accept.handle_io(Io::Read),
+            Some(ListenerEvent::CapReached { current: 2, max: 2 })
+        ));
+        assert_eq!(accept.connection_counter().unwrap().total(), 2);
+    }
+
+    #[test]
+    fn write_interest_only_with_queued_data() {
+        const LEN: usize = 8 * 1024 * 1024;
+
+        let denied = DenyList::from_iter([]);
+        let (mut initiator, mut responder) = noise_pair(&xx_keypair(), denied);
+        let (event, _) = handshake(&mut initiator, &mut responder);
+        assert!(matches!(event, SessionEvent::Established(..)));
+        // Established session is woken up once to write out pending output
+        assert!(initiator.handle_io(Io::Write).is_none());
+        assert!(initiator.interests().is_read_only());
+
+        let mut poller = popol::Poller::new();
+        poller.register(&initiator, initiator.interests());
+        for _ in 0..5 {
+            poller.set_interest(&initiator, initiator.interests());
+            assert_eq!(poller.poll(Some(Duration::from_millis(20))).unwrap(), 0);
+        }
+
+        // Data which don't fit into the socket buffer arm write interest
+        // until they are written out
+        initiator.write_atomic(&vec![1u8; LEN]).unwrap();
+        assert!(initiator.interests().is_read_write());
+        let mut received = 0;
+        while received < LEN {
+            if initiator.interests().write {
+                assert!(initiator.handle_io(Io::Write).is_none());
+            }
+            match responder.handle_io(Io::Read) {
+                Some(SessionEvent::Data(data)) => received += data.len(),
+                None => {}
+                Some(_) => panic!("session is terminated"),
+            }
+        }
+        assert!(!initiator.has_pending_output());
+        assert!(initiator.interests().is_read_only());
+        poller.set_interest(&initiator, initiator.interests());
+        assert_eq!(poller.poll(Some(Duration::from_millis(20))).unwrap(), 0);
+    }
+
+    #[test]
+    fn proxy_protocol() {
+        let mut accept = NetAccept::<NoiseXx<ProxyStream>, ProxyListener<TcpListener>>::bind(
+            &"127.0.0.1:0",
+            xx_keypair(),
+        )
+        .unwrap();
+        let mut accept_proxied = || {
+            let client = TcpStream::connect(accept.local_addr()).unwrap();
+            let Some(ListenerEvent::Accepted(session, _)) = accept.handle_io(Io::Read) else {
+                panic!("connection is not accepted");
+            };
+            (client, NetResource::new(session).unwrap())
+        };
+        let initiate = |client: TcpStream| {
+            client.set_nonblocking(true).unwrap();
+            NetResource::new(NoiseXx::initiate(client, &xx_keypair()).unwrap()).unwrap()
+        };
+
+        // Header arrives across multiple reads
+        let (mut client, mut responder) = accept_proxied();
+        client.write_all(b"PROXY TCP6 2001:db8::1 ").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(responder.handle_io(Io::Read).is_none());
+        assert_eq!(responder.state(), TransportState::Handshake);
+        client.write_all(b"2001:db8::2 56324 443\r\n").unwrap();
+        let mut initiator = initiate(client);
+        let (_, event) = handshake(&mut initiator, &mut responder);
+        let expected = ProxyAddrs {
+            source: "[2001:db8::1]:56324".parse().unwrap(),
+            destination: "[2001:db8::2]:443".parse().unwrap(),
+        };
+        assert!(matches!(event, SessionEvent::Established(_, Some(addrs)) if addrs == expected));
+        assert_eq!(responder.proxy_addrs(), Some(expected));
+        initiator.write_atomic(b"ping").unwrap();
+        let data = loop {
+            if let Some(SessionEvent::Data(data)) = responder.handle_io(Io::Read) {
+                break data;
+            }
+        };
+        assert_eq!(data, b"ping");
+
+        // Connection without the header is terminated
+        let (client, mut responder) = accept_proxied();
+        let mut initiator = initiate(client);
+        assert!(initiator.handle_io(Io::Write).is_none());
+        let err = loop {
+            match responder.handle_io(Io::Read) {
+                Some(SessionEvent::Terminated(err)) => break err,
+                None => std::thread::sleep(Duration::from_millis(10)),
+                Some(_) => panic!("session without PROXY protocol header is accepted"),
+            }
+        };
+        assert_eq!(
+            ProxyHeaderError::from_io_error(&err),
+            Some(ProxyHeaderError::Missing)
+        );
+        assert_eq!(responder.state(), TransportState::Terminated);
+    }
+
+    #[test]
+    fn reconnect_policy() {
+        let policy = ReconnectPolicy {
+            max_retries: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(1),
+            jitter: 0.0,
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(4), Duration::from_millis(800));
+        assert_eq!(policy.delay(5), Duration::from_secs(1));
+        assert_eq!(policy.delay(u32::MAX), Duration::from_secs(1));
+
+        let jittered = ReconnectPolicy {
+            jitter: 0.5,
+            ..policy
+        };
+        for attempt in 1..=10 {
+            let delay = jittered.delay(attempt);
+            assert!(delay <= policy.delay(attempt));
+            assert!(delay >= policy.delay(attempt) / 2);
+        }
+
+        assert!(ReconnectPolicy::is_retriable(
+            &io::ErrorKind::ConnectionRefused.into()
+        ));
+        assert!(ReconnectPolicy::is_retriable(
+            &io::Error::from_raw_os_error(libc::EHOSTUNREACH)
+        ));
+        assert!(!ReconnectPolicy::is_retriable(
+            &io::ErrorKind::InvalidInput.into()
+        ));
+        assert!(!ReconnectPolicy::is_retriable(
+            &io::Error::from_raw_os_error(libc::EMFILE)
+        ));
+    }
+
+    /// Dials Noise_XX session with the address.
+    fn dial(addr: net::SocketAddr) -> io::Result<NoiseXx> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nonblocking(true)?;
+        NoiseXx::initiate(stream, &xx_keypair())
+    }
+
+    /// Accepts Noise_XX session from the listener.
+    fn accept(listener: &TcpListener) -> NetResource<NoiseXx> {
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nonblocking(true).unwrap();
+        NetResource::new(NoiseXx::accept(stream, &xx_keypair()).unwrap()).unwrap()
+    }
+
+    /// Reads from the resource until it reports an event.
+    fn next_event(resource: &mut NetResource<NoiseXx>) -> SessionEvent<NoiseXx> {
+        loop {
+            if let Some(event) = resource.handle_io(Io::Read) {
+                return event;
+            }
+        }
+    }
+
+    #[test]
+    fn reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let policy = ReconnectPolicy {
+            max_retries: 2,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(10),
+            jitter: 0.0,
+        };
+        let session = dial(addr).unwrap();
+        let mut initiator = NetResource::with_state(session, false, TransportState::Handshake)
+            .unwrap()
+            .with_reconnect(policy, move || dial(addr))
+            .unwrap();
+        let id = initiator.id();
+        let mut responder = accept(&listener);
+        let (event, _) = handshake(&mut initiator, &mut responder);
+        assert!(matches!(event, SessionEvent::Established(..)));
+
+        // Dropped connection is re-dialed
+        drop(responder);
+        let event = next_event(&mut initiator);
+        assert!(matches!(
+            event,
+            SessionEvent::Reconnecting { attempt: 1, delay, .. } if delay == policy.base_delay
+        ));
+        assert_eq!(initiator.state(), TransportState::Reconnecting);
+        assert!(initiator.interests().is_none());
+        // Dropped connection is not reported by the poller while waiting
+        let mut poller = popol::Poller::new();
+        poller.register(&initiator, initiator.interests());
+        assert_eq!(poller.poll(Some(Duration::from_millis(20))).unwrap(), 0);
+
+        let deadline = initiator.deadline().unwrap();
+        assert!(initiator
+            .handle_timeout(deadline - Duration::from_millis(1))
+            .is_none());
+        assert_eq!(initiator.state(), TransportState::Reconnecting);
+        assert!(initiator.handle_timeout(deadline).is_none());
+        assert_eq!(initiator.state(), TransportState::Init);
+        assert_eq!(initiator.id(), id);
+
+        let mut responder = accept(&listener);
+        let (event, _) = handshake(&mut initiator, &mut responder);
+        assert!(matches!(event, SessionEvent::Established(..)));
+        initiator.write_atomic(b"ping").unwrap();
+        assert!(matches!(next_event(&mut responder), SessionEvent::Data(data) if data == b"ping"));
+
+        // Re-dial attempts are exhausted once the peer is gone; the counter
+        // of the attempts is reset after the session was established
+        drop(listener);
+        drop(responder);
+        assert!(matches!(
+            next_event(&mut initiator),
+            SessionEvent::Reconnecting { attempt: 1, .. }
+        ));
+        let event = initiator.handle_timeout(initiator.deadline().unwrap());
+        assert!(matches!(
+            event,
+            Some(SessionEvent::Reconnecting { attempt: 2, reason, .. })
+                if reason.kind() == io::ErrorKind::ConnectionRefused
+        ));
+        let event = initiator.handle_timeout(initiator.deadline().unwrap());
+        assert!(matches!(
+            event,
+            Some(SessionEvent::ReconnectExhausted { attempts: 2, reason })
+                if reason.kind() == io::ErrorKind::ConnectionRefused
+        ));
+        assert_eq!(initiator.state(), TransportState::Terminated);
+        assert_eq!(initiator.deadline(), None);
+    }
+
+    #[test]
+    fn reconnect_with_epoll() {
+        /// Drives the handshake of the responder, while the initiator is
+        /// driven by the reactor, until both report the established session.
+        fn handshake(
+            responder: &mut NetResource<NoiseXx>,
+            events: &mpsc::Receiver<SessionEvent<NoiseXx>>,
+        ) {
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_secs(5) {
+                let event = responder
+                    .handle_io(Io::Write)
+                    .or_else(|| responder.handle_io(Io::Read));
+                if let Some(event) = event {
+                    assert!(matches!(event, SessionEvent::Established(..)));
+                    let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+                    assert!(matches!(event, SessionEvent::Established(..)));
+                    return;
+                }
+            }
+            panic!("handshake has not completed");
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let policy = ReconnectPolicy {
+            max_retries: 1,
+            base_delay: Duration::from_millis(10),
+            max_delay: Duration::from_millis(10),
+            jitter: 0.0,
+        };
+        let initiator = NetResource::with_state(dial(addr).unwrap(), false, TransportState::Init)
+            .unwrap()
+            .with_reconnect(policy, move || dial(addr))
+            .unwrap();
+
+        let (sender, events) = mpsc::channel();
+        let reactor = Reactor::new(Events(sender), Epoll::new()).unwrap();
+        reactor.controller().register_transport(initiator).unwrap();
+        let mut responder = accept(&listener);
+        handshake(&mut responder, &events);
+
+        // Re-dialed session is polled under the same descriptor
+        drop(responder);
+        let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(
+            event,
+            SessionEvent::Reconnecting { attempt: 1, .. }
+        ));
+        let mut responder = accept(&listener);
+        handshake(&mut responder, &events);
+        responder.write_atomic(b"ping").unwrap();
+        assert!(matches!(
+            events.recv_timeout(Duration::from_secs(5)).unwrap(),
+            SessionEvent::Data(data) if data == b"ping"
+        ));
+    }
+
+    #[test]
+    fn disconnect_cancels_reconnect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let policy = ReconnectPolicy {
+            base_delay: Duration::from_millis(200),
+            ..default!()
+        };
+        let dials = Arc::new(AtomicUsize::new(0));
+        let counter = dials.clone();
+        let initiator = NetResource::with_state(dial(addr).unwrap(), false, TransportState::Init)
+            .unwrap()
+            .with_handshake_timeout(Duration::from_secs(1))
+            .with_reconnect(policy, move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                dial(addr)
+            })
+            .unwrap();
+        let id = initiator.id();
+        // The peer drops the connection without completing the handshake
+        drop(listener.accept().unwrap());
+
+        let (sender, events) = mpsc::channel();
+        let reactor = Reactor::new(Events(sender), popol::Poller::new()).unwrap();
+        reactor.controller().register_transport(initiator).unwrap();
+        match events.recv_timeout(Duration::from_secs(5)).unwrap() {
+            SessionEvent::Reconnecting { attempt: 1, .. } => {}
+            _ => panic!("re-dial is not scheduled"),
+        }
+        reactor.controller().disconnect(id).unwrap();
+        assert!(events.recv_timeout(Duration::from_millis(500)).is_err());
+        assert_eq!(dials.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn multi_reactor_routes_by_id() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (sender, _events) = mpsc::channel();
+        let reactor = MultiReactor::new(Events(sender), 4, popol::Poller::new).unwrap();
+        let controller = reactor.controller();
+        assert_eq!(controller.shards().len(), 4);
+
+        let mut peers = vec![];
+        let mut ids = vec![];
+        for _ in 0..16 {
+            let transport = NetResource::new(dial(addr).unwrap()).unwrap();
+            ids.push(transport.id());
+            controller.register_transport(transport).unwrap();
+            peers.push(listener.accept().unwrap());
+        }
+        assert_eq!(controller.resource_count(), 16);
+        let least = controller
+            .shards()
+            .iter()
+            .map(|shard| shard.resource_count())
+            .min();
+        assert_eq!(Some(controller.least_loaded().resource_count()), least);
+
+        // Transports are disconnected only by the shards running them
+        for id in ids {
+            controller.disconnect(id).unwrap();
+        }
+        let start = std::time::Instant::now();
+        while controller.resource_count() > 0 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        reactor.shutdown_all().unwrap();
+    }
+
+    #[test]
+    fn drain() {
+        let peer = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (sender, events) = mpsc::channel();
+        let reactor = Reactor::new(Events(sender), popol::Poller::new()).unwrap();
+        let controller = reactor.controller();
+
+        let accept = NetAccept::<NoiseXx>::bind(&"127.0.0.1:0", xx_keypair()).unwrap();
+        let accept_addr = accept.local_addr();
+        controller.register_listener(accept).unwrap();
+        let mut ids = vec![];
+        let mut peers = vec![];
+        for _ in 0..2 {
+            let transport = NetResource::new(dial(peer.local_addr().unwrap()).unwrap()).unwrap();
+            ids.push(transport.id());
+            controller.register_transport(transport).unwrap();
+            peers.push(peer.accept().unwrap());
+        }
+
+        controller
+            .drain(unix_time() + Duration::from_secs(10))
+            .unwrap();
+        let mut draining = 0;
+        while draining < 2 {
+            let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+            if matches!(event, SessionEvent::Draining) {
+                draining += 1;
+            }
+        }
+        // The listener is handed over before the sessions are notified
+        assert!(TcpStream::connect(accept_addr).is_err());
+        assert_eq!(controller.transport_count(), 2);
+
+        // Reactor shuts down once all sessions are finished
+        controller.disconnect(ids[0]).unwrap();
+        let start = std::time::Instant::now();
+        while controller.transport_count() > 1 {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        controller.disconnect(ids[1]).unwrap();
+        reactor.join().unwrap();
+        assert_eq!(controller.transport_count(), 0);
+    }
+
+    #[test]
+    fn drain_deadline() {
+        let peer = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (sender, _events) = mpsc::channel();
+        let reactor = Reactor::new(Events(sender), popol::Poller::new()).unwrap();
+        let controller = reactor.controller();
+        let transport = NetResource::new(dial(peer.local_addr().unwrap()).unwrap()).unwrap();
+        controller.register_transport(transport).unwrap();
+        let (mut stream, _) = peer.accept().unwrap();
+
+        let timeout = Duration::from_millis(100);
+        let start = std::time::Instant::now();
+        controller.drain(unix_time() + timeout).unwrap();
+        reactor.join().unwrap();
+        assert!(start.elapsed() >= timeout - Duration::from_millis(10));
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        // The session left by the deadline is dropped
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        assert!(stream.read_to_end(&mut vec![]).is_ok());
+    }
+}
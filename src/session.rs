@@ -2,19 +2,93 @@ use std::fmt::{Debug, Display};
 use std::io;
 use std::net;
 use std::os::unix::io::{AsRawFd, RawFd};
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use crate::auth::AuthPolicy;
 use crate::connection::Proxy;
+use crate::noise::RekeyThreshold;
+use crate::proxy_protocol::ProxyAddrs;
 use cyphernet::addr::{Addr, HostName, NetAddr};
 
 use crate::resources::SplitIo;
 use crate::NetConnection;
 
+/// Statistics of a session, returned by [`NetSession::stats`].
+///
+/// Counters include only the data of the established session, so the data
+/// exchanged during the handshake are not counted.
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct SessionStats {
+    /// Number of bytes sent to the peer.
+    pub bytes_sent: u64,
+    /// Number of bytes received from the peer.
+    pub bytes_received: u64,
+    /// Number of messages queued for sending to the peer.
+    pub messages_sent: u64,
+    /// Number of reads which have received data from the peer.
+    ///
+    /// The session transfers a byte stream, so a single read may contain
+    /// several messages of the peer or only a part of one.
+    pub reads: u64,
+    /// Time at which the session was established.
+    pub established_at: Instant,
+}
+
+impl SessionStats {
+    /// Constructs statistics of the session established just now.
+    pub fn new() -> Self {
+        SessionStats {
+            bytes_sent: 0,
+            bytes_received: 0,
+            messages_sent: 0,
+            reads: 0,
+            established_at: Instant::now(),
+        }
+    }
+
+    /// Returns time since the session was established.
+    pub fn uptime(&self) -> Duration {
+        self.established_at.elapsed()
+    }
+
+    /// Zeroes the counters, keeping the time the session was established.
+    pub fn reset(&mut self) {
+        *self = SessionStats {
+            established_at: self.established_at,
+            ..SessionStats::new()
+        };
+    }
+}
+
+impl Default for SessionStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// [`Instant`] can't be serialized, so the session uptime is serialized in
+/// place of [`SessionStats::established_at`].
+#[cfg(feature = "serde")]
+impl serde::Serialize for SessionStats {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let mut stats = serializer.serialize_struct("SessionStats", 5)?;
+        stats.serialize_field("bytes_sent", &self.bytes_sent)?;
+        stats.serialize_field("bytes_received", &self.bytes_received)?;
+        stats.serialize_field("messages_sent", &self.messages_sent)?;
+        stats.serialize_field("reads", &self.reads)?;
+        stats.serialize_field("uptime", &self.uptime())?;
+        stats.end()
+    }
+}
+
 pub trait NetSession: io::Read + io::Write + SplitIo + AsRawFd + Send + Sized + Debug {
     type Context: Send;
     type Connection: NetConnection;
     /// A unique identifier of the session. Usually a part of a transition address.
-    type Id: Send;
+    type Id: Send + Debug;
     /// Address used for outgoing connections. May not be known initially for the incoming
     /// connections
     type PeerAddr: Addr + Display;
@@ -55,6 +129,52 @@ pub trait NetSession: io::Read + io::Write + SplitIo + AsRawFd + Send + Sized +
 
     fn set_nonblocking(&mut self, nonblocking: bool) -> io::Result<()>;
 
+    /// Sets amount of data transmitted with the same transport keys after
+    /// which the session rekeys them. Both parties must use the same
+    /// threshold, since the rekeying is not signalled to the peer.
+    ///
+    /// Sessions without transport encryption ignore the threshold.
+    fn set_rekey_threshold(&mut self, _threshold: RekeyThreshold) {}
+
+    /// Sets policy deciding whether the remote peer is accepted once the
+    /// session is established, based on the session id.
+    ///
+    /// Sessions which don't support authorization ignore the policy.
+    fn set_auth_policy(&mut self, _policy: Arc<dyn AuthPolicy<Self::Id>>) {}
+
+    /// Returns statistics of the data exchanged over the session.
+    ///
+    /// Sessions which don't keep statistics return `None`.
+    fn stats(&self) -> Option<&SessionStats> {
+        None
+    }
+
+    /// Detects whether the session keeps data accepted by [`Write::write`],
+    /// which are not yet written to the connection, like a partially sent
+    /// encrypted message. Such data are written out by the next write or
+    /// flush.
+    ///
+    /// Sessions writing directly to the connection return `false`.
+    fn has_pending_output(&self) -> bool {
+        false
+    }
+
+    /// Returns the original addresses of the remote peer which has connected
+    /// through a proxy speaking PROXY protocol, as provided by
+    /// [`NetConnection::proxy_addrs`] of the underlying connection.
+    fn proxy_addrs(&self) -> Option<ProxyAddrs> {
+        None
+    }
+
+    /// Shuts down the writing half of the session, signalling the remote peer
+    /// that no more data will be sent, while still receiving data from it.
+    /// Data which were written but not yet sent are flushed first.
+    ///
+    /// Default implementation fails with [`io::ErrorKind::Unsupported`].
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
     fn disconnect(self) -> io::Result<()>;
 }
 
@@ -127,6 +247,10 @@ impl NetSession for net::TcpStream {
         <Self as NetConnection>::set_nonblocking(self, nonblocking)
     }
 
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.shutdown(net::Shutdown::Write)
+    }
+
     fn disconnect(self) -> io::Result<()> {
         self.shutdown(net::Shutdown::Both)
     }
@@ -200,6 +324,10 @@ impl NetSession for socket2::Socket {
         <Self as NetConnection>::set_nonblocking(self, nonblocking)
     }
 
+    fn shutdown_write(&mut self) -> io::Result<()> {
+        self.shutdown(net::Shutdown::Write)
+    }
+
     fn disconnect(self) -> io::Result<()> {
         self.shutdown(net::Shutdown::Both)
     }
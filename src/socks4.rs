@@ -0,0 +1,318 @@
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs};
+use std::{io, option};
+
+use crate::connection::Proxy;
+use crate::socks5::{Socks5Dst, ToSocks5Dst};
+
+/// SOCKS protocol version implemented by [`Socks4`].
+const SOCKS_VERSION: u8 = 0x04;
+/// Version of the reply sent by the proxy.
+const REPLY_VERSION: u8 = 0x00;
+
+const CMD_CONNECT: u8 = 0x01;
+
+const REPLY_GRANTED: u8 = 90;
+const REPLY_REJECTED: u8 = 91;
+const REPLY_IDENTD_UNREACHABLE: u8 = 92;
+const REPLY_IDENTD_MISMATCH: u8 = 93;
+
+/// Length of the reply to the connection request.
+const REPLY_LEN: usize = 8;
+
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum Socks4Error {
+    #[from]
+    #[from(io::ErrorKind)]
+    #[display(inner)]
+    Io(io::Error),
+
+    /// proxy responded with unsupported reply version {0}
+    InvalidVersion(u8),
+
+    /// proxy rejected the connection request or failed to connect to the
+    /// destination
+    Rejected,
+
+    /// proxy rejected the connection request since it is unable to reach the
+    /// identd service on the client
+    IdentdUnreachable,
+
+    /// proxy rejected the connection request since the identd service reported
+    /// a different user id
+    IdentdMismatch,
+
+    /// proxy responded with unknown reply code {0}
+    UnknownReply(u8),
+
+    /// user id must not contain zero bytes
+    InvalidUserId,
+
+    /// destination address {0} is not supported by the SOCKS4 protocol
+    InvalidDestination(String),
+}
+
+/// Version of the SOCKS4 protocol used by a [`Socks4`] proxy.
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Default)]
+pub enum Socks4Version {
+    /// Original SOCKS4 protocol, supporting IPv4 destinations only.
+    #[default]
+    Socks4,
+
+    /// SOCKS4a extension, which allows the proxy to resolve domain names.
+    Socks4a,
+}
+
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct Socks4 {
+    proxy: SocketAddr,
+    version: Socks4Version,
+    user_id: String,
+}
+
+impl Socks4 {
+    pub fn new(proxy_addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            proxy: proxy_addr
+                .to_socket_addrs()?
+                .next()
+                .ok_or(io::ErrorKind::InvalidInput)?,
+            version: Socks4Version::Socks4,
+            user_id: empty!(),
+        })
+    }
+
+    /// Constructs proxy using SOCKS4a protocol extension.
+    pub fn with_socks4a(proxy_addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(Self {
+            version: Socks4Version::Socks4a,
+            ..Self::new(proxy_addr)?
+        })
+    }
+
+    /// Sets user id sent to the proxy with the connection requests.
+    ///
+    /// # Errors
+    ///
+    /// If the user id contains zero bytes.
+    pub fn with_user_id(mut self, user_id: impl Into<String>) -> Result<Self, Socks4Error> {
+        let user_id = user_id.into();
+        if user_id.contains('\0') {
+            return Err(Socks4Error::InvalidUserId);
+        }
+        self.user_id = user_id;
+        Ok(self)
+    }
+
+    /// Returns version of the protocol used with the proxy.
+    pub fn version(&self) -> Socks4Version {
+        self.version
+    }
+
+    /// Returns user id sent to the proxy.
+    pub fn user_id(&self) -> &str {
+        &self.user_id
+    }
+
+    /// Constructs connection request: `VER | CMD | DSTPORT | DSTIP | USERID |
+    /// 0x00`, followed by `HOSTNAME | 0x00` for the domain names requested
+    /// with SOCKS4a.
+    fn connect_request(&self, dst: &Socks5Dst) -> Result<Vec<u8>, Socks4Error> {
+        let mut request = vec![SOCKS_VERSION, CMD_CONNECT];
+        let hostname = match (dst, self.version) {
+            (Socks5Dst::Ip(SocketAddr::V4(addr)), _) => {
+                request.extend(addr.port().to_be_bytes());
+                request.extend(addr.ip().octets());
+                None
+            }
+            (Socks5Dst::Domain(domain, port), Socks4Version::Socks4a)
+                if !domain.is_empty() && !domain.contains('\0') =>
+            {
+                request.extend(port.to_be_bytes());
+                // Invalid IP address 0.0.0.x with non-zero x tells the proxy
+                // that the hostname follows the user id
+                request.extend([0, 0, 0, 1]);
+                Some(domain)
+            }
+            (Socks5Dst::Ip(addr), _) => {
+                return Err(Socks4Error::InvalidDestination(addr.to_string()))
+            }
+            (Socks5Dst::Domain(domain, port), _) => {
+                return Err(Socks4Error::InvalidDestination(format!("{domain}:{port}")))
+            }
+        };
+        request.extend(self.user_id.as_bytes());
+        request.push(0x00);
+        if let Some(hostname) = hostname {
+            request.extend(hostname.as_bytes());
+            request.push(0x00);
+        }
+        Ok(request)
+    }
+
+    /// Checks the reply to the connection request; the address the proxy has
+    /// bound to is not used.
+    fn check_reply(reply: &[u8; REPLY_LEN]) -> Result<(), Socks4Error> {
+        if reply[0] != REPLY_VERSION {
+            return Err(Socks4Error::InvalidVersion(reply[0]));
+        }
+        match reply[1] {
+            REPLY_GRANTED => Ok(()),
+            REPLY_REJECTED => Err(Socks4Error::Rejected),
+            REPLY_IDENTD_UNREACHABLE => Err(Socks4Error::IdentdUnreachable),
+            REPLY_IDENTD_MISMATCH => Err(Socks4Error::IdentdMismatch),
+            code => Err(Socks4Error::UnknownReply(code)),
+        }
+    }
+}
+
+impl ToSocketAddrs for Socks4 {
+    type Iter = option::IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> io::Result<Self::Iter> {
+        Ok(Some(self.proxy).into_iter())
+    }
+}
+
+impl Proxy for Socks4 {
+    type Error = Socks4Error;
+
+    fn connect_blocking<A: ToSocks5Dst>(&self, addr: A) -> Result<TcpStream, Self::Error> {
+        let dst = addr
+            .to_socks5_dst()
+            .map_err(|err| Socks4Error::InvalidDestination(err.to_string()))?;
+        let mut stream = TcpStream::connect(self.proxy)?;
+        self.tunnel(&mut stream, dst)?;
+        Ok(stream)
+    }
+
+    /// Performs the proxy handshake in blocking mode, returning the stream
+    /// switched to non-blocking mode once the connection is established.
+    #[cfg(feature = "socket2")]
+    fn connect_nonblocking<A: ToSocks5Dst>(&self, addr: A) -> Result<TcpStream, Self::Error> {
+        let stream = self.connect_blocking(addr)?;
+        stream.set_nonblocking(true)?;
+        Ok(stream)
+    }
+
+    fn tunnel(&self, stream: &mut TcpStream, dst: Socks5Dst) -> Result<(), Self::Error> {
+        let request = self.connect_request(&dst)?;
+        stream.write_all(&request)?;
+        let mut reply = [0u8; REPLY_LEN];
+        stream.read_exact(&mut reply)?;
+        Self::check_reply(&reply)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use super::*;
+
+    #[test]
+    fn ipv4_request() {
+        let socks4 = Socks4::new("127.0.0.1:1080")
+            .unwrap()
+            .with_user_id("alice")
+            .unwrap();
+        let dst = "10.0.0.1:8333".to_owned().to_socks5_dst().unwrap();
+        assert_eq!(
+            socks4.connect_request(&dst).unwrap(),
+            b"\x04\x01\x20\x8d\x0a\x00\x00\x01alice\x00"
+        );
+
+        // SOCKS4a sends IPv4 destinations in the same way
+        let socks4a = Socks4::with_socks4a("127.0.0.1:1080").unwrap();
+        assert_eq!(
+            socks4a.connect_request(&dst).unwrap(),
+            b"\x04\x01\x20\x8d\x0a\x00\x00\x01\x00"
+        );
+    }
+
+    #[test]
+    fn hostname_request() {
+        let socks4a = Socks4::with_socks4a("127.0.0.1:1080")
+            .unwrap()
+            .with_user_id("alice")
+            .unwrap();
+        let dst = Socks5Dst::Domain(s!("example.com"), 80);
+        assert_eq!(
+            socks4a.connect_request(&dst).unwrap(),
+            b"\x04\x01\x00\x50\x00\x00\x00\x01alice\x00example.com\x00"
+        );
+
+        let socks4 = Socks4::new("127.0.0.1:1080").unwrap();
+        assert!(matches!(
+            socks4.connect_request(&dst),
+            Err(Socks4Error::InvalidDestination(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_requests() {
+        let socks4a = Socks4::with_socks4a("127.0.0.1:1080").unwrap();
+        let dst = "[::1]:80".to_owned().to_socks5_dst().unwrap();
+        assert!(matches!(
+            socks4a.connect_request(&dst),
+            Err(Socks4Error::InvalidDestination(_))
+        ));
+        assert!(matches!(
+            socks4a.with_user_id("ali\0ce"),
+            Err(Socks4Error::InvalidUserId)
+        ));
+    }
+
+    #[test]
+    fn reply_codes() {
+        let reply = |code| [REPLY_VERSION, code, 0, 80, 127, 0, 0, 1];
+        assert!(Socks4::check_reply(&reply(REPLY_GRANTED)).is_ok());
+        assert!(matches!(
+            Socks4::check_reply(&reply(REPLY_REJECTED)),
+            Err(Socks4Error::Rejected)
+        ));
+        assert!(matches!(
+            Socks4::check_reply(&reply(REPLY_IDENTD_UNREACHABLE)),
+            Err(Socks4Error::IdentdUnreachable)
+        ));
+        assert!(matches!(
+            Socks4::check_reply(&reply(REPLY_IDENTD_MISMATCH)),
+            Err(Socks4Error::IdentdMismatch)
+        ));
+        assert!(matches!(
+            Socks4::check_reply(&reply(0x01)),
+            Err(Socks4Error::UnknownReply(0x01))
+        ));
+        assert!(matches!(
+            Socks4::check_reply(&[SOCKS_VERSION, REPLY_GRANTED, 0, 80, 127, 0, 0, 1]),
+            Err(Socks4Error::InvalidVersion(SOCKS_VERSION))
+        ));
+    }
+
+    #[test]
+    fn connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // Request without user id and hostname has fixed length
+            let mut request = [0u8; 9];
+            stream.read_exact(&mut request).unwrap();
+            stream
+                .write_all(&[REPLY_VERSION, REPLY_GRANTED, 0, 0, 0, 0, 0, 0])
+                .unwrap();
+            request
+        });
+
+        let socks4 = Socks4::new(addr).unwrap();
+        socks4
+            .connect_blocking("192.168.1.1:443".to_owned())
+            .unwrap();
+        assert_eq!(
+            handle.join().unwrap(),
+            [SOCKS_VERSION, CMD_CONNECT, 1, 187, 192, 168, 1, 1, 0]
+        );
+    }
+}
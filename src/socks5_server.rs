@@ -0,0 +1,710 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Debug, Formatter};
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use reactor::poller::IoType;
+use reactor::{Error, Io, Resource, WriteAtomic};
+
+use crate::socks5::{Socks5Auth, Socks5Dst, Socks5Error, Socks5ServerHandshake};
+use crate::tunnel::Forwarder;
+use crate::{IpConnectionGuard, ListenerEvent, NetAccept};
+
+/// Listener accepting SOCKS5 clients.
+pub type Socks5Listener = NetAccept<TcpStream>;
+pub type Socks5Action = reactor::Action<Socks5Listener, Socks5Conn>;
+
+#[derive(Debug)]
+pub enum Socks5Event {
+    /// Client has requested connection to the destination. The connection
+    /// must be handed over to the [`Socks5Server`], which dials the
+    /// destination.
+    Requested(Socks5Dst),
+    /// Dialing the destination has finished, either successfully or not. The
+    /// connection must be handed over to the [`Socks5Server`], which starts
+    /// relaying the data or reports the failure to the client.
+    Dialed,
+    /// Both the client and the destination have closed their connections,
+    /// and all the data were relayed.
+    Closed,
+    Failed(Socks5Error),
+}
+
+enum ConnState {
+    /// Performing handshake with the client.
+    Handshake {
+        client: TcpStream,
+        handshake: Socks5ServerHandshake,
+        output: Vec<u8>,
+        guard: Option<IpConnectionGuard>,
+    },
+    /// Connecting to the destination requested by the client.
+    Dialing {
+        remote: TcpStream,
+        client: TcpStream,
+        handshake: Socks5ServerHandshake,
+        guard: Option<IpConnectionGuard>,
+    },
+    /// Relaying data between the client and the destination; the relay is
+    /// shared with the resource of the other connection.
+    Relay { fd: RawFd, relay: Arc<Mutex<Relay>> },
+}
+
+/// Transport resource of a [`Socks5Server`]: connection of a client or, once
+/// the client has requested connection, of the destination.
+pub struct Socks5Conn(ConnState);
+
+impl Debug for Socks5Conn {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let state = match self.0 {
+            ConnState::Handshake { .. } => "handshake",
+            ConnState::Dialing { .. } => "dialing",
+            ConnState::Relay { .. } => "relay",
+        };
+        f.debug_struct("Socks5Conn")
+            .field("fd", &self.as_raw_fd())
+            .field("state", &state)
+            .finish()
+    }
+}
+
+impl AsRawFd for Socks5Conn {
+    fn as_raw_fd(&self) -> RawFd {
+        match &self.0 {
+            ConnState::Handshake { client, .. } => client.as_raw_fd(),
+            ConnState::Dialing { remote, .. } => remote.as_raw_fd(),
+            ConnState::Relay { fd, .. } => *fd,
+        }
+    }
+}
+
+impl Socks5Conn {
+    /// Constructs resource performing the handshake with the accepted client.
+    /// The connection guard reported by the [`Socks5Listener`] is kept until
+    /// the client connection is closed.
+    pub fn accept(client: TcpStream, auth: Socks5Auth, guard: Option<IpConnectionGuard>) -> Self {
+        Socks5Conn(ConnState::Handshake {
+            client,
+            handshake: Socks5ServerHandshake::new(auth),
+            output: vec![],
+            guard,
+        })
+    }
+
+    /// Starts connecting to the destination requested by the client, returning
+    /// resource of the outbound connection. Domain names are resolved in
+    /// blocking mode.
+    ///
+    /// # Errors
+    ///
+    /// If the destination can't be resolved or dialed; the failure is
+    /// reported to the client.
+    pub fn dial(self) -> Result<Self, Socks5Error> {
+        let (mut client, mut handshake, guard) = match self.0 {
+            ConnState::Handshake {
+                client,
+                handshake,
+                guard,
+                ..
+            } if handshake.is_requested() => (client, handshake, guard),
+            _ => return Err(io::Error::from(io::ErrorKind::NotConnected).into()),
+        };
+        let dst = handshake
+            .destination()
+            .cloned()
+            .expect("requested handshake has destination");
+        match resolve(dst).and_then(connect_nonblocking) {
+            Ok(remote) => Ok(Socks5Conn(ConnState::Dialing {
+                remote,
+                client,
+                handshake,
+                guard,
+            })),
+            Err(err) => {
+                // The reply is small enough to fit the socket buffer of a
+                // client waiting for it
+                let _ = client.write_all(&handshake.reply(Err(&err)));
+                Err(err.into())
+            }
+        }
+    }
+
+    /// Completes dialing the destination, returning resources of the client
+    /// and the destination connections which relay data between each other.
+    ///
+    /// # Errors
+    ///
+    /// If the destination has refused the connection; the failure is reported
+    /// to the client.
+    pub fn into_relay(self) -> Result<(Self, Self), Socks5Error> {
+        let (remote, mut client, mut handshake, guard) = match self.0 {
+            ConnState::Dialing {
+                remote,
+                client,
+                handshake,
+                guard,
+            } => (remote, client, handshake, guard),
+            _ => return Err(io::Error::from(io::ErrorKind::NotConnected).into()),
+        };
+        let bound = match remote.take_error() {
+            Ok(None) => remote.peer_addr().and_then(|_| remote.local_addr()),
+            Ok(Some(err)) | Err(err) => Err(err),
+        };
+        let bound = match bound {
+            Ok(bound) => bound,
+            Err(err) => {
+                let _ = client.write_all(&handshake.reply(Err(&err)));
+                return Err(err.into());
+            }
+        };
+        let relay = Relay {
+            reply: handshake.reply(Ok(bound)),
+            upstream: Forwarder::with_splice(client.as_raw_fd(), remote.as_raw_fd())?,
+            downstream: Forwarder::with_splice(remote.as_raw_fd(), client.as_raw_fd())?,
+            client,
+            remote,
+            _guard: guard,
+        };
+        let (client_fd, remote_fd) = (relay.client.as_raw_fd(), relay.remote.as_raw_fd());
+        let relay = Arc::new(Mutex::new(relay));
+        Ok((
+            Socks5Conn(ConnState::Relay {
+                fd: client_fd,
+                relay: relay.clone(),
+            }),
+            Socks5Conn(ConnState::Relay {
+                fd: remote_fd,
+                relay,
+            }),
+        ))
+    }
+
+    /// Detects whether the client has requested connection to the destination.
+    pub fn is_requested(&self) -> bool {
+        matches!(&self.0, ConnState::Handshake { handshake, .. } if handshake.is_requested())
+    }
+
+    /// Detects whether the destination is being dialed.
+    pub fn is_dialing(&self) -> bool {
+        matches!(self.0, ConnState::Dialing { .. })
+    }
+
+    /// Relays the data which are available without blocking, returning whether
+    /// the relay has finished. Used for the connection which has hung up and
+    /// can't be polled anymore.
+    fn drain(&self) -> io::Result<bool> {
+        match &self.0 {
+            ConnState::Relay { relay, .. } => relay.lock().expect("relay lock is poisoned").pump(),
+            _ => Ok(true),
+        }
+    }
+}
+
+impl Resource for Socks5Conn {
+    type Id = RawFd;
+    type Event = Socks5Event;
+
+    fn id(&self) -> Self::Id {
+        self.as_raw_fd()
+    }
+
+    fn interests(&self) -> IoType {
+        match &self.0 {
+            ConnState::Handshake {
+                handshake, output, ..
+            } => IoType {
+                read: handshake.next_read_len() > 0,
+                write: !output.is_empty(),
+            },
+            ConnState::Dialing { .. } => IoType::write_only(),
+            ConnState::Relay { fd, relay } => {
+                relay.lock().expect("relay lock is poisoned").interests(*fd)
+            }
+        }
+    }
+
+    fn handle_io(&mut self, io: Io) -> Option<Self::Event> {
+        match &mut self.0 {
+            ConnState::Handshake {
+                client,
+                handshake,
+                output,
+                ..
+            } => {
+                // Request is reported once, after the call which has either
+                // received it or flushed the preceding output
+                let mut progress = false;
+                if io == Io::Read {
+                    let mut buf = vec![0u8; handshake.next_read_len()];
+                    match client.read(&mut buf) {
+                        Ok(0) => {
+                            return Some(Socks5Event::Failed(
+                                io::Error::from(io::ErrorKind::ConnectionReset).into(),
+                            ))
+                        }
+                        Ok(len) => match handshake.advance(&buf[..len]) {
+                            Ok(data) => {
+                                output.extend(data);
+                                progress = true;
+                            }
+                            Err(err) => {
+                                let _ = client.write_all(handshake.failure_reply());
+                                return Some(Socks5Event::Failed(err));
+                            }
+                        },
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                        Err(err) => return Some(Socks5Event::Failed(err.into())),
+                    }
+                }
+                if !output.is_empty() {
+                    match client.write(output) {
+                        Ok(len) => {
+                            output.drain(..len);
+                            progress = true;
+                        }
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                        Err(err) => return Some(Socks5Event::Failed(err.into())),
+                    }
+                }
+                match handshake.destination() {
+                    Some(dst) if progress && handshake.is_requested() && output.is_empty() => {
+                        Some(Socks5Event::Requested(dst.clone()))
+                    }
+                    _ => None,
+                }
+            }
+            ConnState::Dialing { .. } if io == Io::Write => Some(Socks5Event::Dialed),
+            ConnState::Dialing { .. } => None,
+            ConnState::Relay { relay, .. } => {
+                match relay.lock().expect("relay lock is poisoned").pump() {
+                    Ok(true) => Some(Socks5Event::Closed),
+                    Ok(false) => None,
+                    Err(err) => Some(Socks5Event::Failed(err.into())),
+                }
+            }
+        }
+    }
+
+    fn disconnect(self) -> io::Result<()> {
+        // Sockets are closed once both resources sharing a relay are dropped
+        Ok(())
+    }
+}
+
+impl Write for Socks5Conn {
+    fn write(&mut self, _buf: &[u8]) -> io::Result<usize> {
+        Err(io::ErrorKind::InvalidInput.into())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Err(io::ErrorKind::InvalidInput.into())
+    }
+}
+
+/// Data are relayed by the server itself and can't be sent to the
+/// connections.
+impl WriteAtomic for Socks5Conn {
+    fn is_ready_to_write(&self) -> bool {
+        false
+    }
+
+    fn write_or_buffer(&mut self, _: &[u8]) -> io::Result<()> {
+        Err(io::ErrorKind::InvalidInput.into())
+    }
+}
+
+/// Pair of the client and the destination connections with the
+/// [`Forwarder`]s moving data between them.
+struct Relay {
+    client: TcpStream,
+    remote: TcpStream,
+    /// Reply to the connection request which is not yet sent to the client.
+    reply: Vec<u8>,
+    /// Forwarder from the client to the destination.
+    upstream: Forwarder,
+    /// Forwarder from the destination to the client.
+    downstream: Forwarder,
+    /// Keeps the client connection counted by the limits of the listener.
+    _guard: Option<IpConnectionGuard>,
+}
+
+impl Relay {
+    fn interests(&self, fd: RawFd) -> IoType {
+        if fd == self.client.as_raw_fd() {
+            IoType {
+                read: self.upstream.wants_read(),
+                write: !self.reply.is_empty() || self.downstream.has_pending(),
+            }
+        } else {
+            IoType {
+                read: self.downstream.wants_read() && self.reply.is_empty(),
+                write: self.upstream.has_pending(),
+            }
+        }
+    }
+
+    /// Moves as much data as possible without blocking, returning whether
+    /// both directions have finished.
+    fn pump(&mut self) -> io::Result<bool> {
+        while !self.reply.is_empty() {
+            match self.client.write(&self.reply) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(len) => {
+                    self.reply.drain(..len);
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(false),
+                Err(err) => return Err(err),
+            }
+        }
+        for (forwarder, outbound) in [
+            (&mut self.upstream, &self.remote),
+            (&mut self.downstream, &self.client),
+        ] {
+            let was_finished = forwarder.is_finished();
+            forwarder.forward()?;
+            if !was_finished && forwarder.is_finished() {
+                // Propagating end of stream; the peer may have already
+                // closed the connection
+                let _ = outbound.shutdown(Shutdown::Write);
+            }
+        }
+        Ok(self.upstream.is_finished() && self.downstream.is_finished())
+    }
+}
+
+/// Resolves the destination, taking the first of its addresses.
+fn resolve(dst: Socks5Dst) -> io::Result<SocketAddr> {
+    match dst {
+        Socks5Dst::Ip(addr) => Ok(addr),
+        // Failure to resolve the name is reported as unreachable host
+        Socks5Dst::Domain(domain, port) => (domain.as_str(), port)
+            .to_socket_addrs()
+            .map_err(|err| io::Error::new(io::ErrorKind::AddrNotAvailable, err))?
+            .next()
+            .ok_or_else(|| io::ErrorKind::AddrNotAvailable.into()),
+    }
+}
+
+fn connect_nonblocking(addr: SocketAddr) -> io::Result<TcpStream> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::STREAM,
+        None,
+    )?;
+    socket.set_nonblocking(true)?;
+    match socket.connect(&addr.into()) {
+        Ok(()) => {}
+        Err(err) if err.raw_os_error() == Some(libc::EINPROGRESS) => {}
+        Err(err) => return Err(err),
+    }
+    Ok(socket.into())
+}
+
+/// SOCKS5 proxy server, which is run as a [`reactor::Handler`].
+///
+/// The server accepts clients on a [`Socks5Listener`] and, once a client
+/// requests connection, dials the destination through the reactor. Data
+/// between the client and the destination are moved with [`Forwarder`]s,
+/// which splice them in kernel space where supported. Only the CONNECT
+/// command is supported.
+pub struct Socks5Server {
+    auth: Socks5Auth,
+    /// Peers of the connections relaying data to each other.
+    peers: HashMap<RawFd, RawFd>,
+    actions: VecDeque<Socks5Action>,
+}
+
+impl Socks5Server {
+    /// Constructs server accepting clients on the listener, which requires
+    /// clients to authenticate with the username and password unless `auth`
+    /// is [`Socks5Auth::NoAuth`].
+    pub fn with_listener(listener: Socks5Listener, auth: Socks5Auth) -> Self {
+        Self {
+            auth,
+            peers: empty!(),
+            actions: VecDeque::from([Socks5Action::RegisterListener(listener)]),
+        }
+    }
+
+    /// Returns number of the connections relaying data.
+    pub fn relay_count(&self) -> usize {
+        self.peers.len() / 2
+    }
+
+    /// Unregisters the connection together with its relay peer.
+    fn close(&mut self, id: RawFd) {
+        if let Some(peer) = self.peers.remove(&id) {
+            self.peers.remove(&peer);
+            self.actions
+                .push_back(Socks5Action::UnregisterTransport(peer));
+        }
+        self.actions
+            .push_back(Socks5Action::UnregisterTransport(id));
+    }
+
+    fn register(&mut self, conn: Socks5Conn) {
+        self.actions
+            .push_back(Socks5Action::RegisterTransport(conn));
+    }
+}
+
+impl reactor::Handler for Socks5Server {
+    type Listener = Socks5Listener;
+    type Transport = Socks5Conn;
+    type Command = ();
+
+    fn tick(&mut self, _time: Duration) {}
+
+    fn handle_wakeup(&mut self) {}
+
+    #[allow(unused_variables)]
+    fn handle_listener_event(
+        &mut self,
+        id: <Self::Listener as Resource>::Id,
+        event: <Self::Listener as Resource>::Event,
+        _time: Duration,
+    ) {
+        match event {
+            ListenerEvent::Accepted(client, guard) => {
+                #[cfg(feature = "log")]
+                log::debug!(target: "socks5", "Accepted SOCKS5 client {:?} on {id}", client.peer_addr());
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "socks5", client = ?client.peer_addr(), %id, "Accepted SOCKS5 client");
+
+                self.register(Socks5Conn::accept(client, self.auth.clone(), guard));
+            }
+            ListenerEvent::RateLimited(_)
+            | ListenerEvent::CapReached { .. }
+            | ListenerEvent::PeerLimitReached { .. } => {}
+            ListenerEvent::Paused {
+                err: _err,
+                backoff: _backoff,
+            } => {
+                #[cfg(feature = "log")]
+                log::warn!(target: "socks5", "Listener {id} is paused for {_backoff:?}: {_err}");
+                #[cfg(feature = "tracing")]
+                tracing::warn!(target: "socks5", %id, backoff = ?_backoff, err = %_err, "Listener is paused");
+            }
+            ListenerEvent::Resumed => {
+                #[cfg(feature = "log")]
+                log::info!(target: "socks5", "Listener {id} is resumed");
+                #[cfg(feature = "tracing")]
+                tracing::info!(target: "socks5", %id, "Listener is resumed");
+            }
+            ListenerEvent::Failure(_err) => {
+                #[cfg(feature = "log")]
+                log::error!(target: "socks5", "Error on listener {id}: {_err}");
+                #[cfg(feature = "tracing")]
+                tracing::error!(target: "socks5", %id, err = %_err, "Error on listener");
+            }
+        }
+    }
+
+    fn handle_transport_event(
+        &mut self,
+        id: <Self::Transport as Resource>::Id,
+        event: <Self::Transport as Resource>::Event,
+        _time: Duration,
+    ) {
+        match event {
+            // The connection is dialed or relayed once it is handed over
+            Socks5Event::Requested(_) | Socks5Event::Dialed => self
+                .actions
+                .push_back(Socks5Action::UnregisterTransport(id)),
+            Socks5Event::Closed => {
+                #[cfg(feature = "log")]
+                log::debug!(target: "socks5", "Relay of {id} has finished");
+                #[cfg(feature = "tracing")]
+                tracing::debug!(target: "socks5", id, "Relay has finished");
+
+                self.close(id);
+            }
+            Socks5Event::Failed(_err) => {
+                #[cfg(feature = "log")]
+                log::warn!(target: "socks5", "SOCKS5 connection {id} has failed: {_err}");
+                #[cfg(feature = "tracing")]
+                tracing::warn!(target: "socks5", id, err = %_err, "SOCKS5 connection has failed");
+
+                self.close(id);
+            }
+        }
+    }
+
+    fn handle_command(&mut self, _cmd: Self::Command) {}
+
+    fn handle_error(&mut self, err: Error<Self::Listener, Self::Transport>) {
+        match err {
+            Error::TransportDisconnect(id, conn, _) => match self.peers.remove(&id) {
+                // Connection which has hung up may still have data to be
+                // relayed to its peer
+                Some(peer) => {
+                    self.peers.remove(&peer);
+                    if !matches!(conn.drain(), Ok(false)) {
+                        self.actions
+                            .push_back(Socks5Action::UnregisterTransport(peer));
+                    }
+                }
+                // Connection to the destination is refused
+                None if conn.is_dialing() => self.handover_transport(conn),
+                None => {}
+            },
+            Error::WriteFailure(id, _) | Error::TransportPollError(id, _) => self.close(id),
+            #[allow(unused_variables)]
+            Error::ListenerDisconnect(id, _, _) | Error::ListenerPollError(id, _) => {
+                #[cfg(feature = "log")]
+                log::error!(target: "socks5", "SOCKS5 listener {id} has failed");
+                #[cfg(feature = "tracing")]
+                tracing::error!(target: "socks5", %id, "SOCKS5 listener has failed");
+            }
+            Error::ListenerUnknown(_)
+            | Error::TransportUnknown(_)
+            | Error::WriteLogicError(..)
+            | Error::Poll(_) => {
+                #[cfg(feature = "log")]
+                log::error!(target: "socks5", "Error: {err}");
+                #[cfg(feature = "tracing")]
+                tracing::error!(target: "socks5", %err, "Reactor error");
+            }
+        }
+    }
+
+    fn handover_listener(&mut self, _listener: Self::Listener) {}
+
+    fn handover_transport(&mut self, conn: Self::Transport) {
+        let res = if conn.is_requested() {
+            conn.dial().map(|conn| self.register(conn))
+        } else if conn.is_dialing() {
+            conn.into_relay().map(|(client, remote)| {
+                self.peers.insert(client.id(), remote.id());
+                self.peers.insert(remote.id(), client.id());
+                self.register(client);
+                self.register(remote);
+            })
+        } else {
+            // Connections are dropped once they are closed
+            Ok(())
+        };
+        if let Err(_err) = res {
+            #[cfg(feature = "log")]
+            log::warn!(target: "socks5", "Unable to connect to the destination: {_err}");
+            #[cfg(feature = "tracing")]
+            tracing::warn!(target: "socks5", err = %_err, "Unable to connect to the destination");
+        }
+    }
+}
+
+impl Iterator for Socks5Server {
+    type Item = Socks5Action;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.actions.pop_front()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+
+    use reactor::poller::popol;
+    use reactor::Reactor;
+
+    use super::*;
+    use crate::socks5::Socks5;
+    use crate::Proxy;
+
+    /// Amount of data relayed by the tests.
+    const LEN: usize = 1024 * 1024;
+
+    fn server(auth: Socks5Auth) -> (Reactor<Socks5Server>, SocketAddr) {
+        let listener = Socks5Listener::bind(&"127.0.0.1:0", ()).unwrap();
+        let addr = listener.local_addr();
+        let server = Socks5Server::with_listener(listener, auth);
+        (Reactor::new(server, popol::Poller::new()).unwrap(), addr)
+    }
+
+    /// Runs server echoing data of a single connection until it is closed.
+    fn echo() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = stream.try_clone().unwrap();
+            io::copy(&mut reader, &mut stream).unwrap();
+        });
+        addr
+    }
+
+    fn credentials(password: &str) -> Socks5Auth {
+        Socks5Auth::UsernamePassword {
+            username: s!("user"),
+            password: password.to_owned(),
+        }
+    }
+
+    #[test]
+    fn relay() {
+        let (_reactor, addr) = server(Socks5Auth::NoAuth);
+        let mut stream = Socks5::new(addr).unwrap().connect_blocking(echo()).unwrap();
+
+        let data = (0..LEN).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let mut writer = stream.try_clone().unwrap();
+        let sent = data.clone();
+        let handle = thread::spawn(move || {
+            writer.write_all(&sent).unwrap();
+            writer.shutdown(Shutdown::Write).unwrap();
+        });
+
+        // Reading until the end of stream, which is propagated through the
+        // relay in both directions
+        let mut received = vec![];
+        stream.read_to_end(&mut received).unwrap();
+        handle.join().unwrap();
+        assert_eq!(received, data);
+    }
+
+    #[test]
+    fn username_password_auth() {
+        let (_reactor, addr) = server(credentials("secret"));
+        let mut stream = Socks5::new(addr)
+            .unwrap()
+            .with_auth(credentials("secret"))
+            .connect_blocking(echo())
+            .unwrap();
+        stream.write_all(b"ping").unwrap();
+        let mut pong = [0u8; 4];
+        stream.read_exact(&mut pong).unwrap();
+        assert_eq!(&pong, b"ping");
+
+        // Destination is not dialed if the client fails to authenticate
+        let dst = s!("127.0.0.1:9");
+        let socks5 = Socks5::new(addr).unwrap();
+        assert!(matches!(
+            socks5
+                .clone()
+                .with_auth(credentials("wrong"))
+                .connect_blocking(dst.clone()),
+            Err(Socks5Error::AuthFailed)
+        ));
+        assert!(matches!(
+            socks5.connect_blocking(dst),
+            Err(Socks5Error::NoAcceptableAuth)
+        ));
+    }
+
+    #[test]
+    fn connection_refused() {
+        let (_reactor, addr) = server(Socks5Auth::NoAuth);
+        let closed = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        assert!(matches!(
+            Socks5::new(addr).unwrap().connect_blocking(closed),
+            Err(Socks5Error::ConnectFailed(0x05))
+        ));
+    }
+}
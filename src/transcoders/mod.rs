@@ -1,5 +1,9 @@
 mod noise;
+#[cfg(feature = "zstd")]
+mod zstd;
 
+#[cfg(feature = "zstd")]
+pub use self::zstd::{ZstdError, ZstdTranscoder};
 use crate::resources::SplitIo;
 
 pub trait Encrypt {
@@ -16,3 +20,59 @@ pub trait Transcode: SplitIo + Encrypt + Decrypt {
     type Encryptor: Encrypt;
     type Decryptor: Decrypt;
 }
+
+/// Errors decoding data with a [`Chain`] of transcoders.
+#[derive(Debug, Display, Error)]
+#[display(doc_comments)]
+pub enum ChainError<I: std::error::Error, O: std::error::Error> {
+    /// inner transcoder failure: {0}
+    Inner(I),
+
+    /// outer transcoder failure: {0}
+    Outer(O),
+}
+
+/// Stack of two transcoders, where the data are encoded first by the `inner`
+/// and then by the `outer` one, and decoded in the reverse order.
+///
+/// Compression must be placed under encryption, i.e. be the inner transcoder:
+/// `Chain::new(ZstdTranscoder::default(), noise)` compresses the data and then
+/// encrypts them, since encrypted data are not compressible.
+pub struct Chain<I: Encrypt + Decrypt, O: Encrypt + Decrypt> {
+    inner: I,
+    outer: O,
+}
+
+impl<I: Encrypt + Decrypt, O: Encrypt + Decrypt> Chain<I, O> {
+    pub fn new(inner: I, outer: O) -> Self {
+        Chain { inner, outer }
+    }
+
+    pub fn inner(&self) -> &I {
+        &self.inner
+    }
+
+    pub fn outer(&self) -> &O {
+        &self.outer
+    }
+
+    pub fn into_inner(self) -> (I, O) {
+        (self.inner, self.outer)
+    }
+}
+
+impl<I: Encrypt + Decrypt, O: Encrypt + Decrypt> Encrypt for Chain<I, O> {
+    fn encrypt(&mut self, data: &[u8]) -> Vec<u8> {
+        let data = self.inner.encrypt(data);
+        self.outer.encrypt(&data)
+    }
+}
+
+impl<I: Encrypt + Decrypt, O: Encrypt + Decrypt> Decrypt for Chain<I, O> {
+    type Error = ChainError<I::Error, O::Error>;
+
+    fn decrypt(&mut self, data: &[u8]) -> Result<Vec<u8>, Self::Error> {
+        let data = self.outer.decrypt(data).map_err(ChainError::Outer)?;
+        self.inner.decrypt(&data).map_err(ChainError::Inner)
+    }
+}
@@ -0,0 +1,323 @@
+use std::io;
+
+use zstd::stream::raw::{Decoder, Encoder, InBuffer, Operation, OutBuffer};
+
+use crate::transcoders::{Decrypt, Encrypt};
+use crate::DEFAULT_MAX_FRAME_BYTES;
+
+/// Flag of a frame sent by [`ZstdTranscoder`] without compression.
+const FLAG_STORED: u8 = 0;
+/// Flag of a frame sent by [`ZstdTranscoder`] compressed with zstd.
+const FLAG_ZSTD: u8 = 1;
+/// Size of the chunks in which the frames are decompressed.
+const CHUNK_LEN: usize = 16 * 1024;
+
+/// Errors decompressing frames with [`ZstdTranscoder`].
+#[derive(Debug, Display, Error, From)]
+#[display(doc_comments)]
+pub enum ZstdError {
+    /// empty compressed frame
+    EmptyFrame,
+
+    /// unknown compression flag {0:#04x}
+    UnknownFlag(u8),
+
+    /// decompressed frame exceeds the limit of {0} bytes
+    FrameTooLarge(usize),
+
+    /// compressed frame is incomplete
+    IncompleteFrame,
+
+    /// compressed frame is followed by {0} bytes of unexpected data
+    TrailingData(usize),
+
+    /// corrupted compressed frame: {0}
+    #[from]
+    Corrupted(io::Error),
+}
+
+impl From<ZstdError> for io::Error {
+    fn from(err: ZstdError) -> Self {
+        match err {
+            ZstdError::Corrupted(err) => err,
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+/// Transcoder compressing each frame with zstd, which is intended to be
+/// chained under an encrypting transcoder (see [`super::Chain`]).
+///
+/// Each frame is prefixed with a flag specifying whether it is compressed.
+/// Frames which do not shrink after compression are stored as is, so the
+/// overhead never exceeds a single byte. Compression and decompression
+/// contexts are reused between the frames; each frame is a separate zstd
+/// frame, so the compression gains across the frames come from the dictionary
+/// (see [`ZstdTranscoder::with_dictionary`]).
+///
+/// Decompression is performed in chunks and is aborted once the frame exceeds
+/// the maximal frame size, protecting from the decompression bombs.
+pub struct ZstdTranscoder {
+    encoder: Encoder<'static>,
+    decoder: Decoder<'static>,
+    level: i32,
+    max_frame_bytes: usize,
+}
+
+impl Default for ZstdTranscoder {
+    fn default() -> Self {
+        Self::new(zstd::DEFAULT_COMPRESSION_LEVEL).expect("default zstd compression level")
+    }
+}
+
+impl ZstdTranscoder {
+    /// Constructs transcoder compressing with the given level, which must be
+    /// within the range supported by zstd (see [`zstd::compression_level_range`]).
+    pub fn new(level: i32) -> io::Result<Self> {
+        Self::with_dictionary(level, &[])
+    }
+
+    /// Constructs transcoder compressing with the given level and dictionary.
+    /// The remote peer must use the same dictionary.
+    pub fn with_dictionary(level: i32, dictionary: &[u8]) -> io::Result<Self> {
+        Ok(Self {
+            encoder: Encoder::with_dictionary(level, dictionary)?,
+            decoder: Decoder::with_dictionary(dictionary)?,
+            level,
+            max_frame_bytes: DEFAULT_MAX_FRAME_BYTES,
+        })
+    }
+
+    /// Sets maximal size of the frames after decompression.
+    pub fn with_max_frame_bytes(mut self, limit: usize) -> Self {
+        self.max_frame_bytes = limit;
+        self
+    }
+
+    pub fn level(&self) -> i32 {
+        self.level
+    }
+
+    pub fn max_frame_bytes(&self) -> usize {
+        self.max_frame_bytes
+    }
+
+    fn compress(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.encoder.reinit()?;
+        let mut compressed = Vec::with_capacity(zstd::zstd_safe::compress_bound(data.len()));
+        let capacity = compressed.capacity();
+        let mut input = InBuffer::around(data);
+        let mut output = OutBuffer::around(&mut compressed);
+        while input.pos() < data.len() {
+            self.encoder.run(&mut input, &mut output)?;
+        }
+        while self.encoder.finish(&mut output, true)? > 0 {
+            // Never happens for the output of the compression bound size
+            if output.pos() == capacity {
+                return Err(io::ErrorKind::WriteZero.into());
+            }
+        }
+        Ok(compressed)
+    }
+
+    fn decompress(&mut self, data: &[u8]) -> Result<Vec<u8>, ZstdError> {
+        self.decoder.reinit()?;
+        let mut decompressed = Vec::new();
+        let mut chunk = vec![0u8; CHUNK_LEN];
+        let mut input = InBuffer::around(data);
+        loop {
+            let mut output = OutBuffer::around(chunk.as_mut_slice());
+            let remaining = self.decoder.run(&mut input, &mut output)?;
+            let written = output.pos();
+            if decompressed.len() + written > self.max_frame_bytes {
+                return Err(ZstdError::FrameTooLarge(self.max_frame_bytes));
+            }
+            decompressed.extend_from_slice(&chunk[..written]);
+            if remaining == 0 {
+                break;
+            }
+            if input.pos() == data.len() && written < chunk.len() {
+                return Err(ZstdError::IncompleteFrame);
+            }
+        }
+        if input.pos() < data.len() {
+            return Err(ZstdError::TrailingData(data.len() - input.pos()));
+        }
+        Ok(decompressed)
+    }
+}
+
+impl Encrypt for ZstdTranscoder {
+    fn encrypt(&mut self, data: &[u8]) -> Vec<u8> {
+        // Compression errors are not expected for in-memory data; if they
+        // happen the frame is just stored
+        let (flag, payload) = match self.compress(data) {
+            Ok(compressed) if compressed.len() < data.len() => (FLAG_ZSTD, compressed),
+            _ => (FLAG_STORED, data.to_vec()),
+        };
+        let mut frame = Vec::with_capacity(1 + payload.len());
+        frame.push(flag);
+        frame.extend(payload);
+        frame
+    }
+}
+
+impl Decrypt for ZstdTranscoder {
+    type Error = ZstdError;
+
+    fn decrypt(&mut self, data: &[u8]) -> Result<Vec<u8>, Self::Error> {
+        let (flag, payload) = data.split_first().ok_or(ZstdError::EmptyFrame)?;
+        match *flag {
+            FLAG_STORED if payload.len() > self.max_frame_bytes => {
+                Err(ZstdError::FrameTooLarge(self.max_frame_bytes))
+            }
+            FLAG_STORED => Ok(payload.to_vec()),
+            FLAG_ZSTD => self.decompress(payload),
+            flag => Err(ZstdError::UnknownFlag(flag)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transcoders::{Chain, ChainError};
+
+    /// Log lines compressing well, like the ones shipped by a logging
+    /// protocol.
+    fn log_lines(count: usize) -> Vec<u8> {
+        (0..count)
+            .map(|no| {
+                format!(
+                    "2023-01-01T00:00:{:02}Z INFO request {no} served in 12ms\n",
+                    no % 60
+                )
+            })
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    /// Cipher xoring the data with a key, authenticated by a trailing
+    /// checksum byte.
+    struct Xor(u8);
+
+    #[derive(Debug, Display, Error)]
+    #[display("invalid checksum")]
+    struct ChecksumError;
+
+    impl Encrypt for Xor {
+        fn encrypt(&mut self, data: &[u8]) -> Vec<u8> {
+            let mut encrypted = data.iter().map(|byte| byte ^ self.0).collect::<Vec<_>>();
+            encrypted.push(data.iter().fold(0, |sum, byte| sum ^ byte));
+            encrypted
+        }
+    }
+
+    impl Decrypt for Xor {
+        type Error = ChecksumError;
+
+        fn decrypt(&mut self, data: &[u8]) -> Result<Vec<u8>, Self::Error> {
+            let (checksum, data) = data.split_last().ok_or(ChecksumError)?;
+            let decrypted = data.iter().map(|byte| byte ^ self.0).collect::<Vec<_>>();
+            if decrypted.iter().fold(0, |sum, byte| sum ^ byte) != *checksum {
+                return Err(ChecksumError);
+            }
+            Ok(decrypted)
+        }
+    }
+
+    #[test]
+    fn compressed_roundtrip() {
+        let mut sender = ZstdTranscoder::default();
+        let mut receiver = ZstdTranscoder::default();
+        for count in [10, 100, 1000] {
+            let data = log_lines(count);
+            let frame = sender.encrypt(&data);
+            assert_eq!(frame[0], FLAG_ZSTD);
+            assert_eq!(receiver.decrypt(&frame).unwrap(), data);
+        }
+        let data = log_lines(1000);
+        assert!(sender.encrypt(&data).len() * 10 < data.len());
+    }
+
+    #[test]
+    fn stored_escape() {
+        let mut transcoder = ZstdTranscoder::default();
+        // Pseudo-random data do not compress
+        let mut state = 0x2545_f491_u32;
+        let data = (0..4096)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect::<Vec<_>>();
+        let frame = transcoder.encrypt(&data);
+        assert_eq!(frame.len(), data.len() + 1);
+        assert_eq!(frame[0], FLAG_STORED);
+        assert_eq!(transcoder.decrypt(&frame).unwrap(), data);
+
+        let frame = transcoder.encrypt(b"");
+        assert_eq!(frame, vec![FLAG_STORED]);
+        assert_eq!(transcoder.decrypt(&frame).unwrap(), b"");
+    }
+
+    #[test]
+    fn dictionary() {
+        let dictionary = log_lines(60);
+        let mut sender = ZstdTranscoder::with_dictionary(19, &dictionary).unwrap();
+        let mut receiver = ZstdTranscoder::with_dictionary(19, &dictionary).unwrap();
+        let data = log_lines(3);
+        let frame = sender.encrypt(&data);
+        assert!(frame.len() < ZstdTranscoder::new(19).unwrap().encrypt(&data).len());
+        assert_eq!(receiver.decrypt(&frame).unwrap(), data);
+        // Frames compressed with a dictionary can't be decompressed without it
+        assert!(ZstdTranscoder::default().decrypt(&frame).is_err());
+    }
+
+    #[test]
+    fn decompression_bomb() {
+        let mut sender = ZstdTranscoder::default();
+        let mut receiver = ZstdTranscoder::default().with_max_frame_bytes(64 * 1024);
+        let frame = sender.encrypt(&vec![0u8; 16 * 1024 * 1024]);
+        assert!(frame.len() < 1024);
+        assert!(matches!(
+            receiver.decrypt(&frame),
+            Err(ZstdError::FrameTooLarge(limit)) if limit == 64 * 1024
+        ));
+        // Receiver keeps working after the violation
+        let data = log_lines(100);
+        assert_eq!(receiver.decrypt(&sender.encrypt(&data)).unwrap(), data);
+
+        let mut truncated = sender.encrypt(&data);
+        truncated.truncate(truncated.len() / 2);
+        assert!(receiver.decrypt(&truncated).is_err());
+    }
+
+    #[test]
+    fn compress_then_encrypt() {
+        let mut sender = Chain::new(ZstdTranscoder::default(), Xor(0x5A));
+        let mut receiver = Chain::new(ZstdTranscoder::default(), Xor(0x5A));
+        let data = log_lines(1000);
+        let frame = sender.encrypt(&data);
+        // Data are compressed before being encrypted
+        assert!(frame.len() * 10 < data.len());
+        assert_eq!(receiver.decrypt(&frame).unwrap(), data);
+
+        let mut tampered = frame.clone();
+        tampered[0] ^= 1;
+        assert!(matches!(
+            receiver.decrypt(&tampered),
+            Err(ChainError::Outer(ChecksumError))
+        ));
+        let mut receiver = Chain::new(
+            ZstdTranscoder::default().with_max_frame_bytes(1024),
+            Xor(0x5A),
+        );
+        assert!(matches!(
+            receiver.decrypt(&frame),
+            Err(ChainError::Inner(ZstdError::FrameTooLarge(1024)))
+        ));
+    }
+}
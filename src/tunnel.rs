@@ -1,7 +1,9 @@
 use std::collections::VecDeque;
 use std::io::{Read, Write};
-use std::os::fd::AsRawFd;
-use std::time::Duration;
+use std::os::fd::{AsRawFd, RawFd};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use std::{io, net};
 
 use reactor::poller::{IoFail, IoType, Poll};
@@ -10,6 +12,449 @@ use crate::NetSession;
 
 pub const READ_BUFFER_SIZE: usize = u16::MAX as usize;
 
+/// Maximal amount of data buffered by a [`Forwarder`]: size of the kernel
+/// pipe used for splicing, or of the user-space buffer used for copying.
+pub const FORWARD_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Moves data from one file descriptor to another, for instance between the
+/// two sockets of a tunnel.
+///
+/// Forwarder constructed with [`Forwarder::with_splice`] moves the data in
+/// kernel space through an intermediate pipe with `splice(2)`, without
+/// copying it to the user memory. On non-Linux targets, or if the descriptors
+/// do not support splicing (`EINVAL`), the data are copied with
+/// `read`/`write` through a user-space buffer.
+///
+/// Both descriptors must be in non-blocking mode. The forwarder does not own
+/// them: they are not closed when the forwarder is dropped.
+pub struct Forwarder {
+    inbound: RawFd,
+    outbound: RawFd,
+    /// Read and write ends of the pipe used for splicing; `None` when the
+    /// data are copied.
+    pipe: Option<[RawFd; 2]>,
+    buf: Vec<u8>,
+    /// Position of the first buffered byte in `buf`.
+    pos: usize,
+    /// Number of bytes buffered in the pipe or in `buf`.
+    len: usize,
+    is_eof: bool,
+    forwarded: Arc<AtomicU64>,
+}
+
+impl Forwarder {
+    /// Constructs forwarder moving data with `splice(2)` on Linux; on other
+    /// targets it is the same as [`Forwarder::with_copy`].
+    pub fn with_splice(inbound: RawFd, outbound: RawFd) -> io::Result<Self> {
+        #[cfg(target_os = "linux")]
+        let pipe = {
+            let mut pipe = [0 as RawFd; 2];
+            if unsafe { libc::pipe2(pipe.as_mut_ptr(), libc::O_NONBLOCK | libc::O_CLOEXEC) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Some(pipe)
+        };
+        #[cfg(not(target_os = "linux"))]
+        let pipe = None;
+        Ok(Self::with_pipe(inbound, outbound, pipe))
+    }
+
+    /// Constructs forwarder copying data through a user-space buffer.
+    pub fn with_copy(inbound: RawFd, outbound: RawFd) -> Self {
+        Self::with_pipe(inbound, outbound, None)
+    }
+
+    fn with_pipe(inbound: RawFd, outbound: RawFd, pipe: Option<[RawFd; 2]>) -> Self {
+        Forwarder {
+            inbound,
+            outbound,
+            pipe,
+            buf: vec![],
+            pos: 0,
+            len: 0,
+            is_eof: false,
+            forwarded: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Detects whether the data are moved with `splice(2)`.
+    pub fn is_splicing(&self) -> bool {
+        self.pipe.is_some()
+    }
+
+    /// Returns number of bytes written to the outbound descriptor so far.
+    pub fn bytes_forwarded(&self) -> u64 {
+        self.forwarded.load(Ordering::Relaxed)
+    }
+
+    /// Returns counter of the forwarded bytes, which can be read from other
+    /// threads.
+    pub fn forwarded_counter(&self) -> Arc<AtomicU64> {
+        self.forwarded.clone()
+    }
+
+    /// Detects whether the forwarder has room for more data from the inbound
+    /// descriptor, i.e. whether it should be polled for reading.
+    pub fn wants_read(&self) -> bool {
+        match self.pipe {
+            _ if self.is_eof => false,
+            Some(_) => self.len < FORWARD_BUFFER_SIZE,
+            None => self.len == 0,
+        }
+    }
+
+    /// Detects whether there are data which are waiting for the outbound
+    /// descriptor to become writable.
+    pub fn has_pending(&self) -> bool {
+        self.len > 0
+    }
+
+    /// Detects whether the inbound descriptor has reached the end of stream
+    /// and all of its data were forwarded.
+    pub fn is_finished(&self) -> bool {
+        self.is_eof && self.len == 0
+    }
+
+    /// Forwards as much data as possible without blocking. Should be called
+    /// each time one of the descriptors becomes ready for I/O.
+    ///
+    /// # Returns
+    ///
+    /// Number of bytes written to the outbound descriptor by the call.
+    pub fn forward(&mut self) -> io::Result<usize> {
+        let mut written = 0;
+        loop {
+            let progress = match self.pipe {
+                Some(pipe) => self.splice(pipe, &mut written)?,
+                None => self.copy(&mut written)?,
+            };
+            if !progress {
+                break;
+            }
+        }
+        self.forwarded.fetch_add(written as u64, Ordering::Relaxed);
+        Ok(written)
+    }
+
+    /// Performs a single read and a single write with `splice(2)`, returning
+    /// whether any of them has succeeded.
+    #[cfg(target_os = "linux")]
+    fn splice(&mut self, [pipe_out, pipe_in]: [RawFd; 2], written: &mut usize) -> io::Result<bool> {
+        let flags = libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK;
+        let mut progress = false;
+        if !self.is_eof && self.len < FORWARD_BUFFER_SIZE {
+            let len = FORWARD_BUFFER_SIZE - self.len;
+            match check(unsafe {
+                libc::splice(
+                    self.inbound,
+                    std::ptr::null_mut(),
+                    pipe_in,
+                    std::ptr::null_mut(),
+                    len,
+                    flags,
+                )
+            }) {
+                Ok(0) => {
+                    self.is_eof = true;
+                    progress = true;
+                }
+                Ok(len) => {
+                    self.len += len;
+                    progress = true;
+                }
+                Err(err) if err.raw_os_error() == Some(libc::EINVAL) => return self.fall_back(),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if self.len > 0 {
+            match check(unsafe {
+                libc::splice(
+                    pipe_out,
+                    std::ptr::null_mut(),
+                    self.outbound,
+                    std::ptr::null_mut(),
+                    self.len,
+                    flags,
+                )
+            }) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(len) => {
+                    self.len -= len;
+                    *written += len;
+                    progress = true;
+                }
+                Err(err) if err.raw_os_error() == Some(libc::EINVAL) => return self.fall_back(),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(progress)
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn splice(&mut self, _: [RawFd; 2], _: &mut usize) -> io::Result<bool> {
+        unreachable!("pipe is not constructed on non-Linux targets")
+    }
+
+    /// Switches to copying the data, moving the data buffered in the pipe into
+    /// the user-space buffer.
+    fn fall_back(&mut self) -> io::Result<bool> {
+        #[cfg(feature = "log")]
+        log::debug!(target: "tunnel", "Descriptors do not support splicing, falling back to copying");
+        #[cfg(feature = "tracing")]
+        tracing::debug!(target: "tunnel", "Descriptors do not support splicing, falling back to copying");
+
+        let [pipe_out, pipe_in] = self.pipe.take().expect("forwarder is splicing");
+        self.buf.resize(FORWARD_BUFFER_SIZE, 0);
+        self.pos = 0;
+        let mut res = Ok(true);
+        let mut read = 0;
+        while read < self.len {
+            match check(unsafe {
+                libc::read(
+                    pipe_out,
+                    self.buf[read..].as_mut_ptr() as *mut libc::c_void,
+                    self.len - read,
+                )
+            }) {
+                Ok(len) => read += len,
+                Err(err) => {
+                    res = Err(err);
+                    break;
+                }
+            }
+        }
+        unsafe {
+            libc::close(pipe_out);
+            libc::close(pipe_in);
+        }
+        res
+    }
+
+    /// Performs a single read and a single write through the user-space
+    /// buffer, returning whether any of them has succeeded.
+    fn copy(&mut self, written: &mut usize) -> io::Result<bool> {
+        let mut progress = false;
+        if !self.is_eof && self.len == 0 {
+            self.buf.resize(FORWARD_BUFFER_SIZE, 0);
+            match check(unsafe {
+                libc::read(
+                    self.inbound,
+                    self.buf.as_mut_ptr() as *mut libc::c_void,
+                    self.buf.len(),
+                )
+            }) {
+                Ok(0) => {
+                    self.is_eof = true;
+                    progress = true;
+                }
+                Ok(len) => {
+                    self.pos = 0;
+                    self.len = len;
+                    progress = true;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if self.len > 0 {
+            let data = &self.buf[self.pos..self.pos + self.len];
+            match check(unsafe {
+                libc::write(
+                    self.outbound,
+                    data.as_ptr() as *const libc::c_void,
+                    data.len(),
+                )
+            }) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(len) => {
+                    self.pos += len;
+                    self.len -= len;
+                    *written += len;
+                    progress = true;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(progress)
+    }
+}
+
+impl Drop for Forwarder {
+    fn drop(&mut self) {
+        if let Some([pipe_out, pipe_in]) = self.pipe {
+            unsafe {
+                libc::close(pipe_out);
+                libc::close(pipe_in);
+            }
+        }
+    }
+}
+
+/// Converts result of a libc call into [`io::Result`].
+fn check(res: isize) -> io::Result<usize> {
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res as usize)
+    }
+}
+
+/// Reason of a tunnel termination, reported in [`TunnelStats`].
+#[derive(Debug, Display)]
+#[display(doc_comments)]
+pub enum CloseReason {
+    /// both sides have finished sending data
+    Completed,
+
+    /// no data were transferred within the timeout
+    TimedOut,
+
+    /// tunnel has failed: {0}
+    Failed(io::Error),
+}
+
+/// Summary of a tunnel run with [`Tunnel::tunnel_once`].
+#[derive(Debug)]
+pub struct TunnelStats {
+    /// Number of bytes sent from the local client to the remote session.
+    pub up_bytes: u64,
+    /// Number of bytes sent from the remote session to the local client.
+    pub down_bytes: u64,
+    /// Time since the local client has connected.
+    pub duration: Duration,
+    pub close_reason: CloseReason,
+}
+
+/// Data sent in one direction of a tunnel, buffered between reading them from
+/// the source and writing them to the destination.
+///
+/// The buffer is bounded by [`READ_BUFFER_SIZE`]: once it is full, the
+/// source is not read until the destination accepts some of the data.
+struct Flow {
+    buf: VecDeque<u8>,
+    bytes: u64,
+    /// Whether the data written to the destination were flushed.
+    is_flushed: bool,
+    /// Whether the source has reached the end of stream.
+    is_eof: bool,
+    /// Whether the writing half of the destination was shut down.
+    is_closed: bool,
+}
+
+impl Flow {
+    fn new() -> Self {
+        Flow {
+            buf: VecDeque::with_capacity(READ_BUFFER_SIZE),
+            bytes: 0,
+            is_flushed: true,
+            is_eof: false,
+            is_closed: false,
+        }
+    }
+
+    fn wants_read(&self) -> bool {
+        !self.is_eof && self.buf.len() < READ_BUFFER_SIZE
+    }
+
+    fn wants_write(&self) -> bool {
+        !self.buf.is_empty() || !self.is_flushed || self.wants_close()
+    }
+
+    /// Detects whether all data from the source were forwarded, so the
+    /// writing half of the destination must be shut down.
+    fn wants_close(&self) -> bool {
+        self.is_eof && self.buf.is_empty() && !self.is_closed
+    }
+
+    /// Reads from the source until it would block, reaches the end of stream
+    /// or the buffer is full.
+    fn read_from(&mut self, src: &mut impl Read, buf: &mut [u8]) -> io::Result<()> {
+        while self.wants_read() {
+            let len = buf.len().min(READ_BUFFER_SIZE - self.buf.len());
+            match src.read(&mut buf[..len]) {
+                Ok(0) => self.is_eof = true,
+                Ok(len) => self.buf.extend(&buf[..len]),
+                // Sessions signal incomplete handshake with the interruption
+                Err(err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::Interrupted =>
+                {
+                    break
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes buffered data to the destination until it would block or the
+    /// buffer is empty.
+    fn write_to(&mut self, dst: &mut impl Write) -> io::Result<()> {
+        while !self.buf.is_empty() {
+            let (data, _) = self.buf.as_slices();
+            match dst.write(data) {
+                Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(len) => {
+                    self.buf.drain(..len);
+                    self.bytes += len as u64;
+                    self.is_flushed = false;
+                }
+                Err(err)
+                    if err.kind() == io::ErrorKind::WouldBlock
+                        || err.kind() == io::ErrorKind::Interrupted =>
+                {
+                    break
+                }
+                Err(err) => return Err(err),
+            }
+        }
+        match dst.flush() {
+            Ok(()) => self.is_flushed = true,
+            Err(err)
+                if err.kind() == io::ErrorKind::WouldBlock
+                    || err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+
+    /// Shuts down the writing half of the destination once all data were
+    /// forwarded, propagating the end of stream. Shutdown which would block
+    /// is retried once the destination becomes writable.
+    fn close(&mut self, shutdown: impl FnOnce() -> io::Result<()>) -> io::Result<()> {
+        if !self.wants_close() {
+            return Ok(());
+        }
+        match shutdown() {
+            Ok(()) => self.is_closed = true,
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => {}
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+}
+
+/// Performs I/O on one side of a tunnel, writing data flowing to it and
+/// reading data flowing from it.
+fn transfer<C: Read + Write>(
+    conn: &mut C,
+    ev: IoType,
+    inflow: &mut Flow,
+    outflow: &mut Flow,
+    buf: &mut [u8],
+) -> io::Result<()> {
+    if ev.write {
+        inflow.write_to(conn)?;
+    }
+    if ev.read {
+        outflow.read_from(conn, buf)?;
+    }
+    Ok(())
+}
+
 pub struct Tunnel<S: NetSession> {
     listener: net::TcpListener,
     session: S,
@@ -28,14 +473,28 @@ impl<S: NetSession> Tunnel<S> {
         self.listener.local_addr()
     }
 
+    /// Accepts a single connection from a local client and tunnels data
+    /// between it and the remote session, until both of them finish sending
+    /// data, the tunnel fails or no data are transferred within `timeout`.
+    ///
+    /// Each direction buffers at most [`READ_BUFFER_SIZE`] bytes, so the
+    /// faster side is not read while the slower one catches up. Once one side
+    /// reaches the end of stream, the writing half of the other side is shut
+    /// down, while the reverse direction keeps working.
+    ///
     /// # Returns
     ///
-    /// Number of bytes which passed through the tunnel
+    /// Statistics of the tunnel, including the reason of its termination.
+    ///
+    /// # Errors
+    ///
+    /// If the local client can't be accepted or the connections can't be
+    /// configured.
     pub fn tunnel_once<P: Poll>(
         &mut self,
         mut poller: P,
         timeout: Duration,
-    ) -> io::Result<(usize, usize)> {
+    ) -> io::Result<TunnelStats> {
         let listener_addr = self
             .listener
             .local_addr()
@@ -44,6 +503,7 @@ impl<S: NetSession> Tunnel<S> {
         log::info!(target: "tunnel", "Tunnel accepting a single connection will run on {listener_addr}");
 
         let (mut stream, socket_addr) = self.listener.accept()?;
+        let start = Instant::now();
         #[cfg(feature = "log")]
         log::debug!(target: "tunnel", "Incoming connection from {socket_addr} for tunnel {listener_addr}");
 
@@ -60,112 +520,282 @@ impl<S: NetSession> Tunnel<S> {
         poller.register(&int_fd, IoType::read_only());
         poller.register(&ext_fd, IoType::read_only());
 
-        let mut in_buf = VecDeque::<u8>::new();
-        let mut out_buf = VecDeque::<u8>::new();
-
-        let mut in_count = 0usize;
-        let mut out_count = 0usize;
-
-        let mut buf = [0u8; READ_BUFFER_SIZE];
-
-        macro_rules! handle {
-            ($call:expr, |$var:ident| $expr:expr) => {
-                match $call {
-                    Ok(0) => {
-                        #[cfg(feature = "log")]
-                        log::info!(target: "tunnel",
-                            "Tunnel {socket_addr} has completed its work. Total {in_count} bytes are received and {out_count} sent"
-                        );
-                        return Ok((in_count, out_count))
-                    },
-                    Ok($var) => $expr,
-                    Err(err) => {
-                        #[cfg(feature = "log")]
-                        log::error!(target: "tunnel",
-                            "Tunnel {socket_addr} has terminated with '{err}'"
-                        );
-                        return Err(err)
-                    },
-                }
-            };
-        }
+        // Data from the local client to the remote and back
+        let mut up = Flow::new();
+        let mut down = Flow::new();
+        let mut buf = vec![0u8; READ_BUFFER_SIZE];
 
         #[cfg(feature = "log")]
         log::info!(target: "tunnel", "Tunnel on {listener_addr} is operational for a client {socket_addr}");
-        loop {
+        let close_reason = loop {
+            if up.is_closed && down.is_closed {
+                break CloseReason::Completed;
+            }
+            poller.set_interest(
+                &int_fd,
+                IoType {
+                    read: up.wants_read(),
+                    write: down.wants_write(),
+                },
+            );
+            poller.set_interest(
+                &ext_fd,
+                IoType {
+                    read: down.wants_read(),
+                    write: up.wants_write(),
+                },
+            );
+
             // Blocking
-            let count = poller.poll(Some(timeout))?;
-            if count == 0 {
-                #[cfg(feature = "log")]
-                log::warn!(target: "tunnel", "Tunnel {listener_addr} timed out with client {socket_addr}");
-                return Err(io::ErrorKind::TimedOut.into());
-            }
-            while let Some((fd, res)) = poller.next() {
-                let ev = match res {
+            match poller.poll(Some(timeout)) {
+                Ok(0) => {
+                    #[cfg(feature = "log")]
+                    log::warn!(target: "tunnel", "Tunnel {listener_addr} timed out with client {socket_addr}");
+                    break CloseReason::TimedOut;
+                }
+                Ok(_) => {}
+                Err(err) => break CloseReason::Failed(err),
+            }
+            let mut res = Ok(());
+            for (fd, ev) in &mut poller {
+                let ev = match ev {
                     Ok(ev) => ev,
-                    Err(IoFail::Connectivity(code)) => {
-                        #[cfg(feature = "log")]
-                        log::info!(target: "tunnel", "Tunnel {socket_addr} has completed its work with the code {code:#b}");
-                        return Ok((in_count, out_count));
-                    }
-                    Err(IoFail::Os(code)) => {
-                        #[cfg(feature = "log")]
-                        log::error!(target: "tunnel", "Tunnel {socket_addr} was terminated with the code {code:#b}");
-                        return Err(io::ErrorKind::BrokenPipe.into());
+                    // The data received before the hang-up are still to be
+                    // read, while the writes fail
+                    Err(IoFail::Connectivity(_)) => IoType::read_write(),
+                    Err(fail @ IoFail::Os(_)) => {
+                        res = Err(io::Error::new(io::ErrorKind::BrokenPipe, fail));
+                        break;
                     }
                 };
-                if fd == int_fd {
-                    if ev.write {
-                        log::trace!(target: "tunnel", "attempting to write {} bytes received from the remote {socket_addr}", in_buf.len());
-                        handle!(stream.write(in_buf.make_contiguous()), |written| {
-                            stream.flush()?;
-                            in_buf.drain(..written);
-                            in_count += written;
-                            if in_buf.is_empty() {
-                                poller.set_interest(&int_fd, IoType::read_only());
-                            }
-                            #[cfg(feature = "log")]
-                            log::trace!(target: "tunnel", "{socket_addr} received {written} bytes from local out of {} buffered", in_buf.len());
-                        });
-                    }
-                    if ev.read {
-                        log::trace!(target: "tunnel", "attempting to read from the {socket_addr}");
-                        handle!(stream.read(&mut buf), |read| {
-                            out_buf.extend(&buf[..read]);
-                            poller.set_interest(&ext_fd, IoType::read_write());
-                            #[cfg(feature = "log")]
-                            log::trace!(target: "tunnel", "{socket_addr} read {read} bytes from local ({} total in the buffer)", out_buf.len());
-                        });
-                    }
+                res = if fd == int_fd {
+                    transfer(&mut stream, ev, &mut down, &mut up, &mut buf)
                 } else if fd == ext_fd {
-                    if ev.write {
-                        log::trace!(target: "tunnel", "attempting to write {} bytes received from {socket_addr} to remote", out_buf.len());
-                        handle!(self.session.write(out_buf.make_contiguous()), |written| {
-                            self.session.flush()?;
-                            out_buf.drain(..written);
-                            out_count += written;
-                            if out_buf.is_empty() {
-                                poller.set_interest(&ext_fd, IoType::read_only());
-                            }
-                            #[cfg(feature = "log")]
-                            log::trace!(target: "tunnel", "{socket_addr} sent {written} bytes to remote out of {} buffered", out_buf.len());
-                        });
-                    }
-                    if ev.read {
-                        log::trace!(target: "tunnel", "attempting to read from the remote");
-                        handle!(self.session.read(&mut buf), |read| {
-                            in_buf.extend(&buf[..read]);
-                            poller.set_interest(&int_fd, IoType::read_write());
-                            #[cfg(feature = "log")]
-                            log::trace!(target: "tunnel", "{socket_addr} read {read} bytes from remote ({} total in the buffer)", in_buf.len());
-                        });
-                    }
+                    transfer(&mut self.session, ev, &mut up, &mut down, &mut buf)
+                } else {
+                    Ok(())
+                };
+                if res.is_err() {
+                    break;
                 }
             }
-        }
+            // End of stream on one side is propagated to the other one
+            let res = res
+                .and_then(|_| up.close(|| self.session.shutdown_write()))
+                .and_then(|_| down.close(|| stream.shutdown(net::Shutdown::Write)));
+            if let Err(err) = res {
+                break CloseReason::Failed(err);
+            }
+        };
+        poller.unregister(&int_fd);
+        poller.unregister(&ext_fd);
+
+        let stats = TunnelStats {
+            up_bytes: up.bytes,
+            down_bytes: down.bytes,
+            duration: start.elapsed(),
+            close_reason,
+        };
+        #[cfg(feature = "log")]
+        log::info!(target: "tunnel",
+            "Tunnel {socket_addr} has terminated after {:?} ({}): {} bytes sent to remote, {} bytes received",
+            stats.duration, stats.close_reason, stats.up_bytes, stats.down_bytes
+        );
+        #[cfg(feature = "tracing")]
+        tracing::info!(target: "tunnel",
+            "Tunnel {socket_addr} has terminated after {:?} ({}): {} bytes sent to remote, {} bytes received",
+            stats.duration, stats.close_reason, stats.up_bytes, stats.down_bytes
+        );
+        Ok(stats)
     }
 
     pub fn into_session(self) -> S {
         self.session
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, OpenOptions};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    use reactor::poller::popol;
+
+    use super::*;
+
+    /// Amount of data forwarded by the tests.
+    const LEN: usize = 1024 * 1024;
+
+    fn data() -> Vec<u8> {
+        (0..LEN).map(|i| (i % 251) as u8).collect()
+    }
+
+    /// Forwards all data from the inbound socket, returning number of the
+    /// forwarded bytes.
+    fn run(forwarder: &mut Forwarder) -> usize {
+        let mut total = 0;
+        while !forwarder.is_finished() {
+            match forwarder.forward().unwrap() {
+                0 => thread::yield_now(),
+                len => total += len,
+            }
+        }
+        total
+    }
+
+    /// Runs forwarder between two pairs of sockets, returning the data
+    /// received on the other end.
+    fn forward_sockets(splice: bool) -> Vec<u8> {
+        let (mut src, inbound) = UnixStream::pair().unwrap();
+        let (outbound, mut dst) = UnixStream::pair().unwrap();
+        inbound.set_nonblocking(true).unwrap();
+        outbound.set_nonblocking(true).unwrap();
+
+        let writer = thread::spawn(move || src.write_all(&data()));
+        let reader = thread::spawn(move || {
+            let mut received = vec![];
+            dst.read_to_end(&mut received).unwrap();
+            received
+        });
+
+        let mut forwarder = match splice {
+            true => Forwarder::with_splice(inbound.as_raw_fd(), outbound.as_raw_fd()).unwrap(),
+            false => Forwarder::with_copy(inbound.as_raw_fd(), outbound.as_raw_fd()),
+        };
+        #[cfg(target_os = "linux")]
+        assert_eq!(forwarder.is_splicing(), splice);
+        assert_eq!(run(&mut forwarder), LEN);
+        assert_eq!(forwarder.bytes_forwarded(), LEN as u64);
+        assert!(!forwarder.has_pending());
+
+        writer.join().unwrap().unwrap();
+        outbound.shutdown(Shutdown::Write).unwrap();
+        reader.join().unwrap()
+    }
+
+    #[test]
+    fn splice() {
+        assert_eq!(forward_sockets(true), data());
+    }
+
+    #[test]
+    fn copy() {
+        assert_eq!(forward_sockets(false), data());
+    }
+
+    #[test]
+    fn splice_fallback() {
+        // Splicing into a file opened in append mode fails with EINVAL
+        let path = std::env::temp_dir().join(format!("netservices-splice-{}", std::process::id()));
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&path)
+            .unwrap();
+        drop(file);
+        let file = OpenOptions::new().append(true).open(&path).unwrap();
+
+        let (mut src, inbound) = UnixStream::pair().unwrap();
+        inbound.set_nonblocking(true).unwrap();
+        let writer = thread::spawn(move || src.write_all(&data()));
+
+        let mut forwarder = Forwarder::with_splice(inbound.as_raw_fd(), file.as_raw_fd()).unwrap();
+        assert_eq!(run(&mut forwarder), LEN);
+        assert!(!forwarder.is_splicing());
+        writer.join().unwrap().unwrap();
+
+        assert_eq!(fs::read(&path).unwrap(), data());
+        fs::remove_file(path).unwrap();
+    }
+
+    /// Runs tunnel for a remote session connected to a server, returning the
+    /// tunnel address, the server side of the session and the handle
+    /// providing the tunnel statistics.
+    #[cfg(feature = "socket2")]
+    fn tunnel() -> (net::SocketAddr, TcpStream, thread::JoinHandle<TunnelStats>) {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let session = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+        let (remote, _) = server.accept().unwrap();
+        let mut tunnel = Tunnel::with(session, "127.0.0.1:0").unwrap();
+        let addr = tunnel.local_addr().unwrap();
+        let handle = thread::spawn(move || {
+            tunnel
+                .tunnel_once(popol::Poller::new(), Duration::from_secs(5))
+                .unwrap()
+        });
+        (addr, remote, handle)
+    }
+
+    #[test]
+    #[cfg(feature = "socket2")]
+    fn tunnel_half_close() {
+        let (addr, mut remote, handle) = tunnel();
+        let mut client = TcpStream::connect(addr).unwrap();
+
+        // Request is followed by the end of stream, which reaches the remote
+        // while the tunnel keeps the reverse direction
+        client.write_all(&data()).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        let mut request = vec![];
+        remote.read_to_end(&mut request).unwrap();
+        assert_eq!(request, data());
+
+        remote.write_all(b"response").unwrap();
+        remote.shutdown(Shutdown::Write).unwrap();
+        let mut response = vec![];
+        client.read_to_end(&mut response).unwrap();
+        assert_eq!(response, b"response");
+
+        let stats = handle.join().unwrap();
+        assert_eq!(stats.up_bytes, LEN as u64);
+        assert_eq!(stats.down_bytes, b"response".len() as u64);
+        assert!(matches!(stats.close_reason, CloseReason::Completed));
+        assert!(stats.duration > Duration::ZERO);
+    }
+
+    #[test]
+    #[cfg(feature = "socket2")]
+    fn tunnel_backpressure() {
+        /// Amount of data which can't fit the socket buffers of the test.
+        const FLOOD: usize = 64 * LEN;
+
+        let (addr, mut remote, handle) = tunnel();
+        let mut client = TcpStream::connect(addr).unwrap();
+        client.set_nonblocking(true).unwrap();
+
+        // The remote does not read, so the tunnel stops reading the client
+        // once its buffer is full
+        let chunk = data();
+        let mut sent = 0;
+        let mut stalls = 0;
+        while sent < FLOOD && stalls < 10 {
+            match client.write(&chunk[..chunk.len().min(FLOOD - sent)]) {
+                Ok(len) => {
+                    sent += len;
+                    stalls = 0;
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                    stalls += 1;
+                    thread::sleep(Duration::from_millis(10));
+                }
+                Err(err) => panic!("{err}"),
+            }
+        }
+        assert!(sent < FLOOD, "tunnel has buffered all of the data");
+
+        client.set_nonblocking(false).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        drop(client);
+        let mut received = vec![];
+        remote.read_to_end(&mut received).unwrap();
+        assert_eq!(received.len(), sent);
+        drop(remote);
+
+        let stats = handle.join().unwrap();
+        assert_eq!(stats.up_bytes, sent as u64);
+        assert_eq!(stats.down_bytes, 0);
+    }
+}